@@ -0,0 +1,30 @@
+#!/usr/bin/env python3
+"""Fail if an Autobahn report contains failed cases.
+
+Usage: check_reports.py autobahn/reports/server/index.json
+"""
+
+import json
+import sys
+
+
+def main(path):
+    with open(path) as f:
+        report = json.load(f)
+
+    failed = []
+    for agent, cases in report.items():
+        for case, result in cases.items():
+            if "FAILED" in (result.get("behavior"), result.get("behaviorClose")):
+                failed.append((agent, case, result["behavior"], result["behaviorClose"]))
+
+    for agent, case, behavior, close in sorted(failed):
+        print(f"{agent} {case}: behavior={behavior} close={close}")
+
+    total = sum(len(cases) for cases in report.values())
+    print(f"{total - len(failed)}/{total} cases passed")
+    return 1 if failed else 0
+
+
+if __name__ == "__main__":
+    sys.exit(main(sys.argv[1]))
@@ -234,7 +234,7 @@ fn bench_handshake(c: &mut Criterion) {
     group.bench_function("write_response", |b| {
         b.iter(|| {
             let mut buf = Vec::with_capacity(256);
-            resp.write(&mut buf);
+            resp.write(&mut buf).unwrap();
             black_box(buf)
         })
     });
@@ -246,7 +246,7 @@ fn bench_handshake(c: &mut Criterion) {
             req.validate().unwrap();
             let resp = HandshakeResponse::from_request(&req);
             let mut buf = Vec::with_capacity(256);
-            resp.write(&mut buf);
+            resp.write(&mut buf).unwrap();
             black_box(buf)
         })
     });
@@ -0,0 +1,150 @@
+//! Autobahn-compatible WebSocket echo client for compliance testing.
+//!
+//! Drives every case of the Autobahn fuzzing server, echoing whatever the
+//! server sends, then prints each case's result and asks the server to
+//! write its HTML/JSON reports.
+//!
+//! Start the fuzzing server first:
+//! ```bash
+//! docker run -it --rm \
+//!   -v "${PWD}/autobahn:/config" \
+//!   -v "${PWD}/autobahn/reports:/reports" \
+//!   --network host \
+//!   crossbario/autobahn-testsuite \
+//!   wstest -m fuzzingserver -s /config/fuzzingserver.json
+//! ```
+//!
+//! Then run: cargo run --example autobahn_client
+//!
+//! The process exits with a non-zero status if any case failed.
+
+use rsws::{Config, Connection, HandshakeResponse, Message, Role, generate_key};
+use std::error::Error;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+const ADDR: &str = "127.0.0.1:9001";
+const AGENT: &str = "rsws";
+
+type BoxError = Box<dyn Error + Send + Sync>;
+
+#[tokio::main]
+async fn main() -> Result<(), BoxError> {
+    let case_count: u32 = read_text("/getCaseCount").await?.trim().parse()?;
+    println!(
+        "Running {} Autobahn cases against ws://{}",
+        case_count, ADDR
+    );
+
+    let mut failed = Vec::new();
+    for case in 1..=case_count {
+        if let Err(e) = run_case(case).await {
+            // Many cases end in a deliberately broken connection.
+            eprintln!("case {}: connection ended: {}", case, e);
+        }
+
+        let status = case_status(case).await?;
+        println!("case {:>3}/{}: {}", case, case_count, status);
+        if status == "FAILED" {
+            failed.push(case);
+        }
+    }
+
+    read_text(&format!("/updateReports?agent={}", AGENT)).await?;
+    println!("Reports written to autobahn/reports/client/index.html");
+
+    if !failed.is_empty() {
+        return Err(format!("{} case(s) failed: {:?}", failed.len(), failed).into());
+    }
+    Ok(())
+}
+
+/// Echo every data message back until the server closes the connection.
+async fn run_case(case: u32) -> Result<(), BoxError> {
+    let mut conn = connect(&format!("/runCase?case={}&agent={}", case, AGENT)).await?;
+
+    while conn.is_open() {
+        let msg = match conn.recv().await {
+            Ok(msg) => msg,
+            Err(e) => {
+                if let Some(code) = e.close_code() {
+                    let _ = conn.close(code, "").await;
+                }
+                return Err(e.into());
+            }
+        };
+
+        match msg {
+            Some(Message::Text(text)) => conn.send(Message::text(text)).await?,
+            Some(Message::Binary(data)) => conn.send(Message::Binary(data)).await?,
+            Some(Message::Close(_)) | None => break,
+            Some(_) => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Ask the server for a case's result, e.g. `OK`, `NON-STRICT` or `FAILED`.
+async fn case_status(case: u32) -> Result<String, BoxError> {
+    let body = read_text(&format!("/getCaseStatus?case={}&agent={}", case, AGENT)).await?;
+    // The body is `{"behavior": "OK"}`; avoid pulling in a JSON parser.
+    let status = body
+        .split('"')
+        .nth(3)
+        .ok_or_else(|| format!("unexpected status response: {}", body))?;
+    Ok(status.to_string())
+}
+
+/// Open a connection to one of the server's control endpoints and return the
+/// first text message it sends.
+async fn read_text(path: &str) -> Result<String, BoxError> {
+    let mut conn = connect(path).await?;
+    let mut text = String::new();
+
+    while let Some(msg) = conn.recv().await? {
+        match msg {
+            Message::Text(t) => text = t,
+            Message::Close(_) => break,
+            _ => {}
+        }
+    }
+
+    Ok(text)
+}
+
+async fn connect(path: &str) -> Result<Connection<TcpStream>, BoxError> {
+    let mut stream = TcpStream::connect(ADDR).await?;
+
+    let key = generate_key();
+    let request = format!(
+        "GET {} HTTP/1.1\r\n\
+         Host: {}\r\n\
+         Upgrade: websocket\r\n\
+         Connection: Upgrade\r\n\
+         Sec-WebSocket-Key: {}\r\n\
+         Sec-WebSocket-Version: 13\r\n\
+         \r\n",
+        path, ADDR, key
+    );
+    stream.write_all(request.as_bytes()).await?;
+
+    // The server may send frames right after the handshake, so read the
+    // response head byte by byte instead of through a buffered reader.
+    let mut response_bytes = Vec::new();
+    while !response_bytes.ends_with(b"\r\n\r\n") {
+        let mut byte = [0u8; 1];
+        if stream.read(&mut byte).await? == 0 {
+            return Err("connection closed during handshake".into());
+        }
+        response_bytes.push(byte[0]);
+    }
+
+    HandshakeResponse::parse(&response_bytes)?.validate(&key)?;
+
+    let mut config = Config::client();
+    config.limits.max_message_size = 64 * 1024 * 1024;
+    config.limits.max_frame_size = 64 * 1024 * 1024;
+
+    Ok(Connection::new(stream, Role::Client, config))
+}
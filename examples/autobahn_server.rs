@@ -17,7 +17,7 @@
 
 use rsws::{Config, Connection, HandshakeRequest, HandshakeResponse, Message, Role};
 use std::error::Error;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
 
 const ADDR: &str = "127.0.0.1:9001";
@@ -49,19 +49,15 @@ async fn main() -> Result<(), Box<dyn Error>> {
 }
 
 async fn handle_connection(mut stream: TcpStream) -> Result<(), Box<dyn Error + Send + Sync>> {
-    let mut reader = BufReader::new(&mut stream);
+    // Read the request head byte by byte so no frame data sent right after
+    // the handshake is swallowed by a buffer.
     let mut request_bytes = Vec::new();
-
-    loop {
-        let mut line = String::new();
-        let bytes_read = reader.read_line(&mut line).await?;
-        if bytes_read == 0 {
+    while !request_bytes.ends_with(b"\r\n\r\n") {
+        let mut byte = [0u8; 1];
+        if stream.read(&mut byte).await? == 0 {
             return Ok(());
         }
-        request_bytes.extend_from_slice(line.as_bytes());
-        if line == "\r\n" {
-            break;
-        }
+        request_bytes.push(byte[0]);
     }
 
     let request = HandshakeRequest::parse(&request_bytes)?;
@@ -69,7 +65,7 @@ async fn handle_connection(mut stream: TcpStream) -> Result<(), Box<dyn Error +
 
     let response = HandshakeResponse::from_request(&request);
     let mut response_bytes = Vec::new();
-    let _ = response.write(&mut response_bytes);
+    response.write(&mut response_bytes)?;
     stream.write_all(&response_bytes).await?;
 
     let mut config = Config::server();
@@ -79,7 +75,18 @@ async fn handle_connection(mut stream: TcpStream) -> Result<(), Box<dyn Error +
     let mut conn = Connection::new(stream, Role::Server, config);
 
     while conn.is_open() {
-        match conn.recv().await? {
+        let msg = match conn.recv().await {
+            Ok(msg) => msg,
+            Err(e) => {
+                // Fail the connection the way RFC 6455 asks: close frame first.
+                if let Some(code) = e.close_code() {
+                    let _ = conn.close(code, "").await;
+                }
+                return Err(e.into());
+            }
+        };
+
+        match msg {
             Some(Message::Text(text)) => {
                 conn.send(Message::text(text)).await?;
             }
@@ -1,4 +1,4 @@
-use rsws::{CloseCode, Config, Connection, HandshakeResponse, Message, Role, compute_accept_key};
+use rsws::{CloseCode, Config, Connection, HandshakeResponse, Message, Role, generate_key};
 use std::net::SocketAddr;
 use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
@@ -276,54 +276,37 @@ fn parse_args() -> TestConfig {
     let mut i = 1;
     while i < args.len() {
         match args[i].as_str() {
-            "-h" | "--host" => {
-                if i + 1 < args.len() {
-                    host = args[i + 1].clone();
-                    i += 1;
-                }
+            "-h" | "--host" if i + 1 < args.len() => {
+                host = args[i + 1].clone();
+                i += 1;
             }
-            "-p" | "--port" => {
-                if i + 1 < args.len() {
-                    port = args[i + 1].parse().unwrap_or(9001);
-                    i += 1;
-                }
+            "-p" | "--port" if i + 1 < args.len() => {
+                port = args[i + 1].parse().unwrap_or(9001);
+                i += 1;
             }
-            "-c" | "--clients" => {
-                if i + 1 < args.len() {
-                    num_clients = args[i + 1].parse().unwrap_or(1000);
-                    i += 1;
-                }
+            "-c" | "--clients" if i + 1 < args.len() => {
+                num_clients = args[i + 1].parse().unwrap_or(1000);
+                i += 1;
             }
-            "-m" | "--messages" => {
-                if i + 1 < args.len() {
-                    messages_per_client = args[i + 1].parse().unwrap_or(100);
-                    i += 1;
-                }
+            "-m" | "--messages" if i + 1 < args.len() => {
+                messages_per_client = args[i + 1].parse().unwrap_or(100);
+                i += 1;
             }
-            "-s" | "--size" => {
-                if i + 1 < args.len() {
-                    message_size = args[i + 1].parse().unwrap_or(128);
-                    i += 1;
-                }
+            "-s" | "--size" if i + 1 < args.len() => {
+                message_size = args[i + 1].parse().unwrap_or(128);
+                i += 1;
             }
-            "--max-concurrent" => {
-                if i + 1 < args.len() {
-                    max_concurrent = args[i + 1].parse().unwrap_or(200);
-                    i += 1;
-                }
+            "--max-concurrent" if i + 1 < args.len() => {
+                max_concurrent = args[i + 1].parse().unwrap_or(200);
+                i += 1;
             }
-            "--connect-timeout" => {
-                if i + 1 < args.len() {
-                    connect_timeout_secs =
-                        args[i + 1].parse().unwrap_or(DEFAULT_CONNECT_TIMEOUT_SECS);
-                    i += 1;
-                }
+            "--connect-timeout" if i + 1 < args.len() => {
+                connect_timeout_secs = args[i + 1].parse().unwrap_or(DEFAULT_CONNECT_TIMEOUT_SECS);
+                i += 1;
             }
-            "--warmup" => {
-                if i + 1 < args.len() {
-                    warmup_ms = args[i + 1].parse().unwrap_or(100);
-                    i += 1;
-                }
+            "--warmup" if i + 1 < args.len() => {
+                warmup_ms = args[i + 1].parse().unwrap_or(100);
+                i += 1;
             }
             "-j" | "--json" => {
                 json = true;
@@ -403,7 +386,7 @@ async fn run_client(
 
     let mut stream = stream;
 
-    let key = generate_websocket_key(client_id);
+    let key = generate_key();
     let request = format!(
         "GET / HTTP/1.1\r\n\
          Host: {}\r\n\
@@ -441,11 +424,7 @@ async fn run_client(
         Err(_) => return Err("Handshake timeout".into()),
     }
 
-    let response = HandshakeResponse::parse(&response_bytes)?;
-    let expected_accept = compute_accept_key(&key);
-    if response.accept != expected_accept {
-        return Err("Invalid Sec-WebSocket-Accept".into());
-    }
+    HandshakeResponse::parse(&response_bytes)?.validate(&key)?;
 
     metrics.connection_success();
 
@@ -491,20 +470,6 @@ async fn run_client(
     Ok(())
 }
 
-fn generate_websocket_key(seed: usize) -> String {
-    use std::time::{SystemTime, UNIX_EPOCH};
-    let time_seed = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_nanos();
-    let combined = time_seed.wrapping_add(seed as u128);
-    let mut bytes = [0u8; 16];
-    for (i, b) in bytes.iter_mut().enumerate() {
-        *b = ((combined >> (i * 4)) & 0xFF) as u8;
-    }
-    base64::Engine::encode(&base64::engine::general_purpose::STANDARD, bytes)
-}
-
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let config = parse_args();
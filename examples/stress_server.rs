@@ -153,26 +153,20 @@ fn parse_args() -> (String, u16, bool, u64) {
     let mut i = 1;
     while i < args.len() {
         match args[i].as_str() {
-            "-h" | "--host" => {
-                if i + 1 < args.len() {
-                    host = args[i + 1].clone();
-                    i += 1;
-                }
+            "-h" | "--host" if i + 1 < args.len() => {
+                host = args[i + 1].clone();
+                i += 1;
             }
-            "-p" | "--port" => {
-                if i + 1 < args.len() {
-                    port = args[i + 1].parse().unwrap_or(9001);
-                    i += 1;
-                }
+            "-p" | "--port" if i + 1 < args.len() => {
+                port = args[i + 1].parse().unwrap_or(9001);
+                i += 1;
             }
             "-j" | "--json" => {
                 json = true;
             }
-            "-i" | "--interval" => {
-                if i + 1 < args.len() {
-                    report_interval = args[i + 1].parse().unwrap_or(5);
-                    i += 1;
-                }
+            "-i" | "--interval" if i + 1 < args.len() => {
+                report_interval = args[i + 1].parse().unwrap_or(5);
+                i += 1;
             }
             "--help" => {
                 println!("WebSocket Stress Test Server");
@@ -199,7 +193,7 @@ fn parse_args() -> (String, u16, bool, u64) {
 async fn handle_connection(stream: TcpStream, metrics: Arc<ServerMetrics>) {
     metrics.connection_opened();
 
-    if let Err(_) = handle_connection_inner(stream, &metrics).await {
+    if handle_connection_inner(stream, &metrics).await.is_err() {
         metrics.error();
     }
 
@@ -1,21 +1,19 @@
 //! TLS WebSocket client example using rustls.
 //!
-//! Run with: cargo run --example wss_client --features tls-rustls
+//! Run with: cargo run --example wss_client --features rustls-aws-lc-rs
 //!
 //! Connects to a public WebSocket echo server over TLS.
 
-#[cfg(not(feature = "tls-rustls"))]
+#[cfg(not(any(feature = "rustls-aws-lc-rs", feature = "rustls-ring")))]
 fn main() {
-    eprintln!("This example requires the 'tls-rustls' feature.");
-    eprintln!("Run with: cargo run --example wss_client --features tls-rustls");
+    eprintln!("This example requires the 'rustls-aws-lc-rs' feature.");
+    eprintln!("Run with: cargo run --example wss_client --features rustls-aws-lc-rs");
 }
 
-#[cfg(feature = "tls-rustls")]
+#[cfg(any(feature = "rustls-aws-lc-rs", feature = "rustls-ring"))]
 mod inner {
     use rsws::tls::TlsConnector;
-    use rsws::{
-        CloseCode, Config, Connection, HandshakeResponse, Message, Role, compute_accept_key,
-    };
+    use rsws::{CloseCode, Config, Connection, HandshakeResponse, Message, Role, generate_key};
     use rustls::ClientConfig;
     use std::error::Error;
     use std::sync::Arc;
@@ -36,7 +34,7 @@ mod inner {
 
         println!("TLS connection established");
 
-        let key = base64_encode_random_key();
+        let key = generate_key();
         let request = format!(
             "GET / HTTP/1.1\r\n\
              Host: {}\r\n\
@@ -60,11 +58,7 @@ mod inner {
             }
         }
 
-        let response = HandshakeResponse::parse(&response_bytes)?;
-        let expected_accept = compute_accept_key(&key);
-        if response.accept != expected_accept {
-            return Err("Invalid Sec-WebSocket-Accept".into());
-        }
+        HandshakeResponse::parse(&response_bytes)?.validate(&key)?;
         println!("WebSocket handshake complete");
 
         let config = Config::client();
@@ -106,25 +100,9 @@ mod inner {
 
         Ok(config)
     }
-
-    fn base64_encode_random_key() -> String {
-        use std::time::{SystemTime, UNIX_EPOCH};
-
-        let seed = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_nanos();
-
-        let mut bytes = [0u8; 16];
-        for (i, b) in bytes.iter_mut().enumerate() {
-            *b = ((seed >> (i * 4)) & 0xFF) as u8;
-        }
-
-        base64::Engine::encode(&base64::engine::general_purpose::STANDARD, bytes)
-    }
 }
 
-#[cfg(feature = "tls-rustls")]
+#[cfg(any(feature = "rustls-aws-lc-rs", feature = "rustls-ring"))]
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     inner::run().await
@@ -0,0 +1,176 @@
+/* rsws C API. Generated from src/ffi.rs by cbindgen; do not edit. */
+
+#ifndef RSWS_H
+#define RSWS_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * The call succeeded.
+ */
+#define RSWS_OK 0
+
+/**
+ * The connection is closed; no more messages will arrive.
+ */
+#define RSWS_CLOSED 1
+
+/**
+ * The call failed; see [`rsws_last_error`].
+ */
+#define RSWS_ERROR -1
+
+/**
+ * An argument was null or otherwise invalid.
+ */
+#define RSWS_INVALID -2
+
+/**
+ * [`RswsMessage::kind`] of a text message.
+ */
+#define RSWS_MESSAGE_TEXT 1
+
+/**
+ * [`RswsMessage::kind`] of a binary message.
+ */
+#define RSWS_MESSAGE_BINARY 2
+
+/**
+ * [`RswsMessage::kind`] of a close frame.
+ */
+#define RSWS_MESSAGE_CLOSE 8
+
+/**
+ * [`RswsMessage::kind`] of a ping.
+ */
+#define RSWS_MESSAGE_PING 9
+
+/**
+ * [`RswsMessage::kind`] of a pong.
+ */
+#define RSWS_MESSAGE_PONG 10
+
+/**
+ * A WebSocket connection owned by C code.
+ */
+typedef struct RswsConnection RswsConnection;
+
+/**
+ * A received message. `data` points into the connection and stays valid
+ * until the next call to `rsws_recv` or `rsws_connection_free`.
+ */
+typedef struct {
+  /**
+   * One of the `RSWS_MESSAGE_*` constants.
+   */
+  uint8_t kind;
+  /**
+   * Payload; for close frames, the reason.
+   */
+  const uint8_t *data;
+  /**
+   * Payload length in bytes.
+   */
+  size_t len;
+  /**
+   * Status code of a close frame, `0` otherwise or if none was sent.
+   */
+  uint16_t close_code;
+} RswsMessage;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Wrap a connected socket whose opening handshake has already completed.
+ *
+ * The connection takes ownership of `fd` and closes it when freed. Pass a
+ * non-zero `is_client` if this end opened the connection, so outgoing
+ * frames are masked. Returns null if `fd` cannot be used; a non-negative
+ * `fd` has been closed by then, so the caller never closes it either way.
+ *
+ * # Safety
+ *
+ * `fd` must be an open stream socket that no other code closes or uses
+ * afterwards, even if null is returned.
+ */
+RswsConnection *rsws_connection_from_fd(int fd, int is_client);
+
+/**
+ * Send a text message. `data` must be `len` bytes of valid UTF-8.
+ *
+ * # Safety
+ *
+ * `conn` must come from [`rsws_connection_from_fd`] and not be freed, and
+ * `data` must point to `len` readable bytes.
+ */
+int32_t rsws_send_text(RswsConnection *conn, const uint8_t *data, size_t len);
+
+/**
+ * Send a binary message.
+ *
+ * # Safety
+ *
+ * `conn` must come from [`rsws_connection_from_fd`] and not be freed, and
+ * `data` must point to `len` readable bytes.
+ */
+int32_t rsws_send_binary(RswsConnection *conn, const uint8_t *data, size_t len);
+
+/**
+ * Block until the next message arrives and describe it in `out`.
+ *
+ * Returns `RSWS_CLOSED` once the connection has closed. Pings are
+ * answered automatically but still reported.
+ *
+ * # Safety
+ *
+ * `conn` must come from [`rsws_connection_from_fd`] and not be freed, and
+ * `out` must point to a writable `RswsMessage`.
+ */
+int32_t rsws_recv(RswsConnection *conn, RswsMessage *out);
+
+/**
+ * Start the closing handshake with `code` and an optional NUL-terminated
+ * `reason` (null for none). Keep calling [`rsws_recv`] until it returns
+ * `RSWS_CLOSED` to complete it.
+ *
+ * # Safety
+ *
+ * `conn` must come from [`rsws_connection_from_fd`] and not be freed, and
+ * `reason` must be null or a valid NUL-terminated string.
+ */
+int32_t rsws_close(RswsConnection *conn, uint16_t code, const char *reason);
+
+/**
+ * Description of the failure if the last call on `conn` returned
+ * `RSWS_ERROR`, or null. Each send, receive or close clears it first, so
+ * the string stays valid until the next one.
+ *
+ * # Safety
+ *
+ * `conn` must be null or come from [`rsws_connection_from_fd`] and not be
+ * freed.
+ */
+const char *rsws_last_error(const RswsConnection *conn);
+
+/**
+ * Free `conn` and close its socket without a closing handshake. Null is
+ * ignored.
+ *
+ * # Safety
+ *
+ * `conn` must be null or come from [`rsws_connection_from_fd`], and must
+ * not be used afterwards.
+ */
+void rsws_connection_free(RswsConnection *conn);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* RSWS_H */
@@ -0,0 +1,360 @@
+//! Frame capture and replay.
+//!
+//! A [`FrameRecorder`] is a [`FrameInterceptor`] that logs every frame a
+//! connection sends or receives, with its direction and the time since
+//! recording started, to any [`Write`]. A [`Capture`] reads such a log back,
+//! and [`Capture::replay`] feeds its inbound frames through a fresh
+//! [`Connection`], so a bug seen in production can be reproduced without the
+//! original peer:
+//!
+//! ```rust,ignore
+//! let file = BufWriter::new(File::create("session.cap")?);
+//! conn.add_interceptor(FrameRecorder::new(file)?);
+//! // ... later, in a test
+//! let capture = Capture::read(File::open("session.cap")?)?;
+//! let mut conn = capture.replay(Role::Server, Config::server());
+//! while let Some(message) = conn.recv().await? { /* ... */ }
+//! ```
+//!
+//! Frames are recorded in wire form, as interceptors see them: inbound
+//! frames before extension decoding, outbound frames after encoding.
+//! Prepared messages are written without passing through interceptors and
+//! are not recorded.
+//!
+//! ## Format
+//!
+//! A capture starts with the 8-byte magic `RSWSCAP\x01`, followed by one
+//! record per frame:
+//!
+//! | Field | Encoding |
+//! |-------|----------|
+//! | Direction | 1 byte: `0` inbound, `1` outbound |
+//! | Timestamp | LEB128 microseconds since recording started |
+//! | Header | 1 byte: FIN, RSV1-3 and opcode, as on the wire |
+//! | Length | LEB128 payload length |
+//! | Payload | Unmasked payload bytes |
+
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+use crate::connection::{Connection, FrameAction, FrameInterceptor, Role};
+use crate::protocol::{Frame, OpCode};
+use crate::testing::{MockStream, client_frame, server_frame};
+
+const MAGIC: &[u8; 8] = b"RSWSCAP\x01";
+
+/// Which way a captured frame travelled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Direction {
+    /// Read from the peer.
+    Inbound,
+    /// Written to the peer.
+    Outbound,
+}
+
+/// One frame of a [`Capture`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapturedFrame {
+    /// Which way the frame travelled.
+    pub direction: Direction,
+    /// Time since recording started.
+    pub elapsed: Duration,
+    /// The frame, unmasked.
+    pub frame: Frame,
+}
+
+/// Records every frame a connection sends or receives.
+///
+/// Register it with [`Connection::add_interceptor`]; register it first to
+/// capture inbound frames exactly as read. Each frame is written as it
+/// passes, so wrap files in a [`BufWriter`](std::io::BufWriter). If a write
+/// fails, recording stops and the connection carries on.
+pub struct FrameRecorder<W> {
+    writer: Option<W>,
+    start: Instant,
+}
+
+impl<W: Write + Send> FrameRecorder<W> {
+    /// Start a capture on `writer`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the capture header cannot be written.
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        writer.write_all(MAGIC)?;
+        Ok(Self {
+            writer: Some(writer),
+            start: Instant::now(),
+        })
+    }
+
+    /// Whether frames are still being recorded, i.e. no write has failed.
+    pub fn is_recording(&self) -> bool {
+        self.writer.is_some()
+    }
+
+    fn record(&mut self, direction: Direction, frame: &Frame) {
+        let Some(writer) = self.writer.as_mut() else {
+            return;
+        };
+        let elapsed = self.start.elapsed();
+        if let Err(_e) = write_record(writer, direction, elapsed, frame) {
+            ws_debug!(error = %_e, "frame capture failed; recording stopped");
+            self.writer = None;
+        }
+    }
+}
+
+impl<W: Write + Send> FrameInterceptor for FrameRecorder<W> {
+    fn outbound(&mut self, frame: &mut Frame) -> FrameAction {
+        self.record(Direction::Outbound, frame);
+        FrameAction::Forward
+    }
+
+    fn inbound(&mut self, frame: &mut Frame) -> FrameAction {
+        self.record(Direction::Inbound, frame);
+        FrameAction::Forward
+    }
+}
+
+fn write_record(
+    writer: &mut impl Write,
+    direction: Direction,
+    elapsed: Duration,
+    frame: &Frame,
+) -> io::Result<()> {
+    let mut head = Vec::with_capacity(24);
+    head.push(match direction {
+        Direction::Inbound => 0,
+        Direction::Outbound => 1,
+    });
+    write_varint(&mut head, elapsed.as_micros() as u64);
+    head.push(header_byte(frame));
+    write_varint(&mut head, frame.payload().len() as u64);
+    writer.write_all(&head)?;
+    writer.write_all(frame.payload())
+}
+
+fn header_byte(frame: &Frame) -> u8 {
+    (u8::from(frame.fin) << 7)
+        | (u8::from(frame.rsv1) << 6)
+        | (u8::from(frame.rsv2) << 5)
+        | (u8::from(frame.rsv3) << 4)
+        | frame.opcode as u8
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn read_varint(reader: &mut impl Read) -> io::Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = read_u8(reader)?;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(invalid("varint too long"))
+}
+
+fn read_u8(reader: &mut impl Read) -> io::Result<u8> {
+    let mut byte = [0];
+    reader.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid capture: {msg}"),
+    )
+}
+
+/// A recorded sequence of frames.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Capture {
+    frames: Vec<CapturedFrame>,
+}
+
+impl Capture {
+    /// Read a capture written by a [`FrameRecorder`]. A record cut short at
+    /// the end, as left by a process that died mid-write, is ignored.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading fails or the data is not a capture.
+    pub fn read(mut reader: impl Read) -> io::Result<Self> {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("bad magic"));
+        }
+
+        let mut frames = Vec::new();
+        loop {
+            let direction = match read_u8(&mut reader) {
+                Ok(0) => Direction::Inbound,
+                Ok(1) => Direction::Outbound,
+                Ok(_) => return Err(invalid("bad direction")),
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e),
+            };
+            match read_frame(&mut reader) {
+                Ok((elapsed, frame)) => frames.push(CapturedFrame {
+                    direction,
+                    elapsed,
+                    frame,
+                }),
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(Self { frames })
+    }
+
+    /// The recorded frames, in order.
+    pub fn frames(&self) -> &[CapturedFrame] {
+        &self.frames
+    }
+
+    /// The recorded frames that travelled in `direction`.
+    pub fn frames_in(&self, direction: Direction) -> impl Iterator<Item = &Frame> {
+        self.frames
+            .iter()
+            .filter(move |captured| captured.direction == direction)
+            .map(|captured| &captured.frame)
+    }
+
+    /// A stream that yields the inbound frames, encoded as the peer of a
+    /// `role` endpoint sent them, and then end-of-stream. What is written to
+    /// it is kept for inspection.
+    #[must_use]
+    pub fn to_stream(&self, role: Role) -> MockStream {
+        let encode = if role == Role::Server {
+            client_frame
+        } else {
+            server_frame
+        };
+        MockStream::new(
+            self.frames_in(Direction::Inbound)
+                .flat_map(encode)
+                .collect(),
+        )
+    }
+
+    /// A connection that receives the captured inbound frames, as the
+    /// recording endpoint did. Timing is not reproduced: frames are
+    /// available as fast as the connection reads them.
+    #[must_use]
+    pub fn replay(&self, role: Role, config: Config) -> Connection<MockStream> {
+        Connection::new(self.to_stream(role), role, config)
+    }
+}
+
+fn read_frame(reader: &mut impl Read) -> io::Result<(Duration, Frame)> {
+    let elapsed = Duration::from_micros(read_varint(reader)?);
+    let head = read_u8(reader)?;
+    let opcode = OpCode::from_u8(head & 0x0f).map_err(|_| invalid("bad opcode"))?;
+    let len = usize::try_from(read_varint(reader)?).map_err(|_| invalid("frame too large"))?;
+    let mut payload = Vec::new();
+    reader.take(len as u64).read_to_end(&mut payload)?;
+    if payload.len() != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    let mut frame = Frame::new(head & 0x80 != 0, opcode, payload);
+    frame.rsv1 = head & 0x40 != 0;
+    frame.rsv2 = head & 0x20 != 0;
+    frame.rsv3 = head & 0x10 != 0;
+    Ok((elapsed, frame))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Message;
+    use crate::testing::{MockStream, client_frame};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_record_and_replay() {
+        let mut input = client_frame(&Frame::new(false, OpCode::Text, b"hel".to_vec()));
+        input.extend(client_frame(&Frame::new(
+            true,
+            OpCode::Continuation,
+            b"lo".to_vec(),
+        )));
+        input.extend(client_frame(&Frame::binary(vec![0; 300])));
+        let buf = SharedBuf::default();
+        let mut conn = Connection::new(MockStream::new(input), Role::Server, Config::server());
+        conn.add_interceptor(FrameRecorder::new(buf.clone()).unwrap());
+        assert_eq!(conn.recv().await.unwrap(), Some(Message::text("hello")));
+        conn.send(Message::text("reply")).await.unwrap();
+        assert_eq!(
+            conn.recv().await.unwrap(),
+            Some(Message::binary(vec![0; 300]))
+        );
+
+        let capture = Capture::read(&buf.0.lock().unwrap()[..]).unwrap();
+        let directions: Vec<_> = capture.frames().iter().map(|c| c.direction).collect();
+        assert_eq!(
+            directions,
+            [
+                Direction::Inbound,
+                Direction::Inbound,
+                Direction::Outbound,
+                Direction::Inbound
+            ]
+        );
+        assert_eq!(capture.frames()[2].frame, Frame::text("reply"));
+        assert!(!capture.frames()[0].frame.fin);
+
+        let mut replayed = capture.replay(Role::Server, Config::server());
+        assert_eq!(replayed.recv().await.unwrap(), Some(Message::text("hello")));
+        assert_eq!(
+            replayed.recv().await.unwrap(),
+            Some(Message::binary(vec![0; 300]))
+        );
+        assert_eq!(replayed.recv().await.unwrap(), None);
+    }
+
+    #[test]
+    fn test_read_rejects_other_data_and_ignores_torn_records() {
+        assert!(Capture::read(&b"not a capture"[..]).is_err());
+
+        let mut data = MAGIC.to_vec();
+        write_record(
+            &mut data,
+            Direction::Outbound,
+            Duration::ZERO,
+            &Frame::ping("p"),
+        )
+        .unwrap();
+        let full = Capture::read(&data[..]).unwrap();
+        assert_eq!(full.frames().len(), 1);
+
+        data.extend_from_slice(&[0, 5, 0x81, 10, b'x']);
+        assert_eq!(Capture::read(&data[..]).unwrap(), full);
+    }
+}
@@ -0,0 +1,209 @@
+//! Dual-stack connection racing (RFC 8305).
+
+use std::future::{Future, poll_fn};
+use std::io;
+use std::net::SocketAddr;
+use std::pin::pin;
+use std::task::Poll;
+use std::time::Duration;
+
+use tokio::net::TcpStream;
+use tokio::task::JoinSet;
+
+use crate::socket::SocketOptions;
+use crate::task;
+
+/// Happy Eyeballs settings for hosts with both IPv6 and IPv4 addresses.
+///
+/// Instead of waiting for each address to fail in turn, the connector starts
+/// a new attempt every `connection_attempt_delay` while earlier ones are
+/// still pending, alternating address families, and keeps the first socket
+/// that connects (RFC 8305).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HappyEyeballs {
+    /// Time to wait for an attempt before starting the next one.
+    ///
+    /// Default: 250 ms, as recommended by RFC 8305 Section 5.
+    pub connection_attempt_delay: Duration,
+
+    /// Number of addresses of the preferred family (that of the first
+    /// resolved address) tried before switching to the other family.
+    ///
+    /// Default: 1
+    pub first_address_family_count: usize,
+}
+
+impl Default for HappyEyeballs {
+    fn default() -> Self {
+        Self {
+            connection_attempt_delay: Duration::from_millis(250),
+            first_address_family_count: 1,
+        }
+    }
+}
+
+impl HappyEyeballs {
+    /// Set the delay between connection attempts.
+    #[must_use]
+    pub fn with_connection_attempt_delay(mut self, delay: Duration) -> Self {
+        self.connection_attempt_delay = delay;
+        self
+    }
+
+    /// Set how many addresses of the preferred family are tried first.
+    #[must_use]
+    pub fn with_first_address_family_count(mut self, count: usize) -> Self {
+        self.first_address_family_count = count.max(1);
+        self
+    }
+}
+
+/// Order addresses for racing: `first_count` addresses of the first
+/// address's family, then alternate families (RFC 8305 Section 4).
+pub(crate) fn interleave(addrs: Vec<SocketAddr>, first_count: usize) -> Vec<SocketAddr> {
+    let Some(first) = addrs.first() else {
+        return addrs;
+    };
+    let prefer_v6 = first.is_ipv6();
+    let (mut preferred, mut other): (Vec<_>, Vec<_>) =
+        addrs.into_iter().partition(|a| a.is_ipv6() == prefer_v6);
+    preferred.reverse();
+    other.reverse();
+
+    let mut ordered = Vec::with_capacity(preferred.len() + other.len());
+    ordered.extend(std::iter::from_fn(|| preferred.pop()).take(first_count));
+    loop {
+        match (other.pop(), preferred.pop()) {
+            (None, None) => break,
+            (a, b) => ordered.extend(a.into_iter().chain(b)),
+        }
+    }
+    ordered
+}
+
+/// Connect to the first address that accepts, starting a new attempt every
+/// `delay` or as soon as the previous one fails.
+pub(crate) async fn race(
+    addrs: Vec<SocketAddr>,
+    delay: Duration,
+    options: &SocketOptions,
+) -> io::Result<TcpStream> {
+    let mut pending = addrs.into_iter();
+    let mut attempts = JoinSet::new();
+    let mut last_error = None;
+
+    if let Some(addr) = pending.next() {
+        let options = options.clone();
+        task::spawn_in(&mut attempts, task::CONNECT_ATTEMPT, async move {
+            options.connect(addr).await
+        });
+    }
+
+    while !attempts.is_empty() {
+        let more = pending.len() > 0;
+        let mut timer = pin!(tokio::time::sleep(delay));
+        let joined = poll_fn(|cx| {
+            if let Poll::Ready(Some(joined)) = attempts.poll_join_next(cx) {
+                return Poll::Ready(Some(joined));
+            }
+            if more && timer.as_mut().poll(cx).is_ready() {
+                return Poll::Ready(None);
+            }
+            Poll::Pending
+        })
+        .await;
+
+        match joined {
+            Some(Ok(Ok(stream))) => return Ok(stream),
+            Some(Ok(Err(e))) => last_error = Some(e),
+            Some(Err(join_err)) if join_err.is_panic() => {
+                std::panic::resume_unwind(join_err.into_panic())
+            }
+            // Cancelled, e.g. by a runtime shutting down: a failed attempt
+            Some(Err(join_err)) => last_error = Some(io::Error::other(join_err)),
+            // The delay passed with no result: start another attempt alongside
+            None => {}
+        }
+        if let Some(addr) = pending.next() {
+            let options = options.clone();
+            task::spawn_in(&mut attempts, task::CONNECT_ATTEMPT, async move {
+                options.connect(addr).await
+            });
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, "host resolved to no addresses")
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_interleave_families() {
+        let addrs = vec![
+            addr("[::1]:1"),
+            addr("[::2]:1"),
+            addr("[::3]:1"),
+            addr("10.0.0.1:1"),
+            addr("10.0.0.2:1"),
+        ];
+        assert_eq!(
+            interleave(addrs.clone(), 1),
+            [
+                addr("[::1]:1"),
+                addr("10.0.0.1:1"),
+                addr("[::2]:1"),
+                addr("10.0.0.2:1"),
+                addr("[::3]:1"),
+            ]
+        );
+        assert_eq!(
+            interleave(addrs, 2),
+            [
+                addr("[::1]:1"),
+                addr("[::2]:1"),
+                addr("10.0.0.1:1"),
+                addr("[::3]:1"),
+                addr("10.0.0.2:1"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_race_skips_refused_address() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let good = listener.local_addr().unwrap();
+
+        // Grab a free port and close it so connecting is refused
+        let refused = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        let stream = race(
+            vec![refused, good],
+            Duration::from_secs(10),
+            &SocketOptions::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), good);
+    }
+
+    #[tokio::test]
+    async fn test_race_no_addresses() {
+        let err = race(Vec::new(), Duration::from_millis(50), &SocketOptions::new())
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+}
@@ -0,0 +1,764 @@
+//! A WebSocket client connector.
+//!
+//! [`Connector`] resolves the host, opens the TCP connection and runs the
+//! opening handshake for a `ws://` URL:
+//!
+//! ```rust,ignore
+//! use rsws::client::Connector;
+//! use rsws::Message;
+//!
+//! let (mut conn, _response) = Connector::new().connect("ws://localhost:9001/chat").await?;
+//! conn.send(Message::text("hello")).await?;
+//! ```
+//!
+//! Host names are looked up by a [`Resolver`], the system resolver unless
+//! another is installed. When a host has both IPv6 and IPv4 addresses,
+//! connection attempts are raced as described by [`HappyEyeballs`]. For `wss://` or custom
+//! transports, [`Connector::connect_with`] wraps the TCP stream before the
+//! upgrade; or connect with [`Connector::open`] or [`Connector::connect_tcp`],
+//! wrap the stream and finish with [`handshake`], outside the connector's
+//! deadline.
+//!
+//! Connections can be tunneled through an HTTP proxy with
+//! [`Connector::with_proxy`], or through whatever `HTTP_PROXY`,
+//! `HTTPS_PROXY` and `NO_PROXY` name with [`Connector::with_proxy_from_env`].
+//!
+//! [`ReconnectingClient`] keeps a connection to one URL alive across drops,
+//! with [`ReconnectHooks`] to restore session state after each reconnect.
+
+use std::convert::Infallible;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::codec::{handshake_deadline, read_head, with_deadline};
+use crate::config::Config;
+use crate::connection::{Connection, Role};
+use crate::error::{Error, Result, Source};
+use crate::protocol::{HandshakeRequest, HandshakeResponse, generate_key};
+use crate::socket::SocketOptions;
+
+mod happy_eyeballs;
+mod proxy;
+mod reconnect;
+mod resolver;
+
+pub use happy_eyeballs::HappyEyeballs;
+pub use proxy::{Proxy, ProxySettings};
+pub use reconnect::{Backoff, HookFuture, NoHooks, ReconnectHooks, ReconnectingClient};
+pub use resolver::{ResolveFuture, Resolver, StaticResolver, SystemResolver};
+
+/// Opens client connections.
+#[derive(Clone)]
+pub struct Connector {
+    config: Config,
+    happy_eyeballs: Option<HappyEyeballs>,
+    resolver: Arc<dyn Resolver>,
+    socket: SocketOptions,
+    proxy: ProxySettings,
+    protocols: Vec<String>,
+    protocol_required: bool,
+}
+
+impl Connector {
+    /// A connector with [`Config::client`] defaults and Happy Eyeballs
+    /// enabled.
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            config: Config::client(),
+            happy_eyeballs: Some(HappyEyeballs::default()),
+            resolver: Arc::new(SystemResolver),
+            socket: SocketOptions::default(),
+            proxy: ProxySettings::default(),
+            protocols: Vec::new(),
+            protocol_required: false,
+        }
+    }
+
+    /// Set the configuration for connections and their handshake.
+    #[must_use]
+    pub fn with_config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Set how dual-stack hosts are raced.
+    #[must_use]
+    pub fn with_happy_eyeballs(mut self, happy_eyeballs: HappyEyeballs) -> Self {
+        self.happy_eyeballs = Some(happy_eyeballs);
+        self
+    }
+
+    /// Look up host names with `resolver` instead of the system resolver.
+    #[must_use]
+    pub fn with_resolver(mut self, resolver: impl Resolver + 'static) -> Self {
+        self.resolver = Arc::new(resolver);
+        self
+    }
+
+    /// Tune the TCP sockets this connector opens.
+    #[must_use]
+    pub fn with_socket_options(mut self, options: SocketOptions) -> Self {
+        self.socket = options;
+        self
+    }
+
+    /// Tunnel every connection through `proxy`.
+    #[must_use]
+    pub fn with_proxy(self, proxy: Proxy) -> Self {
+        self.with_proxy_settings(ProxySettings::all(proxy))
+    }
+
+    /// Choose a proxy per URL from `settings`.
+    #[must_use]
+    pub fn with_proxy_settings(mut self, settings: ProxySettings) -> Self {
+        self.proxy = settings;
+        self
+    }
+
+    /// Use the proxies named by `HTTP_PROXY`, `HTTPS_PROXY` and `NO_PROXY`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidUrl` if a proxy variable cannot be parsed.
+    pub fn with_proxy_from_env(self) -> Result<Self> {
+        Ok(self.with_proxy_settings(ProxySettings::from_env()?))
+    }
+
+    /// Offer `protocols` in `Sec-WebSocket-Protocol`, most preferred first.
+    ///
+    /// The server may pick one of them or none.
+    #[must_use]
+    pub fn with_protocols(mut self, protocols: Vec<String>) -> Self {
+        self.protocols = protocols;
+        self.protocol_required = false;
+        self
+    }
+
+    /// Offer `protocols` and fail the handshake with
+    /// `Error::MissingSubprotocol` unless the server picks one of them.
+    #[must_use]
+    pub fn with_required_protocols(mut self, protocols: Vec<String>) -> Self {
+        self.protocols = protocols;
+        self.protocol_required = true;
+        self
+    }
+
+    /// Try resolved addresses one at a time, in resolver order.
+    #[must_use]
+    pub fn without_happy_eyeballs(mut self) -> Self {
+        self.happy_eyeballs = None;
+        self
+    }
+
+    /// The configuration applied to new connections.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Connect to a `ws://` URL and run the opening handshake.
+    ///
+    /// Resolving, connecting, tunneling and the upgrade together must finish
+    /// within `timeouts.handshake` (its default if no timeouts are set).
+    ///
+    /// # Errors
+    ///
+    /// - `Error::InvalidUrl` if the URL is malformed or not `ws://`; see
+    ///   [`connect_with`](Self::connect_with) for `wss://`
+    /// - `Error::HandshakeTimeout` if the deadline passes first
+    /// - `Error::Io` if the host or proxy cannot be resolved or reached
+    /// - `Error::InvalidHandshake` if the proxy refuses the tunnel
+    /// - `Error::MissingSubprotocol` if protocols are required and the server
+    ///   picked none
+    /// - Handshake errors as for [`handshake`]
+    pub async fn connect(&self, url: &str) -> Result<(Connection<TcpStream>, HandshakeResponse)> {
+        let target = Target::parse(url)?;
+        if target.secure {
+            return Err(Error::InvalidUrl(
+                "wss:// needs a TLS stream; use connect_with".into(),
+            ));
+        }
+        self.connect_target(&target, |stream, _| async { Ok::<_, Infallible>(stream) })
+            .await
+    }
+
+    /// Connect to a `ws://` or `wss://` URL over the stream `wrap` makes of
+    /// the TCP connection, e.g. by a TLS handshake, and run the opening
+    /// handshake.
+    ///
+    /// `wrap` is handed the URL's host along with the stream, as the TLS
+    /// server name. The deadline of [`connect`](Self::connect) covers it
+    /// too.
+    ///
+    /// ```rust,ignore
+    /// let tls = TlsConnector::new(client_config);
+    /// let (conn, _response) = Connector::new()
+    ///     .connect_with("wss://feed.example/ws", |tcp, host| async move {
+    ///         tls.connect(&host, tcp).await
+    ///     })
+    ///     .await?;
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// As for [`connect`](Self::connect), and `Error::Tls` if `wrap` fails.
+    pub async fn connect_with<S, F, Fut, E>(
+        &self,
+        url: &str,
+        wrap: F,
+    ) -> Result<(Connection<S>, HandshakeResponse)>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+        F: FnOnce(TcpStream, String) -> Fut,
+        Fut: Future<Output = std::result::Result<S, E>>,
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        self.connect_target(&Target::parse(url)?, wrap).await
+    }
+
+    async fn connect_target<S, F, Fut, E>(
+        &self,
+        target: &Target,
+        wrap: F,
+    ) -> Result<(Connection<S>, HandshakeResponse)>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+        F: FnOnce(TcpStream, String) -> Fut,
+        Fut: Future<Output = std::result::Result<S, E>>,
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        let deadline = handshake_deadline(&self.config);
+        with_deadline(deadline, async {
+            let stream = self.open_target(target).await?;
+            let stream = wrap(stream, target.host.clone())
+                .await
+                .map_err(|e| Error::Tls(Source::new(e)))?;
+            let mut request =
+                HandshakeRequest::new(target.host_header(), &target.path, generate_key());
+            request.protocols = self.protocols.clone();
+            exchange(
+                stream,
+                &request,
+                self.config.clone(),
+                self.protocol_required,
+            )
+            .await
+        })
+        .await
+    }
+
+    /// Open a TCP stream to the host of a `ws://` or `wss://` URL, tunneled
+    /// through the proxy the connector's settings choose for it.
+    ///
+    /// The stream is ready for TLS (for `wss://`) and then [`handshake`],
+    /// which no deadline bounds; see [`connect_with`](Self::connect_with).
+    ///
+    /// # Errors
+    ///
+    /// - `Error::InvalidUrl` if the URL is malformed
+    /// - `Error::Io` if the host or proxy cannot be resolved or reached
+    /// - `Error::InvalidHandshake` if the proxy refuses the tunnel
+    pub async fn open(&self, url: &str) -> Result<TcpStream> {
+        self.open_target(&Target::parse(url)?).await
+    }
+
+    async fn open_target(&self, target: &Target) -> Result<TcpStream> {
+        let Some(proxy) = self.proxy.proxy_for(&target.host, target.secure) else {
+            return self.connect_tcp(&target.host, target.port).await;
+        };
+        ws_debug!(proxy = proxy.host(), host = %target.host, "connecting through proxy");
+        let mut stream = self.connect_tcp(proxy.host(), proxy.port()).await?;
+        proxy
+            .tunnel(&mut stream, &target.host, target.port, &self.config)
+            .await?;
+        Ok(stream)
+    }
+
+    /// Resolve `host` and open a TCP connection to it. No proxy is used.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Io` if resolution fails or no address accepts, or
+    /// whatever error a custom [`Resolver`] reports.
+    pub async fn connect_tcp(&self, host: &str, port: u16) -> Result<TcpStream> {
+        let mut addrs = match host.parse::<IpAddr>() {
+            Ok(ip) => vec![SocketAddr::new(ip, port)],
+            Err(_) => self.resolver.resolve(host, port).await?,
+        };
+        ws_debug!(host, port, addrs = addrs.len(), "resolved host");
+        if let Some(local) = self.socket.local_addr {
+            addrs.retain(|addr| addr.is_ipv6() == local.is_ipv6());
+        }
+
+        let stream = match &self.happy_eyeballs {
+            Some(he) => {
+                let addrs = happy_eyeballs::interleave(addrs, he.first_address_family_count);
+                happy_eyeballs::race(addrs, he.connection_attempt_delay, &self.socket).await?
+            }
+            None => connect_in_order(&addrs, &self.socket).await?,
+        };
+        Ok(stream)
+    }
+}
+
+/// Connect to the first address that accepts, one at a time.
+async fn connect_in_order(addrs: &[SocketAddr], options: &SocketOptions) -> io::Result<TcpStream> {
+    let mut last_error = None;
+    for &addr in addrs {
+        match options.connect(addr).await {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, "host resolved to no addresses")
+    }))
+}
+
+impl fmt::Debug for Connector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Connector")
+            .field("config", &self.config)
+            .field("happy_eyeballs", &self.happy_eyeballs)
+            .field("socket", &self.socket)
+            .field("proxy", &self.proxy)
+            .field("protocols", &self.protocols)
+            .field("protocol_required", &self.protocol_required)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for Connector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Run the client side of the opening handshake on `stream`.
+///
+/// Sends `request`, checks that the server switched protocols with the
+/// matching `Sec-WebSocket-Accept`, and wraps the stream in a client
+/// connection. Bytes the server sent after its response are kept for the
+/// connection.
+///
+/// # Errors
+///
+/// - `Error::HandshakeTooLarge` if the response exceeds `limits.max_handshake_size`
+/// - `Error::InvalidHandshake` if the server refused or answered incorrectly
+/// - `Error::UnexpectedSubprotocol` if the server selected a subprotocol not
+///   in `request.protocols`
+/// - `Error::Io` for I/O errors, including EOF before the response is complete
+pub async fn handshake<S>(
+    stream: S,
+    request: &HandshakeRequest,
+    config: Config,
+) -> Result<(Connection<S>, HandshakeResponse)>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    exchange(stream, request, config, false).await
+}
+
+/// [`handshake`], optionally failing when no subprotocol was selected.
+async fn exchange<S>(
+    mut stream: S,
+    request: &HandshakeRequest,
+    config: Config,
+    protocol_required: bool,
+) -> Result<(Connection<S>, HandshakeResponse)>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let exchange = async move {
+        let mut buf = Vec::new();
+        request.write(&mut buf)?;
+        stream.write_all(&buf).await?;
+        stream.flush().await?;
+
+        // The pace limit guards servers against slow clients; a slow server
+        // is only bounded by the connect deadline
+        let (head, rest) = read_head(&mut stream, config.limits.max_handshake_size, None).await?;
+        let response = HandshakeResponse::parse_with_compat(&head, &config.compat)?;
+        response.validate(&request.key)?;
+        response.validate_protocol(&request.protocols, protocol_required)?;
+
+        let mut conn = Connection::new(stream, Role::Client, config);
+        conn.set_subprotocol(response.protocol.clone());
+        conn.preload(&rest);
+        Ok((conn, response))
+    };
+    let result = ws_instrument!(
+        tracing::debug_span!("ws_handshake", host = %request.host, path = %request.path),
+        exchange
+    );
+    // Counted here rather than where each check fails, so one handshake
+    // counts once
+    #[cfg(feature = "metrics")]
+    if result.is_err() {
+        crate::metrics::record_handshake_failure();
+    }
+    result
+}
+
+/// The parts of a WebSocket URL the connector needs.
+#[derive(Debug, PartialEq, Eq)]
+struct Target {
+    secure: bool,
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl Target {
+    fn parse(url: &str) -> Result<Self> {
+        let invalid = |reason: &str| Error::InvalidUrl(format!("{}: {}", reason, url));
+
+        let (secure, rest) = if let Some(rest) = url.strip_prefix("ws://") {
+            (false, rest)
+        } else if let Some(rest) = url.strip_prefix("wss://") {
+            (true, rest)
+        } else {
+            return Err(invalid("expected a ws:// or wss:// URL"));
+        };
+
+        // Fragments are never sent (RFC 6455 Section 3)
+        let rest = rest.split('#').next().unwrap_or_default();
+        let (authority, path) = match rest.find(['/', '?']) {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        let path = if path.starts_with('?') {
+            format!("/{}", path)
+        } else {
+            path.to_string()
+        };
+        if authority.contains('@') {
+            return Err(invalid("credentials in URL are not supported"));
+        }
+
+        let (host, port) = if let Some(bracketed) = authority.strip_prefix('[') {
+            let end = bracketed
+                .find(']')
+                .ok_or_else(|| invalid("unterminated IPv6 address"))?;
+            (&bracketed[..end], &bracketed[end + 1..])
+        } else {
+            match authority.rfind(':') {
+                Some(i) => (&authority[..i], &authority[i..]),
+                None => (authority, ""),
+            }
+        };
+        if host.is_empty() {
+            return Err(invalid("missing host"));
+        }
+
+        let port = match port.strip_prefix(':') {
+            Some(port) => port.parse().map_err(|_| invalid("invalid port"))?,
+            None if port.is_empty() => {
+                if secure {
+                    443
+                } else {
+                    80
+                }
+            }
+            None => return Err(invalid("invalid authority")),
+        };
+
+        Ok(Self {
+            secure,
+            host: host.to_string(),
+            port,
+            path,
+        })
+    }
+
+    /// The `Host` header: the port is omitted when it is the default.
+    fn host_header(&self) -> String {
+        let host = if self.host.contains(':') {
+            format!("[{}]", self.host)
+        } else {
+            self.host.clone()
+        };
+        let default_port = if self.secure { 443 } else { 80 };
+        if self.port == default_port {
+            host
+        } else {
+            format!("{}:{}", host, self.port)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Message;
+    use crate::protocol::compute_accept_key;
+    use tokio::io::{AsyncReadExt, duplex};
+
+    #[test]
+    fn test_parse_url() {
+        let target = Target::parse("ws://example.com/chat?room=1#frag").unwrap();
+        assert_eq!(
+            target,
+            Target {
+                secure: false,
+                host: "example.com".into(),
+                port: 80,
+                path: "/chat?room=1".into(),
+            }
+        );
+        assert_eq!(target.host_header(), "example.com");
+
+        let target = Target::parse("wss://[::1]:8443").unwrap();
+        assert!(target.secure);
+        assert_eq!(target.host, "::1");
+        assert_eq!(target.port, 8443);
+        assert_eq!(target.path, "/");
+        assert_eq!(target.host_header(), "[::1]:8443");
+
+        assert_eq!(Target::parse("ws://h?x=1").unwrap().path, "/?x=1");
+    }
+
+    #[test]
+    fn test_parse_url_errors() {
+        for url in [
+            "http://example.com",
+            "ws://",
+            "ws://host:port",
+            "ws://user@host",
+            "ws://[::1",
+        ] {
+            assert!(
+                matches!(Target::parse(url), Err(Error::InvalidUrl(_))),
+                "{url}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handshake_against_server() {
+        let (client, server) = duplex(4096);
+        let server = tokio::spawn(async move {
+            let (mut conn, request) = crate::server::handshake(server, Config::server())
+                .await
+                .unwrap();
+            assert_eq!(request.path, "/echo");
+            assert_eq!(conn.subprotocol(), Some("chat"));
+            let msg = conn.recv().await.unwrap().unwrap();
+            conn.send(msg).await.unwrap();
+        });
+
+        let mut request = HandshakeRequest::new("localhost", "/echo", generate_key());
+        request.protocols = vec!["chat".into()];
+        let (mut conn, response) = handshake(client, &request, Config::client()).await.unwrap();
+        assert_eq!(response.accept, compute_accept_key(&request.key));
+        assert_eq!(conn.subprotocol(), Some("chat"));
+
+        conn.send(Message::text("round trip")).await.unwrap();
+        let echoed = conn.recv().await.unwrap().unwrap();
+        assert_eq!(echoed.as_text(), Some("round trip"));
+        server.await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_handshake_tolerates_slow_server() {
+        use std::time::Duration;
+
+        let (client, mut server) = duplex(4096);
+        let request = HandshakeRequest::new("localhost", "/", generate_key());
+        let accept = compute_accept_key(&request.key);
+        tokio::spawn(async move {
+            let mut buf = [0u8; 1024];
+            let _ = server.read(&mut buf).await;
+            let response = format!(
+                "HTTP/1.1 101 Switching Protocols\r\n\
+                 Upgrade: websocket\r\n\
+                 Connection: Upgrade\r\n\
+                 Sec-WebSocket-Accept: {accept}\r\n\r\n"
+            );
+            // Slower than the default pace a server demands of clients
+            for chunk in response.as_bytes().chunks(16) {
+                server.write_all(chunk).await.unwrap();
+                tokio::time::sleep(Duration::from_secs(10)).await;
+            }
+        });
+
+        assert!(handshake(client, &request, Config::client()).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_handshake_rejects_wrong_accept() {
+        let (client, mut server) = duplex(4096);
+        tokio::spawn(async move {
+            let mut buf = [0u8; 1024];
+            let _ = server.read(&mut buf).await;
+            server
+                .write_all(
+                    b"HTTP/1.1 101 Switching Protocols\r\n\
+                      Upgrade: websocket\r\n\
+                      Connection: Upgrade\r\n\
+                      Sec-WebSocket-Accept: d3Jvbmc=\r\n\r\n",
+                )
+                .await
+                .unwrap();
+        });
+
+        let request = HandshakeRequest::new("localhost", "/", generate_key());
+        let result = handshake(client, &request, Config::client()).await;
+        assert!(matches!(result, Err(Error::InvalidHandshake(_))));
+    }
+
+    #[tokio::test]
+    async fn test_connect_requires_offered_protocol() {
+        use tokio::net::TcpListener;
+
+        // A server that always answers without a subprotocol
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let (head, _) = read_head(&mut stream, 8192, None).await.unwrap();
+                let request = HandshakeRequest::parse(&head).unwrap();
+                let mut response = HandshakeResponse::from_request(&request);
+                response.protocol = None;
+                let mut buf = Vec::new();
+                response.write(&mut buf).unwrap();
+                stream.write_all(&buf).await.unwrap();
+            }
+        });
+
+        let url = format!("ws://{addr}/");
+        let offered = vec!["chat".to_string()];
+        let connector = Connector::new().with_protocols(offered.clone());
+        let (conn, _) = connector.connect(&url).await.unwrap();
+        assert_eq!(conn.subprotocol(), None);
+
+        let connector = Connector::new().with_required_protocols(offered);
+        let result = connector.connect(&url).await;
+        assert!(matches!(result, Err(Error::MissingSubprotocol)));
+    }
+
+    #[tokio::test]
+    async fn test_handshake_rejects_unoffered_protocol() {
+        let (client, mut server) = duplex(4096);
+        tokio::spawn(async move {
+            let (head, _) = read_head(&mut server, 8192, None).await.unwrap();
+            let request = HandshakeRequest::parse(&head).unwrap();
+            let mut response = HandshakeResponse::from_request(&request);
+            response.protocol = Some("mqtt".into());
+            let mut buf = Vec::new();
+            response.write(&mut buf).unwrap();
+            server.write_all(&buf).await.unwrap();
+        });
+
+        let mut request = HandshakeRequest::new("localhost", "/", generate_key());
+        request.protocols = vec!["chat".into()];
+        let result = handshake(client, &request, Config::client()).await;
+        assert!(matches!(result, Err(Error::UnexpectedSubprotocol(p)) if p == "mqtt"));
+    }
+
+    #[tokio::test]
+    async fn test_connect_times_out_on_silent_server() {
+        use crate::config::Timeouts;
+        use std::time::Duration;
+        use tokio::net::TcpListener;
+
+        // Accepts the connection but never answers the upgrade
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move { listener.accept().await });
+
+        let deadline = Duration::from_millis(50);
+        let timeouts = Timeouts {
+            handshake: deadline,
+            ..Timeouts::default()
+        };
+        let connector = Connector::new().with_config(Config::client().with_timeouts(timeouts));
+        let result = connector.connect(&format!("ws://{addr}/")).await;
+        assert!(matches!(result, Err(Error::HandshakeTimeout(d)) if d == deadline));
+        drop(server);
+    }
+
+    #[tokio::test]
+    async fn test_connect_with_deadline_covers_wrap() {
+        use crate::config::Timeouts;
+        use std::time::Duration;
+        use tokio::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move { listener.accept().await });
+
+        let deadline = Duration::from_millis(50);
+        let timeouts = Timeouts {
+            handshake: deadline,
+            ..Timeouts::default()
+        };
+        let connector = Connector::new().with_config(Config::client().with_timeouts(timeouts));
+        // A TLS handshake that never finishes
+        let result = connector
+            .connect_with(&format!("wss://{addr}/"), |stream, host| async move {
+                assert_eq!(host, "127.0.0.1");
+                std::future::pending::<()>().await;
+                Ok::<_, io::Error>(stream)
+            })
+            .await;
+        assert!(matches!(result, Err(Error::HandshakeTimeout(d)) if d == deadline));
+        drop(server);
+    }
+
+    #[tokio::test]
+    async fn test_connect_with_static_resolver() {
+        let mut server = crate::server::Server::bind("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(async move { server.accept().await });
+
+        let connector =
+            Connector::new().with_resolver(StaticResolver::new().with_host("ws.test", [addr]));
+        let (conn, _) = connector.connect("ws://ws.test/").await.unwrap();
+        assert!(conn.is_open());
+
+        let result = connector.connect("ws://unknown.test/").await;
+        assert!(matches!(result, Err(Error::Io(_))));
+    }
+
+    #[tokio::test]
+    async fn test_connect_through_proxy() {
+        use tokio::net::TcpListener;
+
+        let mut server = crate::server::Server::bind("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(async move { server.accept().await });
+
+        // A proxy that only tunnels to the WebSocket server
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_addr = listener.local_addr().unwrap();
+        let proxy = tokio::spawn(async move {
+            let (mut client, _) = listener.accept().await.unwrap();
+            let (head, _) = read_head(&mut client, 4096, None).await.unwrap();
+            let head = String::from_utf8(head.to_vec()).unwrap();
+            let mut upstream = TcpStream::connect(addr).await.unwrap();
+            client.write_all(b"HTTP/1.1 200 OK\r\n\r\n").await.unwrap();
+            let _ = tokio::io::copy_bidirectional(&mut client, &mut upstream).await;
+            head
+        });
+
+        let connector =
+            Connector::new().with_proxy(Proxy::http(&format!("http://{}", proxy_addr)).unwrap());
+        let (conn, _) = connector.connect("ws://ws.test:9001/").await.unwrap();
+        assert!(conn.is_open());
+        drop(conn);
+        assert!(
+            proxy
+                .await
+                .unwrap()
+                .starts_with("CONNECT ws.test:9001 HTTP/1.1\r\n")
+        );
+    }
+}
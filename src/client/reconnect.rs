@@ -0,0 +1,462 @@
+//! A client that reconnects when its connection drops.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::net::TcpStream;
+
+use super::Connector;
+use crate::connection::Connection;
+use crate::error::{Error, Result};
+use crate::message::{CloseCode, Message};
+use crate::protocol::HandshakeResponse;
+
+/// The future returned by [`ReconnectHooks`] methods.
+pub type HookFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Lifecycle callbacks for a [`ReconnectingClient`].
+///
+/// Use them to restore session state the server forgot with the old
+/// connection: authenticate and replay subscriptions in
+/// [`on_connected`](Self::on_connected). Every method has a no-op default.
+pub trait ReconnectHooks: Send + Sync {
+    /// Called after every successful handshake, including the first, before
+    /// the connection is handed to the application.
+    ///
+    /// An error drops the new connection and counts as a failed attempt:
+    /// retryable errors lead to another attempt, others are returned.
+    fn on_connected<'a>(
+        &'a self,
+        conn: &'a mut Connection<TcpStream>,
+        response: &'a HandshakeResponse,
+    ) -> HookFuture<'a, Result<()>> {
+        let _ = (conn, response);
+        Box::pin(async { Ok(()) })
+    }
+
+    /// Called when an established connection is lost. `error` is `None` if
+    /// the server closed it cleanly.
+    fn on_disconnected<'a>(&'a self, error: Option<&'a Error>) -> HookFuture<'a, ()> {
+        let _ = error;
+        Box::pin(async {})
+    }
+
+    /// Called before reconnect attempt number `attempt` (starting at 1),
+    /// after the backoff delay. Return `false` to give up.
+    fn before_reconnect(&self, attempt: u32) -> HookFuture<'_, bool> {
+        let _ = attempt;
+        Box::pin(async { true })
+    }
+}
+
+/// Hooks that do nothing.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoHooks;
+
+impl ReconnectHooks for NoHooks {}
+
+/// Delay between reconnect attempts: exponential, capped, with jitter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backoff {
+    /// Delay before the first reconnect attempt.
+    ///
+    /// Default: 500 ms
+    pub initial: Duration,
+
+    /// Upper bound for the delay, which doubles after each failed attempt.
+    ///
+    /// Default: 30 s
+    pub max: Duration,
+
+    /// Consecutive failed attempts after which the client gives up. `None`
+    /// retries forever.
+    ///
+    /// Default: None
+    pub max_attempts: Option<u32>,
+
+    /// Pick each delay at random between half and all of its nominal value,
+    /// so that clients dropped together do not reconnect together.
+    ///
+    /// Default: true
+    pub jitter: bool,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_millis(500),
+            max: Duration::from_secs(30),
+            max_attempts: None,
+            jitter: true,
+        }
+    }
+}
+
+impl Backoff {
+    /// Set the first delay.
+    #[must_use]
+    pub fn with_initial(mut self, initial: Duration) -> Self {
+        self.initial = initial;
+        self
+    }
+
+    /// Set the largest delay.
+    #[must_use]
+    pub fn with_max(mut self, max: Duration) -> Self {
+        self.max = max;
+        self
+    }
+
+    /// Give up after `attempts` consecutive failures.
+    #[must_use]
+    pub fn with_max_attempts(mut self, attempts: u32) -> Self {
+        self.max_attempts = Some(attempts);
+        self
+    }
+
+    /// Enable or disable jitter.
+    #[must_use]
+    pub fn with_jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// The delay before attempt `failures + 1`.
+    fn delay(&self, failures: u32) -> Duration {
+        let nominal = self
+            .initial
+            .saturating_mul(1 << failures.min(31))
+            .min(self.max);
+        if !self.jitter {
+            return nominal;
+        }
+        let mut random = [0u8; 4];
+        if getrandom::getrandom(&mut random).is_err() {
+            return nominal;
+        }
+        let fraction = f64::from(u32::from_le_bytes(random)) / f64::from(u32::MAX);
+        nominal.mul_f64(0.5 + fraction / 2.0)
+    }
+}
+
+/// A client connection to one URL that is re-established when it drops.
+///
+/// [`recv`](Self::recv) reconnects transparently when the connection is
+/// lost with a retryable error or closed by the server, waiting according
+/// to the [`Backoff`] between attempts. [`send`](Self::send) reports its
+/// error and leaves reconnecting to the next call, since the message may
+/// or may not have arrived.
+///
+/// Only `ws://` URLs are supported: connections are plain TCP, made by
+/// [`Connector::connect`], which refuses `wss://`.
+///
+/// ```rust,ignore
+/// struct Resubscribe;
+///
+/// impl ReconnectHooks for Resubscribe {
+///     fn on_connected<'a>(
+///         &'a self,
+///         conn: &'a mut Connection<TcpStream>,
+///         _response: &'a HandshakeResponse,
+///     ) -> HookFuture<'a, Result<()>> {
+///         Box::pin(async move { conn.send(Message::text("subscribe:prices")).await })
+///     }
+/// }
+///
+/// let mut client = ReconnectingClient::new(Connector::new(), "ws://feed.example/ws")
+///     .with_hooks(Resubscribe);
+/// while let Some(msg) = client.recv().await? {
+///     handle(msg);
+/// }
+/// ```
+pub struct ReconnectingClient {
+    connector: Connector,
+    url: String,
+    backoff: Backoff,
+    hooks: Arc<dyn ReconnectHooks>,
+    conn: Option<Connection<TcpStream>>,
+    connected_once: bool,
+    closed: bool,
+}
+
+impl ReconnectingClient {
+    /// A client for `url` that connects on first use.
+    #[must_use]
+    pub fn new(connector: Connector, url: impl Into<String>) -> Self {
+        Self {
+            connector,
+            url: url.into(),
+            backoff: Backoff::default(),
+            hooks: Arc::new(NoHooks),
+            conn: None,
+            connected_once: false,
+            closed: false,
+        }
+    }
+
+    /// Set the delay between reconnect attempts.
+    #[must_use]
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Install lifecycle hooks.
+    #[must_use]
+    pub fn with_hooks(mut self, hooks: impl ReconnectHooks + 'static) -> Self {
+        self.hooks = Arc::new(hooks);
+        self
+    }
+
+    /// The current connection, if one is established.
+    pub fn connection(&mut self) -> Option<&mut Connection<TcpStream>> {
+        self.conn.as_mut()
+    }
+
+    /// Connect now if not connected, retrying with backoff.
+    ///
+    /// # Errors
+    ///
+    /// Returns the last error once an attempt fails with a non-retryable
+    /// error, the attempt limit is reached or `before_reconnect` gives up,
+    /// and `Error::ConnectionClosed` after [`close`](Self::close).
+    pub async fn connect(&mut self) -> Result<&mut Connection<TcpStream>> {
+        if self.closed {
+            return Err(Error::ConnectionClosed(None));
+        }
+        match self.conn {
+            Some(ref mut conn) => Ok(conn),
+            None => {
+                let conn = self.reconnect().await?;
+                Ok(self.conn.insert(conn))
+            }
+        }
+    }
+
+    /// Send a message, connecting first if needed.
+    ///
+    /// If sending fails the connection is dropped and the error returned;
+    /// the next call reconnects.
+    ///
+    /// # Errors
+    ///
+    /// As for [`connect`](Self::connect) and [`Connection::send`].
+    pub async fn send(&mut self, message: Message) -> Result<()> {
+        let result = self.connect().await?.send(message).await;
+        if let Err(e) = &result {
+            self.disconnected(Some(e)).await;
+        }
+        result
+    }
+
+    /// Receive the next message, reconnecting as often as needed.
+    ///
+    /// Close frames from the server are not returned; they lead to a
+    /// reconnect. Returns `Ok(None)` only after [`close`](Self::close).
+    ///
+    /// # Errors
+    ///
+    /// Non-retryable errors from the connection, and the errors of
+    /// [`connect`](Self::connect).
+    pub async fn recv(&mut self) -> Result<Option<Message>> {
+        loop {
+            if self.closed {
+                return Ok(None);
+            }
+            match self.connect().await?.recv().await {
+                // The server closed; reconnect instead of ending the stream
+                Ok(Some(Message::Close(_)) | None) => self.disconnected(None).await,
+                Ok(Some(message)) => return Ok(Some(message)),
+                Err(e) if e.is_retryable() => self.disconnected(Some(&e)).await,
+                Err(e) => {
+                    self.disconnected(Some(&e)).await;
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    /// Close the connection and stop reconnecting.
+    ///
+    /// # Errors
+    ///
+    /// As for [`Connection::close`].
+    pub async fn close(&mut self, code: CloseCode, reason: &str) -> Result<()> {
+        self.closed = true;
+        match self.conn.take() {
+            Some(mut conn) => conn.close(code, reason).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn disconnected(&mut self, error: Option<&Error>) {
+        ws_debug!(url = %self.url, "connection lost");
+        self.conn = None;
+        self.hooks.on_disconnected(error).await;
+    }
+
+    async fn reconnect(&mut self) -> Result<Connection<TcpStream>> {
+        let mut failures = 0;
+        let mut attempt = 0;
+        let mut last_error = None;
+        loop {
+            if self.connected_once || failures > 0 {
+                attempt += 1;
+                if self.backoff.max_attempts.is_some_and(|max| failures >= max) {
+                    return Err(last_error.unwrap_or(Error::ConnectionClosed(None)));
+                }
+                tokio::time::sleep(self.backoff.delay(failures)).await;
+                if !self.hooks.before_reconnect(attempt).await {
+                    return Err(last_error.unwrap_or(Error::ConnectionClosed(None)));
+                }
+            }
+
+            let result = match self.connector.connect(&self.url).await {
+                Ok((mut conn, response)) => self
+                    .hooks
+                    .on_connected(&mut conn, &response)
+                    .await
+                    .map(|()| conn),
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(conn) => {
+                    ws_debug!(url = %self.url, failures, "connected");
+                    self.connected_once = true;
+                    return Ok(conn);
+                }
+                Err(e) if e.is_retryable() => {
+                    ws_debug!(url = %self.url, error = %e, "connection attempt failed");
+                    failures += 1;
+                    last_error = Some(e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for ReconnectingClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReconnectingClient")
+            .field("url", &self.url)
+            .field("backoff", &self.backoff)
+            .field("connected", &self.conn.is_some())
+            .field("closed", &self.closed)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::Server;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_backoff_delay() {
+        let backoff = Backoff::default()
+            .with_initial(Duration::from_millis(100))
+            .with_max(Duration::from_secs(1))
+            .with_jitter(false);
+        assert_eq!(backoff.delay(0), Duration::from_millis(100));
+        assert_eq!(backoff.delay(2), Duration::from_millis(400));
+        assert_eq!(backoff.delay(10), Duration::from_secs(1));
+        assert_eq!(backoff.delay(u32::MAX), Duration::from_secs(1));
+
+        let jittered = backoff.with_jitter(true).delay(1);
+        assert!(jittered >= Duration::from_millis(100) && jittered <= Duration::from_millis(200));
+    }
+
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<String>>);
+
+    impl ReconnectHooks for Arc<Recorder> {
+        fn on_connected<'a>(
+            &'a self,
+            conn: &'a mut Connection<TcpStream>,
+            _response: &'a HandshakeResponse,
+        ) -> HookFuture<'a, Result<()>> {
+            self.0.lock().unwrap().push("connected".into());
+            Box::pin(async move { conn.send(Message::text("auth")).await })
+        }
+
+        fn on_disconnected<'a>(&'a self, error: Option<&'a Error>) -> HookFuture<'a, ()> {
+            let event = match error {
+                Some(_) => "lost",
+                None => "closed",
+            };
+            self.0.lock().unwrap().push(event.into());
+            Box::pin(async {})
+        }
+
+        fn before_reconnect(&self, attempt: u32) -> HookFuture<'_, bool> {
+            self.0.lock().unwrap().push(format!("retry {}", attempt));
+            Box::pin(async { true })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reconnects_and_replays_hooks() {
+        let mut server = Server::bind("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(async move {
+            for n in 0..2 {
+                let (mut conn, _, _) = server.accept().await.unwrap();
+                let auth = conn.recv().await.unwrap().unwrap();
+                assert_eq!(auth.as_text(), Some("auth"));
+                conn.send(Message::text(format!("session {}", n)))
+                    .await
+                    .unwrap();
+                if n == 0 {
+                    conn.close(CloseCode::GoingAway, "restarting")
+                        .await
+                        .unwrap();
+                    let _ = conn.recv().await;
+                } else {
+                    let _ = conn.recv().await;
+                }
+            }
+        });
+
+        let recorder = Arc::new(Recorder::default());
+        let mut client = ReconnectingClient::new(Connector::new(), format!("ws://{}/", addr))
+            .with_backoff(Backoff::default().with_initial(Duration::from_millis(1)))
+            .with_hooks(recorder.clone());
+
+        let first = client.recv().await.unwrap().unwrap();
+        assert_eq!(first.as_text(), Some("session 0"));
+        let second = client.recv().await.unwrap().unwrap();
+        assert_eq!(second.as_text(), Some("session 1"));
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            ["connected", "closed", "retry 1", "connected"]
+        );
+
+        client.close(CloseCode::Normal, "").await.unwrap();
+        assert!(client.recv().await.unwrap().is_none());
+        assert!(matches!(
+            client.send(Message::text("late")).await,
+            Err(Error::ConnectionClosed(None))
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_gives_up_after_max_attempts() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let recorder = Arc::new(Recorder::default());
+        let mut client = ReconnectingClient::new(Connector::new(), format!("ws://{}/", addr))
+            .with_backoff(Backoff::default().with_max_attempts(2))
+            .with_hooks(recorder.clone());
+        let Err(err) = client.connect().await else {
+            panic!("connected to a closed port");
+        };
+        assert!(err.is_retryable());
+        assert_eq!(*recorder.0.lock().unwrap(), ["retry 1"]);
+    }
+}
@@ -0,0 +1,98 @@
+//! Host name resolution for the connector.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+
+use tokio::net::lookup_host;
+
+use crate::error::Result;
+
+/// The future returned by [`Resolver::resolve`].
+pub type ResolveFuture<'a> = Pin<Box<dyn Future<Output = Result<Vec<SocketAddr>>> + Send + 'a>>;
+
+/// Turns a host name into socket addresses for a
+/// [`Connector`](super::Connector).
+///
+/// Implement this to use another DNS client (e.g. hickory-dns), add caching,
+/// or pin host names to fixed addresses. Hosts that are IP literals never
+/// reach the resolver.
+pub trait Resolver: Send + Sync {
+    /// Resolve `host`. `port` is the port being connected to, to be used for
+    /// the returned addresses unless the resolver knows better.
+    ///
+    /// The order of the result is the order of preference.
+    fn resolve<'a>(&'a self, host: &'a str, port: u16) -> ResolveFuture<'a>;
+}
+
+/// Resolves through the operating system (`getaddrinfo`), on tokio's
+/// blocking pool. This is the default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemResolver;
+
+impl Resolver for SystemResolver {
+    fn resolve<'a>(&'a self, host: &'a str, port: u16) -> ResolveFuture<'a> {
+        Box::pin(async move { Ok(lookup_host((host, port)).await?.collect()) })
+    }
+}
+
+/// Resolves from a fixed table of host names, e.g. in tests.
+///
+/// Unknown hosts fail to resolve.
+#[derive(Debug, Clone, Default)]
+pub struct StaticResolver {
+    hosts: HashMap<String, Vec<SocketAddr>>,
+}
+
+impl StaticResolver {
+    /// An empty table.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Map `host` (case-insensitively) to `addrs`.
+    #[must_use]
+    pub fn with_host(mut self, host: &str, addrs: impl IntoIterator<Item = SocketAddr>) -> Self {
+        self.hosts
+            .insert(host.to_ascii_lowercase(), addrs.into_iter().collect());
+        self
+    }
+}
+
+impl Resolver for StaticResolver {
+    fn resolve<'a>(&'a self, host: &'a str, _port: u16) -> ResolveFuture<'a> {
+        let addrs = self.hosts.get(&host.to_ascii_lowercase()).cloned();
+        Box::pin(async move {
+            addrs.ok_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, format!("unknown host: {}", host)).into()
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+
+    #[tokio::test]
+    async fn test_static_resolver() {
+        let addr: SocketAddr = "127.0.0.1:9001".parse().unwrap();
+        let resolver = StaticResolver::new().with_host("Example.com", [addr]);
+
+        assert_eq!(resolver.resolve("example.COM", 80).await.unwrap(), [addr]);
+        assert!(matches!(
+            resolver.resolve("other.com", 80).await,
+            Err(Error::Io(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_system_resolver_localhost() {
+        let addrs = SystemResolver.resolve("localhost", 80).await.unwrap();
+        assert!(addrs.iter().all(|a| a.ip().is_loopback() && a.port() == 80));
+    }
+}
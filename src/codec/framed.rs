@@ -1,28 +1,22 @@
-use bytes::{Buf, BufMut, BytesMut};
-use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use std::collections::VecDeque;
+use std::io::{self, IoSlice};
+use std::ops::Range;
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker, ready};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::time::Instant;
 
 use crate::config::Config;
 use crate::connection::Role;
 use crate::error::{Error, Result};
 use crate::protocol::Frame;
+use crate::protocol::frame::{FrameHeader, parse_header};
+use crate::protocol::mask::{MaskGenerator, SeededMasks, apply_mask_simd};
 use crate::protocol::validation::FrameValidator;
-
-/// Generate a random seed for mask generation.
-///
-/// # Panics
-///
-/// Panics if the system's random number generator is unavailable.
-/// This is a critical security requirement - WebSocket masking MUST use
-/// cryptographically secure random values to prevent cache poisoning attacks.
-fn random_mask_seed() -> u32 {
-    let mut buf = [0u8; 4];
-    getrandom::getrandom(&mut buf).expect(
-        "Failed to obtain random bytes for WebSocket mask. \
-         This is a critical security requirement. \
-         Ensure your system has a working random number generator.",
-    );
-    u32::from_le_bytes(buf)
-}
+#[cfg(all(feature = "debug-wire", any(feature = "tracing", feature = "log")))]
+use crate::protocol::wire::WireDump;
 
 /// WebSocket frame encoder/decoder over an async I/O stream.
 ///
@@ -31,11 +25,36 @@ fn random_mask_seed() -> u32 {
 pub struct WebSocketCodec<T> {
     io: T,
     read_buf: BytesMut,
+    /// Output being written, kept until all of it is so that a write
+    /// cancelled part way is finished by the next one. Empty when nothing
+    /// is pending.
     write_buf: BytesMut,
+    /// Payloads written after the `write_buf` bytes up to each offset,
+    /// for frames sent without copying their payloads.
+    out_payloads: Vec<(usize, Bytes)>,
+    /// Bytes of the pending output already written.
+    out_written: usize,
     role: Role,
     config: Config,
-    mask_counter: u32,
+    masks: Box<dyn MaskGenerator>,
     validator: FrameValidator,
+    /// Payload of a frame lent out by `lend_payload`, dropped from
+    /// `read_buf` before the next read.
+    lent: Option<Range<usize>>,
+    /// Headers of the complete, validated frames at the front of
+    /// `read_buf`, in order, the first being the one `buffer_frame` last
+    /// returned. Popped as each frame is taken or released.
+    queued: VecDeque<FrameHeader>,
+    /// `read_buf` has grown past the high-water mark since it was last
+    /// replaced.
+    grown: bool,
+    /// Frames written in a row that fit under the write high-water mark
+    /// while `write_buf` is above it.
+    small_writes: u32,
+    /// Encoded frames held back by `write_coalescing`.
+    held: BytesMut,
+    /// When the oldest frame in `held` was added.
+    held_since: Option<Instant>,
 }
 
 impl<T> WebSocketCodec<T> {
@@ -43,42 +62,225 @@ impl<T> WebSocketCodec<T> {
     #[must_use]
     pub fn new(io: T, role: Role, config: Config) -> Self {
         let validator = FrameValidator::new(role, config.limits.clone())
-            .with_accept_unmasked(config.accept_unmasked_frames);
+            .with_accept_unmasked(config.accept_unmasked_frames)
+            .with_accept_masked(config.accept_masked_frames);
         Self {
             io,
             read_buf: BytesMut::with_capacity(config.read_buffer_size),
             write_buf: BytesMut::with_capacity(config.write_buffer_size),
+            out_payloads: Vec::new(),
+            out_written: 0,
             role,
+            masks: match config.mask_seed {
+                Some(seed) => Box::new(SeededMasks::new(seed)),
+                None => Box::new(SeededMasks::random()),
+            },
             config,
-            mask_counter: random_mask_seed(),
             validator,
+            lent: None,
+            queued: VecDeque::new(),
+            grown: false,
+            small_writes: 0,
+            held: BytesMut::new(),
+            held_since: None,
         }
     }
 
+    /// Mask outgoing frames with keys from `masks` instead of the system
+    /// random number generator, e.g. to assert exact wire bytes in tests.
+    ///
+    /// Predictable keys defeat the purpose of masking; never use a
+    /// deterministic generator on real connections.
+    #[must_use]
+    pub fn with_mask_generator(mut self, masks: impl MaskGenerator + 'static) -> Self {
+        self.masks = Box::new(masks);
+        self
+    }
+
     /// Get the role (Client or Server) of this codec.
     #[must_use]
     pub fn role(&self) -> Role {
         self.role
     }
 
+    /// Whether outgoing frames are masked: by role, unless
+    /// [`Config::masking`] overrides it.
+    #[must_use]
+    pub fn masks_outgoing(&self) -> bool {
+        self.config.masking.unwrap_or(self.role.must_mask())
+    }
+
+    /// Accept incoming frames with the RSV bits in `mask` set, as claimed by
+    /// negotiated extensions.
+    pub(crate) fn allow_rsv_bits(&mut self, mask: u8) {
+        self.validator.set_allowed_rsv_bits(mask);
+    }
+
     /// Get a reference to the configuration.
     #[must_use]
     pub fn config(&self) -> &Config {
         &self.config
     }
 
-    fn generate_mask(&mut self) -> [u8; 4] {
-        self.mask_counter = self.mask_counter.wrapping_add(0x9E37_79B9);
-        let a = self.mask_counter;
-        let b = a.wrapping_mul(0x85EB_CA6B);
-        let c = b ^ (b >> 13);
-        let d = c.wrapping_mul(0xC2B2_AE35);
-        d.to_le_bytes()
+    /// Queue bytes that were read from the stream before the codec took it
+    /// over, e.g. frames pipelined behind the handshake.
+    pub(crate) fn preload(&mut self, data: &[u8]) {
+        self.read_buf.extend_from_slice(data);
+    }
+
+    /// Drop frames held back by `write_coalescing` without writing them.
+    pub(crate) fn discard_held(&mut self) {
+        self.held.clear();
+        self.held_since = None;
+    }
+
+    /// Consume the codec, returning the stream and any bytes read from it
+    /// but not yet decoded.
+    pub(crate) fn into_parts(mut self) -> (T, BytesMut) {
+        self.release();
+        (self.io, self.read_buf)
+    }
+
+    /// Unmask the payload of the frame buffered by `buffer_frame` in place and
+    /// lend it out. It stays in the buffer until the next read.
+    pub(crate) fn lend_payload(&mut self, header: &FrameHeader) -> &[u8] {
+        let range = header.header_len..header.header_len + header.payload_len;
+        if let Some(mask) = header.mask {
+            apply_mask_simd(&mut self.read_buf[range.clone()], mask);
+        }
+        self.lent = Some(range.clone());
+        &self.read_buf[range]
+    }
+
+    /// The payload lent out by `lend_payload`, if still in the buffer.
+    pub(crate) fn lent_payload(&self) -> &[u8] {
+        self.lent.clone().map_or(&[], |range| &self.read_buf[range])
+    }
+
+    /// Drop a lent frame from the buffer.
+    fn release(&mut self) {
+        if let Some(range) = self.lent.take() {
+            self.read_buf.advance(range.end);
+            self.queued.pop_front();
+        }
+    }
+
+    /// Swap a grown read buffer for a fresh one once what is left fits.
+    ///
+    /// Frames handed out by `read_frame` share the buffer's allocation, which
+    /// would otherwise stay pinned at its peak size.
+    fn shrink_read_buf(&mut self) {
+        let size = self.config.read_buffer_size;
+        if self.grown && self.read_buf.len() <= size {
+            let mut fresh = BytesMut::with_capacity(size);
+            fresh.extend_from_slice(&self.read_buf);
+            self.read_buf = fresh;
+            self.grown = false;
+        }
+    }
+
+    /// Queue the header of the first frame, `first_len` bytes long, and of
+    /// the complete frames buffered after it, stopping at the first
+    /// incomplete or invalid one. That one is left for `buffer_frame` to
+    /// wait for or report once the queue is drained.
+    fn queue_buffered(&mut self, first: FrameHeader, first_len: usize) {
+        self.queued.push_back(first);
+        let validator = &self.validator;
+        let rules = self.config.frame_rules.as_deref().unwrap_or(validator);
+        let mut offset = first_len;
+        while let Ok(header) = parse_header(&self.read_buf[offset..]) {
+            let Ok(frame_len) = header.frame_len() else {
+                break;
+            };
+            let head = header.head();
+            if self.read_buf.len() - offset < frame_len
+                || self
+                    .config
+                    .limits
+                    .check_frame_size(head.payload_len)
+                    .is_err()
+                || rules.validate(&head, validator).is_err()
+            {
+                break;
+            }
+            offset += frame_len;
+            self.queued.push_back(header);
+        }
+    }
+
+    /// Fail if a frame of `len` bytes cannot fit in the read buffer.
+    fn check_read_cap(&self, len: usize) -> Result<()> {
+        match self.config.max_read_buffer_size {
+            Some(max) if len > max => Err(Error::FrameTooLarge { size: len, max }),
+            _ => Ok(()),
+        }
     }
 }
 
 impl<T: AsyncRead + AsyncWrite + Unpin> WebSocketCodec<T> {
+    /// Read the next frame from the stream.
+    ///
+    /// Every complete frame that arrived with it is parsed and validated in
+    /// the same pass and handed out by the following calls without another
+    /// read, so a peer that batches many small frames costs one read and one
+    /// scan of the buffer per batch. A buffered frame that fails validation
+    /// is reported once the frames before it have been returned.
+    ///
+    /// # Errors
+    ///
+    /// - Validation errors for frames that break RFC 6455 or the limits
+    /// - `Error::ConnectionClosed` on EOF
+    /// - `Error::Io` if the read fails
     pub async fn read_frame(&mut self) -> Result<Frame> {
+        let header = self.buffer_frame().await?;
+        self.take_frame(&header)
+    }
+
+    /// Read at least one frame, then return it with every other complete
+    /// frame already buffered, parsed in a single pass.
+    ///
+    /// A buffered frame that fails validation is left for the next read to
+    /// report, so the frames before it are not lost.
+    ///
+    /// # Errors
+    ///
+    /// As for [`read_frame`](Self::read_frame).
+    pub async fn read_frames(&mut self) -> Result<Vec<Frame>> {
+        let header = self.buffer_frame().await?;
+        let mut frames = vec![self.take_frame(&header)?];
+        while let Some(header) = self.queued.front().cloned() {
+            frames.push(self.take_frame(&header)?);
+        }
+        Ok(frames)
+    }
+
+    /// Take the frame buffered by `buffer_frame` out of the buffer.
+    pub(crate) fn take_frame(&mut self, header: &FrameHeader) -> Result<Frame> {
+        self.queued.pop_front();
+        Frame::split_from(&mut self.read_buf, header)
+    }
+
+    /// Read until a whole frame is buffered and return its header. The frame
+    /// stays at the front of the buffer.
+    pub(crate) async fn buffer_frame(&mut self) -> Result<FrameHeader> {
+        std::future::poll_fn(|cx| self.poll_buffer_frame(cx)).await
+    }
+
+    /// Poll version of `buffer_frame`. Bytes read before `Pending` stay
+    /// buffered, so it can be called again at any time.
+    pub(crate) fn poll_buffer_frame(&mut self, cx: &mut Context<'_>) -> Poll<Result<FrameHeader>> {
+        self.release();
+        self.shrink_read_buf();
+        if let Some(header) = self.queued.front() {
+            #[cfg(feature = "debug-wire")]
+            if let Ok(frame_len) = header.frame_len() {
+                ws_trace!(
+                    wire = %WireDump::new(&self.read_buf[..frame_len]),
+                    "read frame"
+                );
+            }
+            return Poll::Ready(Ok(header.clone()));
+        }
         loop {
             if self.read_buf.len() >= 2 {
                 // Validate frame before parsing (extract metadata from raw buffer)
@@ -113,51 +315,160 @@ impl<T: AsyncRead + AsyncWrite + Unpin> WebSocketCodec<T> {
                     _ => None,
                 };
 
-                // Validate if we have enough bytes to determine payload length
+                // Validate if we have enough bytes to determine payload
+                // length; custom rules wait for the whole header, but the
+                // size limit holds whatever they say
                 if let Some(len) = payload_len {
-                    self.validator
-                        .validate_incoming(masked, rsv1, rsv2, rsv3, len)?;
+                    if self.config.frame_rules.is_none() {
+                        self.validator
+                            .validate_incoming(masked, rsv1, rsv2, rsv3, len)?;
+                    } else {
+                        self.config.limits.check_frame_size(len)?;
+                    }
                 }
 
-                match Frame::parse(&self.read_buf) {
-                    Ok((frame, consumed)) => {
-                        self.read_buf.advance(consumed);
-                        return Ok(frame);
+                match parse_header(&self.read_buf) {
+                    Ok(header) => {
+                        if let Some(rules) = &self.config.frame_rules {
+                            rules.validate(&header.head(), &self.validator)?;
+                        }
+                        let frame_len = header.frame_len()?;
+                        self.check_read_cap(frame_len)?;
+                        if self.read_buf.len() >= frame_len {
+                            #[cfg(feature = "debug-wire")]
+                            ws_trace!(
+                                wire = %WireDump::new(&self.read_buf[..frame_len]),
+                                "read frame"
+                            );
+                            self.queue_buffered(header.clone(), frame_len);
+                            return Poll::Ready(Ok(header));
+                        }
                     }
                     Err(Error::IncompleteFrame { .. }) => {}
-                    Err(e) => return Err(e),
+                    Err(e) => return Poll::Ready(Err(e)),
                 }
             }
 
-            self.read_buf.reserve(4096);
-
-            // SAFETY: `chunk_mut()` returns uninitialized memory as `UninitSlice`.
-            // We create a raw slice to pass to `read()`, which only writes to it.
-            // We only advance by the exact number of bytes `read()` reports writing.
-            let buf = self.read_buf.chunk_mut();
-            let buf_slice =
-                unsafe { std::slice::from_raw_parts_mut(buf.as_mut_ptr(), buf.len().min(4096)) };
+            // Never read past the cap, even into the next frame
+            let want = match self.config.max_read_buffer_size {
+                Some(max) => 4096.min(max.saturating_sub(self.read_buf.len())),
+                None => 4096,
+            };
+            if want == 0 {
+                self.check_read_cap(self.read_buf.len() + 1)?;
+            }
+            self.read_buf.reserve(want);
+            if self.read_buf.capacity() > self.config.read_buffer_high_water {
+                self.grown = true;
+            }
 
-            let n = self.io.read(buf_slice).await?;
+            // SAFETY: `ReadBuf` never de-initializes memory, and we only
+            // advance by the number of bytes it reports as filled.
+            let spare = unsafe { self.read_buf.chunk_mut().as_uninit_slice_mut() };
+            let len = spare.len().min(want);
+            let mut buf = ReadBuf::uninit(&mut spare[..len]);
+            ready!(Pin::new(&mut self.io).poll_read(cx, &mut buf))?;
+            let n = buf.filled().len();
             if n == 0 {
-                return Err(Error::ConnectionClosed(None));
+                return Poll::Ready(Err(Error::ConnectionClosed(None)));
             }
 
-            // SAFETY: `read()` guarantees it initialized exactly `n` bytes.
+            // SAFETY: `poll_read()` initialized exactly `n` bytes.
             // We advance by `n` to mark those bytes as part of the buffer.
             unsafe { self.read_buf.advance_mut(n) };
 
             // Shrink buffer if it's significantly oversized to prevent memory bloat
             if self.read_buf.capacity() > self.read_buf.len() * 4
-                && self.read_buf.capacity() > 64 * 1024
+                && self.read_buf.capacity() > self.config.read_buffer_high_water
             {
                 let remaining = self.read_buf.split();
-                self.read_buf = BytesMut::with_capacity(remaining.len().max(8192));
+                self.read_buf =
+                    BytesMut::with_capacity(remaining.len().max(self.config.read_buffer_size));
                 self.read_buf.extend_from_slice(&remaining);
             }
         }
     }
 
+    /// Write several frames, such as the fragments of one message, with as
+    /// few writes as possible (does not flush).
+    ///
+    /// Unmasked frames go out in one vectored write of their headers and
+    /// payloads, straight from the borrowed frames; masked ones, and all
+    /// frames on streams without vectored writes, are encoded into one
+    /// buffer first. If the write is cancelled part way, the payloads still
+    /// to go are copied so that the next write finishes them.
+    ///
+    /// # Errors
+    ///
+    /// As for [`write_frame`](Self::write_frame).
+    pub async fn write_frames(&mut self, frames: &[Frame]) -> Result<()> {
+        for frame in frames {
+            self.config.limits.check_frame_size(frame.payload().len())?;
+        }
+
+        if !self.vectored(frames.len()) {
+            let mut held_back = true;
+            for frame in frames {
+                held_back &= self.stage(frame);
+            }
+            if held_back {
+                return Ok(());
+            }
+            return self.write_pending().await;
+        }
+
+        self.write_pending().await?;
+        let mut ends = Vec::with_capacity(frames.len());
+        for frame in frames {
+            frame.write_header_to(&mut self.write_buf, None);
+            ends.push(self.write_buf.len());
+        }
+        let mut write = LentWrite {
+            codec: self,
+            frames,
+            ends,
+            done: false,
+        };
+        std::future::poll_fn(|cx| write.poll(cx)).await
+    }
+
+    /// [`write_frames`](Self::write_frames) for frames the caller is done
+    /// with, whose payloads become the pending output as they are.
+    pub(crate) async fn send_frames(&mut self, frames: Vec<Frame>) -> Result<()> {
+        for frame in &frames {
+            self.config.limits.check_frame_size(frame.payload().len())?;
+        }
+
+        if self.vectored(frames.len()) {
+            for frame in frames {
+                frame.write_header_to(&mut self.write_buf, None);
+                let end = self.write_buf.len();
+                self.out_payloads.push((end, frame.into_payload_bytes()));
+            }
+        } else {
+            let mut held_back = true;
+            for frame in &frames {
+                held_back &= self.stage(frame);
+            }
+            if held_back {
+                return Ok(());
+            }
+        }
+        self.write_pending().await
+    }
+
+    /// Whether `count` frames should go out in one vectored write rather
+    /// than be encoded into one buffer. Streams without real vectored
+    /// writes, such as most TLS wrappers, would write each slice on its own.
+    fn vectored(&self, count: usize) -> bool {
+        count > 1
+            && self.io.is_write_vectored()
+            && self.config.write_coalescing.is_none()
+            && self.held.is_empty()
+            && !self.masks_outgoing()
+            && !cfg!(feature = "debug-wire")
+    }
+
     /// Write a frame to the underlying stream (does not flush).
     ///
     /// Clients automatically mask the frame; servers send unmasked.
@@ -171,34 +482,197 @@ impl<T: AsyncRead + AsyncWrite + Unpin> WebSocketCodec<T> {
         let payload_size = frame.payload().len();
         self.config.limits.check_frame_size(payload_size)?;
 
-        let mask = if self.role.must_mask() {
-            Some(self.generate_mask())
-        } else {
-            None
-        };
+        if self.stage(frame) {
+            return Ok(());
+        }
+        self.write_pending().await
+    }
 
+    /// Encode `frame` behind everything not yet written: into `held` if
+    /// `write_coalescing` lets it wait there, otherwise into the output of
+    /// the current write. Returns whether it was held back.
+    ///
+    /// Frames are encoded before anything is awaited, so a cancelled write
+    /// never drops part of what it was given.
+    fn stage(&mut self, frame: &Frame) -> bool {
+        let mask = self.masks_outgoing().then(|| self.masks.next_mask());
         let wire_size = frame.wire_size(mask.is_some());
-        self.write_buf.clear();
-        self.write_buf.reserve(wire_size);
-        self.write_buf.resize(wire_size, 0);
+        let coalesce = self
+            .config
+            .write_coalescing
+            .is_some_and(|coalescing| self.held.len() + wire_size < coalescing.max_bytes);
+        if coalesce {
+            self.held_since.get_or_insert_with(Instant::now);
+        }
+        let buf = if coalesce || !self.held.is_empty() {
+            &mut self.held
+        } else {
+            &mut self.write_buf
+        };
+        #[cfg(feature = "debug-wire")]
+        let start = buf.len();
+        frame.write_to(buf, mask);
+        #[cfg(feature = "debug-wire")]
+        ws_trace!(wire = %WireDump::new(&buf[start..]), "wrote frame");
+        coalesce
+    }
 
-        let written = frame.write(&mut self.write_buf, mask)?;
-        self.io.write_all(&self.write_buf[..written]).await?;
+    /// Give back a write buffer grown past the high-water mark once
+    /// `SHRINK_AFTER` frames in a row have fit under it.
+    fn shrink_write_buf(&mut self, wire_size: usize) {
+        const SHRINK_AFTER: u32 = 32;
+        let high_water = self.config.write_buffer_high_water;
+        if self.write_buf.capacity() <= high_water || wire_size > high_water {
+            self.small_writes = 0;
+            return;
+        }
+        self.small_writes += 1;
+        if self.small_writes >= SHRINK_AFTER {
+            self.write_buf = BytesMut::with_capacity(self.config.write_buffer_size);
+            self.small_writes = 0;
+        }
+    }
 
-        // Shrink write buffer if significantly oversized
-        if self.write_buf.capacity() > 64 * 1024 && self.write_buf.capacity() > wire_size * 4 {
-            self.write_buf = BytesMut::with_capacity(8192);
+    /// Write bytes that already hold one or more encoded frames.
+    pub(crate) async fn write_raw(&mut self, data: &Bytes) -> Result<()> {
+        #[cfg(feature = "debug-wire")]
+        ws_trace!(wire = %WireDump::new(data), "wrote frames");
+        if let Some(coalescing) = self.config.write_coalescing
+            && self.held.len() + data.len() < coalescing.max_bytes
+        {
+            self.held.extend_from_slice(data);
+            self.held_since.get_or_insert_with(Instant::now);
+            return Ok(());
+        }
+        if self.held.is_empty() {
+            let end = self.write_buf.len();
+            self.out_payloads.push((end, data.clone()));
+        } else {
+            self.held.extend_from_slice(data);
         }
+        self.write_pending().await
+    }
 
+    /// Encode `frame` behind everything not yet written, to be written by
+    /// `poll_write_pending` or the next write or flush.
+    pub(crate) fn encode_frame(&mut self, frame: &Frame) -> Result<()> {
+        self.config.limits.check_frame_size(frame.payload().len())?;
+        self.stage(frame);
         Ok(())
     }
 
-    /// Flush any buffered data to the underlying stream.
+    /// Finish the output of the current or a cancelled write, then write
+    /// out frames held back by `write_coalescing`.
+    async fn write_pending(&mut self) -> Result<()> {
+        std::future::poll_fn(|cx| self.poll_write_pending(cx)).await
+    }
+
+    /// Poll version of `write_pending`. What was written before `Pending`
+    /// is not written again.
+    pub(crate) fn poll_write_pending(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        ready!(self.poll_write_out(cx))?;
+        while !self.held.is_empty() {
+            let n = ready!(Pin::new(&mut self.io).poll_write(cx, &self.held))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::Error::from(io::ErrorKind::WriteZero).into()));
+            }
+            self.held.advance(n);
+        }
+        self.held.clear();
+        self.held_since = None;
+        Poll::Ready(Ok(()))
+    }
+
+    /// Write `write_buf`, with `out_payloads` in between, from where the
+    /// last call stopped.
+    fn poll_write_out(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        if !self.writing() {
+            return Poll::Ready(Ok(()));
+        }
+        loop {
+            let n = if self.out_payloads.is_empty() {
+                let rest = &self.write_buf[self.out_written..];
+                if rest.is_empty() {
+                    break;
+                }
+                ready!(Pin::new(&mut self.io).poll_write(cx, rest))?
+            } else {
+                let mut slices = Vec::with_capacity(self.out_payloads.len() * 2 + 1);
+                let mut start = 0;
+                for (end, payload) in &self.out_payloads {
+                    slices.push(IoSlice::new(&self.write_buf[start..*end]));
+                    slices.push(IoSlice::new(payload));
+                    start = *end;
+                }
+                slices.push(IoSlice::new(&self.write_buf[start..]));
+                let mut rest = &mut slices[..];
+                IoSlice::advance_slices(&mut rest, self.out_written);
+                if rest.is_empty() {
+                    break;
+                }
+                ready!(Pin::new(&mut self.io).poll_write_vectored(cx, rest))?
+            };
+            if n == 0 {
+                return Poll::Ready(Err(io::Error::from(io::ErrorKind::WriteZero).into()));
+            }
+            self.out_written += n;
+        }
+
+        let wire_size = self.write_buf.len();
+        self.write_buf.clear();
+        self.out_payloads.clear();
+        self.out_written = 0;
+        if wire_size > 0 {
+            self.shrink_write_buf(wire_size);
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    /// Whether a write was cancelled before all of its output went out.
+    fn writing(&self) -> bool {
+        !self.write_buf.is_empty() || !self.out_payloads.is_empty()
+    }
+
+    /// Poll version of [`flush`](Self::flush).
+    pub(crate) fn poll_flush(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        ready!(self.poll_write_pending(cx))?;
+        Poll::Ready(Ok(ready!(Pin::new(&mut self.io).poll_flush(cx))?))
+    }
+
+    /// Start shutting down the write side of the stream without waiting for
+    /// it to finish. Nothing still buffered is written first.
+    pub(crate) fn start_shutdown(&mut self) {
+        let mut cx = Context::from_waker(Waker::noop());
+        let _ = Pin::new(&mut self.io).poll_shutdown(&mut cx);
+    }
+
+    /// Flush any buffered data to the underlying stream, including frames
+    /// held back by `write_coalescing`.
     pub async fn flush(&mut self) -> Result<()> {
+        self.write_pending().await?;
         self.io.flush().await?;
         Ok(())
     }
 
+    /// Flush unless frames are being held back and their delay has not run
+    /// out yet.
+    pub(crate) async fn flush_due(&mut self) -> Result<()> {
+        match self.flush_deadline() {
+            Some(deadline) if Instant::now() < deadline => Ok(()),
+            _ => self.flush().await,
+        }
+    }
+
+    /// When held-back frames must be flushed, if there are any. The rest of
+    /// a cancelled write is due at once.
+    pub(crate) fn flush_deadline(&self) -> Option<Instant> {
+        if self.writing() {
+            return Some(Instant::now());
+        }
+        let delay = self.config.write_coalescing?.max_delay;
+        self.held_since.map(|since| since + delay)
+    }
+
     /// Consume the codec and return the underlying I/O stream.
     #[must_use]
     pub fn into_inner(self) -> T {
@@ -206,69 +680,68 @@ impl<T: AsyncRead + AsyncWrite + Unpin> WebSocketCodec<T> {
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::io::Cursor;
-    use std::pin::Pin;
-    use std::task::{Context, Poll};
-    use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
-
-    struct MockStream {
-        read_data: Cursor<Vec<u8>>,
-        write_data: Vec<u8>,
-    }
+/// A vectored write of frame headers in the codec's `write_buf` and
+/// payloads borrowed from the frames. Dropped before it finishes, it moves
+/// the payloads into `out_payloads`, so the pending output is complete.
+struct LentWrite<'a, T> {
+    codec: &'a mut WebSocketCodec<T>,
+    frames: &'a [Frame],
+    /// End of each frame's header in `write_buf`.
+    ends: Vec<usize>,
+    done: bool,
+}
 
-    impl MockStream {
-        fn new(data: Vec<u8>) -> Self {
-            Self {
-                read_data: Cursor::new(data),
-                write_data: Vec::new(),
+impl<T: AsyncRead + AsyncWrite + Unpin> LentWrite<'_, T> {
+    fn poll(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let codec = &mut *self.codec;
+        loop {
+            let mut slices = Vec::with_capacity(self.frames.len() * 2);
+            let mut start = 0;
+            for (frame, &end) in self.frames.iter().zip(&self.ends) {
+                slices.push(IoSlice::new(&codec.write_buf[start..end]));
+                slices.push(IoSlice::new(frame.payload()));
+                start = end;
             }
-        }
-
-        fn written(&self) -> &[u8] {
-            &self.write_data
-        }
-    }
-
-    impl AsyncRead for MockStream {
-        fn poll_read(
-            mut self: Pin<&mut Self>,
-            _cx: &mut Context<'_>,
-            buf: &mut ReadBuf<'_>,
-        ) -> Poll<std::io::Result<()>> {
-            let pos = self.read_data.position() as usize;
-            let data = self.read_data.get_ref();
-            if pos >= data.len() {
-                return Poll::Ready(Ok(()));
+            let mut rest = &mut slices[..];
+            IoSlice::advance_slices(&mut rest, codec.out_written);
+            if rest.is_empty() {
+                break;
+            }
+            let n = ready!(Pin::new(&mut codec.io).poll_write_vectored(cx, rest))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::Error::from(io::ErrorKind::WriteZero).into()));
             }
-            let remaining = &data[pos..];
-            let to_copy = std::cmp::min(remaining.len(), buf.remaining());
-            buf.put_slice(&remaining[..to_copy]);
-            self.read_data.set_position((pos + to_copy) as u64);
-            Poll::Ready(Ok(()))
+            codec.out_written += n;
         }
-    }
 
-    impl AsyncWrite for MockStream {
-        fn poll_write(
-            mut self: Pin<&mut Self>,
-            _cx: &mut Context<'_>,
-            buf: &[u8],
-        ) -> Poll<std::io::Result<usize>> {
-            self.write_data.extend_from_slice(buf);
-            Poll::Ready(Ok(buf.len()))
-        }
+        self.done = true;
+        let wire_size = codec.write_buf.len();
+        codec.write_buf.clear();
+        codec.out_written = 0;
+        codec.shrink_write_buf(wire_size);
+        Poll::Ready(Ok(()))
+    }
+}
 
-        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
-            Poll::Ready(Ok(()))
+impl<T> Drop for LentWrite<'_, T> {
+    fn drop(&mut self) {
+        if self.done {
+            return;
         }
-
-        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
-            Poll::Ready(Ok(()))
+        for (frame, &end) in self.frames.iter().zip(&self.ends) {
+            let payload = Bytes::copy_from_slice(frame.payload());
+            self.codec.out_payloads.push((end, payload));
         }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Limits, WriteCoalescing};
+    use crate::protocol::OpCode;
+    use crate::testing::{MockStream, client_frame, server_frame};
+    use std::time::Duration;
 
     #[test]
     fn test_codec_new() {
@@ -306,6 +779,80 @@ mod tests {
         assert_eq!(written.len(), 4);
     }
 
+    #[tokio::test]
+    async fn test_masking_override() {
+        let config = Config::server().with_masking(true);
+        let mut server = WebSocketCodec::new(MockStream::new(vec![]), Role::Server, config);
+        server
+            .write_frame(&Frame::text(b"Hi".to_vec()))
+            .await
+            .unwrap();
+        let written = server.io.into_written();
+        assert_eq!(written[1], 0x82);
+
+        let mut client = WebSocketCodec::new(
+            MockStream::new(written.clone()),
+            Role::Client,
+            Config::client(),
+        );
+        assert!(matches!(
+            client.read_frame().await,
+            Err(Error::MaskedServerFrame)
+        ));
+
+        let config = Config::client().with_accept_masked_frames(true);
+        let mut client = WebSocketCodec::new(MockStream::new(written), Role::Client, config);
+        assert_eq!(client.read_frame().await.unwrap().payload(), b"Hi");
+    }
+
+    #[tokio::test]
+    async fn test_frame_rules_replace_validator() {
+        use crate::protocol::{FrameHead, FrameRules};
+
+        /// Unmasked frames are fine, fragments are not.
+        #[derive(Debug)]
+        struct Lab;
+
+        impl FrameRules for Lab {
+            fn validate(&self, head: &FrameHead, _: &FrameValidator) -> Result<()> {
+                if head.fin {
+                    Ok(())
+                } else {
+                    Err(Error::ProtocolViolation("fragment".into()))
+                }
+            }
+        }
+
+        let mut data = server_frame(&Frame::text(b"plain".to_vec()));
+        data.extend(client_frame(&Frame::new(
+            false,
+            OpCode::Text,
+            b"frag".to_vec(),
+        )));
+        let config = Config::server().with_frame_rules(Lab);
+        let mut codec = WebSocketCodec::new(MockStream::new(data), Role::Server, config);
+
+        assert_eq!(codec.read_frame().await.unwrap().payload(), b"plain");
+        assert!(matches!(
+            codec.read_frame().await,
+            Err(Error::ProtocolViolation(_))
+        ));
+
+        // Skipping the default rules keeps the size limit
+        let mut data = server_frame(&Frame::text(b"ok".to_vec()));
+        data.extend(server_frame(&Frame::binary(vec![0; 2048])));
+        let config = Config::server()
+            .with_limits(Limits::new(1024, 4096, 16, 8192))
+            .with_frame_rules(Lab);
+        let mut codec = WebSocketCodec::new(MockStream::new(data), Role::Server, config);
+
+        assert_eq!(codec.read_frame().await.unwrap().payload(), b"ok");
+        assert!(matches!(
+            codec.read_frame().await,
+            Err(Error::FrameTooLarge { .. })
+        ));
+    }
+
     #[tokio::test]
     async fn test_read_frame() {
         // Server receives masked frame from client: "Hello"
@@ -366,6 +913,183 @@ mod tests {
         assert_eq!(frame2.payload(), &[0x01, 0x02]);
     }
 
+    #[tokio::test]
+    async fn test_read_frames_drains_buffer() {
+        let mut data = client_frame(&Frame::text(b"one".to_vec()));
+        data.extend(client_frame(&Frame::ping(b"p".to_vec())));
+        data.extend(server_frame(&Frame::text(b"bad".to_vec())));
+        let mut codec = WebSocketCodec::new(MockStream::new(data), Role::Server, Config::server());
+
+        let frames = codec.read_frames().await.unwrap();
+        assert_eq!(
+            frames,
+            [Frame::text(b"one".to_vec()), Frame::ping(b"p".to_vec())]
+        );
+        assert!(matches!(
+            codec.read_frames().await,
+            Err(Error::UnmaskedClientFrame)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_read_frame_queues_batched_frames() {
+        let mut data = client_frame(&Frame::text(b"one".to_vec()));
+        data.extend(client_frame(&Frame::text(b"two".to_vec())));
+        data.extend(client_frame(&Frame::ping(b"p".to_vec())));
+        data.extend(server_frame(&Frame::text(b"bad".to_vec())));
+        let mut codec = WebSocketCodec::new(MockStream::new(data), Role::Server, Config::server());
+
+        assert_eq!(codec.read_frame().await.unwrap().payload(), b"one");
+        assert_eq!(codec.queued.len(), 2);
+        // Asking again without taking the frame returns the same one
+        let header = codec.buffer_frame().await.unwrap();
+        assert_eq!(
+            codec.buffer_frame().await.unwrap().payload_len,
+            header.payload_len
+        );
+        assert_eq!(codec.read_frame().await.unwrap().payload(), b"two");
+        assert_eq!(codec.read_frame().await.unwrap().payload(), b"p");
+        assert!(codec.queued.is_empty());
+        assert!(matches!(
+            codec.read_frame().await,
+            Err(Error::UnmaskedClientFrame)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_read_unmasks_in_place() {
+        let payload: Vec<u8> = (0..1000u32).map(|i| i as u8).collect();
+        let mask = [0x0f, 0xf0, 0x55, 0xaa];
+        let mut data = vec![0x82, 0xFE, 0x03, 0xE8];
+        data.extend_from_slice(&mask);
+        data.extend(payload.iter().zip(mask.iter().cycle()).map(|(b, m)| b ^ m));
+        // A trailing frame must survive the split
+        data.extend_from_slice(&[0x89, 0x80, 0, 0, 0, 0]);
+
+        let stream = MockStream::new(data);
+        let mut codec = WebSocketCodec::new(stream, Role::Server, Config::server());
+
+        let frame = codec.read_frame().await.unwrap();
+        assert_eq!(frame.payload(), &payload[..]);
+        let ping = codec.read_frame().await.unwrap();
+        assert_eq!(ping.opcode, crate::protocol::OpCode::Ping);
+        assert!(ping.payload().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_write_frames_in_one_write() {
+        let frames = [
+            Frame::new(false, OpCode::Text, b"frag".to_vec()),
+            Frame::new(false, OpCode::Continuation, vec![b'm'; 300]),
+            Frame::new(true, OpCode::Continuation, b"ent".to_vec()),
+        ];
+
+        let mut expected = MockStream::new(vec![]);
+        for frame in &frames {
+            let mut buf = vec![0; frame.wire_size(false)];
+            frame.write(&mut buf, None).unwrap();
+            expected.write_all(&buf).await.unwrap();
+        }
+
+        let stream = MockStream::new(vec![]);
+        let mut codec = WebSocketCodec::new(stream, Role::Server, Config::server());
+        codec.write_frames(&frames).await.unwrap();
+        assert_eq!(codec.io.written(), expected.written());
+        if !cfg!(feature = "debug-wire") {
+            assert_eq!(codec.io.writes(), 1);
+        }
+
+        let stream = MockStream::new(vec![]);
+        let mut codec = WebSocketCodec::new(stream, Role::Client, Config::client());
+        codec.write_frames(&frames).await.unwrap();
+        assert_eq!(codec.io.writes(), 1);
+        let mut client = WebSocketCodec::new(
+            MockStream::new(codec.io.into_written()),
+            Role::Server,
+            Config::server(),
+        );
+        assert_eq!(client.read_frames().await.unwrap(), frames);
+
+        // Streams that write one slice at a time get one buffer instead
+        let mut codec = WebSocketCodec::new(Unvectored::default(), Role::Server, Config::server());
+        codec.write_frames(&frames).await.unwrap();
+        assert_eq!(codec.io.0.written(), expected.written());
+        assert_eq!(codec.io.0.writes(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_write_frames_finishes_on_next_write() {
+        use std::time::Duration;
+
+        let frames = [
+            Frame::new(false, OpCode::Binary, vec![1; 100]),
+            Frame::new(true, OpCode::Continuation, vec![2; 100]),
+        ];
+        let (ours, mut theirs) = tokio::io::duplex(64);
+        let mut codec = WebSocketCodec::new(ours, Role::Server, Config::server());
+
+        // Stalled on the full pipe and given up on
+        let write = tokio::time::timeout(Duration::from_millis(10), codec.write_frames(&frames));
+        assert!(write.await.is_err());
+        drop(frames);
+
+        let reader = tokio::spawn(async move {
+            let mut wire = Vec::new();
+            tokio::io::AsyncReadExt::read_to_end(&mut theirs, &mut wire)
+                .await
+                .unwrap();
+            wire
+        });
+        codec
+            .write_frame(&Frame::text(b"after".to_vec()))
+            .await
+            .unwrap();
+        drop(codec);
+        let wire = reader.await.unwrap();
+        let expected = [
+            &[0x02, 100][..],
+            &[1; 100],
+            &[0x80, 100],
+            &[2; 100],
+            &[0x81, 5],
+            b"after",
+        ]
+        .concat();
+        assert_eq!(wire, expected);
+    }
+
+    /// A [`MockStream`] without vectored writes.
+    #[derive(Default)]
+    struct Unvectored(MockStream);
+
+    impl AsyncRead for Unvectored {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.0).poll_read(cx, buf)
+        }
+    }
+
+    impl AsyncWrite for Unvectored {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            Pin::new(&mut self.0).poll_write(cx, buf)
+        }
+
+        fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.0).poll_flush(cx)
+        }
+
+        fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.0).poll_shutdown(cx)
+        }
+    }
+
     #[tokio::test]
     async fn test_flush() {
         let stream = MockStream::new(vec![]);
@@ -373,6 +1097,48 @@ mod tests {
         assert!(codec.flush().await.is_ok());
     }
 
+    #[tokio::test(start_paused = true)]
+    async fn test_write_coalescing() {
+        let config = Config::server()
+            .with_write_coalescing(WriteCoalescing::new(16, Duration::from_millis(5)));
+        let mut codec = WebSocketCodec::new(MockStream::new(vec![]), Role::Server, config);
+
+        // Each frame is 4 bytes on the wire; three fit under the limit
+        for _ in 0..3 {
+            codec
+                .write_frame(&Frame::text(b"Hi".to_vec()))
+                .await
+                .unwrap();
+        }
+        assert!(codec.io.written().is_empty());
+        codec.flush_due().await.unwrap();
+        assert!(codec.io.written().is_empty());
+
+        // The fourth would reach the limit, so the held frames go out first
+        codec
+            .write_frame(&Frame::text(b"Hi".to_vec()))
+            .await
+            .unwrap();
+        assert_eq!(codec.io.written().len(), 16);
+
+        codec
+            .write_frame(&Frame::text(b"Hi".to_vec()))
+            .await
+            .unwrap();
+        assert!(codec.flush_deadline().is_some());
+        tokio::time::advance(Duration::from_millis(5)).await;
+        codec.flush_due().await.unwrap();
+        assert_eq!(codec.io.written().len(), 20);
+        assert_eq!(codec.flush_deadline(), None);
+
+        codec
+            .write_raw(&Bytes::from_static(b"\x81\x00"))
+            .await
+            .unwrap();
+        codec.flush().await.unwrap();
+        assert_eq!(codec.io.written().len(), 22);
+    }
+
     #[tokio::test]
     async fn test_codec_with_large_payload() {
         // Large masked frame: 300 bytes of 0xAB
@@ -392,6 +1158,73 @@ mod tests {
         assert!(frame.payload().iter().all(|&b| b == 0xAB));
     }
 
+    #[tokio::test]
+    async fn test_max_read_buffer_size_rejects_large_frame() {
+        // 300-byte frame, well within max_frame_size
+        let mut data = vec![0x82, 0xFE, 0x01, 0x2C, 0x00, 0x00, 0x00, 0x00];
+        data.extend_from_slice(&[0xAB; 300]);
+        let config = Config::server().with_max_read_buffer_size(256);
+        let mut codec = WebSocketCodec::new(MockStream::new(data), Role::Server, config);
+
+        assert_eq!(
+            codec.read_frame().await.unwrap_err(),
+            Error::FrameTooLarge {
+                size: 308,
+                max: 256
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_buffer_shrinks_after_large_frame() {
+        let mut data = vec![0x82, 0xFE, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00];
+        data.extend_from_slice(&[0xAB; 0x8000]);
+        data.extend_from_slice(&[0x81, 0x82, 0x00, 0x00, 0x00, 0x00, b'h', b'i']);
+        let config = Config::server()
+            .with_read_buffer_size(1024)
+            .with_read_buffer_high_water(4096);
+        let mut codec = WebSocketCodec::new(MockStream::new(data), Role::Server, config);
+
+        let large = codec.read_frame().await.unwrap();
+        assert_eq!(large.payload().len(), 0x8000);
+        assert!(codec.grown);
+
+        // The next read starts from a fresh buffer, not the one `large` shares
+        let small = codec.read_frame().await.unwrap();
+        assert_eq!(small.payload(), b"hi");
+        assert!(!codec.grown);
+        assert!(codec.read_buf.capacity() <= 4096);
+    }
+
+    #[tokio::test]
+    async fn test_write_buffer_kept_then_given_back() {
+        let config = Config::server()
+            .with_write_buffer_size(1024)
+            .with_write_buffer_high_water(4096);
+        let mut codec = WebSocketCodec::new(MockStream::new(vec![]), Role::Server, config);
+
+        codec
+            .write_frame(&Frame::binary(vec![0; 8192]))
+            .await
+            .unwrap();
+        let grown = codec.write_buf.capacity();
+        assert!(grown > 4096);
+
+        // Kept through a run of small frames, then replaced
+        for _ in 0..31 {
+            codec
+                .write_frame(&Frame::text(b"hi".to_vec()))
+                .await
+                .unwrap();
+        }
+        assert_eq!(codec.write_buf.capacity(), grown);
+        codec
+            .write_frame(&Frame::text(b"hi".to_vec()))
+            .await
+            .unwrap();
+        assert!(codec.write_buf.capacity() <= 4096);
+    }
+
     #[tokio::test]
     async fn test_read_connection_closed() {
         let stream = MockStream::new(vec![]);
@@ -452,4 +1285,52 @@ mod tests {
             "Different codecs should produce different masks"
         );
     }
+
+    #[tokio::test]
+    async fn test_seeded_masks_are_reproducible() {
+        async fn write(config: Config) -> Vec<u8> {
+            let mut codec = WebSocketCodec::new(MockStream::new(vec![]), Role::Client, config);
+            codec
+                .write_frame(&Frame::text(b"hi".to_vec()))
+                .await
+                .unwrap();
+            codec
+                .write_frame(&Frame::text(b"hi".to_vec()))
+                .await
+                .unwrap();
+            codec.io.written().to_vec()
+        }
+
+        let config = Config::client().with_mask_seed(42);
+        let first = write(config.clone()).await;
+        assert_eq!(first, write(config).await);
+        // Each frame still gets its own key
+        assert_ne!(first[2..6], first[10..14]);
+    }
+
+    #[tokio::test]
+    async fn test_injected_mask_generator() {
+        struct Fixed;
+        impl MaskGenerator for Fixed {
+            fn next_mask(&mut self) -> [u8; 4] {
+                [0x37, 0xfa, 0x21, 0x3d]
+            }
+        }
+
+        let stream = MockStream::new(vec![]);
+        let mut codec =
+            WebSocketCodec::new(stream, Role::Client, Config::client()).with_mask_generator(Fixed);
+        codec
+            .write_frame(&Frame::text(b"Hello".to_vec()))
+            .await
+            .unwrap();
+
+        // RFC 6455 Section 5.7, single-frame masked text message
+        assert_eq!(
+            codec.io.written(),
+            [
+                0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58
+            ]
+        );
+    }
 }
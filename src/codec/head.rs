@@ -0,0 +1,148 @@
+//! Reading the HTTP head that precedes a WebSocket session.
+
+use std::future::Future;
+use std::io;
+use std::time::Duration;
+
+use bytes::BytesMut;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::time::{Instant, timeout_at};
+
+use crate::config::{Config, HandshakeRate, Timeouts};
+use crate::error::{Error, Result};
+
+/// Total time an opening handshake may take: `timeouts.handshake`, or its
+/// default if no timeouts are configured. A handshake is never left without
+/// a deadline, so neither a silent client nor a black-holed server can hold
+/// it open indefinitely.
+pub(crate) fn handshake_deadline(config: &Config) -> Duration {
+    config
+        .timeouts
+        .as_ref()
+        .map_or(Timeouts::default().handshake, |t| t.handshake)
+}
+
+/// Run `fut`, failing with `Error::HandshakeTimeout` once `deadline` has
+/// passed.
+pub(crate) async fn with_deadline<R>(
+    deadline: Duration,
+    fut: impl Future<Output = Result<R>>,
+) -> Result<R> {
+    tokio::time::timeout(deadline, fut)
+        .await
+        .unwrap_or(Err(Error::HandshakeTimeout(deadline)))
+}
+
+/// Read an HTTP request or response head of at most `max` bytes, failing
+/// with a timeout if it arrives slower than `rate`.
+///
+/// Returns the head and whatever was read past it.
+pub(crate) async fn read_head<S>(
+    stream: &mut S,
+    max: usize,
+    rate: Option<HandshakeRate>,
+) -> Result<(BytesMut, BytesMut)>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut buf = BytesMut::with_capacity(1024);
+    // Start of the current window and the bytes received in it
+    let mut window = (Instant::now(), 0);
+
+    let head_len = loop {
+        if let Some(end) = find_head_end(&buf) {
+            break end;
+        }
+        if buf.len() > max {
+            return Err(Error::HandshakeTooLarge {
+                size: buf.len(),
+                max,
+            });
+        }
+
+        let remaining = max.saturating_sub(buf.len()) + 1;
+        let mut chunk = (&mut *stream).take(remaining.min(4096) as u64);
+        let read = chunk.read_buf(&mut buf);
+        let n = match rate {
+            Some(rate) => timeout_at(window.0 + rate.window, read)
+                .await
+                .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "Handshake too slow"))??,
+            None => read.await?,
+        };
+        if n == 0 {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+
+        window.1 += n;
+        if rate.is_some_and(|rate| window.1 >= rate.min_bytes) {
+            window = (Instant::now(), 0);
+        }
+    };
+    let head = buf.split_to(head_len);
+    Ok((head, buf))
+}
+
+/// Length of the head including the terminating blank line.
+fn find_head_end(buf: &[u8]) -> Option<usize> {
+    buf.windows(4).position(|w| w == b"\r\n\r\n").map(|i| i + 4)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::io::AsyncWriteExt;
+
+    #[test]
+    fn test_find_head_end() {
+        assert_eq!(find_head_end(b"GET / HTTP/1.1\r\n\r\nrest"), Some(18));
+        assert_eq!(find_head_end(b"GET / HTTP/1.1\r\n"), None);
+    }
+
+    #[tokio::test]
+    async fn test_read_head_keeps_rest() {
+        let mut data: &[u8] = b"HTTP/1.1 101 OK\r\n\r\n\x81\x00";
+        let (head, rest) = read_head(&mut data, 1024, None).await.unwrap();
+        assert_eq!(&head[..], b"HTTP/1.1 101 OK\r\n\r\n");
+        assert_eq!(&rest[..], b"\x81\x00");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_read_head_rejects_slow_peer() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        let rate = HandshakeRate::new(8, Duration::from_secs(1));
+
+        let trickle = tokio::spawn(async move {
+            for &byte in b"GET / HTTP/1.1\r\n" {
+                client.write_all(&[byte]).await.unwrap();
+                tokio::time::sleep(Duration::from_millis(300)).await;
+            }
+            client
+        });
+
+        let err = read_head(&mut server, 1024, Some(rate)).await.unwrap_err();
+        assert_eq!(err.io_error().unwrap().kind(), io::ErrorKind::TimedOut);
+        drop(trickle);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_read_head_accepts_steady_peer() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        let rate = HandshakeRate::new(8, Duration::from_secs(1));
+
+        tokio::spawn(async move {
+            for line in [
+                &b"GET / HTTP/1.1\r\n"[..],
+                b"Host: example.com\r\n",
+                b"\r\n",
+            ] {
+                client.write_all(line).await.unwrap();
+                tokio::time::sleep(Duration::from_millis(900)).await;
+            }
+            client
+        });
+
+        let (head, _) = read_head(&mut server, 1024, Some(rate)).await.unwrap();
+        assert!(head.ends_with(b"\r\n\r\n"));
+    }
+}
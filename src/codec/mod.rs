@@ -4,6 +4,10 @@
 
 #[cfg(feature = "async-tokio")]
 mod framed;
+#[cfg(feature = "async-tokio")]
+mod head;
 
 #[cfg(feature = "async-tokio")]
 pub use framed::WebSocketCodec;
+#[cfg(feature = "async-tokio")]
+pub(crate) use head::{handshake_deadline, read_head, with_deadline};
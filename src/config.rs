@@ -1,7 +1,18 @@
 //! Configuration and limits for WebSocket connections.
 
+use std::sync::Arc;
 use std::time::Duration;
 
+use thiserror::Error;
+
+use crate::connection::{ConnectionObserver, FixedSize, Fragmenter, Observer, fragment_len};
+use crate::message::CloseCode;
+use crate::protocol::OpCode;
+use crate::protocol::spill::{Spill, SpillSink};
+use crate::protocol::validation::FrameRules;
+
+pub use crate::protocol::handshake::CompatMode;
+
 /// Configuration limits for WebSocket connections.
 ///
 /// These limits prevent resource exhaustion attacks and ensure
@@ -230,6 +241,227 @@ impl Default for Timeouts {
     }
 }
 
+/// Minimum pace at which a peer must send its handshake head.
+///
+/// While the head is incomplete, at least `min_bytes` must arrive within
+/// every `window`, otherwise the handshake fails with a timeout. This turns
+/// away slow-loris clients that trickle a byte at a time to hold a slot
+/// until `timeouts.handshake` runs out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HandshakeRate {
+    /// Bytes that must arrive per window.
+    ///
+    /// Default: 64
+    pub min_bytes: usize,
+
+    /// Length of a window.
+    ///
+    /// Default: 5 seconds
+    pub window: Duration,
+}
+
+impl Default for HandshakeRate {
+    fn default() -> Self {
+        Self {
+            min_bytes: 64,
+            window: Duration::from_secs(5),
+        }
+    }
+}
+
+impl HandshakeRate {
+    /// Require `min_bytes` per `window`.
+    #[must_use]
+    pub const fn new(min_bytes: usize, window: Duration) -> Self {
+        Self { min_bytes, window }
+    }
+}
+
+/// Most a peer may send per window before the connection is failed with
+/// [`CloseCode::PolicyViolation`].
+///
+/// Both limits apply at once. Every frame's payload counts towards
+/// `max_bytes` and every complete message, pings and pongs included,
+/// towards `max_messages`. Windows are fixed: a new one starts with the
+/// first frame after the last has ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InboundRate {
+    /// Messages allowed per window.
+    pub max_messages: usize,
+
+    /// Payload bytes allowed per window.
+    pub max_bytes: usize,
+
+    /// Length of a window.
+    pub window: Duration,
+}
+
+impl InboundRate {
+    /// Allow `max_messages` and `max_bytes` per `window`.
+    #[must_use]
+    pub const fn new(max_messages: usize, max_bytes: usize, window: Duration) -> Self {
+        Self {
+            max_messages,
+            max_bytes,
+            window,
+        }
+    }
+
+    /// Allow `max_messages` and `max_bytes` per second.
+    #[must_use]
+    pub const fn per_second(max_messages: usize, max_bytes: usize) -> Self {
+        Self::new(max_messages, max_bytes, Duration::from_secs(1))
+    }
+}
+
+/// What happens to a peer that stops reading while messages pile up in the
+/// send queue (`Connection::enqueue`).
+///
+/// Applies once more than `max_queued_bytes` of data messages have been
+/// queued for longer than `grace`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlowConsumer {
+    /// Payload bytes of queued messages tolerated for `grace`.
+    pub max_queued_bytes: usize,
+
+    /// How long the queue may stay over `max_queued_bytes`.
+    pub grace: Duration,
+
+    /// What to do after that.
+    pub policy: SlowConsumerPolicy,
+}
+
+impl SlowConsumer {
+    /// Apply `policy` once more than `max_queued_bytes` have been queued for
+    /// `grace`.
+    #[must_use]
+    pub const fn new(max_queued_bytes: usize, grace: Duration, policy: SlowConsumerPolicy) -> Self {
+        Self {
+            max_queued_bytes,
+            grace,
+            policy,
+        }
+    }
+}
+
+/// What [`SlowConsumer`] does with a peer that has fallen behind.
+///
+/// The queue is checked on every `enqueue` and `send_next`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SlowConsumerPolicy {
+    /// Discard the queue and close with this code, usually
+    /// [`PolicyViolation`](CloseCode::PolicyViolation) or
+    /// [`TryAgainLater`](CloseCode::TryAgainLater). The close goes out on
+    /// the next `send_next`, which then fails with `Error::SlowConsumer`.
+    /// If the close cannot be written within `grace` either, the connection
+    /// is aborted.
+    Disconnect(CloseCode),
+    /// Drop the oldest queued messages, `Normal` before `High`, until the
+    /// queue is back under the limit. Suits data where only the latest
+    /// value matters.
+    DropOldest,
+    /// Keep everything, but have `send_next` keep writing until the queue is
+    /// back under the limit, so a producer that alternates `enqueue` and
+    /// `send_next` waits for the peer.
+    Block,
+}
+
+/// Packs small outgoing messages into fewer writes.
+///
+/// Frames are held back after a send until `max_bytes` have accumulated or
+/// the oldest has waited `max_delay`, then written together. Anything held
+/// also goes out on an explicit flush, a close, a pong reply, and once
+/// `max_delay` passes while `recv` waits. Nothing else runs that timer, so a
+/// connection that only sends must keep sending or call `flush` to get the
+/// tail out; a `WsHandle` task waits on the peer all along and writes it
+/// on time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WriteCoalescing {
+    /// Write as soon as this many bytes are held.
+    pub max_bytes: usize,
+
+    /// Longest a frame is held before it is written.
+    pub max_delay: Duration,
+}
+
+impl WriteCoalescing {
+    /// Hold up to `max_bytes` for at most `max_delay`.
+    #[must_use]
+    pub const fn new(max_bytes: usize, max_delay: Duration) -> Self {
+        Self {
+            max_bytes,
+            max_delay,
+        }
+    }
+}
+
+impl Default for WriteCoalescing {
+    /// Up to 16 KB for at most 1 ms.
+    fn default() -> Self {
+        Self::new(16 * 1024, Duration::from_millis(1))
+    }
+}
+
+/// Pings sent to check that an otherwise quiet peer is still there.
+///
+/// A ping goes out once nothing has been received for `interval`, and the
+/// peer is given up on if nothing arrives within `timeout` after it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Keepalive {
+    /// Quiet time before a ping is sent.
+    ///
+    /// Default: 30 seconds
+    pub interval: Duration,
+
+    /// How long to wait for the peer after a ping.
+    ///
+    /// Default: 10 seconds
+    pub timeout: Duration,
+}
+
+impl Keepalive {
+    /// Ping after `interval` of quiet and wait `timeout` for an answer.
+    #[must_use]
+    pub const fn new(interval: Duration, timeout: Duration) -> Self {
+        Self { interval, timeout }
+    }
+}
+
+impl Default for Keepalive {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(30), Duration::from_secs(10))
+    }
+}
+
+/// How received pings are handled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PongPolicy {
+    /// Answer every ping with a pong and also return it from `recv`.
+    #[default]
+    Auto,
+    /// Return pings from `recv` without answering them; the application
+    /// sends its own pongs, e.g. with `Connection::pong`.
+    Manual,
+    /// Answer pings and do not return them from `recv`.
+    Suppress,
+}
+
+impl PongPolicy {
+    /// Returns `true` if pings are answered automatically.
+    #[must_use]
+    pub const fn auto_pong(self) -> bool {
+        !matches!(self, Self::Manual)
+    }
+
+    /// Returns `true` if pings are returned from `recv`.
+    #[must_use]
+    pub const fn surfaces_pings(self) -> bool {
+        !matches!(self, Self::Suppress)
+    }
+}
+
 impl Timeouts {
     /// Create new timeouts with custom values.
     #[must_use]
@@ -251,11 +483,24 @@ pub struct Config {
 
     /// Fragment size for outgoing messages (in bytes).
     ///
-    /// Messages larger than this will be split into multiple frames.
+    /// Messages larger than this will be split into multiple frames, unless
+    /// a `fragmenter` is set.
     ///
     /// Default: 16 KB (16 * 1024)
     pub fragment_size: usize,
 
+    /// Strategy for splitting outgoing messages into frames.
+    ///
+    /// If `None`, messages are split into `fragment_size` pieces.
+    /// Default: None
+    pub fragmenter: Option<Arc<dyn Fragmenter>>,
+
+    /// Rules incoming frames are checked against, in place of the built-in
+    /// [`FrameValidator`](crate::protocol::FrameValidator).
+    ///
+    /// Default: None
+    pub frame_rules: Option<Arc<dyn FrameRules>>,
+
     /// Accept unmasked frames from clients (server only).
     ///
     /// RFC 6455 requires clients to mask all frames. Setting this to `true`
@@ -266,47 +511,213 @@ pub struct Config {
 
     /// Mask frames when sending (client only).
     ///
-    /// RFC 6455 requires clients to mask all frames. This should always be `true`
-    /// for clients and `false` for servers.
+    /// Never read: outgoing frames are masked by role, and
+    /// [`masking`](Self::masking) overrides that.
     ///
     /// Default: true
+    #[deprecated(
+        since = "0.2.5",
+        note = "masking follows the role; override it with `masking` instead"
+    )]
     pub mask_frames: bool,
 
+    /// Mask outgoing frames or not regardless of role, e.g. a server that
+    /// masks or a client that does not, for testing intermediaries.
+    ///
+    /// Either choice breaks RFC 6455 and a compliant peer will close the
+    /// connection; pair it with `accept_unmasked_frames` or
+    /// `accept_masked_frames` on the peer.
+    ///
+    /// Default: None (clients mask, servers do not)
+    pub masking: Option<bool>,
+
+    /// Accept masked frames from servers (client only).
+    ///
+    /// RFC 6455 forbids servers to mask frames. Setting this to `true`
+    /// violates the spec but may be useful for testing.
+    ///
+    /// Default: false
+    pub accept_masked_frames: bool,
+
+    /// Seed for the masking keys of outgoing frames, instead of the system
+    /// random number generator.
+    ///
+    /// For tests that assert exact wire bytes only: predictable keys defeat
+    /// the purpose of masking. See also
+    /// [`WebSocketCodec::with_mask_generator`](crate::codec::WebSocketCodec::with_mask_generator).
+    ///
+    /// Default: None
+    pub mask_seed: Option<u32>,
+
     /// Read buffer size (in bytes).
     ///
     /// Default: 8 KB (8192)
     pub read_buffer_size: usize,
 
+    /// Upper bound on the read buffer (in bytes).
+    ///
+    /// A frame is buffered whole before it is decoded, so this caps the
+    /// memory one connection can pin while reading. Frames whose wire size
+    /// exceeds it fail with `Error::FrameTooLarge`, even within
+    /// `limits.max_frame_size`.
+    ///
+    /// Default: None (bounded only by `limits.max_frame_size`)
+    pub max_read_buffer_size: Option<usize>,
+
+    /// Read buffer capacity (in bytes) above which the buffer is given back.
+    ///
+    /// Once a large frame has grown the buffer past this, it is replaced
+    /// with a fresh `read_buffer_size` buffer as soon as the bytes left in
+    /// it fit, so mostly idle connections do not keep their peak-sized
+    /// buffer.
+    ///
+    /// Default: 64 KB (64 * 1024)
+    pub read_buffer_high_water: usize,
+
     /// Write buffer size (in bytes).
     ///
+    /// The buffer is kept across frames and grows to fit the largest one;
+    /// this is the capacity it starts with and is given back to.
+    ///
     /// Default: 8 KB (8192)
     pub write_buffer_size: usize,
 
+    /// Write buffer capacity (in bytes) above which the buffer is given
+    /// back.
+    ///
+    /// A buffer grown past this by a large frame is kept while large frames
+    /// keep coming, and replaced with a fresh `write_buffer_size` buffer
+    /// after a run of smaller ones, so occasional large sends neither
+    /// reallocate every time nor pin their peak size forever. Must be at
+    /// least `write_buffer_size`.
+    ///
+    /// Default: 64 KB (64 * 1024)
+    pub write_buffer_high_water: usize,
+
+    /// Hold back small writes to send them together.
+    ///
+    /// Default: None (every send is written and flushed at once)
+    pub write_coalescing: Option<WriteCoalescing>,
+
     /// Timeout configuration.
     ///
     /// If `None`, no timeouts are configured (caller must implement their own).
     /// Default: None
     pub timeouts: Option<Timeouts>,
 
+    /// Minimum pace for a client's handshake head, applied when accepting.
+    /// Clients do not hold servers and proxies to it.
+    ///
+    /// If `None`, a slow head is only cut off by `timeouts.handshake`.
+    /// Default: `Some(HandshakeRate::default())`
+    pub handshake_rate: Option<HandshakeRate>,
+
     /// Allowed origins for CSWSH protection.
     ///
     /// If `Some`, only connections from these origins are allowed.
     /// If `None`, origin validation is disabled (not recommended for production).
     /// Default: None
     pub allowed_origins: Option<Vec<String>>,
+
+    /// Allowed `Host` header values for DNS rebinding protection.
+    ///
+    /// If `Some`, only requests naming one of these hosts are allowed. An
+    /// entry without a port matches any port. If `None`, the Host header is
+    /// not checked.
+    /// Default: None
+    pub allowed_hosts: Option<Vec<String>>,
+
+    /// Maximum number of pings awaiting a pong.
+    ///
+    /// Sending another ping once this many are unanswered fails with
+    /// `Error::TooManyPendingPings`, which usually means the peer is gone.
+    ///
+    /// Default: 16
+    pub max_pending_pings: usize,
+
+    /// Whether received pings are answered automatically and whether they
+    /// are returned from `recv`.
+    ///
+    /// Default: `PongPolicy::Auto`
+    pub pong_policy: PongPolicy,
+
+    /// Send an unsolicited Pong (RFC 6455 Section 5.5.3) after this long
+    /// without sending anything, while `recv` waits for the peer.
+    ///
+    /// A cheap keepalive for NAT mappings: the peer does not answer it and
+    /// no round trip is tracked.
+    /// Default: None
+    pub pong_heartbeat: Option<Duration>,
+
+    /// Ping a quiet peer and give up on it if it does not answer.
+    ///
+    /// Applied while `recv` waits for the peer, so no separate task is
+    /// needed; an unanswered ping aborts the connection with
+    /// `Error::KeepaliveTimeout`.
+    /// Default: None
+    pub keepalive: Option<Keepalive>,
+
+    /// Fail the connection with a policy violation (1008) if the peer sends
+    /// faster than this.
+    ///
+    /// Default: None
+    pub inbound_rate: Option<InboundRate>,
+
+    /// Deal with a peer that stops reading while the send queue grows.
+    ///
+    /// Default: None (the queue grows without bound)
+    pub slow_consumer: Option<SlowConsumer>,
+
+    /// Leniency for peers that bend the protocol.
+    ///
+    /// Default: strict (`CompatMode::default()`)
+    pub compat: CompatMode,
+
+    /// Receives lifecycle events from connections using this configuration.
+    ///
+    /// Default: None
+    pub observer: Option<Observer>,
+
+    /// Spill incoming messages above a size threshold out of memory.
+    ///
+    /// `limits.max_message_size` still applies to spilled messages.
+    /// Compressed (permessage-deflate) messages are never spilled.
+    /// Default: None
+    pub spill: Option<Spill>,
 }
 
 impl Default for Config {
+    #[allow(deprecated)]
     fn default() -> Self {
         Self {
             limits: Limits::default(),
             fragment_size: 16 * 1024,
+            fragmenter: None,
+            frame_rules: None,
             accept_unmasked_frames: false,
             mask_frames: true,
+            masking: None,
+            accept_masked_frames: false,
+            mask_seed: None,
             read_buffer_size: 8192,
+            max_read_buffer_size: None,
+            read_buffer_high_water: 64 * 1024,
             write_buffer_size: 8192,
+            write_buffer_high_water: 64 * 1024,
+            write_coalescing: None,
             timeouts: None,
+            handshake_rate: Some(HandshakeRate::default()),
             allowed_origins: None,
+            allowed_hosts: None,
+            max_pending_pings: 16,
+            pong_policy: PongPolicy::Auto,
+            pong_heartbeat: None,
+            keepalive: None,
+            inbound_rate: None,
+            slow_consumer: None,
+            compat: CompatMode::default(),
+            observer: None,
+            spill: None,
         }
     }
 }
@@ -332,6 +743,64 @@ impl Config {
         self
     }
 
+    /// Set the strategy for splitting outgoing messages into frames.
+    ///
+    /// ```rust,ignore
+    /// use rsws::connection::Ramp;
+    ///
+    /// // 1 KB first fragment, growing to 64 KB
+    /// let config = Config::server().with_fragmenter(Ramp::new(1024, 64 * 1024));
+    /// ```
+    #[must_use]
+    pub fn with_fragmenter(mut self, fragmenter: impl Fragmenter + 'static) -> Self {
+        self.fragmenter = Some(Arc::new(fragmenter));
+        self
+    }
+
+    /// Check incoming frames against `rules` instead of the built-in
+    /// validator, e.g. to refuse fragmented messages.
+    #[must_use]
+    pub fn with_frame_rules(mut self, rules: impl FrameRules + 'static) -> Self {
+        self.frame_rules = Some(Arc::new(rules));
+        self
+    }
+
+    /// Length of the next outgoing fragment, as decided by the fragmenter.
+    #[cfg_attr(not(feature = "async-tokio"), allow(dead_code))]
+    pub(crate) fn fragment_len(&self, opcode: OpCode, len: usize, sent: usize) -> usize {
+        match &self.fragmenter {
+            Some(fragmenter) => fragment_len(fragmenter.as_ref(), opcode, len, sent),
+            None => fragment_len(&FixedSize(self.fragment_size), opcode, len, sent),
+        }
+    }
+
+    /// Mask outgoing frames or not regardless of role. For tests only.
+    ///
+    /// ```rust,ignore
+    /// // A server that masks, for a proxy under test
+    /// let config = Config::server().with_masking(true);
+    /// ```
+    #[must_use]
+    pub const fn with_masking(mut self, mask: bool) -> Self {
+        self.masking = Some(mask);
+        self
+    }
+
+    /// Accept masked frames from servers (client only). For tests only.
+    #[must_use]
+    pub const fn with_accept_masked_frames(mut self, accept: bool) -> Self {
+        self.accept_masked_frames = accept;
+        self
+    }
+
+    /// Derive masking keys from `seed` so output is reproducible. For tests
+    /// only.
+    #[must_use]
+    pub const fn with_mask_seed(mut self, seed: u32) -> Self {
+        self.mask_seed = Some(seed);
+        self
+    }
+
     /// Set read buffer size.
     #[must_use]
     pub const fn with_read_buffer_size(mut self, size: usize) -> Self {
@@ -339,6 +808,21 @@ impl Config {
         self
     }
 
+    /// Cap the read buffer at `size` bytes.
+    #[must_use]
+    pub const fn with_max_read_buffer_size(mut self, size: usize) -> Self {
+        self.max_read_buffer_size = Some(size);
+        self
+    }
+
+    /// Set the capacity above which the read buffer is given back once
+    /// drained.
+    #[must_use]
+    pub const fn with_read_buffer_high_water(mut self, size: usize) -> Self {
+        self.read_buffer_high_water = size;
+        self
+    }
+
     /// Set write buffer size.
     #[must_use]
     pub const fn with_write_buffer_size(mut self, size: usize) -> Self {
@@ -346,6 +830,21 @@ impl Config {
         self
     }
 
+    /// Set the capacity above which the write buffer is given back after a
+    /// run of smaller frames.
+    #[must_use]
+    pub const fn with_write_buffer_high_water(mut self, size: usize) -> Self {
+        self.write_buffer_high_water = size;
+        self
+    }
+
+    /// Coalesce small writes.
+    #[must_use]
+    pub const fn with_write_coalescing(mut self, coalescing: WriteCoalescing) -> Self {
+        self.write_coalescing = Some(coalescing);
+        self
+    }
+
     /// Set timeout configuration.
     #[must_use]
     pub fn with_timeouts(mut self, timeouts: Timeouts) -> Self {
@@ -353,6 +852,14 @@ impl Config {
         self
     }
 
+    /// Set the minimum pace for the peer's handshake head, or `None` to
+    /// turn the check off.
+    #[must_use]
+    pub const fn with_handshake_rate(mut self, rate: Option<HandshakeRate>) -> Self {
+        self.handshake_rate = rate;
+        self
+    }
+
     /// Set allowed origins for CSWSH protection.
     ///
     /// Only connections with an Origin header matching one of these values
@@ -364,8 +871,103 @@ impl Config {
         self
     }
 
+    /// Set allowed hosts for DNS rebinding protection.
+    ///
+    /// Only requests whose Host header names one of these hosts will be
+    /// accepted, e.g. `vec!["localhost".into(), "127.0.0.1".into()]` for a
+    /// server bound to loopback.
+    #[must_use]
+    pub fn with_allowed_hosts(mut self, hosts: Vec<String>) -> Self {
+        self.allowed_hosts = Some(hosts);
+        self
+    }
+
+    /// Set the maximum number of unanswered pings.
+    #[must_use]
+    pub const fn with_max_pending_pings(mut self, max: usize) -> Self {
+        self.max_pending_pings = max;
+        self
+    }
+
+    /// Set how received pings are handled.
+    #[must_use]
+    pub const fn with_pong_policy(mut self, policy: PongPolicy) -> Self {
+        self.pong_policy = policy;
+        self
+    }
+
+    /// Send an unsolicited Pong after `interval` without outgoing traffic.
+    #[must_use]
+    pub const fn with_pong_heartbeat(mut self, interval: Duration) -> Self {
+        self.pong_heartbeat = Some(interval);
+        self
+    }
+
+    /// Ping the peer after quiet periods and give up if it stops answering.
+    #[must_use]
+    pub const fn with_keepalive(mut self, keepalive: Keepalive) -> Self {
+        self.keepalive = Some(keepalive);
+        self
+    }
+
+    /// Limit how many messages and bytes the peer may send per window.
+    #[must_use]
+    pub const fn with_inbound_rate(mut self, rate: InboundRate) -> Self {
+        self.inbound_rate = Some(rate);
+        self
+    }
+
+    /// Disconnect, drop messages for or wait on a peer whose send queue
+    /// stays too long.
+    #[must_use]
+    pub const fn with_slow_consumer(mut self, slow_consumer: SlowConsumer) -> Self {
+        self.slow_consumer = Some(slow_consumer);
+        self
+    }
+
+    /// Set the leniency for buggy peers.
+    #[must_use]
+    pub const fn with_compat(mut self, compat: CompatMode) -> Self {
+        self.compat = compat;
+        self
+    }
+
+    /// Install an observer for connection lifecycle events.
+    ///
+    /// See [`ConnectionEvent`](crate::ConnectionEvent) for what is reported.
+    #[must_use]
+    pub fn with_observer(mut self, observer: impl ConnectionObserver + 'static) -> Self {
+        self.observer = Some(Observer::new(observer));
+        self
+    }
+
+    /// Spill incoming messages larger than `threshold` bytes into `sink`,
+    /// delivering them as [`Message::Spilled`](crate::Message::Spilled).
+    ///
+    /// ```rust,ignore
+    /// use rsws::protocol::TempFiles;
+    ///
+    /// // Keep up to 1 MB in memory, accept files of up to 4 GB on disk
+    /// let config = Config::server()
+    ///     .with_limits(Limits::new(16 << 20, 4 << 30, 4096, 8192))
+    ///     .with_spill(1 << 20, TempFiles::new());
+    /// ```
+    #[must_use]
+    pub fn with_spill(mut self, threshold: usize, sink: impl SpillSink + 'static) -> Self {
+        self.spill = Some(Spill::new(threshold, sink));
+        self
+    }
+
+    /// Whether a message of `len` bytes is spilled out of memory.
+    pub(crate) fn spills(&self, len: usize) -> bool {
+        self.spill
+            .as_ref()
+            .is_some_and(|spill| len > spill.threshold)
+    }
+
     /// Configure for server role (no masking, reject unmasked client frames).
     #[must_use]
+    #[allow(deprecated)]
     pub fn server() -> Self {
         Self {
             mask_frames: false,
@@ -376,6 +978,7 @@ impl Config {
 
     /// Configure for client role (mask all frames).
     #[must_use]
+    #[allow(deprecated)]
     pub fn client() -> Self {
         Self {
             mask_frames: true,
@@ -383,6 +986,414 @@ impl Config {
             ..Default::default()
         }
     }
+
+    /// Start building a configuration from the defaults, checked by
+    /// [`ConfigBuilder::build`].
+    #[must_use]
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder {
+            config: Self::default(),
+        }
+    }
+
+    /// Check that the settings are consistent with each other.
+    ///
+    /// Connections accept any configuration, but an inconsistent one fails
+    /// later and less clearly, e.g. every large message erroring with
+    /// `FrameTooLarge` because `fragment_size` exceeds `max_frame_size`.
+    ///
+    /// Only the settings held here are checked. Extensions are configured
+    /// apart from a `Config`: `DeflateConfig`'s setters check its window
+    /// bits and levels, and on a connection a decompressed message is held
+    /// to `limits.max_message_size` whatever its `max_decompressed_size`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first [`ConfigError`] found.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let limits = &self.limits;
+        for (name, value) in [
+            ("limits.max_frame_size", limits.max_frame_size),
+            ("limits.max_message_size", limits.max_message_size),
+            ("limits.max_fragment_count", limits.max_fragment_count),
+            ("limits.max_handshake_size", limits.max_handshake_size),
+            ("fragment_size", self.fragment_size),
+            ("read_buffer_size", self.read_buffer_size),
+            (
+                "max_read_buffer_size",
+                self.max_read_buffer_size.unwrap_or(usize::MAX),
+            ),
+            ("read_buffer_high_water", self.read_buffer_high_water),
+            ("write_buffer_size", self.write_buffer_size),
+            ("write_buffer_high_water", self.write_buffer_high_water),
+            ("max_pending_pings", self.max_pending_pings),
+        ] {
+            if value == 0 {
+                return Err(ConfigError::Zero(name));
+            }
+        }
+
+        if self.fragment_size > limits.max_frame_size {
+            return Err(ConfigError::FragmentExceedsFrameLimit {
+                fragment_size: self.fragment_size,
+                max_frame_size: limits.max_frame_size,
+            });
+        }
+        if limits.max_frame_size > limits.max_message_size {
+            return Err(ConfigError::FrameExceedsMessageLimit {
+                max_frame_size: limits.max_frame_size,
+                max_message_size: limits.max_message_size,
+            });
+        }
+        if self.write_buffer_high_water < self.write_buffer_size {
+            return Err(ConfigError::WriteHighWaterBelowSize {
+                write_buffer_size: self.write_buffer_size,
+                write_buffer_high_water: self.write_buffer_high_water,
+            });
+        }
+
+        if let Some(timeouts) = &self.timeouts {
+            for (name, value) in [
+                ("timeouts.handshake", timeouts.handshake),
+                ("timeouts.read", timeouts.read),
+                ("timeouts.write", timeouts.write),
+                ("timeouts.idle", timeouts.idle),
+            ] {
+                if value.is_zero() {
+                    return Err(ConfigError::Zero(name));
+                }
+            }
+        }
+        if let Some(rate) = &self.handshake_rate {
+            if rate.min_bytes == 0 {
+                return Err(ConfigError::Zero("handshake_rate.min_bytes"));
+            }
+            if rate.window.is_zero() {
+                return Err(ConfigError::Zero("handshake_rate.window"));
+            }
+        }
+        if let Some(coalescing) = &self.write_coalescing {
+            if coalescing.max_bytes == 0 {
+                return Err(ConfigError::Zero("write_coalescing.max_bytes"));
+            }
+            if coalescing.max_delay.is_zero() {
+                return Err(ConfigError::Zero("write_coalescing.max_delay"));
+            }
+        }
+        if self
+            .pong_heartbeat
+            .is_some_and(|interval| interval.is_zero())
+        {
+            return Err(ConfigError::Zero("pong_heartbeat"));
+        }
+        if let Some(keepalive) = &self.keepalive {
+            if keepalive.interval.is_zero() {
+                return Err(ConfigError::Zero("keepalive.interval"));
+            }
+            if keepalive.timeout.is_zero() {
+                return Err(ConfigError::Zero("keepalive.timeout"));
+            }
+        }
+        if let Some(rate) = &self.inbound_rate {
+            if rate.max_messages == 0 {
+                return Err(ConfigError::Zero("inbound_rate.max_messages"));
+            }
+            if rate.max_bytes == 0 {
+                return Err(ConfigError::Zero("inbound_rate.max_bytes"));
+            }
+            if rate.window.is_zero() {
+                return Err(ConfigError::Zero("inbound_rate.window"));
+            }
+        }
+        if self
+            .slow_consumer
+            .is_some_and(|slow| slow.max_queued_bytes == 0)
+        {
+            return Err(ConfigError::Zero("slow_consumer.max_queued_bytes"));
+        }
+        Ok(())
+    }
+}
+
+/// An inconsistency found by [`Config::validate`].
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ConfigError {
+    /// A size, count or duration that must be positive is zero.
+    #[error("{0} must not be zero")]
+    Zero(&'static str),
+
+    /// Outgoing fragments would be rejected by the frame size limit.
+    #[error("fragment_size ({fragment_size}) exceeds limits.max_frame_size ({max_frame_size})")]
+    FragmentExceedsFrameLimit {
+        /// Configured fragment size.
+        fragment_size: usize,
+        /// Configured frame size limit.
+        max_frame_size: usize,
+    },
+
+    /// A single frame may be larger than a whole message.
+    #[error(
+        "limits.max_frame_size ({max_frame_size}) exceeds limits.max_message_size ({max_message_size})"
+    )]
+    FrameExceedsMessageLimit {
+        /// Configured frame size limit.
+        max_frame_size: usize,
+        /// Configured message size limit.
+        max_message_size: usize,
+    },
+
+    /// The write buffer would be given back after every frame, only to be
+    /// allocated again for the next.
+    #[error(
+        "write_buffer_high_water ({write_buffer_high_water}) is below write_buffer_size ({write_buffer_size})"
+    )]
+    WriteHighWaterBelowSize {
+        /// Configured write buffer size.
+        write_buffer_size: usize,
+        /// Configured write buffer high-water mark.
+        write_buffer_high_water: usize,
+    },
+}
+
+/// Builds a [`Config`] and checks it with [`Config::validate`].
+///
+/// ```
+/// use rsws::{Config, Limits};
+///
+/// let config = Config::builder()
+///     .limits(Limits::embedded())
+///     .fragment_size(32 * 1024)
+///     .build()
+///     .unwrap();
+/// assert_eq!(config.fragment_size, 32 * 1024);
+///
+/// // A fragment larger than the 64 KB frame limit is rejected
+/// assert!(Config::builder().limits(Limits::embedded()).fragment_size(1 << 20).build().is_err());
+/// ```
+#[derive(Debug, Clone)]
+pub struct ConfigBuilder {
+    config: Config,
+}
+
+impl ConfigBuilder {
+    /// Start from the server defaults of [`Config::server`].
+    #[must_use]
+    #[allow(deprecated)]
+    pub fn server(mut self) -> Self {
+        self.config.mask_frames = false;
+        self
+    }
+
+    /// Start from the client defaults of [`Config::client`].
+    #[must_use]
+    #[allow(deprecated)]
+    pub fn client(mut self) -> Self {
+        self.config.mask_frames = true;
+        self
+    }
+
+    /// Set resource limits.
+    #[must_use]
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.config.limits = limits;
+        self
+    }
+
+    /// Set the fragment size for outgoing messages.
+    #[must_use]
+    pub fn fragment_size(mut self, size: usize) -> Self {
+        self.config.fragment_size = size;
+        self
+    }
+
+    /// Set the strategy for splitting outgoing messages into frames.
+    #[must_use]
+    pub fn fragmenter(mut self, fragmenter: impl Fragmenter + 'static) -> Self {
+        self.config.fragmenter = Some(Arc::new(fragmenter));
+        self
+    }
+
+    /// Set the rules incoming frames are checked against.
+    #[must_use]
+    pub fn frame_rules(mut self, rules: impl FrameRules + 'static) -> Self {
+        self.config.frame_rules = Some(Arc::new(rules));
+        self
+    }
+
+    /// Set whether unmasked client frames are accepted (server only).
+    #[must_use]
+    pub fn accept_unmasked_frames(mut self, accept: bool) -> Self {
+        self.config.accept_unmasked_frames = accept;
+        self
+    }
+
+    /// Mask outgoing frames or not regardless of role (tests only).
+    #[must_use]
+    pub fn masking(mut self, mask: bool) -> Self {
+        self.config.masking = Some(mask);
+        self
+    }
+
+    /// Set whether masked server frames are accepted (client only).
+    #[must_use]
+    pub fn accept_masked_frames(mut self, accept: bool) -> Self {
+        self.config.accept_masked_frames = accept;
+        self
+    }
+
+    /// Derive masking keys from `seed` (tests only).
+    #[must_use]
+    pub fn mask_seed(mut self, seed: u32) -> Self {
+        self.config.mask_seed = Some(seed);
+        self
+    }
+
+    /// Set the read buffer size.
+    #[must_use]
+    pub fn read_buffer_size(mut self, size: usize) -> Self {
+        self.config.read_buffer_size = size;
+        self
+    }
+
+    /// Cap the read buffer at `size` bytes.
+    #[must_use]
+    pub fn max_read_buffer_size(mut self, size: usize) -> Self {
+        self.config.max_read_buffer_size = Some(size);
+        self
+    }
+
+    /// Set the capacity above which the read buffer is given back once
+    /// drained.
+    #[must_use]
+    pub fn read_buffer_high_water(mut self, size: usize) -> Self {
+        self.config.read_buffer_high_water = size;
+        self
+    }
+
+    /// Set the write buffer size.
+    #[must_use]
+    pub fn write_buffer_size(mut self, size: usize) -> Self {
+        self.config.write_buffer_size = size;
+        self
+    }
+
+    /// Set the capacity above which the write buffer is given back.
+    #[must_use]
+    pub fn write_buffer_high_water(mut self, size: usize) -> Self {
+        self.config.write_buffer_high_water = size;
+        self
+    }
+
+    /// Coalesce small writes.
+    #[must_use]
+    pub fn write_coalescing(mut self, coalescing: WriteCoalescing) -> Self {
+        self.config.write_coalescing = Some(coalescing);
+        self
+    }
+
+    /// Set timeouts.
+    #[must_use]
+    pub fn timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.config.timeouts = Some(timeouts);
+        self
+    }
+
+    /// Set the minimum pace for the peer's handshake head, or `None` to
+    /// turn the check off.
+    #[must_use]
+    pub fn handshake_rate(mut self, rate: Option<HandshakeRate>) -> Self {
+        self.config.handshake_rate = rate;
+        self
+    }
+
+    /// Set allowed origins for CSWSH protection.
+    #[must_use]
+    pub fn allowed_origins(mut self, origins: Vec<String>) -> Self {
+        self.config.allowed_origins = Some(origins);
+        self
+    }
+
+    /// Set allowed hosts for DNS rebinding protection.
+    #[must_use]
+    pub fn allowed_hosts(mut self, hosts: Vec<String>) -> Self {
+        self.config.allowed_hosts = Some(hosts);
+        self
+    }
+
+    /// Set the maximum number of unanswered pings.
+    #[must_use]
+    pub fn max_pending_pings(mut self, max: usize) -> Self {
+        self.config.max_pending_pings = max;
+        self
+    }
+
+    /// Set how received pings are handled.
+    #[must_use]
+    pub fn pong_policy(mut self, policy: PongPolicy) -> Self {
+        self.config.pong_policy = policy;
+        self
+    }
+
+    /// Send an unsolicited Pong after `interval` without outgoing traffic.
+    #[must_use]
+    pub fn pong_heartbeat(mut self, interval: Duration) -> Self {
+        self.config.pong_heartbeat = Some(interval);
+        self
+    }
+
+    /// Ping the peer after quiet periods and give up if it stops answering.
+    #[must_use]
+    pub fn keepalive(mut self, keepalive: Keepalive) -> Self {
+        self.config.keepalive = Some(keepalive);
+        self
+    }
+
+    /// Limit how many messages and bytes the peer may send per window.
+    #[must_use]
+    pub fn inbound_rate(mut self, rate: InboundRate) -> Self {
+        self.config.inbound_rate = Some(rate);
+        self
+    }
+
+    /// Disconnect, drop messages for or wait on a peer whose send queue
+    /// stays too long.
+    #[must_use]
+    pub fn slow_consumer(mut self, slow_consumer: SlowConsumer) -> Self {
+        self.config.slow_consumer = Some(slow_consumer);
+        self
+    }
+
+    /// Set the leniency for buggy peers.
+    #[must_use]
+    pub fn compat(mut self, compat: CompatMode) -> Self {
+        self.config.compat = compat;
+        self
+    }
+
+    /// Install an observer for connection lifecycle events.
+    #[must_use]
+    pub fn observer(mut self, observer: impl ConnectionObserver + 'static) -> Self {
+        self.config.observer = Some(Observer::new(observer));
+        self
+    }
+
+    /// Spill incoming messages larger than `threshold` bytes into `sink`.
+    #[must_use]
+    pub fn spill(mut self, threshold: usize, sink: impl SpillSink + 'static) -> Self {
+        self.config.spill = Some(Spill::new(threshold, sink));
+        self
+    }
+
+    /// Validate and return the configuration.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`ConfigError`] if the settings are inconsistent.
+    pub fn build(self) -> Result<Config, ConfigError> {
+        self.config.validate()?;
+        Ok(self.config)
+    }
 }
 
 #[cfg(test)]
@@ -436,6 +1447,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(deprecated)]
     fn test_config_default() {
         let config = Config::default();
         assert_eq!(config.fragment_size, 16 * 1024);
@@ -444,6 +1456,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(deprecated)]
     fn test_config_server() {
         let config = Config::server();
         assert!(!config.mask_frames);
@@ -451,12 +1464,25 @@ mod tests {
     }
 
     #[test]
+    #[allow(deprecated)]
     fn test_config_client() {
         let config = Config::client();
         assert!(config.mask_frames);
         assert!(!config.accept_unmasked_frames);
     }
 
+    #[test]
+    fn test_masking_override() {
+        assert_eq!(Config::server().masking, None);
+        let config = Config::builder().server().masking(true).build().unwrap();
+        assert_eq!(config.masking, Some(true));
+        let config = Config::client()
+            .with_masking(false)
+            .with_accept_masked_frames(true);
+        assert_eq!(config.masking, Some(false));
+        assert!(config.accept_masked_frames);
+    }
+
     #[test]
     fn test_config_builder() {
         let config = Config::new()
@@ -471,10 +1497,12 @@ mod tests {
     fn test_config_buffer_size() {
         let config = Config::new()
             .with_read_buffer_size(1024)
-            .with_write_buffer_size(2048);
+            .with_write_buffer_size(2048)
+            .with_write_buffer_high_water(1 << 20);
 
         assert_eq!(config.read_buffer_size, 1024);
         assert_eq!(config.write_buffer_size, 2048);
+        assert_eq!(config.write_buffer_high_water, 1 << 20);
     }
 
     #[test]
@@ -500,15 +1528,141 @@ mod tests {
         assert_eq!(config.allowed_origins, Some(origins));
     }
 
+    #[test]
+    fn test_config_with_allowed_hosts() {
+        let hosts = vec!["localhost".to_string()];
+        let config = Config::new().with_allowed_hosts(hosts.clone());
+        assert_eq!(config.allowed_hosts, Some(hosts));
+        assert!(Config::new().allowed_hosts.is_none());
+    }
+
     #[test]
     fn test_config_allowed_origins_none_by_default() {
         let config = Config::default();
         assert!(config.allowed_origins.is_none());
     }
 
+    #[test]
+    fn test_config_max_pending_pings() {
+        assert_eq!(Config::default().max_pending_pings, 16);
+        let config = Config::new().with_max_pending_pings(2);
+        assert_eq!(config.max_pending_pings, 2);
+    }
+
+    #[test]
+    fn test_config_pong_policy() {
+        assert_eq!(Config::default().pong_policy, PongPolicy::Auto);
+        let config = Config::new().with_pong_policy(PongPolicy::Manual);
+        assert!(!config.pong_policy.auto_pong());
+        assert!(config.pong_policy.surfaces_pings());
+        assert!(PongPolicy::Suppress.auto_pong());
+        assert!(!PongPolicy::Suppress.surfaces_pings());
+    }
+
+    #[test]
+    fn test_config_with_observer() {
+        assert!(Config::default().observer.is_none());
+        let config = Config::server().with_observer(|_: u64, _: &crate::ConnectionEvent<'_>| {});
+        assert!(config.clone().observer.is_some());
+    }
+
     #[test]
     fn test_config_timeouts_none_by_default() {
         let config = Config::default();
         assert!(config.timeouts.is_none());
     }
+
+    #[test]
+    fn test_presets_are_valid() {
+        assert_eq!(Config::server().validate(), Ok(()));
+        assert_eq!(Config::client().validate(), Ok(()));
+        assert_eq!(
+            Config::new().with_limits(Limits::embedded()).validate(),
+            Ok(())
+        );
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_builder_builds() {
+        let config = Config::builder()
+            .server()
+            .fragment_size(4096)
+            .max_pending_pings(4)
+            .build()
+            .unwrap();
+        assert!(!config.mask_frames);
+        assert_eq!(config.fragment_size, 4096);
+        assert_eq!(config.max_pending_pings, 4);
+    }
+
+    #[test]
+    fn test_builder_rejects_inconsistent_settings() {
+        let err = Config::builder()
+            .limits(Limits::embedded())
+            .fragment_size(128 * 1024)
+            .build()
+            .unwrap_err();
+        assert_eq!(
+            err,
+            ConfigError::FragmentExceedsFrameLimit {
+                fragment_size: 128 * 1024,
+                max_frame_size: 64 * 1024,
+            }
+        );
+
+        let err = Config::builder().read_buffer_size(0).build().unwrap_err();
+        assert_eq!(err, ConfigError::Zero("read_buffer_size"));
+        assert_eq!(err.to_string(), "read_buffer_size must not be zero");
+
+        let err = Config::builder()
+            .max_read_buffer_size(0)
+            .build()
+            .unwrap_err();
+        assert_eq!(err, ConfigError::Zero("max_read_buffer_size"));
+
+        let err = Config::builder()
+            .pong_heartbeat(Duration::ZERO)
+            .build()
+            .unwrap_err();
+        assert_eq!(err, ConfigError::Zero("pong_heartbeat"));
+
+        let err = Config::builder()
+            .keepalive(Keepalive::new(Duration::from_secs(1), Duration::ZERO))
+            .build()
+            .unwrap_err();
+        assert_eq!(err, ConfigError::Zero("keepalive.timeout"));
+
+        let err = Config::builder()
+            .inbound_rate(InboundRate::per_second(100, 0))
+            .build()
+            .unwrap_err();
+        assert_eq!(err, ConfigError::Zero("inbound_rate.max_bytes"));
+
+        let err = Config::builder()
+            .write_coalescing(WriteCoalescing::new(0, Duration::from_millis(1)))
+            .build()
+            .unwrap_err();
+        assert_eq!(err, ConfigError::Zero("write_coalescing.max_bytes"));
+
+        let err = Config::builder()
+            .limits(Limits::new(1024, 512, 16, 8192))
+            .fragment_size(512)
+            .build()
+            .unwrap_err();
+        assert!(matches!(err, ConfigError::FrameExceedsMessageLimit { .. }));
+
+        let err = Config::builder()
+            .write_buffer_size(64 * 1024)
+            .write_buffer_high_water(8192)
+            .build()
+            .unwrap_err();
+        assert_eq!(
+            err,
+            ConfigError::WriteHighWaterBelowSize {
+                write_buffer_size: 64 * 1024,
+                write_buffer_high_water: 8192,
+            }
+        );
+    }
 }
@@ -0,0 +1,249 @@
+//! [`Stream`] and [`Sink`] adapters over an owned [`Connection`].
+
+use std::future::Future;
+use std::mem;
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+
+use futures_core::Stream;
+use futures_sink::Sink;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::connection::Connection;
+use crate::error::{Error, Result};
+use crate::message::{CloseCode, Message};
+
+/// A connection operation in flight. It owns the connection and hands it
+/// back with the result.
+type Step<T, R> = Pin<Box<dyn Future<Output = (Box<Connection<T>>, Result<R>)> + Send>>;
+
+enum State<T, R> {
+    Idle(Box<Connection<T>>),
+    Busy(Step<T, R>),
+    Done,
+}
+
+impl<T> Connection<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    /// Turn the connection into a stream of received messages, for use with
+    /// stream combinators.
+    ///
+    /// Each item is what [`recv`](Self::recv) returns, including the peer's
+    /// close message. The stream ends once the connection is closed or after
+    /// a fatal error.
+    ///
+    /// ```rust,ignore
+    /// use futures::StreamExt;
+    ///
+    /// let texts = conn.into_stream().filter_map(|msg| async { msg.ok()?.into_text() });
+    /// ```
+    pub fn into_stream(self) -> impl Stream<Item = Result<Message>> + Send {
+        MessageStream {
+            state: State::Idle(Box::new(self)),
+        }
+    }
+
+    /// Turn the connection into a [`MessageSink`] that collects up to
+    /// `buffer` messages before writing them together.
+    ///
+    /// A `buffer` of 0 is treated as 1.
+    pub fn into_sink(self, buffer: usize) -> MessageSink<T> {
+        MessageSink {
+            state: State::Idle(Box::new(self)),
+            buffer: Vec::new(),
+            capacity: buffer.max(1),
+            closing: false,
+        }
+    }
+}
+
+struct MessageStream<T> {
+    state: State<T, Option<Message>>,
+}
+
+impl<T> Stream for MessageStream<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    type Item = Result<Message>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            match mem::replace(&mut this.state, State::Done) {
+                State::Idle(mut conn) => {
+                    this.state = State::Busy(Box::pin(async move {
+                        let result = conn.recv().await;
+                        (conn, result)
+                    }));
+                }
+                State::Busy(mut step) => {
+                    let Poll::Ready((conn, result)) = step.as_mut().poll(cx) else {
+                        this.state = State::Busy(step);
+                        return Poll::Pending;
+                    };
+                    return Poll::Ready(match result {
+                        Ok(Some(message)) => {
+                            this.state = State::Idle(conn);
+                            Some(Ok(message))
+                        }
+                        Ok(None) => None,
+                        Err(e) => {
+                            if !e.is_fatal() {
+                                this.state = State::Idle(conn);
+                            }
+                            Some(Err(e))
+                        }
+                    });
+                }
+                State::Done => return Poll::Ready(None),
+            }
+        }
+    }
+}
+
+/// A [`Sink`] of messages over an owned connection, made with
+/// [`Connection::into_sink`].
+///
+/// Messages are buffered and written with a single flush once the buffer
+/// is full or the sink is flushed. Closing the sink flushes it and then
+/// closes the connection with [`CloseCode::Normal`].
+pub struct MessageSink<T> {
+    state: State<T, ()>,
+    buffer: Vec<Message>,
+    capacity: usize,
+    closing: bool,
+}
+
+impl<T> MessageSink<T> {
+    /// Messages accepted but not yet written.
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+}
+
+impl<T> Sink<Message> for MessageSink<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        if self.buffer.len() < self.capacity {
+            return Poll::Ready(Ok(()));
+        }
+        self.poll_flush(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, message: Message) -> Result<()> {
+        self.get_mut().buffer.push(message);
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        if this.closing {
+            return Poll::Ready(Err(Error::ConnectionClosed(None)));
+        }
+        loop {
+            match mem::replace(&mut this.state, State::Done) {
+                State::Idle(mut conn) => {
+                    if this.buffer.is_empty() {
+                        this.state = State::Idle(conn);
+                        return Poll::Ready(Ok(()));
+                    }
+                    let batch = mem::take(&mut this.buffer);
+                    this.state = State::Busy(Box::pin(async move {
+                        let result = conn.send_batch(batch).await;
+                        (conn, result)
+                    }));
+                }
+                State::Busy(mut step) => {
+                    let Poll::Ready((conn, result)) = step.as_mut().poll(cx) else {
+                        this.state = State::Busy(step);
+                        return Poll::Pending;
+                    };
+                    this.state = State::Idle(conn);
+                    result?;
+                }
+                State::Done => return Poll::Ready(Err(Error::ConnectionClosed(None))),
+            }
+        }
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        if !this.closing {
+            if matches!(this.state, State::Done) {
+                return Poll::Ready(Ok(()));
+            }
+            ready!(Pin::new(&mut *this).poll_flush(cx))?;
+            if let State::Idle(mut conn) = mem::replace(&mut this.state, State::Done) {
+                this.state = State::Busy(Box::pin(async move {
+                    let result = conn.close(CloseCode::Normal, "").await;
+                    (conn, result)
+                }));
+            }
+            this.closing = true;
+        }
+        let State::Busy(step) = &mut this.state else {
+            return Poll::Ready(Ok(()));
+        };
+        let (_, result) = ready!(step.as_mut().poll(cx));
+        this.state = State::Done;
+        Poll::Ready(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{SinkExt, StreamExt};
+
+    use super::*;
+    use crate::config::Config;
+    use crate::connection::Role;
+    use crate::protocol::Frame;
+    use crate::testing::{MockStream, client_frame};
+
+    #[tokio::test]
+    async fn test_stream_yields_messages_until_close() {
+        let mut input = client_frame(&Frame::text(b"one".to_vec()));
+        input.extend(client_frame(&Frame::binary(vec![2])));
+        input.extend(client_frame(&Frame::close(Some(1000), "")));
+        let conn = Connection::new(MockStream::new(input), Role::Server, Config::server());
+
+        let messages: Vec<_> = conn.into_stream().map(Result::unwrap).collect().await;
+        assert_eq!(
+            messages,
+            [
+                Message::text("one"),
+                Message::binary(vec![2]),
+                Message::close(CloseCode::Normal, "")
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sink_buffers_until_flushed() {
+        let (ours, theirs) = tokio::io::duplex(4096);
+        let mut sink = Connection::new(ours, Role::Server, Config::server()).into_sink(4);
+        let mut peer = Connection::new(theirs, Role::Client, Config::client());
+
+        sink.feed(Message::text("a")).await.unwrap();
+        sink.feed(Message::text("b")).await.unwrap();
+        assert_eq!(sink.buffered(), 2);
+        sink.flush().await.unwrap();
+        assert_eq!(sink.buffered(), 0);
+
+        sink.close().await.unwrap();
+        assert_eq!(peer.recv().await.unwrap(), Some(Message::text("a")));
+        assert_eq!(peer.recv().await.unwrap(), Some(Message::text("b")));
+        assert_eq!(
+            peer.recv().await.unwrap(),
+            Some(Message::close(CloseCode::Normal, ""))
+        );
+        assert!(sink.send(Message::text("c")).await.is_err());
+    }
+}
@@ -0,0 +1,152 @@
+//! A connection that closes itself when dropped.
+
+use std::ops::{Deref, DerefMut};
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::connection::{Connection, ConnectionState};
+use crate::message::CloseCode;
+use crate::task;
+
+/// How long the close task waits for the peer to answer.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+impl<T> Connection<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    /// Close the connection with [`CloseCode::GoingAway`] if it is dropped
+    /// while still open, so the peer is not left waiting for a close that
+    /// never comes, e.g. when a task holding it panics or is cancelled.
+    ///
+    /// The close is sent by a short-lived task that waits up to a second for
+    /// the peer's answer. Dropped outside a tokio runtime, the connection is
+    /// closed without a close frame, like a plain `Connection`.
+    ///
+    /// ```rust,ignore
+    /// let mut conn = conn.close_on_drop();
+    /// conn.send(Message::text("hello")).await?;
+    /// // The peer receives a 1001 close when `conn` goes out of scope
+    /// ```
+    pub fn close_on_drop(self) -> CloseOnDrop<T> {
+        CloseOnDrop { conn: Some(self) }
+    }
+}
+
+/// A [`Connection`] that sends a close frame when dropped while open, made
+/// with [`Connection::close_on_drop`].
+///
+/// Derefs to the connection, so it is used like one.
+pub struct CloseOnDrop<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    /// `None` only once dropped or unwrapped.
+    conn: Option<Connection<T>>,
+}
+
+impl<T> CloseOnDrop<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    /// Unwrap the connection; dropping it no longer closes it.
+    pub fn into_inner(mut self) -> Connection<T> {
+        self.conn.take().expect("connection present until dropped")
+    }
+}
+
+impl<T> Deref for CloseOnDrop<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    type Target = Connection<T>;
+
+    fn deref(&self) -> &Connection<T> {
+        self.conn
+            .as_ref()
+            .expect("connection present until dropped")
+    }
+}
+
+impl<T> DerefMut for CloseOnDrop<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    fn deref_mut(&mut self) -> &mut Connection<T> {
+        self.conn
+            .as_mut()
+            .expect("connection present until dropped")
+    }
+}
+
+impl<T> Drop for CloseOnDrop<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    fn drop(&mut self) {
+        let Some(mut conn) = self.conn.take() else {
+            return;
+        };
+        if conn.state() != ConnectionState::Open || tokio::runtime::Handle::try_current().is_err() {
+            return;
+        }
+        ws_debug!(conn = conn.id(), "dropped while open, closing");
+        task::spawn(task::CLOSE_ON_DROP, async move {
+            let _ = conn
+                .close_with_timeout(CloseCode::GoingAway, "", CLOSE_TIMEOUT)
+                .await;
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::connection::Role;
+    use crate::message::Message;
+
+    fn pair() -> (
+        Connection<tokio::io::DuplexStream>,
+        Connection<tokio::io::DuplexStream>,
+    ) {
+        let (ours, theirs) = tokio::io::duplex(4096);
+        (
+            Connection::new(ours, Role::Server, Config::server()),
+            Connection::new(theirs, Role::Client, Config::client()),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_drop_sends_going_away() {
+        let (conn, mut peer) = pair();
+        let mut conn = conn.close_on_drop();
+        conn.send(Message::text("last")).await.unwrap();
+        drop(conn);
+
+        assert_eq!(peer.recv().await.unwrap(), Some(Message::text("last")));
+        assert_eq!(
+            peer.recv().await.unwrap(),
+            Some(Message::close(CloseCode::GoingAway, ""))
+        );
+        assert_eq!(peer.recv().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_closed_or_unwrapped_connection_is_left_alone() {
+        let (conn, mut peer) = pair();
+        let mut conn = conn.close_on_drop();
+        conn.close(CloseCode::Normal, "").await.unwrap();
+        drop(conn);
+        assert_eq!(
+            peer.recv().await.unwrap(),
+            Some(Message::close(CloseCode::Normal, ""))
+        );
+
+        let (conn, mut peer) = pair();
+        let conn = conn.close_on_drop().into_inner();
+        drop(conn);
+        assert_eq!(peer.recv().await.unwrap(), None);
+    }
+}
@@ -1,15 +1,36 @@
+use std::collections::VecDeque;
+use std::future::poll_fn;
+use std::io;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll, ready};
+use std::time::{Duration, Instant};
+
 use bytes::Bytes;
 use tokio::io::{AsyncRead, AsyncWrite};
 
 use crate::codec::WebSocketCodec;
-use crate::config::Config;
-use crate::connection::fragmenter::MessageFragmenter;
-use crate::connection::{ConnectionState, Role};
+use crate::config::{Config, SlowConsumerPolicy};
+use crate::connection::interceptor::{FrameAction, FrameInterceptor, run_chain};
+use crate::connection::queue::{Next, Outgoing, Priority, SendQueue};
+use crate::connection::rate::RateMeter;
+use crate::connection::spill::SpillTask;
+use crate::connection::{ConnectionEvent, ConnectionState, Latency, Role};
 use crate::error::{Error, Result};
-use crate::extensions::ExtensionRegistry;
-use crate::message::{CloseCode, CloseFrame, Message};
-use crate::protocol::assembler::{AssembledMessage, MessageAssembler};
+use crate::extensions::{ExtensionOffer, ExtensionRegistry};
+use crate::hub::PreparedMessage;
+use crate::message::{CloseCode, CloseFrame, Message, MessageOptions, MessageRef};
+use crate::protocol::assembler::{AssembledMessage, MessageAssembler, Pushed};
+use crate::protocol::frame::{FrameHeader, truncate_close_reason};
+use crate::protocol::utf8::validate_utf8;
 use crate::protocol::{Frame, OpCode};
+use crate::server::Slot;
+
+#[cfg(feature = "metrics")]
+use crate::metrics::{self, Direction};
+
+/// Source of [`Connection::id`] values.
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
 
 /// A WebSocket connection wrapping an async I/O stream.
 ///
@@ -39,11 +60,121 @@ use crate::protocol::{Frame, OpCode};
 /// }
 /// ```
 pub struct Connection<T> {
+    id: u64,
     codec: WebSocketCodec<T>,
     state: ConnectionState,
     assembler: MessageAssembler,
+    /// Writes of the incoming message being spilled.
+    spill: SpillTask,
     pending_pong: Option<Bytes>,
+    pending_pings: VecDeque<(Bytes, Instant)>,
+    latency: Option<Latency>,
+    /// When a frame was last written, for `pong_heartbeat`.
+    last_sent: tokio::time::Instant,
+    /// When a frame was last received, for `keepalive`.
+    last_heard: tokio::time::Instant,
+    /// When the keepalive ping still waiting for an answer went out.
+    keepalive_ping: Option<tokio::time::Instant>,
+    /// When `recv_timeout` gives up waiting, with the timeout it was given.
+    recv_deadline: Option<(tokio::time::Instant, Duration)>,
+    /// Counts received traffic against `inbound_rate`.
+    inbound: Option<RateMeter>,
+    /// Since when the send queue has been over `slow_consumer`'s limit.
+    backlog_since: Option<tokio::time::Instant>,
+    /// Close code to send on the next `send_next` after the peer fell too
+    /// far behind.
+    slow_close: Option<CloseCode>,
     extensions: ExtensionRegistry,
+    /// Subprotocol agreed in the handshake.
+    subprotocol: Option<String>,
+    interceptors: Vec<Box<dyn FrameInterceptor>>,
+    queue: SendQueue,
+    /// Message received by `peek` and not yet returned by `recv`.
+    peeked: Option<Message>,
+    /// Message handed out by reference from `recv_ref`.
+    lent: Option<Message>,
+    /// Close frame this endpoint sent, for `close_reason`.
+    sent_close: Option<CloseFrame>,
+    /// Close frame the peer sent, if it carried a status code.
+    received_close: Option<CloseFrame>,
+    /// Client address resolved by the server that accepted the connection.
+    client_addr: Option<IpAddr>,
+    /// Released when the connection is dropped, freeing its place under the
+    /// server's connection limits.
+    _slot: Option<Slot>,
+    #[cfg(feature = "metrics")]
+    _metrics: metrics::ConnectionGuard,
+    /// Holds the connection id and role for everything the connection
+    /// logs.
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+}
+
+/// The pieces of a [`Connection`], from [`Connection::into_parts`].
+#[non_exhaustive]
+pub struct ConnectionParts<T> {
+    /// The underlying stream.
+    pub io: T,
+    /// Bytes read from the stream that the connection has not decoded yet.
+    pub read_buf: Bytes,
+    /// Client or server.
+    pub role: Role,
+    /// The connection's configuration.
+    pub config: Config,
+    /// Negotiated extensions, with their compression state.
+    pub extensions: ExtensionRegistry,
+    /// Subprotocol agreed in the handshake.
+    pub subprotocol: Option<String>,
+    /// Registered frame interceptors.
+    pub interceptors: Vec<Box<dyn FrameInterceptor>>,
+    /// Open, closing or closed.
+    pub state: ConnectionState,
+}
+
+impl<T> ConnectionParts<T> {
+    /// Parts for a freshly opened connection on `io`, e.g. one handed over
+    /// from another process after the handshake.
+    #[must_use]
+    pub fn new(io: T, role: Role, config: Config) -> Self {
+        Self {
+            io,
+            read_buf: Bytes::new(),
+            role,
+            config,
+            extensions: ExtensionRegistry::new(),
+            subprotocol: None,
+            interceptors: Vec::new(),
+            state: ConnectionState::Open,
+        }
+    }
+
+    /// Set bytes already read from the stream.
+    #[must_use]
+    pub fn with_read_buf(mut self, read_buf: impl Into<Bytes>) -> Self {
+        self.read_buf = read_buf.into();
+        self
+    }
+
+    /// Set the negotiated extensions.
+    #[must_use]
+    pub fn with_extensions(mut self, extensions: ExtensionRegistry) -> Self {
+        self.extensions = extensions;
+        self
+    }
+
+    /// Set the subprotocol agreed in the handshake.
+    #[must_use]
+    pub fn with_subprotocol(mut self, protocol: impl Into<String>) -> Self {
+        self.subprotocol = Some(protocol.into());
+        self
+    }
+
+    /// Set the connection state.
+    #[must_use]
+    pub fn with_state(mut self, state: ConnectionState) -> Self {
+        self.state = state;
+        self
+    }
 }
 
 impl<T> Connection<T> {
@@ -78,21 +209,153 @@ impl<T> Connection<T> {
         config: Config,
         extensions: ExtensionRegistry,
     ) -> Self {
+        let conn = Self::assemble(io, role, config, extensions);
+        conn.emit(ConnectionEvent::HandshakeComplete { role });
+        conn
+    }
+
+    fn assemble(io: T, role: Role, config: Config, mut extensions: ExtensionRegistry) -> Self {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        #[cfg(feature = "tracing")]
+        let span = tracing::debug_span!("ws_connection", conn = id, ?role);
+        ws_enter!(span);
+        ws_debug!(conn = id, role = ?role, "connection opened");
+
         let assembler = MessageAssembler::new(config.clone());
+        let inbound = config.inbound_rate.map(RateMeter::new);
+        let mut codec = WebSocketCodec::new(io, role, config);
+        codec.allow_rsv_bits(extensions.negotiated_rsv_bits().mask());
+        extensions.set_limits(&codec.config().limits);
         Self {
-            codec: WebSocketCodec::new(io, role, config),
+            id,
+            codec,
             state: ConnectionState::Open,
             assembler,
+            spill: SpillTask::Idle,
             pending_pong: None,
+            pending_pings: VecDeque::new(),
+            latency: None,
+            last_sent: tokio::time::Instant::now(),
+            last_heard: tokio::time::Instant::now(),
+            keepalive_ping: None,
+            recv_deadline: None,
+            inbound,
+            backlog_since: None,
+            slow_close: None,
             extensions,
+            subprotocol: None,
+            interceptors: Vec::new(),
+            queue: SendQueue::default(),
+            peeked: None,
+            lent: None,
+            sent_close: None,
+            received_close: None,
+            client_addr: None,
+            _slot: None,
+            #[cfg(feature = "metrics")]
+            _metrics: metrics::ConnectionGuard::new(),
+            #[cfg(feature = "tracing")]
+            span,
+        }
+    }
+
+    /// Take the connection apart, e.g. to wrap its stream in another layer or
+    /// hand it to another owner, and put it back together with
+    /// [`from_parts`](Self::from_parts).
+    ///
+    /// Bytes already read from the stream but not yet decoded are returned in
+    /// [`ConnectionParts::read_buf`] and must be passed on with the stream.
+    /// A partially received or peeked message, queued messages, ping tracking and the
+    /// server's connection-limit slot do not survive the round trip, so take
+    /// a connection apart between messages.
+    #[must_use]
+    pub fn into_parts(self) -> ConnectionParts<T> {
+        let role = self.codec.role();
+        let config = self.codec.config().clone();
+        let (io, read_buf) = self.codec.into_parts();
+        ConnectionParts {
+            io,
+            read_buf: read_buf.freeze(),
+            role,
+            config,
+            extensions: self.extensions,
+            subprotocol: self.subprotocol,
+            interceptors: self.interceptors,
+            state: self.state,
         }
     }
 
+    /// Rebuild a connection from [`into_parts`](Self::into_parts).
+    ///
+    /// The stream may have been replaced, e.g. by a TLS stream over the
+    /// original one. The connection gets a new [`id`](Self::id) and, unlike
+    /// [`new`](Self::new), reports no `HandshakeComplete` event.
+    #[must_use]
+    pub fn from_parts(parts: ConnectionParts<T>) -> Self {
+        let mut conn = Self::assemble(parts.io, parts.role, parts.config, parts.extensions);
+        conn.codec.preload(&parts.read_buf);
+        conn.subprotocol = parts.subprotocol;
+        conn.interceptors = parts.interceptors;
+        conn.state = parts.state;
+        conn
+    }
+
+    /// Process-unique identifier for this connection.
+    ///
+    /// The same value is attached to diagnostics emitted with the `tracing`
+    /// feature, so it can be used to correlate application logs with them.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// The `ws_connection` span the connection's own events are recorded
+    /// in, with its id and role, e.g. to instrument the task that drives it.
+    #[cfg(feature = "tracing")]
+    pub fn span(&self) -> &tracing::Span {
+        &self.span
+    }
+
+    /// The address of the client, for connections accepted by a
+    /// [`Server`](crate::server::Server): the peer's address, or the client
+    /// behind it named in forwarding headers when the peer is one of the
+    /// server's [`TrustedProxies`](crate::server::TrustedProxies). `None`
+    /// for connections made otherwise.
+    pub fn client_addr(&self) -> Option<IpAddr> {
+        self.client_addr
+    }
+
     /// Get the current connection state.
     pub fn state(&self) -> ConnectionState {
         self.state
     }
 
+    /// Why the connection closed: the peer's close frame, or the one this
+    /// endpoint sent if the peer's carried no status code or never came.
+    /// `None` while the connection is open.
+    ///
+    /// Kept after [`recv`](Self::recv) has returned `None`, for logging.
+    ///
+    /// ```rust,ignore
+    /// while let Some(msg) = conn.recv().await? { /* ... */ }
+    /// if let Some(close) = conn.close_reason() {
+    ///     log::info!("closed: {} {}", close.code.as_u16(), close.reason);
+    /// }
+    /// ```
+    pub fn close_reason(&self) -> Option<&CloseFrame> {
+        self.received_close.as_ref().or(self.sent_close.as_ref())
+    }
+
+    /// The close frame this endpoint sent, including its answer to the
+    /// peer's.
+    pub fn sent_close(&self) -> Option<&CloseFrame> {
+        self.sent_close.as_ref()
+    }
+
+    /// The close frame the peer sent, if it carried a status code.
+    pub fn received_close(&self) -> Option<&CloseFrame> {
+        self.received_close.as_ref()
+    }
+
     /// Check if the connection is in an open state.
     ///
     /// Returns `true` if messages can be sent and received.
@@ -100,19 +363,169 @@ impl<T> Connection<T> {
         self.state == ConnectionState::Open
     }
 
+    /// Discard everything not yet written and mark the connection closed.
+    fn tear_down(&mut self) {
+        self.codec.discard_held();
+        self.queue = SendQueue::default();
+        self.pending_pong = None;
+        self.peeked = None;
+        self.set_state(ConnectionState::Closed);
+    }
+
+    /// Number of pings sent that have not been answered by a pong yet.
+    pub fn pending_pings(&self) -> usize {
+        self.pending_pings.len()
+    }
+
+    /// Round-trip time measured from pings answered so far.
+    ///
+    /// Each pong that matches an outstanding ping yields one sample. Returns
+    /// `None` until the first ping has been answered.
+    pub fn latency(&self) -> Option<Latency> {
+        self.latency
+    }
+
+    /// The subprotocol agreed in the handshake (`Sec-WebSocket-Protocol`),
+    /// if any. Set by [`server`](crate::server) and
+    /// [`client`](crate::client) handshakes, or with
+    /// [`with_subprotocol`](Self::with_subprotocol) after a handshake done
+    /// elsewhere.
+    pub fn subprotocol(&self) -> Option<&str> {
+        self.subprotocol.as_deref()
+    }
+
+    /// Record the subprotocol agreed in a handshake done outside this crate.
+    #[must_use]
+    pub fn with_subprotocol(mut self, protocol: impl Into<String>) -> Self {
+        self.subprotocol = Some(protocol.into());
+        self
+    }
+
+    /// Names of the extensions negotiated in the handshake, e.g.
+    /// `["permessage-deflate"]`. Empty if none were.
+    pub fn negotiated_extensions(&self) -> Vec<&str> {
+        self.extensions
+            .negotiated()
+            .iter()
+            .map(|e| e.name.as_str())
+            .collect()
+    }
+
+    /// The negotiated extensions with the parameters agreed for each, such
+    /// as `server_no_context_takeover`.
+    pub fn negotiated_extension_params(&self) -> &[ExtensionOffer] {
+        self.extensions.negotiated()
+    }
+
+    /// Estimated bytes of state the negotiated extensions hold, such as
+    /// permessage-deflate's zlib contexts, which `DeflateConfig::max_memory`
+    /// bounds.
+    pub fn extension_memory(&self) -> usize {
+        self.extensions.memory_usage()
+    }
+
     /// Get mutable access to the extension registry.
     pub fn extensions_mut(&mut self) -> &mut ExtensionRegistry {
         &mut self.extensions
     }
+
+    /// Record the subprotocol from a handshake response.
+    pub(crate) fn set_subprotocol(&mut self, protocol: Option<String>) {
+        self.subprotocol = protocol;
+    }
+
+    /// Feed bytes read past the handshake to the frame decoder.
+    pub(crate) fn preload(&mut self, data: &[u8]) {
+        self.codec.preload(data);
+    }
+
+    /// Register a frame interceptor.
+    ///
+    /// Interceptors run in the order they were added, on every frame sent or
+    /// received from then on. See [`FrameInterceptor`].
+    pub fn add_interceptor(&mut self, interceptor: impl FrameInterceptor + 'static) {
+        self.interceptors.push(Box::new(interceptor));
+    }
+
+    pub(crate) fn hold_slot(&mut self, slot: Slot) {
+        self._slot = Some(slot);
+    }
+
+    pub(crate) fn set_client_addr(&mut self, addr: IpAddr) {
+        self.client_addr = Some(addr);
+    }
+
+    fn emit(&self, event: ConnectionEvent<'_>) {
+        if let Some(observer) = &self.codec.config().observer {
+            observer.on_event(self.id, &event);
+        }
+    }
+
+    /// Pass a result through, reporting an error to tracing and the observer.
+    fn report<R>(&self, result: Result<R>) -> Result<R> {
+        if let Err(e) = &result {
+            ws_enter!(self.span);
+            ws_debug!(conn = self.id, error = %e, "operation failed");
+            self.emit(ConnectionEvent::Error(e));
+        }
+        result
+    }
+
+    fn set_state(&mut self, state: ConnectionState) {
+        if self.state != state {
+            ws_enter!(self.span);
+            ws_debug!(conn = self.id, from = ?self.state, to = ?state, "state changed");
+            self.state = state;
+        }
+    }
 }
 
 impl<T: AsyncRead + AsyncWrite + Unpin> Connection<T> {
+    /// Tear the connection down without a closing handshake.
+    ///
+    /// Nothing more is written: frames held back by write coalescing, queued
+    /// messages and a pending pong are discarded, and the state becomes
+    /// `Closed`, so later sends fail and receives return `Ok(None)`. The
+    /// write side of the transport is shut down without waiting, so the peer
+    /// sees it close at once; a transport that cannot shut down straight
+    /// away, like TLS with a full socket, closes when the connection is
+    /// dropped.
+    ///
+    /// Use this when the peer does not deserve a Close frame, e.g. after an
+    /// authentication failure or abuse.
+    pub fn abort(&mut self) {
+        if self.state == ConnectionState::Closed {
+            return;
+        }
+        ws_enter!(self.span);
+        ws_debug!(conn = self.id, "aborting connection");
+        self.tear_down();
+        self.codec.start_shutdown();
+        self.emit(ConnectionEvent::Aborted);
+    }
+
     /// Send a message over the WebSocket connection.
     ///
-    /// Data messages (Text/Binary) are automatically fragmented if they exceed
-    /// the configured `fragment_size` (default: 16 KB). Control frames (Ping,
+    /// Data messages (Text/Binary) are split into frames by the configured
+    /// [`Fragmenter`](crate::Fragmenter), by default in `fragment_size`
+    /// pieces (16 KB). Control frames (Ping,
     /// Pong, Close) are never fragmented per RFC 6455.
     ///
+    /// With `Config::write_coalescing` set, small messages may be held back
+    /// and written together with later ones; see
+    /// [`WriteCoalescing`](crate::config::WriteCoalescing). Their delay
+    /// only runs out while a receive waits, so a connection that only sends
+    /// must [`flush`](Self::flush) after its last message; one moved
+    /// [`into_handle`](Self::into_handle) is flushed on time by its task.
+    ///
+    /// ## Cancel safety
+    ///
+    /// The message is encoded into the connection's write buffer before
+    /// anything is awaited, so dropping the future, e.g. in a losing
+    /// `tokio::select!` branch, never leaves half a frame on the wire:
+    /// either the message was not taken yet, or the rest of it is written
+    /// by the next send, flush or receive.
+    ///
     /// ## Errors
     ///
     /// - `Error::ConnectionClosed` if the connection is not in a state that allows sending
@@ -120,249 +533,1364 @@ impl<T: AsyncRead + AsyncWrite + Unpin> Connection<T> {
     /// - `Error::FrameTooLarge` if a fragment exceeds `limits.max_frame_size`
     /// - I/O errors from the underlying stream
     pub async fn send(&mut self, message: Message) -> Result<()> {
-        if !self.state.can_send() {
-            return Err(Error::ConnectionClosed(None));
-        }
-
-        // Control frames are never fragmented
-        if message.is_control() {
-            let frame = Frame::from(message);
-            self.codec.write_frame(&frame).await?;
-            self.codec.flush().await?;
-            return Ok(());
-        }
-
-        // Validate message size before processing
-        let payload = message.payload();
-        self.codec
-            .config()
-            .limits
-            .check_message_size(payload.len())?;
-
-        let opcode = if message.is_text() {
-            OpCode::Text
-        } else {
-            OpCode::Binary
-        };
-
-        let fragment_size = self.codec.config().fragment_size;
+        self.send_with(message, MessageOptions::default()).await
+    }
 
-        if payload.len() <= fragment_size {
-            // Small message: single frame with extension encoding
-            let mut frame = Frame::from(message);
-            self.extensions.encode(&mut frame)?;
-            self.codec.write_frame(&frame).await?;
-        } else {
-            // Large message: fragment into multiple frames
-            let fragmenter = MessageFragmenter::new(payload, opcode, fragment_size);
-            let mut is_first = true;
+    /// Send a message with per-message options.
+    ///
+    /// ```rust,ignore
+    /// // Already-compressed payloads skip permessage-deflate
+    /// conn.send_with(Message::binary(png), MessageOptions::uncompressed()).await?;
+    /// ```
+    pub async fn send_with(&mut self, message: Message, options: MessageOptions) -> Result<()> {
+        let result = ws_instrument!(self.span, self.write_message(message, options, true));
+        self.report(result)
+    }
 
-            for mut frame in fragmenter {
-                // RFC 7692: Extension encoding only on first frame
-                if is_first && frame.opcode.is_data() {
-                    self.extensions.encode(&mut frame)?;
-                    is_first = false;
-                }
-                self.codec.write_frame(&frame).await?;
+    /// Send a message, giving up if writing it takes longer than `timeout`.
+    ///
+    /// Use this where one slow peer must not hold up others, e.g. in a loop
+    /// forwarding broadcasts. On timeout the rest of the message is still
+    /// waiting for a peer that is not reading, so the connection is torn
+    /// down as by [`abort`](Self::abort) and cannot be used again.
+    ///
+    /// ## Errors
+    ///
+    /// - `Error::WriteTimeout` if the deadline passed
+    /// - Otherwise as for [`send`](Self::send)
+    pub async fn send_timeout(&mut self, message: Message, timeout: Duration) -> Result<()> {
+        match ws_instrument!(
+            self.span,
+            tokio::time::timeout(
+                timeout,
+                self.write_message(message, MessageOptions::default(), true),
+            )
+        ) {
+            Ok(result) => self.report(result),
+            Err(_) => {
+                ws_debug!(conn = self.id, ?timeout, "send timed out");
+                self.abort();
+                self.report(Err(Error::WriteTimeout(timeout)))
             }
         }
+    }
 
-        self.codec.flush().await?;
-        Ok(())
+    /// Send a message without per-message compression, even if it was
+    /// negotiated. Shorthand for [`send_with`](Self::send_with) and
+    /// [`MessageOptions::uncompressed`].
+    pub async fn send_uncompressed(&mut self, message: Message) -> Result<()> {
+        self.send_with(message, MessageOptions::uncompressed())
+            .await
     }
 
     /// Send message without flushing. Call flush() when ready.
     pub async fn send_no_flush(&mut self, message: Message) -> Result<()> {
-        if !self.state.can_send() {
-            return Err(Error::ConnectionClosed(None));
+        let result = ws_instrument!(
+            self.span,
+            self.write_message(message, MessageOptions::default(), false)
+        );
+        self.report(result)
+    }
+
+    /// Send multiple messages with single flush at end.
+    pub async fn send_batch(&mut self, messages: impl IntoIterator<Item = Message>) -> Result<()> {
+        for message in messages {
+            self.send_no_flush(message).await?;
         }
+        self.flush().await
+    }
 
-        // Control frames are never fragmented
-        if message.is_control() {
-            let frame = Frame::from(message);
-            self.codec.write_frame(&frame).await?;
-            return Ok(());
+    /// Send a message encoded ahead of time, e.g. by a [`Hub`](crate::hub::Hub).
+    ///
+    /// The shared bytes are written as they are when this connection would
+    /// have produced the same frame: server role, no negotiated extensions,
+    /// no interceptors and a data message that fits in one fragment.
+    /// Otherwise the message is encoded again as by [`send`](Self::send).
+    pub async fn send_prepared(&mut self, prepared: &PreparedMessage) -> Result<()> {
+        let result = ws_instrument!(self.span, self.write_prepared(prepared));
+        self.report(result)
+    }
+
+    /// Flush pending writes to the underlying stream.
+    pub async fn flush(&mut self) -> Result<()> {
+        let result = ws_instrument!(self.span, self.codec.flush());
+        self.report(result)
+    }
+
+    /// Poll for the next message, as [`recv`](Self::recv) without a future,
+    /// for hand-written futures and executors other than tokio's.
+    ///
+    /// Pongs and close replies owed to the peer are written first, and
+    /// `Pending` is returned while they cannot be. Bytes read before
+    /// `Pending` stay buffered, so the call can be repeated or abandoned at
+    /// any time.
+    ///
+    /// Timers are only driven by the async methods: `Config::keepalive`,
+    /// `pong_heartbeat` and the `write_coalescing` delay do nothing while
+    /// the connection is only polled.
+    ///
+    /// ```rust,ignore
+    /// impl Future for NextText<'_> {
+    ///     type Output = Result<Option<Message>>;
+    ///
+    ///     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    ///         loop {
+    ///             match ready!(self.conn.poll_recv(cx))? {
+    ///                 Some(msg) if !msg.is_text() => continue,
+    ///                 msg => return Poll::Ready(Ok(msg)),
+    ///             }
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    ///
+    /// ## Errors
+    ///
+    /// As for [`recv`](Self::recv), except that a peer gone quiet is not
+    /// detected.
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Result<Option<Message>>> {
+        ws_enter!(self.span);
+        self.recv_deadline = None;
+        let result = ready!(self.poll_next_message(cx));
+        if let Err(e) = &result
+            && let Some(frame) = self.failing(e)
+        {
+            // Best effort, as for `recv`
+            let _ = self.queue_frame(frame);
+            let _ = self.codec.poll_flush(cx);
         }
+        Poll::Ready(self.report(result))
+    }
 
-        // Validate message size before processing
-        let payload = message.payload();
-        self.codec
-            .config()
-            .limits
-            .check_message_size(payload.len())?;
+    /// Poll to send `message`, as [`send`](Self::send) without a future.
+    ///
+    /// Returns `Pending` while earlier output is still being written, then
+    /// encodes the message and starts writing it. `Ready(Ok(()))` means the
+    /// message is queued, not that it was written: finish with
+    /// [`poll_flush`](Self::poll_flush), or keep polling either method.
+    /// A data message is encoded whole, all its fragments at once. A
+    /// spilled one is read from disk a fragment at a time on the blocking
+    /// thread pool, and `Pending` is returned until it is all encoded: poll
+    /// again with the same message.
+    ///
+    /// ## Errors
+    ///
+    /// As for [`send`](Self::send).
+    pub fn poll_send(&mut self, cx: &mut Context<'_>, message: &Message) -> Poll<Result<()>> {
+        ws_enter!(self.span);
+        let result = ready!(self.poll_queue_message(cx, message));
+        Poll::Ready(self.report(result))
+    }
 
-        let opcode = if message.is_text() {
-            OpCode::Text
-        } else {
-            OpCode::Binary
-        };
+    /// Poll until everything sent is written and the stream flushed, as
+    /// [`flush`](Self::flush).
+    ///
+    /// ## Errors
+    ///
+    /// `Error::Io` if writing or flushing fails.
+    pub fn poll_flush(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        ws_enter!(self.span);
+        let result = ready!(self.codec.poll_flush(cx));
+        Poll::Ready(self.report(result))
+    }
 
-        let fragment_size = self.codec.config().fragment_size;
+    /// Queue a message to be sent by [`send_next`](Self::send_next) or
+    /// [`flush_queue`](Self::flush_queue).
+    ///
+    /// Control messages jump the queue, and go out even between the fragments
+    /// of a large message. `High` data messages overtake `Normal` ones but
+    /// never split another data message.
+    ///
+    /// `Config::slow_consumer` bounds how long the queue may stay large;
+    /// without it the queue grows as long as messages are queued faster
+    /// than they are sent. Messages queued after a slow-consumer disconnect
+    /// are discarded.
+    ///
+    /// ```rust,ignore
+    /// conn.enqueue(Message::binary(upload), Priority::Normal);
+    /// while conn.send_next().await? {
+    ///     if let Some(update) = urgent.try_recv().ok() {
+    ///         conn.enqueue(update, Priority::High);
+    ///     }
+    /// }
+    /// conn.flush().await?;
+    /// ```
+    pub fn enqueue(&mut self, message: Message, priority: Priority) {
+        self.enqueue_with(message, priority, MessageOptions::default());
+    }
 
-        if payload.len() <= fragment_size {
-            let mut frame = Frame::from(message);
-            self.extensions.encode(&mut frame)?;
-            self.codec.write_frame(&frame).await?;
-        } else {
-            let fragmenter = MessageFragmenter::new(payload, opcode, fragment_size);
-            let mut is_first = true;
+    /// Queue a message with per-message options.
+    pub fn enqueue_with(&mut self, message: Message, priority: Priority, options: MessageOptions) {
+        if self.slow_close.is_some() {
+            return;
+        }
+        self.queue.push(message, priority, options);
+        self.check_backlog();
+    }
 
-            for mut frame in fragmenter {
-                if is_first && frame.opcode.is_data() {
-                    self.extensions.encode(&mut frame)?;
-                    is_first = false;
-                }
-                self.codec.write_frame(&frame).await?;
+    /// Apply `slow_consumer`'s policy if the send queue has stalled.
+    fn check_backlog(&mut self) {
+        let Some(slow) = self.codec.config().slow_consumer else {
+            return;
+        };
+        if self.slow_close.is_some() || !self.backlogged() {
+            return;
+        }
+        match slow.policy {
+            SlowConsumerPolicy::Disconnect(code) => {
+                ws_debug!(conn = self.id, "send queue stalled, disconnecting");
+                self.queue = SendQueue::default();
+                self.backlog_since = None;
+                self.slow_close = Some(code);
+            }
+            SlowConsumerPolicy::DropOldest => {
+                let _dropped = self.queue.drop_oldest(slow.max_queued_bytes);
+                ws_debug!(
+                    conn = self.id,
+                    dropped = _dropped,
+                    "send queue stalled, dropped oldest"
+                );
+                self.backlog_since = None;
             }
+            SlowConsumerPolicy::Block => {}
         }
-
-        Ok(())
     }
 
-    /// Send multiple messages with single flush at end.
-    pub async fn send_batch(&mut self, messages: impl IntoIterator<Item = Message>) -> Result<()> {
-        for message in messages {
-            self.send_no_flush(message).await?;
+    /// Whether the send queue has been over `slow_consumer`'s limit for
+    /// its whole grace period.
+    fn backlogged(&mut self) -> bool {
+        let Some(slow) = self.codec.config().slow_consumer else {
+            return false;
+        };
+        if self.queue.bytes() <= slow.max_queued_bytes {
+            self.backlog_since = None;
+            return false;
         }
-        self.flush().await
+        let since = *self
+            .backlog_since
+            .get_or_insert_with(tokio::time::Instant::now);
+        since.elapsed() >= slow.grace
     }
 
-    /// Flush pending writes to the underlying stream.
-    pub async fn flush(&mut self) -> Result<()> {
-        self.codec.flush().await
+    /// Number of queued messages not yet started.
+    pub fn queued(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Write the next frame from the send queue, without flushing.
+    ///
+    /// Returns `false` once the queue is empty and no message is in progress.
+    pub async fn send_next(&mut self) -> Result<bool> {
+        let result = ws_instrument!(self.span, self.write_next());
+        self.report(result)
+    }
+
+    /// Write everything in the send queue, then flush.
+    pub async fn flush_queue(&mut self) -> Result<()> {
+        while self.send_next().await? {}
+        self.flush().await
     }
 
     /// Receive the next message from the WebSocket connection.
     ///
     /// This method handles:
-    /// - Automatic pong response to ping frames
+    /// - Pong responses to ping frames, as set by `Config::pong_policy`
+    /// - Pings to a quiet peer, as set by `Config::keepalive`
     /// - Message reassembly from fragments
     /// - Close frame handling and response
     ///
     /// Returns `Ok(Some(Message))` for normal messages, `Ok(None)` when the
     /// connection has been closed, or an error.
     ///
+    /// ## Cancel safety
+    ///
+    /// Bytes read, fragments assembled and a message received while its
+    /// close reply is being written are all kept in the connection, so
+    /// dropping the future, e.g. in a losing `tokio::select!` branch, loses
+    /// nothing and the next call carries on where this one stopped.
+    ///
     /// ## Errors
     ///
     /// - Protocol errors (invalid frame, UTF-8 violation, etc.)
     /// - I/O errors from the underlying stream
+    /// - `Error::KeepaliveTimeout` if a keepalive ping goes unanswered
+    /// - `Error::InboundRateExceeded` if the peer sends faster than
+    ///   `Config::inbound_rate` allows
     pub async fn recv(&mut self) -> Result<Option<Message>> {
+        // Left over if a `recv_timeout` future was dropped
+        self.recv_deadline = None;
+        ws_instrument!(self.span, self.receive())
+    }
+
+    async fn receive(&mut self) -> Result<Option<Message>> {
+        if let Some(message) = self.peeked.take() {
+            return Ok(Some(message));
+        }
         if !self.state.can_receive() {
             return Ok(None);
         }
 
-        loop {
-            if let Some(pong_data) = self.pending_pong.take() {
-                let pong_frame = Frame::pong(pong_data.to_vec());
-                self.codec.write_frame(&pong_frame).await?;
-                self.codec.flush().await?;
-            }
-
-            let frame = match self.codec.read_frame().await {
-                Ok(f) => f,
-                Err(Error::ConnectionClosed(_)) => {
-                    self.state = ConnectionState::Closed;
-                    return Ok(None);
-                }
-                Err(e) => return Err(e),
-            };
+        let result = self.next_message().await;
+        if let Err(e) = &result {
+            self.fail(e).await;
+        }
+        self.report(result)
+    }
 
-            match frame.opcode {
-                OpCode::Ping => {
-                    frame.validate()?;
-                    let payload = frame.into_payload_bytes();
-                    self.pending_pong = Some(payload.clone());
-                    return Ok(Some(Message::Ping(payload)));
-                }
-                OpCode::Pong => {
-                    frame.validate()?;
-                    return Ok(Some(Message::Pong(frame.into_payload_bytes())));
-                }
-                OpCode::Close => {
-                    frame.validate()?;
-                    let close_frame = self.parse_close_frame(&frame);
-
-                    if self.state == ConnectionState::Open {
-                        self.state = ConnectionState::Closing;
-                        let response = if let Some(ref cf) = close_frame {
-                            Frame::close(Some(cf.code.as_u16()), &cf.reason)
-                        } else {
-                            Frame::close(None, "")
-                        };
-                        let _ = self.codec.write_frame(&response).await;
-                        let _ = self.codec.flush().await;
-                    }
+    /// Receive the next message as by [`recv`](Self::recv), giving up after
+    /// `timeout` if none arrives.
+    ///
+    /// Unlike wrapping `recv` in `tokio::time::timeout`, the deadline only
+    /// interrupts the wait for more bytes, never a pong, close reply or
+    /// partly assembled message in progress, so nothing is lost and the
+    /// next call carries on where this one stopped.
+    ///
+    /// ```rust,ignore
+    /// match conn.recv_timeout(Duration::from_secs(5)).await {
+    ///     Ok(Some(msg)) => handle(msg),
+    ///     Ok(None) => return Ok(()), // closed
+    ///     Err(Error::RecvTimeout(_)) => conn.send(Message::text("still there?")).await?,
+    ///     Err(e) => return Err(e),
+    /// }
+    /// ```
+    ///
+    /// ## Errors
+    ///
+    /// `Error::RecvTimeout` if no message arrived in time, which leaves the
+    /// connection usable; otherwise as for [`recv`](Self::recv).
+    pub async fn recv_timeout(&mut self, timeout: Duration) -> Result<Option<Message>> {
+        self.recv_deadline = Some((tokio::time::Instant::now() + timeout, timeout));
+        let result = ws_instrument!(self.span, self.receive());
+        self.recv_deadline = None;
+        result
+    }
 
-                    self.state = ConnectionState::Closed;
-                    return Ok(Some(Message::Close(close_frame)));
-                }
-                OpCode::Text | OpCode::Binary | OpCode::Continuation => {
-                    frame.validate()?;
-                    if let Some(assembled) = self.assembler.push(frame)? {
-                        return Ok(Some(self.assembled_to_message(assembled)?));
-                    }
-                }
+    /// Receive the next data message, which must be text. Pings and pongs
+    /// are handled as by [`recv`](Self::recv) and skipped.
+    ///
+    /// ```rust,ignore
+    /// conn.send(Message::text("GET /status")).await?;
+    /// let status = conn.recv_text().await?;
+    /// ```
+    ///
+    /// A message spilled to disk is read back into memory.
+    ///
+    /// ## Errors
+    ///
+    /// - `Error::ConnectionClosed` with the peer's close code once the
+    ///   connection is closed
+    /// - `Error::UnexpectedMessage` for a binary message, which is consumed
+    ///   and leaves the connection usable
+    /// - Otherwise as for [`recv`](Self::recv)
+    pub async fn recv_text(&mut self) -> Result<String> {
+        match self.recv_data().await? {
+            Message::Text(text) => Ok(text),
+            Message::Spilled(spilled) if spilled.is_text() => {
+                Ok(String::from_utf8(spilled.read().await?).map_err(|_| Error::InvalidUtf8)?)
             }
+            message => Err(Error::UnexpectedMessage {
+                expected: OpCode::Text,
+                got: message.opcode(),
+            }),
         }
     }
 
-    /// Send a ping frame.
+    /// Receive the next data message, which must be binary. Pings and pongs
+    /// are handled as by [`recv`](Self::recv) and skipped.
     ///
-    /// This is a convenience method that wraps `send(Message::Ping(...))`.
-    pub async fn ping(&mut self, data: impl Into<Bytes>) -> Result<()> {
-        self.send(Message::Ping(data.into())).await
+    /// A message spilled to disk is read back into memory.
+    ///
+    /// ## Errors
+    ///
+    /// As for [`recv_text`](Self::recv_text), with a text message being the
+    /// unexpected kind.
+    pub async fn recv_binary(&mut self) -> Result<Bytes> {
+        match self.recv_data().await? {
+            Message::Binary(data) => Ok(data),
+            Message::Spilled(spilled) if spilled.is_binary() => Ok(spilled.read().await?.into()),
+            message => Err(Error::UnexpectedMessage {
+                expected: OpCode::Binary,
+                got: message.opcode(),
+            }),
+        }
     }
 
-    /// Send a pong frame.
-    ///
-    /// This is a convenience method that wraps `send(Message::Pong(...))`.
-    pub async fn pong(&mut self, data: impl Into<Bytes>) -> Result<()> {
-        self.send(Message::Pong(data.into())).await
+    /// The next text, binary or spilled message, with a close turned into
+    /// `Error::ConnectionClosed`.
+    async fn recv_data(&mut self) -> Result<Message> {
+        loop {
+            match self.recv().await? {
+                Some(Message::Ping(_) | Message::Pong(_)) => {}
+                Some(Message::Close(frame)) => {
+                    return Err(Error::ConnectionClosed(frame.map(|f| f.code.as_u16())));
+                }
+                Some(message) => return Ok(message),
+                None => return Err(Error::ConnectionClosed(None)),
+            }
+        }
     }
 
-    /// Initiate a close handshake.
+    /// Receive the next message, borrowing its payload instead of copying it.
     ///
-    /// Sends a close frame with the given status code and reason, then waits
-    /// for the peer's close response.
+    /// A single-frame text or binary message without compression is handed
+    /// out straight from the read buffer, unmasked in place. Other messages
+    /// (fragmented, compressed, control frames, or any message when frame
+    /// interceptors are registered) are received as by [`recv`](Self::recv)
+    /// and lent from the connection. Either way the message stays valid until
+    /// the next call on the connection.
     ///
-    /// ## Arguments
+    /// ```rust,ignore
+    /// while let Some(msg) = conn.recv_ref().await? {
+    ///     if let MessageRef::Binary(data) = msg {
+    ///         sink.write_all(data).await?;
+    ///     }
+    /// }
+    /// ```
     ///
-    /// - `code`: The close status code
-    /// - `reason`: Human-readable reason for closing
+    /// ## Errors
     ///
-    /// This does not close the underlying stream; you should drop the
-    /// `Connection` after calling this.
-    pub async fn close(&mut self, code: CloseCode, reason: &str) -> Result<()> {
-        if self.state != ConnectionState::Open {
-            return Ok(());
+    /// The same as [`recv`](Self::recv).
+    pub async fn recv_ref(&mut self) -> Result<Option<MessageRef<'_>>> {
+        self.recv_deadline = None;
+        let result = ws_instrument!(self.span, self.next_message_ref());
+        if let Err(e) = &result {
+            ws_instrument!(self.span, self.fail(e));
         }
+        Ok(match self.report(result)? {
+            None => None,
+            Some(Lent::Payload(OpCode::Text)) => {
+                // SAFETY: `next_message_ref` validated the payload as UTF-8
+                // and nothing has touched the read buffer since.
+                let text = unsafe { std::str::from_utf8_unchecked(self.codec.lent_payload()) };
+                Some(MessageRef::Text(text))
+            }
+            Some(Lent::Payload(_)) => Some(MessageRef::Binary(self.codec.lent_payload())),
+            Some(Lent::Message) => self.lent.as_ref().map(MessageRef::from),
+        })
+    }
 
-        if code.is_reserved() {
-            return Err(Error::InvalidCloseCode(code.as_u16()));
+    async fn next_message_ref(&mut self) -> Result<Option<Lent>> {
+        self.lent = None;
+        if self.peeked.is_some() || !self.interceptors.is_empty() {
+            self.lent = self.recv().await?;
+            return Ok(self.lent.as_ref().map(|_| Lent::Message));
+        }
+        if !self.state.can_receive() {
+            return Ok(None);
         }
 
-        self.state = ConnectionState::Closing;
-        let frame = Frame::close(Some(code.as_u16()), reason);
+        loop {
+            self.send_pending_pong().await?;
+            if let Some(message) = self.spilled().await? {
+                self.lent = Some(message);
+                return Ok(Some(Lent::Message));
+            }
+
+            let header = match self.buffer_frame().await {
+                Ok(header) => header,
+                Err(e) if self.peer_gone(&e) => {
+                    self.set_state(ConnectionState::Closed);
+                    return Ok(None);
+                }
+                Err(e) => return Err(e),
+            };
+
+            let borrowable = header.fin
+                && matches!(header.opcode, OpCode::Text | OpCode::Binary)
+                && !(header.rsv1 || header.rsv2 || header.rsv3)
+                && !self.assembler.is_assembling()
+                && !self.codec.config().spills(header.payload_len)
+                && !(header.opcode == OpCode::Text && self.codec.config().compat.lossy_utf8);
+            if borrowable {
+                self.codec
+                    .config()
+                    .limits
+                    .check_message_size(header.payload_len)?;
+                self.meter(&header)?;
+                let payload = self.codec.lend_payload(&header);
+                ws_trace!(
+                    conn = self.id,
+                    opcode = ?header.opcode,
+                    fin = header.fin,
+                    len = payload.len(),
+                    "received frame"
+                );
+                #[cfg(feature = "metrics")]
+                metrics::record_payload(Direction::Received, header.opcode, true, payload);
+                if header.opcode == OpCode::Text {
+                    validate_utf8(payload)?;
+                }
+                return Ok(Some(Lent::Payload(header.opcode)));
+            }
+
+            let frame = self.read_frame().await?;
+            if let Some(message) = self.handle_frame(frame).await? {
+                self.lent = Some(message);
+                return Ok(Some(Lent::Message));
+            }
+        }
+    }
+
+    /// Look at the next message without consuming it.
+    ///
+    /// The message is received as by [`recv`](Self::recv), so pings are
+    /// answered and close frames handled as usual, but it stays buffered and
+    /// is returned again by the next `recv`. Peeking twice returns the same
+    /// message.
+    ///
+    /// ```rust,ignore
+    /// // Route on the first message without taking it from the handler
+    /// match conn.peek().await? {
+    ///     Some(msg) if msg.as_text().is_some_and(|t| t.starts_with("admin:")) => admin(conn).await,
+    ///     Some(_) => user(conn).await,
+    ///     None => Ok(()),
+    /// }
+    /// ```
+    ///
+    /// ## Errors
+    ///
+    /// The same as [`recv`](Self::recv).
+    pub async fn peek(&mut self) -> Result<Option<&Message>> {
+        if self.peeked.is_none() {
+            self.peeked = self.recv().await?;
+        }
+        Ok(self.peeked.as_ref())
+    }
+
+    /// Fail the connection after a receive error (RFC 6455 Section 7.1.7).
+    ///
+    /// Errors caused by the peer are answered with the matching close frame
+    /// (see [`Error::close_code`]) before the connection is given up; the
+    /// closing handshake is not awaited.
+    async fn fail(&mut self, err: &Error) {
+        if let Some(frame) = self.failing(err) {
+            // The error being returned matters more than one from writing
+            let _ = self.write_frame(frame).await;
+            let _ = self.codec.flush().await;
+        }
+    }
+
+    /// Close the connection over `err`, returning the close frame to send if
+    /// it was open.
+    fn failing(&mut self, err: &Error) -> Option<Frame> {
+        if !err.is_fatal() {
+            return None;
+        }
+        let code = err.close_code()?;
+        let mut frame = None;
+        if self.state == ConnectionState::Open {
+            let reason = err.to_string();
+            let reason = truncate_close_reason(&reason);
+            let sent = CloseFrame::new(code, reason);
+            self.emit(ConnectionEvent::CloseInitiated(&sent));
+            self.sent_close = Some(sent);
+            ws_debug!(conn = self.id, code = code.as_u16(), "failing connection");
+            frame = Some(Frame::close(Some(code.as_u16()), reason));
+        }
+        self.set_state(ConnectionState::Closed);
+        frame
+    }
+
+    /// Wait until a whole frame is buffered, so that the next
+    /// [`recv`](Self::recv) has something to work on, without taking it.
+    ///
+    /// Errors are left for `recv` to report, as it hits them again.
+    pub(crate) async fn readable(&mut self) {
+        self.recv_deadline = None;
+        if self.peeked.is_none() && self.state.can_receive() {
+            let _ = ws_instrument!(self.span, self.buffer_frame());
+        }
+    }
+
+    /// Wait for the last frame of a message being spilled to be written,
+    /// returning the message if it was the final one.
+    async fn spilled(&mut self) -> Result<Option<Message>> {
+        let spilled = poll_fn(|cx| self.spill.poll(cx)).await?;
+        Ok(spilled.map(Message::Spilled))
+    }
+
+    async fn next_message(&mut self) -> Result<Option<Message>> {
+        loop {
+            self.send_pending_pong().await?;
+            if let Some(message) = self.spilled().await? {
+                return Ok(Some(message));
+            }
+
+            let frame = match self.read_frame().await {
+                Ok(f) => f,
+                Err(e) if self.peer_gone(&e) => {
+                    self.set_state(ConnectionState::Closed);
+                    return Ok(None);
+                }
+                Err(e) => return Err(e),
+            };
+
+            if let Some(message) = self.handle_frame(frame).await? {
+                return Ok(Some(message));
+            }
+        }
+    }
+
+    fn poll_next_message(&mut self, cx: &mut Context<'_>) -> Poll<Result<Option<Message>>> {
+        if let Some(pong_data) = self.pending_pong.take() {
+            self.queue_frame(Frame::pong(pong_data.to_vec()))?;
+        }
+        match self.codec.poll_flush(cx) {
+            Poll::Pending => return Poll::Pending,
+            // A failed close reply is ignored, as by `handle_frame`
+            Poll::Ready(Err(e)) if self.state.can_receive() => return Poll::Ready(Err(e)),
+            Poll::Ready(_) => {}
+        }
+        if let Some(message) = self.peeked.take() {
+            return Poll::Ready(Ok(Some(message)));
+        }
+        if !self.state.can_receive() {
+            return Poll::Ready(Ok(None));
+        }
+
+        loop {
+            if let Some(spilled) = ready!(self.spill.poll(cx))? {
+                return Poll::Ready(Ok(Some(Message::Spilled(spilled))));
+            }
+            let header = match ready!(self.codec.poll_buffer_frame(cx)) {
+                Err(e) if self.peer_gone(&e) => {
+                    self.set_state(ConnectionState::Closed);
+                    return Poll::Ready(Ok(None));
+                }
+                result => self.heard(result)?,
+            };
+            let Some(frame) = self.take_frame(&header)? else {
+                continue;
+            };
+            let (message, reply) = self.process_frame(frame)?;
+            if let Some(reply) = reply {
+                let _ = self.queue_frame(reply);
+                if self.codec.poll_flush(cx).is_pending() {
+                    // Handed out once the reply is written
+                    self.peeked = message;
+                    return Poll::Pending;
+                }
+            }
+            if message.is_some() {
+                return Poll::Ready(Ok(message));
+            }
+        }
+    }
+
+    fn poll_queue_message(&mut self, cx: &mut Context<'_>, message: &Message) -> Poll<Result<()>> {
+        ready!(self.codec.poll_write_pending(cx))?;
+        if !self.state.can_send() {
+            return Poll::Ready(Err(Error::ConnectionClosed(None)));
+        }
+
+        if message.is_control() {
+            if let Message::Close(Some(close)) = message {
+                close.code.validate()?;
+            }
+            let ping = self.check_ping(message)?;
+            self.queue_frame(Frame::try_from(message.clone())?)?;
+            self.pending_pings
+                .extend(ping.map(|payload| (payload, Instant::now())));
+        } else {
+            // Polled again for a spilled message still being read, which
+            // carries on where it left off
+            let resumed = match (message, &self.queue.current) {
+                (Message::Spilled(spilled), Some(outgoing)) => outgoing.is_reading(spilled),
+                _ => false,
+            };
+            if !resumed {
+                // Data messages never interleave, so finish one left by the queue
+                ready!(self.poll_queue_current(cx))?;
+                self.start_message(message.clone(), MessageOptions::default())?;
+            }
+            ready!(self.poll_queue_current(cx))?;
+        }
+
+        // Start writing; the rest goes out with later polls
+        if let Poll::Ready(Err(e)) = self.codec.poll_write_pending(cx) {
+            return Poll::Ready(Err(e));
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    /// Whether a read error means the peer went away without a Close frame:
+    /// EOF, or with `compat.abrupt_close` a reset or truncated stream.
+    fn peer_gone(&self, err: &Error) -> bool {
+        if matches!(err, Error::ConnectionClosed(_)) {
+            return true;
+        }
+        self.codec.config().compat.abrupt_close
+            && err.io_error().is_some_and(|err| {
+                matches!(
+                    err.kind(),
+                    io::ErrorKind::ConnectionReset
+                        | io::ErrorKind::ConnectionAborted
+                        | io::ErrorKind::BrokenPipe
+                        | io::ErrorKind::UnexpectedEof
+                )
+            })
+    }
+
+    async fn send_pending_pong(&mut self) -> Result<()> {
+        if let Some(pong_data) = self.pending_pong.take() {
+            let pong_frame = Frame::pong(pong_data.to_vec());
+            self.write_frame(pong_frame).await?;
+            self.codec.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// Process a received frame, returning a message once one is complete.
+    async fn handle_frame(&mut self, frame: Frame) -> Result<Option<Message>> {
+        let (message, reply) = self.process_frame(frame)?;
+        let Some(reply) = reply else {
+            return Ok(message);
+        };
+        // Kept by the connection until the reply is written, in case the
+        // caller stops waiting
+        self.peeked = message;
+        let _ = self.write_frame(reply).await;
+        let _ = self.codec.flush().await;
+        Ok(self.peeked.take())
+    }
+
+    /// The part of `handle_frame` that does not write: returns the message,
+    /// if complete, and the close frame to answer the peer's with.
+    fn process_frame(&mut self, frame: Frame) -> Result<(Option<Message>, Option<Frame>)> {
+        let message = match frame.opcode {
+            OpCode::Ping => {
+                frame.validate()?;
+                let payload = frame.into_payload_bytes();
+                self.emit(ConnectionEvent::PingReceived(&payload));
+                let policy = self.codec.config().pong_policy;
+                if policy.auto_pong() {
+                    self.pending_pong = Some(payload.clone());
+                }
+                policy.surfaces_pings().then_some(Message::Ping(payload))
+            }
+            OpCode::Pong => {
+                frame.validate()?;
+                let payload = frame.into_payload_bytes();
+                self.emit(ConnectionEvent::PongReceived(&payload));
+                self.acknowledge_pong(&payload);
+                Some(Message::Pong(payload))
+            }
+            OpCode::Close => {
+                frame.validate()?;
+                // An invalid close is answered with an error code, which is
+                // not what the peer sent
+                let (received, close_frame) = match self.parse_close_frame(&frame) {
+                    Ok(received) => (received.clone(), received),
+                    Err(answer) => (None, Some(answer)),
+                };
+                self.emit(ConnectionEvent::CloseReceived(close_frame.as_ref()));
+                self.received_close = received;
+
+                let mut reply = None;
+                if self.state == ConnectionState::Open {
+                    self.set_state(ConnectionState::Closing);
+                    self.sent_close.clone_from(&close_frame);
+                    reply = Some(if let Some(ref cf) = close_frame {
+                        Frame::close(Some(cf.code.as_u16()), &cf.reason)
+                    } else {
+                        Frame::close(None, "")
+                    });
+                }
+
+                self.set_state(ConnectionState::Closed);
+                return Ok((Some(Message::Close(close_frame)), reply));
+            }
+            OpCode::Text | OpCode::Binary | OpCode::Continuation => {
+                frame.validate()?;
+                match self.assembler.push_frame(frame)? {
+                    Pushed::Message(Some(assembled)) => Some(self.assembled_to_message(assembled)?),
+                    Pushed::Message(None) => None,
+                    // Handed out by the next `spilled` once written
+                    Pushed::Spill(chunk) => {
+                        self.spill.start(chunk);
+                        None
+                    }
+                }
+            }
+        };
+        Ok((message, None))
+    }
+
+    /// Send a ping frame.
+    ///
+    /// This is a convenience method that wraps `send(Message::Ping(...))`.
+    pub async fn ping(&mut self, data: impl Into<Bytes>) -> Result<()> {
+        self.send(Message::Ping(data.into())).await
+    }
+
+    /// Send a pong frame.
+    ///
+    /// This is a convenience method that wraps `send(Message::Pong(...))`.
+    pub async fn pong(&mut self, data: impl Into<Bytes>) -> Result<()> {
+        self.send(Message::Pong(data.into())).await
+    }
+
+    /// Initiate a close handshake.
+    ///
+    /// Sends a close frame with the given status code and reason, then waits
+    /// for the peer's close response.
+    ///
+    /// ## Arguments
+    ///
+    /// - `code`: The close status code
+    /// - `reason`: Human-readable reason for closing, truncated at a
+    ///   character boundary to
+    ///   [`MAX_CLOSE_REASON`](crate::protocol::frame::MAX_CLOSE_REASON) bytes
+    ///
+    /// This does not close the underlying stream; you should drop the
+    /// `Connection` after calling this.
+    pub async fn close(&mut self, code: CloseCode, reason: &str) -> Result<()> {
+        if self.state != ConnectionState::Open {
+            return Ok(());
+        }
+
+        code.validate()?;
+        let reason = truncate_close_reason(reason);
+
+        self.set_state(ConnectionState::Closing);
+        let sent = CloseFrame::new(code, reason);
+        self.emit(ConnectionEvent::CloseInitiated(&sent));
+        self.sent_close = Some(sent);
+
+        let frame = Frame::close(Some(code.as_u16()), reason);
+        let result = ws_instrument!(self.span, async {
+            self.write_frame(frame).await?;
+            self.codec.flush().await
+        });
+        self.report(result)
+    }
+
+    /// Close and wait up to `timeout` for the peer to answer with its own
+    /// close frame.
+    ///
+    /// Messages that arrive in the meantime are discarded. Whether or not
+    /// the peer answers, the connection is `Closed` when this returns.
+    /// Calling it after [`close`](Self::close) only waits.
+    ///
+    /// Returns `Ok(true)` if the peer acknowledged the close (or the
+    /// connection was already closed), `Ok(false)` if the timeout expired or
+    /// the peer disconnected without answering.
+    ///
+    /// ## Errors
+    ///
+    /// As for [`close`](Self::close), plus errors reading the peer's
+    /// response.
+    pub async fn close_with_timeout(
+        &mut self,
+        code: CloseCode,
+        reason: &str,
+        timeout: Duration,
+    ) -> Result<bool> {
+        if self.state == ConnectionState::Closed {
+            return Ok(true);
+        }
+        self.recv_deadline = None;
+        code.validate()?;
+        #[cfg(feature = "tracing")]
+        let span = self.span.clone();
+        if let Err(e) = self.close(code, reason).await {
+            self.set_state(ConnectionState::Closed);
+            return Err(e);
+        }
+
+        let wait = async {
+            loop {
+                match self.next_message().await? {
+                    Some(Message::Close(_)) => return Ok(true),
+                    Some(_) => {}
+                    None => return Ok(false),
+                }
+            }
+        };
+        let result = match ws_instrument!(span, tokio::time::timeout(timeout, wait)) {
+            Ok(result) => result,
+            Err(_) => {
+                ws_debug!(conn = self.id, "peer did not acknowledge close in time");
+                Ok(false)
+            }
+        };
+        self.set_state(ConnectionState::Closed);
+        self.report(result)
+    }
+
+    async fn write_message(
+        &mut self,
+        message: Message,
+        options: MessageOptions,
+        flush: bool,
+    ) -> Result<()> {
+        if !self.state.can_send() {
+            return Err(Error::ConnectionClosed(None));
+        }
+
+        // Control frames are never fragmented
+        if message.is_control() {
+            if let Message::Close(Some(close)) = &message {
+                close.code.validate()?;
+            }
+            let ping = self.check_ping(&message)?;
+            self.pending_pings
+                .extend(ping.map(|payload| (payload, Instant::now())));
+            let frame = Frame::try_from(message)?;
+            self.write_frame(frame).await?;
+            if flush {
+                self.codec.flush_due().await?;
+            }
+            return Ok(());
+        }
+
+        // Data messages never interleave, so finish one left by the queue
+        self.finish_current().await?;
+        self.start_message(message, options)?;
+        self.write_current().await?;
+
+        if flush {
+            self.codec.flush_due().await?;
+        }
+        Ok(())
+    }
+
+    /// Make `message` the data message in progress.
+    fn start_message(&mut self, message: Message, options: MessageOptions) -> Result<()> {
+        if !self.state.can_send() {
+            return Err(Error::ConnectionClosed(None));
+        }
+        self.codec
+            .config()
+            .limits
+            .check_message_size(message.len())?;
+
+        self.queue.current = Some(Outgoing::new(message, options));
+        Ok(())
+    }
+
+    /// Write the next fragment of the data message in progress, if any.
+    async fn write_fragment(&mut self) -> Result<bool> {
+        if self.queue.current.is_none() {
+            return Ok(false);
+        }
+        if !self.state.can_send() {
+            self.queue.current = None;
+            return Err(Error::ConnectionClosed(None));
+        }
+
+        // Left in the queue while a spilled fragment is read, in case the
+        // caller stops waiting
+        let (current, codec) = (&mut self.queue.current, &mut self.codec);
+        let next = poll_fn(move |cx| match current.as_mut() {
+            Some(outgoing) => outgoing.poll_next_frame(cx, codec.config()),
+            None => Poll::Ready(Ok(None)),
+        })
+        .await?;
+        let (Some(outgoing), Some((mut frame, first))) = (self.queue.current.take(), next) else {
+            return Ok(false);
+        };
+        if first {
+            // RFC 7692: Extension encoding only on first frame
+            self.encode_extensions(&mut frame, outgoing.options())?;
+        }
+        if !outgoing.is_done() {
+            self.queue.current = Some(outgoing);
+        }
+        self.write_frame(frame).await?;
+        Ok(true)
+    }
+
+    /// Encode every remaining fragment of the data message in progress for
+    /// `poll_*` to write. `Pending` while a spilled fragment is read.
+    fn poll_queue_current(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        if self.queue.current.is_some() && !self.state.can_send() {
+            self.queue.current = None;
+            return Poll::Ready(Err(Error::ConnectionClosed(None)));
+        }
+        while let Some(outgoing) = self.queue.current.as_mut() {
+            let next = ready!(outgoing.poll_next_frame(cx, self.codec.config()))?;
+            let options = outgoing.options();
+            let Some((mut frame, first)) = next else {
+                self.queue.current = None;
+                break;
+            };
+            if outgoing.is_done() {
+                self.queue.current = None;
+            }
+            if first {
+                // RFC 7692: Extension encoding only on first frame
+                self.encode_extensions(&mut frame, options)?;
+            }
+            self.queue_frame(frame)?;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    /// Write every fragment of the data message just started together. A
+    /// message streamed from disk is written a fragment at a time instead,
+    /// so that it is never read into memory whole.
+    async fn write_current(&mut self) -> Result<()> {
+        let Some(mut outgoing) = self.queue.current.take() else {
+            return Ok(());
+        };
+        if !outgoing.is_in_memory() {
+            self.queue.current = Some(outgoing);
+            return self.finish_current().await;
+        }
+
+        let mut frames = Vec::new();
+        loop {
+            let (current, codec) = (&mut outgoing, &mut self.codec);
+            let Some((mut frame, first)) =
+                poll_fn(move |cx| current.poll_next_frame(cx, codec.config())).await?
+            else {
+                break;
+            };
+            if first {
+                // RFC 7692: Extension encoding only on first frame
+                self.encode_extensions(&mut frame, outgoing.options())?;
+            }
+            if run_chain(&mut self.interceptors, &mut frame, true) == FrameAction::Forward {
+                ws_trace!(
+                    conn = self.id,
+                    opcode = ?frame.opcode,
+                    fin = frame.fin,
+                    len = frame.payload().len(),
+                    "sending frame"
+                );
+                frames.push(frame);
+            }
+        }
+
+        #[cfg(feature = "metrics")]
+        for frame in &frames {
+            metrics::record_frame(Direction::Sent, frame);
+        }
+        self.codec.send_frames(frames).await?;
+        self.last_sent = tokio::time::Instant::now();
+        Ok(())
+    }
+
+    async fn finish_current(&mut self) -> Result<()> {
+        while self.write_fragment().await? {}
+        Ok(())
+    }
+
+    async fn write_next(&mut self) -> Result<bool> {
+        // The peer may have stalled since the last enqueue
+        self.check_backlog();
+        if let Some(code) = self.slow_close.take()
+            && let Some(slow) = self.codec.config().slow_consumer
+        {
+            // A peer that stopped reading may never take the close either
+            let closed = tokio::time::timeout(slow.grace, self.close(code, "slow consumer")).await;
+            if !matches!(closed, Ok(Ok(()))) {
+                self.abort();
+            }
+            return Err(Error::SlowConsumer {
+                max_bytes: slow.max_queued_bytes,
+                grace: slow.grace,
+            });
+        }
+        loop {
+            let more = self.write_queued().await?;
+            let blocking = self
+                .codec
+                .config()
+                .slow_consumer
+                .is_some_and(|slow| slow.policy == SlowConsumerPolicy::Block);
+            if !(more && blocking && self.backlogged()) {
+                return Ok(more);
+            }
+        }
+    }
+
+    async fn write_queued(&mut self) -> Result<bool> {
+        match self.queue.pop() {
+            Some(Next::Control(message)) => {
+                self.write_message(message, MessageOptions::default(), false)
+                    .await?;
+                Ok(true)
+            }
+            Some(Next::Data(message, options)) => {
+                self.start_message(message, options)?;
+                self.write_fragment().await
+            }
+            None => self.write_fragment().await,
+        }
+    }
+
+    async fn write_prepared(&mut self, prepared: &PreparedMessage) -> Result<()> {
+        let frame = prepared.frame();
+        let shareable = !self.codec.masks_outgoing()
+            && self.extensions.negotiated_count() == 0
+            && self.interceptors.is_empty()
+            && frame.opcode.is_data()
+            && self
+                .codec
+                .config()
+                .fragment_len(frame.opcode, frame.payload().len(), 0)
+                >= frame.payload().len();
+        if !shareable {
+            return self
+                .write_message(prepared.message().clone(), MessageOptions::default(), true)
+                .await;
+        }
+
+        if !self.state.can_send() {
+            return Err(Error::ConnectionClosed(None));
+        }
+        self.finish_current().await?;
+        let limits = &self.codec.config().limits;
+        limits.check_message_size(frame.payload().len())?;
+        limits.check_frame_size(frame.payload().len())?;
+
+        ws_trace!(
+            conn = self.id,
+            opcode = ?frame.opcode,
+            len = frame.payload().len(),
+            "sending prepared frame"
+        );
+        self.codec.write_raw(prepared.wire_bytes()).await?;
+        #[cfg(feature = "metrics")]
+        metrics::record_frame(Direction::Sent, frame);
+        self.codec.flush_due().await
+    }
+
+    fn encode_extensions(&mut self, frame: &mut Frame, options: MessageOptions) -> Result<()> {
+        if options.compress {
+            self.extensions.encode(frame)
+        } else {
+            self.extensions.encode_uncompressed(frame)
+        }
+    }
+
+    async fn write_frame(&mut self, mut frame: Frame) -> Result<()> {
+        if !self.intercept_outgoing(&mut frame) {
+            return Ok(());
+        }
         self.codec.write_frame(&frame).await?;
-        self.codec.flush().await?;
+        self.frame_sent(&frame);
         Ok(())
     }
 
-    fn parse_close_frame(&self, frame: &Frame) -> Option<CloseFrame> {
+    /// Encode a frame for `poll_*` to write, as `write_frame` writes it.
+    fn queue_frame(&mut self, mut frame: Frame) -> Result<()> {
+        if !self.intercept_outgoing(&mut frame) {
+            return Ok(());
+        }
+        self.codec.encode_frame(&frame)?;
+        self.frame_sent(&frame);
+        Ok(())
+    }
+
+    /// Run the interceptors over an outgoing frame; `false` if one dropped
+    /// it.
+    fn intercept_outgoing(&mut self, frame: &mut Frame) -> bool {
+        if run_chain(&mut self.interceptors, frame, true) == FrameAction::Drop {
+            return false;
+        }
+        ws_trace!(
+            conn = self.id,
+            opcode = ?frame.opcode,
+            fin = frame.fin,
+            len = frame.payload().len(),
+            "sending frame"
+        );
+        true
+    }
+
+    /// Account for a frame just written.
+    fn frame_sent(&mut self, frame: &Frame) {
+        self.last_sent = tokio::time::Instant::now();
+        #[cfg(feature = "metrics")]
+        metrics::record_frame(Direction::Sent, frame);
+
+        match frame.opcode {
+            OpCode::Ping => self.emit(ConnectionEvent::PingSent(frame.payload())),
+            OpCode::Pong => self.emit(ConnectionEvent::PongSent(frame.payload())),
+            _ => {}
+        }
+    }
+
+    async fn read_frame(&mut self) -> Result<Frame> {
+        loop {
+            let header = self.buffer_frame().await?;
+            if let Some(frame) = self.take_frame(&header)? {
+                return Ok(frame);
+            }
+        }
+    }
+
+    /// Take the buffered frame with `header`, or `None` if an interceptor
+    /// dropped it.
+    fn take_frame(&mut self, header: &FrameHeader) -> Result<Option<Frame>> {
+        self.meter(header)?;
+        let mut frame = self.codec.take_frame(header)?;
+        ws_trace!(
+            conn = self.id,
+            opcode = ?frame.opcode,
+            fin = frame.fin,
+            len = frame.payload().len(),
+            "received frame"
+        );
+        #[cfg(feature = "metrics")]
+        metrics::record_frame(Direction::Received, &frame);
+
+        let forward = run_chain(&mut self.interceptors, &mut frame, false) == FrameAction::Forward;
+        Ok(forward.then_some(frame))
+    }
+
+    /// Wait until a whole frame is buffered, sending a heartbeat pong
+    /// whenever `pong_heartbeat` passes without outgoing traffic, a
+    /// `keepalive` ping once the peer has been quiet, and flushing frames
+    /// held back by `write_coalescing` once they are due.
+    ///
+    /// A peer that leaves the keepalive ping unanswered is aborted with
+    /// `Error::KeepaliveTimeout`.
+    async fn buffer_frame(&mut self) -> Result<FrameHeader> {
+        loop {
+            let heartbeat = self
+                .codec
+                .config()
+                .pong_heartbeat
+                .map(|interval| self.last_sent + interval);
+            let keepalive = self.codec.config().keepalive;
+            let quiet = keepalive.map(|keepalive| match self.keepalive_ping {
+                Some(at) => at + keepalive.timeout,
+                None => self.last_heard + keepalive.interval,
+            });
+            let Some(deadline) = heartbeat
+                .into_iter()
+                .chain(quiet)
+                .chain(self.recv_deadline.map(|(at, _)| at))
+                .chain(self.codec.flush_deadline())
+                .min()
+            else {
+                let result = self.codec.buffer_frame().await;
+                return self.heard(result);
+            };
+            // Reading is cancel safe: bytes read so far stay buffered
+            if let Ok(result) = tokio::time::timeout_at(deadline, self.codec.buffer_frame()).await {
+                return self.heard(result);
+            }
+
+            let now = tokio::time::Instant::now();
+            if let Some((at, timeout)) = self.recv_deadline
+                && at <= now
+            {
+                return Err(Error::RecvTimeout(timeout));
+            }
+            if let Some(keepalive) = keepalive
+                && quiet.is_some_and(|due| due <= now)
+            {
+                if self.keepalive_ping.is_some() {
+                    ws_debug!(conn = self.id, "peer did not answer keepalive ping");
+                    self.abort();
+                    return Err(Error::KeepaliveTimeout(keepalive.timeout));
+                }
+                self.keepalive_ping = Some(now);
+                if self.state == ConnectionState::Open {
+                    match self.ping(Bytes::new()).await {
+                        // A full ping queue means pings are already unanswered
+                        Ok(()) | Err(Error::TooManyPendingPings { .. }) => {}
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+            if self.codec.flush_deadline().is_some_and(|due| due <= now) {
+                self.codec.flush().await?;
+            }
+            if heartbeat.is_some_and(|due| due <= now) {
+                if self.state == ConnectionState::Open {
+                    self.write_frame(Frame::pong(Vec::new())).await?;
+                    self.codec.flush().await?;
+                } else {
+                    self.last_sent = now;
+                }
+            }
+        }
+    }
+
+    /// Count a received frame against `inbound_rate`.
+    fn meter(&mut self, header: &FrameHeader) -> Result<()> {
+        match &mut self.inbound {
+            Some(meter) => meter.record(header.fin, header.payload_len),
+            None => Ok(()),
+        }
+    }
+
+    /// Note a successfully buffered frame as a sign of life for `keepalive`.
+    fn heard(&mut self, result: Result<FrameHeader>) -> Result<FrameHeader> {
+        if result.is_ok() {
+            self.last_heard = tokio::time::Instant::now();
+            self.keepalive_ping = None;
+        }
+        result
+    }
+
+    /// For an outgoing ping, check the unanswered-ping limit and return the
+    /// payload to track once the frame is written.
+    fn check_ping(&self, message: &Message) -> Result<Option<Bytes>> {
+        let Message::Ping(payload) = message else {
+            return Ok(None);
+        };
+
+        let max = self.codec.config().max_pending_pings;
+        if self.pending_pings.len() >= max {
+            return Err(Error::TooManyPendingPings { max });
+        }
+        Ok(Some(payload.clone()))
+    }
+
+    /// Match a pong against outstanding pings.
+    ///
+    /// A peer may answer only the most recent of several pings (RFC 6455
+    /// Section 5.5.3), so a match also settles every older ping. Unsolicited
+    /// pongs leave the queue untouched.
+    fn acknowledge_pong(&mut self, payload: &[u8]) {
+        let Some(pos) = self.pending_pings.iter().position(|(p, _)| p == payload) else {
+            return;
+        };
+
+        let (_, sent_at) = self.pending_pings[pos];
+        self.pending_pings.drain(..=pos);
+
+        let sample = sent_at.elapsed();
+        #[cfg(feature = "metrics")]
+        metrics::record_ping_rtt(sample);
+        match &mut self.latency {
+            Some(latency) => latency.update(sample),
+            None => self.latency = Some(Latency::new(sample)),
+        }
+    }
+
+    /// The peer's close frame, or the close frame to answer it with if it
+    /// is invalid.
+    fn parse_close_frame(
+        &self,
+        frame: &Frame,
+    ) -> std::result::Result<Option<CloseFrame>, CloseFrame> {
         let payload = frame.payload();
         if payload.len() >= 2 {
             let code = u16::from_be_bytes([payload[0], payload[1]]);
+            // Codes that may not appear on the wire (1005/1006/1015, unassigned
+            // ranges) are answered with a protocol error instead of echoed.
+            let Ok(code) = CloseCode::from_u16(code).validate() else {
+                return Err(CloseFrame::new(
+                    CloseCode::ProtocolError,
+                    "Invalid close code",
+                ));
+            };
             match std::str::from_utf8(&payload[2..]) {
-                Ok(reason) => Some(CloseFrame::new(
-                    CloseCode::from_u16(code),
-                    reason.to_owned(),
-                )),
-                Err(_) => Some(CloseFrame::new(CloseCode::InvalidPayload, "")),
+                Ok(reason) => Ok(Some(CloseFrame::new(code, reason.to_owned()))),
+                Err(_) => Err(CloseFrame::new(CloseCode::InvalidPayload, "")),
             }
         } else if payload.is_empty() {
-            None
+            Ok(None)
         } else {
-            Some(CloseFrame::new(
+            Err(CloseFrame::new(
                 CloseCode::ProtocolError,
                 "Invalid close frame",
             ))
@@ -370,6 +1898,9 @@ impl<T: AsyncRead + AsyncWrite + Unpin> Connection<T> {
     }
 
     fn assembled_to_message(&mut self, assembled: AssembledMessage) -> Result<Message> {
+        if let Some(spilled) = assembled.spilled {
+            return Ok(Message::Spilled(spilled));
+        }
         let payload = if assembled.rsv1 && self.extensions.negotiated_count() > 0 {
             let mut frame = Frame::new_from_bytes(true, assembled.opcode, assembled.payload);
             frame.rsv1 = true;
@@ -381,7 +1912,14 @@ impl<T: AsyncRead + AsyncWrite + Unpin> Connection<T> {
 
         match assembled.opcode {
             OpCode::Text => {
-                let text = String::from_utf8(payload.to_vec()).map_err(|_| Error::InvalidUtf8)?;
+                let text = match String::from_utf8(payload.to_vec()) {
+                    Ok(text) => text,
+                    Err(e) if self.codec.config().compat.lossy_utf8 => {
+                        self.emit(ConnectionEvent::InvalidUtf8Replaced);
+                        String::from_utf8_lossy(e.as_bytes()).into_owned()
+                    }
+                    Err(_) => return Err(Error::InvalidUtf8),
+                };
                 Ok(Message::Text(text))
             }
             OpCode::Binary => Ok(Message::Binary(payload)),
@@ -390,69 +1928,18 @@ impl<T: AsyncRead + AsyncWrite + Unpin> Connection<T> {
     }
 }
 
+/// Where the message returned by `recv_ref` lives.
+enum Lent {
+    /// A single-frame data message in the codec's read buffer.
+    Payload(OpCode),
+    /// A message in `Connection::lent`.
+    Message,
+}
+
 #[cfg(test)]
 mod tests {
-    use super::*;
-    use std::io::Cursor;
-    use std::pin::Pin;
-    use std::task::{Context, Poll};
-    use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
-
-    struct MockStream {
-        read_data: Cursor<Vec<u8>>,
-        write_data: Vec<u8>,
-    }
-
-    impl MockStream {
-        fn new(data: Vec<u8>) -> Self {
-            Self {
-                read_data: Cursor::new(data),
-                write_data: Vec::new(),
-            }
-        }
-
-        fn written(&self) -> &[u8] {
-            &self.write_data
-        }
-    }
-
-    impl AsyncRead for MockStream {
-        fn poll_read(
-            mut self: Pin<&mut Self>,
-            _cx: &mut Context<'_>,
-            buf: &mut ReadBuf<'_>,
-        ) -> Poll<std::io::Result<()>> {
-            let pos = self.read_data.position() as usize;
-            let data = self.read_data.get_ref();
-            if pos >= data.len() {
-                return Poll::Ready(Ok(()));
-            }
-            let remaining = &data[pos..];
-            let to_copy = std::cmp::min(remaining.len(), buf.remaining());
-            buf.put_slice(&remaining[..to_copy]);
-            self.read_data.set_position((pos + to_copy) as u64);
-            Poll::Ready(Ok(()))
-        }
-    }
-
-    impl AsyncWrite for MockStream {
-        fn poll_write(
-            mut self: Pin<&mut Self>,
-            _cx: &mut Context<'_>,
-            buf: &[u8],
-        ) -> Poll<std::io::Result<usize>> {
-            self.write_data.extend_from_slice(buf);
-            Poll::Ready(Ok(buf.len()))
-        }
-
-        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
-            Poll::Ready(Ok(()))
-        }
-
-        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
-            Poll::Ready(Ok(()))
-        }
-    }
+    use super::*;
+    use crate::testing::MockStream;
 
     #[test]
     fn test_connection_new() {
@@ -462,6 +1949,13 @@ mod tests {
         assert!(conn.is_open());
     }
 
+    #[test]
+    fn test_connection_ids_are_unique() {
+        let a = Connection::new(MockStream::new(vec![]), Role::Client, Config::client());
+        let b = Connection::new(MockStream::new(vec![]), Role::Server, Config::server());
+        assert_ne!(a.id(), b.id());
+    }
+
     #[tokio::test]
     async fn test_send_text_message() {
         let stream = MockStream::new(vec![]);
@@ -514,6 +2008,27 @@ mod tests {
         assert!(conn.pending_pong.is_some());
     }
 
+    #[tokio::test]
+    async fn test_pong_policy() {
+        use crate::config::PongPolicy;
+
+        // Masked ping "p" followed by masked text "t"
+        let data = vec![
+            0x89, 0x81, 0x00, 0x00, 0x00, 0x00, b'p', 0x81, 0x81, 0x00, 0x00, 0x00, 0x00, b't',
+        ];
+
+        let config = Config::server().with_pong_policy(PongPolicy::Manual);
+        let mut conn = Connection::new(MockStream::new(data.clone()), Role::Server, config);
+        assert_eq!(conn.recv().await.unwrap(), Some(Message::ping("p")));
+        assert_eq!(conn.recv().await.unwrap(), Some(Message::text("t")));
+        assert!(conn.codec.into_inner().written().is_empty());
+
+        let config = Config::server().with_pong_policy(PongPolicy::Suppress);
+        let mut conn = Connection::new(MockStream::new(data), Role::Server, config);
+        assert_eq!(conn.recv().await.unwrap(), Some(Message::text("t")));
+        assert_eq!(conn.codec.into_inner().written(), [0x8A, 0x01, b'p']);
+    }
+
     #[tokio::test]
     async fn test_close_handshake() {
         // Masked close with code 1000: mask [0x00, 0x00, 0x00, 0x00], payload [0x03, 0xe8]
@@ -533,6 +2048,839 @@ mod tests {
         assert_eq!(conn.state(), ConnectionState::Closed);
     }
 
+    #[tokio::test]
+    async fn test_close_with_timeout_acknowledged() {
+        // Masked text "hi", then masked close 1000, both with a zero mask
+        let data = vec![
+            0x81, 0x82, 0, 0, 0, 0, b'h', b'i', 0x88, 0x82, 0, 0, 0, 0, 0x03, 0xe8,
+        ];
+        let mut conn = Connection::new(MockStream::new(data), Role::Server, Config::server());
+
+        let acked = conn
+            .close_with_timeout(CloseCode::Normal, "bye", Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert!(acked);
+        assert_eq!(conn.state(), ConnectionState::Closed);
+        assert!(
+            conn.close_with_timeout(CloseCode::Normal, "", Duration::ZERO)
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_close_with_timeout_expires() {
+        let (client, _server) = tokio::io::duplex(1024);
+        let mut conn = Connection::new(client, Role::Client, Config::client());
+
+        assert!(matches!(
+            conn.close_with_timeout(CloseCode::from_u16(999), "", Duration::from_secs(1))
+                .await,
+            Err(Error::InvalidCloseCode(999))
+        ));
+        assert!(conn.is_open());
+
+        let acked = conn
+            .close_with_timeout(CloseCode::GoingAway, "", Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert!(!acked);
+        assert_eq!(conn.state(), ConnectionState::Closed);
+    }
+
+    #[tokio::test]
+    async fn test_abort_writes_nothing() {
+        use crate::config::WriteCoalescing;
+
+        let config = Config::server().with_write_coalescing(WriteCoalescing::default());
+        let mut conn = Connection::new(MockStream::new(vec![]), Role::Server, config);
+        conn.send(Message::text("held")).await.unwrap();
+        conn.enqueue(Message::text("queued"), Priority::Normal);
+
+        conn.abort();
+        assert_eq!(conn.state(), ConnectionState::Closed);
+        assert_eq!(conn.queued(), 0);
+        assert!(matches!(
+            conn.send(Message::text("late")).await,
+            Err(Error::ConnectionClosed(None))
+        ));
+        assert_eq!(conn.recv().await.unwrap(), None);
+        assert!(conn.close(CloseCode::Normal, "").await.is_ok());
+        assert!(conn.codec.into_inner().written().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_abort_shuts_down_transport() {
+        use tokio::io::AsyncReadExt;
+
+        let (ours, mut peer) = tokio::io::duplex(64);
+        let mut conn = Connection::new(ours, Role::Server, Config::server());
+        conn.abort();
+
+        // The peer sees end of stream while the connection is still alive
+        let mut buf = [0u8; 8];
+        assert_eq!(peer.read(&mut buf).await.unwrap(), 0);
+        drop(conn);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_send_timeout() {
+        use std::sync::Arc;
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use tokio::io::AsyncReadExt;
+
+        let aborted = Arc::new(AtomicBool::new(false));
+        let config = Config::server().with_observer({
+            let aborted = aborted.clone();
+            move |_, event: &ConnectionEvent<'_>| {
+                if matches!(event, ConnectionEvent::Aborted) {
+                    aborted.store(true, Ordering::Relaxed);
+                }
+            }
+        });
+        // The peer does not read, so the second message cannot be written
+        let (mut peer, stream) = tokio::io::duplex(64);
+        let mut conn = Connection::new(stream, Role::Server, config);
+        let timeout = Duration::from_secs(1);
+
+        conn.send_timeout(Message::text("fits"), timeout)
+            .await
+            .unwrap();
+        let result = conn
+            .send_timeout(Message::binary(vec![0u8; 1024]), timeout)
+            .await;
+        assert_eq!(result, Err(Error::WriteTimeout(timeout)));
+        assert_eq!(conn.state(), ConnectionState::Closed);
+        assert!(aborted.load(Ordering::Relaxed));
+        assert!(matches!(
+            conn.send(Message::text("late")).await,
+            Err(Error::ConnectionClosed(None))
+        ));
+
+        // Torn down as by `abort`: what got through, then end of stream
+        let mut received = Vec::new();
+        peer.read_to_end(&mut received).await.unwrap();
+        assert_eq!(&received[..6], &[0x81, 0x04, b'f', b'i', b't', b's']);
+        drop(conn);
+    }
+
+    #[tokio::test]
+    async fn test_close_truncates_long_reason() {
+        let stream = MockStream::new(vec![]);
+        let mut conn = Connection::new(stream, Role::Server, Config::server());
+        conn.close(CloseCode::Normal, &"x".repeat(300))
+            .await
+            .unwrap();
+
+        let written = conn.codec.into_inner().written().to_vec();
+        assert_eq!(
+            written[1] as usize,
+            2 + crate::protocol::frame::MAX_CLOSE_REASON
+        );
+        assert!(Frame::parse(&written).unwrap().0.validate().is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_pong_heartbeat() {
+        use std::time::Duration;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let (mut peer, stream) = tokio::io::duplex(64);
+        let config = Config::server().with_pong_heartbeat(Duration::from_secs(10));
+        let mut conn = Connection::new(stream, Role::Server, config);
+        let start = tokio::time::Instant::now();
+        let recv = tokio::spawn(async move { conn.recv().await });
+
+        // Nothing is sent by either side, so empty pongs go out every 10s
+        let mut pong = [0u8; 2];
+        for n in 1..=2 {
+            peer.read_exact(&mut pong).await.unwrap();
+            assert_eq!(pong, [0x8A, 0x00]);
+            assert_eq!(start.elapsed(), Duration::from_secs(10 * n));
+        }
+
+        // recv still returns the next message
+        peer.write_all(&[0x81, 0x81, 0, 0, 0, 0, b'x'])
+            .await
+            .unwrap();
+        let msg = recv.await.unwrap().unwrap();
+        assert_eq!(msg, Some(Message::text("x")));
+    }
+
+    #[tokio::test]
+    async fn test_inbound_rate_fails_flooding_peer() {
+        use crate::config::InboundRate;
+
+        let mut input = Vec::new();
+        for _ in 0..3 {
+            input.extend([0x81, 0x81, 0, 0, 0, 0, b'x']);
+        }
+        let config = Config::server().with_inbound_rate(InboundRate::per_second(2, 1024));
+        let mut conn = Connection::new(MockStream::new(input), Role::Server, config);
+
+        assert!(conn.recv().await.unwrap().is_some());
+        assert!(conn.recv().await.unwrap().is_some());
+        let err = conn.recv().await.unwrap_err();
+        assert!(matches!(err, Error::InboundRateExceeded { max: 2, .. }));
+        assert_eq!(err.close_code(), Some(CloseCode::PolicyViolation));
+
+        let written = conn.codec.into_inner().written().to_vec();
+        let (close, _) = Frame::parse(&written).unwrap();
+        assert_eq!(close.opcode, OpCode::Close);
+        assert_eq!(close.payload()[..2], 1008u16.to_be_bytes());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_recv_sends_keepalive_pings() {
+        use crate::config::Keepalive;
+        use std::time::Duration;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let (mut peer, stream) = tokio::io::duplex(64);
+        let keepalive = Keepalive::new(Duration::from_secs(30), Duration::from_secs(10));
+        let config = Config::server().with_keepalive(keepalive);
+        let mut conn = Connection::new(stream, Role::Server, config);
+        let start = tokio::time::Instant::now();
+        let recv = tokio::spawn(async move {
+            let pong = conn.recv().await;
+            (pong, conn.recv().await, conn.recv().await)
+        });
+
+        // A ping goes out after 30s of quiet while recv waits
+        let mut ping = [0u8; 2];
+        peer.read_exact(&mut ping).await.unwrap();
+        assert_eq!(ping, [0x89, 0x00]);
+        assert_eq!(start.elapsed(), Duration::from_secs(30));
+        peer.write_all(&[0x8A, 0x80, 0, 0, 0, 0]).await.unwrap();
+
+        // The next goes unanswered and the peer is given up 10s later
+        peer.read_exact(&mut ping).await.unwrap();
+        let (pong, timeout, after) = recv.await.unwrap();
+        assert_eq!(pong.unwrap(), Some(Message::Pong(Bytes::new())));
+        assert_eq!(
+            timeout.unwrap_err(),
+            Error::KeepaliveTimeout(Duration::from_secs(10))
+        );
+        assert_eq!(start.elapsed(), Duration::from_secs(70));
+        assert_eq!(after.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_poll_send_and_recv() {
+        use std::future::poll_fn;
+
+        let (ours, theirs) = tokio::io::duplex(64);
+        let mut conn = Connection::new(ours, Role::Client, Config::client());
+        let mut peer = Connection::new(theirs, Role::Server, Config::server());
+
+        // Larger than the pipe, so flushing waits for the peer
+        let large = Message::binary(vec![7; 10_000]);
+        let reader = tokio::spawn(async move {
+            assert_eq!(
+                peer.recv().await.unwrap(),
+                Some(Message::binary(vec![7; 10_000]))
+            );
+            peer.send(Message::ping(b"p".to_vec())).await.unwrap();
+            peer.send(Message::text("reply")).await.unwrap();
+            peer.close(CloseCode::Normal, "").await.unwrap();
+            (peer.recv().await.unwrap(), peer.recv().await.unwrap())
+        });
+        poll_fn(|cx| conn.poll_send(cx, &large)).await.unwrap();
+        poll_fn(|cx| conn.poll_flush(cx)).await.unwrap();
+
+        let mut received = Vec::new();
+        while let Some(message) = poll_fn(|cx| conn.poll_recv(cx)).await.unwrap() {
+            received.push(message);
+        }
+        assert_eq!(
+            received,
+            [
+                Message::ping(b"p".to_vec()),
+                Message::text("reply"),
+                Message::close(CloseCode::Normal, "")
+            ]
+        );
+        assert_eq!(conn.state(), ConnectionState::Closed);
+
+        // The ping was answered and the close echoed
+        let (pong, close) = reader.await.unwrap();
+        assert_eq!(pong, Some(Message::pong(b"p".to_vec())));
+        assert_eq!(close, Some(Message::close(CloseCode::Normal, "")));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_recv_timeout_keeps_partial_message() {
+        use crate::testing::client_frame;
+        use std::time::Duration;
+        use tokio::io::AsyncWriteExt;
+
+        let (mut peer, stream) = tokio::io::duplex(64);
+        let mut conn = Connection::new(stream, Role::Server, Config::server());
+        let first = Frame::new(false, OpCode::Text, b"hel".to_vec());
+        peer.write_all(&client_frame(&first)).await.unwrap();
+
+        let timeout = Duration::from_secs(1);
+        assert_eq!(
+            conn.recv_timeout(timeout).await,
+            Err(Error::RecvTimeout(timeout))
+        );
+        assert_eq!(conn.state(), ConnectionState::Open);
+
+        let last = Frame::new(true, OpCode::Continuation, b"lo".to_vec());
+        peer.write_all(&client_frame(&last)).await.unwrap();
+        assert_eq!(
+            conn.recv_timeout(timeout).await.unwrap(),
+            Some(Message::text("hello"))
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cancelled_send_finishes_on_next_write() {
+        use std::time::Duration;
+
+        let (ours, theirs) = tokio::io::duplex(64);
+        let mut conn = Connection::new(ours, Role::Server, Config::server());
+        let mut peer = Connection::new(theirs, Role::Client, Config::client());
+
+        // Several fragments, stalled on the full pipe and given up on
+        let large = Message::binary(vec![7; 40_000]);
+        let send = tokio::time::timeout(Duration::from_secs(1), conn.send(large.clone()));
+        assert!(send.await.is_err());
+
+        let reader = tokio::spawn(async move {
+            let first = peer.recv().await.unwrap();
+            (first, peer.recv().await.unwrap())
+        });
+        conn.send(Message::text("after")).await.unwrap();
+        let (first, second) = reader.await.unwrap();
+        assert_eq!(first, Some(large));
+        assert_eq!(second, Some(Message::text("after")));
+    }
+
+    #[tokio::test]
+    async fn test_abrupt_close_compat() {
+        use crate::config::CompatMode;
+        use std::time::Duration;
+        use tokio::net::{TcpListener, TcpStream};
+
+        for lenient in [false, true] {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let peer = TcpStream::connect(listener.local_addr().unwrap())
+                .await
+                .unwrap();
+            let (stream, _) = listener.accept().await.unwrap();
+            // Linger 0 makes dropping the socket send a reset
+            socket2::SockRef::from(&peer)
+                .set_linger(Some(Duration::ZERO))
+                .unwrap();
+            drop(peer);
+
+            let config =
+                Config::server().with_compat(CompatMode::default().with_abrupt_close(lenient));
+            let mut conn = Connection::new(stream, Role::Server, config);
+            let result = conn.recv().await;
+            if lenient {
+                assert!(matches!(result, Ok(None)));
+                assert_eq!(conn.state(), ConnectionState::Closed);
+            } else {
+                assert!(matches!(result, Err(Error::Io(_))));
+            }
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_write_coalescing_flushes_while_receiving() {
+        use crate::config::WriteCoalescing;
+        use std::time::Duration;
+        use tokio::io::AsyncReadExt;
+
+        let (mut peer, stream) = tokio::io::duplex(1024);
+        let config = Config::server()
+            .with_write_coalescing(WriteCoalescing::new(1024, Duration::from_millis(5)));
+        let mut conn = Connection::new(stream, Role::Server, config);
+        let start = tokio::time::Instant::now();
+
+        for _ in 0..3 {
+            conn.send(Message::text("tick")).await.unwrap();
+        }
+        let recv = tokio::spawn(async move { conn.recv().await });
+
+        // All three ticks arrive together once the delay runs out
+        let mut ticks = [0u8; 18];
+        peer.read_exact(&mut ticks).await.unwrap();
+        assert_eq!(start.elapsed(), Duration::from_millis(5));
+        assert_eq!(&ticks[..6], b"\x81\x04tick");
+        recv.abort();
+    }
+
+    #[tokio::test]
+    async fn test_observer_receives_events() {
+        use std::sync::{Arc, Mutex};
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&events);
+        let config = Config::server().with_observer(move |_: u64, event: &ConnectionEvent<'_>| {
+            let name = match event {
+                ConnectionEvent::HandshakeComplete { .. } => "handshake",
+                ConnectionEvent::PingReceived(b"hi") => "ping received",
+                ConnectionEvent::PongSent(b"hi") => "pong sent",
+                ConnectionEvent::CloseReceived(Some(_)) => "close received",
+                ConnectionEvent::Error(Error::ConnectionClosed(_)) => "error",
+                _ => "unexpected",
+            };
+            sink.lock().unwrap().push(name);
+        });
+
+        // Masked ping "hi", then masked close 1000
+        let data = vec![
+            0x89, 0x82, 0x00, 0x00, 0x00, 0x00, b'h', b'i', 0x88, 0x82, 0x00, 0x00, 0x00, 0x00,
+            0x03, 0xe8,
+        ];
+        let mut conn = Connection::new(MockStream::new(data), Role::Server, config);
+
+        conn.recv().await.unwrap();
+        conn.recv().await.unwrap();
+        assert!(conn.send(Message::text("late")).await.is_err());
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                "handshake",
+                "ping received",
+                "pong sent",
+                "close received",
+                "error"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_interceptors_rewrite_and_drop_frames() {
+        struct Shaper;
+
+        impl FrameInterceptor for Shaper {
+            fn outbound(&mut self, frame: &mut Frame) -> FrameAction {
+                if frame.opcode == OpCode::Text {
+                    *frame = Frame::text(b"rewritten".to_vec());
+                }
+                FrameAction::Forward
+            }
+
+            fn inbound(&mut self, frame: &mut Frame) -> FrameAction {
+                if frame.opcode == OpCode::Ping {
+                    FrameAction::Drop
+                } else {
+                    FrameAction::Forward
+                }
+            }
+        }
+
+        // Masked ping "hi", then masked text "ok"
+        let data = vec![
+            0x89, 0x82, 0x00, 0x00, 0x00, 0x00, b'h', b'i', 0x81, 0x82, 0x00, 0x00, 0x00, 0x00,
+            b'o', b'k',
+        ];
+        let mut conn = Connection::new(MockStream::new(data), Role::Server, Config::server());
+        conn.add_interceptor(Shaper);
+
+        // The ping never surfaces and is not answered
+        let msg = conn.recv().await.unwrap().unwrap();
+        assert_eq!(msg.as_text(), Some("ok"));
+
+        conn.send(Message::text("original")).await.unwrap();
+        let written = conn.codec.into_inner().written().to_vec();
+        assert_eq!(written, [&[0x81, 0x09][..], b"rewritten"].concat());
+    }
+
+    #[tokio::test]
+    async fn test_pending_pings_matched_by_pong() {
+        // Unmasked server pongs "b" then "z" (unsolicited)
+        let pongs = vec![0x8a, 0x01, b'b', 0x8a, 0x01, b'z'];
+        let stream = MockStream::new(pongs);
+        let mut conn = Connection::new(stream, Role::Client, Config::client());
+
+        conn.ping(&b"a"[..]).await.unwrap();
+        conn.ping(&b"b"[..]).await.unwrap();
+        conn.ping(&b"c"[..]).await.unwrap();
+        assert_eq!(conn.pending_pings(), 3);
+
+        // Pong "b" settles "a" and "b"
+        let msg = conn.recv().await.unwrap().unwrap();
+        assert!(matches!(msg, Message::Pong(ref d) if d == &b"b"[..]));
+        assert_eq!(conn.pending_pings(), 1);
+
+        // Unsolicited pong is surfaced but doesn't touch the queue
+        let msg = conn.recv().await.unwrap().unwrap();
+        assert!(matches!(msg, Message::Pong(ref d) if d == &b"z"[..]));
+        assert_eq!(conn.pending_pings(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_latency_sampled_from_pong() {
+        let pong = vec![0x8a, 0x02, b'h', b'b'];
+        let stream = MockStream::new(pong);
+        let mut conn = Connection::new(stream, Role::Client, Config::client());
+        assert!(conn.latency().is_none());
+
+        conn.ping(&b"hb"[..]).await.unwrap();
+        conn.recv().await.unwrap().unwrap();
+
+        let latency = conn.latency().unwrap();
+        assert_eq!(latency.samples, 1);
+        assert_eq!(latency.last, latency.smoothed);
+    }
+
+    #[tokio::test]
+    async fn test_max_pending_pings() {
+        let stream = MockStream::new(vec![]);
+        let config = Config::server().with_max_pending_pings(2);
+        let mut conn = Connection::new(stream, Role::Server, config);
+
+        conn.ping(&b"1"[..]).await.unwrap();
+        conn.send_no_flush(Message::Ping(Bytes::from_static(b"2")))
+            .await
+            .unwrap();
+        let err = conn.ping(&b"3"[..]).await.unwrap_err();
+        assert_eq!(err, Error::TooManyPendingPings { max: 2 });
+        assert_eq!(conn.pending_pings(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_close_with_invalid_code_answers_protocol_error() {
+        // Masked close with code 1005 (must not be sent on the wire)
+        let close_frame = vec![0x88, 0x82, 0x00, 0x00, 0x00, 0x00, 0x03, 0xed];
+        let stream = MockStream::new(close_frame);
+        let mut conn = Connection::new(stream, Role::Server, Config::server());
+
+        let msg = conn.recv().await.unwrap().unwrap();
+        assert!(matches!(
+            msg,
+            Message::Close(Some(ref cf)) if cf.code == CloseCode::ProtocolError
+        ));
+
+        let written = conn.codec.into_inner().written().to_vec();
+        assert_eq!(written[0], 0x88);
+        assert_eq!(u16::from_be_bytes([written[2], written[3]]), 1002);
+    }
+
+    #[tokio::test]
+    async fn test_protocol_error_sends_close() {
+        // Masked text frame with RSV2 set and no extension to own it
+        let frame = vec![0xA1, 0x81, 0x00, 0x00, 0x00, 0x00, b'x'];
+        let mut conn = Connection::new(MockStream::new(frame), Role::Server, Config::server());
+
+        assert_eq!(conn.recv().await, Err(Error::ReservedBitsSet));
+        assert_eq!(conn.state(), ConnectionState::Closed);
+        assert_eq!(conn.recv().await, Ok(None));
+
+        let written = conn.codec.into_inner().written().to_vec();
+        assert_eq!(written[0], 0x88);
+        assert_eq!(u16::from_be_bytes([written[2], written[3]]), 1002);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_utf8_sends_1007() {
+        let frame = vec![0x81, 0x82, 0x00, 0x00, 0x00, 0x00, 0xC3, 0x28];
+        let mut conn = Connection::new(MockStream::new(frame), Role::Server, Config::server());
+
+        assert_eq!(conn.recv().await, Err(Error::InvalidUtf8));
+        let written = conn.codec.into_inner().written().to_vec();
+        assert_eq!(u16::from_be_bytes([written[2], written[3]]), 1007);
+    }
+
+    #[tokio::test]
+    async fn test_oversized_message_sends_1009() {
+        let config = Config::server().with_limits(crate::Limits {
+            max_message_size: 4,
+            ..crate::Limits::default()
+        });
+        let mut frame = vec![0x82, 0x88, 0x00, 0x00, 0x00, 0x00];
+        frame.extend_from_slice(&[0; 8]);
+        let mut conn = Connection::new(MockStream::new(frame), Role::Server, config);
+
+        assert!(matches!(
+            conn.recv().await,
+            Err(Error::MessageTooLarge { .. } | Error::FrameTooLarge { .. })
+        ));
+        let written = conn.codec.into_inner().written().to_vec();
+        assert_eq!(u16::from_be_bytes([written[2], written[3]]), 1009);
+    }
+
+    #[tokio::test]
+    async fn test_parts_round_trip_keeps_unread_frames() {
+        // Two masked text frames arrive in one read
+        let mut data = vec![0x81, 0x81, 0x00, 0x00, 0x00, 0x00, b'a'];
+        data.extend_from_slice(&[0x81, 0x81, 0x00, 0x00, 0x00, 0x00, b'b']);
+        let mut conn = Connection::new(MockStream::new(data), Role::Server, Config::server());
+        let id = conn.id();
+
+        assert_eq!(conn.recv().await.unwrap(), Some(Message::text("a")));
+
+        let parts = conn.into_parts();
+        assert_eq!(
+            &parts.read_buf[..],
+            [0x81, 0x81, 0x00, 0x00, 0x00, 0x00, b'b']
+        );
+        assert_eq!(parts.role, Role::Server);
+
+        let mut conn = Connection::from_parts(parts);
+        assert_ne!(conn.id(), id);
+        assert!(conn.is_open());
+        assert_eq!(conn.recv().await.unwrap(), Some(Message::text("b")));
+    }
+
+    #[tokio::test]
+    async fn test_from_parts_with_new_stream() {
+        let frame = vec![0x82, 0x81, 0x00, 0x00, 0x00, 0x00, 7];
+        let parts = ConnectionParts::new(MockStream::new(vec![]), Role::Server, Config::server())
+            .with_read_buf(frame)
+            .with_subprotocol("chat")
+            .with_state(ConnectionState::Open);
+        let mut conn = Connection::from_parts(parts);
+        assert_eq!(conn.subprotocol(), Some("chat"));
+        assert_eq!(conn.recv().await.unwrap(), Some(Message::binary(vec![7])));
+    }
+
+    #[tokio::test]
+    async fn test_peek_leaves_message_for_recv() {
+        let mut data = vec![0x81, 0x81, 0x00, 0x00, 0x00, 0x00, b'a'];
+        data.extend_from_slice(&[0x88, 0x82, 0x00, 0x00, 0x00, 0x00, 0x03, 0xe8]);
+        let mut conn = Connection::new(MockStream::new(data), Role::Server, Config::server());
+
+        assert_eq!(conn.peek().await.unwrap(), Some(&Message::text("a")));
+        assert_eq!(conn.peek().await.unwrap(), Some(&Message::text("a")));
+        assert_eq!(conn.recv().await.unwrap(), Some(Message::text("a")));
+
+        // A peeked close is still delivered once the connection has closed
+        assert!(matches!(
+            conn.peek().await.unwrap(),
+            Some(Message::Close(_))
+        ));
+        assert_eq!(conn.state(), ConnectionState::Closed);
+        assert!(matches!(
+            conn.recv().await.unwrap(),
+            Some(Message::Close(_))
+        ));
+        assert_eq!(conn.recv().await.unwrap(), None);
+        assert_eq!(conn.peek().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_recv_ref_borrows_and_falls_back() {
+        let mask = [0x11, 0x22, 0x33, 0x44];
+        let masked = |head: u8, payload: &[u8]| {
+            let mut frame = vec![head, 0x80 | payload.len() as u8];
+            frame.extend_from_slice(&mask);
+            frame.extend(payload.iter().zip(mask.iter().cycle()).map(|(b, m)| b ^ m));
+            frame
+        };
+        let mut data = masked(0x81, b"hello");
+        data.extend(masked(0x82, &[1, 2, 3]));
+        data.extend(masked(0x89, b"p"));
+        data.extend(masked(0x01, b"frag"));
+        data.extend(masked(0x80, b"ment"));
+        data.extend(masked(0x81, b"after"));
+        let mut conn = Connection::new(MockStream::new(data), Role::Server, Config::server());
+
+        assert_eq!(
+            conn.recv_ref().await.unwrap(),
+            Some(MessageRef::Text("hello"))
+        );
+        assert_eq!(
+            conn.recv_ref().await.unwrap(),
+            Some(MessageRef::Binary(&[1, 2, 3]))
+        );
+        assert_eq!(conn.recv_ref().await.unwrap(), Some(MessageRef::Ping(b"p")));
+        assert_eq!(
+            conn.recv_ref().await.unwrap(),
+            Some(MessageRef::Text("fragment"))
+        );
+        // Mixing with recv is fine
+        assert_eq!(conn.recv().await.unwrap(), Some(Message::text("after")));
+        assert_eq!(conn.recv_ref().await.unwrap(), None);
+
+        // The ping was answered
+        let written = conn.codec.into_inner().written().to_vec();
+        assert_eq!(written, [0x8A, 0x01, b'p']);
+    }
+
+    #[tokio::test]
+    async fn test_spilled_message_received_and_streamed_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut data = vec![0x01, 0x83, 0x00, 0x00, 0x00, 0x00];
+        data.extend_from_slice(b"hel");
+        data.extend_from_slice(&[0x80, 0x88, 0x00, 0x00, 0x00, 0x00]);
+        data.extend_from_slice(b"lo world");
+        data.extend_from_slice(&[0x81, 0x82, 0x00, 0x00, 0x00, 0x00, b'h', b'i']);
+        let config = Config::server()
+            .with_fragment_size(4)
+            .with_spill(4, crate::protocol::TempFiles::in_dir(dir.path()));
+        let mut conn = Connection::new(MockStream::new(data), Role::Server, config);
+
+        let Some(Message::Spilled(spilled)) = conn.recv().await.unwrap() else {
+            panic!("expected a spilled message");
+        };
+        assert!(spilled.is_text());
+        assert_eq!(spilled.len(), 11);
+        assert_eq!(spilled.read_to_vec().unwrap(), b"hello world");
+        // Small messages stay in memory
+        assert_eq!(conn.recv().await.unwrap(), Some(Message::text("hi")));
+
+        conn.send(Message::Spilled(spilled)).await.unwrap();
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+
+        let written = conn.codec.into_inner().written().to_vec();
+        let mut client = Connection::new(MockStream::new(written), Role::Client, Config::client());
+        assert_eq!(
+            client.recv().await.unwrap(),
+            Some(Message::text("hello world"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_spilled_message_polled() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut data = vec![0x02, 0x83, 0x00, 0x00, 0x00, 0x00, 1, 2, 3];
+        data.extend_from_slice(&[0x80, 0x84, 0x00, 0x00, 0x00, 0x00, 4, 5, 6, 7]);
+        let config = Config::server()
+            .with_fragment_size(3)
+            .with_spill(4, crate::protocol::TempFiles::in_dir(dir.path()));
+        let mut conn = Connection::new(MockStream::new(data), Role::Server, config);
+
+        let Some(Message::Spilled(spilled)) = poll_fn(|cx| conn.poll_recv(cx)).await.unwrap()
+        else {
+            panic!("expected a spilled message");
+        };
+        assert_eq!(spilled.read().await.unwrap(), [1, 2, 3, 4, 5, 6, 7]);
+
+        let message = Message::Spilled(spilled);
+        poll_fn(|cx| conn.poll_send(cx, &message)).await.unwrap();
+        poll_fn(|cx| conn.poll_flush(cx)).await.unwrap();
+        drop(message);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+
+        let written = conn.codec.into_inner().written().to_vec();
+        let mut client = Connection::new(MockStream::new(written), Role::Client, Config::client());
+        assert_eq!(
+            client.recv().await.unwrap(),
+            Some(Message::binary(vec![1, 2, 3, 4, 5, 6, 7]))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_lossy_utf8_replaces_invalid_text() {
+        use std::sync::Arc;
+        use std::sync::atomic::AtomicUsize;
+
+        use crate::config::CompatMode;
+
+        let mut data = vec![0x81, 0x82, 0x00, 0x00, 0x00, 0x00, 0xC3, 0x28];
+        data.extend([0x01, 0x81, 0x00, 0x00, 0x00, 0x00, 0xE2]);
+        data.extend([0x80, 0x81, 0x00, 0x00, 0x00, 0x00, b'!']);
+        let replaced = Arc::new(AtomicUsize::new(0));
+        let config = Config::server()
+            .with_compat(CompatMode::default().with_lossy_utf8(true))
+            .with_observer({
+                let replaced = replaced.clone();
+                move |_, event: &ConnectionEvent<'_>| {
+                    if matches!(event, ConnectionEvent::InvalidUtf8Replaced) {
+                        replaced.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            });
+        let mut conn = Connection::new(MockStream::new(data), Role::Server, config);
+
+        assert_eq!(conn.recv().await.unwrap(), Some(Message::text("\u{FFFD}(")));
+        assert_eq!(
+            conn.recv_ref().await.unwrap(),
+            Some(MessageRef::Text("\u{FFFD}!"))
+        );
+        assert_eq!(replaced.load(Ordering::Relaxed), 2);
+        assert!(conn.is_open());
+    }
+
+    #[tokio::test]
+    async fn test_recv_text_and_binary() {
+        let (ours, theirs) = tokio::io::duplex(4096);
+        let mut conn = Connection::new(ours, Role::Server, Config::server());
+        let mut peer = Connection::new(theirs, Role::Client, Config::client());
+
+        peer.ping(b"p".to_vec()).await.unwrap();
+        peer.send(Message::text("one")).await.unwrap();
+        peer.send(Message::binary(vec![2])).await.unwrap();
+        peer.send(Message::binary(vec![3])).await.unwrap();
+        peer.close(CloseCode::GoingAway, "").await.unwrap();
+
+        assert_eq!(conn.recv_text().await.unwrap(), "one");
+        let err = conn.recv_text().await.unwrap_err();
+        assert_eq!(
+            err,
+            Error::UnexpectedMessage {
+                expected: OpCode::Text,
+                got: OpCode::Binary
+            }
+        );
+        assert!(!err.is_fatal());
+        assert_eq!(conn.recv_binary().await.unwrap(), &[3][..]);
+        assert_eq!(
+            conn.recv_binary().await,
+            Err(Error::ConnectionClosed(Some(1001)))
+        );
+        assert_eq!(conn.recv_text().await, Err(Error::ConnectionClosed(None)));
+    }
+
+    #[tokio::test]
+    async fn test_close_reason_outlives_close() {
+        let (ours, theirs) = tokio::io::duplex(4096);
+        let mut conn = Connection::new(ours, Role::Server, Config::server());
+        let mut peer = Connection::new(theirs, Role::Client, Config::client());
+        assert_eq!(conn.close_reason(), None);
+
+        peer.close(CloseCode::GoingAway, "restart").await.unwrap();
+        assert!(conn.recv().await.unwrap().is_some());
+        assert_eq!(conn.recv().await.unwrap(), None);
+        let expected = CloseFrame::new(CloseCode::GoingAway, "restart");
+        assert_eq!(conn.close_reason(), Some(&expected));
+        assert_eq!(conn.sent_close(), Some(&expected));
+
+        assert!(peer.recv().await.unwrap().is_some());
+        assert_eq!(peer.sent_close(), Some(&expected));
+        assert_eq!(peer.received_close(), Some(&expected));
+
+        // A connection failed for a protocol error keeps the code it sent
+        let frame = vec![0xA1, 0x81, 0x00, 0x00, 0x00, 0x00, b'x'];
+        let mut conn = Connection::new(MockStream::new(frame), Role::Server, Config::server());
+        assert!(conn.recv().await.is_err());
+        assert_eq!(
+            conn.close_reason().map(|f| f.code),
+            Some(CloseCode::ProtocolError)
+        );
+        assert_eq!(conn.received_close(), None);
+
+        // An invalid close code is answered, not recorded as received
+        let frame = vec![0x88, 0x82, 0x00, 0x00, 0x00, 0x00, 0x03, 0xED];
+        let mut conn = Connection::new(MockStream::new(frame), Role::Server, Config::server());
+        assert!(conn.recv().await.unwrap().is_some());
+        assert_eq!(conn.received_close(), None);
+        let answer = CloseFrame::new(CloseCode::ProtocolError, "Invalid close code");
+        assert_eq!(conn.sent_close(), Some(&answer));
+        assert_eq!(conn.close_reason(), Some(&answer));
+        let written = conn.codec.into_inner().written().to_vec();
+        assert_eq!(u16::from_be_bytes([written[2], written[3]]), 1002);
+    }
+
+    #[tokio::test]
+    async fn test_recv_ref_rejects_invalid_utf8() {
+        let frame = vec![0x81, 0x82, 0x00, 0x00, 0x00, 0x00, 0xC3, 0x28];
+        let mut conn = Connection::new(MockStream::new(frame), Role::Server, Config::server());
+
+        assert_eq!(conn.recv_ref().await, Err(Error::InvalidUtf8));
+        assert_eq!(conn.state(), ConnectionState::Closed);
+        let written = conn.codec.into_inner().written().to_vec();
+        assert_eq!(u16::from_be_bytes([written[2], written[3]]), 1007);
+    }
+
     #[tokio::test]
     async fn test_state_transitions() {
         let stream = MockStream::new(vec![]);
@@ -579,6 +2927,22 @@ mod tests {
         assert_eq!(written[0], 0x88);
     }
 
+    #[tokio::test]
+    async fn test_unsendable_close_codes_rejected() {
+        let stream = MockStream::new(vec![]);
+        let mut conn = Connection::new(stream, Role::Server, Config::server());
+
+        let result = conn.close(CloseCode::Other(2500), "bye").await;
+        assert_eq!(result, Err(Error::InvalidCloseCode(2500)));
+        let result = conn
+            .send(Message::close(CloseCode::AbnormalClosure, ""))
+            .await;
+        assert_eq!(result, Err(Error::InvalidCloseCode(1006)));
+
+        assert!(conn.is_open());
+        assert!(conn.codec.into_inner().written().is_empty());
+    }
+
     #[tokio::test]
     async fn test_send_after_close_fails() {
         let stream = MockStream::new(vec![]);
@@ -649,4 +3013,293 @@ mod tests {
         let written = conn.codec.into_inner().written().to_vec();
         assert_eq!(written[0], 0x81);
     }
+
+    #[tokio::test]
+    async fn test_send_prepared() {
+        let prepared = PreparedMessage::new(Message::text("shared")).unwrap();
+
+        let mut server = Connection::new(MockStream::new(vec![]), Role::Server, Config::server());
+        server.send_prepared(&prepared).await.unwrap();
+        assert_eq!(server.codec.into_inner().written(), prepared.wire());
+
+        // Clients must mask, so the shared bytes cannot be reused
+        let mut client = Connection::new(MockStream::new(vec![]), Role::Client, Config::client());
+        client.send_prepared(&prepared).await.unwrap();
+        let written = client.codec.into_inner().written().to_vec();
+        assert_eq!(written[0], 0x81);
+        assert_eq!(written[1], 0x80 | 6);
+        assert_ne!(written, prepared.wire());
+    }
+
+    #[tokio::test]
+    async fn test_queue_lets_urgent_messages_overtake() {
+        let config = Config::server().with_fragment_size(4);
+        let mut conn = Connection::new(MockStream::new(vec![]), Role::Server, config);
+
+        conn.enqueue(Message::text("aaaaaaaa"), Priority::Normal);
+        conn.enqueue(Message::text("later"), Priority::Normal);
+        assert_eq!(conn.queued(), 2);
+
+        // First fragment of the large message goes out
+        assert!(conn.send_next().await.unwrap());
+        conn.enqueue(Message::text("hi"), Priority::High);
+        conn.enqueue(Message::Ping(Bytes::from_static(b"p")), Priority::Normal);
+        conn.flush_queue().await.unwrap();
+        assert!(!conn.send_next().await.unwrap());
+
+        let written = conn.codec.into_inner().written().to_vec();
+        let expected = [
+            &[0x01, 0x04][..],
+            b"aaaa",
+            &[0x89, 0x01],
+            b"p",
+            &[0x80, 0x04],
+            b"aaaa",
+            &[0x81, 0x02],
+            b"hi",
+            &[0x01, 0x04],
+            b"late",
+            &[0x80, 0x01],
+            b"r",
+        ]
+        .concat();
+        assert_eq!(written, expected);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_slow_consumer_policies() {
+        use crate::config::{SlowConsumer, SlowConsumerPolicy};
+        use std::time::Duration;
+
+        let grace = Duration::from_secs(5);
+        let stalled = async |policy| {
+            let config = Config::server().with_slow_consumer(SlowConsumer::new(4, grace, policy));
+            let mut conn = Connection::new(MockStream::new(vec![]), Role::Server, config);
+            conn.enqueue(Message::text("abc"), Priority::Normal);
+            conn.enqueue(Message::text("def"), Priority::Normal);
+            tokio::time::advance(grace).await;
+            conn.enqueue(Message::text("ghi"), Priority::Normal);
+            conn
+        };
+
+        let mut conn = stalled(SlowConsumerPolicy::Disconnect(CloseCode::TryAgainLater)).await;
+        assert_eq!(conn.queued(), 0);
+        assert!(matches!(
+            conn.send_next().await,
+            Err(Error::SlowConsumer { max_bytes: 4, .. })
+        ));
+        assert_eq!(conn.state(), ConnectionState::Closing);
+        let written = conn.codec.into_inner().written().to_vec();
+        assert_eq!(
+            written,
+            [&[0x88, 15, 0x03, 0xF5][..], b"slow consumer"].concat()
+        );
+
+        let mut conn = stalled(SlowConsumerPolicy::DropOldest).await;
+        assert_eq!(conn.queued(), 1);
+        conn.flush_queue().await.unwrap();
+        assert_eq!(
+            conn.codec.into_inner().written(),
+            [0x81, 3, b'g', b'h', b'i']
+        );
+
+        // Blocking writes until the queue is back under the limit
+        let mut conn = stalled(SlowConsumerPolicy::Block).await;
+        assert_eq!(conn.queued(), 3);
+        assert!(conn.send_next().await.unwrap());
+        assert_eq!(conn.queued(), 1);
+
+        // A peer that stops reading altogether is aborted
+        let (ours, _theirs) = tokio::io::duplex(8);
+        let policy = SlowConsumerPolicy::Disconnect(CloseCode::PolicyViolation);
+        let config = Config::server().with_slow_consumer(SlowConsumer::new(4, grace, policy));
+        let mut conn = Connection::new(ours, Role::Server, config);
+        conn.enqueue(Message::text("abcdef"), Priority::Normal);
+        tokio::time::advance(grace).await;
+        assert!(matches!(
+            conn.send_next().await,
+            Err(Error::SlowConsumer { .. })
+        ));
+        assert_eq!(conn.state(), ConnectionState::Closed);
+    }
+
+    #[tokio::test]
+    async fn test_send_finishes_queued_message_first() {
+        let config = Config::server().with_fragment_size(2);
+        let mut conn = Connection::new(MockStream::new(vec![]), Role::Server, config);
+
+        conn.enqueue(Message::binary(vec![1, 2, 3]), Priority::Normal);
+        assert!(conn.send_next().await.unwrap());
+        conn.send(Message::text("x")).await.unwrap();
+        assert!(!conn.send_next().await.unwrap());
+
+        let written = conn.codec.into_inner().written().to_vec();
+        assert_eq!(written, [0x02, 0x02, 1, 2, 0x80, 0x01, 3, 0x81, 0x01, b'x']);
+    }
+
+    #[tokio::test]
+    async fn test_send_uses_configured_fragmenter() {
+        use crate::connection::{NeverFragment, Ramp};
+
+        let config = Config::server()
+            .with_fragment_size(2)
+            .with_fragmenter(NeverFragment);
+        let mut conn = Connection::new(MockStream::new(vec![]), Role::Server, config);
+        conn.send(Message::binary(vec![7; 5])).await.unwrap();
+        assert_eq!(
+            conn.codec.into_inner().written(),
+            [0x82, 0x05, 7, 7, 7, 7, 7]
+        );
+
+        let config = Config::server().with_fragmenter(Ramp::new(1, 4));
+        let mut conn = Connection::new(MockStream::new(vec![]), Role::Server, config);
+        conn.send(Message::binary(vec![1, 2, 3, 4])).await.unwrap();
+        let written = conn.codec.into_inner().written().to_vec();
+        assert_eq!(written, [0x02, 0x01, 1, 0x00, 0x02, 2, 3, 0x80, 0x01, 4]);
+    }
+
+    #[cfg(feature = "compression")]
+    #[tokio::test]
+    async fn test_send_uncompressed_skips_deflate() {
+        use crate::extensions::deflate::{DeflateConfig, DeflateExtension};
+
+        let mut extensions = ExtensionRegistry::new();
+        extensions
+            .add(Box::new(DeflateExtension::server(DeflateConfig::default())))
+            .unwrap();
+        extensions.negotiate(&[ExtensionOffer::new("permessage-deflate")]);
+
+        let stream = MockStream::new(vec![]);
+        let mut conn =
+            Connection::with_extensions(stream, Role::Server, Config::server(), extensions);
+        assert_eq!(conn.negotiated_extensions(), ["permessage-deflate"]);
+
+        conn.send_uncompressed(Message::text("plain"))
+            .await
+            .unwrap();
+        conn.send(Message::text("squeezed")).await.unwrap();
+
+        let written = conn.codec.into_inner().written().to_vec();
+        // RSV1 clear, payload as-is
+        assert_eq!(&written[..7], [&[0x81, 0x05][..], b"plain"].concat());
+        // RSV1 set on the compressed message
+        assert_eq!(written[7], 0xC1);
+    }
+
+    #[cfg(feature = "compression")]
+    #[tokio::test]
+    async fn test_negotiated_extension_allows_rsv1() {
+        use crate::extensions::deflate::{DeflateConfig, DeflateExtension};
+
+        let mut server = ExtensionRegistry::new();
+        server
+            .add(Box::new(DeflateExtension::server(DeflateConfig::default())))
+            .unwrap();
+        let accepted = server.negotiate(&[ExtensionOffer::new("permessage-deflate")]);
+        let mut client = ExtensionRegistry::new();
+        client
+            .add(Box::new(DeflateExtension::client(DeflateConfig::default())))
+            .unwrap();
+        client.configure(&accepted).unwrap();
+        assert!(client.negotiated_rsv_bits().rsv1);
+
+        let (ours, theirs) = tokio::io::duplex(4096);
+        let mut conn = Connection::with_extensions(ours, Role::Server, Config::server(), server);
+        let mut peer = Connection::with_extensions(theirs, Role::Client, Config::client(), client);
+
+        peer.send(Message::text("squeezed")).await.unwrap();
+        assert_eq!(conn.recv().await.unwrap(), Some(Message::text("squeezed")));
+
+        // RSV1 stays reserved on control frames
+        let mut ping = Frame::ping(b"p".to_vec());
+        ping.rsv1 = true;
+        peer.codec.write_frame(&ping).await.unwrap();
+        peer.flush().await.unwrap();
+        assert_eq!(conn.recv().await, Err(Error::ReservedBitsSet));
+    }
+
+    #[cfg(feature = "compression")]
+    #[tokio::test]
+    async fn test_fragmented_compressed_message() {
+        use crate::extensions::deflate::{DeflateConfig, DeflateExtension};
+
+        let mut server = ExtensionRegistry::new();
+        server
+            .add(Box::new(DeflateExtension::server(DeflateConfig::default())))
+            .unwrap();
+        let accepted = server.negotiate(&[ExtensionOffer::new("permessage-deflate")]);
+        let mut client = ExtensionRegistry::new();
+        client
+            .add(Box::new(DeflateExtension::client(DeflateConfig::default())))
+            .unwrap();
+        client.configure(&accepted).unwrap();
+
+        let (ours, theirs) = tokio::io::duplex(4096);
+        let mut conn = Connection::with_extensions(ours, Role::Server, Config::server(), server);
+        let mut peer = Connection::with_extensions(theirs, Role::Client, Config::client(), client);
+
+        // Compress the whole message, then split it over three frames
+        let text = "fragmented and compressed ".repeat(20);
+        let mut whole = Frame::text(text.clone().into_bytes());
+        peer.extensions.encode(&mut whole).unwrap();
+        assert!(whole.rsv1);
+        let compressed = whole.into_payload_bytes();
+        let third = compressed.len() / 3;
+
+        let mut first = Frame::new_from_bytes(false, OpCode::Text, compressed.slice(..third));
+        first.rsv1 = true;
+        peer.codec.write_frame(&first).await.unwrap();
+        let middle = compressed.slice(third..2 * third);
+        let middle = Frame::new_from_bytes(false, OpCode::Continuation, middle);
+        peer.codec.write_frame(&middle).await.unwrap();
+        let last = compressed.slice(2 * third..);
+        let last = Frame::new_from_bytes(true, OpCode::Continuation, last);
+        peer.codec.write_frame(&last).await.unwrap();
+        peer.flush().await.unwrap();
+        assert_eq!(conn.recv().await.unwrap(), Some(Message::text(text)));
+
+        // RSV1 on a continuation frame is rejected
+        let mut first = Frame::new(false, OpCode::Binary, vec![1]);
+        first.rsv1 = true;
+        peer.codec.write_frame(&first).await.unwrap();
+        let mut next = Frame::new(true, OpCode::Continuation, vec![2]);
+        next.rsv1 = true;
+        peer.codec.write_frame(&next).await.unwrap();
+        peer.flush().await.unwrap();
+        assert!(matches!(
+            conn.recv().await,
+            Err(Error::ProtocolViolation(_))
+        ));
+    }
+
+    #[cfg(feature = "compression")]
+    #[tokio::test]
+    async fn test_max_message_size_bounds_decompressed_size() {
+        use crate::config::Limits;
+        use crate::extensions::deflate::{DeflateConfig, DeflateExtension};
+
+        let mut server = ExtensionRegistry::new();
+        server
+            .add(Box::new(DeflateExtension::server(DeflateConfig::default())))
+            .unwrap();
+        let accepted = server.negotiate(&[ExtensionOffer::new("permessage-deflate")]);
+        let mut client = ExtensionRegistry::new();
+        client
+            .add(Box::new(DeflateExtension::client(DeflateConfig::default())))
+            .unwrap();
+        client.configure(&accepted).unwrap();
+
+        let mut config = Config::server();
+        config.limits = Limits::new(1024, 1024, 16, 8192);
+        let (ours, theirs) = tokio::io::duplex(4096);
+        let mut conn = Connection::with_extensions(ours, Role::Server, config, server);
+        let mut peer = Connection::with_extensions(theirs, Role::Client, Config::client(), client);
+
+        // A few bytes on the wire, but over the limit once inflated
+        peer.send(Message::text("a".repeat(2000))).await.unwrap();
+        assert!(matches!(
+            conn.recv().await,
+            Err(Error::MessageTooLarge { max: 1024, .. })
+        ));
+    }
 }
@@ -1,7 +1,85 @@
 //! Message fragmentation for outgoing WebSocket messages (RFC 6455).
 
+use std::fmt;
+
 use crate::protocol::{Frame, OpCode};
 
+/// Decides how outgoing data messages are split into frames.
+///
+/// Small fragments let control frames and other queued messages through
+/// sooner; large ones cost fewer headers and syscalls. Install a strategy with
+/// [`Config::with_fragmenter`](crate::Config::with_fragmenter); without one,
+/// connections use [`FixedSize`] with [`Config::fragment_size`](crate::Config).
+///
+/// Every fragment still has to fit in `limits.max_frame_size`.
+pub trait Fragmenter: fmt::Debug + Send + Sync {
+    /// Length of the next fragment of a `len`-byte message, `sent` bytes of
+    /// which have been written already.
+    ///
+    /// The result is clamped to at least one byte and at most what is left.
+    fn next_len(&self, opcode: OpCode, len: usize, sent: usize) -> usize;
+}
+
+/// Fragments of at most a fixed size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FixedSize(pub usize);
+
+impl Fragmenter for FixedSize {
+    fn next_len(&self, _opcode: OpCode, _len: usize, _sent: usize) -> usize {
+        self.0
+    }
+}
+
+/// Every message goes out as a single frame.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NeverFragment;
+
+impl Fragmenter for NeverFragment {
+    fn next_len(&self, _opcode: OpCode, len: usize, _sent: usize) -> usize {
+        len
+    }
+}
+
+/// Fragments that grow as the message goes out.
+///
+/// The first fragment is `initial` bytes so the message starts quickly, and
+/// each following one doubles up to `max`. Short messages pay little latency,
+/// long transfers little overhead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ramp {
+    initial: usize,
+    max: usize,
+}
+
+impl Ramp {
+    /// Start at `initial` bytes and double up to `max`.
+    #[must_use]
+    pub const fn new(initial: usize, max: usize) -> Self {
+        Self { initial, max }
+    }
+}
+
+impl Fragmenter for Ramp {
+    fn next_len(&self, _opcode: OpCode, _len: usize, sent: usize) -> usize {
+        // After initial, 2*initial, ..., 2^(n-1)*initial, sent + initial is 2^n*initial
+        let initial = self.initial.max(1);
+        (sent + initial).min(self.max).max(initial)
+    }
+}
+
+/// Clamp a strategy's answer to a valid fragment length.
+pub(crate) fn fragment_len(
+    fragmenter: &dyn Fragmenter,
+    opcode: OpCode,
+    len: usize,
+    sent: usize,
+) -> usize {
+    let remaining = len - sent;
+    fragmenter
+        .next_len(opcode, len, sent)
+        .clamp(1, remaining.max(1))
+}
+
 /// Iterator that produces frames from a message payload.
 ///
 /// Splits large payloads into multiple frames according to the configured
@@ -170,6 +248,27 @@ mod tests {
         assert_eq!(frames[2].opcode, OpCode::Continuation);
     }
 
+    #[test]
+    fn test_strategies() {
+        assert_eq!(fragment_len(&FixedSize(10), OpCode::Text, 25, 20), 5);
+        assert_eq!(fragment_len(&FixedSize(0), OpCode::Text, 25, 0), 1);
+        assert_eq!(
+            fragment_len(&NeverFragment, OpCode::Binary, 1 << 20, 0),
+            1 << 20
+        );
+        assert_eq!(fragment_len(&NeverFragment, OpCode::Binary, 0, 0), 1);
+
+        let ramp = Ramp::new(4, 16);
+        let mut sent = 0;
+        let mut sizes = Vec::new();
+        while sent < 64 {
+            let n = fragment_len(&ramp, OpCode::Binary, 64, sent);
+            sizes.push(n);
+            sent += n;
+        }
+        assert_eq!(sizes, [4, 8, 16, 16, 16, 4]);
+    }
+
     #[test]
     fn test_remaining_bytes() {
         let payload = vec![0xAB; 30];
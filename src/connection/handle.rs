@@ -0,0 +1,324 @@
+//! A cloneable handle to a connection driven by its own task.
+
+use std::fmt;
+use std::future::poll_fn;
+use std::pin::pin;
+use std::sync::{Arc, Mutex};
+use std::task::Poll;
+
+use bytes::Bytes;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::{broadcast, mpsc, oneshot};
+
+use crate::connection::Connection;
+use crate::error::{Error, Result};
+use crate::message::{CloseCode, Message};
+use crate::task;
+
+/// Commands and received messages buffered between handles and the driver.
+const QUEUE_SIZE: usize = 64;
+
+enum Command {
+    Send(Message, oneshot::Sender<Result<()>>),
+    Close(CloseCode, String, oneshot::Sender<Result<()>>),
+}
+
+impl<T> Connection<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    /// Move the connection onto a task of its own and return a handle to it.
+    ///
+    /// The handle is cheap to clone and can be shared between tasks without
+    /// a lock. The task runs until the connection closes, or until every
+    /// handle and subscription is dropped, when it closes the connection
+    /// with [`CloseCode::Normal`].
+    ///
+    /// Must be called from within a tokio runtime.
+    ///
+    /// ```rust,ignore
+    /// let handle = conn.into_handle();
+    /// let mut incoming = handle.subscribe();
+    /// tokio::spawn({
+    ///     let handle = handle.clone();
+    ///     async move { handle.send(Message::text("hello")).await }
+    /// });
+    /// while let Ok(msg) = incoming.recv().await { /* ... */ }
+    /// ```
+    pub fn into_handle(self) -> WsHandle {
+        let (commands, rx) = mpsc::channel(QUEUE_SIZE);
+        let (incoming, first) = broadcast::channel(QUEUE_SIZE);
+        let template = incoming.downgrade();
+        task::spawn(task::CONNECTION_DRIVER, drive(self, rx, incoming));
+        WsHandle {
+            commands,
+            subscriptions: Arc::new(Subscriptions {
+                first: Mutex::new(Some(first)),
+                template,
+            }),
+        }
+    }
+}
+
+/// A handle to a connection running on its own task, made with
+/// [`Connection::into_handle`].
+///
+/// Clones share the connection. Sends are queued to the task in order and
+/// each call resolves once its message has been written.
+#[derive(Clone)]
+pub struct WsHandle {
+    commands: mpsc::Sender<Command>,
+    subscriptions: Arc<Subscriptions>,
+}
+
+struct Subscriptions {
+    /// Handed to the first subscriber, so that it sees every message
+    /// received since the handle was made.
+    first: Mutex<Option<broadcast::Receiver<Message>>>,
+    /// Later subscribers subscribe through this. It is weak so that the
+    /// channel closes when the connection task exits, and holds no
+    /// messages of its own.
+    template: broadcast::WeakSender<Message>,
+}
+
+impl WsHandle {
+    /// Send a message.
+    ///
+    /// ## Errors
+    ///
+    /// - [`Error::ConnectionClosed`] if the connection task has exited.
+    /// - Errors from [`Connection::send`].
+    pub async fn send(&self, message: Message) -> Result<()> {
+        self.request(|reply| Command::Send(message, reply)).await
+    }
+
+    /// Send `data` as a binary message without copying it, so one payload
+    /// can be sent to many handles.
+    ///
+    /// ## Errors
+    ///
+    /// As for [`send`](Self::send).
+    pub async fn send_shared(&self, data: Bytes) -> Result<()> {
+        self.send(Message::Binary(data)).await
+    }
+
+    /// Start the close handshake. Subscriptions end once the peer answers.
+    ///
+    /// ## Errors
+    ///
+    /// As for [`send`](Self::send), and [`Connection::close`].
+    pub async fn close(&self, code: CloseCode, reason: &str) -> Result<()> {
+        self.request(|reply| Command::Close(code, reason.to_owned(), reply))
+            .await
+    }
+
+    /// Subscribe to received messages, including the peer's close message.
+    ///
+    /// The first subscription sees every message since the handle was made;
+    /// later ones see messages received after they subscribe. A subscriber
+    /// more than 64 messages behind skips ahead with
+    /// [`RecvError::Lagged`](broadcast::error::RecvError::Lagged). The
+    /// subscription ends with `RecvError::Closed` when the connection does.
+    #[must_use]
+    pub fn subscribe(&self) -> broadcast::Receiver<Message> {
+        let mut first = self
+            .subscriptions
+            .first
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        first.take().unwrap_or_else(|| {
+            match self.subscriptions.template.upgrade() {
+                Some(incoming) => incoming.subscribe(),
+                // The task has exited; hand out a subscription that has
+                // already ended
+                None => broadcast::channel(1).1,
+            }
+        })
+    }
+
+    /// Whether the connection task has exited.
+    #[must_use]
+    pub fn is_closed(&self) -> bool {
+        self.commands.is_closed()
+    }
+
+    async fn request(
+        &self,
+        command: impl FnOnce(oneshot::Sender<Result<()>>) -> Command,
+    ) -> Result<()> {
+        let (reply, result) = oneshot::channel();
+        self.commands
+            .send(command(reply))
+            .await
+            .map_err(|_| Error::ConnectionClosed(None))?;
+        result.await.map_err(|_| Error::ConnectionClosed(None))?
+    }
+}
+
+impl fmt::Debug for WsHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WsHandle")
+            .field("closed", &self.is_closed())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Run `conn`, executing commands and publishing received messages, until
+/// it closes or nobody is left to use it.
+async fn drive<T>(
+    mut conn: Connection<T>,
+    mut commands: mpsc::Receiver<Command>,
+    incoming: broadcast::Sender<Message>,
+) where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut handles = true;
+    loop {
+        let command = {
+            let mut readable = pin!(conn.readable());
+            poll_fn(|cx| {
+                if handles && let Poll::Ready(command) = commands.poll_recv(cx) {
+                    return Poll::Ready(Some(command));
+                }
+                readable.as_mut().poll(cx).map(|()| None)
+            })
+            .await
+        };
+
+        match command {
+            Some(Some(Command::Send(message, reply))) => {
+                let _ = reply.send(conn.send(message).await);
+            }
+            Some(Some(Command::Close(code, reason, reply))) => {
+                let _ = reply.send(conn.close(code, &reason).await);
+            }
+            Some(None) => {
+                handles = false;
+                if incoming.receiver_count() == 0 {
+                    break;
+                }
+            }
+            None => match conn.recv().await {
+                Ok(Some(message)) => {
+                    // Errs only once every subscription is gone
+                    if incoming.send(message).is_err() && !handles {
+                        break;
+                    }
+                }
+                Ok(None) => return,
+                Err(_e) if !_e.is_fatal() => {}
+                Err(_e) => {
+                    ws_debug!(error = %_e, "connection driver stopped");
+                    return;
+                }
+            },
+        }
+    }
+    let _ = conn.close(CloseCode::Normal, "").await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::connection::Role;
+
+    fn pair() -> (WsHandle, Connection<tokio::io::DuplexStream>) {
+        let (ours, theirs) = tokio::io::duplex(4096);
+        (
+            Connection::new(ours, Role::Server, Config::server()).into_handle(),
+            Connection::new(theirs, Role::Client, Config::client()),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_clones_send_and_subscribers_receive() {
+        let (handle, mut peer) = pair();
+        let mut first = handle.subscribe();
+
+        peer.send(Message::text("early")).await.unwrap();
+        assert_eq!(first.recv().await.unwrap(), Message::text("early"));
+
+        let mut second = handle.subscribe();
+        handle.clone().send(Message::text("one")).await.unwrap();
+        handle
+            .send_shared(Bytes::from_static(b"two"))
+            .await
+            .unwrap();
+        assert_eq!(peer.recv().await.unwrap(), Some(Message::text("one")));
+        assert_eq!(
+            peer.recv().await.unwrap(),
+            Some(Message::binary(b"two".to_vec()))
+        );
+
+        peer.send(Message::text("late")).await.unwrap();
+        assert_eq!(first.recv().await.unwrap(), Message::text("late"));
+        assert_eq!(second.recv().await.unwrap(), Message::text("late"));
+    }
+
+    #[tokio::test]
+    async fn test_close_ends_subscriptions() {
+        let (handle, mut peer) = pair();
+        let mut incoming = handle.subscribe();
+
+        handle.close(CloseCode::GoingAway, "bye").await.unwrap();
+        assert_eq!(
+            peer.recv().await.unwrap(),
+            Some(Message::close(CloseCode::GoingAway, "bye"))
+        );
+        assert_eq!(peer.recv().await.unwrap(), None);
+
+        assert!(incoming.recv().await.unwrap().is_control());
+        assert!(incoming.recv().await.is_err());
+        assert!(matches!(
+            handle.send(Message::text("x")).await,
+            Err(Error::ConnectionClosed(_))
+        ));
+        assert!(handle.is_closed());
+        assert!(handle.subscribe().recv().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_dropping_handles_and_subscriptions_closes() {
+        let (handle, mut peer) = pair();
+        let first = handle.subscribe();
+        let later = handle.subscribe();
+        drop(handle);
+
+        // Still subscribed, so messages are delivered
+        peer.send(Message::text("kept")).await.unwrap();
+        drop((first, later));
+        peer.send(Message::text("dropped")).await.unwrap();
+        assert_eq!(
+            peer.recv().await.unwrap(),
+            Some(Message::close(CloseCode::Normal, ""))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_held_frames_flushed_on_time() {
+        use crate::config::WriteCoalescing;
+        use std::time::Duration;
+
+        let (ours, theirs) = tokio::io::duplex(4096);
+        let config = Config::server()
+            .with_write_coalescing(WriteCoalescing::new(1024, Duration::from_millis(5)));
+        let handle = Connection::new(ours, Role::Server, config).into_handle();
+        let mut peer = Connection::new(theirs, Role::Client, Config::client());
+
+        // The peer sends nothing, so only the delay gets this out
+        handle.send(Message::text("tick")).await.unwrap();
+        let received = tokio::time::timeout(Duration::from_secs(5), peer.recv()).await;
+        assert_eq!(received.unwrap().unwrap(), Some(Message::text("tick")));
+    }
+
+    #[tokio::test]
+    async fn test_dropping_every_handle_closes() {
+        let (handle, mut peer) = pair();
+        drop(handle);
+        assert_eq!(
+            peer.recv().await.unwrap(),
+            Some(Message::close(CloseCode::Normal, ""))
+        );
+    }
+}
@@ -0,0 +1,212 @@
+//! A read loop that hands messages to a [`Handler`].
+
+use std::future::Future;
+use std::pin::Pin;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::connection::Connection;
+use crate::error::{Error, Result};
+use crate::message::{CloseCode, CloseFrame, Message};
+
+/// The future returned by [`Handler`] methods.
+pub type HandlerFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Callbacks for [`Connection::run`].
+///
+/// Only [`on_message`](Self::on_message) is required; the others default to
+/// doing nothing.
+pub trait Handler<T>: Send {
+    /// Called for each text, binary or spilled message. Reply through
+    /// `conn`.
+    ///
+    /// An error closes the connection with [`CloseCode::InternalError`] and
+    /// is returned from `run`.
+    fn on_message<'a>(
+        &'a mut self,
+        conn: &'a mut Connection<T>,
+        message: Message,
+    ) -> HandlerFuture<'a, Result<()>>;
+
+    /// Called when the peer closes the connection, with its close frame.
+    /// The close has already been answered.
+    fn on_close<'a>(&'a mut self, frame: Option<&'a CloseFrame>) -> HandlerFuture<'a, ()> {
+        let _ = frame;
+        Box::pin(async {})
+    }
+
+    /// Called for every error receiving messages, and when the peer stops
+    /// answering keepalive pings. Errors that leave the connection usable
+    /// do not stop `run`.
+    fn on_error<'a>(&'a mut self, error: &'a Error) -> HandlerFuture<'a, ()> {
+        let _ = error;
+        Box::pin(async {})
+    }
+}
+
+impl<T> Connection<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    /// Receive messages and hand them to `handler` until the connection
+    /// closes.
+    ///
+    /// Pings are answered and, with [`Config::keepalive`], sent to a quiet
+    /// peer as by [`recv`](Self::recv); a peer that then stays silent is
+    /// dropped with [`Error::KeepaliveTimeout`]. The peer's close is
+    /// answered before [`Handler::on_close`] is called.
+    ///
+    /// ```rust,ignore
+    /// struct Echo;
+    ///
+    /// impl<T: AsyncRead + AsyncWrite + Unpin + Send> Handler<T> for Echo {
+    ///     fn on_message<'a>(&'a mut self, conn: &'a mut Connection<T>, msg: Message)
+    ///         -> HandlerFuture<'a, Result<()>> {
+    ///         Box::pin(conn.send(msg))
+    ///     }
+    /// }
+    ///
+    /// conn.run(Echo).await?;
+    /// ```
+    ///
+    /// [`Config::keepalive`]: crate::Config::keepalive
+    ///
+    /// ## Errors
+    ///
+    /// The first fatal error receiving, [`Error::KeepaliveTimeout`], or an
+    /// error returned by the handler.
+    pub async fn run<H: Handler<T>>(mut self, mut handler: H) -> Result<()> {
+        loop {
+            let message = match self.recv().await {
+                Ok(Some(message)) => message,
+                Ok(None) => return Ok(()),
+                Err(e) => {
+                    handler.on_error(&e).await;
+                    if e.is_fatal() {
+                        return Err(e);
+                    }
+                    continue;
+                }
+            };
+
+            match message {
+                Message::Close(frame) => handler.on_close(frame.as_ref()).await,
+                Message::Ping(_) | Message::Pong(_) => {}
+                message => {
+                    if let Err(e) = handler.on_message(&mut self, message).await {
+                        let _ = self.close(CloseCode::InternalError, "").await;
+                        return Err(e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use super::*;
+    use crate::config::{Config, Keepalive};
+    use crate::connection::Role;
+
+    /// Echoes text, fails on binary and records what it saw.
+    #[derive(Default, Clone)]
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl Recorder {
+        fn push(&self, event: String) {
+            self.0.lock().unwrap().push(event);
+        }
+
+        fn events(&self) -> Vec<String> {
+            self.0.lock().unwrap().clone()
+        }
+    }
+
+    impl<T: AsyncRead + AsyncWrite + Unpin + Send> Handler<T> for Recorder {
+        fn on_message<'a>(
+            &'a mut self,
+            conn: &'a mut Connection<T>,
+            message: Message,
+        ) -> HandlerFuture<'a, Result<()>> {
+            Box::pin(async move {
+                match message {
+                    Message::Text(text) => conn.send(Message::text(text)).await,
+                    _ => Err(Error::ProtocolViolation("binary".into())),
+                }
+            })
+        }
+
+        fn on_close<'a>(&'a mut self, frame: Option<&'a CloseFrame>) -> HandlerFuture<'a, ()> {
+            self.push(format!("close {:?}", frame.map(|f| f.code)));
+            Box::pin(async {})
+        }
+
+        fn on_error<'a>(&'a mut self, error: &'a Error) -> HandlerFuture<'a, ()> {
+            self.push(format!("error {error}"));
+            Box::pin(async {})
+        }
+    }
+
+    fn pair(
+        config: Config,
+    ) -> (
+        Connection<tokio::io::DuplexStream>,
+        Connection<tokio::io::DuplexStream>,
+    ) {
+        let (ours, theirs) = tokio::io::duplex(4096);
+        (
+            Connection::new(ours, Role::Server, config),
+            Connection::new(theirs, Role::Client, Config::client()),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_run_echoes_until_close() {
+        let (conn, mut peer) = pair(Config::server());
+        let recorder = Recorder::default();
+        let server = tokio::spawn(conn.run(recorder.clone()));
+
+        peer.send(Message::text("hi")).await.unwrap();
+        assert_eq!(peer.recv().await.unwrap(), Some(Message::text("hi")));
+        peer.close(CloseCode::Normal, "").await.unwrap();
+        assert!(peer.recv().await.unwrap().is_some_and(|m| m.is_control()));
+
+        server.await.unwrap().unwrap();
+        assert_eq!(recorder.events(), ["close Some(Normal)"]);
+    }
+
+    #[tokio::test]
+    async fn test_handler_error_closes() {
+        let (conn, mut peer) = pair(Config::server());
+        let server = tokio::spawn(conn.run(Recorder::default()));
+
+        peer.send(Message::binary(vec![1])).await.unwrap();
+        assert_eq!(
+            peer.recv().await.unwrap(),
+            Some(Message::close(CloseCode::InternalError, ""))
+        );
+        assert!(server.await.unwrap().is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_keepalive_pings_then_gives_up() {
+        let keepalive = Keepalive::new(Duration::from_secs(30), Duration::from_secs(10));
+        let (conn, mut peer) = pair(Config::server().with_keepalive(keepalive));
+        let recorder = Recorder::default();
+        let server = tokio::spawn(conn.run(recorder.clone()));
+
+        // Answered pings keep the connection up
+        for _ in 0..3 {
+            assert!(matches!(peer.recv().await.unwrap(), Some(Message::Ping(_))));
+        }
+
+        // A peer that stops reading is dropped
+        let err = server.await.unwrap().unwrap_err();
+        assert_eq!(err, Error::KeepaliveTimeout(Duration::from_secs(10)));
+        assert_eq!(recorder.events(), ["error Keepalive timed out after 10s"]);
+    }
+}
@@ -0,0 +1,116 @@
+//! Frame-level middleware.
+
+use crate::protocol::Frame;
+
+/// What to do with a frame after an interceptor has seen it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum FrameAction {
+    /// Pass the (possibly modified) frame on.
+    Forward,
+    /// Discard the frame. Later interceptors do not see it.
+    Drop,
+}
+
+/// Inspects, rewrites or drops frames as they cross the wire.
+///
+/// Interceptors are registered per connection with
+/// [`Connection::add_interceptor`](crate::Connection::add_interceptor) and run
+/// in registration order. They see frames in wire form: outbound frames after
+/// extension encoding, inbound frames before validation, reassembly and
+/// extension decoding. Unlike extensions they take part in no negotiation and
+/// own no RSV bits, so they should leave `rsv1`..`rsv3` alone.
+///
+/// Dropping or rewriting control frames can break the protocol (e.g. a
+/// dropped close frame stalls the closing handshake); that is the point when
+/// injecting faults, but ordinary middleware should forward them unchanged.
+pub trait FrameInterceptor: Send {
+    /// Called for each frame about to be written.
+    fn outbound(&mut self, frame: &mut Frame) -> FrameAction {
+        let _ = frame;
+        FrameAction::Forward
+    }
+
+    /// Called for each frame read from the peer.
+    fn inbound(&mut self, frame: &mut Frame) -> FrameAction {
+        let _ = frame;
+        FrameAction::Forward
+    }
+}
+
+/// Run `frame` through a chain of interceptors in order.
+#[cfg_attr(not(feature = "async-tokio"), allow(dead_code))]
+pub(crate) fn run_chain<'a>(
+    chain: impl IntoIterator<Item = &'a mut Box<dyn FrameInterceptor>>,
+    frame: &mut Frame,
+    outbound: bool,
+) -> FrameAction {
+    for interceptor in chain {
+        let action = if outbound {
+            interceptor.outbound(frame)
+        } else {
+            interceptor.inbound(frame)
+        };
+        if action == FrameAction::Drop {
+            return FrameAction::Drop;
+        }
+    }
+    FrameAction::Forward
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::OpCode;
+
+    struct Upper;
+
+    impl FrameInterceptor for Upper {
+        fn outbound(&mut self, frame: &mut Frame) -> FrameAction {
+            let upper = frame.payload().to_ascii_uppercase();
+            *frame = Frame::new(frame.fin, frame.opcode, upper);
+            FrameAction::Forward
+        }
+    }
+
+    struct DropPings;
+
+    impl FrameInterceptor for DropPings {
+        fn inbound(&mut self, frame: &mut Frame) -> FrameAction {
+            if frame.opcode == OpCode::Ping {
+                FrameAction::Drop
+            } else {
+                FrameAction::Forward
+            }
+        }
+    }
+
+    #[test]
+    fn test_chain_rewrites_in_order() {
+        let mut chain: Vec<Box<dyn FrameInterceptor>> = vec![Box::new(Upper), Box::new(DropPings)];
+
+        let mut frame = Frame::text(b"hi".to_vec());
+        assert_eq!(
+            run_chain(&mut chain, &mut frame, true),
+            FrameAction::Forward
+        );
+        assert_eq!(frame.payload(), b"HI");
+
+        // Upper has no inbound hook, so inbound frames pass through untouched
+        let mut frame = Frame::text(b"hi".to_vec());
+        assert_eq!(
+            run_chain(&mut chain, &mut frame, false),
+            FrameAction::Forward
+        );
+        assert_eq!(frame.payload(), b"hi");
+    }
+
+    #[test]
+    fn test_chain_drop() {
+        let mut chain: Vec<Box<dyn FrameInterceptor>> = vec![Box::new(DropPings)];
+
+        let mut ping = Frame::new(true, OpCode::Ping, vec![]);
+        assert_eq!(run_chain(&mut chain, &mut ping, false), FrameAction::Drop);
+        assert_eq!(run_chain(&mut chain, &mut ping, true), FrameAction::Forward);
+    }
+}
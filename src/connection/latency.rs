@@ -0,0 +1,69 @@
+//! Round-trip latency sampling from ping/pong exchanges.
+
+use std::time::Duration;
+
+/// Round-trip time measured from answered pings.
+///
+/// The smoothed value uses the same exponentially weighted moving average as
+/// TCP's SRTT (RFC 6298): each new sample contributes 1/8.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Latency {
+    /// Most recent round-trip sample.
+    pub last: Duration,
+    /// Smoothed round-trip time.
+    pub smoothed: Duration,
+    /// Number of samples taken.
+    pub samples: u64,
+}
+
+// Samples are only taken by the tokio-backed `Connection`.
+#[cfg_attr(not(feature = "async-tokio"), allow(dead_code))]
+impl Latency {
+    /// Start an estimate from a first sample.
+    pub(crate) fn new(sample: Duration) -> Self {
+        Self {
+            last: sample,
+            smoothed: sample,
+            samples: 1,
+        }
+    }
+
+    /// Fold a new sample into the estimate.
+    pub(crate) fn update(&mut self, sample: Duration) {
+        self.last = sample;
+        self.smoothed = (self.smoothed * 7 + sample) / 8;
+        self.samples += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_first_sample() {
+        let latency = Latency::new(Duration::from_millis(40));
+        assert_eq!(latency.last, Duration::from_millis(40));
+        assert_eq!(latency.smoothed, Duration::from_millis(40));
+        assert_eq!(latency.samples, 1);
+    }
+
+    #[test]
+    fn test_smoothing() {
+        let mut latency = Latency::new(Duration::from_millis(80));
+        latency.update(Duration::from_millis(160));
+
+        assert_eq!(latency.last, Duration::from_millis(160));
+        assert_eq!(latency.smoothed, Duration::from_millis(90));
+        assert_eq!(latency.samples, 2);
+    }
+
+    #[test]
+    fn test_smoothing_converges() {
+        let mut latency = Latency::new(Duration::from_millis(500));
+        for _ in 0..100 {
+            latency.update(Duration::from_millis(20));
+        }
+        assert!(latency.smoothed < Duration::from_millis(21));
+    }
+}
@@ -26,13 +26,18 @@
 //! conn.close(CloseCode::Normal, "done").await?;
 //! ```
 
+mod interceptor;
+mod latency;
+mod observer;
 mod role;
 mod state;
 
+pub use interceptor::{FrameAction, FrameInterceptor};
+pub use latency::Latency;
+pub use observer::{ConnectionEvent, ConnectionObserver, Observer};
 pub use role::Role;
 pub use state::ConnectionState;
 
-#[cfg(feature = "async-tokio")]
 mod fragmenter;
 
 #[cfg(feature = "async-tokio")]
@@ -40,7 +45,38 @@ mod fragmenter;
 mod connection;
 
 #[cfg(feature = "async-tokio")]
-pub use connection::Connection;
+mod queue;
+
+#[cfg(feature = "async-tokio")]
+mod rate;
+
+#[cfg(feature = "async-tokio")]
+mod spill;
 
 #[cfg(feature = "async-tokio")]
-pub use fragmenter::MessageFragmenter;
+mod adapters;
+
+#[cfg(feature = "async-tokio")]
+mod close_on_drop;
+
+#[cfg(feature = "async-tokio")]
+mod handle;
+
+#[cfg(feature = "async-tokio")]
+mod handler;
+
+#[cfg(feature = "async-tokio")]
+pub use adapters::MessageSink;
+#[cfg(feature = "async-tokio")]
+pub use close_on_drop::CloseOnDrop;
+#[cfg(feature = "async-tokio")]
+pub use connection::{Connection, ConnectionParts};
+#[cfg(feature = "async-tokio")]
+pub use handle::WsHandle;
+#[cfg(feature = "async-tokio")]
+pub use handler::{Handler, HandlerFuture};
+#[cfg(feature = "async-tokio")]
+pub use queue::Priority;
+
+pub(crate) use fragmenter::fragment_len;
+pub use fragmenter::{FixedSize, Fragmenter, MessageFragmenter, NeverFragment, Ramp};
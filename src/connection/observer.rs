@@ -0,0 +1,115 @@
+//! Connection lifecycle observation.
+
+use std::fmt;
+use std::sync::Arc;
+
+use crate::connection::Role;
+use crate::error::Error;
+use crate::message::CloseFrame;
+
+/// A notable point in a connection's lifetime, passed to a
+/// [`ConnectionObserver`].
+///
+/// Payloads are borrowed from the connection; copy what you need to keep.
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+pub enum ConnectionEvent<'a> {
+    /// The connection was created on top of a completed handshake.
+    HandshakeComplete {
+        /// The local endpoint's role.
+        role: Role,
+    },
+    /// A ping was written to the peer.
+    PingSent(&'a [u8]),
+    /// A ping arrived from the peer.
+    PingReceived(&'a [u8]),
+    /// A pong was written to the peer, including automatic replies.
+    PongSent(&'a [u8]),
+    /// A pong arrived from the peer.
+    PongReceived(&'a [u8]),
+    /// This endpoint started the close handshake.
+    CloseInitiated(&'a CloseFrame),
+    /// The peer sent a close frame. `None` if it carried no status code.
+    CloseReceived(Option<&'a CloseFrame>),
+    /// The connection was torn down with
+    /// [`Connection::abort`](crate::Connection::abort).
+    Aborted,
+    /// A text message had invalid UTF-8 replaced with U+FFFD, as allowed by
+    /// [`CompatMode::lossy_utf8`](crate::config::CompatMode::lossy_utf8).
+    InvalidUtf8Replaced,
+    /// A send, receive or flush returned an error.
+    Error(&'a Error),
+}
+
+/// Receives [`ConnectionEvent`]s from every connection built with the
+/// [`Config`](crate::Config) it is installed in.
+///
+/// Observers run inline on the connection's task and should return quickly.
+/// Closures of the form `Fn(u64, &ConnectionEvent)` implement this trait.
+pub trait ConnectionObserver: Send + Sync {
+    /// Called for each event. `id` is the connection's
+    /// [`Connection::id`](crate::Connection::id).
+    fn on_event(&self, id: u64, event: &ConnectionEvent<'_>);
+}
+
+impl<F> ConnectionObserver for F
+where
+    F: Fn(u64, &ConnectionEvent<'_>) + Send + Sync,
+{
+    fn on_event(&self, id: u64, event: &ConnectionEvent<'_>) {
+        self(id, event)
+    }
+}
+
+/// A shared, cloneable handle to a [`ConnectionObserver`].
+#[derive(Clone)]
+pub struct Observer(Arc<dyn ConnectionObserver>);
+
+impl Observer {
+    /// Wrap an observer so it can be shared between configurations.
+    pub fn new(observer: impl ConnectionObserver + 'static) -> Self {
+        Self(Arc::new(observer))
+    }
+
+    /// Deliver an event to the wrapped observer.
+    pub fn on_event(&self, id: u64, event: &ConnectionEvent<'_>) {
+        self.0.on_event(id, event);
+    }
+}
+
+impl fmt::Debug for Observer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Observer(..)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_closure_observer() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&seen);
+        let observer = Observer::new(move |id: u64, event: &ConnectionEvent<'_>| {
+            sink.lock().unwrap().push((id, format!("{:?}", event)));
+        });
+
+        observer.on_event(7, &ConnectionEvent::PingSent(b"hi"));
+        observer
+            .clone()
+            .on_event(8, &ConnectionEvent::PongReceived(b"hi"));
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 2);
+        assert_eq!(seen[0].0, 7);
+        assert!(seen[1].1.starts_with("PongReceived"));
+    }
+
+    #[test]
+    fn test_observer_debug() {
+        let observer = Observer::new(|_: u64, _: &ConnectionEvent<'_>| {});
+        assert_eq!(format!("{:?}", observer), "Observer(..)");
+    }
+}
@@ -0,0 +1,278 @@
+//! Prioritised outgoing message queue.
+
+use std::collections::VecDeque;
+use std::task::{Context, Poll, ready};
+
+use bytes::Bytes;
+
+use crate::config::Config;
+use crate::connection::spill::SpillReader;
+use crate::error::Result;
+use crate::message::{Message, MessageOptions};
+use crate::protocol::{Frame, OpCode, SpilledMessage};
+
+/// Lane for a queued data message.
+///
+/// Control messages (ping, pong, close) always go first, even between the
+/// fragments of a data message. A data message cannot be interleaved with
+/// another one (RFC 6455 Section 5.4), so a `High` message waits for the
+/// message currently on the wire to finish, but overtakes every queued
+/// `Normal` one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Priority {
+    /// Sent before any queued `Normal` message.
+    High,
+    /// Sent in order after `High` messages.
+    #[default]
+    Normal,
+}
+
+/// Where the payload of an outgoing message comes from.
+enum Body {
+    Bytes(Bytes),
+    /// A spilled payload, read one fragment at a time.
+    Reader(SpillReader),
+}
+
+/// A data message being written out fragment by fragment.
+pub(crate) struct Outgoing {
+    body: Body,
+    len: usize,
+    opcode: OpCode,
+    offset: usize,
+    options: MessageOptions,
+    started: bool,
+}
+
+impl Outgoing {
+    pub(crate) fn new(message: Message, mut options: MessageOptions) -> Self {
+        let len = message.len();
+        let (body, opcode) = match message {
+            Message::Text(text) => (Body::Bytes(Bytes::from(text.into_bytes())), OpCode::Text),
+            Message::Binary(data) => (Body::Bytes(data), OpCode::Binary),
+            Message::Spilled(spilled) => {
+                // Extensions only see the first fragment, so a streamed
+                // payload is never compressed
+                options.compress = false;
+                let opcode = spilled.opcode();
+                (Body::Reader(SpillReader::new(spilled)), opcode)
+            }
+            _ => unreachable!("control messages are never fragmented"),
+        };
+        Self {
+            body,
+            len,
+            opcode,
+            offset: 0,
+            options,
+            started: false,
+        }
+    }
+
+    pub(crate) fn options(&self) -> MessageOptions {
+        self.options
+    }
+
+    /// The next frame, sized by the configured fragmenter, and whether it is
+    /// the first of the message.
+    ///
+    /// A spilled payload is read on the blocking thread pool, returning
+    /// `Pending` until the fragment is in; an in-memory one is always ready.
+    pub(crate) fn poll_next_frame(
+        &mut self,
+        cx: &mut Context<'_>,
+        config: &Config,
+    ) -> Poll<Result<Option<(Frame, bool)>>> {
+        if self.is_done() {
+            return Poll::Ready(Ok(None));
+        }
+
+        let len = self.len;
+        let end = len.min(self.offset + config.fragment_len(self.opcode, len, self.offset));
+        let chunk = match &mut self.body {
+            Body::Bytes(payload) => payload.slice(self.offset..end),
+            Body::Reader(reader) => ready!(reader.poll_read(cx, end - self.offset))?,
+        };
+        let fin = end == len;
+        let first = !self.started;
+        let opcode = if first {
+            self.opcode
+        } else {
+            OpCode::Continuation
+        };
+
+        self.offset = end;
+        self.started = true;
+        Poll::Ready(Ok(Some((Frame::new_from_bytes(fin, opcode, chunk), first))))
+    }
+
+    /// Whether the payload is in memory, so fragments share it rather
+    /// than being read one at a time.
+    pub(crate) fn is_in_memory(&self) -> bool {
+        matches!(self.body, Body::Bytes(_))
+    }
+
+    /// Whether this is the payload of `spilled` being read.
+    pub(crate) fn is_reading(&self, spilled: &SpilledMessage) -> bool {
+        matches!(&self.body, Body::Reader(reader) if reader.message() == spilled)
+    }
+
+    pub(crate) fn is_done(&self) -> bool {
+        self.started && self.offset >= self.len
+    }
+}
+
+/// What to write next.
+pub(crate) enum Next {
+    Control(Message),
+    Data(Message, MessageOptions),
+}
+
+/// Messages waiting to be written, by lane, plus the data message whose
+/// fragments are being written.
+#[derive(Default)]
+pub(crate) struct SendQueue {
+    control: VecDeque<Message>,
+    high: VecDeque<(Message, MessageOptions)>,
+    normal: VecDeque<(Message, MessageOptions)>,
+    /// Payload bytes of the queued data messages.
+    bytes: usize,
+    pub(crate) current: Option<Outgoing>,
+}
+
+impl SendQueue {
+    pub(crate) fn push(&mut self, message: Message, priority: Priority, options: MessageOptions) {
+        if message.is_control() {
+            self.control.push_back(message);
+            return;
+        }
+        self.bytes += message.len();
+        match priority {
+            Priority::High => self.high.push_back((message, options)),
+            Priority::Normal => self.normal.push_back((message, options)),
+        }
+    }
+
+    /// Queued messages, not counting the one in progress.
+    pub(crate) fn len(&self) -> usize {
+        self.control.len() + self.high.len() + self.normal.len()
+    }
+
+    /// Payload bytes of the queued data messages, not counting the one in
+    /// progress.
+    pub(crate) fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Drop the oldest queued data messages, `Normal` before `High`, until
+    /// at most `max_bytes` are queued. Returns how many were dropped.
+    pub(crate) fn drop_oldest(&mut self, max_bytes: usize) -> usize {
+        let mut dropped = 0;
+        while self.bytes > max_bytes {
+            let Some((message, _)) = self.normal.pop_front().or_else(|| self.high.pop_front())
+            else {
+                break;
+            };
+            self.bytes -= message.len();
+            dropped += 1;
+        }
+        dropped
+    }
+
+    /// The next whole message to start, if nothing must be finished first.
+    ///
+    /// Returns `None` while a data message is in progress and no control
+    /// message is waiting.
+    pub(crate) fn pop(&mut self) -> Option<Next> {
+        if let Some(message) = self.control.pop_front() {
+            return Some(Next::Control(message));
+        }
+        if self.current.is_some() {
+            return None;
+        }
+        let (message, options) = self.high.pop_front().or_else(|| self.normal.pop_front())?;
+        self.bytes -= message.len();
+        Some(Next::Data(message, options))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frames(mut outgoing: Outgoing, fragment_size: usize) -> Vec<Frame> {
+        let config = Config::default().with_fragment_size(fragment_size);
+        let mut cx = Context::from_waker(std::task::Waker::noop());
+        std::iter::from_fn(|| match outgoing.poll_next_frame(&mut cx, &config) {
+            Poll::Ready(next) => next.unwrap().map(|(f, _)| f),
+            Poll::Pending => panic!("in-memory payloads are always ready"),
+        })
+        .collect()
+    }
+
+    #[test]
+    fn test_outgoing_fragments() {
+        let frames = frames(
+            Outgoing::new(Message::text("abcdefg"), MessageOptions::default()),
+            3,
+        );
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[0].opcode, OpCode::Text);
+        assert_eq!(frames[1].opcode, OpCode::Continuation);
+        assert_eq!(frames[2].payload(), b"g");
+        assert!(frames[2].fin && !frames[0].fin);
+    }
+
+    #[test]
+    fn test_outgoing_empty_message() {
+        let frames = frames(
+            Outgoing::new(Message::binary(Vec::new()), MessageOptions::default()),
+            16,
+        );
+        assert_eq!(frames.len(), 1);
+        assert!(frames[0].fin);
+        assert_eq!(frames[0].opcode, OpCode::Binary);
+    }
+
+    #[test]
+    fn test_queue_order() {
+        let mut queue = SendQueue::default();
+        let options = MessageOptions::default();
+        queue.push(Message::text("normal"), Priority::Normal, options);
+        queue.push(Message::text("high"), Priority::High, options);
+        queue.push(Message::Ping(Bytes::new()), Priority::Normal, options);
+        assert_eq!(queue.len(), 3);
+
+        assert!(matches!(queue.pop(), Some(Next::Control(Message::Ping(_)))));
+        assert!(matches!(queue.pop(), Some(Next::Data(m, _)) if m.as_text() == Some("high")));
+
+        // A message in progress blocks data, but not control
+        queue.current = Some(Outgoing::new(Message::text("x"), options));
+        assert!(queue.pop().is_none());
+        queue.push(Message::Pong(Bytes::new()), Priority::Normal, options);
+        assert!(matches!(queue.pop(), Some(Next::Control(_))));
+
+        queue.current = None;
+        assert!(matches!(queue.pop(), Some(Next::Data(m, _)) if m.as_text() == Some("normal")));
+        assert!(queue.pop().is_none());
+    }
+
+    #[test]
+    fn test_drop_oldest() {
+        let mut queue = SendQueue::default();
+        let options = MessageOptions::default();
+        queue.push(Message::text("high"), Priority::High, options);
+        queue.push(Message::text("old"), Priority::Normal, options);
+        queue.push(Message::text("new"), Priority::Normal, options);
+        queue.push(Message::Ping(Bytes::new()), Priority::Normal, options);
+        assert_eq!(queue.bytes(), 10);
+
+        assert_eq!(queue.drop_oldest(7), 1);
+        assert_eq!(queue.bytes(), 7);
+        assert!(matches!(queue.pop(), Some(Next::Control(_))));
+        assert!(matches!(queue.pop(), Some(Next::Data(m, _)) if m.as_text() == Some("high")));
+        assert!(matches!(queue.pop(), Some(Next::Data(m, _)) if m.as_text() == Some("new")));
+        assert_eq!(queue.bytes(), 0);
+    }
+}
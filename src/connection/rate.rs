@@ -0,0 +1,89 @@
+//! Enforcement of `Config::inbound_rate`.
+
+use tokio::time::Instant;
+
+use crate::config::InboundRate;
+use crate::error::{Error, Result};
+
+/// Counts what the peer sent in the current window.
+#[derive(Debug)]
+pub(crate) struct RateMeter {
+    rate: InboundRate,
+    /// When the current window started, `None` before the first frame.
+    start: Option<Instant>,
+    messages: usize,
+    bytes: usize,
+}
+
+impl RateMeter {
+    pub(crate) fn new(rate: InboundRate) -> Self {
+        Self {
+            rate,
+            start: None,
+            messages: 0,
+            bytes: 0,
+        }
+    }
+
+    /// Count a received frame of `len` payload bytes, ending a message if
+    /// `fin` is set.
+    pub(crate) fn record(&mut self, fin: bool, len: usize) -> Result<()> {
+        let now = Instant::now();
+        if self
+            .start
+            .is_none_or(|start| now.duration_since(start) >= self.rate.window)
+        {
+            self.start = Some(now);
+            self.messages = 0;
+            self.bytes = 0;
+        }
+
+        self.bytes = self.bytes.saturating_add(len);
+        if self.bytes > self.rate.max_bytes {
+            return Err(self.exceeded("bytes", self.rate.max_bytes));
+        }
+        self.messages += usize::from(fin);
+        if self.messages > self.rate.max_messages {
+            return Err(self.exceeded("messages", self.rate.max_messages));
+        }
+        Ok(())
+    }
+
+    fn exceeded(&self, what: &'static str, max: usize) -> Error {
+        Error::InboundRateExceeded {
+            what,
+            max,
+            window: self.rate.window,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_limits_reset_each_window() {
+        let mut meter = RateMeter::new(InboundRate::per_second(2, 100));
+        meter.record(false, 10).unwrap();
+        meter.record(true, 10).unwrap();
+        meter.record(true, 10).unwrap();
+        assert_eq!(
+            meter.record(true, 10),
+            Err(Error::InboundRateExceeded {
+                what: "messages",
+                max: 2,
+                window: Duration::from_secs(1),
+            })
+        );
+
+        tokio::time::advance(Duration::from_secs(1)).await;
+        meter.record(true, 100).unwrap();
+        assert!(matches!(
+            meter.record(false, 1),
+            Err(Error::InboundRateExceeded { what: "bytes", .. })
+        ));
+    }
+}
@@ -14,7 +14,10 @@ pub enum Role {
 impl Role {
     /// Check if this role must mask outgoing frames.
     ///
-    /// Clients must mask all frames sent to servers.
+    /// Clients must mask all frames sent to servers. [`Config::masking`]
+    /// can override this for testing.
+    ///
+    /// [`Config::masking`]: crate::Config::masking
     #[inline]
     #[must_use]
     pub const fn must_mask(&self) -> bool {
@@ -71,6 +74,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::clone_on_copy)]
     fn test_role_clone_and_copy() {
         let role = Role::Client;
         let cloned = role.clone();
@@ -0,0 +1,135 @@
+//! Spill I/O on tokio's blocking thread pool.
+//!
+//! [`SpillSink`](crate::protocol::SpillSink) and
+//! [`SpilledPayload`](crate::protocol::SpilledPayload) are blocking, so
+//! the connection hands every write and read to a blocking thread and
+//! polls for the result instead of calling them on its own task.
+
+use std::future::Future;
+use std::io::{self, Read};
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+
+use bytes::Bytes;
+use tokio::task::{JoinHandle, spawn_blocking};
+
+use crate::protocol::SpilledMessage;
+use crate::protocol::spill::{SpillChunk, SpillStep, SpillWriter, joined};
+
+/// The incoming message being spilled, if any.
+#[derive(Default)]
+pub(crate) enum SpillTask {
+    #[default]
+    Idle,
+    /// Waiting for the next chunk.
+    Open(Box<dyn SpillWriter>),
+    /// A chunk is being written.
+    ///
+    /// Dropping the handle leaves the write to finish on its own; the
+    /// writer, or the finished message, is then dropped there, which
+    /// abandons the message.
+    Writing(JoinHandle<io::Result<SpillStep>>),
+}
+
+impl SpillTask {
+    /// Start writing `chunk` to the message's writer.
+    pub(crate) fn start(&mut self, chunk: SpillChunk) {
+        let writer = match std::mem::take(self) {
+            Self::Open(writer) => Some(writer),
+            _ => None,
+        };
+        *self = Self::Writing(spawn_blocking(move || chunk.write(writer)));
+    }
+
+    /// Wait for the chunk being written, if any. Returns the message once
+    /// its last chunk is written.
+    pub(crate) fn poll(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<Option<SpilledMessage>>> {
+        let Self::Writing(handle) = self else {
+            return Poll::Ready(Ok(None));
+        };
+        let result = ready!(Pin::new(handle).poll(cx));
+        *self = Self::Idle;
+        match joined(result)? {
+            SpillStep::Open(writer) => {
+                *self = Self::Open(writer);
+                Poll::Ready(Ok(None))
+            }
+            SpillStep::Done(spilled) => Poll::Ready(Ok(Some(spilled))),
+        }
+    }
+}
+
+/// Reads a spilled payload being sent, one fragment at a time.
+pub(crate) struct SpillReader {
+    message: SpilledMessage,
+    state: ReadState,
+}
+
+type ReadResult = io::Result<(Box<dyn Read + Send>, Vec<u8>)>;
+
+enum ReadState {
+    /// The payload is opened with the first read.
+    Unopened,
+    Ready(Box<dyn Read + Send>),
+    Reading(JoinHandle<ReadResult>),
+    /// A read failed, leaving the position unknown.
+    Failed,
+}
+
+impl SpillReader {
+    pub(crate) fn new(message: SpilledMessage) -> Self {
+        Self {
+            message,
+            state: ReadState::Unopened,
+        }
+    }
+
+    /// The message being read.
+    pub(crate) fn message(&self) -> &SpilledMessage {
+        &self.message
+    }
+
+    /// Read the next `len` bytes.
+    pub(crate) fn poll_read(
+        &mut self,
+        cx: &mut Context<'_>,
+        len: usize,
+    ) -> Poll<io::Result<Bytes>> {
+        let reader = match std::mem::replace(&mut self.state, ReadState::Failed) {
+            ReadState::Unopened => None,
+            ReadState::Ready(reader) => Some(reader),
+            ReadState::Reading(handle) => {
+                self.state = ReadState::Reading(handle);
+                return self.poll_reading(cx);
+            }
+            ReadState::Failed => {
+                return Poll::Ready(Err(io::Error::other("spilled payload failed to read")));
+            }
+        };
+        let message = self.message.clone();
+        self.state = ReadState::Reading(spawn_blocking(move || {
+            let mut reader = match reader {
+                Some(reader) => reader,
+                None => message.open()?,
+            };
+            let mut chunk = vec![0u8; len];
+            reader.read_exact(&mut chunk)?;
+            Ok((reader, chunk))
+        }));
+        self.poll_reading(cx)
+    }
+
+    fn poll_reading(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<Bytes>> {
+        let ReadState::Reading(handle) = &mut self.state else {
+            return Poll::Ready(Err(io::Error::other("no read in progress")));
+        };
+        let result = ready!(Pin::new(handle).poll(cx));
+        self.state = ReadState::Failed;
+        let (reader, chunk) = joined(result)?;
+        self.state = ReadState::Ready(reader);
+        Poll::Ready(Ok(Bytes::from(chunk)))
+    }
+}
@@ -3,10 +3,148 @@
 //! This module defines all error conditions that can occur during WebSocket
 //! operations, following RFC 6455 requirements.
 
+use alloc::string::String;
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::fmt;
+#[cfg(feature = "std")]
+use std::io;
+#[cfg(feature = "std")]
+use std::ops::Deref;
+#[cfg(feature = "std")]
+use std::sync::Arc;
+
 use thiserror::Error;
 
+#[cfg(feature = "std")]
+use crate::config::ConfigError;
+#[cfg(feature = "std")]
+use crate::message::CloseCode;
+use crate::protocol::OpCode;
+
 /// Result type alias for WebSocket operations.
-pub type Result<T> = std::result::Result<T, Error>;
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// Broad category of an [`enum@Error`].
+///
+/// Kinds are stable across releases even as individual variants are added,
+/// so match on these rather than on the variants themselves where possible.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// The transport failed: reset, refused, timed out, EOF.
+    Io,
+    /// The peer broke RFC 6455 framing or message rules.
+    Protocol,
+    /// The opening HTTP handshake was invalid or refused.
+    Handshake,
+    /// A size or count limit was exceeded.
+    Capacity,
+    /// The TLS layer failed, usually during its handshake.
+    Tls,
+    /// An extension failed to negotiate, encode or decode.
+    Extension,
+    /// The connection was already closed.
+    Closed,
+    /// The caller passed an invalid URL or configuration.
+    Config,
+    /// A service handling messages failed.
+    Service,
+    /// A message could not be encoded or decoded, or was not of the kind
+    /// expected.
+    Payload,
+}
+
+/// The underlying error of an I/O or TLS [`enum@Error`], returned from its
+/// [`source`](std::error::Error::source).
+///
+/// Shared so that `Error` stays `Clone`. Two sources compare equal when their
+/// messages do.
+#[cfg(feature = "std")]
+#[derive(Clone)]
+pub struct Source(Arc<dyn std::error::Error + Send + Sync>);
+
+#[cfg(feature = "std")]
+impl Source {
+    /// Wrap an error.
+    pub fn new(err: impl std::error::Error + Send + Sync + 'static) -> Self {
+        Self(Arc::new(err))
+    }
+
+    /// The first `io::Error` in this error's chain, if any.
+    #[must_use]
+    pub fn io_error(&self) -> Option<&io::Error> {
+        let mut err: Option<&(dyn std::error::Error + 'static)> = Some(self.0.as_ref());
+        while let Some(e) = err {
+            if let Some(io) = e.downcast_ref::<io::Error>() {
+                return Some(io);
+            }
+            err = e.source();
+        }
+        None
+    }
+}
+
+#[cfg(feature = "std")]
+impl Deref for Source {
+    type Target = dyn std::error::Error + Send + Sync;
+
+    fn deref(&self) -> &Self::Target {
+        self.0.as_ref()
+    }
+}
+
+#[cfg(feature = "std")]
+impl fmt::Debug for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.0, f)
+    }
+}
+
+#[cfg(feature = "std")]
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+#[cfg(feature = "std")]
+impl PartialEq for Source {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0) || self.0.to_string() == other.0.to_string()
+    }
+}
+
+#[cfg(feature = "std")]
+impl Eq for Source {}
+
+#[cfg(feature = "std")]
+impl From<io::Error> for Source {
+    fn from(err: io::Error) -> Self {
+        Self::new(err)
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<Box<dyn std::error::Error + Send + Sync>> for Source {
+    fn from(err: Box<dyn std::error::Error + Send + Sync>) -> Self {
+        Self(Arc::from(err))
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<String> for Source {
+    fn from(msg: String) -> Self {
+        Self::new(io::Error::other(msg))
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<&str> for Source {
+    fn from(msg: &str) -> Self {
+        Self::from(msg.to_owned())
+    }
+}
 
 /// Errors that can occur during WebSocket operations.
 #[derive(Error, Debug, Clone, PartialEq, Eq)]
@@ -60,8 +198,14 @@ pub enum Error {
     InvalidHandshake(String),
 
     /// I/O error occurred.
+    #[cfg(feature = "std")]
     #[error("I/O error: {0}")]
-    Io(String),
+    Io(#[source] Source),
+
+    /// TLS error, e.g. a failed TLS handshake.
+    #[cfg(feature = "std")]
+    #[error("TLS error: {0}")]
+    Tls(#[source] Source),
 
     /// Extension-related error.
     #[error("Extension error: {0}")]
@@ -135,6 +279,28 @@ pub enum Error {
         origin: String,
     },
 
+    /// Host not in allowed list (DNS rebinding protection).
+    #[error("Host not allowed: {host}")]
+    HostNotAllowed {
+        /// The rejected Host header value.
+        host: String,
+    },
+
+    /// The server selected a subprotocol the client did not offer.
+    #[error("Server selected a subprotocol that was not offered: {0}")]
+    UnexpectedSubprotocol(String),
+
+    /// The client required a subprotocol and the server selected none.
+    #[error("Server selected no subprotocol")]
+    MissingSubprotocol,
+
+    /// Too many pings sent without a matching pong.
+    #[error("Too many pending pings (max: {max})")]
+    TooManyPendingPings {
+        /// Maximum allowed unanswered pings.
+        max: usize,
+    },
+
     /// Handshake data too large (DoS protection).
     #[error("Handshake too large: {size} bytes (max: {max})")]
     HandshakeTooLarge {
@@ -143,16 +309,247 @@ pub enum Error {
         /// Maximum allowed size.
         max: usize,
     },
+
+    /// A WebSocket URL could not be used.
+    #[error("Invalid URL: {0}")]
+    InvalidUrl(String),
+
+    /// A configuration failed validation.
+    #[cfg(feature = "std")]
+    #[error("Invalid configuration: {0}")]
+    InvalidConfig(#[from] ConfigError),
+
+    /// A write did not finish within its deadline. Part of a frame may
+    /// have been written, so the connection was closed.
+    #[error("Write timed out after {0:?}")]
+    WriteTimeout(Duration),
+
+    /// The peer sent nothing within the keepalive timeout after a ping.
+    #[error("Keepalive timed out after {0:?}")]
+    KeepaliveTimeout(Duration),
+
+    /// No message arrived within the timeout given to
+    /// `Connection::recv_timeout`. Nothing received so far was lost and the
+    /// connection stays usable.
+    #[error("No message received within {0:?}")]
+    RecvTimeout(Duration),
+
+    /// The peer sent more than `Config::inbound_rate` allows.
+    #[error("Inbound rate exceeded: more than {max} {what} per {window:?}")]
+    InboundRateExceeded {
+        /// What went over its limit: `"messages"` or `"bytes"`.
+        what: &'static str,
+        /// The limit per window.
+        max: usize,
+        /// Length of the window.
+        window: Duration,
+    },
+
+    /// The peer stopped reading and the connection was closed under
+    /// `Config::slow_consumer`.
+    #[error("Slow consumer: send queue over {max_bytes} bytes for {grace:?}")]
+    SlowConsumer {
+        /// The queue limit in bytes.
+        max_bytes: usize,
+        /// How long the queue was allowed to stay over it.
+        grace: Duration,
+    },
+
+    /// The opening handshake did not finish within `timeouts.handshake`.
+    #[error("Handshake timed out after {0:?}")]
+    HandshakeTimeout(Duration),
+
+    /// A value could not be encoded as a message.
+    #[cfg(feature = "std")]
+    #[error("Encode error: {0}")]
+    Encode(#[source] Source),
+
+    /// A message did not decode as the expected type.
+    #[cfg(feature = "std")]
+    #[error("Decode error: {0}")]
+    Decode(#[source] Source),
+
+    /// A message of another kind arrived than the one asked for, e.g.
+    /// binary from `recv_text`. It was consumed; the connection stays
+    /// usable.
+    #[error("Expected a {expected:?} message, got {got:?}")]
+    UnexpectedMessage {
+        /// The kind asked for.
+        expected: OpCode,
+        /// The kind received.
+        got: OpCode,
+    },
+
+    /// A service serving the connection failed or panicked.
+    #[cfg(feature = "std")]
+    #[error("Service error: {0}")]
+    Service(#[source] Source),
+}
+
+impl Error {
+    /// The category this error belongs to.
+    #[must_use]
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            #[cfg(feature = "std")]
+            Error::Io(_) => ErrorKind::Io,
+            Error::WriteTimeout(_)
+            | Error::HandshakeTimeout(_)
+            | Error::KeepaliveTimeout(_)
+            | Error::RecvTimeout(_) => ErrorKind::Io,
+            #[cfg(feature = "std")]
+            Error::Tls(_) => ErrorKind::Tls,
+            Error::InvalidFrame(_)
+            | Error::ProtocolViolation(_)
+            | Error::InvalidUtf8
+            | Error::InvalidCloseCode(_)
+            | Error::ReservedOpcode(_)
+            | Error::FragmentedControlFrame
+            | Error::ControlFrameTooLarge(_)
+            | Error::UnmaskedClientFrame
+            | Error::MaskedServerFrame
+            | Error::ReservedBitsSet
+            | Error::IncompleteFrame { .. }
+            | Error::InvalidOpcode(_) => ErrorKind::Protocol,
+            Error::FrameTooLarge { .. }
+            | Error::MessageTooLarge { .. }
+            | Error::TooManyFragments { .. }
+            | Error::PayloadTooLargeForPlatform { .. }
+            | Error::HandshakeTooLarge { .. }
+            | Error::TooManyPendingPings { .. }
+            | Error::InboundRateExceeded { .. }
+            | Error::SlowConsumer { .. } => ErrorKind::Capacity,
+            Error::InvalidHandshake(_)
+            | Error::InvalidHeaderValue { .. }
+            | Error::OriginNotAllowed { .. }
+            | Error::HostNotAllowed { .. }
+            | Error::UnexpectedSubprotocol(_)
+            | Error::MissingSubprotocol => ErrorKind::Handshake,
+            Error::Extension(_) | Error::InvalidExtension(_) => ErrorKind::Extension,
+            Error::ConnectionClosed(_) => ErrorKind::Closed,
+            Error::InvalidUrl(_) => ErrorKind::Config,
+            #[cfg(feature = "std")]
+            Error::InvalidConfig(_) => ErrorKind::Config,
+            #[cfg(feature = "std")]
+            Error::Service(_) => ErrorKind::Service,
+            Error::UnexpectedMessage { .. } => ErrorKind::Payload,
+            #[cfg(feature = "std")]
+            Error::Encode(_) | Error::Decode(_) => ErrorKind::Payload,
+        }
+    }
+
+    /// The underlying `io::Error`, for I/O errors and TLS errors caused by
+    /// one.
+    #[cfg(feature = "std")]
+    #[must_use]
+    pub fn io_error(&self) -> Option<&io::Error> {
+        match self {
+            Error::Io(source) | Error::Tls(source) => source.io_error(),
+            _ => None,
+        }
+    }
+
+    /// Whether the connection is unusable after this error.
+    ///
+    /// Only errors that reject a call's arguments before anything reaches
+    /// the wire leave the connection usable: an invalid close code or header
+    /// value, a refused ping, a bad URL or configuration, and a
+    /// [`RecvTimeout`](Self::RecvTimeout), which only gives up waiting. Size limits are
+    /// treated as fatal because the same errors fail incoming messages.
+    #[must_use]
+    pub fn is_fatal(&self) -> bool {
+        match self {
+            Error::InvalidCloseCode(_)
+            | Error::InvalidHeaderValue { .. }
+            | Error::TooManyPendingPings { .. }
+            | Error::UnexpectedMessage { .. }
+            | Error::RecvTimeout(_)
+            | Error::InvalidUrl(_) => false,
+            #[cfg(feature = "std")]
+            Error::InvalidConfig(_) | Error::Encode(_) | Error::Decode(_) => false,
+            _ => true,
+        }
+    }
+
+    /// Whether reconnecting (or retrying the handshake) may succeed.
+    ///
+    /// True for transient network failures (refused, reset, timed out,
+    /// unreachable), closed connections, write and handshake timeouts and a peer that
+    /// stopped answering pings. Protocol, handshake, capacity and configuration errors will
+    /// happen again and are not retryable.
+    #[must_use]
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::ConnectionClosed(_)
+            | Error::TooManyPendingPings { .. }
+            | Error::WriteTimeout(_)
+            | Error::HandshakeTimeout(_)
+            | Error::KeepaliveTimeout(_) => true,
+            #[cfg(feature = "std")]
+            Error::Io(_) | Error::Tls(_) => self.io_error().is_some_and(|err| {
+                matches!(
+                    err.kind(),
+                    io::ErrorKind::ConnectionRefused
+                        | io::ErrorKind::ConnectionReset
+                        | io::ErrorKind::ConnectionAborted
+                        | io::ErrorKind::NotConnected
+                        | io::ErrorKind::BrokenPipe
+                        | io::ErrorKind::TimedOut
+                        | io::ErrorKind::Interrupted
+                        | io::ErrorKind::WouldBlock
+                        | io::ErrorKind::UnexpectedEof
+                        | io::ErrorKind::HostUnreachable
+                        | io::ErrorKind::NetworkUnreachable
+                        | io::ErrorKind::NetworkDown
+                )
+            }),
+            _ => false,
+        }
+    }
+
+    /// The close code to fail the connection with after this error.
+    ///
+    /// RFC 6455 requires an endpoint that detects a protocol violation to
+    /// send a Close frame before dropping the connection: 1007 for invalid
+    /// UTF-8, 1009 for oversized messages and 1002 for other framing errors.
+    /// A peer over `Config::inbound_rate` is sent 1008. Returns `None` for
+    /// errors that are not the peer's fault or occur before/after the
+    /// WebSocket session (I/O, handshake, closed).
+    #[cfg(feature = "std")]
+    #[must_use]
+    pub fn close_code(&self) -> Option<CloseCode> {
+        match self {
+            Error::InvalidUtf8 => Some(CloseCode::InvalidPayload),
+            Error::FrameTooLarge { .. }
+            | Error::MessageTooLarge { .. }
+            | Error::TooManyFragments { .. }
+            | Error::PayloadTooLargeForPlatform { .. } => Some(CloseCode::MessageTooBig),
+            Error::InboundRateExceeded { .. } => Some(CloseCode::PolicyViolation),
+            Error::InvalidFrame(_)
+            | Error::ProtocolViolation(_)
+            | Error::Extension(_)
+            | Error::InvalidCloseCode(_)
+            | Error::ReservedOpcode(_)
+            | Error::FragmentedControlFrame
+            | Error::ControlFrameTooLarge(_)
+            | Error::UnmaskedClientFrame
+            | Error::MaskedServerFrame
+            | Error::ReservedBitsSet
+            | Error::InvalidOpcode(_) => Some(CloseCode::ProtocolError),
+            _ => None,
+        }
+    }
 }
 
-impl From<std::io::Error> for Error {
-    fn from(err: std::io::Error) -> Self {
-        Error::Io(err.to_string())
+#[cfg(feature = "std")]
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(Source::new(err))
     }
 }
 
-impl From<std::str::Utf8Error> for Error {
-    fn from(_: std::str::Utf8Error) -> Self {
+impl From<core::str::Utf8Error> for Error {
+    fn from(_: core::str::Utf8Error) -> Self {
         Error::InvalidUtf8
     }
 }
@@ -180,6 +577,105 @@ mod tests {
         assert!(matches!(ws_err, Error::Io(_)));
     }
 
+    #[test]
+    fn test_error_close_code() {
+        assert_eq!(
+            Error::InvalidUtf8.close_code(),
+            Some(CloseCode::InvalidPayload)
+        );
+        assert_eq!(
+            Error::MessageTooLarge { size: 2, max: 1 }.close_code(),
+            Some(CloseCode::MessageTooBig)
+        );
+        assert_eq!(
+            Error::ReservedBitsSet.close_code(),
+            Some(CloseCode::ProtocolError)
+        );
+        assert_eq!(
+            Error::ControlFrameTooLarge(126).close_code(),
+            Some(CloseCode::ProtocolError)
+        );
+        assert_eq!(Error::Io("reset".into()).close_code(), None);
+        assert_eq!(Error::ConnectionClosed(None).close_code(), None);
+        assert_eq!(Error::InvalidHandshake("bad".into()).close_code(), None);
+    }
+
+    #[test]
+    fn test_io_error_is_source() {
+        use std::error::Error as _;
+
+        let err = Error::from(io::Error::new(io::ErrorKind::ConnectionReset, "reset"));
+        let source = err.source().unwrap();
+        let io = source.downcast_ref::<io::Error>().unwrap();
+        assert_eq!(io.kind(), io::ErrorKind::ConnectionReset);
+        assert_eq!(
+            err.io_error().unwrap().kind(),
+            io::ErrorKind::ConnectionReset
+        );
+        assert_eq!(err.kind(), ErrorKind::Io);
+        assert_eq!(err.clone(), err);
+        assert!(Error::InvalidUtf8.source().is_none());
+    }
+
+    #[test]
+    fn test_tls_error_finds_io_cause() {
+        #[derive(Debug)]
+        struct Handshake(io::Error);
+
+        impl fmt::Display for Handshake {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("handshake failed")
+            }
+        }
+
+        impl std::error::Error for Handshake {
+            fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+                Some(&self.0)
+            }
+        }
+
+        let err = Error::Tls(Source::new(Handshake(io::ErrorKind::TimedOut.into())));
+        assert_eq!(err.kind(), ErrorKind::Tls);
+        assert_eq!(err.to_string(), "TLS error: handshake failed");
+        assert!(err.is_retryable());
+    }
+
+    #[test]
+    fn test_error_retryability() {
+        let refused = Error::from(io::Error::from(io::ErrorKind::ConnectionRefused));
+        assert!(refused.is_retryable());
+        assert!(refused.is_fatal());
+
+        let denied = Error::from(io::Error::from(io::ErrorKind::PermissionDenied));
+        assert!(!denied.is_retryable());
+        assert!(!Error::Io("reset".into()).is_retryable());
+
+        assert!(Error::ConnectionClosed(None).is_retryable());
+        assert!(Error::WriteTimeout(Duration::from_secs(1)).is_retryable());
+        assert!(Error::HandshakeTimeout(Duration::from_secs(1)).is_retryable());
+        assert!(!Error::InvalidUtf8.is_retryable());
+        assert!(!Error::InvalidHandshake("bad".into()).is_retryable());
+
+        assert!(!Error::InvalidCloseCode(1005).is_fatal());
+        assert!(!Error::InvalidUrl("ftp://x".into()).is_fatal());
+        assert!(Error::ReservedBitsSet.is_fatal());
+    }
+
+    #[test]
+    fn test_error_kinds() {
+        assert_eq!(Error::ReservedBitsSet.kind(), ErrorKind::Protocol);
+        assert_eq!(
+            Error::MessageTooLarge { size: 2, max: 1 }.kind(),
+            ErrorKind::Capacity
+        );
+        assert_eq!(
+            Error::OriginNotAllowed { origin: "x".into() }.kind(),
+            ErrorKind::Handshake
+        );
+        assert_eq!(Error::Extension("x".into()).kind(), ErrorKind::Extension);
+        assert_eq!(Error::ConnectionClosed(None).kind(), ErrorKind::Closed);
+    }
+
     #[test]
     fn test_error_clone() {
         let err = Error::InvalidUtf8;
@@ -209,6 +705,12 @@ mod tests {
         };
         assert!(err.to_string().contains("evil.com"));
 
+        // HostNotAllowed
+        let err = Error::HostNotAllowed {
+            host: "attacker.test".into(),
+        };
+        assert!(err.to_string().contains("attacker.test"));
+
         // HandshakeTooLarge
         let err = Error::HandshakeTooLarge {
             size: 10000,
@@ -1,21 +1,37 @@
 //! Permessage-deflate WebSocket compression extension (RFC 7692).
 
+use crate::config::Limits;
 use crate::error::{Error, Result};
+use crate::extensions::zlib::Compressor;
 use crate::extensions::{Extension, ExtensionParam, RsvBits};
 use crate::protocol::Frame;
-use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress};
+use flate2::{Decompress, FlushDecompress};
 
 const MIN_WINDOW_BITS: u8 = 8;
 const MAX_WINDOW_BITS: u8 = 15;
 const DEFAULT_WINDOW_BITS: u8 = 15;
+const MIN_MEM_LEVEL: u8 = 1;
+const MAX_MEM_LEVEL: u8 = 9;
+const DEFAULT_MEM_LEVEL: u8 = 8;
 const DEFLATE_TRAILER: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
 const MAX_COMPRESSION_ITERATIONS: usize = 100_000;
 const MAX_DECOMPRESSION_RATIO: usize = 100;
 const DEFAULT_MAX_DECOMPRESSED_SIZE: usize = 64 * 1024 * 1024;
 
+/// The zlib implementation compression runs on: `"zlib-ng"` with the
+/// `zlib-ng` feature, which is roughly twice as fast, else `"zlib"`.
+pub const BACKEND: &str = if cfg!(feature = "zlib-ng") {
+    "zlib-ng"
+} else {
+    "zlib"
+};
+
 /// Configuration for the permessage-deflate extension.
 ///
 /// Controls compression parameters like window bits and context takeover.
+/// On a client these are offered as they are. On a server they are
+/// preferences: each client offer is accepted with the stricter of the
+/// offered and preferred settings.
 #[derive(Debug, Clone)]
 pub struct DeflateConfig {
     /// If true, server discards compression context after each message.
@@ -28,9 +44,21 @@ pub struct DeflateConfig {
     pub client_max_window_bits: u8,
     /// Compression level (0-9, default 6). Higher = better compression, slower.
     pub compression_level: u32,
-    /// Maximum decompressed message size in bytes (default 64MB).
-    /// Prevents decompression bomb attacks.
+    /// Maximum decompressed message size in bytes for an extension used on
+    /// its own (default 64MB). Prevents decompression bomb attacks.
+    ///
+    /// On a connection, [`Limits::max_message_size`] applies as well, so
+    /// messages are bounded by the smaller of the two whether they were
+    /// compressed or not.
     pub max_decompressed_size: usize,
+    /// zlib memory level of the compressor (1-9, default 8). Lower uses
+    /// less memory, `1 << (mem_level + 9)` bytes, for slightly worse
+    /// compression.
+    pub mem_level: u8,
+    /// Most compression state, in bytes, a connection keeps between
+    /// messages (default unlimited). See
+    /// [`max_memory`](Self::max_memory).
+    pub max_memory: Option<usize>,
 }
 
 impl Default for DeflateConfig {
@@ -42,6 +70,8 @@ impl Default for DeflateConfig {
             client_max_window_bits: DEFAULT_WINDOW_BITS,
             compression_level: 6,
             max_decompressed_size: DEFAULT_MAX_DECOMPRESSED_SIZE,
+            mem_level: DEFAULT_MEM_LEVEL,
+            max_memory: None,
         }
     }
 }
@@ -113,6 +143,105 @@ impl DeflateConfig {
         self.compression_level = level;
         Ok(self)
     }
+
+    /// Set the compressor's zlib memory level (1-9).
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidExtension` if level is not in range 1-9.
+    pub fn mem_level(mut self, level: u8) -> Result<Self> {
+        if !(MIN_MEM_LEVEL..=MAX_MEM_LEVEL).contains(&level) {
+            return Err(Error::InvalidExtension(format!(
+                "mem_level must be {}-{}, got {}",
+                MIN_MEM_LEVEL, MAX_MEM_LEVEL, level
+            )));
+        }
+        self.mem_level = level;
+        Ok(self)
+    }
+
+    /// Cap the compression state a connection keeps between messages, as
+    /// estimated by [`retained_memory`](Self::retained_memory).
+    ///
+    /// Over the cap, the connection drops its compressor after each
+    /// message (no context takeover for its own side), and if that is not
+    /// enough asks the peer to do the same so its decompressor can go too.
+    /// Idle connections then hold no zlib state at all, at some cost in
+    /// compression ratio; state is only allocated while a message is
+    /// being compressed or decompressed.
+    ///
+    /// ```rust,ignore
+    /// // 100k mostly idle connections: keep nothing between messages
+    /// let config = DeflateConfig::new().max_memory(0);
+    /// ```
+    #[must_use]
+    pub fn max_memory(mut self, bytes: usize) -> Self {
+        self.max_memory = Some(bytes);
+        self
+    }
+
+    /// Estimated bytes of zlib state one connection keeps between messages
+    /// with these settings, on the server or client side: compressor and
+    /// decompressor, each unless its context is dropped after every
+    /// message.
+    #[must_use]
+    pub fn retained_memory(&self, is_server: bool) -> usize {
+        let (own_bits, own_reset, peer_bits, peer_reset) = if is_server {
+            (
+                self.server_max_window_bits,
+                self.server_no_context_takeover,
+                self.client_max_window_bits,
+                self.client_no_context_takeover,
+            )
+        } else {
+            (
+                self.client_max_window_bits,
+                self.client_no_context_takeover,
+                self.server_max_window_bits,
+                self.server_no_context_takeover,
+            )
+        };
+        let compressor = if own_reset {
+            0
+        } else {
+            compressor_memory(own_bits, self.mem_level)
+        };
+        let decompressor = if peer_reset {
+            0
+        } else {
+            decompressor_memory(peer_bits)
+        };
+        compressor + decompressor
+    }
+
+    /// Turn off context takeover, own side first, until the retained
+    /// memory fits `max_memory`.
+    fn fit_memory(&mut self, is_server: bool) {
+        let Some(max) = self.max_memory else {
+            return;
+        };
+        for own in [true, false] {
+            if self.retained_memory(is_server) <= max {
+                return;
+            }
+            if own == is_server {
+                self.server_no_context_takeover = true;
+            } else {
+                self.client_no_context_takeover = true;
+            }
+        }
+    }
+}
+
+/// zlib's estimate for a deflate stream, plus a few kilobytes of
+/// bookkeeping.
+fn compressor_memory(window_bits: u8, mem_level: u8) -> usize {
+    (1 << (window_bits + 2)) + (1 << (mem_level + 9)) + 6 * 1024
+}
+
+/// zlib's estimate for an inflate stream.
+fn decompressor_memory(window_bits: u8) -> usize {
+    (1 << window_bits) + 7 * 1024
 }
 
 /// Permessage-deflate WebSocket extension (RFC 7692).
@@ -128,20 +257,27 @@ pub struct DeflateExtension {
     /// Whether this extension is used on the server side.
     is_server: bool,
     /// Persistent compression state for context takeover.
-    encoder: Option<Compress>,
+    encoder: Option<Compressor>,
     /// Persistent decompression state for context takeover.
     decoder: Option<Decompress>,
+    /// The connection's `Limits::max_message_size`, once set.
+    max_message_size: Option<usize>,
 }
 
 impl DeflateExtension {
     /// Create a new extension with the given configuration.
-    pub fn new(config: DeflateConfig, is_server: bool) -> Self {
+    pub fn new(mut config: DeflateConfig, is_server: bool) -> Self {
+        // A server fits the negotiated settings instead
+        if !is_server {
+            config.fit_memory(false);
+        }
         Self {
             config,
             negotiated: false,
             is_server,
             encoder: None,
             decoder: None,
+            max_message_size: None,
         }
     }
 
@@ -155,19 +291,18 @@ impl DeflateExtension {
         Self::new(config, true)
     }
 
-    pub(crate) fn ensure_encoder(&mut self) -> Result<&mut Compress> {
+    pub(crate) fn ensure_encoder(&mut self) -> Result<&mut Compressor> {
         if self.encoder.is_none() {
             let window_bits = if self.is_server {
                 self.config.server_max_window_bits
             } else {
                 self.config.client_max_window_bits
             };
-            let compression = Compression::new(self.config.compression_level);
-            self.encoder = Some(Compress::new_with_window_bits(
-                compression,
-                false, // raw deflate, no zlib header
+            self.encoder = Some(Compressor::new(
+                self.config.compression_level,
                 window_bits,
-            ));
+                self.config.mem_level,
+            )?);
         }
         self.encoder
             .as_mut()
@@ -219,15 +354,8 @@ impl DeflateExtension {
             let before_in = encoder.total_in();
             let before_out = encoder.total_out();
 
-            let flush = if input_pos + remaining.len() >= data.len() {
-                FlushCompress::Sync
-            } else {
-                FlushCompress::None
-            };
-
-            encoder
-                .compress(remaining, &mut compressed[old_len..], flush)
-                .map_err(|e| Error::Extension(format!("Compression failed: {}", e)))?;
+            let sync = input_pos + remaining.len() >= data.len();
+            encoder.compress(remaining, &mut compressed[old_len..], sync)?;
 
             let consumed = (encoder.total_in() - before_in) as usize;
             let produced = (encoder.total_out() - before_out) as usize;
@@ -263,6 +391,7 @@ impl DeflateExtension {
         let mut input = data.to_vec();
         input.extend_from_slice(&DEFLATE_TRAILER);
 
+        let max_message_size = self.max_message_size;
         let max_size = self.config.max_decompressed_size;
         let max_ratio_size = data.len().saturating_mul(MAX_DECOMPRESSION_RATIO);
 
@@ -303,6 +432,15 @@ impl DeflateExtension {
             decompressed.truncate(old_len + produced);
             input_pos += consumed;
 
+            match max_message_size {
+                Some(max) if max <= max_size && decompressed.len() > max => {
+                    return Err(Error::MessageTooLarge {
+                        size: decompressed.len(),
+                        max,
+                    });
+                }
+                _ => {}
+            }
             if decompressed.len() > max_size {
                 return Err(Error::Extension(format!(
                     "Decompressed size {} exceeds limit {}",
@@ -356,9 +494,8 @@ impl DeflateExtension {
     }
 }
 
-// SAFETY: `flate2::Compress` and `flate2::Decompress` are Send + Sync when using
-// the default miniz_oxide backend (pure Rust). The zlib feature also uses thread-safe
-// implementations. We verify this at compile time below.
+// SAFETY: `Compressor` and `flate2::Decompress` are Send + Sync: zlib keeps no
+// thread-local state. We verify this at compile time below.
 unsafe impl Send for DeflateExtension {}
 unsafe impl Sync for DeflateExtension {}
 
@@ -366,9 +503,9 @@ unsafe impl Sync for DeflateExtension {}
 const _: () = {
     const fn assert_send<T: Send>() {}
     const fn assert_sync<T: Sync>() {}
-    // These will fail to compile if Compress/Decompress are not Send+Sync
-    assert_send::<flate2::Compress>();
-    assert_sync::<flate2::Compress>();
+    // These will fail to compile if Compressor/Decompress are not Send+Sync
+    assert_send::<Compressor>();
+    assert_sync::<Compressor>();
     assert_send::<flate2::Decompress>();
     assert_sync::<flate2::Decompress>();
 };
@@ -382,38 +519,33 @@ impl Extension for DeflateExtension {
         RsvBits::RSV1
     }
 
+    /// Accept a client's offer, intersected with this extension's config as
+    /// the server's preferences (RFC 7692 Section 7.1).
+    ///
+    /// Context takeover is disabled if either side asks for it. Window bits
+    /// are the smaller of the offer and the preference; the client's are only
+    /// limited if the client offered `client_max_window_bits`.
     fn negotiate(&mut self, params: &[ExtensionParam]) -> Result<Vec<ExtensionParam>> {
-        let mut response = Vec::new();
-
-        for param in params {
+        let mut server_no_context_takeover = false;
+        let mut client_no_context_takeover = false;
+        let mut server_max_window_bits = None;
+        let mut client_max_window_bits = None;
+
+        for (i, param) in params.iter().enumerate() {
+            if params[..i].iter().any(|p| p.name == param.name) {
+                return Err(Error::InvalidExtension(format!(
+                    "Duplicate parameter: {}",
+                    param.name
+                )));
+            }
             match param.name.as_str() {
-                "server_no_context_takeover" => {
-                    self.config.server_no_context_takeover = true;
-                    response.push(ExtensionParam::flag("server_no_context_takeover"));
-                }
-                "client_no_context_takeover" => {
-                    self.config.client_no_context_takeover = true;
-                    response.push(ExtensionParam::flag("client_no_context_takeover"));
-                }
+                "server_no_context_takeover" => server_no_context_takeover = true,
+                "client_no_context_takeover" => client_no_context_takeover = true,
                 "server_max_window_bits" => {
-                    let bits = Self::parse_window_bits(param.value.as_deref())?;
-                    self.config.server_max_window_bits = bits;
-                    response.push(ExtensionParam::new(
-                        "server_max_window_bits",
-                        bits.to_string(),
-                    ));
+                    server_max_window_bits = Some(Self::parse_window_bits(param.value.as_deref())?);
                 }
                 "client_max_window_bits" => {
-                    let bits = if param.value.is_some() {
-                        Self::parse_window_bits(param.value.as_deref())?
-                    } else {
-                        self.config.client_max_window_bits
-                    };
-                    self.config.client_max_window_bits = bits;
-                    response.push(ExtensionParam::new(
-                        "client_max_window_bits",
-                        bits.to_string(),
-                    ));
+                    client_max_window_bits = Some(Self::parse_window_bits(param.value.as_deref())?);
                 }
                 _ => {
                     return Err(Error::InvalidExtension(format!(
@@ -424,6 +556,48 @@ impl Extension for DeflateExtension {
             }
         }
 
+        let preferred = &self.config;
+        let server_no_context_takeover =
+            server_no_context_takeover || preferred.server_no_context_takeover;
+        let client_no_context_takeover =
+            client_no_context_takeover || preferred.client_no_context_takeover;
+        let server_max_window_bits = server_max_window_bits
+            .unwrap_or(MAX_WINDOW_BITS)
+            .min(preferred.server_max_window_bits);
+        let client_max_window_bits =
+            client_max_window_bits.map(|bits| bits.min(preferred.client_max_window_bits));
+
+        let mut response = Vec::new();
+        if server_no_context_takeover {
+            response.push(ExtensionParam::flag("server_no_context_takeover"));
+        }
+        if client_no_context_takeover {
+            response.push(ExtensionParam::flag("client_no_context_takeover"));
+        }
+        if server_max_window_bits < MAX_WINDOW_BITS {
+            response.push(ExtensionParam::new(
+                "server_max_window_bits",
+                server_max_window_bits.to_string(),
+            ));
+        }
+        if let Some(bits) = client_max_window_bits {
+            response.push(ExtensionParam::new(
+                "client_max_window_bits",
+                bits.to_string(),
+            ));
+        }
+
+        self.config.server_no_context_takeover = server_no_context_takeover;
+        self.config.client_no_context_takeover = client_no_context_takeover;
+        self.config.server_max_window_bits = server_max_window_bits;
+        self.config.client_max_window_bits = client_max_window_bits.unwrap_or(MAX_WINDOW_BITS);
+        self.config.fit_memory(true);
+        if self.config.server_no_context_takeover && !server_no_context_takeover {
+            response.push(ExtensionParam::flag("server_no_context_takeover"));
+        }
+        if self.config.client_no_context_takeover && !client_no_context_takeover {
+            response.push(ExtensionParam::flag("client_no_context_takeover"));
+        }
         self.negotiated = true;
         Ok(response)
     }
@@ -509,6 +683,35 @@ impl Extension for DeflateExtension {
 
         params
     }
+
+    fn set_limits(&mut self, limits: &Limits) {
+        self.max_message_size = Some(limits.max_message_size);
+    }
+
+    /// The compressor and decompressor that exist between messages, as
+    /// estimated by zlib.
+    fn memory_usage(&self) -> usize {
+        let (own_bits, peer_bits) = if self.is_server {
+            (
+                self.config.server_max_window_bits,
+                self.config.client_max_window_bits,
+            )
+        } else {
+            (
+                self.config.client_max_window_bits,
+                self.config.server_max_window_bits,
+            )
+        };
+        let compressor = self
+            .encoder
+            .as_ref()
+            .map_or(0, |_| compressor_memory(own_bits, self.config.mem_level));
+        let decompressor = self
+            .decoder
+            .as_ref()
+            .map_or(0, |_| decompressor_memory(peer_bits));
+        compressor + decompressor
+    }
 }
 
 #[cfg(test)]
@@ -556,6 +759,46 @@ mod tests {
         assert!(response.iter().any(|p| p.name == "client_max_window_bits"));
     }
 
+    #[test]
+    fn test_server_preferences_intersect_offer() {
+        let preferred = DeflateConfig::new()
+            .client_no_context_takeover(true)
+            .server_max_window_bits(10)
+            .unwrap()
+            .client_max_window_bits(11)
+            .unwrap();
+
+        // A bare offer gets the server's own limits, but the client's window
+        // stays unlimited since it did not offer client_max_window_bits
+        let mut ext = DeflateExtension::server(preferred.clone());
+        let response = ext.negotiate(&[]).unwrap();
+        let names: Vec<_> = response.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(
+            names,
+            ["client_no_context_takeover", "server_max_window_bits"]
+        );
+        assert_eq!(ext.config.server_max_window_bits, 10);
+        assert_eq!(ext.config.client_max_window_bits, 15);
+
+        // The stricter of offer and preference wins
+        let mut ext = DeflateExtension::server(preferred);
+        let response = ext
+            .negotiate(&[
+                ExtensionParam::new("server_max_window_bits", "9"),
+                ExtensionParam::flag("client_max_window_bits"),
+            ])
+            .unwrap();
+        assert!(response.contains(&ExtensionParam::new("server_max_window_bits", "9")));
+        assert!(response.contains(&ExtensionParam::new("client_max_window_bits", "11")));
+
+        let mut ext = DeflateExtension::server(DeflateConfig::new());
+        let twice = [
+            ExtensionParam::flag("server_no_context_takeover"),
+            ExtensionParam::flag("server_no_context_takeover"),
+        ];
+        assert!(ext.negotiate(&twice).is_err());
+    }
+
     #[test]
     fn test_control_frame_bypass() {
         let mut ext = DeflateExtension::new(DeflateConfig::default(), false);
@@ -816,4 +1059,106 @@ mod tests {
         server_ext.decode(&mut frame2).unwrap();
         assert_eq!(frame2.payload(), &message[..]);
     }
+
+    #[test]
+    fn test_backend_matches_linked_zlib() {
+        // SAFETY: zlibVersion returns a static, NUL-terminated string
+        let version = unsafe { std::ffi::CStr::from_ptr(libz_sys::zlibVersion()) };
+        let version = version.to_str().unwrap();
+        assert_eq!(
+            version.contains("zlib-ng"),
+            BACKEND == "zlib-ng",
+            "{version}"
+        );
+    }
+
+    #[test]
+    fn test_mem_level() {
+        assert!(DeflateConfig::new().mem_level(0).is_err());
+        assert!(DeflateConfig::new().mem_level(10).is_err());
+
+        let config = DeflateConfig::new().mem_level(1).unwrap();
+        let mut client_ext = DeflateExtension::client(config);
+        let mut server_ext = DeflateExtension::server(DeflateConfig::default());
+        client_ext.negotiated = true;
+        server_ext.negotiated = true;
+
+        // Incompressible and larger than one output chunk
+        let data: Vec<u8> = (0..50_000u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect();
+        let mut frame = Frame::binary(data.clone());
+        client_ext.encode(&mut frame).unwrap();
+        server_ext.decode(&mut frame).unwrap();
+        assert_eq!(frame.payload(), &data[..]);
+        // A 128 KB window, but 1 KB of hash tables instead of 128 KB
+        assert_eq!(client_ext.memory_usage(), (1 << 17) + (1 << 10) + 6 * 1024);
+    }
+
+    #[test]
+    fn test_max_memory_drops_context_takeover() {
+        // Over the cap the server drops its own context, then the client's
+        let mut server_ext = DeflateExtension::server(DeflateConfig::new().max_memory(0));
+        let response = server_ext.negotiate(&[]).unwrap();
+        assert!(response.contains(&ExtensionParam::flag("server_no_context_takeover")));
+        assert!(response.contains(&ExtensionParam::flag("client_no_context_takeover")));
+        assert_eq!(server_ext.config.retained_memory(true), 0);
+
+        // Dropping its own context is enough for this client
+        let config = DeflateConfig::new().max_memory(100 * 1024);
+        let mut client_ext = DeflateExtension::client(config);
+        assert_eq!(
+            client_ext.offer_params(),
+            [ExtensionParam::flag("client_no_context_takeover")]
+        );
+        client_ext.negotiated = true;
+        server_ext.negotiated = true;
+
+        let mut frame = Frame::text(b"Hello, compression".to_vec());
+        server_ext.encode(&mut frame).unwrap();
+        client_ext.decode(&mut frame).unwrap();
+        assert_eq!(server_ext.memory_usage(), 0);
+        assert_eq!(client_ext.memory_usage(), (1 << 15) + 7 * 1024);
+    }
+
+    #[test]
+    fn test_limits_bound_decompressed_size() {
+        let mut client_ext = DeflateExtension::client(DeflateConfig::default());
+        let mut server_ext = DeflateExtension::server(DeflateConfig::default());
+        client_ext.negotiated = true;
+        server_ext.negotiated = true;
+        server_ext.set_limits(&Limits::new(1024, 1024, 16, 8192));
+
+        let mut frame = Frame::text(vec![b'a'; 1024]);
+        client_ext.encode(&mut frame).unwrap();
+        server_ext.decode(&mut frame).unwrap();
+        assert_eq!(frame.payload().len(), 1024);
+
+        let mut frame = Frame::text(vec![b'a'; 1025]);
+        client_ext.encode(&mut frame).unwrap();
+        assert!(matches!(
+            server_ext.decode(&mut frame),
+            Err(Error::MessageTooLarge { max: 1024, .. })
+        ));
+    }
+
+    #[test]
+    fn test_smaller_decompressed_size_kept_under_limits() {
+        let config = DeflateConfig {
+            max_decompressed_size: 512,
+            ..DeflateConfig::default()
+        };
+        let mut client_ext = DeflateExtension::client(DeflateConfig::default());
+        let mut server_ext = DeflateExtension::server(config);
+        client_ext.negotiated = true;
+        server_ext.negotiated = true;
+        server_ext.set_limits(&Limits::new(1024, 1024, 16, 8192));
+
+        let mut frame = Frame::text(vec![b'a'; 600]);
+        client_ext.encode(&mut frame).unwrap();
+        assert!(matches!(
+            server_ext.decode(&mut frame),
+            Err(Error::Extension(_))
+        ));
+    }
 }
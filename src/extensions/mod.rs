@@ -23,7 +23,10 @@
 
 #[cfg(feature = "compression")]
 pub mod deflate;
+#[cfg(feature = "compression")]
+mod zlib;
 
+use crate::config::Limits;
 use crate::error::{Error, Result};
 use crate::protocol::Frame;
 use std::fmt;
@@ -193,6 +196,11 @@ impl RsvBits {
         rsv3: false,
     };
 
+    /// The bits as they appear in the first byte of a frame header.
+    pub fn mask(&self) -> u8 {
+        (u8::from(self.rsv1) << 6) | (u8::from(self.rsv2) << 5) | (u8::from(self.rsv3) << 4)
+    }
+
     /// Check if any bits conflict with another RsvBits declaration.
     pub fn conflicts_with(&self, other: &RsvBits) -> bool {
         (self.rsv1 && other.rsv1) || (self.rsv2 && other.rsv2) || (self.rsv3 && other.rsv3)
@@ -285,8 +293,9 @@ pub trait Extension: Send + Sync {
 
     /// Encode a frame before sending.
     ///
-    /// Extensions are applied in registration order for encoding.
-    /// This method may modify the frame's payload and RSV bits.
+    /// Negotiated extensions encode in their negotiated order; see
+    /// [`ExtensionRegistry`]. This method may modify the frame's payload
+    /// and RSV bits.
     ///
     /// # Arguments
     ///
@@ -305,8 +314,9 @@ pub trait Extension: Send + Sync {
 
     /// Decode a frame after receiving.
     ///
-    /// Extensions are applied in reverse registration order for decoding.
-    /// This method may modify the frame's payload and should clear RSV bits it handles.
+    /// Negotiated extensions decode in reverse negotiated order, undoing
+    /// the peer's encoding last step first. This method may modify the
+    /// frame's payload and should clear RSV bits it handles.
     ///
     /// # Arguments
     ///
@@ -332,6 +342,21 @@ pub trait Extension: Send + Sync {
     fn offer_params(&self) -> Vec<ExtensionParam> {
         Vec::new()
     }
+
+    /// Called by the connection with its [`Limits`], which bound what
+    /// [`decode`](Self::decode) may produce: a decoded message larger than
+    /// `max_message_size` should fail with [`Error::MessageTooLarge`].
+    ///
+    /// Default implementation does nothing.
+    fn set_limits(&mut self, _limits: &Limits) {}
+
+    /// Estimated bytes of state the extension holds, such as compression
+    /// contexts, for reporting.
+    ///
+    /// Default returns 0.
+    fn memory_usage(&self) -> usize {
+        0
+    }
 }
 
 /// Registry for managing multiple WebSocket extensions.
@@ -341,14 +366,43 @@ pub trait Extension: Send + Sync {
 /// - RSV bit conflict detection
 /// - Handshake negotiation
 /// - Frame encoding/decoding pipeline
+///
+/// # Ordering
+///
+/// Extensions are kept sorted by priority, lowest first, and in the order
+/// they were added among equal priorities; [`insert_before`] and
+/// [`insert_after`] place one next to another. Clients offer them in this
+/// order, and a server accepts them in this order whatever the order of the
+/// offers.
+///
+/// The accepted list, in the server's response, is the negotiated order
+/// (RFC 6455 Section 9.1): outgoing frames are encoded by the first
+/// negotiated extension, then the second, and so on, and incoming frames
+/// are decoded in reverse, so with `foo, bar` data is sent as
+/// `bar(foo(data))`.
+///
+/// ```rust,ignore
+/// let mut registry = ExtensionRegistry::new();
+/// registry.add(Box::new(DeflateExtension::server(DeflateConfig::new())))?;
+/// // Sees frames before they are compressed, and after they are inflated
+/// registry.insert_before("permessage-deflate", Box::new(Audit::new()))?;
+/// ```
+///
+/// [`insert_before`]: Self::insert_before
+/// [`insert_after`]: Self::insert_after
 #[derive(Default)]
 pub struct ExtensionRegistry {
     /// Registered extensions in order.
     extensions: Vec<Box<dyn Extension>>,
+    /// Priority of each registered extension, which `extensions` is sorted
+    /// by.
+    priorities: Vec<i32>,
     /// Combined RSV bit usage.
     used_rsv_bits: RsvBits,
     /// Extensions that were successfully negotiated.
     negotiated: Vec<usize>,
+    /// Names and parameters agreed for each negotiated extension.
+    agreed: Vec<ExtensionOffer>,
 }
 
 impl ExtensionRegistry {
@@ -357,13 +411,69 @@ impl ExtensionRegistry {
         Self::default()
     }
 
-    /// Add an extension to the registry.
+    /// Add an extension to the registry, with priority 0.
     ///
     /// # Errors
     ///
     /// Returns [`Error::InvalidExtension`] if the extension's RSV bits conflict with already
     /// registered extensions.
     pub fn add(&mut self, extension: Box<dyn Extension>) -> Result<()> {
+        self.add_with_priority(extension, 0)
+    }
+
+    /// Add an extension after every one with the same or a lower
+    /// `priority`, and before those with a higher one.
+    ///
+    /// # Errors
+    ///
+    /// As for [`add`](Self::add).
+    pub fn add_with_priority(
+        &mut self,
+        extension: Box<dyn Extension>,
+        priority: i32,
+    ) -> Result<()> {
+        let pos = self.priorities.partition_point(|&p| p <= priority);
+        self.insert(pos, priority, extension)
+    }
+
+    /// Add an extension just before the one called `name`, with its
+    /// priority, so that it encodes first and decodes last.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidExtension`] if no extension called `name` is
+    /// registered, or as for [`add`](Self::add).
+    pub fn insert_before(&mut self, name: &str, extension: Box<dyn Extension>) -> Result<()> {
+        let pos = self.position(name)?;
+        self.insert(pos, self.priorities[pos], extension)
+    }
+
+    /// Add an extension just after the one called `name`, with its
+    /// priority, so that it encodes last and decodes first.
+    ///
+    /// # Errors
+    ///
+    /// As for [`insert_before`](Self::insert_before).
+    pub fn insert_after(&mut self, name: &str, extension: Box<dyn Extension>) -> Result<()> {
+        let pos = self.position(name)?;
+        self.insert(pos + 1, self.priorities[pos], extension)
+    }
+
+    /// Names of the registered extensions, in order.
+    pub fn names(&self) -> Vec<&str> {
+        self.extensions.iter().map(|e| e.name()).collect()
+    }
+
+    fn position(&self, name: &str) -> Result<usize> {
+        self.extensions
+            .iter()
+            .position(|e| e.name() == name)
+            .ok_or_else(|| {
+                Error::InvalidExtension(format!("Extension '{}' is not registered", name))
+            })
+    }
+
+    fn insert(&mut self, pos: usize, priority: i32, extension: Box<dyn Extension>) -> Result<()> {
         let rsv = extension.rsv_bits();
 
         if self.used_rsv_bits.conflicts_with(&rsv) {
@@ -377,7 +487,13 @@ impl ExtensionRegistry {
         self.used_rsv_bits.rsv2 |= rsv.rsv2;
         self.used_rsv_bits.rsv3 |= rsv.rsv3;
 
-        self.extensions.push(extension);
+        self.extensions.insert(pos, extension);
+        self.priorities.insert(pos, priority);
+        for idx in &mut self.negotiated {
+            if *idx >= pos {
+                *idx += 1;
+            }
+        }
         Ok(())
     }
 
@@ -396,6 +512,38 @@ impl ExtensionRegistry {
         self.negotiated.len()
     }
 
+    /// The negotiated extensions with the parameters agreed in the
+    /// handshake, in registration order of the offers.
+    pub fn negotiated(&self) -> &[ExtensionOffer] {
+        &self.agreed
+    }
+
+    /// RSV bits claimed by the negotiated extensions, which incoming frames
+    /// may therefore set.
+    pub fn negotiated_rsv_bits(&self) -> RsvBits {
+        self.negotiated
+            .iter()
+            .map(|&idx| self.extensions[idx].rsv_bits())
+            .fold(RsvBits::NONE, |acc, rsv| RsvBits {
+                rsv1: acc.rsv1 | rsv.rsv1,
+                rsv2: acc.rsv2 | rsv.rsv2,
+                rsv3: acc.rsv3 | rsv.rsv3,
+            })
+    }
+
+    /// Estimated bytes of state held by the negotiated extensions.
+    pub fn memory_usage(&self) -> usize {
+        self.negotiated
+            .iter()
+            .map(|&idx| self.extensions[idx].memory_usage())
+            .sum()
+    }
+
+    /// Check if the extension called `name` was negotiated.
+    pub fn is_negotiated(&self, name: &str) -> bool {
+        self.agreed.iter().any(|e| e.name == name)
+    }
+
     /// Generate the Sec-WebSocket-Extensions header value for client handshake.
     ///
     /// Returns a comma-separated list of extension offers.
@@ -417,7 +565,9 @@ impl ExtensionRegistry {
 
     /// Negotiate extensions based on offers from the peer (server-side).
     ///
-    /// Processes each offer and returns the accepted extensions.
+    /// Processes each offer and returns the accepted extensions, in
+    /// registry order. Several offers of one extension are alternatives:
+    /// the first it accepts wins.
     ///
     /// # Arguments
     ///
@@ -427,36 +577,38 @@ impl ExtensionRegistry {
     ///
     /// A list of accepted extension responses to include in the server's response.
     pub fn negotiate(&mut self, offers: &[ExtensionOffer]) -> Vec<ExtensionOffer> {
-        let mut accepted = Vec::new();
-        self.negotiated.clear();
+        let mut accepted: Vec<(usize, ExtensionOffer)> = Vec::new();
 
         for offer in offers {
             // Find matching registered extension
-            if let Some((idx, ext)) = self
-                .extensions
-                .iter_mut()
-                .enumerate()
-                .find(|(_, e)| e.name() == offer.name)
+            let Some(idx) = self.extensions.iter().position(|e| e.name() == offer.name) else {
+                continue;
+            };
+            if accepted.iter().any(|&(i, _)| i == idx) {
+                continue;
+            }
+            let ext = &mut self.extensions[idx];
+            // Try to negotiate, then configure with final params
+            if let Ok(response_params) = ext.negotiate(&offer.params)
+                && ext.configure(&response_params).is_ok()
             {
-                // Try to negotiate
-                if let Ok(response_params) = ext.negotiate(&offer.params) {
-                    // Configure with final params
-                    if ext.configure(&response_params).is_ok() {
-                        self.negotiated.push(idx);
-                        accepted.push(ExtensionOffer::with_params(
-                            offer.name.clone(),
-                            response_params,
-                        ));
-                    }
-                }
+                accepted.push((
+                    idx,
+                    ExtensionOffer::with_params(offer.name.clone(), response_params),
+                ));
             }
         }
 
-        accepted
+        accepted.sort_by_key(|&(idx, _)| idx);
+        self.negotiated = accepted.iter().map(|&(idx, _)| idx).collect();
+        self.agreed = accepted.into_iter().map(|(_, offer)| offer).collect();
+        self.agreed.clone()
     }
 
     /// Configure extensions based on server response (client-side).
     ///
+    /// The order of the response is the negotiated order.
+    ///
     /// # Arguments
     ///
     /// * `responses` - Extension responses from server's Sec-WebSocket-Extensions header
@@ -464,9 +616,10 @@ impl ExtensionRegistry {
     /// # Errors
     ///
     /// Returns [`Error::InvalidExtension`] if any extension fails to configure with
-    /// the provided parameters.
+    /// the provided parameters, or is accepted twice.
     pub fn configure(&mut self, responses: &[ExtensionOffer]) -> Result<()> {
         self.negotiated.clear();
+        self.agreed.clear();
 
         for response in responses {
             if let Some((idx, ext)) = self
@@ -475,8 +628,15 @@ impl ExtensionRegistry {
                 .enumerate()
                 .find(|(_, e)| e.name() == response.name)
             {
+                if self.negotiated.contains(&idx) {
+                    return Err(Error::InvalidExtension(format!(
+                        "Extension '{}' accepted twice",
+                        response.name
+                    )));
+                }
                 ext.configure(&response.params)?;
                 self.negotiated.push(idx);
+                self.agreed.push(response.clone());
             }
         }
 
@@ -485,7 +645,7 @@ impl ExtensionRegistry {
 
     /// Encode a frame through all negotiated extensions.
     ///
-    /// Extensions are applied in registration order.
+    /// Extensions are applied in negotiated order.
     ///
     /// # Errors
     ///
@@ -497,9 +657,28 @@ impl ExtensionRegistry {
         Ok(())
     }
 
+    /// Encode a frame through all negotiated extensions except per-message
+    /// compression, i.e. those claiming RSV1 (RFC 7692 Section 6).
+    ///
+    /// The frame goes out with RSV1 clear, which the peer reads as an
+    /// uncompressed message.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Extension`] if any extension fails to encode the frame.
+    pub fn encode_uncompressed(&mut self, frame: &mut Frame) -> Result<()> {
+        for &idx in &self.negotiated {
+            let extension = &mut self.extensions[idx];
+            if !extension.rsv_bits().rsv1 {
+                extension.encode(frame)?;
+            }
+        }
+        Ok(())
+    }
+
     /// Decode a frame through all negotiated extensions.
     ///
-    /// Extensions are applied in reverse registration order.
+    /// Extensions are applied in reverse negotiated order.
     ///
     /// # Errors
     ///
@@ -511,6 +690,13 @@ impl ExtensionRegistry {
         Ok(())
     }
 
+    /// Hand `limits` to every extension; see [`Extension::set_limits`].
+    pub fn set_limits(&mut self, limits: &Limits) {
+        for extension in &mut self.extensions {
+            extension.set_limits(limits);
+        }
+    }
+
     /// Format accepted extensions for Sec-WebSocket-Extensions response header.
     pub fn response_header(&self, accepted: &[ExtensionOffer]) -> String {
         accepted
@@ -529,7 +715,7 @@ impl fmt::Debug for ExtensionRegistry {
                 &self.extensions.iter().map(|e| e.name()).collect::<Vec<_>>(),
             )
             .field("used_rsv_bits", &self.used_rsv_bits)
-            .field("negotiated", &self.negotiated)
+            .field("negotiated", &self.agreed)
             .finish()
     }
 }
@@ -864,6 +1050,9 @@ mod tests {
         assert_eq!(accepted[0].name, "ext1");
         assert_eq!(accepted[1].name, "ext2");
         assert_eq!(registry.negotiated_count(), 2);
+        assert_eq!(registry.negotiated(), accepted);
+        assert!(registry.is_negotiated("ext2"));
+        assert!(!registry.is_negotiated("unknown"));
     }
 
     #[test]
@@ -896,6 +1085,108 @@ mod tests {
         assert_eq!(registry.negotiated_count(), 2);
     }
 
+    #[test]
+    fn test_registry_ordering() {
+        /// Appends its tag when encoding and checks it is last when decoding.
+        struct Tag(&'static str);
+
+        impl Extension for Tag {
+            fn name(&self) -> &str {
+                self.0
+            }
+
+            fn negotiate(&mut self, _params: &[ExtensionParam]) -> Result<Vec<ExtensionParam>> {
+                Ok(vec![])
+            }
+
+            fn encode(&mut self, frame: &mut Frame) -> Result<()> {
+                let payload = [frame.payload(), self.0.as_bytes()].concat();
+                *frame = Frame::text(payload);
+                Ok(())
+            }
+
+            fn decode(&mut self, frame: &mut Frame) -> Result<()> {
+                let payload = frame.payload();
+                let rest = payload
+                    .strip_suffix(self.0.as_bytes())
+                    .ok_or_else(|| Error::Extension(format!("{} not last", self.0)))?;
+                *frame = Frame::text(rest.to_vec());
+                Ok(())
+            }
+        }
+
+        let mut registry = ExtensionRegistry::new();
+        registry.add(Box::new(Tag("b"))).unwrap();
+        registry.add_with_priority(Box::new(Tag("d")), 10).unwrap();
+        registry.add_with_priority(Box::new(Tag("a")), -1).unwrap();
+        registry.insert_after("b", Box::new(Tag("c"))).unwrap();
+        registry.insert_before("a", Box::new(Tag("first"))).unwrap();
+        assert_eq!(registry.names(), ["first", "a", "b", "c", "d"]);
+        assert!(registry.insert_before("x", Box::new(Tag("y"))).is_err());
+
+        // Accepted in registry order, the first of repeated offers winning
+        let offers = ["d", "b", "a", "b"].map(ExtensionOffer::new);
+        let accepted = registry.negotiate(&offers);
+        let names: Vec<_> = accepted.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["a", "b", "d"]);
+
+        let mut frame = Frame::text(b"x".to_vec());
+        registry.encode(&mut frame).unwrap();
+        assert_eq!(frame.payload(), b"xabd");
+        registry.decode(&mut frame).unwrap();
+        assert_eq!(frame.payload(), b"x");
+
+        // A client follows the order of the response
+        let response = ["d", "a"].map(ExtensionOffer::new);
+        registry.configure(&response).unwrap();
+        registry.encode(&mut frame).unwrap();
+        assert_eq!(frame.payload(), b"xda");
+        assert!(
+            registry
+                .configure(&["a", "a"].map(ExtensionOffer::new))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_registry_encode_uncompressed_skips_rsv1() {
+        struct Compressor;
+
+        impl Extension for Compressor {
+            fn name(&self) -> &str {
+                "x-compress"
+            }
+
+            fn rsv_bits(&self) -> RsvBits {
+                RsvBits::RSV1
+            }
+
+            fn negotiate(&mut self, _params: &[ExtensionParam]) -> Result<Vec<ExtensionParam>> {
+                Ok(vec![])
+            }
+
+            fn encode(&mut self, frame: &mut Frame) -> Result<()> {
+                frame.rsv1 = true;
+                Ok(())
+            }
+
+            fn decode(&mut self, _frame: &mut Frame) -> Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut registry = ExtensionRegistry::new();
+        registry.add(Box::new(Compressor)).unwrap();
+        registry.negotiate(&[ExtensionOffer::new("x-compress")]);
+
+        let mut frame = Frame::text(b"test".to_vec());
+        registry.encode_uncompressed(&mut frame).unwrap();
+        assert!(!frame.rsv1);
+
+        registry.encode(&mut frame).unwrap();
+        assert!(frame.rsv1);
+    }
+
     #[test]
     fn test_registry_configure_client_side() {
         let mut registry = ExtensionRegistry::new();
@@ -959,6 +1250,7 @@ mod tests {
         // Client side: configure with server response
         client_registry.configure(&accepted).unwrap();
         assert_eq!(client_registry.negotiated_count(), 1);
+        assert_eq!(client_registry.negotiated(), accepted);
     }
 
     #[test]
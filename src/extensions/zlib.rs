@@ -0,0 +1,193 @@
+//! A raw deflate compressor over zlib with a configurable memory level.
+//!
+//! `flate2::Compress` always passes a memory level of 8 to `deflateInit2`
+//! and has no constructor taking another, so `DeflateConfig::mem_level`
+//! could not shrink the compressor's hash tables through it. Inflating has
+//! no memory level and stays on `flate2::Decompress`.
+
+use std::alloc::{self, Layout};
+use std::ffi::c_int;
+use std::mem::{self, MaybeUninit};
+use std::ptr;
+
+use libz_sys::{
+    Z_BUF_ERROR, Z_DEFAULT_STRATEGY, Z_DEFLATED, Z_NO_FLUSH, Z_OK, Z_SYNC_FLUSH, uInt, voidpf,
+    z_stream,
+};
+
+use crate::error::{Error, Result};
+
+/// Alignment of every block handed to zlib, which also holds its size.
+const ALIGN: usize = mem::align_of::<usize>();
+
+/// Raw deflate (no zlib header) compression state.
+pub(crate) struct Compressor {
+    /// Boxed because zlib keeps a pointer back to the stream.
+    stream: Box<z_stream>,
+    total_in: u64,
+    total_out: u64,
+}
+
+impl Compressor {
+    /// Start a stream with zlib's `level` (0-9), window and memory level.
+    pub(crate) fn new(level: u32, window_bits: u8, mem_level: u8) -> Result<Self> {
+        // SAFETY: all-zero is a valid bit pattern for every field but the
+        // allocator functions, which are set before the stream is used.
+        let mut stream: Box<MaybeUninit<z_stream>> = Box::new(MaybeUninit::zeroed());
+        // SAFETY: as above; `zalloc` and `zfree` are written without reading
+        // the zeroed function pointers.
+        let mut stream = unsafe {
+            let raw = stream.as_mut_ptr();
+            ptr::addr_of_mut!((*raw).zalloc).write(zalloc);
+            ptr::addr_of_mut!((*raw).zfree).write(zfree);
+            Box::from_raw(Box::into_raw(stream).cast::<z_stream>())
+        };
+        // SAFETY: `stream` is initialised and outlives the zlib state, which
+        // `Drop` frees.
+        let ret = unsafe {
+            libz_sys::deflateInit2_(
+                &mut *stream,
+                level as c_int,
+                Z_DEFLATED,
+                -c_int::from(window_bits),
+                c_int::from(mem_level),
+                Z_DEFAULT_STRATEGY,
+                libz_sys::zlibVersion(),
+                mem::size_of::<z_stream>() as c_int,
+            )
+        };
+        if ret != Z_OK {
+            return Err(Error::Extension(format!(
+                "Failed to initialize encoder (zlib error {ret})"
+            )));
+        }
+        Ok(Self {
+            stream,
+            total_in: 0,
+            total_out: 0,
+        })
+    }
+
+    /// Bytes consumed so far.
+    pub(crate) fn total_in(&self) -> u64 {
+        self.total_in
+    }
+
+    /// Bytes produced so far.
+    pub(crate) fn total_out(&self) -> u64 {
+        self.total_out
+    }
+
+    /// Compress as much of `input` into `output` as fits, ending with a sync
+    /// flush if `sync` is set.
+    pub(crate) fn compress(&mut self, input: &[u8], output: &mut [u8], sync: bool) -> Result<()> {
+        let stream = &mut *self.stream;
+        stream.next_in = input.as_ptr().cast_mut();
+        stream.avail_in = input.len().min(uInt::MAX as usize) as uInt;
+        stream.next_out = output.as_mut_ptr();
+        stream.avail_out = output.len().min(uInt::MAX as usize) as uInt;
+
+        let flush = if sync { Z_SYNC_FLUSH } else { Z_NO_FLUSH };
+        // SAFETY: the stream was initialised by `deflateInit2_`, and the
+        // buffers it points into live until the call returns.
+        let ret = unsafe { libz_sys::deflate(stream, flush) };
+
+        // zlib's own counters may be 32 bits wide
+        self.total_in += (stream.next_in as usize - input.as_ptr() as usize) as u64;
+        self.total_out += (stream.next_out as usize - output.as_ptr() as usize) as u64;
+        stream.next_in = ptr::null_mut();
+        stream.avail_in = 0;
+        stream.next_out = ptr::null_mut();
+        stream.avail_out = 0;
+
+        match ret {
+            // No progress possible is not an error; the caller stops
+            Z_OK | Z_BUF_ERROR => Ok(()),
+            ret => Err(Error::Extension(format!(
+                "Compression failed (zlib error {ret})"
+            ))),
+        }
+    }
+}
+
+impl Drop for Compressor {
+    fn drop(&mut self) {
+        // SAFETY: the stream was initialised by `deflateInit2_` and is not
+        // used again.
+        unsafe {
+            libz_sys::deflateEnd(&mut *self.stream);
+        }
+    }
+}
+
+// SAFETY: the stream is only reached through `&mut self`, and zlib keeps no
+// thread-local state.
+unsafe impl Send for Compressor {}
+unsafe impl Sync for Compressor {}
+
+/// zlib allocator over the Rust global allocator, storing each block's
+/// size in front of it.
+unsafe extern "C" fn zalloc(_opaque: voidpf, items: uInt, size: uInt) -> voidpf {
+    let Some(len) = (items as usize)
+        .checked_mul(size as usize)
+        .and_then(|len| len.checked_add(ALIGN))
+    else {
+        return ptr::null_mut();
+    };
+    let Ok(layout) = Layout::from_size_align(len, ALIGN) else {
+        return ptr::null_mut();
+    };
+    // SAFETY: `layout` is non-zero in size, and the block is big enough for
+    // the size written at its start.
+    unsafe {
+        let block = alloc::alloc(layout);
+        if block.is_null() {
+            return ptr::null_mut();
+        }
+        block.cast::<usize>().write(len);
+        block.add(ALIGN).cast()
+    }
+}
+
+/// Frees a block from `zalloc`.
+unsafe extern "C" fn zfree(_opaque: voidpf, address: voidpf) {
+    if address.is_null() {
+        return;
+    }
+    // SAFETY: `address` came from `zalloc`, which put the block's size
+    // `ALIGN` bytes before it.
+    unsafe {
+        let block = address.cast::<u8>().sub(ALIGN);
+        let len = block.cast::<usize>().read();
+        alloc::dealloc(block, Layout::from_size_align_unchecked(len, ALIGN));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new_rejects_bad_parameters() {
+        assert!(Compressor::new(6, 15, 0).is_err());
+        assert!(Compressor::new(6, 15, 10).is_err());
+        assert!(Compressor::new(6, 16, 8).is_err());
+        assert!(Compressor::new(6, 15, 1).is_ok());
+    }
+
+    #[test]
+    fn test_compress_without_room_makes_no_progress() {
+        let mut compressor = Compressor::new(6, 15, 8).unwrap();
+        compressor.compress(b"hello", &mut [], true).unwrap();
+        assert_eq!(compressor.total_in(), 0);
+        assert_eq!(compressor.total_out(), 0);
+
+        // Nothing to do at all is zlib's Z_BUF_ERROR, not a failure
+        let mut output = [0; 64];
+        compressor.compress(b"hello", &mut output, true).unwrap();
+        let produced = compressor.total_out();
+        assert!(produced > 0);
+        compressor.compress(&[], &mut output, false).unwrap();
+        assert_eq!(compressor.total_out(), produced);
+    }
+}
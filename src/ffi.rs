@@ -0,0 +1,385 @@
+//! C API.
+//!
+//! A blocking, handle-based interface for embedding rsws in C and C++
+//! programs, enabled by the `ffi` feature (Unix only). The caller performs
+//! the HTTP upgrade and hands over the socket; each handle then drives its
+//! connection on a private single-threaded runtime. `include/rsws.h`
+//! declares these functions; regenerate it with `cbindgen --config
+//! cbindgen.toml --output include/rsws.h` after changing them.
+//!
+//! Build a library to link against with:
+//!
+//! ```text
+//! cargo rustc --release --features ffi --crate-type cdylib
+//! ```
+//!
+//! Functions returning `int32_t` report one of the `RSWS_*` status codes;
+//! on `RSWS_ERROR`, [`rsws_last_error`] describes the failure. A handle must
+//! not be used from two threads at once.
+
+use std::ffi::{CStr, CString, c_char, c_int};
+use std::os::fd::{FromRawFd, RawFd};
+use std::ptr;
+
+use tokio::net::TcpStream;
+use tokio::runtime::Runtime;
+
+use crate::{CloseCode, Config, Connection, Error, Message, Role};
+
+/// The call succeeded.
+pub const RSWS_OK: i32 = 0;
+/// The connection is closed; no more messages will arrive.
+pub const RSWS_CLOSED: i32 = 1;
+/// The call failed; see [`rsws_last_error`].
+pub const RSWS_ERROR: i32 = -1;
+/// An argument was null or otherwise invalid.
+pub const RSWS_INVALID: i32 = -2;
+
+/// [`RswsMessage::kind`] of a text message.
+pub const RSWS_MESSAGE_TEXT: u8 = 1;
+/// [`RswsMessage::kind`] of a binary message.
+pub const RSWS_MESSAGE_BINARY: u8 = 2;
+/// [`RswsMessage::kind`] of a close frame.
+pub const RSWS_MESSAGE_CLOSE: u8 = 8;
+/// [`RswsMessage::kind`] of a ping.
+pub const RSWS_MESSAGE_PING: u8 = 9;
+/// [`RswsMessage::kind`] of a pong.
+pub const RSWS_MESSAGE_PONG: u8 = 10;
+
+/// A WebSocket connection owned by C code.
+pub struct RswsConnection {
+    runtime: Runtime,
+    conn: Connection<TcpStream>,
+    /// Payload of the last message returned by `rsws_recv`.
+    received: Vec<u8>,
+    last_error: Option<CString>,
+}
+
+/// A received message. `data` points into the connection and stays valid
+/// until the next call to `rsws_recv` or `rsws_connection_free`.
+#[repr(C)]
+pub struct RswsMessage {
+    /// One of the `RSWS_MESSAGE_*` constants.
+    pub kind: u8,
+    /// Payload; for close frames, the reason.
+    pub data: *const u8,
+    /// Payload length in bytes.
+    pub len: usize,
+    /// Status code of a close frame, `0` otherwise or if none was sent.
+    pub close_code: u16,
+}
+
+impl RswsConnection {
+    fn fail(&mut self, error: &Error) -> i32 {
+        let message = error.to_string().replace('\0', " ");
+        self.last_error = CString::new(message).ok();
+        RSWS_ERROR
+    }
+}
+
+/// Wrap a connected socket whose opening handshake has already completed.
+///
+/// The connection takes ownership of `fd` and closes it when freed. Pass a
+/// non-zero `is_client` if this end opened the connection, so outgoing
+/// frames are masked. Returns null if `fd` cannot be used; a non-negative
+/// `fd` has been closed by then, so the caller never closes it either way.
+///
+/// # Safety
+///
+/// `fd` must be an open stream socket that no other code closes or uses
+/// afterwards, even if null is returned.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rsws_connection_from_fd(
+    fd: c_int,
+    is_client: c_int,
+) -> *mut RswsConnection {
+    if fd < 0 {
+        return ptr::null_mut();
+    }
+    // Owned from here on, so every early return below closes it
+    // SAFETY: the caller hands over sole ownership of `fd`
+    let std_stream = unsafe { std::net::TcpStream::from_raw_fd(fd as RawFd) };
+    let Ok(runtime) = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+    else {
+        return ptr::null_mut();
+    };
+    if std_stream.set_nonblocking(true).is_err() {
+        return ptr::null_mut();
+    }
+    let Ok(stream) = runtime.block_on(async { TcpStream::from_std(std_stream) }) else {
+        return ptr::null_mut();
+    };
+    let (role, config) = if is_client != 0 {
+        (Role::Client, Config::client())
+    } else {
+        (Role::Server, Config::server())
+    };
+    let conn = Connection::new(stream, role, config);
+    Box::into_raw(Box::new(RswsConnection {
+        runtime,
+        conn,
+        received: Vec::new(),
+        last_error: None,
+    }))
+}
+
+/// Send a text message. `data` must be `len` bytes of valid UTF-8.
+///
+/// # Safety
+///
+/// `conn` must come from [`rsws_connection_from_fd`] and not be freed, and
+/// `data` must point to `len` readable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rsws_send_text(
+    conn: *mut RswsConnection,
+    data: *const u8,
+    len: usize,
+) -> i32 {
+    // SAFETY: forwarded from the caller
+    let Some((conn, bytes)) = (unsafe { handle_and_bytes(conn, data, len) }) else {
+        return RSWS_INVALID;
+    };
+    let Ok(text) = std::str::from_utf8(bytes) else {
+        return RSWS_INVALID;
+    };
+    send(conn, Message::text(text))
+}
+
+/// Send a binary message.
+///
+/// # Safety
+///
+/// `conn` must come from [`rsws_connection_from_fd`] and not be freed, and
+/// `data` must point to `len` readable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rsws_send_binary(
+    conn: *mut RswsConnection,
+    data: *const u8,
+    len: usize,
+) -> i32 {
+    // SAFETY: forwarded from the caller
+    let Some((conn, bytes)) = (unsafe { handle_and_bytes(conn, data, len) }) else {
+        return RSWS_INVALID;
+    };
+    send(conn, Message::binary(bytes.to_vec()))
+}
+
+/// Block until the next message arrives and describe it in `out`.
+///
+/// Returns `RSWS_CLOSED` once the connection has closed. Pings are
+/// answered automatically but still reported.
+///
+/// # Safety
+///
+/// `conn` must come from [`rsws_connection_from_fd`] and not be freed, and
+/// `out` must point to a writable `RswsMessage`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rsws_recv(conn: *mut RswsConnection, out: *mut RswsMessage) -> i32 {
+    // SAFETY: the caller guarantees both pointers are valid if non-null
+    let (Some(conn), Some(out)) = (unsafe { handle(conn) }, unsafe { out.as_mut() }) else {
+        return RSWS_INVALID;
+    };
+    let message = match conn.runtime.block_on(conn.conn.recv()) {
+        Ok(Some(message)) => message,
+        Ok(None) => return RSWS_CLOSED,
+        Err(e) => return conn.fail(&e),
+    };
+    let (kind, close_code) = match &message {
+        Message::Text(_) => (RSWS_MESSAGE_TEXT, 0),
+        Message::Spilled(spilled) if spilled.is_text() => (RSWS_MESSAGE_TEXT, 0),
+        Message::Binary(_) | Message::Spilled(_) => (RSWS_MESSAGE_BINARY, 0),
+        Message::Ping(_) => (RSWS_MESSAGE_PING, 0),
+        Message::Pong(_) => (RSWS_MESSAGE_PONG, 0),
+        Message::Close(frame) => (
+            RSWS_MESSAGE_CLOSE,
+            frame.as_ref().map_or(0, |f| f.code.as_u16()),
+        ),
+    };
+    conn.received = match message {
+        Message::Text(text) => text.into_bytes(),
+        Message::Binary(data) | Message::Ping(data) | Message::Pong(data) => data.to_vec(),
+        Message::Close(frame) => frame.map(|f| f.reason.into_bytes()).unwrap_or_default(),
+        Message::Spilled(spilled) => match spilled.read_to_vec() {
+            Ok(data) => data,
+            Err(e) => return conn.fail(&e.into()),
+        },
+    };
+    *out = RswsMessage {
+        kind,
+        data: conn.received.as_ptr(),
+        len: conn.received.len(),
+        close_code,
+    };
+    RSWS_OK
+}
+
+/// Start the closing handshake with `code` and an optional NUL-terminated
+/// `reason` (null for none). Keep calling [`rsws_recv`] until it returns
+/// `RSWS_CLOSED` to complete it.
+///
+/// # Safety
+///
+/// `conn` must come from [`rsws_connection_from_fd`] and not be freed, and
+/// `reason` must be null or a valid NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rsws_close(
+    conn: *mut RswsConnection,
+    code: u16,
+    reason: *const c_char,
+) -> i32 {
+    // SAFETY: forwarded from the caller
+    let Some(conn) = (unsafe { handle(conn) }) else {
+        return RSWS_INVALID;
+    };
+    let reason = if reason.is_null() {
+        ""
+    } else {
+        // SAFETY: the caller guarantees a NUL-terminated string
+        match unsafe { CStr::from_ptr(reason) }.to_str() {
+            Ok(reason) => reason,
+            Err(_) => return RSWS_INVALID,
+        }
+    };
+    match conn
+        .runtime
+        .block_on(conn.conn.close(CloseCode::from_u16(code), reason))
+    {
+        Ok(()) => RSWS_OK,
+        Err(e) => conn.fail(&e),
+    }
+}
+
+/// Description of the failure if the last call on `conn` returned
+/// `RSWS_ERROR`, or null. Each send, receive or close clears it first, so
+/// the string stays valid until the next one.
+///
+/// # Safety
+///
+/// `conn` must be null or come from [`rsws_connection_from_fd`] and not be
+/// freed.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rsws_last_error(conn: *const RswsConnection) -> *const c_char {
+    // SAFETY: the caller guarantees `conn` is valid if non-null
+    unsafe { conn.as_ref() }
+        .and_then(|conn| conn.last_error.as_ref())
+        .map_or(ptr::null(), |e| e.as_ptr())
+}
+
+/// Free `conn` and close its socket without a closing handshake. Null is
+/// ignored.
+///
+/// # Safety
+///
+/// `conn` must be null or come from [`rsws_connection_from_fd`], and must
+/// not be used afterwards.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rsws_connection_free(conn: *mut RswsConnection) {
+    if !conn.is_null() {
+        // SAFETY: the caller gives the handle back
+        drop(unsafe { Box::from_raw(conn) });
+    }
+}
+
+/// # Safety
+///
+/// See the callers.
+unsafe fn handle_and_bytes<'a>(
+    conn: *mut RswsConnection,
+    data: *const u8,
+    len: usize,
+) -> Option<(&'a mut RswsConnection, &'a [u8])> {
+    // SAFETY: forwarded from the caller
+    let conn = unsafe { handle(conn) }?;
+    let bytes = match (data.is_null(), len) {
+        (_, 0) => &[][..],
+        (true, _) => return None,
+        // SAFETY: the caller guarantees `len` readable bytes at `data`
+        (false, _) => unsafe { std::slice::from_raw_parts(data, len) },
+    };
+    Some((conn, bytes))
+}
+
+/// Borrow the handle behind `conn` for a call, clearing the error left by
+/// the previous one.
+///
+/// # Safety
+///
+/// `conn` must be null or come from [`rsws_connection_from_fd`] and not be
+/// freed.
+unsafe fn handle<'a>(conn: *mut RswsConnection) -> Option<&'a mut RswsConnection> {
+    // SAFETY: the caller guarantees `conn` is valid if non-null
+    let conn = unsafe { conn.as_mut() }?;
+    conn.last_error = None;
+    Some(conn)
+}
+
+fn send(conn: &mut RswsConnection, message: Message) -> i32 {
+    match conn.runtime.block_on(conn.conn.send(message)) {
+        Ok(()) => RSWS_OK,
+        Err(e) => conn.fail(&e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::Frame;
+    use crate::testing::client_frame;
+    use std::io::{Read, Write};
+    use std::os::fd::IntoRawFd;
+
+    #[test]
+    fn test_round_trip_over_fd() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut peer = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+
+        let conn = unsafe { rsws_connection_from_fd(server.into_raw_fd(), 0) };
+        assert!(!conn.is_null());
+
+        peer.write_all(&client_frame(&Frame::text("hi"))).unwrap();
+        let mut message = RswsMessage {
+            kind: 0,
+            data: ptr::null(),
+            len: 0,
+            close_code: 0,
+        };
+        assert_eq!(unsafe { rsws_recv(conn, &mut message) }, RSWS_OK);
+        assert_eq!(message.kind, RSWS_MESSAGE_TEXT);
+        let data = unsafe { std::slice::from_raw_parts(message.data, message.len) };
+        assert_eq!(data, b"hi");
+
+        assert_eq!(
+            unsafe { rsws_send_binary(conn, b"ok".as_ptr(), 2) },
+            RSWS_OK
+        );
+        let mut reply = [0; 4];
+        peer.read_exact(&mut reply).unwrap();
+        assert_eq!(reply, [0x82, 0x02, b'o', b'k']);
+
+        assert_eq!(
+            unsafe { rsws_send_text(conn, [0xff].as_ptr(), 1) },
+            RSWS_INVALID
+        );
+        assert_eq!(unsafe { rsws_close(conn, 1000, c"bye".as_ptr()) }, RSWS_OK);
+        peer.write_all(&client_frame(&Frame::close(Some(1000), "")))
+            .unwrap();
+        assert_eq!(unsafe { rsws_recv(conn, &mut message) }, RSWS_OK);
+        assert_eq!(message.kind, RSWS_MESSAGE_CLOSE);
+        assert_eq!(message.close_code, 1000);
+        assert_eq!(unsafe { rsws_recv(conn, &mut message) }, RSWS_CLOSED);
+        assert!(unsafe { rsws_last_error(conn) }.is_null());
+
+        // An error lasts only until the next call
+        assert_eq!(
+            unsafe { rsws_send_binary(conn, b"late".as_ptr(), 4) },
+            RSWS_ERROR
+        );
+        assert!(!unsafe { rsws_last_error(conn) }.is_null());
+        assert_eq!(unsafe { rsws_recv(conn, &mut message) }, RSWS_CLOSED);
+        assert!(unsafe { rsws_last_error(conn) }.is_null());
+        unsafe { rsws_connection_free(conn) };
+    }
+}
@@ -0,0 +1,734 @@
+//! Fan-out of one message to many connections.
+//!
+//! A [`Hub`] keeps a bounded outbound queue per subscribed peer. Each
+//! connection task owns its [`Subscription`] and forwards whatever arrives on
+//! it, so a broadcast never waits on a socket:
+//!
+//! ```rust,ignore
+//! use rsws::hub::{Backpressure, Hub};
+//!
+//! let hub = Hub::new();
+//!
+//! // Per connection:
+//! let mut feed = hub.subscribe(Backpressure::Disconnect);
+//! loop {
+//!     tokio::select! {
+//!         prepared = feed.recv() => match prepared {
+//!             Some(prepared) => conn.send_prepared(&prepared).await?,
+//!             None => break, // removed for falling behind
+//!         },
+//!         msg = conn.recv() => match msg? {
+//!             Some(msg) if msg.is_text() => {
+//!                 hub.broadcast_except(feed.id(), msg).await?;
+//!             }
+//!             Some(_) => {}
+//!             None => break,
+//!         },
+//!     }
+//! }
+//! ```
+//!
+//! A broadcast message is encoded into a [`PreparedMessage`] once and the
+//! same buffer is shared by every peer; see
+//! [`Connection::send_prepared`](crate::Connection::send_prepared) for when
+//! the shared bytes can be written directly.
+//!
+//! Peers can also be grouped into named [`Room`]s and addressed per room:
+//!
+//! ```rust,ignore
+//! let lobby = hub.room("lobby");
+//! lobby.join(feed.id());
+//! lobby.broadcast(Message::text("someone joined")).await?;
+//! ```
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::{Arc, Mutex, Weak};
+
+use bytes::Bytes;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+
+use crate::error::{Error, Result};
+use crate::message::Message;
+use crate::protocol::{Frame, SpilledMessage};
+
+/// Default number of messages queued per peer.
+const DEFAULT_QUEUE_SIZE: usize = 64;
+
+/// A message encoded once for sending to many connections.
+///
+/// Holds the message together with its unmasked, single-frame wire encoding.
+/// Cloning is cheap.
+#[derive(Clone)]
+pub struct PreparedMessage(Arc<Prepared>);
+
+struct Prepared {
+    message: Message,
+    frame: Frame,
+    wire: Bytes,
+}
+
+impl PreparedMessage {
+    /// Encode `message` as a single unmasked frame.
+    ///
+    /// A [`Message::Spilled`] is read back into memory first, blocking the
+    /// caller; the hub's broadcasts read it on the blocking thread pool.
+    pub fn new(message: Message) -> Result<Self> {
+        let message = match message {
+            Message::Spilled(spilled) => read_back(&spilled, spilled.read_to_vec()?)?,
+            message => message,
+        };
+        let frame = Frame::try_from(message.clone())?;
+        let mut wire = vec![0u8; frame.wire_size(false)];
+        let written = frame.write(&mut wire, None)?;
+        wire.truncate(written);
+
+        Ok(Self(Arc::new(Prepared {
+            message,
+            frame,
+            wire: Bytes::from(wire),
+        })))
+    }
+
+    /// The message this was prepared from.
+    pub fn message(&self) -> &Message {
+        &self.0.message
+    }
+
+    pub(crate) fn frame(&self) -> &Frame {
+        &self.0.frame
+    }
+
+    /// The encoded frame, header included.
+    pub fn wire(&self) -> &[u8] {
+        &self.0.wire
+    }
+
+    pub(crate) fn wire_bytes(&self) -> &Bytes {
+        &self.0.wire
+    }
+
+    /// As [`new`](Self::new), reading a spilled payload without blocking.
+    async fn prepare(message: Message) -> Result<Self> {
+        match message {
+            Message::Spilled(spilled) => Self::new(read_back(&spilled, spilled.read().await?)?),
+            message => Self::new(message),
+        }
+    }
+}
+
+/// The in-memory message for the payload of `spilled`.
+fn read_back(spilled: &SpilledMessage, payload: Vec<u8>) -> Result<Message> {
+    if spilled.is_text() {
+        Ok(Message::Text(
+            String::from_utf8(payload).map_err(|_| Error::InvalidUtf8)?,
+        ))
+    } else {
+        Ok(Message::Binary(payload.into()))
+    }
+}
+
+impl fmt::Debug for PreparedMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PreparedMessage")
+            .field("message", &self.0.message)
+            .field("wire_len", &self.0.wire.len())
+            .finish()
+    }
+}
+
+/// Identifies a peer subscribed to a [`Hub`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PeerId(u64);
+
+impl PeerId {
+    /// The raw identifier.
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Display for PeerId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// What a broadcast does when a peer's queue is full.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Backpressure {
+    /// Skip this message for the slow peer. Suits data where only the
+    /// latest value matters, such as quotes.
+    #[default]
+    DropMessage,
+    /// Remove the peer from the hub. Its [`Subscription`] drains what is
+    /// already queued and then yields `None`.
+    Disconnect,
+    /// Wait for room in the queue. One slow peer delays the whole broadcast.
+    Wait,
+}
+
+struct Peer {
+    tx: mpsc::Sender<PreparedMessage>,
+    policy: Backpressure,
+    rooms: HashSet<Arc<str>>,
+}
+
+#[derive(Default)]
+struct Peers {
+    next_id: u64,
+    map: HashMap<PeerId, Peer>,
+    rooms: HashMap<Arc<str>, HashSet<PeerId>>,
+}
+
+impl Peers {
+    fn remove(&mut self, id: PeerId) -> bool {
+        let Some(peer) = self.map.remove(&id) else {
+            return false;
+        };
+        for room in &peer.rooms {
+            self.leave_room(room, id);
+        }
+        true
+    }
+
+    fn join(&mut self, room: &Arc<str>, id: PeerId) -> bool {
+        let Some(peer) = self.map.get_mut(&id) else {
+            return false;
+        };
+        if !peer.rooms.insert(Arc::clone(room)) {
+            return false;
+        }
+        self.rooms.entry(Arc::clone(room)).or_default().insert(id);
+        true
+    }
+
+    fn leave(&mut self, room: &str, id: PeerId) -> bool {
+        let Some(peer) = self.map.get_mut(&id) else {
+            return false;
+        };
+        if !peer.rooms.remove(room) {
+            return false;
+        }
+        self.leave_room(room, id);
+        true
+    }
+
+    /// Drop `id` from a room's member set, forgetting the room once empty.
+    fn leave_room(&mut self, room: &str, id: PeerId) {
+        if let Some(members) = self.rooms.get_mut(room) {
+            members.remove(&id);
+            if members.is_empty() {
+                self.rooms.remove(room);
+            }
+        }
+    }
+}
+
+/// Broadcasts messages to a set of subscribed peers.
+///
+/// `Hub` is a handle; clones share the same set of peers.
+#[derive(Clone)]
+pub struct Hub {
+    peers: Arc<Mutex<Peers>>,
+    queue_size: usize,
+}
+
+impl Hub {
+    /// Create an empty hub.
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            peers: Arc::new(Mutex::new(Peers::default())),
+            queue_size: DEFAULT_QUEUE_SIZE,
+        }
+    }
+
+    /// Set the number of messages queued per peer for subscriptions made
+    /// from now on (default: 64).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `queue_size` is zero.
+    #[must_use]
+    pub fn with_queue_size(mut self, queue_size: usize) -> Self {
+        assert!(queue_size > 0, "queue_size must be at least 1");
+        self.queue_size = queue_size;
+        self
+    }
+
+    /// Add a peer that is handled according to `policy` when it falls behind.
+    ///
+    /// The peer stays subscribed until the returned [`Subscription`] is
+    /// dropped, [`remove`](Self::remove) is called or the policy disconnects
+    /// it.
+    pub fn subscribe(&self, policy: Backpressure) -> Subscription {
+        let (tx, rx) = mpsc::channel(self.queue_size);
+        let mut peers = self.lock();
+        peers.next_id += 1;
+        let id = PeerId(peers.next_id);
+        peers.map.insert(
+            id,
+            Peer {
+                tx,
+                policy,
+                rooms: HashSet::new(),
+            },
+        );
+
+        Subscription {
+            id,
+            rx,
+            peers: Arc::downgrade(&self.peers),
+        }
+    }
+
+    /// Remove a peer, and with it its room memberships. Returns `false` if
+    /// it was not subscribed.
+    pub fn remove(&self, id: PeerId) -> bool {
+        self.lock().remove(id)
+    }
+
+    /// A handle to the room called `name`.
+    ///
+    /// Rooms need no setup: one exists while it has members.
+    pub fn room(&self, name: impl Into<Arc<str>>) -> Room {
+        Room {
+            hub: self.clone(),
+            name: name.into(),
+        }
+    }
+
+    /// Names of the rooms that currently have members.
+    pub fn rooms(&self) -> Vec<String> {
+        self.lock()
+            .rooms
+            .keys()
+            .map(|name| name.to_string())
+            .collect()
+    }
+
+    /// Names of the rooms `id` belongs to.
+    pub fn rooms_of(&self, id: PeerId) -> Vec<String> {
+        self.lock()
+            .map
+            .get(&id)
+            .map(|peer| peer.rooms.iter().map(|name| name.to_string()).collect())
+            .unwrap_or_default()
+    }
+
+    /// Number of subscribed peers.
+    pub fn len(&self) -> usize {
+        self.lock().map.len()
+    }
+
+    /// Whether no peers are subscribed.
+    pub fn is_empty(&self) -> bool {
+        self.lock().map.is_empty()
+    }
+
+    /// Queue a message for every peer.
+    ///
+    /// Returns the number of peers the message was queued for.
+    ///
+    /// # Errors
+    ///
+    /// Fails only if the message cannot be encoded.
+    pub async fn broadcast(&self, message: Message) -> Result<usize> {
+        Ok(self
+            .deliver(PreparedMessage::prepare(message).await?, None, None)
+            .await)
+    }
+
+    /// Queue a message for every peer except `except`, typically its sender.
+    ///
+    /// # Errors
+    ///
+    /// Fails only if the message cannot be encoded.
+    pub async fn broadcast_except(&self, except: PeerId, message: Message) -> Result<usize> {
+        Ok(self
+            .deliver(PreparedMessage::prepare(message).await?, None, Some(except))
+            .await)
+    }
+
+    /// Queue `prepared` for the members of `room`, or every peer if `None`.
+    async fn deliver(
+        &self,
+        prepared: PreparedMessage,
+        room: Option<&str>,
+        except: Option<PeerId>,
+    ) -> usize {
+        let mut delivered = 0;
+        let mut waiting = Vec::new();
+        let mut gone = Vec::new();
+
+        {
+            let peers = self.lock();
+            let targets: Vec<PeerId> = match room {
+                Some(room) => peers
+                    .rooms
+                    .get(room)
+                    .map(|members| members.iter().copied().collect())
+                    .unwrap_or_default(),
+                None => peers.map.keys().copied().collect(),
+            };
+
+            for id in targets {
+                if Some(id) == except {
+                    continue;
+                }
+                let Some(peer) = peers.map.get(&id) else {
+                    continue;
+                };
+                match peer.tx.try_send(prepared.clone()) {
+                    Ok(()) => delivered += 1,
+                    Err(TrySendError::Closed(_)) => gone.push(id),
+                    Err(TrySendError::Full(_)) => match peer.policy {
+                        Backpressure::DropMessage => {}
+                        Backpressure::Disconnect => gone.push(id),
+                        Backpressure::Wait => waiting.push((id, peer.tx.clone())),
+                    },
+                }
+            }
+        }
+
+        // The lock is released before waiting on slow peers
+        for (id, tx) in waiting {
+            match tx.send(prepared.clone()).await {
+                Ok(()) => delivered += 1,
+                Err(_) => gone.push(id),
+            }
+        }
+
+        if !gone.is_empty() {
+            let mut peers = self.lock();
+            for id in gone {
+                ws_debug!(peer = id.0, "removing peer from hub");
+                peers.remove(id);
+            }
+        }
+        delivered
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Peers> {
+        // Peers holds no invariants a panicking holder could break
+        self.peers.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Default for Hub {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Hub {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Hub")
+            .field("peers", &self.len())
+            .field("queue_size", &self.queue_size)
+            .finish()
+    }
+}
+
+/// A named group of peers within a [`Hub`], for topics, channels or
+/// presence.
+///
+/// Obtained from [`Hub::room`]. A peer may be in any number of rooms and
+/// leaves all of them when it is removed from the hub.
+#[derive(Clone)]
+pub struct Room {
+    hub: Hub,
+    name: Arc<str>,
+}
+
+impl Room {
+    /// The room's name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Add a subscribed peer. Returns `false` if it is unknown to the hub or
+    /// already a member.
+    pub fn join(&self, id: PeerId) -> bool {
+        self.hub.lock().join(&self.name, id)
+    }
+
+    /// Remove a member. Returns `false` if it was not one.
+    pub fn leave(&self, id: PeerId) -> bool {
+        self.hub.lock().leave(&self.name, id)
+    }
+
+    /// Whether `id` is a member.
+    pub fn contains(&self, id: PeerId) -> bool {
+        self.hub
+            .lock()
+            .rooms
+            .get(&self.name)
+            .is_some_and(|members| members.contains(&id))
+    }
+
+    /// The current members, in no particular order.
+    pub fn members(&self) -> Vec<PeerId> {
+        self.hub
+            .lock()
+            .rooms
+            .get(&self.name)
+            .map(|members| members.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// Number of members.
+    pub fn len(&self) -> usize {
+        self.hub
+            .lock()
+            .rooms
+            .get(&self.name)
+            .map_or(0, HashSet::len)
+    }
+
+    /// Whether the room has no members.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Queue a message for every member, with each member's backpressure
+    /// policy applied as in [`Hub::broadcast`].
+    ///
+    /// # Errors
+    ///
+    /// Fails only if the message cannot be encoded.
+    pub async fn broadcast(&self, message: Message) -> Result<usize> {
+        Ok(self
+            .hub
+            .deliver(
+                PreparedMessage::prepare(message).await?,
+                Some(&self.name),
+                None,
+            )
+            .await)
+    }
+
+    /// Queue a message for every member except `except`.
+    ///
+    /// # Errors
+    ///
+    /// Fails only if the message cannot be encoded.
+    pub async fn broadcast_except(&self, except: PeerId, message: Message) -> Result<usize> {
+        Ok(self
+            .hub
+            .deliver(
+                PreparedMessage::prepare(message).await?,
+                Some(&self.name),
+                Some(except),
+            )
+            .await)
+    }
+}
+
+impl fmt::Debug for Room {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Room")
+            .field("name", &self.name)
+            .field("members", &self.len())
+            .finish()
+    }
+}
+
+/// A peer's queue of broadcast messages.
+///
+/// Dropping the subscription removes the peer from its hub.
+pub struct Subscription {
+    id: PeerId,
+    rx: mpsc::Receiver<PreparedMessage>,
+    peers: Weak<Mutex<Peers>>,
+}
+
+impl Subscription {
+    /// This peer's identifier within the hub.
+    pub fn id(&self) -> PeerId {
+        self.id
+    }
+
+    /// Wait for the next broadcast message.
+    ///
+    /// Returns `None` once the peer has been removed from the hub (or the
+    /// hub dropped) and its queue is drained. Cancellation safe.
+    pub async fn recv(&mut self) -> Option<PreparedMessage> {
+        self.rx.recv().await
+    }
+
+    /// Take a queued message without waiting.
+    pub fn try_recv(&mut self) -> Option<PreparedMessage> {
+        self.rx.try_recv().ok()
+    }
+}
+
+impl fmt::Debug for Subscription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Subscription")
+            .field("id", &self.id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        if let Some(peers) = self.peers.upgrade() {
+            let mut peers = peers.lock().unwrap_or_else(|e| e.into_inner());
+            peers.remove(self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prepared_message_wire() {
+        let prepared = PreparedMessage::new(Message::text("hi")).unwrap();
+        assert_eq!(prepared.wire(), &[0x81, 0x02, b'h', b'i']);
+        assert_eq!(prepared.message(), &Message::text("hi"));
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_reaches_all_peers() {
+        let hub = Hub::new();
+        let mut a = hub.subscribe(Backpressure::DropMessage);
+        let mut b = hub.subscribe(Backpressure::DropMessage);
+        assert_eq!(hub.len(), 2);
+
+        assert_eq!(hub.broadcast(Message::text("all")).await.unwrap(), 2);
+        assert_eq!(a.recv().await.unwrap().message(), &Message::text("all"));
+        assert_eq!(b.recv().await.unwrap().message(), &Message::text("all"));
+
+        assert_eq!(
+            hub.broadcast_except(a.id(), Message::text("not a"))
+                .await
+                .unwrap(),
+            1
+        );
+        assert!(a.try_recv().is_none());
+        assert_eq!(b.recv().await.unwrap().message(), &Message::text("not a"));
+    }
+
+    #[tokio::test]
+    async fn test_drop_message_policy() {
+        let hub = Hub::new().with_queue_size(1);
+        let mut slow = hub.subscribe(Backpressure::DropMessage);
+
+        assert_eq!(hub.broadcast(Message::text("1")).await.unwrap(), 1);
+        assert_eq!(hub.broadcast(Message::text("2")).await.unwrap(), 0);
+
+        assert_eq!(slow.recv().await.unwrap().message(), &Message::text("1"));
+        assert!(slow.try_recv().is_none());
+        assert_eq!(hub.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_policy() {
+        let hub = Hub::new().with_queue_size(1);
+        let mut slow = hub.subscribe(Backpressure::Disconnect);
+        let mut fast = hub.subscribe(Backpressure::Disconnect);
+
+        hub.broadcast(Message::text("1")).await.unwrap();
+        fast.recv().await.unwrap();
+        assert_eq!(hub.broadcast(Message::text("2")).await.unwrap(), 1);
+
+        assert_eq!(hub.len(), 1);
+        assert_eq!(slow.recv().await.unwrap().message(), &Message::text("1"));
+        assert!(slow.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_wait_policy() {
+        let hub = Hub::new().with_queue_size(1);
+        let mut slow = hub.subscribe(Backpressure::Wait);
+        hub.broadcast(Message::text("1")).await.unwrap();
+
+        let sender = hub.clone();
+        let pending = tokio::spawn(async move { sender.broadcast(Message::text("2")).await });
+
+        assert_eq!(slow.recv().await.unwrap().message(), &Message::text("1"));
+        assert_eq!(pending.await.unwrap().unwrap(), 1);
+        assert_eq!(slow.recv().await.unwrap().message(), &Message::text("2"));
+    }
+
+    #[test]
+    fn test_dropping_subscription_unsubscribes() {
+        let hub = Hub::new();
+        let sub = hub.subscribe(Backpressure::default());
+        let id = sub.id();
+        assert!(!hub.is_empty());
+
+        drop(sub);
+        assert!(hub.is_empty());
+        assert!(!hub.remove(id));
+    }
+
+    #[tokio::test]
+    async fn test_room_broadcast() {
+        let hub = Hub::new();
+        let mut alice = hub.subscribe(Backpressure::default());
+        let mut bob = hub.subscribe(Backpressure::default());
+        let mut carol = hub.subscribe(Backpressure::default());
+
+        let rust = hub.room("rust");
+        assert!(rust.join(alice.id()));
+        assert!(rust.join(bob.id()));
+        assert!(!rust.join(bob.id()));
+        assert_eq!(rust.len(), 2);
+
+        assert_eq!(rust.broadcast(Message::text("hi")).await.unwrap(), 2);
+        assert_eq!(alice.recv().await.unwrap().message(), &Message::text("hi"));
+        assert_eq!(bob.recv().await.unwrap().message(), &Message::text("hi"));
+        assert!(carol.try_recv().is_none());
+
+        assert_eq!(
+            rust.broadcast_except(alice.id(), Message::text("from alice"))
+                .await
+                .unwrap(),
+            1
+        );
+        assert!(alice.try_recv().is_none());
+        assert!(bob.try_recv().is_some());
+
+        assert_eq!(
+            hub.room("empty")
+                .broadcast(Message::text("x"))
+                .await
+                .unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_room_membership() {
+        let hub = Hub::new();
+        let alice = hub.subscribe(Backpressure::default());
+        let bob = hub.subscribe(Backpressure::default());
+
+        hub.room("a").join(alice.id());
+        hub.room("b").join(alice.id());
+        hub.room("b").join(bob.id());
+
+        let mut rooms = hub.rooms_of(alice.id());
+        rooms.sort();
+        assert_eq!(rooms, ["a", "b"]);
+        assert!(hub.room("b").contains(bob.id()));
+
+        assert!(hub.room("a").leave(alice.id()));
+        assert!(!hub.room("a").leave(alice.id()));
+        assert_eq!(hub.rooms(), ["b"]);
+
+        // Leaving the hub leaves every room
+        drop(alice);
+        assert_eq!(hub.room("b").members(), [bob.id()]);
+        drop(bob);
+        assert!(hub.rooms().is_empty());
+        assert!(!hub.room("b").join(PeerId(1)));
+    }
+}
@@ -21,32 +21,98 @@
 //! let conn = Connection::new(stream, Role::Client, config).await?;
 //! ```
 
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+#[macro_use]
+mod trace;
+
+#[cfg(feature = "std")]
 pub mod config;
+#[cfg(feature = "std")]
 pub mod connection;
 pub mod error;
+#[cfg(feature = "std")]
 pub mod extensions;
+#[cfg(feature = "std")]
 pub mod message;
 pub mod protocol;
 
+#[cfg(feature = "capture")]
+pub mod capture;
+
 #[cfg(feature = "async-tokio")]
 pub mod codec;
 
+#[cfg(feature = "async-tokio")]
+pub mod client;
+
+#[cfg(all(feature = "ffi", unix))]
+pub mod ffi;
+
+#[cfg(feature = "async-tokio")]
+pub mod hub;
+
+#[cfg(feature = "async-tokio")]
+pub mod server;
+
+#[cfg(feature = "async-tokio")]
+pub mod socket;
+
+#[cfg(feature = "async-tokio")]
+mod task;
+
+#[cfg(all(feature = "async-tokio", any(test, feature = "testing")))]
+pub mod testing;
+
+#[cfg(feature = "metrics")]
+pub mod metrics;
+
+#[cfg(feature = "tungstenite")]
+pub mod tungstenite;
+
+#[cfg(feature = "tower")]
+pub mod tower;
+
+#[cfg(feature = "serde")]
+pub mod typed;
+
 pub use bytes::Bytes;
-pub use config::{Config, Limits};
+#[cfg(feature = "std")]
+pub use config::{Config, ConfigBuilder, ConfigError, InboundRate, Keepalive, Limits};
 #[cfg(feature = "async-tokio")]
-pub use connection::Connection;
-pub use connection::{ConnectionState, Role};
-pub use error::{Error, Result};
-pub use message::{CloseCode, CloseFrame, Message};
+pub use connection::{
+    CloseOnDrop, Connection, ConnectionParts, Handler, HandlerFuture, MessageSink, Priority,
+    WsHandle,
+};
+#[cfg(feature = "std")]
+pub use connection::{
+    ConnectionEvent, ConnectionObserver, ConnectionState, Fragmenter, FrameAction,
+    FrameInterceptor, Latency, Observer, Role,
+};
+#[cfg(feature = "std")]
+pub use error::Source;
+pub use error::{Error, ErrorKind, Result};
+#[cfg(feature = "std")]
+pub use message::{CloseCode, CloseFrame, Message, MessageOptions, MessageRef};
 pub use protocol::{HandshakeRequest, HandshakeResponse, OpCode, WS_GUID, compute_accept_key};
+#[cfg(feature = "std")]
+pub use protocol::{SpilledMessage, generate_key};
 
 #[cfg(feature = "async-tokio")]
 pub use codec::WebSocketCodec;
+#[cfg(feature = "async-tokio")]
+pub use socket::SocketOptions;
 
-#[cfg(feature = "tls-rustls")]
+#[cfg(any(
+    feature = "tls-rustls",
+    feature = "tls-native",
+    feature = "tls-openssl"
+))]
 pub mod tls;
 
-#[cfg(test)]
+#[cfg(all(test, feature = "std"))]
 mod tests {
     use super::*;
 
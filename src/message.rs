@@ -2,6 +2,10 @@
 
 use bytes::Bytes;
 
+use crate::error::Error;
+use crate::protocol::OpCode;
+use crate::protocol::spill::SpilledMessage;
+
 /// WebSocket close status code per RFC 6455 Section 7.4.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
 #[non_exhaustive]
@@ -25,7 +29,22 @@ pub enum CloseCode {
     MandatoryExtension,
     /// Internal error (1011). Server encountered an unexpected condition.
     InternalError,
-    /// Custom close code (3000-4999 for applications, 1012-1014 for registered codes).
+    /// Service restart (1012). Server is restarting; the client may reconnect.
+    ServiceRestart,
+    /// Try again later (1013). Server is temporarily overloaded.
+    TryAgainLater,
+    /// Bad gateway (1014). A gateway or proxy received an invalid upstream response.
+    BadGateway,
+    /// No status received (1005). Reported locally for a close frame without a
+    /// code; never sent.
+    NoStatusReceived,
+    /// Abnormal closure (1006). Reported locally when the connection dropped
+    /// without a close frame; never sent.
+    AbnormalClosure,
+    /// TLS handshake failure (1015). Reported locally; never sent.
+    TlsHandshake,
+    /// Any other code: 3000-3999 registered for libraries and frameworks,
+    /// 4000-4999 for private use, everything else unassigned.
     Other(u16),
 }
 
@@ -43,6 +62,12 @@ impl CloseCode {
             1009 => CloseCode::MessageTooBig,
             1010 => CloseCode::MandatoryExtension,
             1011 => CloseCode::InternalError,
+            1012 => CloseCode::ServiceRestart,
+            1013 => CloseCode::TryAgainLater,
+            1014 => CloseCode::BadGateway,
+            1005 => CloseCode::NoStatusReceived,
+            1006 => CloseCode::AbnormalClosure,
+            1015 => CloseCode::TlsHandshake,
             other => CloseCode::Other(other),
         }
     }
@@ -60,6 +85,12 @@ impl CloseCode {
             CloseCode::MessageTooBig => 1009,
             CloseCode::MandatoryExtension => 1010,
             CloseCode::InternalError => 1011,
+            CloseCode::ServiceRestart => 1012,
+            CloseCode::TryAgainLater => 1013,
+            CloseCode::BadGateway => 1014,
+            CloseCode::NoStatusReceived => 1005,
+            CloseCode::AbnormalClosure => 1006,
+            CloseCode::TlsHandshake => 1015,
             CloseCode::Other(code) => *code,
         }
     }
@@ -92,6 +123,55 @@ impl CloseCode {
         let code = self.as_u16();
         matches!(code, 1004..=1006 | 1015)
     }
+
+    /// Check if this code is in the 3000-3999 range, registered with IANA for
+    /// libraries, frameworks and applications.
+    #[must_use]
+    pub const fn is_library(&self) -> bool {
+        matches!(self.as_u16(), 3000..=3999)
+    }
+
+    /// Check if this code is in the 4000-4999 range, for private use by
+    /// applications without registration.
+    #[must_use]
+    pub const fn is_private(&self) -> bool {
+        matches!(self.as_u16(), 4000..=4999)
+    }
+
+    /// Check if this code is defined by the WebSocket protocol itself
+    /// (1000-2999), whether or not it may be sent.
+    #[must_use]
+    pub const fn is_protocol(&self) -> bool {
+        matches!(self.as_u16(), 1000..=2999)
+    }
+
+    /// Return the code if it may appear in a Close frame, or
+    /// `Error::InvalidCloseCode` if not.
+    ///
+    /// Applied to codes before they are sent and to codes read from the peer.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidCloseCode` for reserved and unassigned codes.
+    pub const fn validate(self) -> Result<Self, Error> {
+        if self.is_valid() {
+            Ok(self)
+        } else {
+            Err(Error::InvalidCloseCode(self.as_u16()))
+        }
+    }
+}
+
+impl From<u16> for CloseCode {
+    fn from(code: u16) -> Self {
+        Self::from_u16(code)
+    }
+}
+
+impl From<CloseCode> for u16 {
+    fn from(code: CloseCode) -> Self {
+        code.as_u16()
+    }
 }
 
 /// Close frame containing status code and optional reason.
@@ -128,6 +208,11 @@ pub enum Message {
     Pong(Bytes),
     /// A close frame (control frame, may include status code and reason).
     Close(Option<CloseFrame>),
+    /// A text or binary message stored outside memory, see
+    /// [`Config::with_spill`](crate::Config::with_spill).
+    ///
+    /// Sending one streams the payload back from its store.
+    Spilled(SpilledMessage),
 }
 
 impl Message {
@@ -173,10 +258,13 @@ impl Message {
         matches!(self, Message::Binary(_))
     }
 
-    /// Returns `true` if this is a data message (text or binary).
+    /// Returns `true` if this is a data message (text, binary or spilled).
     #[must_use]
     pub const fn is_data(&self) -> bool {
-        matches!(self, Message::Text(_) | Message::Binary(_))
+        matches!(
+            self,
+            Message::Text(_) | Message::Binary(_) | Message::Spilled(_)
+        )
     }
 
     /// Returns `true` if this is a control message (ping, pong, or close).
@@ -188,6 +276,19 @@ impl Message {
         )
     }
 
+    /// The opcode of the frame this message is sent in.
+    #[must_use]
+    pub fn opcode(&self) -> OpCode {
+        match self {
+            Message::Text(_) => OpCode::Text,
+            Message::Binary(_) => OpCode::Binary,
+            Message::Ping(_) => OpCode::Ping,
+            Message::Pong(_) => OpCode::Pong,
+            Message::Close(_) => OpCode::Close,
+            Message::Spilled(spilled) => spilled.opcode(),
+        }
+    }
+
     /// Consume and return the text content, if this is a text message.
     #[must_use]
     pub fn into_text(self) -> Option<String> {
@@ -228,6 +329,7 @@ impl Message {
     ///
     /// Returns the text content as bytes for Text messages,
     /// the binary data for Binary messages, or the control frame data.
+    /// Spilled payloads are not in memory, so they return an empty slice.
     #[inline]
     #[must_use]
     pub fn payload(&self) -> &[u8] {
@@ -237,30 +339,178 @@ impl Message {
             Message::Ping(b) => b,
             Message::Pong(b) => b,
             Message::Close(Some(cf)) => cf.reason.as_bytes(),
-            Message::Close(None) => &[],
+            Message::Close(None) | Message::Spilled(_) => &[],
         }
     }
 
-    /// Get the length of the payload in bytes.
+    /// Get the length of the payload in bytes, including spilled payloads.
     #[inline]
     #[must_use]
     pub fn len(&self) -> usize {
-        self.payload().len()
+        match self {
+            Message::Spilled(spilled) => usize::try_from(spilled.len()).unwrap_or(usize::MAX),
+            _ => self.payload().len(),
+        }
     }
 
     /// Check if the message payload is empty.
     #[inline]
     #[must_use]
     pub fn is_empty(&self) -> bool {
-        self.payload().is_empty()
+        self.len() == 0
+    }
+
+    /// Borrow the spilled message, if this is one.
+    #[must_use]
+    pub fn as_spilled(&self) -> Option<&SpilledMessage> {
+        match self {
+            Message::Spilled(spilled) => Some(spilled),
+            _ => None,
+        }
     }
 }
 
-use crate::protocol::Frame;
+/// A received message that borrows its payload, from
+/// [`Connection::recv_ref`](crate::Connection::recv_ref).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum MessageRef<'a> {
+    /// A text message.
+    Text(&'a str),
+    /// A binary message.
+    Binary(&'a [u8]),
+    /// A ping frame.
+    Ping(&'a [u8]),
+    /// A pong frame.
+    Pong(&'a [u8]),
+    /// A close frame.
+    Close(Option<&'a CloseFrame>),
+    /// A spilled message.
+    Spilled(&'a SpilledMessage),
+}
+
+impl MessageRef<'_> {
+    /// Returns `true` if this is a text message.
+    #[must_use]
+    pub const fn is_text(&self) -> bool {
+        matches!(self, MessageRef::Text(_))
+    }
+
+    /// Returns `true` if this is a binary message.
+    #[must_use]
+    pub const fn is_binary(&self) -> bool {
+        matches!(self, MessageRef::Binary(_))
+    }
+
+    /// Returns `true` if this is a control message (ping, pong, or close).
+    #[must_use]
+    pub const fn is_control(&self) -> bool {
+        matches!(
+            self,
+            MessageRef::Ping(_) | MessageRef::Pong(_) | MessageRef::Close(_)
+        )
+    }
 
-impl From<Message> for Frame {
-    fn from(message: Message) -> Self {
+    /// The text content, if this is a text message.
+    #[must_use]
+    pub fn as_text(&self) -> Option<&str> {
+        match self {
+            MessageRef::Text(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// The payload bytes, as for [`Message::payload`].
+    #[must_use]
+    pub fn payload(&self) -> &[u8] {
+        match self {
+            MessageRef::Text(s) => s.as_bytes(),
+            MessageRef::Binary(b) | MessageRef::Ping(b) | MessageRef::Pong(b) => b,
+            MessageRef::Close(Some(cf)) => cf.reason.as_bytes(),
+            MessageRef::Close(None) | MessageRef::Spilled(_) => &[],
+        }
+    }
+
+    /// Copy into an owned [`Message`].
+    #[must_use]
+    pub fn to_message(&self) -> Message {
+        match *self {
+            MessageRef::Text(s) => Message::Text(s.to_owned()),
+            MessageRef::Binary(b) => Message::Binary(Bytes::copy_from_slice(b)),
+            MessageRef::Ping(b) => Message::Ping(Bytes::copy_from_slice(b)),
+            MessageRef::Pong(b) => Message::Pong(Bytes::copy_from_slice(b)),
+            MessageRef::Close(cf) => Message::Close(cf.cloned()),
+            MessageRef::Spilled(spilled) => Message::Spilled(spilled.clone()),
+        }
+    }
+}
+
+impl<'a> From<&'a Message> for MessageRef<'a> {
+    fn from(message: &'a Message) -> Self {
         match message {
+            Message::Text(s) => MessageRef::Text(s),
+            Message::Binary(b) => MessageRef::Binary(b),
+            Message::Ping(b) => MessageRef::Ping(b),
+            Message::Pong(b) => MessageRef::Pong(b),
+            Message::Close(cf) => MessageRef::Close(cf.as_ref()),
+            Message::Spilled(spilled) => MessageRef::Spilled(spilled),
+        }
+    }
+}
+
+/// Per-message send options, for
+/// [`Connection::send_with`](crate::Connection::send_with).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct MessageOptions {
+    /// Run the message through a negotiated per-message compression
+    /// extension such as permessage-deflate. Turn off for payloads that are
+    /// already compressed or encrypted, where deflate only costs CPU.
+    ///
+    /// Default: true
+    pub compress: bool,
+}
+
+impl Default for MessageOptions {
+    fn default() -> Self {
+        Self { compress: true }
+    }
+}
+
+impl MessageOptions {
+    /// Default options.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Options that skip per-message compression.
+    #[must_use]
+    pub fn uncompressed() -> Self {
+        Self { compress: false }
+    }
+
+    /// Set whether the message is compressed.
+    #[must_use]
+    pub fn with_compress(mut self, compress: bool) -> Self {
+        self.compress = compress;
+        self
+    }
+}
+
+use crate::protocol::Frame;
+
+impl TryFrom<Message> for Frame {
+    type Error = Error;
+
+    /// A [`Message::Spilled`] payload is read into memory;
+    /// `Connection::send` streams it instead.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Io`] if a spilled payload cannot be read back.
+    fn try_from(message: Message) -> Result<Self, Error> {
+        Ok(match message {
             Message::Text(text) => Frame::text(text.into_bytes()),
             Message::Binary(data) => Frame::binary_from_bytes(data),
             Message::Ping(data) => Frame::ping(data.to_vec()),
@@ -272,7 +522,8 @@ impl From<Message> for Frame {
                     Frame::close(None, "")
                 }
             }
-        }
+            Message::Spilled(spilled) => Frame::new(true, spilled.opcode(), spilled.read_to_vec()?),
+        })
     }
 }
 
@@ -444,6 +695,67 @@ mod tests {
         assert!(!CloseCode::Other(3000).is_reserved());
     }
 
+    #[test]
+    fn test_close_code_registry_round_trip() {
+        for code in (0..=5000).chain([u16::MAX]) {
+            assert_eq!(CloseCode::from_u16(code).as_u16(), code);
+        }
+        assert_eq!(CloseCode::from(1012), CloseCode::ServiceRestart);
+        assert_eq!(CloseCode::from(1013), CloseCode::TryAgainLater);
+        assert_eq!(CloseCode::from(1014), CloseCode::BadGateway);
+        assert_eq!(CloseCode::from(1015), CloseCode::TlsHandshake);
+        assert_eq!(u16::from(CloseCode::AbnormalClosure), 1006);
+    }
+
+    #[test]
+    fn test_close_code_ranges() {
+        assert!(CloseCode::Other(3000).is_library());
+        assert!(CloseCode::Other(3999).is_library());
+        assert!(!CloseCode::Other(4000).is_library());
+        assert!(CloseCode::Other(4000).is_private());
+        assert!(CloseCode::Other(4999).is_private());
+        assert!(!CloseCode::Other(5000).is_private());
+        assert!(CloseCode::Normal.is_protocol());
+        assert!(CloseCode::TlsHandshake.is_protocol());
+        assert!(!CloseCode::Other(3000).is_protocol());
+    }
+
+    #[test]
+    fn test_close_code_validate() {
+        assert_eq!(CloseCode::BadGateway.validate(), Ok(CloseCode::BadGateway));
+        assert_eq!(
+            CloseCode::Other(4001).validate(),
+            Ok(CloseCode::Other(4001))
+        );
+        assert_eq!(
+            CloseCode::NoStatusReceived.validate(),
+            Err(Error::InvalidCloseCode(1005))
+        );
+        assert_eq!(
+            CloseCode::Other(2000).validate(),
+            Err(Error::InvalidCloseCode(2000))
+        );
+    }
+
+    #[test]
+    fn test_message_ref_round_trip() {
+        let messages = [
+            Message::text("hi"),
+            Message::binary(vec![1, 2]),
+            Message::ping(vec![3]),
+            Message::pong(vec![]),
+            Message::close(CloseCode::Normal, "bye"),
+            Message::Close(None),
+        ];
+        for message in &messages {
+            let borrowed = MessageRef::from(message);
+            assert_eq!(borrowed.payload(), message.payload());
+            assert_eq!(borrowed.is_control(), message.is_control());
+            assert_eq!(&borrowed.to_message(), message);
+        }
+        assert_eq!(MessageRef::Text("hi").as_text(), Some("hi"));
+    }
+
     #[test]
     fn test_message_is_text() {
         assert!(Message::text("hello").is_text());
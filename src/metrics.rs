@@ -0,0 +1,309 @@
+//! Process-wide connection metrics.
+//!
+//! With the `metrics` feature enabled, every [`Connection`](crate::Connection)
+//! reports into the [`metrics`](https://docs.rs/metrics) facade. Nothing is
+//! exported until the application installs a recorder, e.g.
+//! `metrics-exporter-prometheus`:
+//!
+//! ```rust,ignore
+//! metrics_exporter_prometheus::PrometheusBuilder::new().install()?;
+//! rsws::metrics::describe();
+//! ```
+//!
+//! Counters are monotonic totals; rates such as messages/s or bytes/s are
+//! derived by the backend (`rate(rsws_messages_sent_total[1m])` in PromQL).
+
+#[cfg(feature = "async-tokio")]
+use std::time::Duration;
+
+use metrics::{Unit, describe_counter, describe_gauge, describe_histogram};
+#[cfg(feature = "async-tokio")]
+use metrics::{counter, gauge, histogram};
+
+#[cfg(feature = "async-tokio")]
+use crate::protocol::{Frame, OpCode};
+
+/// Gauge: connections currently alive.
+pub const CONNECTIONS_OPEN: &str = "rsws_connections_open";
+/// Counter: connections created.
+pub const CONNECTIONS_TOTAL: &str = "rsws_connections_total";
+/// Counter: complete data messages sent.
+pub const MESSAGES_SENT: &str = "rsws_messages_sent_total";
+/// Counter: complete data messages received.
+pub const MESSAGES_RECEIVED: &str = "rsws_messages_received_total";
+/// Counter: frame payload bytes sent.
+pub const BYTES_SENT: &str = "rsws_bytes_sent_total";
+/// Counter: frame payload bytes received.
+pub const BYTES_RECEIVED: &str = "rsws_bytes_received_total";
+/// Counter: close frames, labelled by `direction` (`sent`/`received`) and
+/// `code`.
+pub const CLOSE_FRAMES: &str = "rsws_close_frames_total";
+/// Counter: client and server handshakes that failed, once per attempt.
+pub const HANDSHAKE_FAILURES: &str = "rsws_handshake_failures_total";
+/// Histogram: ping round-trip time in seconds.
+pub const PING_RTT: &str = "rsws_ping_rtt_seconds";
+/// Gauge: background tasks the crate has spawned that are still running,
+/// labelled by `task` name (e.g. `rsws::server::handshake`).
+pub const TASKS_ACTIVE: &str = "rsws_tasks_active";
+
+/// Register descriptions and units for all metrics with the installed
+/// recorder.
+///
+/// Optional; call it once after installing the recorder so exporters can
+/// emit `HELP` text.
+pub fn describe() {
+    describe_gauge!(CONNECTIONS_OPEN, "WebSocket connections currently open");
+    describe_counter!(CONNECTIONS_TOTAL, "WebSocket connections created");
+    describe_counter!(MESSAGES_SENT, "WebSocket data messages sent");
+    describe_counter!(MESSAGES_RECEIVED, "WebSocket data messages received");
+    describe_counter!(BYTES_SENT, Unit::Bytes, "WebSocket payload bytes sent");
+    describe_counter!(
+        BYTES_RECEIVED,
+        Unit::Bytes,
+        "WebSocket payload bytes received"
+    );
+    describe_counter!(CLOSE_FRAMES, "WebSocket close frames by direction and code");
+    describe_counter!(HANDSHAKE_FAILURES, "WebSocket handshakes rejected");
+    describe_histogram!(PING_RTT, Unit::Seconds, "WebSocket ping round-trip time");
+    describe_gauge!(TASKS_ACTIVE, "Background tasks running, by task name");
+}
+
+/// Keeps [`CONNECTIONS_OPEN`] in step with a connection's lifetime.
+#[cfg(feature = "async-tokio")]
+#[derive(Debug)]
+pub(crate) struct ConnectionGuard(());
+
+#[cfg(feature = "async-tokio")]
+impl ConnectionGuard {
+    pub(crate) fn new() -> Self {
+        counter!(CONNECTIONS_TOTAL).increment(1);
+        gauge!(CONNECTIONS_OPEN).increment(1.0);
+        Self(())
+    }
+}
+
+#[cfg(feature = "async-tokio")]
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        gauge!(CONNECTIONS_OPEN).decrement(1.0);
+    }
+}
+
+/// Keeps [`TASKS_ACTIVE`] in step with a task's lifetime.
+#[cfg(feature = "async-tokio")]
+#[derive(Debug)]
+pub(crate) struct TaskGuard(&'static str);
+
+#[cfg(feature = "async-tokio")]
+impl TaskGuard {
+    pub(crate) fn new(name: &'static str) -> Self {
+        gauge!(TASKS_ACTIVE, "task" => name).increment(1.0);
+        Self(name)
+    }
+}
+
+#[cfg(feature = "async-tokio")]
+impl Drop for TaskGuard {
+    fn drop(&mut self) {
+        gauge!(TASKS_ACTIVE, "task" => self.0).decrement(1.0);
+    }
+}
+
+/// Direction of a frame relative to this endpoint.
+#[cfg(feature = "async-tokio")]
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Direction {
+    Sent,
+    Received,
+}
+
+/// Account for one frame written to or read from the wire.
+///
+/// A data message is counted once, on its final frame.
+#[cfg(feature = "async-tokio")]
+pub(crate) fn record_frame(direction: Direction, frame: &Frame) {
+    record_payload(direction, frame.opcode, frame.fin, frame.payload());
+}
+
+/// Like [`record_frame`], for a frame whose payload was read in place.
+#[cfg(feature = "async-tokio")]
+pub(crate) fn record_payload(direction: Direction, opcode: OpCode, fin: bool, payload: &[u8]) {
+    let (messages, bytes, label) = match direction {
+        Direction::Sent => (MESSAGES_SENT, BYTES_SENT, "sent"),
+        Direction::Received => (MESSAGES_RECEIVED, BYTES_RECEIVED, "received"),
+    };
+
+    counter!(bytes).increment(payload.len() as u64);
+
+    match opcode {
+        OpCode::Text | OpCode::Binary | OpCode::Continuation if fin => {
+            counter!(messages).increment(1);
+        }
+        OpCode::Close => {
+            let code = match payload {
+                [hi, lo, ..] => u16::from_be_bytes([*hi, *lo]).to_string(),
+                _ => "none".to_string(),
+            };
+            counter!(CLOSE_FRAMES, "direction" => label, "code" => code).increment(1);
+        }
+        _ => {}
+    }
+}
+
+/// Count a failed handshake.
+#[cfg(feature = "async-tokio")]
+pub(crate) fn record_handshake_failure() {
+    counter!(HANDSHAKE_FAILURES).increment(1);
+}
+
+/// Record a ping round-trip sample.
+#[cfg(feature = "async-tokio")]
+pub(crate) fn record_ping_rtt(sample: Duration) {
+    histogram!(PING_RTT).record(sample.as_secs_f64());
+}
+
+#[cfg(all(test, feature = "async-tokio"))]
+mod tests {
+    use super::*;
+    use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+
+    fn counter_value(
+        snapshot: &[(
+            metrics_util::CompositeKey,
+            Option<Unit>,
+            Option<metrics::SharedString>,
+            DebugValue,
+        )],
+        name: &str,
+    ) -> u64 {
+        snapshot
+            .iter()
+            .filter(|(key, ..)| key.key().name() == name)
+            .map(|(.., value)| match value {
+                DebugValue::Counter(n) => *n,
+                _ => 0,
+            })
+            .sum()
+    }
+
+    #[test]
+    fn test_record_frame_counts_messages_once() {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+
+        metrics::with_local_recorder(&recorder, || {
+            let mut first = Frame::text(b"hello ".to_vec());
+            first.fin = false;
+            let last = Frame::new(true, OpCode::Continuation, b"world".to_vec());
+
+            record_frame(Direction::Sent, &first);
+            record_frame(Direction::Sent, &last);
+            record_frame(Direction::Received, &Frame::binary(vec![0; 4]));
+        });
+
+        let snapshot = snapshotter.snapshot().into_vec();
+        assert_eq!(counter_value(&snapshot, MESSAGES_SENT), 1);
+        assert_eq!(counter_value(&snapshot, BYTES_SENT), 11);
+        assert_eq!(counter_value(&snapshot, MESSAGES_RECEIVED), 1);
+        assert_eq!(counter_value(&snapshot, BYTES_RECEIVED), 4);
+    }
+
+    #[test]
+    fn test_task_guard_tracks_running_tasks() {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+
+        let running = metrics::with_local_recorder(&recorder, || {
+            let first = TaskGuard::new("rsws::server::handshake");
+            let _second = TaskGuard::new("rsws::server::handshake");
+            drop(first);
+            snapshotter.snapshot().into_vec()
+        });
+
+        let (key, .., value) = running
+            .iter()
+            .find(|(key, ..)| key.key().name() == TASKS_ACTIVE)
+            .unwrap();
+        assert!(matches!(value, DebugValue::Gauge(v) if v.into_inner() == 1.0));
+        assert!(
+            key.key()
+                .labels()
+                .any(|l| l.key() == "task" && l.value() == "rsws::server::handshake")
+        );
+    }
+
+    #[test]
+    fn test_record_close_code() {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+
+        metrics::with_local_recorder(&recorder, || {
+            record_frame(Direction::Received, &Frame::close(Some(1001), "bye"));
+        });
+
+        let snapshot = snapshotter.snapshot().into_vec();
+        let (key, ..) = snapshot
+            .iter()
+            .find(|(key, ..)| key.key().name() == CLOSE_FRAMES)
+            .unwrap();
+        let labels: Vec<_> = key
+            .key()
+            .labels()
+            .map(|l| (l.key().to_string(), l.value().to_string()))
+            .collect();
+        assert!(labels.contains(&("code".to_string(), "1001".to_string())));
+        assert!(labels.contains(&("direction".to_string(), "received".to_string())));
+    }
+
+    #[test]
+    fn test_connection_guard_tracks_open_gauge() {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+
+        metrics::with_local_recorder(&recorder, || {
+            let guard = ConnectionGuard::new();
+            drop(guard);
+            let _open = ConnectionGuard::new();
+
+            let snapshot = snapshotter.snapshot().into_vec();
+            let open = snapshot.iter().find_map(|(key, .., value)| match value {
+                DebugValue::Gauge(v) if key.key().name() == CONNECTIONS_OPEN => Some(v.0),
+                _ => None,
+            });
+            assert_eq!(open, Some(1.0));
+            assert_eq!(counter_value(&snapshot, CONNECTIONS_TOTAL), 2);
+        });
+    }
+
+    #[test]
+    fn test_handshake_failure_counted_once() {
+        use tokio::io::AsyncWriteExt;
+
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        metrics::with_local_recorder(&recorder, || {
+            runtime.block_on(async {
+                // Parses, then fails validation
+                let (mut client, server) = tokio::io::duplex(4096);
+                client
+                    .write_all(
+                        b"GET / HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\n\
+                          Connection: Upgrade\r\nSec-WebSocket-Key: short\r\n\
+                          Sec-WebSocket-Version: 13\r\n\r\n",
+                    )
+                    .await
+                    .unwrap();
+                let config = crate::Config::server();
+                assert!(crate::server::handshake(server, config).await.is_err());
+            });
+        });
+
+        let snapshot = snapshotter.snapshot().into_vec();
+        assert_eq!(counter_value(&snapshot, HANDSHAKE_FAILURES), 1);
+    }
+}
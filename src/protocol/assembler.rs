@@ -1,18 +1,27 @@
 //! Message fragmentation and reassembly for WebSocket (RFC 6455).
 
+use std::sync::Arc;
+
 use bytes::{Bytes, BytesMut};
 
 use crate::config::Config;
 use crate::error::{Error, Result};
+use crate::protocol::spill::{SpillChunk, SpillSink, SpillStep, SpillWriter, SpilledMessage};
 use crate::protocol::utf8::Utf8Validator;
 use crate::protocol::{Frame, OpCode};
 
 /// Reassembles fragmented WebSocket messages.
 ///
 /// Handles message fragmentation per RFC 6455, including UTF-8 validation
-/// for text messages and enforcement of size/fragment limits.
+/// for text messages and enforcement of size/fragment limits. Messages
+/// larger than the configured [`Spill`](crate::protocol::Spill) threshold
+/// are streamed to its sink instead of the buffer.
 pub struct MessageAssembler {
     buffer: BytesMut,
+    /// Whether the message in progress is being spilled.
+    spilling: bool,
+    /// The writer of a message spilled by [`push`](Self::push).
+    spill: Option<Box<dyn SpillWriter>>,
     fragment_count: usize,
     opcode: Option<OpCode>,
     total_size: usize,
@@ -27,6 +36,8 @@ impl MessageAssembler {
     pub fn new(config: Config) -> Self {
         Self {
             buffer: BytesMut::new(),
+            spilling: false,
+            spill: None,
             fragment_count: 0,
             opcode: None,
             total_size: 0,
@@ -47,9 +58,32 @@ impl MessageAssembler {
     /// - `Error::TooManyFragments` if fragment limit exceeded
     /// - `Error::MessageTooLarge` if message size limit exceeded
     /// - `Error::InvalidUtf8` if text message contains invalid UTF-8
+    /// - `Error::Io` if spilling fails
     pub fn push(&mut self, frame: Frame) -> Result<Option<AssembledMessage>> {
+        let chunk = match self.push_frame(frame)? {
+            Pushed::Message(message) => return Ok(message),
+            Pushed::Spill(chunk) => chunk,
+        };
+        match chunk.write(self.spill.take())? {
+            SpillStep::Open(writer) => {
+                self.spill = Some(writer);
+                Ok(None)
+            }
+            SpillStep::Done(spilled) => Ok(Some(AssembledMessage {
+                opcode: spilled.opcode(),
+                payload: Bytes::new(),
+                rsv1: false,
+                spilled: Some(spilled),
+            })),
+        }
+    }
+
+    /// Add a frame as [`push`](Self::push), but hand payload to be spilled
+    /// back to the caller instead of writing it, so that the blocking write
+    /// can be moved off an async task.
+    pub(crate) fn push_frame(&mut self, frame: Frame) -> Result<Pushed> {
         if frame.opcode.is_control() {
-            return Ok(None);
+            return Ok(Pushed::Message(None));
         }
 
         if frame.opcode == OpCode::Continuation {
@@ -58,6 +92,12 @@ impl MessageAssembler {
                     "Unexpected continuation frame".into(),
                 ));
             }
+            // RFC 7692: only the first frame of a message carries RSV1
+            if frame.rsv1 {
+                return Err(Error::ProtocolViolation(
+                    "RSV1 set on continuation frame".into(),
+                ));
+            }
         } else {
             if self.opcode.is_some() {
                 return Err(Error::ProtocolViolation(
@@ -67,7 +107,8 @@ impl MessageAssembler {
             self.opcode = Some(frame.opcode);
             self.first_frame_rsv1 = frame.rsv1;
 
-            if frame.opcode == OpCode::Text {
+            // Compressed text is validated once decoded, lossy text never
+            if frame.opcode == OpCode::Text && !frame.rsv1 && !self.config.compat.lossy_utf8 {
                 self.utf8_validator = Some(Utf8Validator::new());
             }
         }
@@ -79,11 +120,20 @@ impl MessageAssembler {
         let new_size = self.total_size + frame.payload().len();
         self.config.limits.check_message_size(new_size)?;
 
+        self.total_size = new_size;
+
+        let sink = if !self.spilling && !self.first_frame_rsv1 && self.config.spills(new_size) {
+            self.start_spill()?
+        } else {
+            None
+        };
+
         if let Some(ref mut validator) = self.utf8_validator {
             validator.validate(frame.payload(), frame.fin)?;
         }
-
-        self.total_size = new_size;
+        if self.spilling {
+            return self.spill_frame(frame, sink).map(Pushed::Spill);
+        }
 
         // Fast path: single-frame message — skip buffer entirely
         if frame.fin && self.fragment_count == 0 {
@@ -95,11 +145,12 @@ impl MessageAssembler {
             })?;
             let rsv1 = self.first_frame_rsv1;
             self.reset_state();
-            return Ok(Some(AssembledMessage {
+            return Ok(Pushed::Message(Some(AssembledMessage {
                 opcode,
                 payload,
                 rsv1,
-            }));
+                spilled: None,
+            })));
         }
 
         // Multi-frame: accumulate in buffer
@@ -119,14 +170,61 @@ impl MessageAssembler {
             })?;
             let rsv1 = self.first_frame_rsv1;
             self.reset_state();
-            Ok(Some(AssembledMessage {
+            Ok(Pushed::Message(Some(AssembledMessage {
                 opcode,
                 payload,
                 rsv1,
-            }))
+                spilled: None,
+            })))
         } else {
-            Ok(None)
+            Ok(Pushed::Message(None))
+        }
+    }
+
+    /// Switch the message in progress to spilling, returning the sink to
+    /// create its writer with.
+    fn start_spill(&mut self) -> Result<Option<Arc<dyn SpillSink>>> {
+        let (Some(spill), Some(opcode)) = (&self.config.spill, self.opcode) else {
+            return Ok(None);
+        };
+        // Spilled text cannot be repaired, so it is validated even when
+        // lossy UTF-8 is allowed
+        if opcode == OpCode::Text && self.utf8_validator.is_none() {
+            let mut validator = Utf8Validator::new();
+            validator.validate(&self.buffer, false)?;
+            self.utf8_validator = Some(validator);
         }
+        self.spilling = true;
+        Ok(Some(Arc::clone(&spill.sink)))
+    }
+
+    /// The chunk to spill for `frame`, led by the message buffered so far
+    /// when spilling just started.
+    fn spill_frame(
+        &mut self,
+        frame: Frame,
+        sink: Option<Arc<dyn SpillSink>>,
+    ) -> Result<SpillChunk> {
+        let opcode = self.opcode.ok_or_else(|| {
+            Error::ProtocolViolation("Internal error: no message being spilled".into())
+        })?;
+        let mut data = Vec::with_capacity(2);
+        if !self.buffer.is_empty() {
+            data.push(self.buffer.split().freeze());
+            if self.buffer.capacity() > 64 * 1024 {
+                self.buffer = BytesMut::new();
+            }
+        }
+        let fin = frame.fin;
+        data.push(frame.into_payload_bytes());
+        self.fragment_count += 1;
+
+        let len = fin.then_some(self.total_size as u64);
+        if fin {
+            self.opcode = None;
+            self.reset_state();
+        }
+        Ok(SpillChunk::new(opcode, sink, data, len))
     }
 
     /// Returns `true` if a message is currently being assembled.
@@ -135,6 +233,7 @@ impl MessageAssembler {
     }
 
     fn reset_state(&mut self) {
+        self.spilling = false;
         self.total_size = 0;
         self.fragment_count = 0;
         self.utf8_validator = None;
@@ -144,6 +243,8 @@ impl MessageAssembler {
     /// Reset the assembler, discarding any partial message.
     pub fn reset(&mut self) {
         self.buffer.clear();
+        self.spilling = false;
+        self.spill = None;
         // Shrink buffer if significantly oversized to prevent memory bloat
         if self.buffer.capacity() > 64 * 1024 {
             self.buffer = BytesMut::with_capacity(8192);
@@ -156,14 +257,28 @@ impl MessageAssembler {
     }
 }
 
+/// What [`MessageAssembler::push_frame`] made of a frame.
+pub(crate) enum Pushed {
+    /// The completed message, if the frame completed one.
+    Message(Option<AssembledMessage>),
+    /// Payload to write to the message's spill writer.
+    Spill(SpillChunk),
+}
+
 /// A fully assembled WebSocket message.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct AssembledMessage {
     /// The opcode of the original message (Text or Binary).
     pub opcode: OpCode,
     /// The complete message payload.
     pub payload: Bytes,
-    /// RSV1 from first frame (RFC 7692: indicates compression)
+    /// RSV1 from first frame (RFC 7692: indicates compression). The
+    /// payload is then the compressed fragments concatenated, to be
+    /// decompressed as one.
     pub rsv1: bool,
+    /// Set if the payload was spilled out of memory, in which case
+    /// `payload` is empty.
+    pub spilled: Option<SpilledMessage>,
 }
 
 impl AssembledMessage {
@@ -344,6 +459,7 @@ mod tests {
             opcode: OpCode::Text,
             payload: Bytes::from_static(b"Hello"),
             rsv1: false,
+            spilled: None,
         };
         assert_eq!(msg.into_text().unwrap(), "Hello");
     }
@@ -377,6 +493,87 @@ mod tests {
         assert!(matches!(result, Err(Error::ProtocolViolation(_))));
     }
 
+    #[test]
+    fn test_spills_past_threshold() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = test_config().with_spill(4, crate::protocol::TempFiles::in_dir(dir.path()));
+        let mut assembler = MessageAssembler::new(config);
+
+        // Under the threshold: buffered as usual
+        let msg = assembler
+            .push(Frame::binary(vec![1, 2, 3]))
+            .unwrap()
+            .unwrap();
+        assert!(msg.spilled.is_none());
+
+        let frame1 = Frame::new(false, OpCode::Binary, vec![1, 2, 3]);
+        assert!(assembler.push(frame1).unwrap().is_none());
+        let frame2 = Frame::new(false, OpCode::Continuation, vec![4, 5, 6]);
+        assert!(assembler.push(frame2).unwrap().is_none());
+        let frame3 = Frame::new(true, OpCode::Continuation, vec![7]);
+        let msg = assembler.push(frame3).unwrap().unwrap();
+
+        assert!(msg.payload.is_empty());
+        let spilled = msg.spilled.unwrap();
+        assert!(spilled.is_binary());
+        assert_eq!(spilled.len(), 7);
+        assert_eq!(spilled.read_to_vec().unwrap(), vec![1, 2, 3, 4, 5, 6, 7]);
+        assert!(!assembler.is_assembling());
+
+        // Compressed messages stay in memory
+        let mut frame = Frame::binary(vec![0; 8]);
+        frame.rsv1 = true;
+        assert!(assembler.push(frame).unwrap().unwrap().spilled.is_none());
+    }
+
+    #[test]
+    fn test_rsv1_only_on_first_frame() {
+        let mut assembler = MessageAssembler::new(test_config());
+
+        let mut frame1 = Frame::new(false, OpCode::Text, vec![1, 2]);
+        frame1.rsv1 = true;
+        assert!(assembler.push(frame1).unwrap().is_none());
+        let frame2 = Frame::new(true, OpCode::Continuation, vec![3]);
+        let msg = assembler.push(frame2).unwrap().unwrap();
+        assert!(msg.rsv1);
+        assert_eq!(msg.payload.as_ref(), &[1, 2, 3]);
+
+        let frame1 = Frame::new(false, OpCode::Binary, vec![1]);
+        assert!(assembler.push(frame1).unwrap().is_none());
+        let mut frame2 = Frame::new(true, OpCode::Continuation, vec![2]);
+        frame2.rsv1 = true;
+        assert!(matches!(
+            assembler.push(frame2),
+            Err(Error::ProtocolViolation(_))
+        ));
+    }
+
+    #[test]
+    fn test_spilled_text_validated_when_lossy() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config =
+            test_config().with_spill(1, crate::protocol::TempFiles::in_dir(dir.path()));
+        config.compat.lossy_utf8 = true;
+        let mut assembler = MessageAssembler::new(config);
+
+        let frame1 = Frame::new(false, OpCode::Text, vec![b'a', 0xff]);
+        assert!(matches!(assembler.push(frame1), Err(Error::InvalidUtf8)));
+    }
+
+    #[test]
+    fn test_reset_abandons_spill() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = test_config().with_spill(2, crate::protocol::TempFiles::in_dir(dir.path()));
+        let mut assembler = MessageAssembler::new(config);
+
+        let frame = Frame::new(false, OpCode::Text, b"partial".to_vec());
+        assert!(assembler.push(frame).unwrap().is_none());
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        assembler.reset();
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
     #[test]
     fn test_reassembly_single_allocation() {
         let mut assembler = MessageAssembler::new(test_config());
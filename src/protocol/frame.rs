@@ -2,7 +2,10 @@
 //!
 //! This module provides zero-copy frame parsing with full RFC 6455 compliance.
 
-use bytes::Bytes;
+use alloc::format;
+use alloc::vec::Vec;
+
+use bytes::{Buf, Bytes, BytesMut};
 
 use crate::error::{Error, Result};
 use crate::protocol::OpCode;
@@ -11,16 +14,85 @@ use crate::protocol::mask::{apply_mask, apply_mask_simd};
 /// Maximum payload size for control frames (RFC 6455).
 pub const MAX_CONTROL_FRAME_PAYLOAD: usize = 125;
 
+/// Maximum close reason length: the control frame payload less the status
+/// code.
+pub const MAX_CLOSE_REASON: usize = MAX_CONTROL_FRAME_PAYLOAD - 2;
+
+/// Payload bytes masked per write by [`Frame::write_to_async`]. A multiple
+/// of 4, so every chunk starts at the same offset into the mask.
+#[cfg(feature = "async-tokio")]
+const MASK_CHUNK: usize = 16 * 1024;
+
+/// Longest prefix of `reason` that fits in a close frame, cut at a character
+/// boundary so it stays valid UTF-8.
+pub(crate) fn truncate_close_reason(reason: &str) -> &str {
+    if reason.len() <= MAX_CLOSE_REASON {
+        return reason;
+    }
+    let mut end = MAX_CLOSE_REASON;
+    while !reason.is_char_boundary(end) {
+        end -= 1;
+    }
+    &reason[..end]
+}
+
 #[derive(Debug, Clone)]
-struct FrameHeader {
-    fin: bool,
-    rsv1: bool,
-    rsv2: bool,
-    rsv3: bool,
-    opcode: OpCode,
-    mask: Option<[u8; 4]>,
-    payload_len: usize,
-    header_len: usize,
+pub(crate) struct FrameHeader {
+    pub(crate) fin: bool,
+    pub(crate) rsv1: bool,
+    pub(crate) rsv2: bool,
+    pub(crate) rsv3: bool,
+    pub(crate) opcode: OpCode,
+    pub(crate) mask: Option<[u8; 4]>,
+    pub(crate) payload_len: usize,
+    pub(crate) header_len: usize,
+}
+
+/// What is known of an incoming frame once its header is read, before its
+/// payload is, as seen by [`FrameRules`](crate::protocol::FrameRules).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct FrameHead {
+    /// Final fragment flag.
+    pub fin: bool,
+    /// Reserved bit 1.
+    pub rsv1: bool,
+    /// Reserved bit 2.
+    pub rsv2: bool,
+    /// Reserved bit 3.
+    pub rsv3: bool,
+    /// Frame opcode.
+    pub opcode: OpCode,
+    /// Whether the payload is masked.
+    pub masked: bool,
+    /// Payload length in bytes.
+    pub payload_len: usize,
+}
+
+impl FrameHeader {
+    /// The header as seen by frame rules.
+    #[cfg_attr(not(feature = "async-tokio"), allow(dead_code))]
+    pub(crate) fn head(&self) -> FrameHead {
+        FrameHead {
+            fin: self.fin,
+            rsv1: self.rsv1,
+            rsv2: self.rsv2,
+            rsv3: self.rsv3,
+            opcode: self.opcode,
+            masked: self.mask.is_some(),
+            payload_len: self.payload_len,
+        }
+    }
+
+    /// Header and payload length together.
+    pub(crate) fn frame_len(&self) -> Result<usize> {
+        self.header_len
+            .checked_add(self.payload_len)
+            .ok_or(Error::PayloadTooLargeForPlatform {
+                size: self.payload_len as u64,
+                max: usize::MAX as u64,
+            })
+    }
 }
 
 /// Parse frame header from buffer.
@@ -35,7 +107,7 @@ struct FrameHeader {
 /// - `Error::ReservedOpcode` if a reserved opcode is used
 /// - `Error::PayloadTooLargeForPlatform` if payload length exceeds platform limits
 #[inline]
-fn parse_header(buf: &[u8]) -> Result<FrameHeader> {
+pub(crate) fn parse_header(buf: &[u8]) -> Result<FrameHeader> {
     // Need at least 2 bytes for the header
     if buf.len() < 2 {
         return Err(Error::IncompleteFrame {
@@ -123,7 +195,7 @@ fn parse_header(buf: &[u8]) -> Result<FrameHeader> {
 }
 
 /// Internal payload representation for zero-copy optimization.
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone)]
 enum Payload {
     /// Owned data (used after unmasking or when creating new frames).
     Owned(Vec<u8>),
@@ -131,6 +203,25 @@ enum Payload {
     Shared(Bytes),
 }
 
+// Equality is by content: a parsed frame equals the frame that was written
+// regardless of which representation the parser chose.
+impl PartialEq for Payload {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+impl Payload {
+    fn as_slice(&self) -> &[u8] {
+        match self {
+            Payload::Owned(data) => data,
+            Payload::Shared(data) => data,
+        }
+    }
+}
+
+impl Eq for Payload {}
+
 /// A WebSocket frame as defined in RFC 6455.
 ///
 /// Frames are the basic unit of communication in the WebSocket protocol.
@@ -201,6 +292,30 @@ impl Frame {
         }
     }
 
+    /// Create a frame from a parsed header and its unmasked payload.
+    pub(crate) fn from_header(header: &FrameHeader, payload: Bytes) -> Self {
+        Self {
+            fin: header.fin,
+            rsv1: header.rsv1,
+            rsv2: header.rsv2,
+            rsv3: header.rsv3,
+            opcode: header.opcode,
+            payload: Payload::Shared(payload),
+        }
+    }
+
+    /// Split the frame described by `header` off the front of `buf`,
+    /// unmasking in place so the payload is a view of the buffer.
+    #[cfg_attr(not(feature = "async-tokio"), allow(dead_code))]
+    pub(crate) fn split_from(buf: &mut BytesMut, header: &FrameHeader) -> Result<Self> {
+        let mut payload = buf.split_to(header.frame_len()?);
+        payload.advance(header.header_len);
+        if let Some(mask) = header.mask {
+            apply_mask_simd(&mut payload, mask);
+        }
+        Ok(Self::from_header(header, payload.freeze()))
+    }
+
     /// Create a text frame.
     #[must_use]
     pub fn text(data: impl Into<Vec<u8>>) -> Self {
@@ -220,11 +335,14 @@ impl Frame {
     }
 
     /// Create a close frame with optional status code and reason.
+    ///
+    /// A reason longer than [`MAX_CLOSE_REASON`] bytes is truncated at a
+    /// character boundary, so the frame is always a legal control frame.
     #[must_use]
     pub fn close(code: Option<u16>, reason: &str) -> Self {
         let payload = if let Some(code) = code {
             let mut data = code.to_be_bytes().to_vec();
-            data.extend_from_slice(reason.as_bytes());
+            data.extend_from_slice(truncate_close_reason(reason).as_bytes());
             data
         } else {
             Vec::new()
@@ -248,10 +366,7 @@ impl Frame {
     #[inline]
     #[must_use]
     pub fn payload(&self) -> &[u8] {
-        match &self.payload {
-            Payload::Owned(data) => data,
-            Payload::Shared(data) => data,
-        }
+        self.payload.as_slice()
     }
 
     /// Take ownership of the payload as `Vec<u8>`.
@@ -288,12 +403,7 @@ impl Frame {
     pub fn parse(buf: &[u8]) -> Result<(Self, usize)> {
         let header = parse_header(buf)?;
 
-        let total_size = header.header_len.checked_add(header.payload_len).ok_or(
-            Error::PayloadTooLargeForPlatform {
-                size: header.payload_len as u64,
-                max: usize::MAX as u64,
-            },
-        )?;
+        let total_size = header.frame_len()?;
 
         if buf.len() < total_size {
             return Err(Error::IncompleteFrame {
@@ -413,20 +523,8 @@ impl Frame {
     /// Returns an error if the buffer is too small.
     pub fn write(&self, buf: &mut [u8], mask: Option<[u8; 4]>) -> Result<usize> {
         let payload = self.payload();
-        let payload_len = payload.len();
-
-        // Calculate header size
-        let (len_bytes, extended_len_size) = if payload_len <= 125 {
-            (payload_len as u8, 0)
-        } else if payload_len <= 65535 {
-            (126, 2)
-        } else {
-            (127, 8)
-        };
-
-        let mask_size = if mask.is_some() { 4 } else { 0 };
-        let header_size = 2 + extended_len_size + mask_size;
-        let total_size = header_size + payload_len;
+        let (header, header_size) = self.header(mask);
+        let total_size = header_size + payload.len();
 
         // Check buffer size
         if buf.len() < total_size {
@@ -437,6 +535,83 @@ impl Frame {
             )));
         }
 
+        buf[..header_size].copy_from_slice(&header[..header_size]);
+        buf[header_size..total_size].copy_from_slice(payload);
+
+        // Apply mask if needed
+        if let Some(mask_key) = mask {
+            apply_mask(&mut buf[header_size..total_size], mask_key);
+        }
+
+        Ok(total_size)
+    }
+
+    /// Append the frame to `buf`, growing it as needed.
+    ///
+    /// Unlike [`write`](Self::write), the buffer need not be sized (and
+    /// zeroed) up front, so large frames are copied once and not cleared
+    /// first. Returns the number of bytes appended.
+    pub fn write_to(&self, buf: &mut BytesMut, mask: Option<[u8; 4]>) -> usize {
+        let payload = self.payload();
+        let (header, header_size) = self.header(mask);
+        buf.reserve(header_size + payload.len());
+        buf.extend_from_slice(&header[..header_size]);
+        let start = buf.len();
+        buf.extend_from_slice(payload);
+        if let Some(mask_key) = mask {
+            apply_mask_simd(&mut buf[start..], mask_key);
+        }
+        header_size + payload.len()
+    }
+
+    /// Write the frame to `writer`: the header, then the payload, masked on
+    /// the fly in 16 KiB chunks if `mask` is set. A large frame is never
+    /// copied whole into a wire-size buffer. Does not flush.
+    ///
+    /// Returns the number of bytes written.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Io` if writing fails, after which part of the frame
+    /// may have been written.
+    #[cfg(feature = "async-tokio")]
+    pub async fn write_to_async<W>(&self, writer: &mut W, mask: Option<[u8; 4]>) -> Result<usize>
+    where
+        W: tokio::io::AsyncWrite + Unpin + ?Sized,
+    {
+        use tokio::io::AsyncWriteExt;
+
+        let payload = self.payload();
+        let (header, header_size) = self.header(mask);
+        writer.write_all(&header[..header_size]).await?;
+        match mask {
+            None => writer.write_all(payload).await?,
+            Some(mask_key) => {
+                let mut chunk = Vec::with_capacity(payload.len().min(MASK_CHUNK));
+                for part in payload.chunks(MASK_CHUNK) {
+                    chunk.clear();
+                    chunk.extend_from_slice(part);
+                    apply_mask_simd(&mut chunk, mask_key);
+                    writer.write_all(&chunk).await?;
+                }
+            }
+        }
+        Ok(header_size + payload.len())
+    }
+
+    /// Append only the frame header to `buf`, for writing the payload from
+    /// where it is.
+    #[cfg_attr(not(feature = "async-tokio"), allow(dead_code))]
+    pub(crate) fn write_header_to(&self, buf: &mut BytesMut, mask: Option<[u8; 4]>) {
+        let (header, header_size) = self.header(mask);
+        buf.extend_from_slice(&header[..header_size]);
+    }
+
+    /// Encode the frame header, returning it with its length.
+    fn header(&self, mask: Option<[u8; 4]>) -> ([u8; 14], usize) {
+        let payload_len = self.payload().len();
+        let mut header = [0; 14];
+
         // Build first byte
         let mut byte0 = self.opcode.as_u8();
         if self.fin {
@@ -451,47 +626,34 @@ impl Frame {
         if self.rsv3 {
             byte0 |= 0x10;
         }
-        buf[0] = byte0;
-
-        // Build second byte
-        let mut byte1 = len_bytes;
-        if mask.is_some() {
-            byte1 |= 0x80;
-        }
-        buf[1] = byte1;
+        header[0] = byte0;
 
-        // Write extended payload length
+        // Build second byte and extended payload length
         let mut offset = 2;
-        match extended_len_size {
-            2 => {
-                let len_bytes = (payload_len as u16).to_be_bytes();
-                buf[offset] = len_bytes[0];
-                buf[offset + 1] = len_bytes[1];
-                offset += 2;
-            }
-            8 => {
-                let len_bytes = (payload_len as u64).to_be_bytes();
-                buf[offset..offset + 8].copy_from_slice(&len_bytes);
-                offset += 8;
-            }
-            _ => {}
-        }
+        let len_bytes = if payload_len <= 125 {
+            payload_len as u8
+        } else if payload_len <= 65535 {
+            header[2..4].copy_from_slice(&(payload_len as u16).to_be_bytes());
+            offset += 2;
+            126
+        } else {
+            header[2..10].copy_from_slice(&(payload_len as u64).to_be_bytes());
+            offset += 8;
+            127
+        };
+        header[1] = if mask.is_some() {
+            len_bytes | 0x80
+        } else {
+            len_bytes
+        };
 
         // Write masking key
         if let Some(mask_key) = mask {
-            buf[offset..offset + 4].copy_from_slice(&mask_key);
+            header[offset..offset + 4].copy_from_slice(&mask_key);
             offset += 4;
         }
 
-        // Write payload
-        buf[offset..offset + payload_len].copy_from_slice(payload);
-
-        // Apply mask if needed
-        if let Some(mask_key) = mask {
-            apply_mask(&mut buf[offset..offset + payload_len], mask_key);
-        }
-
-        Ok(total_size)
+        (header, offset)
     }
 
     /// Calculate the size needed to write this frame.
@@ -916,6 +1078,39 @@ mod tests {
         assert!(matches!(result, Err(Error::InvalidFrame(_))));
     }
 
+    #[test]
+    fn test_write_to_matches_write() {
+        for len in [0, 125, 126, 70_000] {
+            let frame = Frame::binary(vec![0xAB; len]);
+            for mask in [None, Some([1, 2, 3, 4])] {
+                let mut expected = vec![0; frame.wire_size(mask.is_some())];
+                frame.write(&mut expected, mask).unwrap();
+
+                let mut buf = BytesMut::from(&b"prefix"[..]);
+                assert_eq!(frame.write_to(&mut buf, mask), expected.len());
+                assert_eq!(&buf[..6], b"prefix");
+                assert_eq!(&buf[6..], expected);
+            }
+        }
+    }
+
+    #[cfg(feature = "async-tokio")]
+    #[tokio::test]
+    async fn test_write_to_async_matches_write() {
+        for len in [0, 126, 40_003] {
+            let frame = Frame::binary(vec![0xAB; len]);
+            for mask in [None, Some([1, 2, 3, 4])] {
+                let mut expected = vec![0; frame.wire_size(mask.is_some())];
+                frame.write(&mut expected, mask).unwrap();
+
+                let mut out = Vec::new();
+                let n = frame.write_to_async(&mut out, mask).await.unwrap();
+                assert_eq!(n, expected.len());
+                assert_eq!(out, expected);
+            }
+        }
+    }
+
     // --------------------------------------------------------------------------
     // Test 28: Wire size calculation
     // --------------------------------------------------------------------------
@@ -951,6 +1146,17 @@ mod tests {
         assert_eq!(&payload[2..], b"Normal closure");
     }
 
+    #[test]
+    fn test_close_frame_truncates_reason() {
+        let frame = Frame::close(Some(1000), &"é".repeat(100));
+        assert!(frame.validate().is_ok());
+        // 61 two-byte characters fit in 123 bytes
+        assert_eq!(frame.payload().len(), 2 + 122);
+        assert!(std::str::from_utf8(&frame.payload()[2..]).is_ok());
+
+        assert_eq!(truncate_close_reason("short"), "short");
+    }
+
     // --------------------------------------------------------------------------
     // Test 30: Validate valid frame
     // --------------------------------------------------------------------------
@@ -1026,6 +1232,17 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_frame_equality_ignores_payload_representation() {
+        let data = Bytes::from_static(&[0x81, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f]);
+        let (shared, _) = Frame::parse_zero_copy(&data).unwrap();
+        let owned = Frame::text(b"Hello".to_vec());
+
+        assert!(matches!(shared.payload, Payload::Shared(_)));
+        assert_eq!(shared, owned);
+        assert_ne!(shared, Frame::text(b"Hellx".to_vec()));
+    }
+
     // --------------------------------------------------------------------------
     // Test 35: Payload exceeds platform max (32-bit overflow protection)
     // --------------------------------------------------------------------------
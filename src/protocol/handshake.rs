@@ -2,65 +2,251 @@
 //!
 //! This module handles the HTTP Upgrade mechanism for establishing WebSocket connections.
 
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use alloc::{format, vec};
+
 use crate::error::{Error, Result};
 use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
 use sha1::{Digest, Sha1};
-use std::collections::HashMap;
+
+use super::headers::Headers;
 
 /// The WebSocket GUID used in the Sec-WebSocket-Accept calculation (RFC 6455).
 pub const WS_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
 
-/// Parse HTTP headers from an iterator of lines into a case-insensitive HashMap.
+/// Opt-in leniency for peers that bend RFC 6455, e.g. embedded devices with
+/// hand-written HTTP stacks. Everything is off by default.
 ///
-/// Optionally checks for duplicate security-critical headers when `security_headers` is provided.
+/// Case-insensitive tokens and a `Connection` list such as
+/// `keep-alive, Upgrade` are always accepted, as the RFC requires; these
+/// switches go further.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompatMode {
+    /// Accept a handshake whose `Connection` header is missing or does not
+    /// list `upgrade`, e.g. `Connection: keep-alive`.
+    pub connection_without_upgrade: bool,
+
+    /// Accept a request without `Sec-WebSocket-Version`, assuming 13.
+    pub missing_version: bool,
+
+    /// Accept an `Upgrade` header that lists `websocket` among other
+    /// protocols, e.g. `websocket, h2c`.
+    pub upgrade_list: bool,
+
+    /// Treat a peer that resets the connection or cuts off the TLS stream
+    /// like one that closed it without a Close frame (1006): `recv` returns
+    /// `Ok(None)` instead of an I/O error.
+    pub abrupt_close: bool,
+
+    /// Replace invalid UTF-8 in text messages with U+FFFD instead of
+    /// failing the connection with 1007. Each repaired message is reported
+    /// to the observer as `ConnectionEvent::InvalidUtf8Replaced`.
+    ///
+    /// Text spilled out of memory (see `Config::with_spill`) is not
+    /// repaired: it is still validated and fails the connection.
+    pub lossy_utf8: bool,
+}
+
+impl CompatMode {
+    /// Every leniency switched on.
+    #[must_use]
+    pub const fn lenient() -> Self {
+        Self {
+            connection_without_upgrade: true,
+            missing_version: true,
+            upgrade_list: true,
+            abrupt_close: true,
+            lossy_utf8: true,
+        }
+    }
+
+    /// Accept a `Connection` header without the `upgrade` token.
+    #[must_use]
+    pub const fn with_connection_without_upgrade(mut self, enabled: bool) -> Self {
+        self.connection_without_upgrade = enabled;
+        self
+    }
+
+    /// Accept a request without `Sec-WebSocket-Version`.
+    #[must_use]
+    pub const fn with_missing_version(mut self, enabled: bool) -> Self {
+        self.missing_version = enabled;
+        self
+    }
+
+    /// Accept `websocket` among other protocols in `Upgrade`.
+    #[must_use]
+    pub const fn with_upgrade_list(mut self, enabled: bool) -> Self {
+        self.upgrade_list = enabled;
+        self
+    }
+
+    /// Treat connection resets as abrupt closes.
+    #[must_use]
+    pub const fn with_abrupt_close(mut self, enabled: bool) -> Self {
+        self.abrupt_close = enabled;
+        self
+    }
+
+    /// Repair invalid UTF-8 in text messages rather than closing.
+    #[must_use]
+    pub const fn with_lossy_utf8(mut self, enabled: bool) -> Self {
+        self.lossy_utf8 = enabled;
+        self
+    }
+}
+
+/// Room for every header line in `data`. The head is already bounded by
+/// `limits.max_handshake_size`, so no separate header count limit applies.
+pub(crate) fn header_slots<'a>(data: &[u8]) -> Vec<httparse::Header<'a>> {
+    vec![httparse::EMPTY_HEADER; data.iter().filter(|&&b| b == b'\n').count()]
+}
+
+/// Turn an `httparse` error or incomplete head into a handshake error.
+pub(crate) fn malformed(what: &str, result: httparse::Result<usize>) -> Result<()> {
+    match result {
+        Ok(httparse::Status::Complete(_)) => Ok(()),
+        Ok(httparse::Status::Partial) => {
+            Err(Error::InvalidHandshake(format!("Incomplete {}", what)))
+        }
+        Err(e) => Err(Error::InvalidHandshake(format!(
+            "Malformed {}: {}",
+            what, e
+        ))),
+    }
+}
+
+/// The name and value of a parsed header.
 ///
-/// # Arguments
-/// * `lines` - Iterator over header lines (after the request/status line)
-/// * `security_headers` - Optional slice of header names that should not be duplicated
+/// Values folded over several lines (obsolete, RFC 9112 Section 5.2) are
+/// unfolded with single spaces, and surrounding whitespace is trimmed.
 ///
-/// # Returns
-/// A HashMap with lowercase header names as keys and trimmed values.
+/// # Errors
+/// Returns `Error::InvalidHandshake` if the value is not valid UTF-8.
+pub(crate) fn header_pair<'a>(header: &httparse::Header<'a>) -> Result<(&'a str, String)> {
+    let value = core::str::from_utf8(header.value).map_err(|_| {
+        Error::InvalidHandshake(format!("Invalid UTF-8 in header: {}", header.name))
+    })?;
+    let value = if value.contains(['\r', '\n']) {
+        value
+            .split(['\r', '\n'])
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ")
+    } else {
+        value.trim().to_string()
+    };
+    Ok((header.name, value))
+}
+
+/// Collect parsed headers, checking that none of `security_headers` is
+/// sent more than once.
 ///
 /// # Errors
-/// Returns `Error::InvalidHandshake` if a security-critical header is duplicated.
-fn parse_headers<'a, I>(
-    lines: I,
-    security_headers: Option<&[&str]>,
-) -> Result<HashMap<String, String>>
-where
-    I: Iterator<Item = &'a str>,
-{
-    let mut headers: HashMap<String, String> = HashMap::new();
-
-    for line in lines {
-        if line.is_empty() {
-            break;
+/// Returns `Error::InvalidHandshake` if a security-critical header is
+/// duplicated or a value is not valid UTF-8.
+fn collect_headers(parsed: &[httparse::Header<'_>], security_headers: &[&str]) -> Result<Headers> {
+    collect_pairs(parsed.iter().map(header_pair), security_headers)
+}
+
+/// [`collect_headers`] over already decoded name/value pairs.
+pub(crate) fn collect_pairs<'a>(
+    pairs: impl IntoIterator<Item = Result<(&'a str, String)>>,
+    security_headers: &[&str],
+) -> Result<Headers> {
+    let mut headers = Headers::new();
+    for pair in pairs {
+        let (name, value) = pair?;
+        if headers.contains(name)
+            && security_headers
+                .iter()
+                .any(|sec| sec.eq_ignore_ascii_case(name))
+        {
+            return Err(Error::InvalidHandshake(format!(
+                "Duplicate header: {}",
+                name
+            )));
         }
-        if let Some((name, value)) = line.split_once(':') {
-            let name_lower = name.trim().to_lowercase();
+        headers.append(name, value);
+    }
+    Ok(headers)
+}
 
-            #[allow(clippy::collapsible_if)]
-            if let Some(sec_headers) = security_headers {
-                if sec_headers.contains(&name_lower.as_str()) && headers.contains_key(&name_lower) {
-                    return Err(Error::InvalidHandshake(format!(
-                        "Duplicate header: {}",
-                        name.trim()
-                    )));
-                }
-            }
+/// Request headers that must not be sent more than once.
+pub(crate) const SECURITY_FIELDS: &[&str] = REQUEST_FIELDS.split_at(5).0;
+
+/// Headers a request's fields are parsed from and written as.
+const REQUEST_FIELDS: [&str; 8] = [
+    "host",
+    "upgrade",
+    "connection",
+    "sec-websocket-key",
+    "sec-websocket-version",
+    "origin",
+    "sec-websocket-protocol",
+    "sec-websocket-extensions",
+];
+
+/// Headers a response's fields are parsed from and written as.
+const RESPONSE_FIELDS: [&str; 5] = [
+    "upgrade",
+    "connection",
+    "sec-websocket-accept",
+    "sec-websocket-protocol",
+    "sec-websocket-extensions",
+];
+
+/// The headers in `all` not named in `fields`.
+fn others(all: &Headers, fields: &[&str]) -> Headers {
+    all.iter()
+        .filter(|(name, _)| !fields.iter().any(|f| f.eq_ignore_ascii_case(name)))
+        .collect()
+}
 
-            headers.insert(name_lower, value.trim().to_string());
-        }
+/// Check the `Upgrade` and `Connection` headers of a handshake. `context`
+/// is appended to errors about missing headers.
+fn check_upgrade(headers: &Headers, compat: &CompatMode, context: &str) -> Result<()> {
+    let upgrade = headers
+        .get("upgrade")
+        .ok_or_else(|| Error::InvalidHandshake(format!("Missing Upgrade header{}", context)))?;
+    let is_websocket = |token: &str| token.trim().eq_ignore_ascii_case("websocket");
+    let upgrade_ok = if compat.upgrade_list {
+        upgrade.split(',').any(is_websocket)
+    } else {
+        is_websocket(upgrade)
+    };
+    if !upgrade_ok {
+        return Err(Error::InvalidHandshake(format!(
+            "Invalid Upgrade header: {}",
+            upgrade
+        )));
     }
 
-    Ok(headers)
+    if compat.connection_without_upgrade {
+        return Ok(());
+    }
+    let connection = headers
+        .get("connection")
+        .ok_or_else(|| Error::InvalidHandshake(format!("Missing Connection header{}", context)))?;
+    if !connection
+        .split(',')
+        .any(|token| token.trim().eq_ignore_ascii_case("upgrade"))
+    {
+        return Err(Error::InvalidHandshake(format!(
+            "Invalid Connection header: {}",
+            connection
+        )));
+    }
+    Ok(())
 }
 
 /// Validate that a header value does not contain CR or LF characters.
 ///
 /// # Errors
 /// Returns `Error::InvalidHeaderValue` if the value contains `\r` or `\n`.
-fn validate_header_value(header_name: &str, value: &str) -> Result<()> {
+pub(crate) fn validate_header_value(header_name: &str, value: &str) -> Result<()> {
     if value.contains('\r') || value.contains('\n') {
         return Err(Error::InvalidHeaderValue {
             header: header_name.to_string(),
@@ -70,6 +256,22 @@ fn validate_header_value(header_name: &str, value: &str) -> Result<()> {
     Ok(())
 }
 
+/// Validate that a header name is a token (RFC 9110 Section 5.6.2).
+///
+/// # Errors
+/// Returns `Error::InvalidHeaderValue` if the name is empty or contains a
+/// character other than a letter, digit or one of ``!#$%&'*+-.^_`|~``.
+pub(crate) fn validate_header_name(name: &str) -> Result<()> {
+    let tchar = |b: u8| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b);
+    if name.is_empty() || !name.bytes().all(tchar) {
+        return Err(Error::InvalidHeaderValue {
+            header: name.to_string(),
+            reason: "name is not a token".to_string(),
+        });
+    }
+    Ok(())
+}
+
 /// Computes the Sec-WebSocket-Accept value from the client's Sec-WebSocket-Key.
 ///
 /// The accept key is calculated as: Base64(SHA-1(key + GUID))
@@ -91,6 +293,21 @@ pub fn compute_accept_key(key: &str) -> String {
     BASE64.encode(hash)
 }
 
+/// Generate a `Sec-WebSocket-Key`: 16 random bytes, base64-encoded
+/// (RFC 6455 Section 4.1).
+///
+/// # Panics
+///
+/// Panics if the system's random number generator is unavailable. The key
+/// must be unpredictable, so there is no fallback.
+#[cfg(feature = "std")]
+#[must_use]
+pub fn generate_key() -> String {
+    let mut nonce = [0u8; 16];
+    getrandom::getrandom(&mut nonce).expect("Failed to obtain random bytes for Sec-WebSocket-Key");
+    BASE64.encode(nonce)
+}
+
 /// Validate the Origin header against a list of allowed origins.
 ///
 /// # Arguments
@@ -119,6 +336,52 @@ pub fn validate_origin(origin: Option<&str>, allowed: &[String]) -> Result<()> {
     }
 }
 
+/// Validate the Host header against a list of allowed hosts.
+///
+/// Names compare case-insensitively. An allowed entry without a port
+/// matches the host on any port; one with a port (`localhost:8080`,
+/// `[::1]:8080`) matches only that port.
+///
+/// # Errors
+/// Returns `Error::HostNotAllowed` if `allowed` is not empty and `host`
+/// doesn't match any value.
+///
+/// If `allowed` is empty, any host is accepted.
+pub fn validate_host(host: &str, allowed: &[String]) -> Result<()> {
+    if allowed.is_empty() {
+        return Ok(());
+    }
+
+    let (name, _) = split_port(host);
+    let matches = |entry: &String| match split_port(entry) {
+        (entry_name, None) => entry_name.eq_ignore_ascii_case(name),
+        (_, Some(_)) => entry.eq_ignore_ascii_case(host),
+    };
+    if allowed.iter().any(matches) {
+        Ok(())
+    } else {
+        Err(Error::HostNotAllowed {
+            host: host.to_string(),
+        })
+    }
+}
+
+/// Split `host[:port]` into its name and port. Bracketed IPv6 literals keep
+/// their brackets; a bare IPv6 address has no port.
+fn split_port(host: &str) -> (&str, Option<&str>) {
+    let name_end = if host.starts_with('[') {
+        host.find(']').map_or(host.len(), |i| i + 1)
+    } else if host.matches(':').count() == 1 {
+        host.find(':').unwrap_or(host.len())
+    } else {
+        host.len()
+    };
+    match host[name_end..].strip_prefix(':') {
+        Some(port) => (&host[..name_end], Some(port)),
+        None => (host, None),
+    }
+}
+
 /// Parsed WebSocket handshake request from client.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct HandshakeRequest {
@@ -136,16 +399,69 @@ pub struct HandshakeRequest {
     pub protocols: Vec<String>,
     /// The Sec-WebSocket-Extensions values (optional).
     pub extensions: Vec<String>,
+    /// Every other header, e.g. `Cookie` or `Authorization`.
+    pub headers: Headers,
 }
 
 impl HandshakeRequest {
+    /// Create a client request for `path` on `host` with the given
+    /// `Sec-WebSocket-Key`.
+    pub fn new(host: impl Into<String>, path: impl Into<String>, key: impl Into<String>) -> Self {
+        Self {
+            path: path.into(),
+            host: host.into(),
+            key: key.into(),
+            version: 13,
+            origin: None,
+            protocols: Vec::new(),
+            extensions: Vec::new(),
+            headers: Headers::new(),
+        }
+    }
+
+    /// Write the HTTP request to a buffer.
+    ///
+    /// # Errors
+    /// Returns `Error::InvalidHeaderValue` if any field contains CR/LF.
+    pub fn write(&self, buf: &mut Vec<u8>) -> Result<()> {
+        validate_header_value("Request-Target", &self.path)?;
+        validate_header_value("Host", &self.host)?;
+        validate_header_value("Sec-WebSocket-Key", &self.key)?;
+
+        buf.extend_from_slice(format!("GET {} HTTP/1.1\r\n", self.path).as_bytes());
+        buf.extend_from_slice(format!("Host: {}\r\n", self.host).as_bytes());
+        buf.extend_from_slice(b"Upgrade: websocket\r\n");
+        buf.extend_from_slice(b"Connection: Upgrade\r\n");
+        buf.extend_from_slice(format!("Sec-WebSocket-Key: {}\r\n", self.key).as_bytes());
+        buf.extend_from_slice(format!("Sec-WebSocket-Version: {}\r\n", self.version).as_bytes());
+
+        if let Some(ref origin) = self.origin {
+            validate_header_value("Origin", origin)?;
+            buf.extend_from_slice(format!("Origin: {}\r\n", origin).as_bytes());
+        }
+        if !self.protocols.is_empty() {
+            let protocols = self.protocols.join(", ");
+            validate_header_value("Sec-WebSocket-Protocol", &protocols)?;
+            buf.extend_from_slice(format!("Sec-WebSocket-Protocol: {}\r\n", protocols).as_bytes());
+        }
+        for ext in &self.extensions {
+            validate_header_value("Sec-WebSocket-Extensions", ext)?;
+            buf.extend_from_slice(format!("Sec-WebSocket-Extensions: {}\r\n", ext).as_bytes());
+        }
+        self.headers.write(buf)?;
+
+        buf.extend_from_slice(b"\r\n");
+        Ok(())
+    }
+
     /// Parse a WebSocket handshake request from raw HTTP data.
     ///
     /// # Errors
     ///
     /// Returns [`Error::InvalidHandshake`] if:
-    /// - The data is not valid UTF-8.
-    /// - The request line is malformed or missing.
+    /// - The data is not a complete, well-formed HTTP/1.x request head.
+    /// - A header value is not valid UTF-8.
+    /// - A security-critical header is sent more than once.
     /// - The HTTP method is not `GET`.
     /// - The HTTP version is not `HTTP/1.1`.
     /// - Any required headers are missing: `Upgrade`, `Connection`, `Host`, `Sec-WebSocket-Key`, `Sec-WebSocket-Version`.
@@ -153,103 +469,94 @@ impl HandshakeRequest {
     /// - The `Connection` header does not contain `upgrade`.
     /// - The `Sec-WebSocket-Version` is not a valid integer.
     pub fn parse(data: &[u8]) -> Result<Self> {
-        let text = std::str::from_utf8(data)
-            .map_err(|_| Error::InvalidHandshake("Invalid UTF-8".into()))?;
-
-        let mut lines = text.lines();
+        Self::parse_with_compat(data, &CompatMode::default())
+    }
 
-        // Parse request line: "GET /path HTTP/1.1"
-        let request_line = lines
-            .next()
-            .ok_or_else(|| Error::InvalidHandshake("Empty request".into()))?;
+    /// Parse a handshake request, accepting the deviations allowed by
+    /// `compat`.
+    ///
+    /// # Errors
+    ///
+    /// As for [`parse`](Self::parse), less what `compat` tolerates.
+    pub fn parse_with_compat(data: &[u8], compat: &CompatMode) -> Result<Self> {
+        let req = Self::parse_request(data, compat).map_err(rejected)?;
+        ws_debug!(
+            path = %req.path,
+            host = %req.host,
+            version = req.version,
+            "parsed handshake request"
+        );
+        Ok(req)
+    }
 
-        let parts: Vec<&str> = request_line.split_whitespace().collect();
-        if parts.len() != 3 {
-            return Err(Error::InvalidHandshake("Invalid request line".into()));
-        }
+    fn parse_request(data: &[u8], compat: &CompatMode) -> Result<Self> {
+        let mut slots = header_slots(data);
+        let mut request = httparse::Request::new(&mut slots);
+        malformed("request", request.parse(data))?;
 
-        if parts[0] != "GET" {
+        // A complete parse fills in the request line
+        let method = request.method.unwrap_or_default();
+        if method != "GET" {
             return Err(Error::InvalidHandshake(format!(
                 "Expected GET method, got {}",
-                parts[0]
+                method
             )));
         }
 
-        if !parts[2].starts_with("HTTP/1.1") {
+        if request.version != Some(1) {
             return Err(Error::InvalidHandshake(format!(
-                "Expected HTTP/1.1, got {}",
-                parts[2]
+                "Expected HTTP/1.1, got HTTP/1.{}",
+                request.version.unwrap_or_default()
             )));
         }
 
-        let path = parts[1].to_string();
+        let path = request.path.unwrap_or_default().to_string();
 
         // Parse headers with duplicate detection for security-critical headers
-        let security_headers = [
-            "host",
-            "upgrade",
-            "connection",
-            "sec-websocket-key",
-            "sec-websocket-version",
-        ];
-        let headers = parse_headers(lines, Some(&security_headers))?;
+        let headers = collect_headers(request.headers, SECURITY_FIELDS)?;
+        Self::from_headers(path, &headers, compat)
+    }
 
-        // Validate Upgrade header
-        let upgrade = headers
-            .get("upgrade")
-            .ok_or_else(|| Error::InvalidHandshake("Missing Upgrade header".into()))?;
-        if !upgrade.eq_ignore_ascii_case("websocket") {
-            return Err(Error::InvalidHandshake(format!(
-                "Invalid Upgrade header: {}",
-                upgrade
-            )));
-        }
-
-        // Validate Connection header
-        let connection = headers
-            .get("connection")
-            .ok_or_else(|| Error::InvalidHandshake("Missing Connection header".into()))?;
-        if !connection.to_lowercase().contains("upgrade") {
-            return Err(Error::InvalidHandshake(format!(
-                "Invalid Connection header: {}",
-                connection
-            )));
-        }
+    /// Build a request for `path` from its headers, checking them as
+    /// [`parse`](Self::parse) does.
+    pub(crate) fn from_headers(
+        path: String,
+        headers: &Headers,
+        compat: &CompatMode,
+    ) -> Result<Self> {
+        check_upgrade(headers, compat, "")?;
 
         // Extract Host header
         let host = headers
             .get("host")
             .ok_or_else(|| Error::InvalidHandshake("Missing Host header".into()))?
-            .clone();
+            .to_string();
 
         // Extract Sec-WebSocket-Key
         let key = headers
             .get("sec-websocket-key")
             .ok_or_else(|| Error::InvalidHandshake("Missing Sec-WebSocket-Key header".into()))?
-            .clone();
+            .to_string();
 
         // Extract Sec-WebSocket-Version
-        let version_str = headers.get("sec-websocket-version").ok_or_else(|| {
-            Error::InvalidHandshake("Missing Sec-WebSocket-Version header".into())
-        })?;
-        let version: u8 = version_str
-            .parse()
-            .map_err(|_| Error::InvalidHandshake(format!("Invalid version: {}", version_str)))?;
+        let version: u8 = match headers.get("sec-websocket-version") {
+            Some(version_str) => version_str.parse().map_err(|_| {
+                Error::InvalidHandshake(format!("Invalid version: {}", version_str))
+            })?,
+            None if compat.missing_version => 13,
+            None => {
+                return Err(Error::InvalidHandshake(
+                    "Missing Sec-WebSocket-Version header".into(),
+                ));
+            }
+        };
 
         // Extract optional Origin
-        let origin = headers.get("origin").cloned();
+        let origin = headers.get("origin").map(ToString::to_string);
 
-        // Extract optional Sec-WebSocket-Protocol (comma-separated)
-        let protocols = headers
-            .get("sec-websocket-protocol")
-            .map(|p| p.split(',').map(|s| s.trim().to_string()).collect())
-            .unwrap_or_default();
-
-        // Extract optional Sec-WebSocket-Extensions (comma-separated)
-        let extensions = headers
-            .get("sec-websocket-extensions")
-            .map(|e| e.split(',').map(|s| s.trim().to_string()).collect())
-            .unwrap_or_default();
+        // Optional lists, possibly split over several header lines
+        let protocols = headers.list("sec-websocket-protocol");
+        let extensions = headers.list("sec-websocket-extensions");
 
         Ok(Self {
             path,
@@ -259,6 +566,7 @@ impl HandshakeRequest {
             origin,
             protocols,
             extensions,
+            headers: others(headers, &REQUEST_FIELDS),
         })
     }
 
@@ -272,6 +580,10 @@ impl HandshakeRequest {
     /// - The decoded `Sec-WebSocket-Key` is not exactly 16 bytes.
     /// - The `Host` header is empty.
     pub fn validate(&self) -> Result<()> {
+        self.validate_fields().map_err(rejected)
+    }
+
+    fn validate_fields(&self) -> Result<()> {
         // Version must be 13
         if self.version != 13 {
             return Err(Error::InvalidHandshake(format!(
@@ -333,6 +645,8 @@ pub struct HandshakeResponse {
     pub protocol: Option<String>,
     /// The negotiated Sec-WebSocket-Extensions (optional).
     pub extensions: Vec<String>,
+    /// Every other header, e.g. `Set-Cookie`.
+    pub headers: Headers,
 }
 
 impl HandshakeResponse {
@@ -342,6 +656,7 @@ impl HandshakeResponse {
             accept: compute_accept_key(&req.key),
             protocol: req.protocols.first().cloned(),
             extensions: Vec::new(), // No extensions supported yet
+            headers: Headers::new(),
         }
     }
 
@@ -364,6 +679,7 @@ impl HandshakeResponse {
             validate_header_value("Sec-WebSocket-Extensions", ext)?;
             buf.extend_from_slice(format!("Sec-WebSocket-Extensions: {}\r\n", ext).as_bytes());
         }
+        self.headers.write(buf)?;
 
         buf.extend_from_slice(b"\r\n");
         Ok(())
@@ -374,77 +690,138 @@ impl HandshakeResponse {
     /// # Errors
     ///
     /// Returns [`Error::InvalidHandshake`] if:
-    /// - The data is not valid UTF-8.
-    /// - The response is empty or the status line is missing.
+    /// - The data is not a complete, well-formed HTTP/1.x response head.
+    /// - A header value is not valid UTF-8.
     /// - The status code is not `101 Switching Protocols`.
     /// - Any required headers are missing: `Upgrade`, `Connection`, `Sec-WebSocket-Accept`.
     /// - The `Upgrade` header is not `websocket`.
     /// - The `Connection` header does not contain `upgrade`.
     pub fn parse(data: &[u8]) -> Result<Self> {
-        let text = std::str::from_utf8(data)
-            .map_err(|_| Error::InvalidHandshake("Invalid UTF-8".into()))?;
-
-        let mut lines = text.lines();
+        Self::parse_with_compat(data, &CompatMode::default())
+    }
 
-        // Parse status line: "HTTP/1.1 101 Switching Protocols"
-        let status_line = lines
-            .next()
-            .ok_or_else(|| Error::InvalidHandshake("Empty response".into()))?;
+    /// Parse a handshake response, accepting the deviations allowed by
+    /// `compat`.
+    ///
+    /// # Errors
+    ///
+    /// As for [`parse`](Self::parse), less what `compat` tolerates.
+    pub fn parse_with_compat(data: &[u8], compat: &CompatMode) -> Result<Self> {
+        let resp = Self::parse_response(data, compat).map_err(rejected)?;
+        ws_debug!(
+            protocol = ?resp.protocol,
+            extensions = ?resp.extensions,
+            "parsed handshake response"
+        );
+        Ok(resp)
+    }
 
-        if !status_line.starts_with("HTTP/1.1 101") {
-            return Err(Error::InvalidHandshake(format!(
-                "Expected 101 status, got: {}",
-                status_line
-            )));
+    /// Check `Sec-WebSocket-Accept` against the key the client sent.
+    ///
+    /// The comparison takes the same time wherever the values differ.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidHandshake`] if the accept value does not
+    /// match `request_key`.
+    pub fn validate(&self, request_key: &str) -> Result<()> {
+        let expected = compute_accept_key(request_key);
+        if constant_time_eq(self.accept.as_bytes(), expected.as_bytes()) {
+            Ok(())
+        } else {
+            Err(rejected(Error::InvalidHandshake(
+                "Sec-WebSocket-Accept does not match the request key".into(),
+            )))
         }
+    }
 
-        let headers = parse_headers(lines, None)?;
-
-        // Validate Upgrade header
-        let upgrade = headers
-            .get("upgrade")
-            .ok_or_else(|| Error::InvalidHandshake("Missing Upgrade header in response".into()))?;
-        if !upgrade.eq_ignore_ascii_case("websocket") {
-            return Err(Error::InvalidHandshake(format!(
-                "Invalid Upgrade header: {}",
-                upgrade
-            )));
+    /// Check the selected subprotocol against those the client offered.
+    ///
+    /// # Errors
+    ///
+    /// - [`Error::UnexpectedSubprotocol`] if the server selected a protocol
+    ///   not in `offered`
+    /// - [`Error::MissingSubprotocol`] if `required` and the server selected
+    ///   none
+    pub fn validate_protocol(&self, offered: &[String], required: bool) -> Result<()> {
+        match &self.protocol {
+            Some(protocol) if !offered.contains(protocol) => {
+                Err(rejected(Error::UnexpectedSubprotocol(protocol.clone())))
+            }
+            None if required => Err(rejected(Error::MissingSubprotocol)),
+            _ => Ok(()),
         }
+    }
 
-        // Validate Connection header
-        let connection = headers.get("connection").ok_or_else(|| {
-            Error::InvalidHandshake("Missing Connection header in response".into())
-        })?;
-        if !connection.to_lowercase().contains("upgrade") {
+    fn parse_response(data: &[u8], compat: &CompatMode) -> Result<Self> {
+        let mut slots = header_slots(data);
+        let mut response = httparse::Response::new(&mut slots);
+        let result = httparse::ParserConfig::default()
+            .allow_obsolete_multiline_headers_in_responses(true)
+            .parse_response(&mut response, data);
+        malformed("response", result)?;
+
+        // A complete parse fills in the status line
+        let code = response.code.unwrap_or_default();
+        if response.version != Some(1) || code != 101 {
             return Err(Error::InvalidHandshake(format!(
-                "Invalid Connection header: {}",
-                connection
+                "Expected 101 status, got: HTTP/1.{} {} {}",
+                response.version.unwrap_or_default(),
+                code,
+                response.reason.unwrap_or_default()
             )));
         }
 
+        let headers = collect_headers(response.headers, &[])?;
+        Self::from_headers(&headers, compat)
+    }
+
+    /// Build a `101` response from its headers, checking them as
+    /// [`parse`](Self::parse) does.
+    pub(crate) fn from_headers(headers: &Headers, compat: &CompatMode) -> Result<Self> {
+        check_upgrade(headers, compat, " in response")?;
+
         // Extract Sec-WebSocket-Accept
         let accept = headers
             .get("sec-websocket-accept")
             .ok_or_else(|| Error::InvalidHandshake("Missing Sec-WebSocket-Accept header".into()))?
-            .clone();
+            .to_string();
 
-        // Extract optional protocol
-        let protocol = headers.get("sec-websocket-protocol").cloned();
+        // Extract optional protocol; the server selects at most one
+        let mut protocols = headers.get_all("sec-websocket-protocol");
+        let protocol = protocols.next().map(ToString::to_string);
+        if protocols.next().is_some() {
+            return Err(Error::InvalidHandshake(
+                "Duplicate header: Sec-WebSocket-Protocol".into(),
+            ));
+        }
 
-        // Extract optional extensions
-        let extensions = headers
-            .get("sec-websocket-extensions")
-            .map(|e| e.split(',').map(|s| s.trim().to_string()).collect())
-            .unwrap_or_default();
+        // Extract optional extensions, possibly split over several lines
+        let extensions = headers.list("sec-websocket-extensions");
 
         Ok(Self {
             accept,
             protocol,
             extensions,
+            headers: others(headers, &RESPONSE_FIELDS),
         })
     }
 }
 
+/// Compare without exiting early at the first differing byte.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Log a handshake failure before handing the error back.
+///
+/// Failures are counted by the client and server handshakes instead, which
+/// may run several of these checks on one request.
+pub(crate) fn rejected(err: Error) -> Error {
+    ws_debug!(error = %err, "handshake rejected");
+    err
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -458,6 +835,14 @@ mod tests {
         assert_eq!(compute_accept_key(key), expected);
     }
 
+    #[test]
+    fn test_generate_key_is_valid() {
+        let key = generate_key();
+        assert_eq!(BASE64.decode(&key).unwrap().len(), 16);
+        assert!(HandshakeRequest::new("host", "/", key).validate().is_ok());
+        assert_ne!(generate_key(), generate_key());
+    }
+
     // Test 2: Full client request parsing
     #[test]
     fn test_parse_valid_request() {
@@ -542,6 +927,7 @@ mod tests {
             origin: None,
             protocols: vec![],
             extensions: vec![],
+            headers: Headers::new(),
         };
         assert!(valid_req.validate().is_ok());
 
@@ -571,6 +957,7 @@ mod tests {
             origin: None,
             protocols: vec!["chat".to_string(), "superchat".to_string()],
             extensions: vec![],
+            headers: Headers::new(),
         };
 
         let resp = HandshakeResponse::from_request(&req);
@@ -585,6 +972,7 @@ mod tests {
             accept: "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=".to_string(),
             protocol: Some("chat".to_string()),
             extensions: vec![],
+            headers: Headers::new(),
         };
 
         let mut buf = Vec::new();
@@ -643,6 +1031,47 @@ mod tests {
         let expected_accept = compute_accept_key(&req.key);
         assert_eq!(parsed_resp.accept, expected_accept);
         assert_eq!(parsed_resp.accept, "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+        assert!(parsed_resp.validate(&req.key).is_ok());
+    }
+
+    #[test]
+    fn test_response_validate_rejects_wrong_accept() {
+        let mut resp = HandshakeResponse::from_request(&HandshakeRequest::new(
+            "example.com",
+            "/",
+            "dGhlIHNhbXBsZSBub25jZQ==",
+        ));
+        assert!(matches!(
+            resp.validate("AQIDBAUGBwgJCgsMDQ4PEA=="),
+            Err(Error::InvalidHandshake(_))
+        ));
+
+        resp.accept.pop();
+        assert!(resp.validate("dGhlIHNhbXBsZSBub25jZQ==").is_err());
+    }
+
+    #[test]
+    fn test_response_validate_protocol() {
+        let offered = vec!["chat".to_string(), "superchat".to_string()];
+        let mut resp = HandshakeResponse::from_request(&HandshakeRequest::new(
+            "example.com",
+            "/",
+            "dGhlIHNhbXBsZSBub25jZQ==",
+        ));
+        assert!(resp.validate_protocol(&offered, false).is_ok());
+        assert!(matches!(
+            resp.validate_protocol(&offered, true),
+            Err(Error::MissingSubprotocol)
+        ));
+
+        resp.protocol = Some("superchat".into());
+        assert!(resp.validate_protocol(&offered, true).is_ok());
+
+        resp.protocol = Some("mqtt".into());
+        assert!(matches!(
+            resp.validate_protocol(&offered, false),
+            Err(Error::UnexpectedSubprotocol(p)) if p == "mqtt"
+        ));
     }
 
     #[test]
@@ -676,6 +1105,26 @@ mod tests {
         assert!(validate_origin(None, &allowed).is_ok());
     }
 
+    #[test]
+    fn test_host_allowed() {
+        let allowed = vec!["localhost".to_string(), "[::1]:9001".to_string()];
+        assert!(validate_host("localhost", &allowed).is_ok());
+        assert!(validate_host("LocalHost:8080", &allowed).is_ok());
+        assert!(validate_host("[::1]:9001", &allowed).is_ok());
+        assert!(validate_host("[::1]:9002", &allowed).is_err());
+        assert!(validate_host("[::1]", &allowed).is_err());
+    }
+
+    #[test]
+    fn test_host_not_allowed() {
+        let allowed = vec!["localhost:8080".to_string()];
+        let result = validate_host("rebind.attacker.test:8080", &allowed);
+        assert!(matches!(result, Err(Error::HostNotAllowed { .. })));
+        assert!(validate_host("localhost:8081", &allowed).is_err());
+        assert!(validate_host("localhost.attacker.test", &allowed).is_err());
+        assert!(validate_host("anything", &[]).is_ok());
+    }
+
     #[test]
     fn test_case_insensitive_headers() {
         let request = b"GET /chat HTTP/1.1\r\n\
@@ -709,6 +1158,145 @@ Sec-WebSocket-Version: 13\r\n\r\n";
         ));
     }
 
+    #[test]
+    fn test_duplicate_response_protocol_rejected() {
+        let response = b"HTTP/1.1 101 Switching Protocols\r\n\
+Upgrade: websocket\r\n\
+Connection: Upgrade\r\n\
+Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\
+Sec-WebSocket-Protocol: chat\r\n\
+Sec-WebSocket-Protocol: superchat\r\n\r\n";
+
+        let result = HandshakeResponse::parse(response);
+        assert!(matches!(
+            result,
+            Err(Error::InvalidHandshake(msg)) if msg.contains("Duplicate")
+        ));
+    }
+
+    #[test]
+    fn test_repeated_list_headers_are_joined() {
+        let request = b"GET / HTTP/1.1\r\n\
+Host:example.com \t\r\n\
+Upgrade: websocket\r\n\
+Connection: keep-alive, Upgrade\r\n\
+Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+Sec-WebSocket-Version: 13\r\n\
+Sec-WebSocket-Protocol: chat\r\n\
+Sec-WebSocket-Protocol: superchat\r\n\
+Sec-WebSocket-Extensions: permessage-deflate\r\n\
+Sec-WebSocket-Extensions: x-custom; a=1\r\n\r\n";
+
+        let req = HandshakeRequest::parse(request).unwrap();
+        assert_eq!(req.host, "example.com");
+        assert_eq!(req.protocols, ["chat", "superchat"]);
+        assert_eq!(req.extensions, ["permessage-deflate", "x-custom; a=1"]);
+    }
+
+    #[test]
+    fn test_other_headers_kept() {
+        let mut request = HandshakeRequest::new("example.com", "/", "dGhlIHNhbXBsZSBub25jZQ==");
+        request.headers.append("Cookie", "a=1");
+        request.headers.append("Cookie", "b=2");
+        request.headers.append("Authorization", "Bearer token");
+
+        let mut buf = Vec::new();
+        request.write(&mut buf).unwrap();
+        let parsed = HandshakeRequest::parse(&buf).unwrap();
+        assert_eq!(parsed.headers.get_all("cookie").count(), 2);
+        assert_eq!(parsed.headers.get("authorization"), Some("Bearer token"));
+        assert!(!parsed.headers.contains("Host"));
+        assert_eq!(parsed, request);
+
+        let response = b"HTTP/1.1 101 Switching Protocols\r\n\
+Upgrade: websocket\r\n\
+Connection: Upgrade\r\n\
+Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\
+Set-Cookie: session=1\r\n\r\n";
+        let resp = HandshakeResponse::parse(response).unwrap();
+        assert_eq!(
+            resp.headers.iter().collect::<Vec<_>>(),
+            [("Set-Cookie", "session=1")]
+        );
+    }
+
+    #[test]
+    fn test_compat_mode() {
+        let request = b"GET / HTTP/1.1\r\n\
+Host: example.com\r\n\
+Upgrade: websocket, h2c\r\n\
+Connection: keep-alive\r\n\
+Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+
+        assert!(HandshakeRequest::parse(request).is_err());
+        let lenient = CompatMode::lenient();
+        let req = HandshakeRequest::parse_with_compat(request, &lenient).unwrap();
+        assert_eq!(req.version, 13);
+
+        for compat in [
+            lenient.with_upgrade_list(false),
+            lenient.with_connection_without_upgrade(false),
+            lenient.with_missing_version(false),
+        ] {
+            assert!(HandshakeRequest::parse_with_compat(request, &compat).is_err());
+        }
+
+        let response = b"HTTP/1.1 101 Switching Protocols\r\n\
+upgrade: WebSocket\r\n\
+Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\r\n";
+        assert!(HandshakeResponse::parse(response).is_err());
+        assert!(HandshakeResponse::parse_with_compat(response, &lenient).is_ok());
+    }
+
+    #[test]
+    fn test_connection_token_list() {
+        let request = |connection: &str| {
+            format!(
+                "GET / HTTP/1.1\r\nHost: x\r\nUpgrade: websocket\r\nConnection: {}\r\n\
+                 Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+                connection
+            )
+        };
+        assert!(HandshakeRequest::parse(request("keep-alive, upgrade").as_bytes()).is_ok());
+        assert!(HandshakeRequest::parse(request("not-upgrade").as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_folded_headers() {
+        // Servers must not accept obsolete line folding in requests
+        let request = b"GET / HTTP/1.1\r\n\
+Host: example.com\r\n\
+Upgrade:\r\n websocket\r\n\
+Connection: Upgrade\r\n\
+Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+Sec-WebSocket-Version: 13\r\n\r\n";
+        assert!(matches!(
+            HandshakeRequest::parse(request),
+            Err(Error::InvalidHandshake(msg)) if msg.contains("Malformed")
+        ));
+
+        // Clients unfold them in responses
+        let response = b"HTTP/1.1 101 Switching Protocols\r\n\
+Upgrade: websocket\r\n\
+Connection: Upgrade\r\n\
+Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\
+Sec-WebSocket-Extensions: permessage-deflate;\r\n client_max_window_bits\r\n\r\n";
+        let resp = HandshakeResponse::parse(response).unwrap();
+        assert_eq!(
+            resp.extensions,
+            ["permessage-deflate; client_max_window_bits"]
+        );
+    }
+
+    #[test]
+    fn test_incomplete_request_rejected() {
+        let request = b"GET / HTTP/1.1\r\nHost: example.com\r\n";
+        assert!(matches!(
+            HandshakeRequest::parse(request),
+            Err(Error::InvalidHandshake(msg)) if msg.contains("Incomplete")
+        ));
+    }
+
     #[test]
     fn test_handshake_too_large() {
         let large_data = vec![b'A'; 10000];
@@ -795,6 +1383,7 @@ Sec-WebSocket-Version: 13\r\n\r\n";
             accept: "dGhlIHNhbXBsZSBub25jZQ==".to_string(),
             protocol: Some("chat\r\nX-Injected: evil".to_string()),
             extensions: vec![],
+            headers: Headers::new(),
         };
         let mut buf = Vec::new();
         let result = response.write(&mut buf);
@@ -807,6 +1396,7 @@ Sec-WebSocket-Version: 13\r\n\r\n";
             accept: "dGhlIHNhbXBsZSBub25jZQ==".to_string(),
             protocol: None,
             extensions: vec!["permessage-deflate\nX-Evil: bad".to_string()],
+            headers: Headers::new(),
         };
         let mut buf = Vec::new();
         let result = response.write(&mut buf);
@@ -819,10 +1409,35 @@ Sec-WebSocket-Version: 13\r\n\r\n";
             accept: "dGhlIHNhbXBsZSBub25jZQ==".to_string(),
             protocol: Some("chat".to_string()),
             extensions: vec!["permessage-deflate".to_string()],
+            headers: Headers::new(),
         };
         let mut buf = Vec::new();
         let result = response.write(&mut buf);
         assert!(result.is_ok());
         assert!(!buf.is_empty());
     }
+
+    #[test]
+    fn test_request_write_round_trip() {
+        let mut request = HandshakeRequest::new(
+            "example.com:8080",
+            "/chat?room=1",
+            "dGhlIHNhbXBsZSBub25jZQ==",
+        );
+        request.origin = Some("https://example.com".to_string());
+        request.protocols = vec!["chat".to_string(), "superchat".to_string()];
+
+        let mut buf = Vec::new();
+        request.write(&mut buf).unwrap();
+        let parsed = HandshakeRequest::parse(&buf).unwrap();
+        assert_eq!(parsed, request);
+        assert!(parsed.validate().is_ok());
+    }
+
+    #[test]
+    fn test_crlf_in_request_path_rejected() {
+        let request = HandshakeRequest::new("example.com", "/\r\nX-Evil: 1", "key");
+        let result = request.write(&mut Vec::new());
+        assert!(matches!(result, Err(Error::InvalidHeaderValue { .. })));
+    }
 }
@@ -0,0 +1,165 @@
+//! An ordered HTTP header multimap.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use crate::error::Result;
+use crate::protocol::handshake::{validate_header_name, validate_header_value};
+
+/// HTTP header fields in the order they were sent.
+///
+/// A name may appear more than once. Names keep the case they were given
+/// but are matched case-insensitively.
+///
+/// ```
+/// use rsws::protocol::Headers;
+///
+/// let mut headers = Headers::new();
+/// headers.append("Cookie", "a=1");
+/// headers.append("cookie", "b=2");
+/// assert_eq!(headers.get("COOKIE"), Some("a=1"));
+/// assert_eq!(headers.get_all("cookie").collect::<Vec<_>>(), ["a=1", "b=2"]);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Headers {
+    entries: Vec<(String, String)>,
+}
+
+impl Headers {
+    /// An empty header map.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a field, keeping any already present under the same name.
+    pub fn append(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        self.entries.push((name.into(), value.into()));
+    }
+
+    /// Remove every field called `name`, returning how many there were.
+    pub fn remove(&mut self, name: &str) -> usize {
+        let before = self.entries.len();
+        self.entries.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+        before - self.entries.len()
+    }
+
+    /// The first value of `name`.
+    #[must_use]
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Every value of `name`, in order.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.entries
+            .iter()
+            .filter(move |(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Returns `true` if `name` is present.
+    #[must_use]
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// All fields as `(name, value)` pairs, in order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|(n, v)| (n.as_str(), v.as_str()))
+    }
+
+    /// Number of fields, counting repeated names separately.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if there are no fields.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The comma-separated list items of every `name` field, trimmed, with
+    /// empty items dropped (RFC 9110 Section 5.6.1).
+    pub(crate) fn list(&self, name: &str) -> Vec<String> {
+        self.get_all(name)
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    /// Write every field as a header line.
+    ///
+    /// # Errors
+    /// Returns `Error::InvalidHeaderValue` if a name is not a token or a
+    /// value contains CR/LF.
+    pub(crate) fn write(&self, buf: &mut Vec<u8>) -> Result<()> {
+        for (name, value) in &self.entries {
+            validate_header_name(name)?;
+            validate_header_value(name, value)?;
+            buf.extend_from_slice(format!("{}: {}\r\n", name, value).as_bytes());
+        }
+        Ok(())
+    }
+}
+
+impl<N: Into<String>, V: Into<String>> FromIterator<(N, V)> for Headers {
+    fn from_iter<I: IntoIterator<Item = (N, V)>>(iter: I) -> Self {
+        Self {
+            entries: iter
+                .into_iter()
+                .map(|(n, v)| (n.into(), v.into()))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_list_merges_repeated_fields() {
+        let headers: Headers = [
+            ("Sec-WebSocket-Protocol", "chat, ,superchat"),
+            ("X-Other", "1"),
+            ("sec-websocket-protocol", "v2"),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(
+            headers.list("Sec-WebSocket-Protocol"),
+            ["chat", "superchat", "v2"]
+        );
+        assert!(headers.list("missing").is_empty());
+    }
+
+    #[test]
+    fn test_remove_and_write() {
+        let mut headers = Headers::new();
+        headers.append("X-Trace", "1");
+        headers.append("Authorization", "Bearer t");
+        headers.append("x-trace", "2");
+        assert_eq!(headers.remove("X-TRACE"), 2);
+
+        let mut buf = Vec::new();
+        headers.write(&mut buf).unwrap();
+        assert_eq!(buf, b"Authorization: Bearer t\r\n");
+
+        headers.append("X-Evil", "a\r\nInjected: 1");
+        assert!(headers.write(&mut Vec::new()).is_err());
+
+        for name in ["", "X Space", "X-Colon:", "X-Évil", "X-Evil\r\nInjected"] {
+            let headers: Headers = [(name, "1")].into_iter().collect();
+            assert!(headers.write(&mut Vec::new()).is_err(), "{name:?}");
+        }
+    }
+}
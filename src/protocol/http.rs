@@ -0,0 +1,282 @@
+//! Conversions between handshakes and [`http`] crate types.
+//!
+//! Enabled by the `http` feature, so frameworks that already hand out
+//! `http::Request`s can validate an upgrade with rsws and answer it with an
+//! `http::Response`:
+//!
+//! ```rust,ignore
+//! let request = HandshakeRequest::try_from(&req)?;
+//! request.validate()?;
+//! let response: http::Response<()> = HandshakeResponse::from_request(&request).try_into()?;
+//! ```
+//!
+//! Requests and responses are checked as [`HandshakeRequest::parse`] and
+//! [`HandshakeResponse::parse`] check raw heads, with the default
+//! [`CompatMode`].
+
+use http::header::{HeaderMap, HeaderName, HeaderValue};
+use http::{Method, Request, Response, StatusCode, Version};
+
+use super::handshake::{
+    CompatMode, HandshakeRequest, HandshakeResponse, SECURITY_FIELDS, collect_pairs, rejected,
+};
+use super::headers::Headers;
+use crate::error::{Error, Result};
+
+impl<B> TryFrom<&Request<B>> for HandshakeRequest {
+    type Error = Error;
+
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidHandshake`] if the request is not an
+    /// HTTP/1.1 `GET` upgrade to WebSocket, as for [`HandshakeRequest::parse`].
+    fn try_from(request: &Request<B>) -> Result<Self> {
+        from_request(request).map_err(rejected)
+    }
+}
+
+fn from_request<B>(request: &Request<B>) -> Result<HandshakeRequest> {
+    if request.method() != Method::GET {
+        return Err(Error::InvalidHandshake(format!(
+            "Expected GET method, got {}",
+            request.method()
+        )));
+    }
+    if request.version() != Version::HTTP_11 {
+        return Err(Error::InvalidHandshake(format!(
+            "Expected HTTP/1.1, got {:?}",
+            request.version()
+        )));
+    }
+
+    let mut headers = collect(request.headers(), SECURITY_FIELDS)?;
+    // Servers may have moved the Host header into the URI
+    if !headers.contains("host")
+        && let Some(authority) = request.uri().authority()
+    {
+        headers.append("Host", authority.as_str());
+    }
+    let path = request
+        .uri()
+        .path_and_query()
+        .map_or("/", |path| path.as_str())
+        .to_string();
+    HandshakeRequest::from_headers(path, &headers, &CompatMode::default())
+}
+
+impl TryFrom<&HandshakeRequest> for Request<()> {
+    type Error = Error;
+
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidHeaderValue`] if the path or a header cannot
+    /// be represented in `http` types.
+    fn try_from(request: &HandshakeRequest) -> Result<Self> {
+        let mut builder = Request::get(request.path.as_str())
+            .version(Version::HTTP_11)
+            .header("Host", value("Host", &request.host)?)
+            .header("Upgrade", "websocket")
+            .header("Connection", "Upgrade")
+            .header(
+                "Sec-WebSocket-Key",
+                value("Sec-WebSocket-Key", &request.key)?,
+            )
+            .header("Sec-WebSocket-Version", request.version.to_string());
+        if let Some(origin) = &request.origin {
+            builder = builder.header("Origin", value("Origin", origin)?);
+        }
+        if !request.protocols.is_empty() {
+            let protocols = request.protocols.join(", ");
+            builder = builder.header(
+                "Sec-WebSocket-Protocol",
+                value("Sec-WebSocket-Protocol", &protocols)?,
+            );
+        }
+        for ext in &request.extensions {
+            builder = builder.header(
+                "Sec-WebSocket-Extensions",
+                value("Sec-WebSocket-Extensions", ext)?,
+            );
+        }
+        let mut http = builder.body(()).map_err(|e| Error::InvalidHeaderValue {
+            header: "path".into(),
+            reason: e.to_string(),
+        })?;
+        extend(http.headers_mut(), &request.headers)?;
+        Ok(http)
+    }
+}
+
+impl<B> TryFrom<&Response<B>> for HandshakeResponse {
+    type Error = Error;
+
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidHandshake`] if the response is not a `101`
+    /// upgrade to WebSocket, as for [`HandshakeResponse::parse`].
+    fn try_from(response: &Response<B>) -> Result<Self> {
+        from_response(response).map_err(rejected)
+    }
+}
+
+fn from_response<B>(response: &Response<B>) -> Result<HandshakeResponse> {
+    if response.status() != StatusCode::SWITCHING_PROTOCOLS {
+        return Err(Error::InvalidHandshake(format!(
+            "Expected 101 status, got: {}",
+            response.status()
+        )));
+    }
+    let headers = collect(response.headers(), &[])?;
+    HandshakeResponse::from_headers(&headers, &CompatMode::default())
+}
+
+impl TryFrom<&HandshakeResponse> for Response<()> {
+    type Error = Error;
+
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidHeaderValue`] if a header cannot be
+    /// represented in `http` types.
+    fn try_from(response: &HandshakeResponse) -> Result<Self> {
+        let mut http = Response::new(());
+        *http.status_mut() = StatusCode::SWITCHING_PROTOCOLS;
+        let headers = http.headers_mut();
+        headers.insert("upgrade", HeaderValue::from_static("websocket"));
+        headers.insert("connection", HeaderValue::from_static("Upgrade"));
+        headers.insert(
+            "sec-websocket-accept",
+            value("Sec-WebSocket-Accept", &response.accept)?,
+        );
+        if let Some(protocol) = &response.protocol {
+            headers.insert(
+                "sec-websocket-protocol",
+                value("Sec-WebSocket-Protocol", protocol)?,
+            );
+        }
+        for ext in &response.extensions {
+            headers.append(
+                "sec-websocket-extensions",
+                value("Sec-WebSocket-Extensions", ext)?,
+            );
+        }
+        extend(headers, &response.headers)?;
+        Ok(http)
+    }
+}
+
+impl TryFrom<HandshakeResponse> for Response<()> {
+    type Error = Error;
+
+    /// See the impl for `&HandshakeResponse`.
+    fn try_from(response: HandshakeResponse) -> Result<Self> {
+        Self::try_from(&response)
+    }
+}
+
+/// Collect `map` into [`Headers`], rejecting duplicated
+/// `security_headers` and values that are not UTF-8.
+fn collect(map: &HeaderMap, security_headers: &[&str]) -> Result<Headers> {
+    let pairs = map.iter().map(|(name, value)| {
+        let value = core::str::from_utf8(value.as_bytes())
+            .map_err(|_| Error::InvalidHandshake(format!("Invalid UTF-8 in header: {}", name)))?;
+        Ok((name.as_str(), value.trim().to_string()))
+    });
+    collect_pairs(pairs, security_headers)
+}
+
+/// Append `headers` to `map`.
+fn extend(map: &mut HeaderMap, headers: &Headers) -> Result<()> {
+    for (name, val) in headers.iter() {
+        let header =
+            HeaderName::from_bytes(name.as_bytes()).map_err(|e| Error::InvalidHeaderValue {
+                header: name.to_string(),
+                reason: e.to_string(),
+            })?;
+        map.append(header, value(name, val)?);
+    }
+    Ok(())
+}
+
+fn value(header: &str, value: &str) -> Result<HeaderValue> {
+    HeaderValue::from_str(value).map_err(|e| Error::InvalidHeaderValue {
+        header: header.to_string(),
+        reason: e.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::compute_accept_key;
+
+    const KEY: &str = "dGhlIHNhbXBsZSBub25jZQ==";
+
+    #[test]
+    fn test_request_round_trip() {
+        let http = Request::get("/chat?room=1")
+            .header("Host", "example.com")
+            .header("Upgrade", "websocket")
+            .header("Connection", "keep-alive, Upgrade")
+            .header("Sec-WebSocket-Key", KEY)
+            .header("Sec-WebSocket-Version", "13")
+            .header("Sec-WebSocket-Protocol", "chat, superchat")
+            .header("Cookie", "session=1")
+            .body(())
+            .unwrap();
+
+        let request = HandshakeRequest::try_from(&http).unwrap();
+        assert_eq!(request.path, "/chat?room=1");
+        assert_eq!(request.host, "example.com");
+        assert_eq!(request.protocols, ["chat", "superchat"]);
+        assert_eq!(request.headers.get("cookie"), Some("session=1"));
+        request.validate().unwrap();
+
+        let back = Request::try_from(&request).unwrap();
+        assert_eq!(HandshakeRequest::try_from(&back).unwrap(), request);
+    }
+
+    #[test]
+    fn test_request_rejects_non_upgrades() {
+        let post = Request::post("/").body(()).unwrap();
+        assert!(matches!(
+            HandshakeRequest::try_from(&post),
+            Err(Error::InvalidHandshake(_))
+        ));
+
+        let plain = Request::get("/")
+            .header("Host", "example.com")
+            .body(())
+            .unwrap();
+        assert!(HandshakeRequest::try_from(&plain).is_err());
+
+        let twice = Request::get("/")
+            .header("Host", "example.com")
+            .header("Upgrade", "websocket")
+            .header("Connection", "Upgrade")
+            .header("Sec-WebSocket-Key", KEY)
+            .header("Sec-WebSocket-Key", KEY)
+            .header("Sec-WebSocket-Version", "13")
+            .body(())
+            .unwrap();
+        assert!(HandshakeRequest::try_from(&twice).is_err());
+    }
+
+    #[test]
+    fn test_response_round_trip() {
+        let mut response =
+            HandshakeResponse::from_request(&HandshakeRequest::new("example.com", "/", KEY));
+        response.protocol = Some("chat".into());
+        response.headers.append("set-cookie", "a=b");
+
+        let http = Response::try_from(&response).unwrap();
+        assert_eq!(http.status(), StatusCode::SWITCHING_PROTOCOLS);
+        assert_eq!(
+            http.headers()["sec-websocket-accept"],
+            compute_accept_key(KEY).as_str()
+        );
+        assert_eq!(HandshakeResponse::try_from(&http).unwrap(), response);
+
+        let refused = Response::builder().status(403).body(()).unwrap();
+        assert!(HandshakeResponse::try_from(&refused).is_err());
+    }
+}
@@ -42,9 +42,9 @@ fn apply_mask_scalar(data: &mut [u8], mask: [u8; 4]) {
 #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
 mod x86_simd {
     #[cfg(target_arch = "x86")]
-    use std::arch::x86::*;
+    use core::arch::x86::*;
     #[cfg(target_arch = "x86_64")]
-    use std::arch::x86_64::*;
+    use core::arch::x86_64::*;
 
     /// SSE2 implementation: processes 16 bytes per iteration.
     ///
@@ -252,7 +252,7 @@ mod sve {
 
 #[cfg(target_arch = "aarch64")]
 mod aarch64_simd {
-    use std::arch::aarch64::*;
+    use core::arch::aarch64::*;
 
     /// NEON implementation: processes 64 bytes per iteration (4x 128-bit vectors).
     ///
@@ -357,14 +357,14 @@ mod aarch64_simd {
 pub fn apply_mask_simd(data: &mut [u8], mask: [u8; 4]) {
     #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
     {
-        // SAFETY: is_x86_feature_detected! is a safe macro that checks CPU features at runtime.
-        // We only call the unsafe SIMD function if the corresponding feature is detected.
-        if is_x86_feature_detected!("avx2") {
+        // SAFETY: x86_feature_detected! checks CPU features at runtime (at build time
+        // without std). We only call the unsafe SIMD function if the feature is present.
+        if x86_feature_detected!("avx2") {
             // SAFETY: AVX2 feature is confirmed available by the runtime check above.
             // apply_mask_avx2 requires AVX2, which we just verified is present.
             return unsafe { x86_simd::apply_mask_avx2(data, mask) };
         }
-        if is_x86_feature_detected!("sse2") {
+        if x86_feature_detected!("sse2") {
             // SAFETY: SSE2 feature is confirmed available by the runtime check above.
             // apply_mask_sse2 requires SSE2, which we just verified is present.
             return unsafe { x86_simd::apply_mask_sse2(data, mask) };
@@ -373,10 +373,10 @@ pub fn apply_mask_simd(data: &mut [u8], mask: [u8; 4]) {
 
     #[cfg(target_arch = "aarch64")]
     {
-        if std::arch::is_aarch64_feature_detected!("sve") {
+        if aarch64_feature_detected!("sve") {
             return unsafe { sve::apply_mask_sve(data, mask) };
         }
-        if std::arch::is_aarch64_feature_detected!("neon") {
+        if aarch64_feature_detected!("neon") {
             return unsafe { aarch64_simd::apply_mask_neon(data, mask) };
         }
     }
@@ -394,10 +394,88 @@ pub fn apply_mask_fast(data: &mut [u8], mask: [u8; 4]) {
     apply_mask_simd(data, mask)
 }
 
+/// Produces the masking keys for outgoing client frames.
+///
+/// RFC 6455 Section 5.3 requires keys an intermediary cannot predict, which
+/// `SeededMasks::random` (with `std`) provides. Other generators are for
+/// tests that need exact wire bytes.
+pub trait MaskGenerator: Send + Sync {
+    /// The key for the next frame.
+    fn next_mask(&mut self) -> [u8; 4];
+}
+
+/// A fast mixing generator over a 32-bit seed.
+///
+/// The same seed always yields the same keys, so golden-byte tests and fuzz
+/// reproductions can assert exact output:
+///
+/// ```
+/// use rsws::protocol::mask::{MaskGenerator, SeededMasks};
+///
+/// let mut a = SeededMasks::new(7);
+/// let mut b = SeededMasks::new(7);
+/// assert_eq!(a.next_mask(), b.next_mask());
+/// ```
+#[derive(Debug, Clone)]
+pub struct SeededMasks {
+    state: u32,
+}
+
+impl SeededMasks {
+    /// Keys derived from `seed`. Predictable; do not use on real
+    /// connections.
+    #[must_use]
+    pub const fn new(seed: u32) -> Self {
+        Self { state: seed }
+    }
+
+    /// Keys derived from a seed drawn from the system random number
+    /// generator. This is what codecs use unless told otherwise.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the system's random number generator is unavailable.
+    /// This is a critical security requirement - WebSocket masking MUST use
+    /// cryptographically secure random values to prevent cache poisoning attacks.
+    #[cfg(feature = "std")]
+    #[must_use]
+    pub fn random() -> Self {
+        let mut buf = [0u8; 4];
+        getrandom::getrandom(&mut buf).expect(
+            "Failed to obtain random bytes for WebSocket mask. \
+             This is a critical security requirement. \
+             Ensure your system has a working random number generator.",
+        );
+        Self::new(u32::from_le_bytes(buf))
+    }
+}
+
+impl MaskGenerator for SeededMasks {
+    fn next_mask(&mut self) -> [u8; 4] {
+        self.state = self.state.wrapping_add(0x9E37_79B9);
+        let a = self.state;
+        let b = a.wrapping_mul(0x85EB_CA6B);
+        let c = b ^ (b >> 13);
+        let d = c.wrapping_mul(0xC2B2_AE35);
+        d.to_le_bytes()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_seeded_masks_repeat() {
+        let keys = |seed| {
+            let mut masks = SeededMasks::new(seed);
+            [masks.next_mask(), masks.next_mask()]
+        };
+        assert_eq!(keys(1), keys(1));
+        assert_ne!(keys(1), keys(2));
+        assert_ne!(keys(1)[0], keys(1)[1]);
+    }
+
     #[test]
     fn test_masking_reversible() {
         let mask = [0x12, 0x34, 0x56, 0x78];
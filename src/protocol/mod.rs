@@ -1,18 +1,71 @@
 //! WebSocket protocol core implementation (RFC 6455).
+//!
+//! Frame parsing, masking, opcodes, UTF-8 validation and handshake parsing
+//! need only `alloc`, so they stay available without the `std` feature.
 
+// Without `std` there is no runtime detection, so SIMD paths are taken only
+// when the build targets the feature.
+#[cfg(feature = "std")]
+#[allow(unused_macros)]
+macro_rules! x86_feature_detected {
+    ($feature:tt) => {
+        ::std::is_x86_feature_detected!($feature)
+    };
+}
+
+#[cfg(not(feature = "std"))]
+#[allow(unused_macros)]
+macro_rules! x86_feature_detected {
+    ($feature:tt) => {
+        cfg!(target_feature = $feature)
+    };
+}
+
+#[cfg(feature = "std")]
+#[allow(unused_macros)]
+macro_rules! aarch64_feature_detected {
+    ($feature:tt) => {
+        ::std::arch::is_aarch64_feature_detected!($feature)
+    };
+}
+
+#[cfg(not(feature = "std"))]
+#[allow(unused_macros)]
+macro_rules! aarch64_feature_detected {
+    ($feature:tt) => {
+        cfg!(target_feature = $feature)
+    };
+}
+
+#[cfg(feature = "std")]
 pub mod assembler;
 pub mod frame;
 pub mod handshake;
+pub mod headers;
+#[cfg(feature = "http")]
+pub mod http;
 pub mod mask;
 pub mod opcode;
+#[cfg(feature = "std")]
+pub mod spill;
 pub mod utf8;
 pub mod utf8_simd;
+#[cfg(feature = "std")]
 pub mod validation;
+#[cfg(feature = "debug-wire")]
+pub mod wire;
 
+#[cfg(feature = "std")]
 pub use assembler::{AssembledMessage, MessageAssembler};
-pub use frame::Frame;
-pub use handshake::{HandshakeRequest, HandshakeResponse, WS_GUID, compute_accept_key};
-pub use mask::{apply_mask, apply_mask_fast};
+pub use frame::{Frame, FrameHead};
+#[cfg(feature = "std")]
+pub use handshake::generate_key;
+pub use handshake::{CompatMode, HandshakeRequest, HandshakeResponse, WS_GUID, compute_accept_key};
+pub use headers::Headers;
+pub use mask::{MaskGenerator, SeededMasks, apply_mask, apply_mask_fast};
 pub use opcode::OpCode;
+#[cfg(feature = "std")]
+pub use spill::{Spill, SpillSink, SpillWriter, SpilledMessage, SpilledPayload, TempFiles};
 pub use utf8::{Utf8Validator, validate_utf8};
-pub use validation::FrameValidator;
+#[cfg(feature = "std")]
+pub use validation::{FrameRules, FrameValidator};
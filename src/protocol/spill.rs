@@ -0,0 +1,437 @@
+//! Spilling large incoming messages out of memory.
+//!
+//! With [`Config::with_spill`](crate::Config::with_spill) set, a message that
+//! grows past the threshold while it is being assembled is streamed fragment
+//! by fragment into a [`SpillSink`] instead of a memory buffer, and arrives
+//! as [`Message::Spilled`](crate::Message::Spilled). Relays moving large
+//! files then hold at most one frame of each message in memory.
+//!
+//! [`TempFiles`] writes to the system temporary directory; implement the
+//! traits to spill anywhere else. The traits are blocking: a `Connection`
+//! calls them on tokio's blocking thread pool, never on the task receiving
+//! or sending the message.
+
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use bytes::Bytes;
+
+use crate::protocol::OpCode;
+
+/// Creates a destination for each spilled message.
+pub trait SpillSink: fmt::Debug + Send + Sync {
+    /// Open a writer for a new `Text` or `Binary` message.
+    fn create(&self, opcode: OpCode) -> io::Result<Box<dyn SpillWriter>>;
+}
+
+/// Receives the payload of one spilled message.
+///
+/// Dropping a writer without calling [`finish`](Self::finish) abandons the
+/// message, e.g. when the connection fails half way through.
+pub trait SpillWriter: Write + Send {
+    /// Complete the message and return a handle to the stored payload.
+    fn finish(self: Box<Self>) -> io::Result<Arc<dyn SpilledPayload>>;
+}
+
+/// A payload stored by a [`SpillWriter`].
+pub trait SpilledPayload: fmt::Debug + Send + Sync {
+    /// Read the payload from the start.
+    fn open(&self) -> io::Result<Box<dyn Read + Send>>;
+
+    /// The file holding the payload, if it is stored in one.
+    fn path(&self) -> Option<&Path> {
+        None
+    }
+}
+
+/// When and where to spill incoming messages.
+#[derive(Debug, Clone)]
+pub struct Spill {
+    /// Messages larger than this many bytes are spilled.
+    pub threshold: usize,
+    /// Where spilled payloads go.
+    pub sink: Arc<dyn SpillSink>,
+}
+
+impl Spill {
+    /// Spill messages larger than `threshold` bytes into `sink`.
+    #[must_use]
+    pub fn new(threshold: usize, sink: impl SpillSink + 'static) -> Self {
+        Self {
+            threshold,
+            sink: Arc::new(sink),
+        }
+    }
+}
+
+/// A received message whose payload was spilled out of memory.
+///
+/// Cloning is cheap and shares the stored payload. Payloads from
+/// [`TempFiles`] are deleted once the last clone is dropped.
+#[derive(Clone)]
+pub struct SpilledMessage {
+    opcode: OpCode,
+    len: u64,
+    payload: Arc<dyn SpilledPayload>,
+}
+
+impl SpilledMessage {
+    /// Wrap a stored payload of `len` bytes.
+    ///
+    /// `opcode` must be `Text` or `Binary`. A text payload must be valid
+    /// UTF-8; it is sent as is.
+    #[must_use]
+    pub fn new(opcode: OpCode, len: u64, payload: Arc<dyn SpilledPayload>) -> Self {
+        debug_assert!(matches!(opcode, OpCode::Text | OpCode::Binary));
+        Self {
+            opcode,
+            len,
+            payload,
+        }
+    }
+
+    /// The opcode of the original message (`Text` or `Binary`).
+    #[must_use]
+    pub const fn opcode(&self) -> OpCode {
+        self.opcode
+    }
+
+    /// Returns `true` if this was a text message. Its payload has been
+    /// validated as UTF-8.
+    #[must_use]
+    pub fn is_text(&self) -> bool {
+        self.opcode == OpCode::Text
+    }
+
+    /// Returns `true` if this was a binary message.
+    #[must_use]
+    pub fn is_binary(&self) -> bool {
+        self.opcode == OpCode::Binary
+    }
+
+    /// Payload length in bytes.
+    #[must_use]
+    pub const fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns `true` if the payload is empty.
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The file holding the payload, if it is stored in one.
+    #[must_use]
+    pub fn path(&self) -> Option<&Path> {
+        self.payload.path()
+    }
+
+    /// The stored payload.
+    #[must_use]
+    pub fn payload(&self) -> &Arc<dyn SpilledPayload> {
+        &self.payload
+    }
+
+    /// Read the payload from the start.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error from the underlying store.
+    pub fn open(&self) -> io::Result<Box<dyn Read + Send>> {
+        self.payload.open()
+    }
+
+    /// Read the whole payload into memory.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error from the underlying store.
+    pub fn read_to_vec(&self) -> io::Result<Vec<u8>> {
+        let mut data = Vec::with_capacity(usize::try_from(self.len).unwrap_or(0));
+        self.open()?.read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    /// Read the whole payload into memory on tokio's blocking thread pool,
+    /// as [`read_to_vec`](Self::read_to_vec) without blocking the task.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error from the underlying store.
+    #[cfg(feature = "async-tokio")]
+    pub async fn read(&self) -> io::Result<Vec<u8>> {
+        let message = self.clone();
+        joined(tokio::task::spawn_blocking(move || message.read_to_vec()).await)
+    }
+}
+
+/// The result of a blocking spill task, with a panic passed on.
+#[cfg(feature = "async-tokio")]
+pub(crate) fn joined<T>(result: Result<io::Result<T>, tokio::task::JoinError>) -> io::Result<T> {
+    match result {
+        Ok(result) => result,
+        Err(join_err) if join_err.is_panic() => std::panic::resume_unwind(join_err.into_panic()),
+        Err(join_err) => Err(io::Error::other(join_err)),
+    }
+}
+
+/// Payload of a message being spilled, for the writer to take.
+///
+/// Produced by the assembler without touching the sink, so that the
+/// blocking [`write`](Self::write) can run off the async task.
+pub(crate) struct SpillChunk {
+    opcode: OpCode,
+    /// Set on the first chunk of a message, which creates the writer.
+    sink: Option<Arc<dyn SpillSink>>,
+    data: Vec<Bytes>,
+    /// Set on the last chunk of a message: its length.
+    len: Option<u64>,
+}
+
+/// Where a spilled message stands once a [`SpillChunk`] is written.
+pub(crate) enum SpillStep {
+    /// More chunks follow, for this writer.
+    Open(Box<dyn SpillWriter>),
+    /// The message is complete.
+    Done(SpilledMessage),
+}
+
+impl SpillChunk {
+    pub(crate) fn new(
+        opcode: OpCode,
+        sink: Option<Arc<dyn SpillSink>>,
+        data: Vec<Bytes>,
+        len: Option<u64>,
+    ) -> Self {
+        Self {
+            opcode,
+            sink,
+            data,
+            len,
+        }
+    }
+
+    /// Write the chunk into `writer`, or a new one for the first chunk, and
+    /// finish the message on the last. Blocks on the sink's I/O.
+    pub(crate) fn write(self, writer: Option<Box<dyn SpillWriter>>) -> io::Result<SpillStep> {
+        let mut writer = match (self.sink, writer) {
+            (Some(sink), _) => sink.create(self.opcode)?,
+            (None, Some(writer)) => writer,
+            (None, None) => return Err(io::Error::other("no spilled message in progress")),
+        };
+        for data in &self.data {
+            writer.write_all(data)?;
+        }
+        match self.len {
+            Some(len) => Ok(SpillStep::Done(SpilledMessage::new(
+                self.opcode,
+                len,
+                writer.finish()?,
+            ))),
+            None => Ok(SpillStep::Open(writer)),
+        }
+    }
+}
+
+impl fmt::Debug for SpilledMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SpilledMessage")
+            .field("opcode", &self.opcode)
+            .field("len", &self.len)
+            .field("payload", &self.payload)
+            .finish()
+    }
+}
+
+impl PartialEq for SpilledMessage {
+    /// Two handles are equal if they share the same stored payload.
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.payload, &other.payload)
+    }
+}
+
+impl Eq for SpilledMessage {}
+
+/// Spills into files in a directory, the system temporary directory by
+/// default.
+///
+/// Each message gets its own file, removed when the message is dropped or
+/// abandoned. Point this at local storage: each fragment still ties up a
+/// blocking thread while it is written.
+#[derive(Debug, Clone)]
+pub struct TempFiles {
+    dir: PathBuf,
+}
+
+impl TempFiles {
+    /// Spill into [`std::env::temp_dir`].
+    #[must_use]
+    pub fn new() -> Self {
+        Self::in_dir(std::env::temp_dir())
+    }
+
+    /// Spill into `dir`, which must exist.
+    #[must_use]
+    pub fn in_dir(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// The directory files are created in.
+    #[must_use]
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+}
+
+impl Default for TempFiles {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SpillSink for TempFiles {
+    fn create(&self, _opcode: OpCode) -> io::Result<Box<dyn SpillWriter>> {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+
+        loop {
+            let name = format!(
+                "rsws-spill-{}-{}",
+                std::process::id(),
+                NEXT.fetch_add(1, Ordering::Relaxed)
+            );
+            let path = self.dir.join(name);
+            let mut options = OpenOptions::new();
+            options.write(true).create_new(true);
+            // Payloads are readable by the owner only
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+            match options.open(&path) {
+                Ok(file) => {
+                    return Ok(Box::new(TempFileWriter {
+                        file: Some(BufWriter::new(file)),
+                        path,
+                    }));
+                }
+                // Left behind by an earlier process with the same pid
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// A file being written by [`TempFiles`].
+struct TempFileWriter {
+    /// `None` once finished.
+    file: Option<BufWriter<File>>,
+    path: PathBuf,
+}
+
+impl Write for TempFileWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.file {
+            Some(file) => file.write(buf),
+            None => Err(io::Error::other("spill file already finished")),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.file {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl SpillWriter for TempFileWriter {
+    fn finish(mut self: Box<Self>) -> io::Result<Arc<dyn SpilledPayload>> {
+        if let Some(mut file) = self.file.take() {
+            file.flush()?;
+        }
+        let path = std::mem::take(&mut self.path);
+        Ok(Arc::new(TempFile { path }))
+    }
+}
+
+impl Drop for TempFileWriter {
+    fn drop(&mut self) {
+        if self.file.take().is_some() {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// A finished [`TempFiles`] payload, deleted on drop.
+#[derive(Debug)]
+struct TempFile {
+    path: PathBuf,
+}
+
+impl SpilledPayload for TempFile {
+    fn open(&self) -> io::Result<Box<dyn Read + Send>> {
+        Ok(Box::new(io::BufReader::new(File::open(&self.path)?)))
+    }
+
+    fn path(&self) -> Option<&Path> {
+        Some(&self.path)
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_temp_file_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let sink = TempFiles::in_dir(dir.path());
+
+        let mut writer = sink.create(OpCode::Binary).unwrap();
+        writer.write_all(b"hello ").unwrap();
+        writer.write_all(b"spill").unwrap();
+        let payload = writer.finish().unwrap();
+
+        let message = SpilledMessage::new(OpCode::Binary, 11, payload);
+        assert!(message.is_binary());
+        assert_eq!(message.len(), 11);
+        assert_eq!(message.read_to_vec().unwrap(), b"hello spill");
+
+        // The file lives as long as the last clone
+        let path = message.path().unwrap().to_path_buf();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        let clone = message.clone();
+        assert_eq!(clone, message);
+        drop(message);
+        assert!(path.exists());
+        drop(clone);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_abandoned_writer_removes_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let sink = TempFiles::in_dir(dir.path());
+
+        let mut writer = sink.create(OpCode::Text).unwrap();
+        writer.write_all(b"partial").unwrap();
+        drop(writer);
+
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+}
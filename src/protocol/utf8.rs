@@ -3,6 +3,8 @@
 //! This module provides incremental UTF-8 validation for fragmented messages,
 //! handling partial multi-byte sequences across fragment boundaries.
 
+use alloc::vec::Vec;
+
 use crate::error::{Error, Result};
 use crate::protocol::utf8_simd::validate_utf8_simd;
 
@@ -45,7 +47,7 @@ impl Utf8Validator {
     ///
     /// Returns `Error::InvalidUtf8` if the data contains invalid UTF-8 sequences.
     pub fn validate(&mut self, data: &[u8], is_final: bool) -> Result<()> {
-        use std::borrow::Cow;
+        use alloc::borrow::Cow;
 
         // Prepend any incomplete bytes from previous fragment
         let check_data: Cow<[u8]> = if self.incomplete_len > 0 {
@@ -64,7 +66,7 @@ impl Utf8Validator {
             return Ok(());
         }
 
-        match std::str::from_utf8(&check_data) {
+        match core::str::from_utf8(&check_data) {
             Ok(_) => Ok(()),
             Err(e) => {
                 let valid_up_to = e.valid_up_to();
@@ -1,11 +1,13 @@
 //! SIMD-accelerated UTF-8 validation for WebSocket text frames.
 //!
 //! This module provides high-performance UTF-8 validation using NEON SIMD
-//! instructions on aarch64, with a scalar fallback for other platforms.
+//! instructions on aarch64 and SSSE3/AVX2 on x86, with a scalar fallback for
+//! other platforms.
 //!
-//! The implementation uses the "range-check" algorithm which validates UTF-8
-//! by checking that each byte falls within valid ranges for its position in
-//! a multi-byte sequence.
+//! On aarch64 ASCII input is confirmed with NEON and anything else is handed
+//! to the standard library. On x86 the lookup-table algorithm validates
+//! multi-byte text in SIMD registers too, so CJK or emoji-heavy payloads do
+//! not fall back to byte-at-a-time decoding.
 
 use crate::error::{Error, Result};
 
@@ -15,7 +17,7 @@ use crate::error::{Error, Result};
 
 #[cfg(target_arch = "aarch64")]
 mod aarch64_simd {
-    use std::arch::aarch64::*;
+    use core::arch::aarch64::*;
 
     /// Check if all bytes in a 16-byte vector are ASCII (< 0x80).
     ///
@@ -36,10 +38,10 @@ mod aarch64_simd {
     ///
     /// Uses a simple, correct approach:
     /// 1. Fast path: SIMD check if entire buffer is ASCII
-    /// 2. Slow path: Fall back to std::str::from_utf8 for non-ASCII
+    /// 2. Slow path: Fall back to core::str::from_utf8 for non-ASCII
     ///
     /// This approach is:
-    /// - Correct: Uses battle-tested std::str::from_utf8 for non-ASCII
+    /// - Correct: Uses battle-tested core::str::from_utf8 for non-ASCII
     /// - Fast for ASCII: SIMD check is very fast, and most WebSocket traffic is ASCII
     /// - Simple: No complex state machine to get wrong
     ///
@@ -81,7 +83,281 @@ mod aarch64_simd {
             return true;
         }
 
-        std::str::from_utf8(data).is_ok()
+        core::str::from_utf8(data).is_ok()
+    }
+}
+
+// ============================================================================
+// x86/x86_64 SSSE3 and AVX2 implementation
+// ============================================================================
+
+/// Lookup-table UTF-8 validation (Keiser & Lemire, "Validating UTF-8 In Less
+/// Than One Instruction Per Byte").
+///
+/// Every byte is classified by three 16-entry tables indexed by the high
+/// nibble of the previous byte, the low nibble of the previous byte and the
+/// high nibble of the current byte. ANDing the three entries leaves a bit set
+/// only when the pair is an error; 3- and 4-byte sequences are then checked
+/// for the right number of continuation bytes with two saturating subtracts.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+mod x86_simd {
+    #[cfg(target_arch = "x86")]
+    use core::arch::x86::*;
+    #[cfg(target_arch = "x86_64")]
+    use core::arch::x86_64::*;
+
+    const TOO_SHORT: u8 = 1 << 0;
+    const TOO_LONG: u8 = 1 << 1;
+    const OVERLONG_3: u8 = 1 << 2;
+    const TOO_LARGE: u8 = 1 << 3;
+    const SURROGATE: u8 = 1 << 4;
+    const OVERLONG_2: u8 = 1 << 5;
+    const TOO_LARGE_1000: u8 = 1 << 6;
+    const OVERLONG_4: u8 = 1 << 6;
+    const TWO_CONTS: u8 = 1 << 7;
+    const CARRY: u8 = TOO_SHORT | TOO_LONG | TWO_CONTS;
+
+    /// Errors possible given the high nibble of the previous byte.
+    const BYTE_1_HIGH: [u8; 16] = [
+        // 0xxx: ASCII
+        TOO_LONG,
+        TOO_LONG,
+        TOO_LONG,
+        TOO_LONG,
+        TOO_LONG,
+        TOO_LONG,
+        TOO_LONG,
+        TOO_LONG,
+        // 10xx: continuation
+        TWO_CONTS,
+        TWO_CONTS,
+        TWO_CONTS,
+        TWO_CONTS,
+        // 1100: 2-byte lead, C0/C1 are overlong
+        TOO_SHORT | OVERLONG_2,
+        // 1101: 2-byte lead
+        TOO_SHORT,
+        // 1110: 3-byte lead
+        TOO_SHORT | OVERLONG_3 | SURROGATE,
+        // 1111: 4-byte lead
+        TOO_SHORT | TOO_LARGE | TOO_LARGE_1000 | OVERLONG_4,
+    ];
+
+    /// Errors possible given the low nibble of the previous byte.
+    const BYTE_1_LOW: [u8; 16] = [
+        CARRY | OVERLONG_3 | OVERLONG_2 | OVERLONG_4,
+        CARRY | OVERLONG_2,
+        CARRY,
+        CARRY,
+        CARRY | TOO_LARGE,
+        CARRY | TOO_LARGE | TOO_LARGE_1000,
+        CARRY | TOO_LARGE | TOO_LARGE_1000,
+        CARRY | TOO_LARGE | TOO_LARGE_1000,
+        CARRY | TOO_LARGE | TOO_LARGE_1000,
+        CARRY | TOO_LARGE | TOO_LARGE_1000,
+        CARRY | TOO_LARGE | TOO_LARGE_1000,
+        CARRY | TOO_LARGE | TOO_LARGE_1000,
+        CARRY | TOO_LARGE | TOO_LARGE_1000,
+        CARRY | TOO_LARGE | TOO_LARGE_1000 | SURROGATE,
+        CARRY | TOO_LARGE | TOO_LARGE_1000,
+        CARRY | TOO_LARGE | TOO_LARGE_1000,
+    ];
+
+    /// Errors possible given the high nibble of the current byte.
+    const BYTE_2_HIGH: [u8; 16] = [
+        // 0xxx: ASCII
+        TOO_SHORT,
+        TOO_SHORT,
+        TOO_SHORT,
+        TOO_SHORT,
+        TOO_SHORT,
+        TOO_SHORT,
+        TOO_SHORT,
+        TOO_SHORT,
+        // 1000
+        TOO_LONG | OVERLONG_2 | TWO_CONTS | OVERLONG_3 | TOO_LARGE_1000 | OVERLONG_4,
+        // 1001
+        TOO_LONG | OVERLONG_2 | TWO_CONTS | OVERLONG_3 | TOO_LARGE,
+        // 101x
+        TOO_LONG | OVERLONG_2 | TWO_CONTS | SURROGATE | TOO_LARGE,
+        TOO_LONG | OVERLONG_2 | TWO_CONTS | SURROGATE | TOO_LARGE,
+        // 11xx: lead byte
+        TOO_SHORT,
+        TOO_SHORT,
+        TOO_SHORT,
+        TOO_SHORT,
+    ];
+
+    /// Subtracting this from the last block leaves a non-zero byte wherever a
+    /// multi-byte sequence starts too close to the end to be complete.
+    const fn max_incomplete<const N: usize>() -> [u8; N] {
+        let mut max = [0xFF; N];
+        max[N - 3] = 0b1111_0000 - 1;
+        max[N - 2] = 0b1110_0000 - 1;
+        max[N - 1] = 0b1100_0000 - 1;
+        max
+    }
+
+    /// SSSE3 implementation: processes 16 bytes per iteration.
+    ///
+    /// # Safety
+    /// Caller must ensure that SSSE3 is available on the current CPU.
+    #[target_feature(enable = "ssse3")]
+    pub unsafe fn validate_utf8_ssse3(data: &[u8]) -> bool {
+        const MAX_INCOMPLETE: [u8; 16] = max_incomplete::<16>();
+
+        // SAFETY: every load reads a 16-byte array or a chunk checked to be
+        // 16 bytes long; the intrinsics only need SSSE3, which the caller
+        // guarantees.
+        unsafe {
+            let byte_1_high = _mm_loadu_si128(BYTE_1_HIGH.as_ptr() as *const __m128i);
+            let byte_1_low = _mm_loadu_si128(BYTE_1_LOW.as_ptr() as *const __m128i);
+            let byte_2_high = _mm_loadu_si128(BYTE_2_HIGH.as_ptr() as *const __m128i);
+            let max_incomplete = _mm_loadu_si128(MAX_INCOMPLETE.as_ptr() as *const __m128i);
+            let nibble = _mm_set1_epi8(0x0F);
+
+            let mut error = _mm_setzero_si128();
+            let mut prev_input = _mm_setzero_si128();
+            let mut prev_incomplete = _mm_setzero_si128();
+
+            for chunk in data.chunks(16) {
+                let input = if chunk.len() == 16 {
+                    _mm_loadu_si128(chunk.as_ptr() as *const __m128i)
+                } else {
+                    // Pad the tail with ASCII zeros
+                    let mut tail = [0u8; 16];
+                    tail[..chunk.len()].copy_from_slice(chunk);
+                    _mm_loadu_si128(tail.as_ptr() as *const __m128i)
+                };
+
+                if _mm_movemask_epi8(input) == 0 {
+                    error = _mm_or_si128(error, prev_incomplete);
+                    prev_incomplete = _mm_setzero_si128();
+                } else {
+                    let prev1 = _mm_alignr_epi8::<15>(input, prev_input);
+                    let prev2 = _mm_alignr_epi8::<14>(input, prev_input);
+                    let prev3 = _mm_alignr_epi8::<13>(input, prev_input);
+
+                    let special = _mm_and_si128(
+                        _mm_and_si128(
+                            _mm_shuffle_epi8(
+                                byte_1_high,
+                                _mm_and_si128(_mm_srli_epi16::<4>(prev1), nibble),
+                            ),
+                            _mm_shuffle_epi8(byte_1_low, _mm_and_si128(prev1, nibble)),
+                        ),
+                        _mm_shuffle_epi8(
+                            byte_2_high,
+                            _mm_and_si128(_mm_srli_epi16::<4>(input), nibble),
+                        ),
+                    );
+
+                    // Bytes two after a 3/4-byte lead or three after a 4-byte
+                    // lead must be continuations
+                    let third =
+                        _mm_subs_epu8(prev2, _mm_set1_epi8(0xE0u8.wrapping_sub(0x80) as i8));
+                    let fourth =
+                        _mm_subs_epu8(prev3, _mm_set1_epi8(0xF0u8.wrapping_sub(0x80) as i8));
+                    let must_be_cont =
+                        _mm_and_si128(_mm_or_si128(third, fourth), _mm_set1_epi8(0x80u8 as i8));
+
+                    error = _mm_or_si128(error, _mm_xor_si128(must_be_cont, special));
+                    prev_incomplete = _mm_subs_epu8(input, max_incomplete);
+                }
+                prev_input = input;
+            }
+
+            error = _mm_or_si128(error, prev_incomplete);
+            _mm_movemask_epi8(_mm_cmpeq_epi8(error, _mm_setzero_si128())) == 0xFFFF
+        }
+    }
+
+    /// Load a 16-entry table into both 128-bit lanes.
+    ///
+    /// # Safety
+    /// Requires AVX2 support on the target platform.
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    unsafe fn broadcast_table(table: &[u8; 16]) -> __m256i {
+        // SAFETY: table is a valid 16-byte array, _mm_loadu_si128 handles unaligned loads
+        unsafe { _mm256_broadcastsi128_si256(_mm_loadu_si128(table.as_ptr() as *const __m128i)) }
+    }
+
+    /// AVX2 implementation: processes 32 bytes per iteration.
+    ///
+    /// # Safety
+    /// Caller must ensure that AVX2 is available on the current CPU.
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn validate_utf8_avx2(data: &[u8]) -> bool {
+        const MAX_INCOMPLETE: [u8; 32] = max_incomplete::<32>();
+
+        // SAFETY: every load reads a 16/32-byte array or a chunk checked to
+        // be 32 bytes long; the intrinsics only need AVX2, which the caller
+        // guarantees.
+        unsafe {
+            let byte_1_high = broadcast_table(&BYTE_1_HIGH);
+            let byte_1_low = broadcast_table(&BYTE_1_LOW);
+            let byte_2_high = broadcast_table(&BYTE_2_HIGH);
+            let max_incomplete = _mm256_loadu_si256(MAX_INCOMPLETE.as_ptr() as *const __m256i);
+            let nibble = _mm256_set1_epi8(0x0F);
+
+            let mut error = _mm256_setzero_si256();
+            let mut prev_input = _mm256_setzero_si256();
+            let mut prev_incomplete = _mm256_setzero_si256();
+
+            for chunk in data.chunks(32) {
+                let input = if chunk.len() == 32 {
+                    _mm256_loadu_si256(chunk.as_ptr() as *const __m256i)
+                } else {
+                    // Pad the tail with ASCII zeros
+                    let mut tail = [0u8; 32];
+                    tail[..chunk.len()].copy_from_slice(chunk);
+                    _mm256_loadu_si256(tail.as_ptr() as *const __m256i)
+                };
+
+                if _mm256_movemask_epi8(input) == 0 {
+                    error = _mm256_or_si256(error, prev_incomplete);
+                    prev_incomplete = _mm256_setzero_si256();
+                } else {
+                    // alignr works per 128-bit lane, so splice the previous
+                    // block's high lane in front of this block's low lane
+                    let shifted = _mm256_permute2x128_si256::<0x21>(prev_input, input);
+                    let prev1 = _mm256_alignr_epi8::<15>(input, shifted);
+                    let prev2 = _mm256_alignr_epi8::<14>(input, shifted);
+                    let prev3 = _mm256_alignr_epi8::<13>(input, shifted);
+
+                    let special = _mm256_and_si256(
+                        _mm256_and_si256(
+                            _mm256_shuffle_epi8(
+                                byte_1_high,
+                                _mm256_and_si256(_mm256_srli_epi16::<4>(prev1), nibble),
+                            ),
+                            _mm256_shuffle_epi8(byte_1_low, _mm256_and_si256(prev1, nibble)),
+                        ),
+                        _mm256_shuffle_epi8(
+                            byte_2_high,
+                            _mm256_and_si256(_mm256_srli_epi16::<4>(input), nibble),
+                        ),
+                    );
+
+                    let third =
+                        _mm256_subs_epu8(prev2, _mm256_set1_epi8(0xE0u8.wrapping_sub(0x80) as i8));
+                    let fourth =
+                        _mm256_subs_epu8(prev3, _mm256_set1_epi8(0xF0u8.wrapping_sub(0x80) as i8));
+                    let must_be_cont = _mm256_and_si256(
+                        _mm256_or_si256(third, fourth),
+                        _mm256_set1_epi8(0x80u8 as i8),
+                    );
+
+                    error = _mm256_or_si256(error, _mm256_xor_si256(must_be_cont, special));
+                    prev_incomplete = _mm256_subs_epu8(input, max_incomplete);
+                }
+                prev_input = input;
+            }
+
+            error = _mm256_or_si256(error, prev_incomplete);
+            _mm256_testz_si256(error, error) == 1
+        }
     }
 }
 
@@ -92,7 +368,7 @@ mod aarch64_simd {
 /// Scalar UTF-8 validation using standard library.
 #[inline]
 fn validate_utf8_scalar(data: &[u8]) -> bool {
-    std::str::from_utf8(data).is_ok()
+    core::str::from_utf8(data).is_ok()
 }
 
 // ============================================================================
@@ -103,6 +379,8 @@ fn validate_utf8_scalar(data: &[u8]) -> bool {
 ///
 /// This function automatically selects the best available implementation:
 /// - NEON (128-bit, 16 bytes/iteration) on ARM64
+/// - AVX2 (256-bit, 32 bytes/iteration) on x86/x86_64
+/// - SSSE3 (128-bit, 16 bytes/iteration) on x86/x86_64 without AVX2
 /// - Scalar fallback on unsupported platforms
 ///
 /// # Errors
@@ -119,14 +397,16 @@ fn validate_utf8_scalar(data: &[u8]) -> bool {
 /// assert!(validate_utf8_simd(&[0x80, 0x81]).is_err());
 /// ```
 #[inline]
+// Without std both feature checks may be the same `false` constant
+#[cfg_attr(not(feature = "std"), allow(clippy::ifs_same_cond))]
 pub fn validate_utf8_simd(data: &[u8]) -> Result<()> {
     let is_valid = {
         #[cfg(target_arch = "aarch64")]
         {
-            // SAFETY: is_aarch64_feature_detected! is a safe macro that checks
-            // CPU features at runtime. We only call the unsafe SIMD function
-            // if the corresponding feature is detected.
-            if std::arch::is_aarch64_feature_detected!("neon") {
+            // SAFETY: aarch64_feature_detected! checks CPU features at
+            // runtime (at build time without std). We only call the unsafe
+            // SIMD function if the corresponding feature is present.
+            if aarch64_feature_detected!("neon") {
                 // SAFETY: NEON feature is confirmed available by the runtime
                 // check above. validate_utf8_neon requires NEON, which we just
                 // verified is present.
@@ -136,7 +416,20 @@ pub fn validate_utf8_simd(data: &[u8]) -> Result<()> {
             }
         }
 
-        #[cfg(not(target_arch = "aarch64"))]
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        {
+            if x86_feature_detected!("avx2") {
+                // SAFETY: AVX2 is confirmed available by the runtime check above
+                unsafe { x86_simd::validate_utf8_avx2(data) }
+            } else if x86_feature_detected!("ssse3") {
+                // SAFETY: SSSE3 is confirmed available by the runtime check above
+                unsafe { x86_simd::validate_utf8_ssse3(data) }
+            } else {
+                validate_utf8_scalar(data)
+            }
+        }
+
+        #[cfg(not(any(target_arch = "aarch64", target_arch = "x86", target_arch = "x86_64")))]
         {
             validate_utf8_scalar(data)
         }
@@ -507,9 +800,114 @@ mod tests {
         }
     }
 
+    // ========================================================================
+    // x86 SIMD Path Verification
+    // ========================================================================
+
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    type Path = (&'static str, fn(&[u8]) -> bool);
+
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    fn x86_paths() -> Vec<Path> {
+        let mut paths: Vec<Path> = Vec::new();
+        if is_x86_feature_detected!("ssse3") {
+            // SAFETY: SSSE3 support checked above
+            paths.push(("ssse3", |data| unsafe {
+                x86_simd::validate_utf8_ssse3(data)
+            }));
+        }
+        if is_x86_feature_detected!("avx2") {
+            // SAFETY: AVX2 support checked above
+            paths.push(("avx2", |data| unsafe { x86_simd::validate_utf8_avx2(data) }));
+        }
+        paths
+    }
+
+    /// Place `seq` at several offsets around the 16- and 32-byte block
+    /// boundaries and compare every x86 path against the standard library.
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    fn check_x86_at_boundaries(paths: &[Path], seq: &[u8]) {
+        for offset in [0, 13, 15, 30, 31, 46] {
+            let mut data = vec![b'a'; offset];
+            data.extend_from_slice(seq);
+            for trailing in [0, 1] {
+                data.extend(std::iter::repeat_n(b'z', trailing));
+                let expected = std::str::from_utf8(&data).is_ok();
+                for (name, validate) in paths {
+                    assert_eq!(
+                        validate(&data),
+                        expected,
+                        "{} mismatch for {:02X?} at offset {}",
+                        name,
+                        seq,
+                        offset
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    fn test_x86_paths_match_stdlib_pairs() {
+        let paths = x86_paths();
+        for first in 0x80..=0xFFu8 {
+            for second in 0..=0xFFu8 {
+                check_x86_at_boundaries(&paths, &[first, second]);
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    fn test_x86_paths_match_stdlib_long_sequences() {
+        let paths = x86_paths();
+        let interesting = [
+            0x00, 0x7F, 0x80, 0x8F, 0x90, 0x9F, 0xA0, 0xBF, 0xC0, 0xC2, 0xE0, 0xED, 0xF0, 0xF4,
+            0xFF,
+        ];
+        for lead in 0xE0..=0xF7u8 {
+            for &b1 in &interesting {
+                for &b2 in &interesting {
+                    check_x86_at_boundaries(&paths, &[lead, b1, b2]);
+                    for &b3 in &interesting {
+                        check_x86_at_boundaries(&paths, &[lead, b1, b2, b3]);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    fn test_x86_paths_match_stdlib_mixed_text() {
+        let paths = x86_paths();
+        let pieces = ["a", "é", "€", "中", "🎉", " ", "Ω"];
+        let mut seed = 0x2545_F491u32;
+        for _ in 0..200 {
+            let mut text = Vec::new();
+            for _ in 0..(seed % 97) {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                text.extend_from_slice(pieces[(seed >> 16) as usize % pieces.len()].as_bytes());
+            }
+            for (name, validate) in &paths {
+                assert!(validate(&text), "{} rejected valid text", name);
+            }
+            if !text.is_empty() {
+                // Corrupt one byte; the result must still agree with std
+                let at = (seed as usize) % text.len();
+                text[at] ^= 0x40;
+                let expected = std::str::from_utf8(&text).is_ok();
+                for (name, validate) in &paths {
+                    assert_eq!(validate(&text), expected, "{} mismatch at {}", name, at);
+                }
+            }
+        }
+    }
+
     #[test]
-    #[cfg(not(target_arch = "aarch64"))]
-    fn test_scalar_fallback_on_non_arm64() {
+    #[cfg(not(any(target_arch = "aarch64", target_arch = "x86", target_arch = "x86_64")))]
+    fn test_scalar_fallback_on_other_targets() {
         // On non-ARM64 platforms, verify the scalar fallback works correctly
         let test_cases: &[(&[u8], bool)] = &[
             (b"Hello, World!", true),
@@ -4,10 +4,60 @@
 //! - Masking rules per RFC 6455 Section 5.1
 //! - RSV bits validation
 //! - Frame size limits
+//!
+//! [`FrameRules`] replaces or extends these checks per deployment.
+
+use std::fmt;
 
 use crate::config::Limits;
 use crate::connection::Role;
 use crate::error::{Error, Result};
+use crate::protocol::frame::FrameHead;
+
+/// Rules incoming frames must follow, checked once each frame header is
+/// read and before its payload is.
+///
+/// Connections use the built-in [`FrameValidator`] unless rules are set
+/// with [`Config::with_frame_rules`](crate::Config::with_frame_rules). It is
+/// passed to every check, configured for the connection's role, limits and
+/// negotiated extensions, so rules can keep it and add their own:
+///
+/// ```rust
+/// use rsws::Result;
+/// use rsws::protocol::{FrameHead, FrameRules, FrameValidator};
+///
+/// /// Refuse fragmented messages outright.
+/// #[derive(Debug)]
+/// struct NoFragments;
+///
+/// impl FrameRules for NoFragments {
+///     fn validate(&self, head: &FrameHead, default: &FrameValidator) -> Result<()> {
+///         default.validate_head(head)?;
+///         if !head.fin {
+///             return Err(rsws::Error::ProtocolViolation("fragmented message".into()));
+///         }
+///         Ok(())
+///     }
+/// }
+/// ```
+///
+/// Rules that skip `default` take over masking and RSV checks too; the
+/// frame size limit and the control frame rules of RFC 6455 are enforced
+/// regardless.
+pub trait FrameRules: fmt::Debug + Send + Sync {
+    /// Accept or reject a frame from its header.
+    ///
+    /// # Errors
+    ///
+    /// The error to fail the connection with.
+    fn validate(&self, head: &FrameHead, default: &FrameValidator) -> Result<()>;
+}
+
+impl FrameRules for FrameValidator {
+    fn validate(&self, head: &FrameHead, _default: &FrameValidator) -> Result<()> {
+        self.validate_head(head)
+    }
+}
 
 /// Frame validator for incoming WebSocket frames.
 ///
@@ -20,6 +70,8 @@ pub struct FrameValidator {
     limits: Limits,
     /// Whether to accept unmasked frames (server-side, non-compliant).
     accept_unmasked_frames: bool,
+    /// Whether to accept masked frames (client-side, non-compliant).
+    accept_masked_frames: bool,
     /// Allowed RSV bits (bitmask: RSV1=0x40, RSV2=0x20, RSV3=0x10).
     allowed_rsv_bits: u8,
 }
@@ -36,6 +88,7 @@ impl FrameValidator {
             role,
             limits,
             accept_unmasked_frames: false,
+            accept_masked_frames: false,
             allowed_rsv_bits: 0,
         }
     }
@@ -48,6 +101,15 @@ impl FrameValidator {
         self
     }
 
+    /// Create a validator that accepts masked frames from servers (non-RFC
+    /// compliant).
+    ///
+    /// This is useful for testing but should not be used in production.
+    pub fn with_accept_masked(mut self, accept: bool) -> Self {
+        self.accept_masked_frames = accept;
+        self
+    }
+
     /// Set which RSV bits are allowed.
     ///
     /// Bitmask values:
@@ -100,6 +162,21 @@ impl FrameValidator {
         Ok(())
     }
 
+    /// Validate an incoming frame from its header.
+    ///
+    /// # Errors
+    ///
+    /// As for [`validate_incoming`](Self::validate_incoming).
+    pub fn validate_head(&self, head: &FrameHead) -> Result<()> {
+        self.validate_incoming(
+            head.masked,
+            head.rsv1,
+            head.rsv2,
+            head.rsv3,
+            head.payload_len,
+        )
+    }
+
     /// Validate masking rules per RFC 6455 Section 5.1.
     ///
     /// - Server MUST reject unmasked client frames
@@ -114,7 +191,7 @@ impl FrameValidator {
             }
             Role::Client => {
                 // Client expects unmasked frames from servers
-                if masked {
+                if masked && !self.accept_masked_frames {
                     return Err(Error::MaskedServerFrame);
                 }
             }
@@ -216,6 +293,19 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn test_client_accepts_masked_when_configured() {
+        let validator =
+            FrameValidator::new(Role::Client, Limits::default()).with_accept_masked(true);
+
+        let result = validator.validate_incoming(
+            true, // masked
+            false, false, false, 10,
+        );
+
+        assert!(result.is_ok());
+    }
+
     // --------------------------------------------------------------------------
     // RSV bits validation tests (RFC 6455 Section 5.2)
     // --------------------------------------------------------------------------
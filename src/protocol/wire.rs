@@ -0,0 +1,146 @@
+//! Annotated hexdumps of raw frames.
+//!
+//! With the `debug-wire` feature the codec logs every frame it reads or
+//! writes at `TRACE`, through `tracing` or `log`, as a [`WireDump`]:
+//!
+//! ```text
+//! FIN=1 RSV=000 opcode=Text (0x1) len=5 MASK=37fa213d
+//! 0000  81 85 37 fa 21 3d 7f 9f  4d 51 58                 |..7.!=..MQX|
+//! ```
+//!
+//! Payloads are cut off after [`PAYLOAD_PREFIX`] bytes and shown as on the
+//! wire, i.e. still masked.
+
+use std::fmt;
+
+use super::frame::parse_header;
+
+/// Payload bytes shown per frame.
+pub const PAYLOAD_PREFIX: usize = 64;
+
+const ROW: usize = 16;
+
+/// Formats a buffer holding one or more encoded frames.
+///
+/// Each frame gets a line decoding its header followed by a hexdump of the
+/// header and the start of its payload. Bytes that do not parse as a
+/// complete frame are dumped without annotation.
+#[derive(Debug, Clone, Copy)]
+pub struct WireDump<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> WireDump<'a> {
+    /// Dump `bytes`.
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+}
+
+impl fmt::Display for WireDump<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut rest = self.bytes;
+        while !rest.is_empty() {
+            let Ok(header) = parse_header(rest) else {
+                writeln!(f, "unparsed ({} bytes)", rest.len())?;
+                return hexdump(f, &rest[..rest.len().min(PAYLOAD_PREFIX)], rest.len());
+            };
+            let frame_len = header.header_len + header.payload_len;
+            write!(
+                f,
+                "FIN={} RSV={}{}{} opcode={:?} ({:#x}) len={}",
+                u8::from(header.fin),
+                u8::from(header.rsv1),
+                u8::from(header.rsv2),
+                u8::from(header.rsv3),
+                header.opcode,
+                header.opcode as u8,
+                header.payload_len,
+            )?;
+            match header.mask {
+                Some(mask) => writeln!(f, " MASK={}", hex(&mask))?,
+                None => writeln!(f, " MASK=-")?,
+            }
+            let shown = frame_len
+                .min(header.header_len + PAYLOAD_PREFIX)
+                .min(rest.len());
+            hexdump(f, &rest[..shown], frame_len.min(rest.len()))?;
+            rest = &rest[frame_len.min(rest.len())..];
+        }
+        Ok(())
+    }
+}
+
+/// Dump `bytes` in rows of 16, noting how many of `total` were left out.
+fn hexdump(f: &mut fmt::Formatter<'_>, bytes: &[u8], total: usize) -> fmt::Result {
+    for (row, chunk) in bytes.chunks(ROW).enumerate() {
+        write!(f, "{:04x} ", row * ROW)?;
+        for i in 0..ROW {
+            if i == ROW / 2 {
+                f.write_str(" ")?;
+            }
+            match chunk.get(i) {
+                Some(byte) => write!(f, " {byte:02x}")?,
+                None => f.write_str("   ")?,
+            }
+        }
+        let ascii: String = chunk
+            .iter()
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        writeln!(f, "  |{ascii}|")?;
+    }
+    if total > bytes.len() {
+        writeln!(f, "      ... {} more bytes", total - bytes.len())?;
+    }
+    Ok(())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::Frame;
+
+    fn encode(frame: &Frame, mask: Option<[u8; 4]>) -> Vec<u8> {
+        let mut buf = vec![0; frame.wire_size(mask.is_some())];
+        let len = frame.write(&mut buf, mask).unwrap();
+        buf.truncate(len);
+        buf
+    }
+
+    #[test]
+    fn test_masked_frame() {
+        let wire = encode(&Frame::text("Hello"), Some([0x37, 0xfa, 0x21, 0x3d]));
+        assert_eq!(
+            WireDump::new(&wire).to_string(),
+            "FIN=1 RSV=000 opcode=Text (0x1) len=5 MASK=37fa213d\n\
+             0000  81 85 37 fa 21 3d 7f 9f  4d 51 58                 |..7.!=..MQX|\n"
+        );
+    }
+
+    #[test]
+    fn test_several_frames_and_long_payloads() {
+        let mut wire = encode(&Frame::ping("p"), None);
+        wire.extend(encode(&Frame::binary(vec![b'a'; 100]), None));
+        wire.push(0x81);
+        let dump = WireDump::new(&wire).to_string();
+        let lines: Vec<&str> = dump.lines().collect();
+
+        assert_eq!(lines[0], "FIN=1 RSV=000 opcode=Ping (0x9) len=1 MASK=-");
+        assert_eq!(lines[2], "FIN=1 RSV=000 opcode=Binary (0x2) len=100 MASK=-");
+        // 2 header bytes and 64 payload bytes make five rows
+        assert_eq!(lines[8], "      ... 36 more bytes");
+        assert_eq!(lines[9], "unparsed (1 bytes)");
+        assert_eq!(lines.len(), 11);
+    }
+}
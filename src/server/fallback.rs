@@ -0,0 +1,329 @@
+//! Plain HTTP answers for requests that are not WebSocket upgrades.
+
+use std::fmt::Write as _;
+
+use bytes::Bytes;
+
+use crate::error::{Error, Result};
+use crate::protocol::handshake::{header_pair, header_slots, malformed, validate_header_value};
+
+/// A request that did not ask for a WebSocket upgrade.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct HttpRequest {
+    /// The request method, e.g. `GET`.
+    pub method: String,
+    /// The request target, e.g. `/healthz`.
+    pub path: String,
+    /// Headers in the order they were sent.
+    pub headers: Vec<(String, String)>,
+    /// The body, as given by `Content-Length`.
+    pub body: Bytes,
+}
+
+impl HttpRequest {
+    /// Parse a request head. The body is left empty.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidHandshake` if the head is not a complete
+    /// HTTP/1.x request or a header value is not valid UTF-8.
+    pub fn parse(head: &[u8]) -> Result<Self> {
+        let mut slots = header_slots(head);
+        let mut request = httparse::Request::new(&mut slots);
+        malformed("request", request.parse(head))?;
+
+        let headers = request
+            .headers
+            .iter()
+            .map(|header| header_pair(header).map(|(name, value)| (name.to_string(), value)))
+            .collect::<Result<_>>()?;
+
+        // A complete parse fills in the request line
+        Ok(Self {
+            method: request.method.unwrap_or_default().to_string(),
+            path: request.path.unwrap_or_default().to_string(),
+            headers,
+            body: Bytes::new(),
+        })
+    }
+
+    /// The first value of header `name`, matched case-insensitively.
+    #[must_use]
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Returns `true` if this request asks for a WebSocket upgrade.
+    #[must_use]
+    pub fn is_upgrade(&self) -> bool {
+        self.header("upgrade").is_some_and(|upgrade| {
+            upgrade
+                .split(',')
+                .any(|token| token.trim().eq_ignore_ascii_case("websocket"))
+        })
+    }
+
+    /// The `Content-Length`, or 0 if there is none.
+    pub(crate) fn content_length(&self) -> Result<usize> {
+        self.header("content-length").map_or(Ok(0), |len| {
+            len.parse()
+                .map_err(|_| Error::InvalidHandshake(format!("Invalid Content-Length: {}", len)))
+        })
+    }
+}
+
+/// The answer to an [`HttpRequest`]. The connection is closed after it is
+/// sent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Bytes,
+}
+
+impl HttpResponse {
+    /// An empty response with the given status code.
+    #[must_use]
+    pub fn new(status: u16) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: Bytes::new(),
+        }
+    }
+
+    /// `200 OK` with a plain text body.
+    #[must_use]
+    pub fn ok(body: impl Into<Bytes>) -> Self {
+        Self::new(200)
+            .with_header("Content-Type", "text/plain; charset=utf-8")
+            .with_body(body)
+    }
+
+    /// `302 Found` pointing at `location`.
+    #[must_use]
+    pub fn redirect(location: impl Into<String>) -> Self {
+        Self::new(302).with_header("Location", location)
+    }
+
+    /// `404 Not Found`.
+    #[must_use]
+    pub fn not_found() -> Self {
+        Self::new(404)
+    }
+
+    /// `400 Bad Request`, naming the WebSocket version the server speaks.
+    #[must_use]
+    pub fn bad_request() -> Self {
+        Self::new(400).with_header("Sec-WebSocket-Version", "13")
+    }
+
+    /// `426 Upgrade Required`, for an upgrade asking for a WebSocket version
+    /// other than 13 (RFC 6455 Section 4.2.2).
+    #[must_use]
+    pub fn upgrade_required() -> Self {
+        Self::new(426).with_header("Sec-WebSocket-Version", "13")
+    }
+
+    /// `405 Method Not Allowed`, listing the `allow`ed methods.
+    #[must_use]
+    pub fn method_not_allowed(allow: impl Into<String>) -> Self {
+        Self::new(405).with_header("Allow", allow)
+    }
+
+    /// Add a header. `Content-Length` and `Connection` are always set by the
+    /// server.
+    #[must_use]
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Set the body.
+    #[must_use]
+    pub fn with_body(mut self, body: impl Into<Bytes>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// The status code.
+    #[must_use]
+    pub const fn status(&self) -> u16 {
+        self.status
+    }
+
+    /// The headers added with [`with_header`](Self::with_header).
+    #[must_use]
+    pub fn headers(&self) -> &[(String, String)] {
+        &self.headers
+    }
+
+    /// The body.
+    #[must_use]
+    pub fn body(&self) -> &Bytes {
+        &self.body
+    }
+
+    /// Serialize the response.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidHeaderValue` if a header contains CR or LF.
+    pub fn write(&self, buf: &mut Vec<u8>) -> Result<()> {
+        let mut head = format!("HTTP/1.1 {} {}\r\n", self.status, reason(self.status));
+        for (name, value) in &self.headers {
+            validate_header_value(name, name)?;
+            validate_header_value(name, value)?;
+            let _ = write!(head, "{}: {}\r\n", name, value);
+        }
+        let _ = write!(
+            head,
+            "Content-Length: {}\r\nConnection: close\r\n\r\n",
+            self.body.len()
+        );
+        buf.extend_from_slice(head.as_bytes());
+        buf.extend_from_slice(&self.body);
+        Ok(())
+    }
+}
+
+/// Reason phrase for common status codes.
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        204 => "No Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        426 => "Upgrade Required",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
+/// How the server answers a request it cannot upgrade, set with
+/// [`Server::with_invalid_request`](super::Server::with_invalid_request).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum InvalidRequest {
+    /// Answer `405 Method Not Allowed` with `Allow: GET` for other methods,
+    /// `426 Upgrade Required` for a `Sec-WebSocket-Version` other than 13
+    /// and `400 Bad Request` for other malformed upgrades, both with
+    /// `Sec-WebSocket-Version: 13`, so clients can tell what went wrong.
+    #[default]
+    Respond,
+    /// Close the socket without a response.
+    Drop,
+}
+
+impl InvalidRequest {
+    /// The answer to the request head `head`.
+    pub(crate) fn response(head: &[u8]) -> HttpResponse {
+        match HttpRequest::parse(head) {
+            Ok(request) if request.method != "GET" => HttpResponse::method_not_allowed("GET"),
+            Ok(request)
+                if request
+                    .header("Sec-WebSocket-Version")
+                    .is_some_and(|version| version != "13") =>
+            {
+                HttpResponse::upgrade_required()
+            }
+            _ => HttpResponse::bad_request(),
+        }
+    }
+}
+
+/// Answers requests that are not WebSocket upgrades, set with
+/// [`Server::with_fallback`](super::Server::with_fallback).
+///
+/// Implemented for closures taking an [`HttpRequest`]:
+///
+/// ```rust,ignore
+/// let server = Server::bind("0.0.0.0:9001").await?.with_fallback(|req: &HttpRequest| {
+///     match req.path.as_str() {
+///         "/healthz" => HttpResponse::ok("ok"),
+///         _ => HttpResponse::not_found(),
+///     }
+/// });
+/// ```
+pub trait Fallback: Send + Sync + 'static {
+    /// Build the response to `request`.
+    fn respond(&self, request: &HttpRequest) -> HttpResponse;
+}
+
+impl<F> Fallback for F
+where
+    F: Fn(&HttpRequest) -> HttpResponse + Send + Sync + 'static,
+{
+    fn respond(&self, request: &HttpRequest) -> HttpResponse {
+        self(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_plain_request() {
+        let request = HttpRequest::parse(
+            b"POST /submit?x=1 HTTP/1.1\r\nHost: example.com\r\nContent-Length: 5\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/submit?x=1");
+        assert_eq!(request.header("HOST"), Some("example.com"));
+        assert_eq!(request.content_length().unwrap(), 5);
+        assert!(!request.is_upgrade());
+
+        let upgrade = HttpRequest::parse(b"GET / HTTP/1.1\r\nUpgrade: WebSocket\r\n\r\n").unwrap();
+        assert!(upgrade.is_upgrade());
+
+        assert!(HttpRequest::parse(b"garbage\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn test_write_response() {
+        let mut buf = Vec::new();
+        HttpResponse::ok("up").write(&mut buf).unwrap();
+        assert_eq!(
+            buf,
+            b"HTTP/1.1 200 OK\r\n\
+              Content-Type: text/plain; charset=utf-8\r\n\
+              Content-Length: 2\r\n\
+              Connection: close\r\n\r\nup"
+        );
+
+        let mut buf = Vec::new();
+        InvalidRequest::response(b"POST / HTTP/1.1\r\nHost: x\r\n\r\n")
+            .write(&mut buf)
+            .unwrap();
+        assert!(buf.starts_with(b"HTTP/1.1 405 Method Not Allowed\r\nAllow: GET\r\n"));
+
+        let mut buf = Vec::new();
+        InvalidRequest::response(b"garbage\r\n\r\n")
+            .write(&mut buf)
+            .unwrap();
+        assert!(buf.starts_with(b"HTTP/1.1 400 Bad Request\r\nSec-WebSocket-Version: 13\r\n"));
+
+        let mut buf = Vec::new();
+        InvalidRequest::response(b"GET / HTTP/1.1\r\nSec-WebSocket-Version: 8\r\n\r\n")
+            .write(&mut buf)
+            .unwrap();
+        assert!(buf.starts_with(b"HTTP/1.1 426 Upgrade Required\r\nSec-WebSocket-Version: 13\r\n"));
+
+        let injected = HttpResponse::redirect("/x\r\nSet-Cookie: a=b");
+        assert!(injected.write(&mut Vec::new()).is_err());
+    }
+}
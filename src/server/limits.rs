@@ -0,0 +1,186 @@
+//! Concurrent connection caps.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+/// Caps on concurrent connections, enforced as sockets are accepted.
+///
+/// A connection counts from the moment its socket is accepted, through the
+/// handshake, until the [`Connection`](crate::Connection) is dropped. Peers
+/// are counted by IP address; IPv4-mapped IPv6 addresses count as their IPv4
+/// address.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConnectionLimits {
+    /// Maximum concurrent connections in total. Default: unlimited.
+    pub max_connections: Option<usize>,
+
+    /// Maximum concurrent connections from a single IP address.
+    /// Default: unlimited.
+    pub max_per_ip: Option<usize>,
+
+    /// What to do with a socket over either limit.
+    pub over_limit: OverLimit,
+}
+
+impl ConnectionLimits {
+    /// No limits.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cap concurrent connections in total.
+    #[must_use]
+    pub fn with_max_connections(mut self, max: usize) -> Self {
+        self.max_connections = Some(max);
+        self
+    }
+
+    /// Cap concurrent connections per IP address.
+    #[must_use]
+    pub fn with_max_per_ip(mut self, max: usize) -> Self {
+        self.max_per_ip = Some(max);
+        self
+    }
+
+    /// Set what happens to sockets over a limit.
+    #[must_use]
+    pub fn with_over_limit(mut self, over_limit: OverLimit) -> Self {
+        self.over_limit = over_limit;
+        self
+    }
+}
+
+/// How a socket over a [`ConnectionLimits`] cap is turned away.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum OverLimit {
+    /// Read the request and answer `503 Service Unavailable`, so clients
+    /// can tell the server is busy. Runs the TLS handshake, if any, first.
+    #[default]
+    Reject,
+    /// Close the socket immediately. Cheapest under a flood.
+    Drop,
+}
+
+#[derive(Default)]
+struct Counts {
+    total: usize,
+    per_ip: HashMap<IpAddr, usize>,
+}
+
+/// Shared connection counts for one server.
+pub(crate) struct Tracker {
+    limits: ConnectionLimits,
+    counts: Mutex<Counts>,
+}
+
+impl Tracker {
+    pub(crate) fn new(limits: ConnectionLimits) -> Arc<Self> {
+        Arc::new(Self {
+            limits,
+            counts: Mutex::new(Counts::default()),
+        })
+    }
+
+    pub(crate) fn over_limit(&self) -> OverLimit {
+        self.limits.over_limit
+    }
+
+    /// Count a connection from `ip`, or return `None` if that would exceed a
+    /// limit.
+    pub(crate) fn try_acquire(self: &Arc<Self>, ip: IpAddr) -> Option<Slot> {
+        let ip = ip.to_canonical();
+        let mut counts = self.lock();
+
+        if self
+            .limits
+            .max_connections
+            .is_some_and(|max| counts.total >= max)
+        {
+            return None;
+        }
+        let from_ip = counts.per_ip.get(&ip).copied().unwrap_or(0);
+        if self.limits.max_per_ip.is_some_and(|max| from_ip >= max) {
+            return None;
+        }
+
+        counts.total += 1;
+        counts.per_ip.insert(ip, from_ip + 1);
+        Some(Slot {
+            tracker: Arc::clone(self),
+            ip,
+        })
+    }
+
+    /// Connections currently counted.
+    pub(crate) fn active(&self) -> usize {
+        self.lock().total
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Counts> {
+        self.counts.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// One counted connection; released on drop.
+pub(crate) struct Slot {
+    tracker: Arc<Tracker>,
+    ip: IpAddr,
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        let mut counts = self.tracker.lock();
+        counts.total -= 1;
+        if let Some(n) = counts.per_ip.get_mut(&self.ip) {
+            *n -= 1;
+            if *n == 0 {
+                counts.per_ip.remove(&self.ip);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_per_ip_limit() {
+        let tracker = Tracker::new(ConnectionLimits::new().with_max_per_ip(2));
+        let a: IpAddr = "10.0.0.1".parse().unwrap();
+        let b: IpAddr = "10.0.0.2".parse().unwrap();
+
+        let first = tracker.try_acquire(a).unwrap();
+        let _second = tracker.try_acquire(a).unwrap();
+        assert!(tracker.try_acquire(a).is_none());
+        assert!(tracker.try_acquire(b).is_some());
+
+        drop(first);
+        assert!(tracker.try_acquire(a).is_some());
+    }
+
+    #[test]
+    fn test_global_limit() {
+        let tracker = Tracker::new(ConnectionLimits::new().with_max_connections(1));
+        let slot = tracker.try_acquire("10.0.0.1".parse().unwrap()).unwrap();
+        assert!(tracker.try_acquire("10.0.0.2".parse().unwrap()).is_none());
+        assert_eq!(tracker.active(), 1);
+
+        drop(slot);
+        assert_eq!(tracker.active(), 0);
+    }
+
+    #[test]
+    fn test_mapped_ipv6_counts_as_ipv4() {
+        let tracker = Tracker::new(ConnectionLimits::new().with_max_per_ip(1));
+        let _v4 = tracker.try_acquire("10.0.0.1".parse().unwrap()).unwrap();
+        assert!(
+            tracker
+                .try_acquire("::ffff:10.0.0.1".parse().unwrap())
+                .is_none()
+        );
+    }
+}
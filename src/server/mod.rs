@@ -0,0 +1,691 @@
+//! A ready-made WebSocket server accept loop.
+//!
+//! [`Server`] owns a `TcpListener`, runs the opening handshake for every
+//! incoming socket (optionally after a TLS handshake) and hands out
+//! connections that are ready to use:
+//!
+//! ```rust,ignore
+//! use rsws::server::Server;
+//! use rsws::{Config, Message};
+//!
+//! let mut server = Server::bind("0.0.0.0:9001").await?.with_config(Config::server());
+//!
+//! loop {
+//!     let (mut conn, request, peer) = server.accept().await?;
+//!     tokio::spawn(async move {
+//!         println!("{} connected to {}", peer, request.path);
+//!         while let Ok(Some(msg)) = conn.recv().await {
+//!             if msg.is_text() || msg.is_binary() {
+//!                 let _ = conn.send(msg).await;
+//!             }
+//!         }
+//!     });
+//! }
+//! ```
+//!
+//! Handshakes run on their own tasks, so a slow or silent client never holds
+//! up the others. Each must finish within `timeouts.handshake` (30 seconds
+//! if unset) and send its request at least as fast as
+//! [`Config::handshake_rate`], so clients trickling bytes cannot pin a slot.
+//! Clients whose handshake fails or times out are dropped and never returned
+//! from [`accept`](Server::accept).
+//!
+//! [`ConnectionLimits`] caps concurrent connections in total and per client
+//! IP address.
+//!
+//! A [`Router`] set with [`with_router`](Server::with_router) picks the
+//! config, subprotocols and extensions for each request path before the
+//! upgrade is answered.
+//!
+//! Requests that are not WebSocket upgrades fail the handshake, unless a
+//! [`Fallback`] set with [`with_fallback`](Server::with_fallback) answers
+//! them, so one port can serve e.g. `/healthz` next to WebSocket. Other
+//! methods and malformed upgrades get `405` or `400`, as set by
+//! [`InvalidRequest`].
+
+use std::future::{Future, poll_fn};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use bytes::BytesMut;
+use futures_core::Stream;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+use tokio::task::JoinSet;
+
+use crate::codec::{handshake_deadline, read_head, with_deadline};
+use crate::config::Config;
+use crate::connection::{Connection, Role};
+use crate::error::{Error, Result};
+use crate::protocol::handshake::{validate_host, validate_origin};
+use crate::protocol::{HandshakeRequest, HandshakeResponse};
+use crate::socket::SocketOptions;
+use crate::task;
+
+mod fallback;
+mod limits;
+mod proxy;
+mod router;
+
+pub use fallback::{Fallback, HttpRequest, HttpResponse, InvalidRequest};
+pub(crate) use limits::Slot;
+use limits::Tracker;
+pub use limits::{ConnectionLimits, OverLimit};
+pub use proxy::TrustedProxies;
+pub(crate) use proxy::in_network;
+pub use router::{Route, Router};
+
+/// A connection that completed the opening handshake, with the request it
+/// was opened by and the peer's address.
+pub type Accepted<S> = (Connection<S>, HandshakeRequest, SocketAddr);
+
+/// Wraps freshly accepted TCP sockets before the WebSocket handshake,
+/// typically in TLS.
+pub trait Acceptor: Send + Sync + 'static {
+    /// The stream WebSocket frames are carried over.
+    type Stream: AsyncRead + AsyncWrite + Unpin + Send + 'static;
+
+    /// Prepare an accepted socket, e.g. by running a TLS handshake.
+    fn accept(&self, stream: TcpStream) -> impl Future<Output = Result<Self::Stream>> + Send;
+}
+
+/// Plain TCP, used for `ws://` servers.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Plain;
+
+impl Acceptor for Plain {
+    type Stream = TcpStream;
+
+    async fn accept(&self, stream: TcpStream) -> Result<TcpStream> {
+        Ok(stream)
+    }
+}
+
+#[cfg(feature = "tls-rustls")]
+impl Acceptor for crate::tls::TlsAcceptor {
+    type Stream = crate::tls::TlsStream<TcpStream>;
+
+    async fn accept(&self, stream: TcpStream) -> Result<Self::Stream> {
+        #[cfg(all(feature = "ktls", target_os = "linux"))]
+        let tls = self.accept_ktls(stream).await;
+        #[cfg(not(all(feature = "ktls", target_os = "linux")))]
+        let tls = crate::tls::TlsAcceptor::accept(self, stream).await;
+
+        tls.map_err(|e| Error::Tls(crate::error::Source::new(e)))
+    }
+}
+
+#[cfg(feature = "tls-native")]
+impl Acceptor for crate::tls::NativeTlsAcceptor {
+    type Stream = crate::tls::NativeTlsStream<TcpStream>;
+
+    async fn accept(&self, stream: TcpStream) -> Result<Self::Stream> {
+        crate::tls::NativeTlsAcceptor::accept(self, stream)
+            .await
+            .map_err(|e| Error::Tls(crate::error::Source::new(e)))
+    }
+}
+
+#[cfg(feature = "tls-openssl")]
+impl Acceptor for crate::tls::OpensslAcceptor {
+    type Stream = crate::tls::OpensslStream<TcpStream>;
+
+    async fn accept(&self, stream: TcpStream) -> Result<Self::Stream> {
+        crate::tls::OpensslAcceptor::accept(self, stream)
+            .await
+            .map_err(|e| Error::Tls(crate::error::Source::new(e)))
+    }
+}
+
+/// A WebSocket server bound to a TCP port.
+///
+/// Also a [`Stream`] of accepted connections.
+pub struct Server<A: Acceptor = Plain> {
+    listener: TcpListener,
+    acceptor: Arc<A>,
+    config: Config,
+    tracker: Option<Arc<Tracker>>,
+    fallback: Option<Arc<dyn Fallback>>,
+    router: Option<Arc<Router>>,
+    invalid_request: InvalidRequest,
+    proxies: Option<Arc<TrustedProxies>>,
+    socket: SocketOptions,
+    handshakes: JoinSet<Result<Accepted<A::Stream>>>,
+}
+
+impl Server<Plain> {
+    /// Bind a plain `ws://` server with [`Config::server`] defaults.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Io` if the address cannot be bound.
+    pub async fn bind(addr: impl ToSocketAddrs) -> Result<Self> {
+        Ok(Self::from_listener(TcpListener::bind(addr).await?))
+    }
+
+    /// Serve on an already bound listener.
+    pub fn from_listener(listener: TcpListener) -> Self {
+        Self {
+            listener,
+            acceptor: Arc::new(Plain),
+            config: Config::server(),
+            tracker: None,
+            fallback: None,
+            router: None,
+            invalid_request: InvalidRequest::default(),
+            proxies: None,
+            socket: SocketOptions::default(),
+            handshakes: JoinSet::new(),
+        }
+    }
+}
+
+impl<A: Acceptor> Server<A> {
+    /// Set the configuration applied to every accepted connection.
+    ///
+    /// Its `limits.max_handshake_size`, `allowed_origins`, `allowed_hosts`,
+    /// `timeouts.handshake` and `handshake_rate` also govern the handshake
+    /// itself.
+    #[must_use]
+    pub fn with_config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Cap concurrent connections. Sockets over a limit are turned away as
+    /// set by [`ConnectionLimits::over_limit`].
+    ///
+    /// Connections accepted before this call are not counted, so call it
+    /// before accepting.
+    #[must_use]
+    pub fn with_connection_limits(mut self, limits: ConnectionLimits) -> Self {
+        self.tracker = Some(Tracker::new(limits));
+        self
+    }
+
+    /// Answer requests that are not WebSocket upgrades with `fallback`
+    /// instead of dropping them. The socket is closed after the response.
+    ///
+    /// Request bodies are read up to `limits.max_handshake_size`, and the
+    /// whole exchange must finish within the handshake deadline.
+    #[must_use]
+    pub fn with_fallback(mut self, fallback: impl Fallback) -> Self {
+        self.fallback = Some(Arc::new(fallback));
+        self
+    }
+
+    /// Set up connections per request path with `router`. Upgrade requests
+    /// for a path it has no route for get `404 Not Found`.
+    #[must_use]
+    pub fn with_router(mut self, router: Router) -> Self {
+        self.router = Some(Arc::new(router));
+        self
+    }
+
+    /// Set how requests that cannot be upgraded, e.g. a `POST` or an
+    /// upgrade without `Sec-WebSocket-Key`, are answered. By default they
+    /// get `405` or `400`.
+    #[must_use]
+    pub fn with_invalid_request(mut self, policy: InvalidRequest) -> Self {
+        self.invalid_request = policy;
+        self
+    }
+
+    /// Believe forwarding headers from `proxies` when resolving
+    /// [`Connection::client_addr`], for a server behind a reverse proxy or
+    /// load balancer. Without this the client is always the peer.
+    #[must_use]
+    pub fn with_trusted_proxies(mut self, proxies: TrustedProxies) -> Self {
+        self.proxies = Some(Arc::new(proxies));
+        self
+    }
+
+    /// Tune every accepted socket with `options`. Its `local_addr` and
+    /// `interface` only apply to outgoing connections and are ignored.
+    #[must_use]
+    pub fn with_socket_options(mut self, options: SocketOptions) -> Self {
+        self.socket = options;
+        self
+    }
+
+    /// Run every accepted socket through `acceptor`, e.g. a TLS acceptor for
+    /// `wss://`, before the WebSocket handshake.
+    ///
+    /// Handshakes already in progress are dropped, so call this before
+    /// accepting.
+    #[must_use]
+    pub fn with_tls<B: Acceptor>(self, acceptor: B) -> Server<B> {
+        Server {
+            listener: self.listener,
+            acceptor: Arc::new(acceptor),
+            config: self.config,
+            tracker: self.tracker,
+            fallback: self.fallback,
+            router: self.router,
+            invalid_request: self.invalid_request,
+            proxies: self.proxies,
+            socket: self.socket,
+            handshakes: JoinSet::new(),
+        }
+    }
+
+    /// The address the server is listening on.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Io` if the socket address cannot be queried.
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// The configuration applied to accepted connections.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Connections counted against the [`ConnectionLimits`], including
+    /// those still in the handshake. Always 0 without limits.
+    pub fn active_connections(&self) -> usize {
+        self.tracker.as_ref().map_or(0, |tracker| tracker.active())
+    }
+
+    /// Handshakes running in background tasks, including `503` answers to
+    /// clients over the connection limits.
+    pub fn handshakes_in_progress(&self) -> usize {
+        self.handshakes.len()
+    }
+
+    /// Abort the handshakes in progress and wait for their tasks to end,
+    /// e.g. before shutting down the runtime. Completed handshakes not yet
+    /// returned by [`accept`](Self::accept) are dropped too.
+    ///
+    /// The server keeps listening; sockets accepted later start new
+    /// handshakes.
+    pub async fn shutdown(&mut self) {
+        self.handshakes.shutdown().await;
+    }
+
+    /// Wait for the next client to complete the handshake.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Io` if accepting on the listener fails. Failed
+    /// handshakes are not reported here.
+    pub async fn accept(&mut self) -> Result<Accepted<A::Stream>> {
+        poll_fn(|cx| self.poll_accept(cx)).await
+    }
+
+    /// Poll for the next client to complete the handshake.
+    ///
+    /// See [`accept`](Self::accept).
+    pub fn poll_accept(&mut self, cx: &mut Context<'_>) -> Poll<Result<Accepted<A::Stream>>> {
+        // Start a handshake for every socket that is waiting.
+        loop {
+            match self.listener.poll_accept(cx) {
+                Poll::Ready(Ok((stream, peer))) => self.admit(stream, peer),
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e.into())),
+                Poll::Pending => break,
+            }
+        }
+
+        loop {
+            match self.handshakes.poll_join_next(cx) {
+                Poll::Ready(Some(Ok(Ok(accepted)))) => return Poll::Ready(Ok(accepted)),
+                // A failed handshake only concerns that client.
+                Poll::Ready(Some(Ok(Err(_)))) => {}
+                Poll::Ready(Some(Err(join_err))) if join_err.is_panic() => {
+                    std::panic::resume_unwind(join_err.into_panic())
+                }
+                Poll::Ready(Some(Err(_))) => {}
+                Poll::Ready(None) | Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+impl<A: Acceptor> Server<A> {
+    /// Start the handshake for a new socket, or turn it away if it is over
+    /// the connection limits.
+    fn admit(&mut self, stream: TcpStream, peer: SocketAddr) {
+        if let Err(_e) = self.socket.apply(&stream) {
+            ws_debug!(%peer, error = %_e, "failed to set socket options");
+            return;
+        }
+        let acceptor = Arc::clone(&self.acceptor);
+        let config = self.config.clone();
+        let routing = Routing {
+            fallback: self.fallback.clone(),
+            router: self.router.clone(),
+            invalid_request: self.invalid_request,
+            proxies: self.proxies.clone(),
+        };
+
+        let Some(tracker) = &self.tracker else {
+            task::spawn_in(
+                &mut self.handshakes,
+                task::SERVER_HANDSHAKE,
+                accept_client(acceptor, stream, peer, config, routing, None),
+            );
+            return;
+        };
+
+        match tracker.try_acquire(peer.ip()) {
+            Some(slot) => {
+                task::spawn_in(
+                    &mut self.handshakes,
+                    task::SERVER_HANDSHAKE,
+                    accept_client(acceptor, stream, peer, config, routing, Some(slot)),
+                );
+            }
+            None => {
+                ws_debug!(%peer, "connection limit reached");
+                if tracker.over_limit() == OverLimit::Reject {
+                    task::spawn_in(
+                        &mut self.handshakes,
+                        task::SERVER_REJECT,
+                        reject_client(acceptor, stream, config),
+                    );
+                }
+            }
+        }
+    }
+}
+
+impl<A: Acceptor> Stream for Server<A> {
+    type Item = Result<Accepted<A::Stream>>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_accept(cx).map(Some)
+    }
+}
+
+async fn accept_client<A: Acceptor>(
+    acceptor: Arc<A>,
+    stream: TcpStream,
+    peer: SocketAddr,
+    config: Config,
+    routing: Routing,
+    slot: Option<Slot>,
+) -> Result<Accepted<A::Stream>> {
+    let deadline = handshake_deadline(&config);
+    let handshake = async {
+        let mut stream = acceptor.accept(stream).await?;
+        let max = config.limits.max_handshake_size;
+        let (head, buf) = read_head(&mut stream, max, config.handshake_rate).await?;
+
+        if let Some(fallback) = routing.fallback {
+            let request = HttpRequest::parse(&head)?;
+            if !request.is_upgrade() {
+                return serve_fallback(stream, request, buf, max, &*fallback).await;
+            }
+        }
+
+        let parsed = HandshakeRequest::parse_with_compat(&head, &config.compat);
+        let request = match parsed.and_then(|request| request.validate().map(|()| request)) {
+            Ok(request) => request,
+            Err(e) => {
+                if routing.invalid_request == InvalidRequest::Respond {
+                    let mut response = Vec::new();
+                    InvalidRequest::response(&head).write(&mut response)?;
+                    stream.write_all(&response).await?;
+                    stream.flush().await?;
+                }
+                return Err(e);
+            }
+        };
+        let Some(router) = routing.router else {
+            return upgrade(stream, config, &Route::new(), request, &buf).await;
+        };
+        match router.find(&request.path) {
+            Some(route) => upgrade(stream, config, route, request, &buf).await,
+            None => {
+                let mut response = Vec::new();
+                HttpResponse::not_found().write(&mut response)?;
+                stream.write_all(&response).await?;
+                stream.flush().await?;
+                Err(Error::InvalidHandshake(format!(
+                    "No route for {}",
+                    request.path
+                )))
+            }
+        }
+    };
+
+    let handshake = with_deadline(deadline, handshake);
+    match ws_instrument!(tracing::debug_span!("ws_handshake", %peer), handshake) {
+        Ok((mut conn, request)) => {
+            if let Some(slot) = slot {
+                conn.hold_slot(slot);
+            }
+            let client = match &routing.proxies {
+                Some(proxies) => proxies.client_addr(peer.ip(), &request),
+                None => peer.ip().to_canonical(),
+            };
+            conn.set_client_addr(client);
+            ws_debug!(conn = conn.id(), %peer, path = %request.path, "accepted connection");
+            Ok((conn, request, peer))
+        }
+        Err(e) => {
+            ws_debug!(%peer, error = %e, "handshake failed");
+            #[cfg(feature = "metrics")]
+            crate::metrics::record_handshake_failure();
+            Err(e)
+        }
+    }
+}
+
+/// Answer a client over the connection limits with `503 Service
+/// Unavailable`. Always returns an error, so it is never accepted.
+async fn reject_client<A: Acceptor>(
+    acceptor: Arc<A>,
+    stream: TcpStream,
+    config: Config,
+) -> Result<Accepted<A::Stream>> {
+    let deadline = handshake_deadline(&config);
+    let max = config.limits.max_handshake_size;
+    let respond = async {
+        let mut stream = acceptor.accept(stream).await?;
+        // Closing with the request unread could reset the connection before
+        // the client sees the response.
+        read_head(&mut stream, max, config.handshake_rate).await?;
+        stream.write_all(SERVICE_UNAVAILABLE).await?;
+        stream.flush().await?;
+        Ok(())
+    };
+
+    with_deadline(deadline, respond).await?;
+    Err(Error::Io("Connection limit reached".into()))
+}
+
+/// What decides how a request is answered, shared by all handshakes.
+struct Routing {
+    fallback: Option<Arc<dyn Fallback>>,
+    router: Option<Arc<Router>>,
+    invalid_request: InvalidRequest,
+    proxies: Option<Arc<TrustedProxies>>,
+}
+
+/// Answer a plain HTTP request with `fallback`. Always returns an error, so
+/// the client is never accepted.
+async fn serve_fallback<S, R>(
+    mut stream: S,
+    mut request: HttpRequest,
+    mut body: BytesMut,
+    max: usize,
+    fallback: &dyn Fallback,
+) -> Result<R>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let len = request.content_length()?;
+    if len > max {
+        return Err(Error::HandshakeTooLarge { size: len, max });
+    }
+    if body.len() < len {
+        let read = body.len();
+        body.resize(len, 0);
+        stream.read_exact(&mut body[read..]).await?;
+    }
+    body.truncate(len);
+    request.body = body.freeze();
+
+    ws_debug!(method = %request.method, path = %request.path, "serving fallback");
+    let mut response = Vec::new();
+    fallback.respond(&request).write(&mut response)?;
+    stream.write_all(&response).await?;
+    stream.flush().await?;
+    stream.shutdown().await?;
+    Err(Error::InvalidHandshake("Not a WebSocket upgrade".into()))
+}
+
+const SERVICE_UNAVAILABLE: &[u8] =
+    b"HTTP/1.1 503 Service Unavailable\r\nConnection: close\r\nContent-Length: 0\r\n\r\n";
+
+/// Run the server side of the opening handshake on `stream`.
+///
+/// Reads the request head, validates it against `config` and answers with
+/// `101 Switching Protocols`. Bytes the client sent after the head are kept
+/// for the connection.
+///
+/// # Errors
+///
+/// - `Error::HandshakeTooLarge` if the head exceeds `limits.max_handshake_size`
+/// - `Error::InvalidHandshake` for malformed requests
+/// - `Error::OriginNotAllowed` if `allowed_origins` rejects the request
+/// - `Error::HostNotAllowed` if `allowed_hosts` rejects the request
+/// - `Error::Io` for I/O errors, including EOF before the head is complete
+///   and a `TimedOut` error if the head arrives slower than `handshake_rate`
+pub async fn handshake<S>(
+    mut stream: S,
+    config: Config,
+) -> Result<(Connection<S>, HandshakeRequest)>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let handshake = async move {
+        let max = config.limits.max_handshake_size;
+        let (head, buf) = read_head(&mut stream, max, config.handshake_rate).await?;
+        let request = HandshakeRequest::parse_with_compat(&head, &config.compat)?;
+        upgrade(stream, config, &Route::new(), request, &buf).await
+    };
+    let result = ws_instrument!(tracing::debug_span!("ws_handshake"), handshake);
+    #[cfg(feature = "metrics")]
+    if result.is_err() {
+        crate::metrics::record_handshake_failure();
+    }
+    result
+}
+
+/// Validate a parsed request and answer with `101 Switching Protocols` as
+/// set up by `route`. `buf` holds the bytes received after the head.
+async fn upgrade<S>(
+    mut stream: S,
+    config: Config,
+    route: &Route,
+    request: HandshakeRequest,
+    buf: &[u8],
+) -> Result<(Connection<S>, HandshakeRequest)>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let config = route.config(config);
+    request.validate()?;
+    if let Some(allowed) = &config.allowed_origins {
+        validate_origin(request.origin.as_deref(), allowed)?;
+    }
+    if let Some(allowed) = &config.allowed_hosts {
+        validate_host(&request.host, allowed)?;
+    }
+
+    let mut response = HandshakeResponse::from_request(&request);
+    let extensions = route.negotiate(&request, &mut response);
+
+    let mut head = Vec::new();
+    response.write(&mut head)?;
+    stream.write_all(&head).await?;
+    stream.flush().await?;
+
+    let mut conn = Connection::with_extensions(stream, Role::Server, config, extensions);
+    conn.set_subprotocol(response.protocol.clone());
+    conn.preload(buf);
+    Ok((conn, request))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, duplex};
+
+    const REQUEST: &[u8] = b"GET /chat HTTP/1.1\r\n\
+        Host: localhost\r\n\
+        Upgrade: websocket\r\n\
+        Connection: Upgrade\r\n\
+        Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+        Sec-WebSocket-Version: 13\r\n\
+        Origin: https://example.com\r\n\r\n";
+
+    #[tokio::test]
+    async fn test_handshake_keeps_trailing_frame() {
+        let (mut client, server) = duplex(4096);
+
+        // Request immediately followed by a masked text frame "hi"
+        let mut data = REQUEST.to_vec();
+        data.extend_from_slice(&[0x81, 0x82, 0, 0, 0, 0, b'h', b'i']);
+        client.write_all(&data).await.unwrap();
+
+        let (mut conn, request) = handshake(server, Config::server()).await.unwrap();
+        assert_eq!(request.path, "/chat");
+
+        let msg = conn.recv().await.unwrap().unwrap();
+        assert_eq!(msg.as_text(), Some("hi"));
+
+        let mut response = vec![0u8; 19];
+        client.read_exact(&mut response).await.unwrap();
+        assert_eq!(&response, b"HTTP/1.1 101 Switch");
+    }
+
+    #[tokio::test]
+    async fn test_handshake_rejects_origin() {
+        let (mut client, server) = duplex(4096);
+        client.write_all(REQUEST).await.unwrap();
+
+        let config = Config::server().with_allowed_origins(vec!["https://other.com".into()]);
+        let result = handshake(server, config).await;
+        assert!(matches!(result, Err(Error::OriginNotAllowed { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_handshake_rejects_host() {
+        let (mut client, server) = duplex(4096);
+        client.write_all(REQUEST).await.unwrap();
+
+        let config = Config::server().with_allowed_hosts(vec!["127.0.0.1".into()]);
+        let result = handshake(server, config).await;
+        assert!(matches!(result, Err(Error::HostNotAllowed { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_handshake_too_large() {
+        let (mut client, server) = duplex(16 * 1024);
+        let mut data = b"GET / HTTP/1.1\r\n".to_vec();
+        data.extend(std::iter::repeat_n(b'a', 9000));
+        client.write_all(&data).await.unwrap();
+
+        let result = handshake(server, Config::server()).await;
+        assert!(matches!(result, Err(Error::HandshakeTooLarge { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_handshake_eof() {
+        let (mut client, server) = duplex(4096);
+        client.write_all(b"GET / HTTP/1.1\r\n").await.unwrap();
+        drop(client);
+
+        assert!(matches!(
+            handshake(server, Config::server()).await,
+            Err(Error::Io(_))
+        ));
+    }
+}
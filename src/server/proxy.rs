@@ -0,0 +1,226 @@
+//! Client addresses behind reverse proxies.
+
+use std::net::IpAddr;
+
+use crate::protocol::{HandshakeRequest, Headers};
+
+/// Reverse proxies whose `Forwarded`, `X-Forwarded-For` and `X-Real-IP`
+/// headers are believed, set with
+/// [`Server::with_trusted_proxies`](super::Server::with_trusted_proxies).
+///
+/// Forwarding headers are only read when the socket itself comes from a
+/// trusted proxy, and the chain of forwarded addresses is only followed
+/// through trusted proxies, so a client cannot pose as another address by
+/// sending the headers itself.
+///
+/// ```rust,ignore
+/// let proxies = TrustedProxies::new()
+///     .with_proxy("127.0.0.1".parse()?)
+///     .with_network("10.0.0.0".parse()?, 8);
+/// let server = Server::bind("0.0.0.0:9001").await?.with_trusted_proxies(proxies);
+/// let (conn, _, _) = server.accept().await?;
+/// println!("client {:?}", conn.client_addr());
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrustedProxies {
+    /// Networks as address and prefix length.
+    networks: Vec<(IpAddr, u8)>,
+}
+
+impl TrustedProxies {
+    /// No trusted proxies: the client is always the peer.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Trust the proxy at `addr`.
+    #[must_use]
+    pub fn with_proxy(self, addr: IpAddr) -> Self {
+        let prefix_len = if addr.is_ipv4() { 32 } else { 128 };
+        self.with_network(addr, prefix_len)
+    }
+
+    /// Trust every address in `network/prefix_len`, e.g. the private range
+    /// a load balancer connects from. The prefix is capped at the address
+    /// length.
+    #[must_use]
+    pub fn with_network(mut self, network: IpAddr, prefix_len: u8) -> Self {
+        let network = network.to_canonical();
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        self.networks.push((network, prefix_len.min(max)));
+        self
+    }
+
+    /// Whether `addr` is a trusted proxy.
+    #[must_use]
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        let addr = addr.to_canonical();
+        self.networks
+            .iter()
+            .any(|&(network, prefix_len)| in_network(addr, network, prefix_len))
+    }
+
+    /// The address of the client behind the proxies, for a request that
+    /// arrived from `peer`.
+    ///
+    /// Uses `Forwarded` (RFC 7239) if present, else `X-Forwarded-For`, else
+    /// `X-Real-IP`, and walks the forwarded addresses from the nearest hop
+    /// back, stopping at the first that is not a trusted proxy. A hop that
+    /// cannot be parsed, such as an obfuscated `for=_hidden`, stops the walk
+    /// at the last address known.
+    #[must_use]
+    pub fn client_addr(&self, peer: IpAddr, request: &HandshakeRequest) -> IpAddr {
+        let mut client = peer.to_canonical();
+        if !self.contains(client) {
+            return client;
+        }
+        for hop in forwarded_hops(&request.headers).into_iter().rev() {
+            let Some(addr) = hop else {
+                break;
+            };
+            client = addr.to_canonical();
+            if !self.contains(client) {
+                break;
+            }
+        }
+        client
+    }
+}
+
+/// Whether the first `prefix_len` bits of `addr` and `network` agree.
+pub(crate) fn in_network(addr: IpAddr, network: IpAddr, prefix_len: u8) -> bool {
+    match (addr, network) {
+        (IpAddr::V4(addr), IpAddr::V4(network)) => {
+            let mask = u32::MAX
+                .checked_shl(32 - u32::from(prefix_len))
+                .unwrap_or(0);
+            u32::from(addr) & mask == u32::from(network) & mask
+        }
+        (IpAddr::V6(addr), IpAddr::V6(network)) => {
+            let mask = u128::MAX
+                .checked_shl(128 - u32::from(prefix_len))
+                .unwrap_or(0);
+            u128::from(addr) & mask == u128::from(network) & mask
+        }
+        _ => false,
+    }
+}
+
+/// The forwarded client addresses, first hop first. `None` marks a hop
+/// that could not be parsed.
+fn forwarded_hops(headers: &Headers) -> Vec<Option<IpAddr>> {
+    if headers.contains("forwarded") {
+        return headers
+            .get_all("forwarded")
+            .flat_map(|value| value.split(','))
+            .map(|element| {
+                element.split(';').find_map(|pair| {
+                    let (name, value) = pair.split_once('=')?;
+                    name.trim()
+                        .eq_ignore_ascii_case("for")
+                        .then(|| parse_node(value.trim().trim_matches('"')))
+                })?
+            })
+            .collect();
+    }
+    if headers.contains("x-forwarded-for") {
+        return headers
+            .get_all("x-forwarded-for")
+            .flat_map(|value| value.split(','))
+            .map(|node| parse_node(node.trim()))
+            .collect();
+    }
+    headers
+        .get("x-real-ip")
+        .map(|node| parse_node(node.trim()))
+        .into_iter()
+        .collect()
+}
+
+/// Parse an address with an optional port: `192.0.2.1`, `192.0.2.1:80`,
+/// `2001:db8::1` or `[2001:db8::1]:80`.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    if let Some(rest) = node.strip_prefix('[') {
+        let (addr, _port) = rest.split_once(']')?;
+        return addr.parse().ok();
+    }
+    if let Ok(addr) = node.parse() {
+        return Some(addr);
+    }
+    let (addr, _port) = node.split_once(':')?;
+    addr.parse::<std::net::Ipv4Addr>().ok().map(IpAddr::V4)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(headers: &[(&str, &str)]) -> HandshakeRequest {
+        let mut request = HandshakeRequest::new("example.com", "/", "key");
+        for (name, value) in headers {
+            request.headers.append(*name, *value);
+        }
+        request
+    }
+
+    fn ip(addr: &str) -> IpAddr {
+        addr.parse().unwrap()
+    }
+
+    #[test]
+    fn test_networks() {
+        let proxies = TrustedProxies::new()
+            .with_proxy(ip("192.0.2.1"))
+            .with_network(ip("10.0.0.0"), 8)
+            .with_network(ip("fd00::"), 8);
+        assert!(proxies.contains(ip("192.0.2.1")));
+        assert!(!proxies.contains(ip("192.0.2.2")));
+        assert!(proxies.contains(ip("10.255.0.1")));
+        assert!(proxies.contains(ip("::ffff:10.1.2.3")));
+        assert!(proxies.contains(ip("fd12::1")));
+        assert!(!proxies.contains(ip("fe80::1")));
+        assert!(
+            TrustedProxies::new()
+                .with_network(ip("0.0.0.0"), 0)
+                .contains(ip("8.8.8.8"))
+        );
+    }
+
+    #[test]
+    fn test_client_addr_walks_trusted_hops() {
+        let proxies = TrustedProxies::new().with_network(ip("10.0.0.0"), 8);
+        let forwarded = request(&[(
+            "Forwarded",
+            r#"for=198.51.100.7;proto=https, for="[2001:db8::17]:4711", for=10.0.0.2"#,
+        )]);
+        assert_eq!(
+            proxies.client_addr(ip("10.0.0.1"), &forwarded),
+            ip("2001:db8::17")
+        );
+
+        // Headers from an untrusted peer are ignored
+        assert_eq!(
+            proxies.client_addr(ip("203.0.113.9"), &forwarded),
+            ip("203.0.113.9")
+        );
+
+        let xff = request(&[
+            ("X-Forwarded-For", "198.51.100.7, 10.0.0.3:8080"),
+            ("X-Real-IP", "192.0.2.99"),
+        ]);
+        assert_eq!(
+            proxies.client_addr(ip("10.0.0.1"), &xff),
+            ip("198.51.100.7")
+        );
+
+        let real_ip = request(&[("X-Real-IP", "192.0.2.99")]);
+        assert_eq!(
+            proxies.client_addr(ip("10.0.0.1"), &real_ip),
+            ip("192.0.2.99")
+        );
+
+        let hidden = request(&[("Forwarded", "for=_hidden, for=10.0.0.4")]);
+        assert_eq!(proxies.client_addr(ip("10.0.0.1"), &hidden), ip("10.0.0.4"));
+    }
+}
@@ -0,0 +1,244 @@
+//! Per-path handshake settings.
+
+use std::fmt;
+use std::sync::Arc;
+
+use crate::config::Config;
+use crate::extensions::{ExtensionOffer, ExtensionRegistry};
+use crate::protocol::{HandshakeRequest, HandshakeResponse};
+
+type ExtensionFactory = Arc<dyn Fn() -> ExtensionRegistry + Send + Sync>;
+
+/// How connections on one path are set up.
+///
+/// Anything left unset falls back to the server's behaviour: its
+/// [`Config`], the first subprotocol the client offers and no extensions.
+#[derive(Clone, Default)]
+pub struct Route {
+    config: Option<Config>,
+    protocols: Option<Vec<String>>,
+    extensions: Option<ExtensionFactory>,
+}
+
+impl Route {
+    /// A route with the server's settings.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use `config` for connections on this route instead of the server's.
+    ///
+    /// The request head has already been read under the server's
+    /// `limits.max_handshake_size` and `handshake_rate` by then.
+    #[must_use]
+    pub fn with_config(mut self, config: Config) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// Subprotocols this route speaks. The first one the client offers that
+    /// is in the list is selected; if none matches, the response names no
+    /// subprotocol.
+    #[must_use]
+    pub fn with_protocols<I, P>(mut self, protocols: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: Into<String>,
+    {
+        self.protocols = Some(protocols.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Negotiate extensions from a fresh registry built by `factory` for each
+    /// connection, e.g. one holding a `DeflateExtension`.
+    #[must_use]
+    pub fn with_extensions<F>(mut self, factory: F) -> Self
+    where
+        F: Fn() -> ExtensionRegistry + Send + Sync + 'static,
+    {
+        self.extensions = Some(Arc::new(factory));
+        self
+    }
+
+    /// The config for this route, or `default` if none was set.
+    pub(crate) fn config(&self, default: Config) -> Config {
+        self.config.clone().unwrap_or(default)
+    }
+
+    /// Select the subprotocol and negotiate extensions for `request`,
+    /// filling in `response`.
+    pub(crate) fn negotiate(
+        &self,
+        request: &HandshakeRequest,
+        response: &mut HandshakeResponse,
+    ) -> ExtensionRegistry {
+        if let Some(protocols) = &self.protocols {
+            response.protocol = request
+                .protocols
+                .iter()
+                .find(|offered| protocols.contains(offered))
+                .cloned();
+        }
+
+        let Some(factory) = &self.extensions else {
+            return ExtensionRegistry::new();
+        };
+        let mut registry = factory();
+        // Offers we cannot parse are declined like unknown ones
+        let offers: Vec<_> = request
+            .extensions
+            .iter()
+            .filter_map(|offer| ExtensionOffer::parse(offer).ok())
+            .collect();
+        response.extensions = registry
+            .negotiate(&offers)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        registry
+    }
+}
+
+impl fmt::Debug for Route {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Route")
+            .field("config", &self.config)
+            .field("protocols", &self.protocols)
+            .field("extensions", &self.extensions.as_ref().map(|_| ".."))
+            .finish()
+    }
+}
+
+/// Maps request paths to [`Route`]s, set with
+/// [`Server::with_router`](super::Server::with_router).
+///
+/// A pattern is either an exact path such as `/feed`, or a prefix ending in
+/// `/*` such as `/rooms/*`, which matches `/rooms/` and everything below it.
+/// The query string is ignored. Exact patterns win over prefixes, and longer
+/// prefixes over shorter ones. Requests matching no pattern get the default
+/// route, or `404 Not Found` if there is none.
+///
+/// ```rust,ignore
+/// let router = Router::new()
+///     .route("/feed", Route::new().with_extensions(|| {
+///         let mut registry = ExtensionRegistry::new();
+///         registry.add(Box::new(DeflateExtension::server(DeflateConfig::new()))).unwrap();
+///         registry
+///     }))
+///     .route("/control", Route::new().with_protocols(["control.v1"]));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Router {
+    routes: Vec<(String, Route)>,
+    default: Option<Route>,
+}
+
+impl Router {
+    /// A router without routes, which turns every request away.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a route for `pattern`. A later route for the same pattern
+    /// replaces the earlier one.
+    #[must_use]
+    pub fn route(mut self, pattern: impl Into<String>, route: Route) -> Self {
+        let pattern = pattern.into();
+        self.routes.retain(|(p, _)| *p != pattern);
+        self.routes.push((pattern, route));
+        self
+    }
+
+    /// Use `route` for requests no pattern matches.
+    #[must_use]
+    pub fn with_default(mut self, route: Route) -> Self {
+        self.default = Some(route);
+        self
+    }
+
+    /// The route for a request to `path`.
+    #[must_use]
+    pub fn find(&self, path: &str) -> Option<&Route> {
+        let path = path.split_once('?').map_or(path, |(path, _)| path);
+
+        let exact = self.routes.iter().find(|(pattern, _)| pattern == path);
+        let prefix = || {
+            self.routes
+                .iter()
+                .filter_map(|(pattern, route)| {
+                    let prefix = pattern.strip_suffix('*')?;
+                    (prefix.ends_with('/') && path.starts_with(prefix))
+                        .then_some((prefix.len(), route))
+                })
+                .max_by_key(|(len, _)| *len)
+                .map(|(_, route)| route)
+        };
+
+        exact
+            .map(|(_, route)| route)
+            .or_else(prefix)
+            .or(self.default.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tagged(protocol: &str) -> Route {
+        Route::new().with_protocols([protocol])
+    }
+
+    fn protocols(route: Option<&Route>) -> Option<Vec<String>> {
+        route.and_then(|route| route.protocols.clone())
+    }
+
+    #[test]
+    fn test_find_route() {
+        let router = Router::new()
+            .route("/feed", tagged("exact"))
+            .route("/rooms/*", tagged("rooms"))
+            .route("/rooms/admin/*", tagged("admin"));
+
+        assert_eq!(
+            protocols(router.find("/feed?since=3")),
+            Some(vec!["exact".into()])
+        );
+        assert_eq!(
+            protocols(router.find("/rooms/42")),
+            Some(vec!["rooms".into()])
+        );
+        assert_eq!(
+            protocols(router.find("/rooms/admin/1")),
+            Some(vec!["admin".into()])
+        );
+        assert!(router.find("/feed/more").is_none());
+        assert!(router.find("/rooms").is_none());
+
+        let router = router.with_default(tagged("default"));
+        assert_eq!(
+            protocols(router.find("/other")),
+            Some(vec!["default".into()])
+        );
+    }
+
+    #[test]
+    fn test_negotiate_protocol() {
+        let mut request = HandshakeRequest::new("localhost", "/", "dGhlIHNhbXBsZSBub25jZQ==");
+        request.protocols = vec!["v2".into(), "v1".into()];
+        let mut response = HandshakeResponse::from_request(&request);
+        assert_eq!(response.protocol.as_deref(), Some("v2"));
+
+        Route::new()
+            .with_protocols(["v1"])
+            .negotiate(&request, &mut response);
+        assert_eq!(response.protocol.as_deref(), Some("v1"));
+
+        Route::new()
+            .with_protocols(["v3"])
+            .negotiate(&request, &mut response);
+        assert_eq!(response.protocol, None);
+    }
+}
@@ -0,0 +1,205 @@
+//! TCP socket tuning for the sockets rsws opens and accepts.
+//!
+//! [`SocketOptions`] is set with
+//! [`Connector::with_socket_options`](crate::client::Connector::with_socket_options)
+//! for outgoing connections and
+//! [`Server::with_socket_options`](crate::server::Server::with_socket_options)
+//! for accepted ones:
+//!
+//! ```rust,ignore
+//! let options = SocketOptions::new()
+//!     .with_nodelay(true)
+//!     .with_keepalive(Duration::from_secs(60));
+//! let connector = Connector::new().with_socket_options(options.clone());
+//! let server = Server::bind("0.0.0.0:9001").await?.with_socket_options(options);
+//! ```
+
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use socket2::{SockRef, TcpKeepalive};
+use tokio::net::{TcpSocket, TcpStream};
+
+/// Options applied to TCP sockets. Anything left unset keeps the operating
+/// system default.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SocketOptions {
+    /// `TCP_NODELAY`: send small segments at once instead of waiting to
+    /// fill them (Nagle's algorithm).
+    pub nodelay: Option<bool>,
+
+    /// `SO_KEEPALIVE`, with this much idle time before the first probe.
+    pub keepalive: Option<Duration>,
+
+    /// `SO_SNDBUF` in bytes.
+    pub send_buffer_size: Option<u32>,
+
+    /// `SO_RCVBUF` in bytes.
+    pub recv_buffer_size: Option<u32>,
+
+    /// Local address outgoing connections are made from. Addresses of the
+    /// other family are not tried.
+    pub local_addr: Option<IpAddr>,
+
+    /// Network interface outgoing connections are bound to
+    /// (`SO_BINDTODEVICE`). Only supported on Linux, Android and Fuchsia.
+    pub interface: Option<String>,
+}
+
+impl SocketOptions {
+    /// Options that change nothing.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set `TCP_NODELAY`.
+    #[must_use]
+    pub fn with_nodelay(mut self, nodelay: bool) -> Self {
+        self.nodelay = Some(nodelay);
+        self
+    }
+
+    /// Enable TCP keepalive, probing after `idle` without traffic.
+    #[must_use]
+    pub fn with_keepalive(mut self, idle: Duration) -> Self {
+        self.keepalive = Some(idle);
+        self
+    }
+
+    /// Set the kernel send buffer size.
+    #[must_use]
+    pub fn with_send_buffer_size(mut self, size: u32) -> Self {
+        self.send_buffer_size = Some(size);
+        self
+    }
+
+    /// Set the kernel receive buffer size.
+    #[must_use]
+    pub fn with_recv_buffer_size(mut self, size: u32) -> Self {
+        self.recv_buffer_size = Some(size);
+        self
+    }
+
+    /// Connect from `addr`.
+    #[must_use]
+    pub fn with_local_addr(mut self, addr: IpAddr) -> Self {
+        self.local_addr = Some(addr);
+        self
+    }
+
+    /// Connect through the network interface named `name`, e.g. `eth1`.
+    #[must_use]
+    pub fn with_interface(mut self, name: impl Into<String>) -> Self {
+        self.interface = Some(name.into());
+        self
+    }
+
+    /// Open a connection to `addr` with these options.
+    pub(crate) async fn connect(&self, addr: SocketAddr) -> io::Result<TcpStream> {
+        let socket = if addr.is_ipv6() {
+            TcpSocket::new_v6()?
+        } else {
+            TcpSocket::new_v4()?
+        };
+        // Buffer sizes must be set before connecting to affect window scaling
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+        if let Some(interface) = &self.interface {
+            bind_device(&socket, interface)?;
+        }
+        if let Some(ip) = self.local_addr {
+            if ip.is_ipv6() != addr.is_ipv6() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("local address {} cannot reach {}", ip, addr),
+                ));
+            }
+            socket.bind(SocketAddr::new(ip, 0))?;
+        }
+
+        let stream = socket.connect(addr).await?;
+        self.apply_connected(&stream)?;
+        Ok(stream)
+    }
+
+    /// Apply the options that make sense for an accepted socket.
+    /// `local_addr` and `interface` are ignored.
+    pub(crate) fn apply(&self, stream: &TcpStream) -> io::Result<()> {
+        let socket = SockRef::from(stream);
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size as usize)?;
+        }
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size as usize)?;
+        }
+        self.apply_connected(stream)
+    }
+
+    fn apply_connected(&self, stream: &TcpStream) -> io::Result<()> {
+        if let Some(nodelay) = self.nodelay {
+            stream.set_nodelay(nodelay)?;
+        }
+        if let Some(idle) = self.keepalive {
+            SockRef::from(stream).set_tcp_keepalive(&TcpKeepalive::new().with_time(idle))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+fn bind_device(socket: &TcpSocket, interface: &str) -> io::Result<()> {
+    socket.bind_device(Some(interface.as_bytes()))
+}
+
+#[cfg(not(any(target_os = "android", target_os = "fuchsia", target_os = "linux")))]
+fn bind_device(_socket: &TcpSocket, _interface: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "binding to an interface is not supported on this platform",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_connect_with_options() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let options = SocketOptions::new()
+            .with_nodelay(true)
+            .with_keepalive(Duration::from_secs(60))
+            .with_recv_buffer_size(64 * 1024)
+            .with_local_addr("127.0.0.1".parse().unwrap());
+
+        let stream = options.connect(addr).await.unwrap();
+        assert!(stream.nodelay().unwrap());
+        assert!(SockRef::from(&stream).keepalive().unwrap());
+        assert_eq!(
+            stream.local_addr().unwrap().ip(),
+            options.local_addr.unwrap()
+        );
+
+        let (accepted, _) = listener.accept().await.unwrap();
+        options.apply(&accepted).unwrap();
+        assert!(accepted.nodelay().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_local_addr_family_mismatch() {
+        let options = SocketOptions::new().with_local_addr("::1".parse().unwrap());
+        let err = options
+            .connect("127.0.0.1:9".parse().unwrap())
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
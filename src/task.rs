@@ -0,0 +1,104 @@
+//! Background tasks spawned by the crate.
+//!
+//! Every task is named `rsws::<module>::<task>`. Built with
+//! `RUSTFLAGS="--cfg tokio_unstable"` and the `tracing` feature, the names
+//! show up in tokio-console. With the `metrics` feature, running tasks are
+//! counted in `rsws_tasks_active`, labelled by name.
+
+use std::future::Future;
+
+use tokio::task::JoinHandle;
+use tokio::task::JoinSet;
+
+/// Opening handshake of one accepted socket.
+pub(crate) const SERVER_HANDSHAKE: &str = "rsws::server::handshake";
+/// `503` answer to a socket over the connection limits.
+pub(crate) const SERVER_REJECT: &str = "rsws::server::reject";
+/// One Happy Eyeballs connection attempt.
+pub(crate) const CONNECT_ATTEMPT: &str = "rsws::client::connect_attempt";
+/// Driver of a connection behind a `WsHandle`.
+pub(crate) const CONNECTION_DRIVER: &str = "rsws::connection::driver";
+/// Close of a connection dropped while open.
+pub(crate) const CLOSE_ON_DROP: &str = "rsws::connection::close_on_drop";
+/// One call to a service serving a connection.
+#[cfg(feature = "tower")]
+pub(crate) const TOWER_CALL: &str = "rsws::tower::call";
+/// Periodic OCSP response refresh.
+#[cfg(feature = "tls-rustls")]
+pub(crate) const OCSP_REFRESH: &str = "rsws::tls::ocsp_refresh";
+
+/// Spawn `future` on the current runtime as task `name`.
+pub(crate) fn spawn<F>(name: &'static str, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let future = instrument(name, future);
+    #[cfg(all(tokio_unstable, feature = "tracing"))]
+    {
+        let (named, unnamed) = hand_off(future);
+        // Without a name if the builder fails
+        tokio::task::Builder::new()
+            .name(name)
+            .spawn(named)
+            .unwrap_or_else(|_| tokio::spawn(unnamed))
+    }
+    #[cfg(not(all(tokio_unstable, feature = "tracing")))]
+    tokio::spawn(future)
+}
+
+/// Spawn `future` into `set` as task `name`.
+pub(crate) fn spawn_in<T, F>(set: &mut JoinSet<T>, name: &'static str, future: F)
+where
+    T: Send + 'static,
+    F: Future<Output = T> + Send + 'static,
+{
+    let future = instrument(name, future);
+    #[cfg(all(tokio_unstable, feature = "tracing"))]
+    {
+        let (named, unnamed) = hand_off(future);
+        // Without a name if the builder fails
+        if set.build_task().name(name).spawn(named).is_err() {
+            set.spawn(unnamed);
+        }
+    }
+    #[cfg(not(all(tokio_unstable, feature = "tracing")))]
+    set.spawn(future);
+}
+
+/// Two futures running `future`, for whichever one gets spawned: the task
+/// builder consumes what it is given even when it fails. The other one
+/// never completes.
+#[cfg(all(tokio_unstable, feature = "tracing"))]
+fn hand_off<F>(
+    future: F,
+) -> (
+    impl Future<Output = F::Output>,
+    impl Future<Output = F::Output>,
+)
+where
+    F: Future + Send,
+{
+    use std::sync::{Arc, Mutex, PoisonError};
+
+    let slot = Arc::new(Mutex::new(Some(future)));
+    let take = |slot: Arc<Mutex<Option<F>>>| async move {
+        let future = slot.lock().unwrap_or_else(PoisonError::into_inner).take();
+        match future {
+            Some(future) => future.await,
+            None => std::future::pending().await,
+        }
+    };
+    (take(Arc::clone(&slot)), take(slot))
+}
+
+#[cfg(feature = "metrics")]
+async fn instrument<F: Future>(name: &'static str, future: F) -> F::Output {
+    let _guard = crate::metrics::TaskGuard::new(name);
+    future.await
+}
+
+#[cfg(not(feature = "metrics"))]
+fn instrument<F: Future>(_name: &'static str, future: F) -> F {
+    future
+}
@@ -0,0 +1,344 @@
+//! Helpers for testing code built on `rsws`.
+//!
+//! Enabled by the `testing` feature. Add it to `[dev-dependencies]` only:
+//!
+//! ```toml
+//! [dev-dependencies]
+//! rsws = { version = "0.2", features = ["testing"] }
+//! ```
+//!
+//! [`MockStream`] stands in for a socket, so a [`Connection`] can be driven
+//! from scripted bytes without any I/O; [`client_frame`] and
+//! [`server_frame`] encode the frames a peer would send. [`TestServer`] and
+//! [`TestClient`] are a loopback echo server and a minimal client for tests
+//! that do want a real socket.
+//!
+//! ```rust,ignore
+//! use rsws::testing::{MockStream, client_frame};
+//!
+//! let input = client_frame(&Frame::text("hello"));
+//! let mut conn = Connection::new(MockStream::new(input), Role::Server, Config::server());
+//! assert_eq!(conn.recv().await?, Some(Message::text("hello")));
+//! ```
+
+use std::future::{Future, poll_fn};
+use std::io::{Cursor, IoSlice};
+use std::net::SocketAddr;
+use std::pin::{Pin, pin};
+use std::task::{Context, Poll};
+
+use bytes::BytesMut;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+
+use crate::client::Connector;
+use crate::protocol::Frame;
+use crate::server::Server;
+use crate::{CloseCode, Connection, Message, Result};
+
+/// Mask [`client_frame`] applies, the one from the RFC 6455 examples.
+pub const CLIENT_MASK: [u8; 4] = [0x37, 0xfa, 0x21, 0x3d];
+
+/// An in-memory stream: reads come from a fixed buffer, writes are recorded.
+///
+/// Reads return end-of-stream once the buffer is exhausted. Writes and
+/// flushes always succeed.
+#[derive(Debug, Default)]
+pub struct MockStream {
+    read_data: Cursor<Vec<u8>>,
+    write_data: Vec<u8>,
+    writes: usize,
+}
+
+impl MockStream {
+    /// A stream that yields `data` to readers.
+    #[must_use]
+    pub fn new(data: Vec<u8>) -> Self {
+        Self {
+            read_data: Cursor::new(data),
+            write_data: Vec::new(),
+            writes: 0,
+        }
+    }
+
+    /// Everything written so far.
+    pub fn written(&self) -> &[u8] {
+        &self.write_data
+    }
+
+    /// Number of write calls so far, vectored or not.
+    pub fn writes(&self) -> usize {
+        self.writes
+    }
+
+    /// Consume the stream and return everything written to it.
+    pub fn into_written(self) -> Vec<u8> {
+        self.write_data
+    }
+}
+
+impl AsyncRead for MockStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let pos = self.read_data.position() as usize;
+        let data = self.read_data.get_ref();
+        if pos >= data.len() {
+            return Poll::Ready(Ok(()));
+        }
+        let remaining = &data[pos..];
+        let to_copy = std::cmp::min(remaining.len(), buf.remaining());
+        buf.put_slice(&remaining[..to_copy]);
+        self.read_data.set_position((pos + to_copy) as u64);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for MockStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        self.write_data.extend_from_slice(buf);
+        self.writes += 1;
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<std::io::Result<usize>> {
+        let len = bufs.iter().map(|buf| buf.len()).sum();
+        for buf in bufs {
+            self.write_data.extend_from_slice(buf);
+        }
+        self.writes += 1;
+        Poll::Ready(Ok(len))
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        true
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Encode `frame` as a client sends it, masked with [`CLIENT_MASK`].
+pub fn client_frame(frame: &Frame) -> Vec<u8> {
+    encode(frame, Some(CLIENT_MASK))
+}
+
+/// Encode `frame` as a server sends it, unmasked.
+pub fn server_frame(frame: &Frame) -> Vec<u8> {
+    encode(frame, None)
+}
+
+fn encode(frame: &Frame, mask: Option<[u8; 4]>) -> Vec<u8> {
+    let mut buf = BytesMut::with_capacity(frame.wire_size(mask.is_some()));
+    frame.write_to(&mut buf, mask);
+    buf.into()
+}
+
+/// An echo server on a loopback port.
+///
+/// Text and binary messages are sent back unchanged. The server runs in a
+/// spawned task until [`shutdown`](Self::shutdown) is called or it is
+/// dropped.
+#[derive(Debug)]
+pub struct TestServer {
+    shutdown_tx: Option<oneshot::Sender<()>>,
+    handle: JoinHandle<()>,
+}
+
+impl TestServer {
+    /// Spawn a server on an OS-assigned port and return it with its address.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no loopback port can be bound.
+    pub async fn spawn() -> (Self, SocketAddr) {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("failed to bind a loopback port");
+        let addr = listener.local_addr().expect("listener has an address");
+        let (shutdown_tx, shutdown_rx) = oneshot::channel();
+        let handle = tokio::spawn(Self::run(Server::from_listener(listener), shutdown_rx));
+        let server = Self {
+            shutdown_tx: Some(shutdown_tx),
+            handle,
+        };
+        (server, addr)
+    }
+
+    /// Stop accepting connections and wait for the accept loop to end.
+    /// Connections already accepted keep echoing until their peers leave.
+    pub async fn shutdown(mut self) {
+        if let Some(tx) = self.shutdown_tx.take() {
+            let _ = tx.send(());
+        }
+        let _ = (&mut self.handle).await;
+    }
+
+    async fn run(mut server: Server, mut shutdown_rx: oneshot::Receiver<()>) {
+        loop {
+            let accepted = {
+                let mut accept = pin!(server.accept());
+                poll_fn(|cx| {
+                    if Pin::new(&mut shutdown_rx).poll(cx).is_ready() {
+                        return Poll::Ready(None);
+                    }
+                    accept.as_mut().poll(cx).map(Some)
+                })
+                .await
+            };
+            match accepted {
+                None => break,
+                Some(Ok((conn, _, _))) => {
+                    tokio::spawn(Self::echo(conn));
+                }
+                Some(Err(_)) => {}
+            }
+        }
+    }
+
+    async fn echo(mut conn: Connection<TcpStream>) -> Result<()> {
+        while let Some(message) = conn.recv().await? {
+            match message {
+                Message::Text(_) | Message::Binary(_) => conn.send(message).await?,
+                Message::Close(_) => break,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+/// A minimal client for exercising a server over a real socket.
+pub struct TestClient {
+    conn: Connection<TcpStream>,
+    id: Option<usize>,
+}
+
+impl TestClient {
+    /// Connect and handshake with the server at `addr`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the connection or the handshake fails.
+    pub async fn connect(addr: SocketAddr) -> Result<Self> {
+        Self::connect_inner(addr, None).await
+    }
+
+    /// Like [`connect`](Self::connect), tagging the client with `id`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the connection or the handshake fails.
+    pub async fn connect_with_id(addr: SocketAddr, id: usize) -> Result<Self> {
+        Self::connect_inner(addr, Some(id)).await
+    }
+
+    async fn connect_inner(addr: SocketAddr, id: Option<usize>) -> Result<Self> {
+        let (conn, _) = Connector::new().connect(&format!("ws://{addr}/")).await?;
+        Ok(Self { conn, id })
+    }
+
+    /// The id given to [`connect_with_id`](Self::connect_with_id).
+    pub fn id(&self) -> Option<usize> {
+        self.id
+    }
+
+    /// The underlying connection.
+    pub fn connection(&mut self) -> &mut Connection<TcpStream> {
+        &mut self.conn
+    }
+
+    /// Send a text message.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the message cannot be sent.
+    pub async fn send_text(&mut self, text: &str) -> Result<()> {
+        self.conn.send(Message::text(text)).await
+    }
+
+    /// Receive the next text message. Returns `None` once the connection
+    /// closes or a message of another type arrives.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading fails.
+    pub async fn recv_text(&mut self) -> Result<Option<String>> {
+        match self.conn.recv().await? {
+            Some(Message::Text(text)) => Ok(Some(text)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Close the connection and wait for the server's close frame.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the close handshake fails.
+    pub async fn close(&mut self) -> Result<()> {
+        self.conn.close(CloseCode::Normal, "").await?;
+        while let Some(message) = self.conn.recv().await? {
+            if matches!(message, Message::Close(_)) {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Config, Role};
+
+    #[tokio::test]
+    async fn test_mock_stream_drives_a_connection() {
+        let mut input = client_frame(&Frame::text("hello"));
+        input.extend(client_frame(&Frame::close(Some(1000), "")));
+        let mut conn = Connection::new(MockStream::new(input), Role::Server, Config::server());
+
+        assert_eq!(conn.recv().await.unwrap(), Some(Message::text("hello")));
+        assert!(matches!(
+            conn.recv().await.unwrap(),
+            Some(Message::Close(_))
+        ));
+
+        let written = conn.into_parts().io.into_written();
+        assert_eq!(written, server_frame(&Frame::close(Some(1000), "")));
+    }
+
+    #[tokio::test]
+    async fn test_echo_server() {
+        let (server, addr) = TestServer::spawn().await;
+        let mut client = TestClient::connect_with_id(addr, 7).await.unwrap();
+        assert_eq!(client.id(), Some(7));
+
+        client.send_text("ping").await.unwrap();
+        assert_eq!(client.recv_text().await.unwrap().as_deref(), Some("ping"));
+        client.close().await.unwrap();
+        server.shutdown().await;
+    }
+}
@@ -0,0 +1,529 @@
+//! Kernel TLS (kTLS) offload for rustls streams.
+//!
+//! After rustls completes the handshake, the negotiated traffic secrets are
+//! installed on the socket with `setsockopt(SOL_TLS, ...)` so the kernel
+//! encrypts and decrypts application data records. Handshake and alert
+//! records that arrive afterwards are surfaced through `recvmsg` control
+//! messages and handled here.
+
+use std::io::{self, Read};
+use std::os::fd::{AsRawFd, RawFd};
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+
+use bytes::{Buf, BytesMut};
+use rustls::{CipherSuite, ConnectionCommon, ConnectionTrafficSecrets, ProtocolVersion};
+use tokio::io::{AsyncRead, AsyncWrite, Interest, ReadBuf};
+use tokio::net::TcpStream;
+
+use super::TlsError;
+
+const RECORD_ALERT: u8 = 21;
+const RECORD_HANDSHAKE: u8 = 22;
+const RECORD_APPLICATION_DATA: u8 = 23;
+
+const ALERT_CLOSE_NOTIFY: u8 = 0;
+const HANDSHAKE_NEW_SESSION_TICKET: u8 = 4;
+const HANDSHAKE_KEY_UPDATE: u8 = 24;
+
+/// Largest plaintext the kernel hands back for a single record.
+const MAX_RECORD_PLAINTEXT: usize = 16 * 1024 + 256;
+
+/// Control message buffer, 8-byte aligned and large enough for one
+/// `CMSG_SPACE(1)` record-type message.
+type CmsgBuf = [u64; 4];
+
+/// Check whether a finished handshake can be moved into the kernel, and if
+/// so attach the `tls` upper layer protocol to the socket.
+///
+/// Returns `false` without touching the socket when the negotiated cipher
+/// suite has no kTLS equivalent, rustls still holds records to send, or the
+/// kernel lacks the `tls` module, in which case the caller keeps the
+/// userspace stream. Records rustls has already read are decrypted first,
+/// so their plaintext is carried over by [`KtlsStream::new`]; a record
+/// read only in part cannot be seen through rustls and is lost.
+///
+/// Once this returns `true` there is no way back: the socket must become a
+/// [`KtlsStream`] or be dropped.
+pub(crate) fn prepare<D>(io: &TcpStream, conn: &mut ConnectionCommon<D>) -> Result<bool, TlsError> {
+    let suite_supported = conn
+        .negotiated_cipher_suite()
+        .is_some_and(|suite| is_supported_suite(suite.suite()));
+    let version_supported = matches!(
+        conn.protocol_version(),
+        Some(ProtocolVersion::TLSv1_2 | ProtocolVersion::TLSv1_3)
+    );
+
+    if !suite_supported || !version_supported || conn.is_handshaking() {
+        return Ok(false);
+    }
+
+    // Ciphertext left in rustls would never reach the kernel
+    conn.process_new_packets()
+        .map_err(|e| TlsError::Io(io::Error::new(io::ErrorKind::InvalidData, e)))?;
+    if conn.wants_write() {
+        return Ok(false);
+    }
+
+    let ulp = b"tls";
+    // SAFETY: `ulp` is valid for `ulp.len()` bytes for the duration of the call.
+    let ret = unsafe {
+        libc::setsockopt(
+            io.as_raw_fd(),
+            libc::SOL_TCP,
+            libc::TCP_ULP,
+            ulp.as_ptr().cast(),
+            ulp.len() as libc::socklen_t,
+        )
+    };
+    Ok(ret == 0)
+}
+
+fn is_supported_suite(suite: CipherSuite) -> bool {
+    matches!(
+        suite,
+        CipherSuite::TLS13_AES_128_GCM_SHA256
+            | CipherSuite::TLS13_AES_256_GCM_SHA384
+            | CipherSuite::TLS13_CHACHA20_POLY1305_SHA256
+            | CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256
+            | CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384
+            | CipherSuite::TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256
+            | CipherSuite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256
+            | CipherSuite::TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384
+            | CipherSuite::TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256
+    )
+}
+
+/// Kernel crypto parameters for one direction of a connection.
+#[derive(Clone, Copy)]
+enum CryptoInfo {
+    AesGcm128(libc::tls12_crypto_info_aes_gcm_128),
+    AesGcm256(libc::tls12_crypto_info_aes_gcm_256),
+    Chacha20Poly1305(libc::tls12_crypto_info_chacha20_poly1305),
+}
+
+impl CryptoInfo {
+    fn new(version: u16, seq: u64, secrets: &ConnectionTrafficSecrets) -> Result<Self, TlsError> {
+        let rec_seq = seq.to_be_bytes();
+
+        match secrets {
+            ConnectionTrafficSecrets::Aes128Gcm { key, iv } => {
+                let (salt, iv) = iv.as_ref().split_at(libc::TLS_CIPHER_AES_GCM_128_SALT_SIZE);
+                Ok(Self::AesGcm128(libc::tls12_crypto_info_aes_gcm_128 {
+                    info: libc::tls_crypto_info {
+                        version,
+                        cipher_type: libc::TLS_CIPHER_AES_GCM_128,
+                    },
+                    iv: to_array(iv)?,
+                    key: to_array(key.as_ref())?,
+                    salt: to_array(salt)?,
+                    rec_seq,
+                }))
+            }
+            ConnectionTrafficSecrets::Aes256Gcm { key, iv } => {
+                let (salt, iv) = iv.as_ref().split_at(libc::TLS_CIPHER_AES_GCM_256_SALT_SIZE);
+                Ok(Self::AesGcm256(libc::tls12_crypto_info_aes_gcm_256 {
+                    info: libc::tls_crypto_info {
+                        version,
+                        cipher_type: libc::TLS_CIPHER_AES_GCM_256,
+                    },
+                    iv: to_array(iv)?,
+                    key: to_array(key.as_ref())?,
+                    salt: to_array(salt)?,
+                    rec_seq,
+                }))
+            }
+            ConnectionTrafficSecrets::Chacha20Poly1305 { key, iv } => Ok(Self::Chacha20Poly1305(
+                libc::tls12_crypto_info_chacha20_poly1305 {
+                    info: libc::tls_crypto_info {
+                        version,
+                        cipher_type: libc::TLS_CIPHER_CHACHA20_POLY1305,
+                    },
+                    iv: to_array(iv.as_ref())?,
+                    key: to_array(key.as_ref())?,
+                    salt: [],
+                    rec_seq,
+                },
+            )),
+            _ => Err(TlsError::Configuration(
+                "cipher suite not supported by kTLS".to_string(),
+            )),
+        }
+    }
+
+    fn install(&self, fd: RawFd, direction: libc::c_int) -> io::Result<()> {
+        let (ptr, len): (*const libc::c_void, usize) = match self {
+            Self::AesGcm128(info) => (std::ptr::from_ref(info).cast(), size_of_val(info)),
+            Self::AesGcm256(info) => (std::ptr::from_ref(info).cast(), size_of_val(info)),
+            Self::Chacha20Poly1305(info) => (std::ptr::from_ref(info).cast(), size_of_val(info)),
+        };
+
+        // SAFETY: `ptr` points to a live crypto info struct of `len` bytes.
+        let ret =
+            unsafe { libc::setsockopt(fd, libc::SOL_TLS, direction, ptr, len as libc::socklen_t) };
+        if ret == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+}
+
+fn to_array<const N: usize>(bytes: &[u8]) -> Result<[u8; N], TlsError> {
+    bytes
+        .try_into()
+        .map_err(|_| TlsError::Configuration("unexpected kTLS secret length".to_string()))
+}
+
+/// A TLS stream whose record layer runs in the kernel.
+///
+/// Created by [`TlsAcceptor::accept_ktls`](super::TlsAcceptor::accept_ktls)
+/// and [`TlsConnector::connect_ktls`](super::TlsConnector::connect_ktls).
+/// Session tickets received after the handshake are discarded and a peer
+/// `KeyUpdate` fails the read, since rustls no longer tracks the session.
+pub struct KtlsStream {
+    io: TcpStream,
+    pending: BytesMut,
+    eof: bool,
+    close_notify_sent: bool,
+}
+
+impl KtlsStream {
+    /// Install the connection's traffic secrets on the socket.
+    ///
+    /// [`prepare`] must have returned `true` for this socket. Plaintext that
+    /// rustls already decrypted is kept and returned by the first reads.
+    ///
+    /// On error the socket is dropped rather than returned: the `tls` layer
+    /// is attached, and its keys may be installed in one direction only.
+    pub(crate) fn new(io: TcpStream, mut conn: rustls::Connection) -> Result<Self, TlsError> {
+        let version = match conn.protocol_version() {
+            Some(ProtocolVersion::TLSv1_2) => libc::TLS_1_2_VERSION,
+            Some(ProtocolVersion::TLSv1_3) => libc::TLS_1_3_VERSION,
+            _ => {
+                return Err(TlsError::Configuration(
+                    "protocol version not supported by kTLS".to_string(),
+                ));
+            }
+        };
+
+        let mut pending = BytesMut::new();
+        let mut eof = false;
+        let mut chunk = [0u8; 4096];
+        loop {
+            match conn.reader().read(&mut chunk) {
+                Ok(0) => {
+                    eof = true;
+                    break;
+                }
+                Ok(n) => pending.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => return Err(TlsError::Io(e)),
+            }
+        }
+
+        let secrets = conn
+            .dangerous_extract_secrets()
+            .map_err(|e| TlsError::Configuration(e.to_string()))?;
+        let tx = CryptoInfo::new(version, secrets.tx.0, &secrets.tx.1)?;
+        let rx = CryptoInfo::new(version, secrets.rx.0, &secrets.rx.1)?;
+
+        let fd = io.as_raw_fd();
+        tx.install(fd, libc::TLS_TX)
+            .and_then(|()| rx.install(fd, libc::TLS_RX))
+            .map_err(|e| {
+                TlsError::Io(io::Error::new(
+                    e.kind(),
+                    format!("kTLS install failed: {e}"),
+                ))
+            })?;
+
+        Ok(Self {
+            io,
+            pending,
+            eof,
+            close_notify_sent: false,
+        })
+    }
+
+    pub fn get_ref(&self) -> &TcpStream {
+        &self.io
+    }
+}
+
+/// Inspect post-handshake messages the kernel passed up.
+fn check_post_handshake(mut data: &[u8]) -> io::Result<()> {
+    while data.len() >= 4 {
+        let len = usize::from(data[1]) << 16 | usize::from(data[2]) << 8 | usize::from(data[3]);
+        match data[0] {
+            HANDSHAKE_NEW_SESSION_TICKET => {}
+            HANDSHAKE_KEY_UPDATE => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "peer requested a TLS key update, which kTLS offload does not support",
+                ));
+            }
+            other => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unexpected post-handshake message type {}", other),
+                ));
+            }
+        }
+        data = data.get(4 + len..).unwrap_or_default();
+    }
+    Ok(())
+}
+
+/// Map an alert record to end-of-stream or an error.
+fn check_alert(data: &[u8]) -> io::Result<()> {
+    match data {
+        [_, ALERT_CLOSE_NOTIFY] => Ok(()),
+        [_, desc] => Err(io::Error::new(
+            io::ErrorKind::ConnectionAborted,
+            format!("received TLS alert {}", desc),
+        )),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "malformed TLS alert record",
+        )),
+    }
+}
+
+/// Receive one record, returning its length and content type.
+fn recv_record(fd: RawFd, buf: &mut [u8]) -> io::Result<(usize, u8)> {
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr().cast(),
+        iov_len: buf.len(),
+    };
+    let mut cmsg_buf: CmsgBuf = [0; 4];
+    // SAFETY: an all-zero msghdr is a valid empty header.
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = cmsg_buf.as_mut_ptr().cast();
+    msg.msg_controllen = size_of_val(&cmsg_buf) as _;
+
+    // SAFETY: `msg` references `iov` and `cmsg_buf`, both live for the call.
+    let n = unsafe { libc::recvmsg(fd, &mut msg, 0) };
+    if n < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut record_type = RECORD_APPLICATION_DATA;
+    // SAFETY: the kernel filled `msg_control` with well-formed cmsg headers.
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_TLS && (*cmsg).cmsg_type == libc::TLS_GET_RECORD_TYPE
+            {
+                record_type = *libc::CMSG_DATA(cmsg);
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+
+    Ok((n as usize, record_type))
+}
+
+/// Send a single record with an explicit content type.
+fn send_record(fd: RawFd, record_type: u8, data: &[u8]) -> io::Result<usize> {
+    let mut iov = libc::iovec {
+        iov_base: data.as_ptr().cast_mut().cast(),
+        iov_len: data.len(),
+    };
+    let mut cmsg_buf: CmsgBuf = [0; 4];
+    // SAFETY: an all-zero msghdr is a valid empty header.
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = cmsg_buf.as_mut_ptr().cast();
+
+    // SAFETY: `cmsg_buf` is aligned and larger than CMSG_SPACE(1).
+    unsafe {
+        msg.msg_controllen = libc::CMSG_SPACE(1) as _;
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_TLS;
+        (*cmsg).cmsg_type = libc::TLS_SET_RECORD_TYPE;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(1) as _;
+        *libc::CMSG_DATA(cmsg) = record_type;
+    }
+
+    // SAFETY: `msg` references `iov` and `cmsg_buf`, both live for the call.
+    let n = unsafe { libc::sendmsg(fd, &msg, 0) };
+    if n < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(n as usize)
+    }
+}
+
+impl AsyncRead for KtlsStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        if !this.pending.is_empty() {
+            let n = this.pending.len().min(buf.remaining());
+            buf.put_slice(&this.pending[..n]);
+            this.pending.advance(n);
+            return Poll::Ready(Ok(()));
+        }
+
+        if this.eof {
+            return Poll::Ready(Ok(()));
+        }
+
+        let fd = this.io.as_raw_fd();
+        let mut record = [0u8; MAX_RECORD_PLAINTEXT];
+        loop {
+            ready!(this.io.poll_read_ready(cx))?;
+
+            let (n, record_type) = match this
+                .io
+                .try_io(Interest::READABLE, || recv_record(fd, &mut record))
+            {
+                Ok(res) => res,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                Err(e) => return Poll::Ready(Err(e)),
+            };
+
+            match record_type {
+                RECORD_APPLICATION_DATA => {
+                    if n == 0 {
+                        this.eof = true;
+                    } else {
+                        let direct = n.min(buf.remaining());
+                        buf.put_slice(&record[..direct]);
+                        this.pending.extend_from_slice(&record[direct..n]);
+                    }
+                    return Poll::Ready(Ok(()));
+                }
+                RECORD_ALERT => {
+                    check_alert(&record[..n])?;
+                    this.eof = true;
+                    return Poll::Ready(Ok(()));
+                }
+                RECORD_HANDSHAKE => check_post_handshake(&record[..n])?,
+                other => {
+                    return Poll::Ready(Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("unexpected TLS record type {}", other),
+                    )));
+                }
+            }
+        }
+    }
+}
+
+impl AsyncWrite for KtlsStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().io).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let fd = this.io.as_raw_fd();
+
+        while !this.close_notify_sent {
+            ready!(this.io.poll_write_ready(cx))?;
+            match this.io.try_io(Interest::WRITABLE, || {
+                send_record(fd, RECORD_ALERT, &[1, ALERT_CLOSE_NOTIFY])
+            }) {
+                Ok(_) => this.close_notify_sent = true,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                Err(e) => return Poll::Ready(Err(e)),
+            }
+        }
+
+        Pin::new(&mut this.io).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustls::crypto::cipher::{AeadKey, Iv};
+
+    fn iv(bytes: [u8; 12]) -> Iv {
+        Iv::from(bytes)
+    }
+
+    #[test]
+    fn test_crypto_info_aes_gcm_splits_salt() {
+        let secrets = ConnectionTrafficSecrets::Aes256Gcm {
+            key: AeadKey::from([7u8; 32]),
+            iv: iv([1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12]),
+        };
+
+        let info = CryptoInfo::new(libc::TLS_1_3_VERSION, 5, &secrets).unwrap();
+        let CryptoInfo::AesGcm256(info) = info else {
+            panic!("expected AES-256-GCM");
+        };
+        assert_eq!(info.info.version, libc::TLS_1_3_VERSION);
+        assert_eq!(info.info.cipher_type, libc::TLS_CIPHER_AES_GCM_256);
+        assert_eq!(info.salt, [1, 2, 3, 4]);
+        assert_eq!(info.iv, [5, 6, 7, 8, 9, 10, 11, 12]);
+        assert_eq!(info.key, [7u8; 32]);
+        assert_eq!(info.rec_seq, 5u64.to_be_bytes());
+    }
+
+    #[test]
+    fn test_crypto_info_chacha_keeps_full_iv() {
+        let secrets = ConnectionTrafficSecrets::Chacha20Poly1305 {
+            key: AeadKey::from([9u8; 32]),
+            iv: iv([3u8; 12]),
+        };
+
+        let info = CryptoInfo::new(libc::TLS_1_2_VERSION, 0, &secrets).unwrap();
+        let CryptoInfo::Chacha20Poly1305(info) = info else {
+            panic!("expected ChaCha20-Poly1305");
+        };
+        assert_eq!(info.info.cipher_type, libc::TLS_CIPHER_CHACHA20_POLY1305);
+        assert_eq!(info.iv, [3u8; 12]);
+        assert_eq!(info.key, [9u8; 32]);
+    }
+
+    #[test]
+    fn test_supported_suites() {
+        assert!(is_supported_suite(CipherSuite::TLS13_AES_256_GCM_SHA384));
+        assert!(is_supported_suite(
+            CipherSuite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256
+        ));
+        assert!(!is_supported_suite(
+            CipherSuite::TLS_RSA_WITH_AES_128_CBC_SHA
+        ));
+    }
+
+    #[test]
+    fn test_post_handshake_messages() {
+        let ticket = [HANDSHAKE_NEW_SESSION_TICKET, 0, 0, 2, 0xaa, 0xbb];
+        assert!(check_post_handshake(&ticket).is_ok());
+
+        let key_update = [HANDSHAKE_KEY_UPDATE, 0, 0, 1, 0];
+        let err = check_post_handshake(&key_update).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+
+        let hello_request = [0, 0, 0, 0];
+        assert!(check_post_handshake(&hello_request).is_err());
+    }
+
+    #[test]
+    fn test_alerts() {
+        assert!(check_alert(&[1, ALERT_CLOSE_NOTIFY]).is_ok());
+        let err = check_alert(&[2, 40]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionAborted);
+        assert!(check_alert(&[1]).is_err());
+    }
+}
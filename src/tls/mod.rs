@@ -3,21 +3,49 @@
 //! This module provides TLS/SSL support for secure WebSocket (wss://) connections.
 //! Multiple TLS backends are supported:
 //!
-//! - **rustls** (feature `tls-rustls`): Pure Rust TLS implementation
+//! - **rustls** (feature `tls-rustls`): Pure Rust TLS implementation, with
+//!   its crypto from `rustls-aws-lc-rs`, `rustls-ring` or a process-wide
+//!   `CryptoProvider` the application installs
 //! - **native-tls** (feature `tls-native`): Platform-native TLS (OpenSSL/Schannel/Security.framework)
+//! - **OpenSSL** (feature `tls-openssl`): OpenSSL directly, for deployments that
+//!   require it (e.g. FIPS-validated builds)
+//!
+//! On Linux, the `ktls` feature lets rustls streams hand record encryption to
+//! the kernel once the handshake is done.
 
 #[cfg(feature = "tls-rustls")]
 mod rustls_impl;
 
+#[cfg(feature = "tls-rustls")]
+mod ocsp;
+
 #[cfg(feature = "tls-native")]
 mod native;
 
+#[cfg(feature = "tls-openssl")]
+mod openssl_impl;
+
+#[cfg(all(feature = "ktls", target_os = "linux"))]
+mod ktls;
+
 #[cfg(feature = "tls-rustls")]
 pub use rustls_impl::{
-    TlsAcceptor, TlsConnector, TlsError, TlsStream, load_certs_from_file,
+    SniCertificates, TlsAcceptor, TlsConnector, TlsError, TlsStream, load_certs_from_file,
     load_private_key_from_file,
 };
 
+#[cfg(feature = "tls-rustls")]
+pub use ocsp::OcspStapler;
+
+#[cfg(all(feature = "ktls", target_os = "linux"))]
+pub use ktls::KtlsStream;
+
+#[cfg(feature = "tls-openssl")]
+pub use openssl_impl::{
+    OpensslAcceptor, OpensslConnector, OpensslError, OpensslStream,
+    openssl_acceptor_from_pem_files, openssl_client_connector,
+};
+
 #[cfg(feature = "tls-native")]
 pub use native::{
     NativeTlsAcceptor, NativeTlsConnector, NativeTlsError, NativeTlsStream,
@@ -30,14 +58,165 @@ use std::sync::Arc;
 #[cfg(feature = "tls-rustls")]
 use tokio_rustls::rustls::{ClientConfig, ServerConfig};
 
+/// TLS session resumption settings for the rustls config builders.
+///
+/// Resumed handshakes skip certificate exchange and key agreement, which
+/// matters for clients that reconnect frequently.
+#[cfg(feature = "tls-rustls")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionResumption {
+    /// Number of sessions the client caches in memory. `0` disables
+    /// client-side resumption.
+    ///
+    /// Default: 256
+    pub client_cache_size: usize,
+
+    /// Number of sessions the server caches in memory for stateful
+    /// resumption. `0` disables the server session cache.
+    ///
+    /// Default: 256
+    pub server_cache_size: usize,
+
+    /// Issue stateless session tickets from the server. Needs the
+    /// `rustls-aws-lc-rs` or `rustls-ring` feature for the ticket keys.
+    ///
+    /// Default: false
+    pub server_tickets: bool,
+}
+
+#[cfg(feature = "tls-rustls")]
+impl Default for SessionResumption {
+    fn default() -> Self {
+        Self {
+            client_cache_size: 256,
+            server_cache_size: 256,
+            server_tickets: false,
+        }
+    }
+}
+
+#[cfg(feature = "tls-rustls")]
+impl SessionResumption {
+    /// Create resumption settings with default values.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Disable session resumption on both sides.
+    #[must_use]
+    pub fn disabled() -> Self {
+        Self {
+            client_cache_size: 0,
+            server_cache_size: 0,
+            server_tickets: false,
+        }
+    }
+
+    /// Set the client session cache size.
+    #[must_use]
+    pub fn with_client_cache_size(mut self, size: usize) -> Self {
+        self.client_cache_size = size;
+        self
+    }
+
+    /// Set the server session cache size.
+    #[must_use]
+    pub fn with_server_cache_size(mut self, size: usize) -> Self {
+        self.server_cache_size = size;
+        self
+    }
+
+    /// Enable or disable stateless server session tickets.
+    #[must_use]
+    pub fn with_server_tickets(mut self, enabled: bool) -> Self {
+        self.server_tickets = enabled;
+        self
+    }
+
+    /// Apply the client-side settings to an existing `ClientConfig`.
+    pub fn apply_to_client(&self, config: &mut ClientConfig) {
+        config.resumption = if self.client_cache_size == 0 {
+            rustls::client::Resumption::disabled()
+        } else {
+            rustls::client::Resumption::in_memory_sessions(self.client_cache_size)
+        };
+    }
+
+    /// Apply the server-side settings to an existing `ServerConfig`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `TlsError::Configuration` if the ticket key generator fails,
+    /// or tickets are enabled without a crypto backend feature.
+    pub fn apply_to_server(&self, config: &mut ServerConfig) -> Result<(), TlsError> {
+        config.session_storage = if self.server_cache_size == 0 {
+            Arc::new(rustls::server::NoServerSessionStorage {})
+        } else {
+            rustls::server::ServerSessionMemoryCache::new(self.server_cache_size)
+        };
+
+        if self.server_tickets {
+            config.ticketer = ticketer()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The crypto provider for building keys: the process default if one is
+/// installed, else the backend enabled by the crate features.
+#[cfg(feature = "tls-rustls")]
+pub(crate) fn crypto_provider() -> Result<Arc<rustls::crypto::CryptoProvider>, TlsError> {
+    if let Some(provider) = rustls::crypto::CryptoProvider::get_default() {
+        return Ok(Arc::clone(provider));
+    }
+    #[cfg(feature = "rustls-aws-lc-rs")]
+    return Ok(Arc::new(rustls::crypto::aws_lc_rs::default_provider()));
+    #[cfg(all(feature = "rustls-ring", not(feature = "rustls-aws-lc-rs")))]
+    return Ok(Arc::new(rustls::crypto::ring::default_provider()));
+    #[cfg(not(any(feature = "rustls-aws-lc-rs", feature = "rustls-ring")))]
+    Err(TlsError::Configuration(
+        "no rustls crypto provider installed; enable rustls-aws-lc-rs or rustls-ring".to_string(),
+    ))
+}
+
+/// Session ticket keys from the backend enabled by the crate features.
+/// `CryptoProvider` has no ticketer, so an installed default cannot be used.
+#[cfg(feature = "tls-rustls")]
+fn ticketer() -> Result<Arc<dyn rustls::server::ProducesTickets>, TlsError> {
+    #[cfg(feature = "rustls-aws-lc-rs")]
+    let ticketer = rustls::crypto::aws_lc_rs::Ticketer::new();
+    #[cfg(all(feature = "rustls-ring", not(feature = "rustls-aws-lc-rs")))]
+    let ticketer = rustls::crypto::ring::Ticketer::new();
+    #[cfg(not(any(feature = "rustls-aws-lc-rs", feature = "rustls-ring")))]
+    let ticketer = Err(rustls::Error::General(
+        "session tickets need rustls-aws-lc-rs or rustls-ring".to_string(),
+    ));
+    ticketer.map_err(|e| TlsError::Configuration(e.to_string()))
+}
+
 #[cfg(feature = "tls-rustls")]
 pub fn client_config_with_native_roots() -> Result<Arc<ClientConfig>, TlsError> {
+    client_config_with_resumption(&SessionResumption::default())
+}
+
+/// Build a client config with webpki roots and the given resumption settings.
+///
+/// # Errors
+///
+/// Currently infallible; returns `Result` for parity with the other builders.
+#[cfg(feature = "tls-rustls")]
+pub fn client_config_with_resumption(
+    resumption: &SessionResumption,
+) -> Result<Arc<ClientConfig>, TlsError> {
     let root_store =
         rustls::RootCertStore::from_iter(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
 
-    let config = ClientConfig::builder()
+    let mut config = ClientConfig::builder()
         .with_root_certificates(root_store)
         .with_no_client_auth();
+    resumption.apply_to_client(&mut config);
 
     Ok(Arc::new(config))
 }
@@ -47,10 +226,70 @@ pub fn server_config(
     cert_chain: Vec<rustls::pki_types::CertificateDer<'static>>,
     private_key: rustls::pki_types::PrivateKeyDer<'static>,
 ) -> Result<Arc<ServerConfig>, TlsError> {
-    let config = ServerConfig::builder()
+    server_config_with_resumption(cert_chain, private_key, &SessionResumption::default())
+}
+
+/// Build a server config with the given resumption settings.
+///
+/// # Errors
+///
+/// Returns `TlsError::Configuration` if the certificate/key pair is invalid
+/// or the ticket key generator fails.
+#[cfg(feature = "tls-rustls")]
+pub fn server_config_with_resumption(
+    cert_chain: Vec<rustls::pki_types::CertificateDer<'static>>,
+    private_key: rustls::pki_types::PrivateKeyDer<'static>,
+    resumption: &SessionResumption,
+) -> Result<Arc<ServerConfig>, TlsError> {
+    let mut config = ServerConfig::builder()
         .with_no_client_auth()
         .with_single_cert(cert_chain, private_key)
         .map_err(|e| TlsError::Configuration(e.to_string()))?;
+    resumption.apply_to_server(&mut config)?;
+
+    Ok(Arc::new(config))
+}
+
+/// Build a server config that staples `ocsp_response` to the certificate.
+///
+/// The response is fixed for the life of the config; use [`OcspStapler`] to
+/// refresh it.
+///
+/// # Errors
+///
+/// Returns `TlsError::Configuration` if the certificate/key pair is invalid
+/// or the ticket key generator fails.
+#[cfg(feature = "tls-rustls")]
+pub fn server_config_with_ocsp(
+    cert_chain: Vec<rustls::pki_types::CertificateDer<'static>>,
+    private_key: rustls::pki_types::PrivateKeyDer<'static>,
+    ocsp_response: Vec<u8>,
+    resumption: &SessionResumption,
+) -> Result<Arc<ServerConfig>, TlsError> {
+    let mut config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert_with_ocsp(cert_chain, private_key, ocsp_response)
+        .map_err(|e| TlsError::Configuration(e.to_string()))?;
+    resumption.apply_to_server(&mut config)?;
+
+    Ok(Arc::new(config))
+}
+
+/// Build a server config that picks its certificate per connection, e.g.
+/// from [`SniCertificates`].
+///
+/// # Errors
+///
+/// Returns `TlsError::Configuration` if the ticket key generator fails.
+#[cfg(feature = "tls-rustls")]
+pub fn server_config_with_cert_resolver(
+    resolver: Arc<dyn rustls::server::ResolvesServerCert>,
+    resumption: &SessionResumption,
+) -> Result<Arc<ServerConfig>, TlsError> {
+    let mut config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_cert_resolver(resolver);
+    resumption.apply_to_server(&mut config)?;
 
     Ok(Arc::new(config))
 }
@@ -98,17 +98,40 @@ impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for NativeTlsStream<S> {
     }
 }
 
+/// Strip the brackets from a URL-style IPv6 literal (`[::1]` -> `::1`).
+fn strip_ipv6_brackets(name: &str) -> &str {
+    name.strip_prefix('[')
+        .and_then(|n| n.strip_suffix(']'))
+        .unwrap_or(name)
+}
+
 pub struct NativeTlsConnector {
     inner: tokio_native_tls::TlsConnector,
+    server_name: Option<String>,
 }
 
 impl NativeTlsConnector {
     pub fn new(connector: native_tls::TlsConnector) -> Self {
         Self {
             inner: tokio_native_tls::TlsConnector::from(connector),
+            server_name: None,
         }
     }
 
+    /// Override the name used for SNI and certificate verification.
+    ///
+    /// By default the `domain` passed to [`connect`](Self::connect) is used.
+    /// native-tls does not send SNI for IP literals.
+    pub fn with_server_name(mut self, name: impl Into<String>) -> Self {
+        self.server_name = Some(name.into());
+        self
+    }
+
+    /// Get the configured SNI override, if any.
+    pub fn server_name(&self) -> Option<&str> {
+        self.server_name.as_deref()
+    }
+
     pub async fn connect<S>(
         &self,
         domain: &str,
@@ -117,6 +140,8 @@ impl NativeTlsConnector {
     where
         S: AsyncRead + AsyncWrite + Unpin,
     {
+        let domain = strip_ipv6_brackets(self.server_name.as_deref().unwrap_or(domain));
+
         let tls_stream = self
             .inner
             .connect(domain, stream)
@@ -251,6 +276,22 @@ mod tests {
         assert!(matches!(tls_err, NativeTlsError::Io(_)));
     }
 
+    #[test]
+    fn test_strip_ipv6_brackets() {
+        assert_eq!(strip_ipv6_brackets("[::1]"), "::1");
+        assert_eq!(strip_ipv6_brackets("::1"), "::1");
+        assert_eq!(strip_ipv6_brackets("example.com"), "example.com");
+    }
+
+    #[test]
+    fn test_connector_server_name_override() {
+        let connector = NativeTlsConnector::new(native_tls::TlsConnector::new().unwrap());
+        assert!(connector.server_name().is_none());
+
+        let connector = connector.with_server_name("backend.internal");
+        assert_eq!(connector.server_name(), Some("backend.internal"));
+    }
+
     #[test]
     fn test_load_identity_pkcs12_file_not_found() {
         let result =
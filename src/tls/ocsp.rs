@@ -0,0 +1,170 @@
+//! OCSP stapling for rustls servers.
+//!
+//! A stapled OCSP response lets clients check revocation without contacting
+//! the certificate authority themselves. Responses expire, so long-running
+//! servers should refresh them; [`OcspStapler`] holds the current response
+//! and can fetch a new one on an interval:
+//!
+//! ```rust,ignore
+//! let stapler = Arc::new(OcspStapler::new(certs, key, initial_response)?);
+//! stapler.refresh_every(Duration::from_secs(3600), || async {
+//!     fetch_ocsp_response().await.ok()
+//! });
+//! let config = server_config_with_cert_resolver(stapler, &SessionResumption::new())?;
+//! ```
+
+use std::fmt;
+use std::future::Future;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use tokio::task::JoinHandle;
+
+use super::TlsError;
+use super::rustls_impl::certified_key;
+use crate::task;
+
+/// A server certificate whose stapled OCSP response can be replaced while
+/// the server runs.
+///
+/// Use it as the certificate resolver of a server config, e.g. with
+/// [`server_config_with_cert_resolver`](super::server_config_with_cert_resolver).
+/// Handshakes already in progress keep the response they started with.
+pub struct OcspStapler {
+    current: Mutex<Arc<CertifiedKey>>,
+}
+
+impl OcspStapler {
+    /// Serve `cert_chain` with `ocsp_response` stapled. An empty response
+    /// staples nothing until [`update`](Self::update) is called.
+    ///
+    /// # Errors
+    ///
+    /// Returns `TlsError::Configuration` if the key does not match the
+    /// certificate.
+    pub fn new(
+        cert_chain: Vec<CertificateDer<'static>>,
+        private_key: PrivateKeyDer<'static>,
+        ocsp_response: Vec<u8>,
+    ) -> Result<Self, TlsError> {
+        let mut key = certified_key(cert_chain, private_key)?;
+        key.ocsp = (!ocsp_response.is_empty()).then_some(ocsp_response);
+        Ok(Self {
+            current: Mutex::new(Arc::new(key)),
+        })
+    }
+
+    /// Staple `ocsp_response` to new handshakes. An empty response stops
+    /// stapling.
+    pub fn update(&self, ocsp_response: Vec<u8>) {
+        let mut current = self.lock();
+        let mut key = CertifiedKey::clone(&current);
+        key.ocsp = (!ocsp_response.is_empty()).then_some(ocsp_response);
+        *current = Arc::new(key);
+    }
+
+    /// The response currently stapled, if any.
+    pub fn ocsp_response(&self) -> Option<Vec<u8>> {
+        self.lock().ocsp.clone()
+    }
+
+    /// Spawn a task that calls `fetch` every `interval` and staples the
+    /// response it returns. `None` keeps the previous response, so a failed
+    /// fetch does not drop a still-valid staple.
+    ///
+    /// The first fetch happens after one `interval`. The task ends once the
+    /// stapler is dropped, or when the returned handle is aborted.
+    pub fn refresh_every<F, Fut>(
+        self: &Arc<Self>,
+        interval: Duration,
+        mut fetch: F,
+    ) -> JoinHandle<()>
+    where
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = Option<Vec<u8>>> + Send,
+    {
+        let stapler: Weak<Self> = Arc::downgrade(self);
+        task::spawn(task::OCSP_REFRESH, async move {
+            let start = tokio::time::Instant::now() + interval;
+            let mut ticks = tokio::time::interval_at(start, interval);
+            loop {
+                ticks.tick().await;
+                if stapler.strong_count() == 0 {
+                    return;
+                }
+                let Some(response) = fetch().await else {
+                    ws_debug!("OCSP refresh returned no response; keeping the current one");
+                    continue;
+                };
+                match stapler.upgrade() {
+                    Some(stapler) => stapler.update(response),
+                    None => return,
+                }
+            }
+        })
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Arc<CertifiedKey>> {
+        // Only ever holds a fully built key
+        self.current.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl fmt::Debug for OcspStapler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OcspStapler")
+            .field("stapled", &self.lock().ocsp.is_some())
+            .finish()
+    }
+}
+
+impl ResolvesServerCert for OcspStapler {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(Arc::clone(&self.lock()))
+    }
+}
+
+#[cfg(all(test, any(feature = "rustls-aws-lc-rs", feature = "rustls-ring")))]
+mod tests {
+    use super::*;
+
+    fn stapler(ocsp_response: &[u8]) -> Arc<OcspStapler> {
+        let rcgen::CertifiedKey { cert, key_pair } =
+            rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let certs = vec![CertificateDer::from(cert.der().to_vec())];
+        let key = PrivateKeyDer::Pkcs8(key_pair.serialize_der().into());
+        Arc::new(OcspStapler::new(certs, key, ocsp_response.to_vec()).unwrap())
+    }
+
+    #[test]
+    fn test_update() {
+        let stapler = stapler(b"");
+        assert_eq!(stapler.ocsp_response(), None);
+        stapler.update(b"first".to_vec());
+        assert_eq!(stapler.ocsp_response().as_deref(), Some(&b"first"[..]));
+        stapler.update(Vec::new());
+        assert_eq!(stapler.ocsp_response(), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_refresh_every() {
+        let stapler = stapler(b"initial");
+        let mut responses = vec![None, Some(b"refreshed".to_vec())].into_iter();
+        let task = stapler.refresh_every(Duration::from_secs(60), move || {
+            let next = responses.next().flatten();
+            async move { next }
+        });
+
+        tokio::time::sleep(Duration::from_secs(61)).await;
+        assert_eq!(stapler.ocsp_response().as_deref(), Some(&b"initial"[..]));
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        assert_eq!(stapler.ocsp_response().as_deref(), Some(&b"refreshed"[..]));
+
+        drop(stapler);
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        assert!(task.is_finished());
+    }
+}
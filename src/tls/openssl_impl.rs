@@ -0,0 +1,299 @@
+use std::path::Path;
+use std::pin::Pin;
+
+use openssl::error::ErrorStack;
+use openssl::ssl::{SslAcceptor, SslConnector, SslMethod};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_openssl::SslStream;
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum OpensslError {
+    Io(std::io::Error),
+    Ssl(openssl::ssl::Error),
+    Configuration(ErrorStack),
+    NoCertificatesFound,
+    NoPrivateKeyFound,
+}
+
+impl std::fmt::Display for OpensslError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OpensslError::Io(e) => write!(f, "TLS I/O error: {}", e),
+            OpensslError::Ssl(e) => write!(f, "TLS error: {}", e),
+            OpensslError::Configuration(e) => write!(f, "TLS configuration error: {}", e),
+            OpensslError::NoCertificatesFound => write!(f, "no certificates found in file"),
+            OpensslError::NoPrivateKeyFound => write!(f, "no private key found in file"),
+        }
+    }
+}
+
+impl std::error::Error for OpensslError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            OpensslError::Io(e) => Some(e),
+            OpensslError::Ssl(e) => Some(e),
+            OpensslError::Configuration(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for OpensslError {
+    fn from(err: std::io::Error) -> Self {
+        OpensslError::Io(err)
+    }
+}
+
+impl From<ErrorStack> for OpensslError {
+    fn from(err: ErrorStack) -> Self {
+        OpensslError::Configuration(err)
+    }
+}
+
+impl From<openssl::ssl::Error> for OpensslError {
+    fn from(err: openssl::ssl::Error) -> Self {
+        OpensslError::Ssl(err)
+    }
+}
+
+pub enum OpensslStream<S> {
+    Client(SslStream<S>),
+    Server(SslStream<S>),
+}
+
+impl<S> OpensslStream<S> {
+    /// Get the underlying OpenSSL session, e.g. to inspect the negotiated
+    /// cipher or peer certificate.
+    pub fn ssl(&self) -> &openssl::ssl::SslRef {
+        match self {
+            OpensslStream::Client(s) | OpensslStream::Server(s) => s.ssl(),
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for OpensslStream<S> {
+    fn poll_read(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            OpensslStream::Client(s) => std::pin::Pin::new(s).poll_read(cx, buf),
+            OpensslStream::Server(s) => std::pin::Pin::new(s).poll_read(cx, buf),
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for OpensslStream<S> {
+    fn poll_write(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> std::task::Poll<std::io::Result<usize>> {
+        match self.get_mut() {
+            OpensslStream::Client(s) => std::pin::Pin::new(s).poll_write(cx, buf),
+            OpensslStream::Server(s) => std::pin::Pin::new(s).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            OpensslStream::Client(s) => std::pin::Pin::new(s).poll_flush(cx),
+            OpensslStream::Server(s) => std::pin::Pin::new(s).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            OpensslStream::Client(s) => std::pin::Pin::new(s).poll_shutdown(cx),
+            OpensslStream::Server(s) => std::pin::Pin::new(s).poll_shutdown(cx),
+        }
+    }
+}
+
+/// Strip the brackets from a URL-style IPv6 literal (`[::1]` -> `::1`).
+fn strip_ipv6_brackets(name: &str) -> &str {
+    name.strip_prefix('[')
+        .and_then(|n| n.strip_suffix(']'))
+        .unwrap_or(name)
+}
+
+pub struct OpensslConnector {
+    inner: SslConnector,
+    server_name: Option<String>,
+}
+
+impl OpensslConnector {
+    pub fn new(connector: SslConnector) -> Self {
+        Self {
+            inner: connector,
+            server_name: None,
+        }
+    }
+
+    /// Override the name used for SNI and certificate verification.
+    ///
+    /// By default the `domain` passed to [`connect`](Self::connect) is used.
+    /// IP literals are verified against the certificate's IP SANs and do not
+    /// send SNI.
+    pub fn with_server_name(mut self, name: impl Into<String>) -> Self {
+        self.server_name = Some(name.into());
+        self
+    }
+
+    /// Get the configured SNI override, if any.
+    pub fn server_name(&self) -> Option<&str> {
+        self.server_name.as_deref()
+    }
+
+    pub async fn connect<S>(
+        &self,
+        domain: &str,
+        stream: S,
+    ) -> Result<OpensslStream<S>, OpensslError>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let domain = strip_ipv6_brackets(self.server_name.as_deref().unwrap_or(domain));
+
+        let ssl = self.inner.configure()?.into_ssl(domain)?;
+        let mut tls_stream = SslStream::new(ssl, stream)?;
+        Pin::new(&mut tls_stream).connect().await?;
+
+        Ok(OpensslStream::Client(tls_stream))
+    }
+}
+
+pub struct OpensslAcceptor {
+    inner: SslAcceptor,
+}
+
+impl OpensslAcceptor {
+    pub fn new(acceptor: SslAcceptor) -> Self {
+        Self { inner: acceptor }
+    }
+
+    pub async fn accept<S>(&self, stream: S) -> Result<OpensslStream<S>, OpensslError>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let ssl = openssl::ssl::Ssl::new(self.inner.context())?;
+        let mut tls_stream = SslStream::new(ssl, stream)?;
+        Pin::new(&mut tls_stream).accept().await?;
+
+        Ok(OpensslStream::Server(tls_stream))
+    }
+}
+
+/// Build a client connector that verifies peers against the system trust
+/// store.
+pub fn openssl_client_connector() -> Result<SslConnector, OpensslError> {
+    Ok(SslConnector::builder(SslMethod::tls_client())?.build())
+}
+
+/// Build a server acceptor from PEM certificate chain and private key files,
+/// using Mozilla's intermediate TLS profile.
+pub fn openssl_acceptor_from_pem_files(
+    cert_path: &Path,
+    key_path: &Path,
+) -> Result<SslAcceptor, OpensslError> {
+    let cert_pem = std::fs::read(cert_path)?;
+    let key_pem = std::fs::read(key_path)?;
+
+    let chain = openssl::x509::X509::stack_from_pem(&cert_pem)?;
+    let mut chain = chain.into_iter();
+    let leaf = chain.next().ok_or(OpensslError::NoCertificatesFound)?;
+    let key = openssl::pkey::PKey::private_key_from_pem(&key_pem)
+        .map_err(|_| OpensslError::NoPrivateKeyFound)?;
+
+    let mut builder = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls_server())?;
+    builder.set_certificate(&leaf)?;
+    for cert in chain {
+        builder.add_extra_chain_cert(cert)?;
+    }
+    builder.set_private_key(&key)?;
+    builder.check_private_key()?;
+
+    Ok(builder.build())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_openssl_error_display() {
+        let io_err = OpensslError::Io(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "file not found",
+        ));
+        assert!(io_err.to_string().contains("TLS I/O error"));
+
+        let no_certs = OpensslError::NoCertificatesFound;
+        assert!(no_certs.to_string().contains("no certificates found"));
+
+        let no_key = OpensslError::NoPrivateKeyFound;
+        assert!(no_key.to_string().contains("no private key found"));
+    }
+
+    #[test]
+    fn test_openssl_error_source() {
+        let io_err = OpensslError::Io(std::io::Error::new(std::io::ErrorKind::NotFound, "test"));
+        assert!(io_err.source().is_some());
+
+        let no_certs = OpensslError::NoCertificatesFound;
+        assert!(no_certs.source().is_none());
+    }
+
+    #[test]
+    fn test_io_error_conversion() {
+        let io_err = std::io::Error::new(std::io::ErrorKind::PermissionDenied, "access denied");
+        let tls_err: OpensslError = io_err.into();
+        assert!(matches!(tls_err, OpensslError::Io(_)));
+    }
+
+    #[test]
+    fn test_strip_ipv6_brackets() {
+        assert_eq!(strip_ipv6_brackets("[::1]"), "::1");
+        assert_eq!(strip_ipv6_brackets("example.com"), "example.com");
+    }
+
+    #[test]
+    fn test_connector_server_name_override() {
+        let connector = OpensslConnector::new(openssl_client_connector().unwrap());
+        assert!(connector.server_name().is_none());
+
+        let connector = connector.with_server_name("backend.internal");
+        assert_eq!(connector.server_name(), Some("backend.internal"));
+    }
+
+    #[test]
+    fn test_acceptor_from_pem_files_not_found() {
+        let result = openssl_acceptor_from_pem_files(
+            Path::new("/nonexistent/cert.pem"),
+            Path::new("/nonexistent/key.pem"),
+        );
+        assert!(matches!(result, Err(OpensslError::Io(_))));
+    }
+
+    #[test]
+    fn test_acceptor_from_pem_files_no_certs() {
+        let mut cert_temp = NamedTempFile::new().unwrap();
+        cert_temp.write_all(b"not a cert").unwrap();
+        cert_temp.flush().unwrap();
+        let key_temp = NamedTempFile::new().unwrap();
+
+        let result = openssl_acceptor_from_pem_files(cert_temp.path(), key_temp.path());
+        assert!(matches!(result, Err(OpensslError::NoCertificatesFound)));
+    }
+}
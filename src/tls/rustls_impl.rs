@@ -1,12 +1,20 @@
 use std::fs::File;
 use std::io::BufReader;
+use std::net::IpAddr;
 use std::path::Path;
 use std::sync::Arc;
 
 use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::server::{ClientHello, ResolvesServerCert, ResolvesServerCertUsingSni};
+use rustls::sign::CertifiedKey;
 use tokio::io::{AsyncRead, AsyncWrite};
+#[cfg(all(feature = "ktls", target_os = "linux"))]
+use tokio::net::TcpStream;
 use tokio_rustls::rustls::{ClientConfig, ServerConfig};
 
+#[cfg(all(feature = "ktls", target_os = "linux"))]
+use super::ktls::{self, KtlsStream};
+
 #[derive(Debug)]
 pub enum TlsError {
     Io(std::io::Error),
@@ -48,6 +56,63 @@ impl From<std::io::Error> for TlsError {
 pub enum TlsStream<S> {
     Client(tokio_rustls::client::TlsStream<S>),
     Server(tokio_rustls::server::TlsStream<S>),
+    /// Record layer offloaded to the kernel; see [`KtlsStream`].
+    #[cfg(all(feature = "ktls", target_os = "linux"))]
+    Ktls(KtlsStream),
+}
+
+impl<S> TlsStream<S> {
+    /// Returns `true` if record encryption runs in the kernel.
+    pub fn is_ktls(&self) -> bool {
+        #[cfg(all(feature = "ktls", target_os = "linux"))]
+        if let TlsStream::Ktls(_) = self {
+            return true;
+        }
+        false
+    }
+}
+
+#[cfg(all(feature = "ktls", target_os = "linux"))]
+impl TlsStream<TcpStream> {
+    /// Move the record layer into the kernel, keeping the userspace stream
+    /// when the cipher suite or kernel does not support it.
+    ///
+    /// A failure after the socket was handed to the kernel drops the
+    /// connection.
+    async fn offload(mut self) -> Result<Self, TlsError> {
+        // Send whatever rustls queued, such as TLS 1.3 session tickets
+        tokio::io::AsyncWriteExt::flush(&mut self)
+            .await
+            .map_err(TlsError::Io)?;
+
+        let ready = match &mut self {
+            TlsStream::Client(s) => {
+                let (io, conn) = s.get_mut();
+                ktls::prepare(io, conn)?
+            }
+            TlsStream::Server(s) => {
+                let (io, conn) = s.get_mut();
+                ktls::prepare(io, conn)?
+            }
+            TlsStream::Ktls(_) => false,
+        };
+        if !ready {
+            return Ok(self);
+        }
+
+        let stream = match self {
+            TlsStream::Client(s) => {
+                let (io, conn) = s.into_inner();
+                KtlsStream::new(io, conn.into())?
+            }
+            TlsStream::Server(s) => {
+                let (io, conn) = s.into_inner();
+                KtlsStream::new(io, conn.into())?
+            }
+            TlsStream::Ktls(s) => s,
+        };
+        Ok(TlsStream::Ktls(stream))
+    }
 }
 
 impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for TlsStream<S> {
@@ -59,6 +124,8 @@ impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for TlsStream<S> {
         match self.get_mut() {
             TlsStream::Client(s) => std::pin::Pin::new(s).poll_read(cx, buf),
             TlsStream::Server(s) => std::pin::Pin::new(s).poll_read(cx, buf),
+            #[cfg(all(feature = "ktls", target_os = "linux"))]
+            TlsStream::Ktls(s) => std::pin::Pin::new(s).poll_read(cx, buf),
         }
     }
 }
@@ -72,6 +139,8 @@ impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for TlsStream<S> {
         match self.get_mut() {
             TlsStream::Client(s) => std::pin::Pin::new(s).poll_write(cx, buf),
             TlsStream::Server(s) => std::pin::Pin::new(s).poll_write(cx, buf),
+            #[cfg(all(feature = "ktls", target_os = "linux"))]
+            TlsStream::Ktls(s) => std::pin::Pin::new(s).poll_write(cx, buf),
         }
     }
 
@@ -82,6 +151,8 @@ impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for TlsStream<S> {
         match self.get_mut() {
             TlsStream::Client(s) => std::pin::Pin::new(s).poll_flush(cx),
             TlsStream::Server(s) => std::pin::Pin::new(s).poll_flush(cx),
+            #[cfg(all(feature = "ktls", target_os = "linux"))]
+            TlsStream::Ktls(s) => std::pin::Pin::new(s).poll_flush(cx),
         }
     }
 
@@ -92,27 +163,95 @@ impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for TlsStream<S> {
         match self.get_mut() {
             TlsStream::Client(s) => std::pin::Pin::new(s).poll_shutdown(cx),
             TlsStream::Server(s) => std::pin::Pin::new(s).poll_shutdown(cx),
+            #[cfg(all(feature = "ktls", target_os = "linux"))]
+            TlsStream::Ktls(s) => std::pin::Pin::new(s).poll_shutdown(cx),
         }
     }
 }
 
+/// Parse a TLS server name, accepting DNS names and IP literals.
+///
+/// IPv6 literals may be given in URL form (`[::1]`). IP addresses produce a
+/// `ServerName::IpAddress`, for which rustls does not send SNI and verifies
+/// the certificate against its IP SANs instead.
+fn parse_server_name(name: &str) -> Result<ServerName<'static>, TlsError> {
+    let trimmed = name
+        .strip_prefix('[')
+        .and_then(|n| n.strip_suffix(']'))
+        .unwrap_or(name);
+
+    if let Ok(ip) = trimmed.parse::<IpAddr>() {
+        return Ok(ServerName::IpAddress(ip.into()));
+    }
+
+    ServerName::try_from(trimmed.to_string())
+        .map_err(|_| TlsError::InvalidDnsName(name.to_string()))
+}
+
 pub struct TlsConnector {
     inner: tokio_rustls::TlsConnector,
+    server_name: Option<ServerName<'static>>,
+    #[cfg(all(feature = "ktls", target_os = "linux"))]
+    ktls: bool,
 }
 
 impl TlsConnector {
     pub fn new(config: Arc<ClientConfig>) -> Self {
         Self {
             inner: tokio_rustls::TlsConnector::from(config),
+            server_name: None,
+            #[cfg(all(feature = "ktls", target_os = "linux"))]
+            ktls: false,
         }
     }
 
+    /// Enable kernel TLS offload for [`connect_ktls`](Self::connect_ktls).
+    ///
+    /// This clones the client config with secret extraction enabled, which
+    /// rustls requires before handing keys to the kernel.
+    #[cfg(all(feature = "ktls", target_os = "linux"))]
+    pub fn with_ktls(mut self) -> Self {
+        let mut config = ClientConfig::clone(self.inner.config());
+        config.enable_secret_extraction = true;
+        self.inner = tokio_rustls::TlsConnector::from(Arc::new(config));
+        self.ktls = true;
+        self
+    }
+
+    /// Override the name used for SNI and certificate verification.
+    ///
+    /// By default the `domain` passed to [`connect`](Self::connect) is used.
+    /// Set this when the TCP target differs from the certificate name, e.g.
+    /// when dialing a load balancer by IP. IP literals disable SNI.
+    ///
+    /// # Errors
+    ///
+    /// Returns `TlsError::InvalidDnsName` if `name` is neither a valid DNS
+    /// name nor an IP address.
+    pub fn with_server_name(mut self, name: &str) -> Result<Self, TlsError> {
+        self.server_name = Some(parse_server_name(name)?);
+        Ok(self)
+    }
+
+    /// Returns `true` if kernel TLS offload is enabled.
+    #[cfg(all(feature = "ktls", target_os = "linux"))]
+    pub fn ktls_enabled(&self) -> bool {
+        self.ktls
+    }
+
+    /// Get the configured SNI override, if any.
+    pub fn server_name(&self) -> Option<&ServerName<'static>> {
+        self.server_name.as_ref()
+    }
+
     pub async fn connect<S>(&self, domain: &str, stream: S) -> Result<TlsStream<S>, TlsError>
     where
         S: AsyncRead + AsyncWrite + Unpin,
     {
-        let server_name = ServerName::try_from(domain.to_string())
-            .map_err(|_| TlsError::InvalidDnsName(domain.to_string()))?;
+        let server_name = match &self.server_name {
+            Some(name) => name.clone(),
+            None => parse_server_name(domain)?,
+        };
 
         let tls_stream = self
             .inner
@@ -122,19 +261,164 @@ impl TlsConnector {
 
         Ok(TlsStream::Client(tls_stream))
     }
+
+    /// Connect over TCP and move the record layer into the kernel.
+    ///
+    /// Falls back to the userspace stream when kTLS was not enabled with
+    /// [`with_ktls`](Self::with_ktls), the kernel has no `tls` module, or the
+    /// negotiated cipher suite has no kernel implementation. Check
+    /// [`TlsStream::is_ktls`] to see which path was taken.
+    #[cfg(all(feature = "ktls", target_os = "linux"))]
+    pub async fn connect_ktls(
+        &self,
+        domain: &str,
+        stream: TcpStream,
+    ) -> Result<TlsStream<TcpStream>, TlsError> {
+        let tls_stream = self.connect(domain, stream).await?;
+        if self.ktls {
+            tls_stream.offload().await
+        } else {
+            Ok(tls_stream)
+        }
+    }
+}
+
+/// Server certificates selected by the name the client sends in SNI.
+///
+/// Lets one listener serve several hostnames, each with its own
+/// certificate. Names match exactly (no wildcards); clients that send no SNI
+/// or an unknown name get the fallback certificate, or fail the handshake
+/// if there is none.
+///
+/// ```rust,ignore
+/// let certs = SniCertificates::new()
+///     .with_certificate("a.example.com", a_chain, a_key)?
+///     .with_certificate("b.example.com", b_chain, b_key)?
+///     .with_fallback(a_chain, a_key)?;
+/// let config = server_config_with_cert_resolver(Arc::new(certs), &SessionResumption::new())?;
+/// let acceptor = TlsAcceptor::new(config);
+/// ```
+#[derive(Debug)]
+pub struct SniCertificates {
+    by_name: ResolvesServerCertUsingSni,
+    fallback: Option<Arc<CertifiedKey>>,
+}
+
+impl Default for SniCertificates {
+    fn default() -> Self {
+        Self {
+            by_name: ResolvesServerCertUsingSni::new(),
+            fallback: None,
+        }
+    }
+}
+
+impl SniCertificates {
+    /// An empty certificate set.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serve `cert_chain` to clients asking for `name`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `TlsError::Configuration` if `name` is not a DNS name, the
+    /// certificate is not valid for it, or the key does not match.
+    pub fn with_certificate(
+        mut self,
+        name: &str,
+        cert_chain: Vec<CertificateDer<'static>>,
+        private_key: PrivateKeyDer<'static>,
+    ) -> Result<Self, TlsError> {
+        let key = certified_key(cert_chain, private_key)?;
+        self.by_name
+            .add(name, key)
+            .map_err(|e| TlsError::Configuration(format!("certificate for {}: {}", name, e)))?;
+        Ok(self)
+    }
+
+    /// Serve `cert_chain` when the client sends no SNI or an unknown name.
+    ///
+    /// # Errors
+    ///
+    /// Returns `TlsError::Configuration` if the key does not match.
+    pub fn with_fallback(
+        mut self,
+        cert_chain: Vec<CertificateDer<'static>>,
+        private_key: PrivateKeyDer<'static>,
+    ) -> Result<Self, TlsError> {
+        self.fallback = Some(Arc::new(certified_key(cert_chain, private_key)?));
+        Ok(self)
+    }
+}
+
+impl ResolvesServerCert for SniCertificates {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        self.by_name
+            .resolve(client_hello)
+            .or_else(|| self.fallback.clone())
+    }
+}
+
+pub(crate) fn certified_key(
+    cert_chain: Vec<CertificateDer<'static>>,
+    private_key: PrivateKeyDer<'static>,
+) -> Result<CertifiedKey, TlsError> {
+    if cert_chain.is_empty() {
+        return Err(TlsError::NoCertificatesFound);
+    }
+    let provider = crate::tls::crypto_provider()?;
+    CertifiedKey::from_der(cert_chain, private_key, &provider)
+        .map_err(|e| TlsError::Configuration(e.to_string()))
 }
 
 pub struct TlsAcceptor {
     inner: tokio_rustls::TlsAcceptor,
+    #[cfg(all(feature = "ktls", target_os = "linux"))]
+    ktls: bool,
 }
 
 impl TlsAcceptor {
     pub fn new(config: Arc<ServerConfig>) -> Self {
         Self {
             inner: tokio_rustls::TlsAcceptor::from(config),
+            #[cfg(all(feature = "ktls", target_os = "linux"))]
+            ktls: false,
         }
     }
 
+    /// Enable kernel TLS offload for [`accept_ktls`](Self::accept_ktls).
+    ///
+    /// The server config is cloned with secret extraction enabled.
+    #[cfg(all(feature = "ktls", target_os = "linux"))]
+    pub fn with_ktls(mut self) -> Self {
+        let mut config = ServerConfig::clone(self.inner.config());
+        config.enable_secret_extraction = true;
+        self.inner = tokio_rustls::TlsAcceptor::from(Arc::new(config));
+        self.ktls = true;
+        self
+    }
+
+    /// Choose the certificate per connection, e.g. by SNI with
+    /// [`SniCertificates`].
+    ///
+    /// The server config is cloned with `resolver` in place of its
+    /// certificate.
+    pub fn with_cert_resolver(mut self, resolver: Arc<dyn ResolvesServerCert>) -> Self {
+        let mut config = ServerConfig::clone(self.inner.config());
+        config.cert_resolver = resolver;
+        self.inner = tokio_rustls::TlsAcceptor::from(Arc::new(config));
+        self
+    }
+
+    /// Returns `true` if kernel TLS offload is enabled.
+    #[cfg(all(feature = "ktls", target_os = "linux"))]
+    pub fn ktls_enabled(&self) -> bool {
+        self.ktls
+    }
+
     pub async fn accept<S>(&self, stream: S) -> Result<TlsStream<S>, TlsError>
     where
         S: AsyncRead + AsyncWrite + Unpin,
@@ -142,6 +426,19 @@ impl TlsAcceptor {
         let tls_stream = self.inner.accept(stream).await.map_err(TlsError::Io)?;
         Ok(TlsStream::Server(tls_stream))
     }
+
+    /// Accept a TCP connection and move the record layer into the kernel.
+    ///
+    /// Same fallback rules as [`TlsConnector::connect_ktls`].
+    #[cfg(all(feature = "ktls", target_os = "linux"))]
+    pub async fn accept_ktls(&self, stream: TcpStream) -> Result<TlsStream<TcpStream>, TlsError> {
+        let tls_stream = self.accept(stream).await?;
+        if self.ktls {
+            tls_stream.offload().await
+        } else {
+            Ok(tls_stream)
+        }
+    }
 }
 
 pub fn load_certs_from_file(path: &Path) -> Result<Vec<CertificateDer<'static>>, TlsError> {
@@ -219,6 +516,52 @@ mod tests {
         assert!(no_certs.source().is_none());
     }
 
+    #[test]
+    fn test_parse_server_name_dns() {
+        let name = parse_server_name("example.com").unwrap();
+        assert!(matches!(name, ServerName::DnsName(_)));
+    }
+
+    #[test]
+    fn test_parse_server_name_ip_literals() {
+        assert!(matches!(
+            parse_server_name("127.0.0.1").unwrap(),
+            ServerName::IpAddress(_)
+        ));
+        assert!(matches!(
+            parse_server_name("::1").unwrap(),
+            ServerName::IpAddress(_)
+        ));
+        assert!(matches!(
+            parse_server_name("[::1]").unwrap(),
+            ServerName::IpAddress(_)
+        ));
+    }
+
+    #[test]
+    fn test_parse_server_name_invalid() {
+        let result = parse_server_name("invalid..name");
+        assert!(matches!(result, Err(TlsError::InvalidDnsName(n)) if n == "invalid..name"));
+    }
+
+    #[test]
+    #[cfg(any(feature = "rustls-aws-lc-rs", feature = "rustls-ring"))]
+    fn test_connector_server_name_override() {
+        let config = crate::tls::client_config_with_native_roots().unwrap();
+        let connector = TlsConnector::new(config.clone());
+        assert!(connector.server_name().is_none());
+
+        let connector = TlsConnector::new(config.clone())
+            .with_server_name("backend.internal")
+            .unwrap();
+        assert!(matches!(
+            connector.server_name(),
+            Some(ServerName::DnsName(_))
+        ));
+
+        assert!(TlsConnector::new(config).with_server_name("bad..").is_err());
+    }
+
     #[test]
     fn test_load_certs_file_not_found() {
         let result = load_certs_from_file(Path::new("/nonexistent/path/cert.pem"));
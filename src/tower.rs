@@ -0,0 +1,230 @@
+//! Serving a [`tower_service::Service`] over a connection.
+//!
+//! Enabled by the `tower` feature. Each data message received is a request
+//! to the service, and the response it returns, if any, is sent back:
+//!
+//! ```rust,ignore
+//! use tower::service_fn;
+//!
+//! let echo = service_fn(|msg: Message| async move { Ok::<_, Infallible>(Some(msg)) });
+//! rsws::tower::Serve::new(echo).with_concurrency(8).run(conn).await?;
+//! ```
+//!
+//! Up to [`with_concurrency`](Serve::with_concurrency) calls run at once,
+//! each on its own task; reading pauses while the limit is reached.
+//! Responses are sent as calls finish, so with a limit above 1 they may
+//! overtake one another and requests should carry an id to match them.
+
+use std::error::Error as StdError;
+use std::future::poll_fn;
+use std::pin::pin;
+use std::task::Poll;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::task::JoinSet;
+use tower_service::Service;
+
+use crate::connection::Connection;
+use crate::error::{Error, Result, Source};
+use crate::message::{CloseCode, Message};
+use crate::task;
+
+/// Serve `service` on `conn` one request at a time.
+///
+/// Shorthand for `Serve::new(service).run(conn)`.
+///
+/// ## Errors
+///
+/// As for [`Serve::run`].
+pub async fn serve<T, S>(conn: Connection<T>, service: S) -> Result<()>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+    S: Service<Message, Response = Option<Message>>,
+    S::Error: Into<Box<dyn StdError + Send + Sync>> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    Serve::new(service).run(conn).await
+}
+
+/// Drives a [`Service`] from the messages of a connection.
+///
+/// Control messages are handled by the connection and never reach the
+/// service.
+#[derive(Debug, Clone)]
+pub struct Serve<S> {
+    service: S,
+    concurrency: usize,
+}
+
+impl<S> Serve<S> {
+    /// Serve with `service`, handling one request at a time.
+    pub fn new(service: S) -> Self {
+        Self {
+            service,
+            concurrency: 1,
+        }
+    }
+
+    /// Run up to `limit` calls at once. A `limit` of 0 is treated as 1.
+    #[must_use]
+    pub fn with_concurrency(mut self, limit: usize) -> Self {
+        self.concurrency = limit.max(1);
+        self
+    }
+}
+
+impl<S> Serve<S>
+where
+    S: Service<Message, Response = Option<Message>>,
+    S::Error: Into<Box<dyn StdError + Send + Sync>> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    /// Serve requests until the peer closes the connection.
+    ///
+    /// Calls still running when the peer closes are cancelled, since their
+    /// responses could no longer be sent.
+    ///
+    /// ## Errors
+    ///
+    /// - [`Error::Service`] if the service fails or a call panics. The
+    ///   connection is closed with [`CloseCode::InternalError`] first.
+    /// - Errors receiving requests or sending responses.
+    pub async fn run<T>(mut self, mut conn: Connection<T>) -> Result<()>
+    where
+        T: AsyncRead + AsyncWrite + Unpin,
+    {
+        let mut calls: JoinSet<std::result::Result<_, S::Error>> = JoinSet::new();
+        loop {
+            let room = calls.len() < self.concurrency;
+            let finished = {
+                let mut readable = pin!(conn.readable());
+                poll_fn(|cx| {
+                    if let Poll::Ready(Some(done)) = calls.poll_join_next(cx) {
+                        return Poll::Ready(Some(done));
+                    }
+                    if room && readable.as_mut().poll(cx).is_ready() {
+                        return Poll::Ready(None);
+                    }
+                    Poll::Pending
+                })
+                .await
+            };
+
+            match finished {
+                Some(Ok(Ok(Some(response)))) => conn.send(response).await?,
+                Some(Ok(Ok(None))) => {}
+                Some(Ok(Err(e))) => return fail(&mut conn, Source::from(e.into())).await,
+                Some(Err(e)) => return fail(&mut conn, Source::new(e)).await,
+                None => match conn.recv().await? {
+                    Some(request) if request.is_data() => {
+                        if let Err(e) = poll_fn(|cx| self.service.poll_ready(cx)).await {
+                            return fail(&mut conn, Source::from(e.into())).await;
+                        }
+                        task::spawn_in(&mut calls, task::TOWER_CALL, self.service.call(request));
+                    }
+                    Some(_) => {}
+                    None => return Ok(()),
+                },
+            }
+        }
+    }
+}
+
+async fn fail<T>(conn: &mut Connection<T>, source: Source) -> Result<()>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    ws_debug!(error = %source, "service failed, closing connection");
+    let _ = conn.close(CloseCode::InternalError, "").await;
+    Err(Error::Service(source))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::{Ready, ready};
+    use std::task::Context;
+
+    use super::*;
+    use crate::config::Config;
+    use crate::connection::Role;
+
+    /// Answers text with its uppercase form, leaves empty text unanswered
+    /// and fails on binary.
+    #[derive(Clone)]
+    struct Upper;
+
+    impl Service<Message> for Upper {
+        type Response = Option<Message>;
+        type Error = Box<dyn StdError + Send + Sync>;
+        type Future = Ready<std::result::Result<Option<Message>, Self::Error>>;
+
+        fn poll_ready(
+            &mut self,
+            _: &mut Context<'_>,
+        ) -> Poll<std::result::Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, msg: Message) -> Self::Future {
+            ready(match msg {
+                Message::Text(text) if text.is_empty() => Ok(None),
+                Message::Text(text) => Ok(Some(Message::text(text.to_uppercase()))),
+                _ => Err("binary not supported".into()),
+            })
+        }
+    }
+
+    fn pair() -> (
+        Connection<tokio::io::DuplexStream>,
+        Connection<tokio::io::DuplexStream>,
+    ) {
+        let (ours, theirs) = tokio::io::duplex(4096);
+        (
+            Connection::new(ours, Role::Server, Config::server()),
+            Connection::new(theirs, Role::Client, Config::client()),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_serve_answers_each_request() {
+        let (conn, mut peer) = pair();
+        let server = tokio::spawn(Serve::new(Upper).with_concurrency(4).run(conn));
+
+        for word in ["a", "b", "c"] {
+            peer.send(Message::text(word)).await.unwrap();
+            let reply = peer.recv().await.unwrap().unwrap();
+            assert_eq!(reply, Message::text(word.to_uppercase()));
+        }
+        peer.close(CloseCode::Normal, "").await.unwrap();
+        assert!(peer.recv().await.unwrap().is_some_and(|m| m.is_control()));
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_service_error_closes_connection() {
+        let (conn, mut peer) = pair();
+        let server = tokio::spawn(serve(conn, Upper));
+
+        peer.send(Message::binary(vec![1])).await.unwrap();
+        assert_eq!(
+            peer.recv().await.unwrap(),
+            Some(Message::close(CloseCode::InternalError, ""))
+        );
+        let err = server.await.unwrap().unwrap_err();
+        assert_eq!(err.kind(), crate::error::ErrorKind::Service);
+    }
+
+    #[tokio::test]
+    async fn test_notifications_get_no_reply() {
+        let (conn, mut peer) = pair();
+        let server = tokio::spawn(serve(conn, Upper));
+
+        peer.send(Message::text("")).await.unwrap();
+        peer.close(CloseCode::Normal, "").await.unwrap();
+        assert_eq!(
+            peer.recv().await.unwrap(),
+            Some(Message::close(CloseCode::Normal, ""))
+        );
+        server.await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,144 @@
+//! Internal diagnostics macros.
+//!
+//! With the `tracing` feature enabled these forward to the matching
+//! [`tracing`](https://docs.rs/tracing) macros. With only the `log` feature
+//! they forward to the [`log`](https://docs.rs/log) macros instead, with the
+//! fields appended to the message as `key=value`. With neither they expand
+//! to nothing, so call sites need no `cfg` of their own. Arguments are not
+//! evaluated unless the event is enabled.
+//!
+//! Call sites use `tracing`'s field syntax: `key = value`, `key = %value`,
+//! `key = ?value`, `%ident`, `?ident` or `ident`, followed by a message
+//! literal.
+
+#[allow(unused_macros)] // only used by `Connection`, which needs `async-tokio`
+macro_rules! ws_trace {
+    ($($arg:tt)+) => {
+        #[cfg(feature = "tracing")]
+        {
+            ::tracing::trace!($($arg)+);
+        }
+        #[cfg(all(feature = "log", not(feature = "tracing")))]
+        {
+            ws_log!(::log::Level::Trace, [] [] $($arg)+);
+        }
+    };
+}
+
+macro_rules! ws_debug {
+    ($($arg:tt)+) => {
+        #[cfg(feature = "tracing")]
+        {
+            ::tracing::debug!($($arg)+);
+        }
+        #[cfg(all(feature = "log", not(feature = "tracing")))]
+        {
+            ws_log!(::log::Level::Debug, [] [] $($arg)+);
+        }
+    };
+}
+
+/// Await `$fut` inside `$span`, a `tracing::Span`. Without the `tracing`
+/// feature the span expression is not compiled and the future is awaited
+/// as is.
+#[allow(unused_macros)] // only used with `async-tokio`
+macro_rules! ws_instrument {
+    ($span:expr, $fut:expr) => {{
+        #[cfg(feature = "tracing")]
+        let output = {
+            let span = $span.clone();
+            ::tracing::Instrument::instrument($fut, span).await
+        };
+        #[cfg(not(feature = "tracing"))]
+        let output = $fut.await;
+        output
+    }};
+}
+
+/// Enter `$span` until the end of the enclosing block. Only for code that
+/// does not await while inside it.
+#[allow(unused_macros)] // only used with `async-tokio`
+macro_rules! ws_enter {
+    ($span:expr) => {
+        #[cfg(feature = "tracing")]
+        let _entered = $span.clone().entered();
+    };
+}
+
+/// Rewrites `tracing` fields into a `log` format string, one field at a time.
+#[cfg(all(feature = "log", not(feature = "tracing")))]
+macro_rules! ws_log {
+    ($level:expr, [$($fmt:tt)*] [$($args:expr,)*] $msg:literal) => {
+        ::log::log!($level, concat!($msg $($fmt)*) $(, $args)*)
+    };
+    ($level:expr, [$($fmt:tt)*] [$($args:expr,)*] $key:ident = %$val:expr, $($rest:tt)+) => {
+        ws_log!($level, [$($fmt)* , " ", stringify!($key), "={}"] [$($args,)* $val,] $($rest)+)
+    };
+    ($level:expr, [$($fmt:tt)*] [$($args:expr,)*] $key:ident = ?$val:expr, $($rest:tt)+) => {
+        ws_log!($level, [$($fmt)* , " ", stringify!($key), "={:?}"] [$($args,)* $val,] $($rest)+)
+    };
+    ($level:expr, [$($fmt:tt)*] [$($args:expr,)*] $key:ident = $val:expr, $($rest:tt)+) => {
+        ws_log!($level, [$($fmt)* , " ", stringify!($key), "={}"] [$($args,)* $val,] $($rest)+)
+    };
+    ($level:expr, [$($fmt:tt)*] [$($args:expr,)*] %$key:ident, $($rest:tt)+) => {
+        ws_log!($level, [$($fmt)* , " ", stringify!($key), "={}"] [$($args,)* $key,] $($rest)+)
+    };
+    ($level:expr, [$($fmt:tt)*] [$($args:expr,)*] ?$key:ident, $($rest:tt)+) => {
+        ws_log!($level, [$($fmt)* , " ", stringify!($key), "={:?}"] [$($args,)* $key,] $($rest)+)
+    };
+    ($level:expr, [$($fmt:tt)*] [$($args:expr,)*] $key:ident, $($rest:tt)+) => {
+        ws_log!($level, [$($fmt)* , " ", stringify!($key), "={}"] [$($args,)* $key,] $($rest)+)
+    };
+}
+
+#[cfg(all(test, feature = "log", not(feature = "tracing")))]
+mod tests {
+    use std::sync::Mutex;
+    use std::thread::{self, ThreadId};
+
+    /// Records logged on one thread. The logger is global, so tests running
+    /// alongside on other threads log into it too.
+    struct Capture(Mutex<(Option<ThreadId>, Vec<String>)>);
+
+    impl log::Log for Capture {
+        fn enabled(&self, _metadata: &log::Metadata<'_>) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record<'_>) {
+            let mut capture = self.0.lock().unwrap();
+            if capture.0 == Some(thread::current().id()) {
+                capture
+                    .1
+                    .push(format!("{} {}", record.level(), record.args()));
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    static CAPTURE: Capture = Capture(Mutex::new((None, Vec::new())));
+
+    #[test]
+    fn test_fields_are_appended_to_the_message() {
+        CAPTURE.0.lock().unwrap().0 = Some(thread::current().id());
+        log::set_logger(&CAPTURE).unwrap();
+        log::set_max_level(log::LevelFilter::Trace);
+
+        let peer = "127.0.0.1:9000";
+        let timeout = std::time::Duration::from_secs(1);
+        let failures = 3;
+        ws_debug!("no fields");
+        ws_debug!(%peer, conn = 7, error = %"boom", ?timeout, failures, "failed");
+        ws_trace!(opcode = ?crate::OpCode::Text, len = 5, "received frame");
+
+        assert_eq!(
+            CAPTURE.0.lock().unwrap().1,
+            [
+                "DEBUG no fields",
+                "DEBUG failed peer=127.0.0.1:9000 conn=7 error=boom timeout=1s failures=3",
+                "TRACE received frame opcode=Text len=5",
+            ]
+        );
+    }
+}
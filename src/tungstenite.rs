@@ -0,0 +1,115 @@
+//! Conversions to and from [`tungstenite`] types.
+//!
+//! Enabled by the `tungstenite` feature, so a codebase can move from
+//! tungstenite (or tokio-tungstenite) one module at a time:
+//!
+//! ```rust,ignore
+//! let msg: rsws::Message = tungstenite_msg.try_into()?;
+//! let back: tungstenite::Message = msg.try_into()?;
+//! ```
+//!
+//! Close codes and close frames convert losslessly. Messages convert with
+//! `TryFrom`, since neither side can express everything the other can: a
+//! raw tungstenite [`Frame`](::tungstenite::protocol::frame::Frame) has no
+//! rsws equivalent, and a [`Message::Spilled`] payload is not in memory.
+//! The error hands the message back unchanged.
+
+use ::tungstenite::Message as TMessage;
+use ::tungstenite::protocol::CloseFrame as TCloseFrame;
+use ::tungstenite::protocol::frame::coding::CloseCode as TCloseCode;
+
+use crate::message::{CloseCode, CloseFrame, Message};
+
+impl From<TCloseCode> for CloseCode {
+    fn from(code: TCloseCode) -> Self {
+        Self::from_u16(code.into())
+    }
+}
+
+impl From<CloseCode> for TCloseCode {
+    fn from(code: CloseCode) -> Self {
+        Self::from(code.as_u16())
+    }
+}
+
+impl From<TCloseFrame> for CloseFrame {
+    fn from(frame: TCloseFrame) -> Self {
+        Self::new(frame.code.into(), frame.reason.as_str())
+    }
+}
+
+impl From<CloseFrame> for TCloseFrame {
+    fn from(frame: CloseFrame) -> Self {
+        Self {
+            code: frame.code.into(),
+            reason: frame.reason.into(),
+        }
+    }
+}
+
+impl TryFrom<TMessage> for Message {
+    type Error = TMessage;
+
+    /// Fails for [`TMessage::Frame`], which tungstenite never returns from
+    /// a read.
+    fn try_from(msg: TMessage) -> Result<Self, TMessage> {
+        Ok(match msg {
+            TMessage::Text(text) => Message::Text(text.as_str().to_owned()),
+            TMessage::Binary(data) => Message::Binary(data),
+            TMessage::Ping(data) => Message::Ping(data),
+            TMessage::Pong(data) => Message::Pong(data),
+            TMessage::Close(frame) => Message::Close(frame.map(Into::into)),
+            frame @ TMessage::Frame(_) => return Err(frame),
+        })
+    }
+}
+
+impl TryFrom<Message> for TMessage {
+    type Error = Message;
+
+    /// Fails for [`Message::Spilled`]; read the payload back first.
+    fn try_from(msg: Message) -> Result<Self, Message> {
+        Ok(match msg {
+            Message::Text(text) => TMessage::Text(text.into()),
+            Message::Binary(data) => TMessage::Binary(data),
+            Message::Ping(data) => TMessage::Ping(data),
+            Message::Pong(data) => TMessage::Pong(data),
+            Message::Close(frame) => TMessage::Close(frame.map(Into::into)),
+            spilled @ Message::Spilled(_) => return Err(spilled),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::tungstenite::protocol::frame::Frame as TFrame;
+
+    #[test]
+    fn test_close_codes_round_trip() {
+        for code in [1000, 1001, 1008, 1011, 1014, 3000, 4999] {
+            let ours = CloseCode::from_u16(code);
+            let theirs = TCloseCode::from(ours);
+            assert_eq!(u16::from(theirs), code);
+            assert_eq!(CloseCode::from(theirs), ours);
+        }
+    }
+
+    #[test]
+    fn test_messages_round_trip() {
+        for msg in [
+            Message::text("hello"),
+            Message::binary(vec![1, 2, 3]),
+            Message::ping(b"p".to_vec()),
+            Message::pong(b"p".to_vec()),
+            Message::close(CloseCode::GoingAway, "bye"),
+            Message::Close(None),
+        ] {
+            let theirs = TMessage::try_from(msg.clone()).unwrap();
+            assert_eq!(Message::try_from(theirs).unwrap(), msg);
+        }
+
+        let frame = TMessage::Frame(TFrame::pong(vec![]));
+        assert_eq!(Message::try_from(frame.clone()), Err(frame));
+    }
+}
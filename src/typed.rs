@@ -0,0 +1,233 @@
+//! Connections that send and receive serde types.
+//!
+//! Enabled by the `serde` feature, with [`Json`] behind `json` and
+//! [`MessagePack`] behind `msgpack`:
+//!
+//! ```rust,ignore
+//! let mut conn: TypedConnection<_, Request, Response, _> = TypedConnection::new(conn, Json);
+//! conn.send(&Request::Subscribe { topic: "prices".into() }).await?;
+//! while let Some(response) = conn.recv().await? { /* ... */ }
+//! ```
+//!
+//! A message that does not decode as `Rx` fails with [`Error::Decode`] and
+//! leaves the connection usable, so a peer speaking an older schema can be
+//! answered rather than dropped.
+
+use std::marker::PhantomData;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::connection::Connection;
+#[cfg(any(feature = "json", feature = "msgpack", doc))]
+use crate::error::Error;
+use crate::error::Result;
+#[cfg(any(feature = "json", feature = "msgpack"))]
+use crate::error::Source;
+use crate::message::{CloseCode, Message};
+
+/// A serialization format for [`TypedConnection`].
+pub trait Format: Send {
+    /// Encode `value` as a message.
+    ///
+    /// ## Errors
+    ///
+    /// [`Error::Encode`] if `value` cannot be represented.
+    fn encode<V: Serialize + ?Sized>(&self, value: &V) -> Result<Message>;
+
+    /// Decode the payload of a text or binary message.
+    ///
+    /// ## Errors
+    ///
+    /// [`Error::Decode`] if `payload` is not a valid `V`.
+    fn decode<V: DeserializeOwned>(&self, payload: &[u8]) -> Result<V>;
+}
+
+/// JSON, sent as text messages.
+#[cfg(feature = "json")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Json;
+
+#[cfg(feature = "json")]
+impl Format for Json {
+    fn encode<V: Serialize + ?Sized>(&self, value: &V) -> Result<Message> {
+        let text = serde_json::to_string(value).map_err(|e| Error::Encode(Source::new(e)))?;
+        Ok(Message::Text(text))
+    }
+
+    fn decode<V: DeserializeOwned>(&self, payload: &[u8]) -> Result<V> {
+        serde_json::from_slice(payload).map_err(|e| Error::Decode(Source::new(e)))
+    }
+}
+
+/// MessagePack, sent as binary messages. Structs are encoded as maps, so
+/// fields can be added without breaking older peers.
+#[cfg(feature = "msgpack")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MessagePack;
+
+#[cfg(feature = "msgpack")]
+impl Format for MessagePack {
+    fn encode<V: Serialize + ?Sized>(&self, value: &V) -> Result<Message> {
+        let data = rmp_serde::to_vec_named(value).map_err(|e| Error::Encode(Source::new(e)))?;
+        Ok(Message::Binary(data.into()))
+    }
+
+    fn decode<V: DeserializeOwned>(&self, payload: &[u8]) -> Result<V> {
+        rmp_serde::from_slice(payload).map_err(|e| Error::Decode(Source::new(e)))
+    }
+}
+
+/// A connection that sends `Tx` values and receives `Rx` values, encoded
+/// with `F`.
+pub struct TypedConnection<T, Tx, Rx, F> {
+    conn: Connection<T>,
+    format: F,
+    _types: PhantomData<fn(&Tx) -> Rx>,
+}
+
+impl<T, Tx, Rx, F> TypedConnection<T, Tx, Rx, F>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+    Tx: Serialize,
+    Rx: DeserializeOwned,
+    F: Format,
+{
+    /// Wrap `conn`, encoding with `format`.
+    pub fn new(conn: Connection<T>, format: F) -> Self {
+        Self {
+            conn,
+            format,
+            _types: PhantomData,
+        }
+    }
+
+    /// Encode and send `value`.
+    ///
+    /// ## Errors
+    ///
+    /// [`Error::Encode`] if `value` cannot be encoded, which leaves the
+    /// connection usable, or errors from [`Connection::send`].
+    pub async fn send(&mut self, value: &Tx) -> Result<()> {
+        let message = self.format.encode(value)?;
+        self.conn.send(message).await
+    }
+
+    /// Receive and decode the next data message. Returns `Ok(None)` once the
+    /// connection is closed; control messages are skipped.
+    ///
+    /// ## Errors
+    ///
+    /// [`Error::Decode`] if the message is not a valid `Rx`, which leaves
+    /// the connection usable, or errors from [`Connection::recv`].
+    pub async fn recv(&mut self) -> Result<Option<Rx>> {
+        loop {
+            let Some(message) = self.conn.recv().await? else {
+                return Ok(None);
+            };
+            match &message {
+                Message::Text(_) | Message::Binary(_) => {
+                    return self.format.decode(message.payload()).map(Some);
+                }
+                Message::Spilled(spilled) => {
+                    let payload = spilled.read().await?;
+                    return self.format.decode(&payload).map(Some);
+                }
+                Message::Ping(_) | Message::Pong(_) | Message::Close(_) => {}
+            }
+        }
+    }
+
+    /// Start the close handshake, as [`Connection::close`].
+    ///
+    /// ## Errors
+    ///
+    /// As for [`Connection::close`].
+    pub async fn close(&mut self, code: CloseCode, reason: &str) -> Result<()> {
+        self.conn.close(code, reason).await
+    }
+}
+
+impl<T, Tx, Rx, F> TypedConnection<T, Tx, Rx, F> {
+    /// The underlying connection, e.g. to send an untyped message.
+    pub fn get_mut(&mut self) -> &mut Connection<T> {
+        &mut self.conn
+    }
+
+    /// Unwrap the underlying connection.
+    pub fn into_inner(self) -> Connection<T> {
+        self.conn
+    }
+}
+
+#[cfg(all(test, feature = "json"))]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::config::Config;
+    use crate::connection::Role;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Point {
+        x: i32,
+        y: i32,
+    }
+
+    fn pair() -> (
+        TypedConnection<tokio::io::DuplexStream, Point, Point, Json>,
+        Connection<tokio::io::DuplexStream>,
+    ) {
+        let (ours, theirs) = tokio::io::duplex(4096);
+        (
+            TypedConnection::new(Connection::new(ours, Role::Server, Config::server()), Json),
+            Connection::new(theirs, Role::Client, Config::client()),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_json_round_trip() {
+        let (mut conn, mut peer) = pair();
+
+        conn.send(&Point { x: 1, y: 2 }).await.unwrap();
+        assert_eq!(
+            peer.recv().await.unwrap(),
+            Some(Message::text(r#"{"x":1,"y":2}"#))
+        );
+
+        peer.send(Message::ping(b"p".to_vec())).await.unwrap();
+        peer.send(Message::text(r#"{"x":3,"y":4}"#)).await.unwrap();
+        assert_eq!(conn.recv().await.unwrap(), Some(Point { x: 3, y: 4 }));
+    }
+
+    #[tokio::test]
+    async fn test_schema_mismatch_is_not_fatal() {
+        let (mut conn, mut peer) = pair();
+
+        peer.send(Message::text(r#"{"x":"one"}"#)).await.unwrap();
+        peer.send(Message::binary(br#"{"x":5,"y":6}"#.to_vec()))
+            .await
+            .unwrap();
+        let err = conn.recv().await.unwrap_err();
+        assert!(matches!(err, Error::Decode(_)));
+        assert!(!err.is_fatal());
+        assert_eq!(conn.recv().await.unwrap(), Some(Point { x: 5, y: 6 }));
+
+        peer.close(CloseCode::Normal, "").await.unwrap();
+        assert_eq!(conn.recv().await.unwrap(), None);
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn test_msgpack_round_trip() {
+        let message = MessagePack.encode(&Point { x: 7, y: 8 }).unwrap();
+        assert!(message.is_binary());
+        let point: Point = MessagePack.decode(message.payload()).unwrap();
+        assert_eq!(point, Point { x: 7, y: 8 });
+        assert!(matches!(
+            MessagePack.decode::<Point>(b"\xc1"),
+            Err(Error::Decode(_))
+        ));
+    }
+}
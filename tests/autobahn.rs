@@ -26,6 +26,9 @@
 //!    ```
 //!
 //! 3. View results in `autobahn/reports/server/index.html`
+//!
+//! The client side is covered by `cargo run --example autobahn_client`
+//! against `wstest -m fuzzingserver -s /config/fuzzingserver.json`.
 
 #[test]
 #[ignore = "Manual test - run Autobahn via Docker, see module docs"]
@@ -345,12 +345,10 @@ async fn test_barrier_synchronized_connect_500() {
 
             match TestClient::connect_with_id(addr, i).await {
                 Ok(mut client) => {
-                    if client.send_text("sync").await.is_ok() {
-                        if client.recv_text().await.is_ok() {
-                            let _ = client.close().await;
-                            success.fetch_add(1, Ordering::Relaxed);
-                            return;
-                        }
+                    if client.send_text("sync").await.is_ok() && client.recv_text().await.is_ok() {
+                        let _ = client.close().await;
+                        success.fetch_add(1, Ordering::Relaxed);
+                        return;
                     }
                     failure.fetch_add(1, Ordering::Relaxed);
                 }
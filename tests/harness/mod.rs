@@ -1,10 +1,7 @@
 //! Test harness utilities for concurrency and stress testing.
 
-pub mod client;
 pub mod metrics;
-pub mod server;
 
-pub use client::TestClient;
 #[allow(unused_imports)]
 pub use metrics::{Latencies, Metrics};
-pub use server::TestServer;
+pub use rsws::testing::{TestClient, TestServer};
@@ -0,0 +1,116 @@
+#![cfg(feature = "tls-openssl")]
+
+use std::io::Write;
+
+use openssl::ssl::{SslConnector, SslMethod};
+use openssl::x509::X509;
+use rcgen::{CertifiedKey, generate_simple_self_signed};
+use rsws::tls::{OpensslAcceptor, OpensslConnector, openssl_acceptor_from_pem_files};
+use rsws::{Config, Connection, Message, Role};
+use tempfile::NamedTempFile;
+use tokio::net::{TcpListener, TcpStream};
+
+struct TestCert {
+    cert_pem: String,
+    cert_file: NamedTempFile,
+    key_file: NamedTempFile,
+}
+
+fn generate_test_cert(subject_alt_names: Vec<String>) -> TestCert {
+    let CertifiedKey { cert, key_pair } = generate_simple_self_signed(subject_alt_names).unwrap();
+    let cert_pem = cert.pem();
+
+    let mut cert_file = NamedTempFile::new().unwrap();
+    cert_file.write_all(cert_pem.as_bytes()).unwrap();
+    cert_file.flush().unwrap();
+
+    let mut key_file = NamedTempFile::new().unwrap();
+    key_file
+        .write_all(key_pair.serialize_pem().as_bytes())
+        .unwrap();
+    key_file.flush().unwrap();
+
+    TestCert {
+        cert_pem,
+        cert_file,
+        key_file,
+    }
+}
+
+fn trusting_connector(cert_pem: &str) -> SslConnector {
+    let mut builder = SslConnector::builder(SslMethod::tls_client()).unwrap();
+    builder
+        .cert_store_mut()
+        .add_cert(X509::from_pem(cert_pem.as_bytes()).unwrap())
+        .unwrap();
+    builder.build()
+}
+
+async fn run_echo(cert: &TestCert, connector: OpensslConnector, domain: &str) {
+    let acceptor = OpensslAcceptor::new(
+        openssl_acceptor_from_pem_files(cert.cert_file.path(), cert.key_file.path()).unwrap(),
+    );
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server_handle = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let tls_stream = acceptor.accept(stream).await.unwrap();
+        let mut conn = Connection::new(tls_stream, Role::Server, Config::server());
+
+        let msg = conn.recv().await.unwrap().unwrap();
+        conn.send(msg).await.unwrap();
+    });
+
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let tls_stream = connector.connect(domain, stream).await.unwrap();
+    assert!(tls_stream.ssl().current_cipher().is_some());
+
+    let mut conn = Connection::new(tls_stream, Role::Client, Config::client());
+    conn.send(Message::text("hello over openssl"))
+        .await
+        .unwrap();
+    let echoed = conn.recv().await.unwrap().unwrap();
+    assert_eq!(echoed.as_text(), Some("hello over openssl"));
+
+    server_handle.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_openssl_websocket_echo() {
+    let cert = generate_test_cert(vec!["localhost".to_string()]);
+    let connector = OpensslConnector::new(trusting_connector(&cert.cert_pem));
+    run_echo(&cert, connector, "localhost").await;
+}
+
+#[tokio::test]
+async fn test_openssl_server_name_override() {
+    let cert = generate_test_cert(vec!["backend.internal".to_string()]);
+    let connector = OpensslConnector::new(trusting_connector(&cert.cert_pem))
+        .with_server_name("backend.internal");
+    run_echo(&cert, connector, "127.0.0.1").await;
+}
+
+#[tokio::test]
+async fn test_openssl_rejects_untrusted_certificate() {
+    let cert = generate_test_cert(vec!["localhost".to_string()]);
+    let acceptor = OpensslAcceptor::new(
+        openssl_acceptor_from_pem_files(cert.cert_file.path(), cert.key_file.path()).unwrap(),
+    );
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server_handle = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let _ = acceptor.accept(stream).await;
+    });
+
+    let other = generate_test_cert(vec!["localhost".to_string()]);
+    let connector = OpensslConnector::new(trusting_connector(&other.cert_pem));
+    let stream = TcpStream::connect(addr).await.unwrap();
+    assert!(connector.connect("localhost", stream).await.is_err());
+
+    server_handle.await.unwrap();
+}
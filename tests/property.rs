@@ -2,8 +2,11 @@
 //!
 //! These tests use proptest to fuzz the frame parsing logic and find edge cases.
 
+use bytes::Bytes;
 use proptest::prelude::*;
-use rsws::protocol::{Frame, HandshakeRequest, OpCode, apply_mask};
+use rsws::Config;
+use rsws::connection::MessageFragmenter;
+use rsws::protocol::{Frame, HandshakeRequest, MessageAssembler, OpCode, apply_mask};
 
 /// Strategy for generating valid data frame opcodes.
 fn data_opcode_strategy() -> impl Strategy<Value = OpCode> {
@@ -355,3 +358,132 @@ proptest! {
         prop_assert!(result.is_ok(), "Valid request should parse: {:?}", result);
     }
 }
+
+/// Payload lengths that cover all three length encodings (7-bit, 16-bit and
+/// 64-bit) without making every case large.
+fn payload_len_strategy() -> impl Strategy<Value = usize> {
+    prop_oneof![
+        4 => 0usize..=125,
+        3 => 126usize..=65535,
+        1 => 65536usize..=70000,
+    ]
+}
+
+/// Arbitrary frames that are valid on the wire: control frames are final and
+/// carry at most 125 bytes.
+fn frame_strategy() -> impl Strategy<Value = Frame> {
+    (
+        any_opcode_strategy(),
+        any::<bool>(),
+        any::<[bool; 3]>(),
+        payload_len_strategy(),
+        any::<u8>(),
+    )
+        .prop_map(|(opcode, fin, [rsv1, rsv2, rsv3], len, seed)| {
+            let (fin, len) = if opcode.is_control() {
+                (true, len.min(125))
+            } else {
+                (fin, len)
+            };
+            let payload = (0..len)
+                .map(|i| (i as u8).wrapping_mul(31) ^ seed)
+                .collect();
+            let mut frame = Frame::new(fin, opcode, payload);
+            frame.rsv1 = rsv1;
+            frame.rsv2 = rsv2;
+            frame.rsv3 = rsv3;
+            frame
+        })
+}
+
+fn assembly_config() -> Config {
+    let mut config = Config::default();
+    config.limits.max_fragment_count = usize::MAX;
+    config
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    // =========================================================================
+    // Full round trip: every header bit, length encoding and mask survives
+    // =========================================================================
+    #[test]
+    fn test_roundtrip_any_frame(
+        frame in frame_strategy(),
+        mask in proptest::option::of(any::<[u8; 4]>())
+    ) {
+        let mut buf = vec![0u8; frame.wire_size(mask.is_some())];
+        let written = frame.write(&mut buf, mask).unwrap();
+        prop_assert_eq!(written, buf.len());
+
+        let (parsed, consumed) = Frame::parse(&buf).unwrap();
+        prop_assert_eq!(consumed, written);
+        prop_assert_eq!(&parsed, &frame);
+
+        let (zero_copy, consumed) = Frame::parse_zero_copy(&Bytes::from(buf)).unwrap();
+        prop_assert_eq!(consumed, written);
+        prop_assert_eq!(&zero_copy, &frame);
+    }
+
+    // =========================================================================
+    // Fragmenter output is well-formed and reassembles to the original
+    // =========================================================================
+    #[test]
+    fn test_fragment_assemble_roundtrip(
+        payload in prop::collection::vec(any::<u8>(), 0..4000),
+        fragment_size in 1usize..600,
+    ) {
+        let frames: Vec<Frame> =
+            MessageFragmenter::new(&payload, OpCode::Binary, fragment_size).collect();
+
+        let expected = payload.len().div_ceil(fragment_size).max(1);
+        prop_assert_eq!(frames.len(), expected);
+        prop_assert_eq!(frames[0].opcode, OpCode::Binary);
+        for (i, frame) in frames.iter().enumerate() {
+            prop_assert_eq!(frame.fin, i == frames.len() - 1);
+            if i > 0 {
+                prop_assert_eq!(frame.opcode, OpCode::Continuation);
+            }
+            prop_assert!(frame.payload().len() <= fragment_size);
+        }
+
+        let mut assembler = MessageAssembler::new(assembly_config());
+        let mut assembled = None;
+        for frame in frames {
+            prop_assert!(assembled.is_none(), "message completed before final frame");
+            assembled = assembler.push(frame).unwrap();
+        }
+
+        let assembled = assembled.expect("final frame completes the message");
+        prop_assert_eq!(assembled.opcode, OpCode::Binary);
+        prop_assert_eq!(assembled.payload.as_ref(), payload.as_slice());
+        prop_assert!(!assembler.is_assembling());
+    }
+
+    // =========================================================================
+    // Text split at arbitrary byte offsets (inside code points) still
+    // reassembles, with every fragment going through the wire format
+    // =========================================================================
+    #[test]
+    fn test_fragment_wire_assemble_text(
+        text in "\\PC{0,300}",
+        fragment_size in 1usize..64,
+        mask in any::<[u8; 4]>(),
+    ) {
+        let mut assembler = MessageAssembler::new(assembly_config());
+        let mut assembled = None;
+
+        for frame in MessageFragmenter::new(text.as_bytes(), OpCode::Text, fragment_size) {
+            let mut buf = vec![0u8; frame.wire_size(true)];
+            frame.write(&mut buf, Some(mask)).unwrap();
+            let (parsed, _) = Frame::parse(&buf).unwrap();
+            prop_assert_eq!(&parsed, &frame);
+            assembled = assembler.push(parsed).unwrap();
+        }
+
+        let assembled = assembled.expect("final frame completes the message");
+        prop_assert_eq!(assembled.opcode, OpCode::Text);
+        prop_assert_eq!(assembled.into_text().unwrap(), text);
+    }
+}
@@ -0,0 +1,400 @@
+//! Tests for the `server::Server` accept loop.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use futures::StreamExt;
+use rsws::config::Timeouts;
+use rsws::server::{
+    ConnectionLimits, HttpRequest, HttpResponse, InvalidRequest, OverLimit, Route, Router, Server,
+    TrustedProxies,
+};
+use rsws::{Config, Connection, HandshakeResponse, Message, Role, compute_accept_key};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+const KEY: &str = "dGhlIHNhbXBsZSBub25jZQ==";
+
+fn request(addr: SocketAddr) -> String {
+    format!(
+        "GET /chat HTTP/1.1\r\n\
+         Host: {}\r\n\
+         Upgrade: websocket\r\n\
+         Connection: Upgrade\r\n\
+         Sec-WebSocket-Key: {}\r\n\
+         Sec-WebSocket-Version: 13\r\n\
+         \r\n",
+        addr, KEY
+    )
+}
+
+async fn connect(addr: SocketAddr) -> Connection<TcpStream> {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let request = request(addr);
+    stream.write_all(request.as_bytes()).await.unwrap();
+
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        head.push(stream.read_u8().await.unwrap());
+    }
+    let response = HandshakeResponse::parse(&head).unwrap();
+    assert_eq!(response.accept, compute_accept_key(KEY));
+
+    Connection::new(stream, Role::Client, Config::client())
+}
+
+fn spawn_echo(mut server: Server) {
+    tokio::spawn(async move {
+        while let Some(Ok((mut conn, _, _))) = server.next().await {
+            tokio::spawn(async move {
+                while let Ok(Some(msg)) = conn.recv().await {
+                    if msg.is_text() || msg.is_binary() {
+                        let _ = conn.send(msg).await;
+                    }
+                }
+            });
+        }
+    });
+}
+
+#[tokio::test]
+async fn test_server_accepts_and_echoes() {
+    let server = Server::bind("127.0.0.1:0").await.unwrap();
+    let addr = server.local_addr().unwrap();
+    spawn_echo(server);
+
+    let mut client = connect(addr).await;
+    client.send(Message::text("hello server")).await.unwrap();
+    let echoed = client.recv().await.unwrap().unwrap();
+    assert_eq!(echoed.as_text(), Some("hello server"));
+}
+
+#[tokio::test]
+async fn test_accept_returns_request_and_peer() {
+    let mut server = Server::bind("127.0.0.1:0").await.unwrap();
+    let addr = server.local_addr().unwrap();
+
+    let client = tokio::spawn(async move {
+        let mut client = connect(addr).await;
+        client.send(Message::text("ping")).await.unwrap();
+        client
+    });
+
+    let (mut conn, request, peer) = server.accept().await.unwrap();
+    assert_eq!(request.path, "/chat");
+    assert!(peer.ip().is_loopback());
+
+    let msg = conn.recv().await.unwrap().unwrap();
+    assert_eq!(msg, Message::text("ping"));
+    client.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_stalled_handshake_does_not_block_others() {
+    let mut server = Server::bind("127.0.0.1:0").await.unwrap();
+    let addr = server.local_addr().unwrap();
+
+    // Connects but never sends a request
+    let _silent = TcpStream::connect(addr).await.unwrap();
+
+    let client = tokio::spawn(async move { connect(addr).await });
+    let accepted = tokio::time::timeout(Duration::from_secs(5), server.accept()).await;
+    assert!(accepted.unwrap().is_ok());
+    client.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_shutdown_ends_handshake_tasks() {
+    let mut server = Server::bind("127.0.0.1:0").await.unwrap();
+    let addr = server.local_addr().unwrap();
+
+    let mut silent = TcpStream::connect(addr).await.unwrap();
+    let accept = tokio::time::timeout(Duration::from_millis(50), server.accept()).await;
+    assert!(accept.is_err());
+    assert_eq!(server.handshakes_in_progress(), 1);
+
+    server.shutdown().await;
+    assert_eq!(server.handshakes_in_progress(), 0);
+    let mut buf = [0u8; 1];
+    let n = silent.read(&mut buf).await.unwrap_or(0);
+    assert_eq!(n, 0);
+}
+
+#[tokio::test]
+async fn test_handshake_timeout_drops_client() {
+    let config = Config::server().with_timeouts(Timeouts::new(
+        Duration::from_millis(50),
+        Duration::from_secs(60),
+        Duration::from_secs(60),
+        Duration::from_secs(60),
+    ));
+    let mut server = Server::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .with_config(config);
+    let addr = server.local_addr().unwrap();
+
+    let mut silent = TcpStream::connect(addr).await.unwrap();
+    let accept = tokio::time::timeout(Duration::from_millis(300), server.accept()).await;
+    assert!(
+        accept.is_err(),
+        "a timed-out handshake must not be returned"
+    );
+
+    // The server closed the silent socket
+    let mut buf = [0u8; 1];
+    let n = silent.read(&mut buf).await.unwrap_or(0);
+    assert_eq!(n, 0);
+}
+
+#[tokio::test]
+async fn test_per_ip_limit_rejects_with_503() {
+    let mut server = Server::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .with_connection_limits(ConnectionLimits::new().with_max_per_ip(1));
+    let addr = server.local_addr().unwrap();
+
+    let first = tokio::spawn(async move { connect(addr).await });
+    let (conn, _, _) = server.accept().await.unwrap();
+    let _client = first.await.unwrap();
+    assert_eq!(server.active_connections(), 1);
+
+    let mut second = TcpStream::connect(addr).await.unwrap();
+    second.write_all(request(addr).as_bytes()).await.unwrap();
+    let accept = tokio::time::timeout(Duration::from_millis(200), server.accept()).await;
+    assert!(
+        accept.is_err(),
+        "a client over the limit must not be accepted"
+    );
+
+    let mut response = String::new();
+    second.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 503"), "{response}");
+
+    // Dropping the connection frees its slot
+    drop(conn);
+    assert_eq!(server.active_connections(), 0);
+    let third = tokio::spawn(async move { connect(addr).await });
+    assert!(server.accept().await.is_ok());
+    third.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_client_addr_behind_trusted_proxy() {
+    let mut server = Server::bind("127.0.0.1:0").await.unwrap();
+    let addr = server.local_addr().unwrap();
+    let client = tokio::spawn(async move { connect(addr).await });
+    let (conn, _, _) = server.accept().await.unwrap();
+    assert_eq!(conn.client_addr(), Some("127.0.0.1".parse().unwrap()));
+    client.await.unwrap();
+
+    let mut server =
+        server.with_trusted_proxies(TrustedProxies::new().with_proxy("127.0.0.1".parse().unwrap()));
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let request = request(addr).replace(
+        "\r\n\r\n",
+        "\r\nX-Forwarded-For: 203.0.113.5, 127.0.0.1\r\n\r\n",
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let (conn, _, peer) = server.accept().await.unwrap();
+    assert_eq!(peer.ip(), addr.ip());
+    assert_eq!(conn.client_addr(), Some("203.0.113.5".parse().unwrap()));
+}
+
+#[tokio::test]
+async fn test_global_limit_drops_socket() {
+    let mut server = Server::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .with_connection_limits(
+            ConnectionLimits::new()
+                .with_max_connections(1)
+                .with_over_limit(OverLimit::Drop),
+        );
+    let addr = server.local_addr().unwrap();
+
+    // A socket still in the handshake already counts
+    let _pending = TcpStream::connect(addr).await.unwrap();
+    let mut over = TcpStream::connect(addr).await.unwrap();
+    let _ = tokio::time::timeout(Duration::from_millis(200), server.accept()).await;
+
+    let mut buf = [0u8; 1];
+    let n = over.read(&mut buf).await.unwrap_or(0);
+    assert_eq!(n, 0);
+    assert_eq!(server.active_connections(), 1);
+}
+
+#[tokio::test]
+async fn test_fallback_serves_plain_requests() {
+    let server = Server::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .with_fallback(|req: &HttpRequest| match req.path.as_str() {
+            "/healthz" => HttpResponse::ok("ok"),
+            "/echo" => HttpResponse::new(200).with_body(req.body.clone()),
+            _ => HttpResponse::not_found(),
+        });
+    let addr = server.local_addr().unwrap();
+    spawn_echo(server);
+
+    let mut health = TcpStream::connect(addr).await.unwrap();
+    health
+        .write_all(b"GET /healthz HTTP/1.1\r\nHost: x\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = String::new();
+    health.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+    assert!(response.ends_with("\r\n\r\nok"), "{response}");
+
+    let mut post = TcpStream::connect(addr).await.unwrap();
+    post.write_all(b"POST /echo HTTP/1.1\r\nContent-Length: 5\r\n\r\nhel")
+        .await
+        .unwrap();
+    post.write_all(b"lo").await.unwrap();
+    let mut response = String::new();
+    post.read_to_string(&mut response).await.unwrap();
+    assert!(response.ends_with("\r\n\r\nhello"), "{response}");
+
+    // WebSocket clients on the same port are unaffected
+    let mut client = connect(addr).await;
+    client.send(Message::text("still here")).await.unwrap();
+    let echoed = client.recv().await.unwrap().unwrap();
+    assert_eq!(echoed.as_text(), Some("still here"));
+}
+
+/// Send `request` on a new connection and return everything the server
+/// sends before closing.
+async fn exchange(addr: SocketAddr, request: &str) -> String {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    let _ = stream.read_to_string(&mut response).await;
+    response
+}
+
+#[tokio::test]
+async fn test_invalid_requests_are_answered() {
+    let server = Server::bind("127.0.0.1:0").await.unwrap();
+    let addr = server.local_addr().unwrap();
+    spawn_echo(server);
+
+    let post = request(addr).replacen("GET", "POST", 1);
+    let response = exchange(addr, &post).await;
+    assert!(
+        response.starts_with("HTTP/1.1 405 Method Not Allowed\r\nAllow: GET\r\n"),
+        "{response}"
+    );
+
+    let keyless = request(addr).replacen(&format!("Sec-WebSocket-Key: {KEY}\r\n"), "", 1);
+    let response = exchange(addr, &keyless).await;
+    assert!(
+        response.starts_with("HTTP/1.1 400 Bad Request\r\nSec-WebSocket-Version: 13\r\n"),
+        "{response}"
+    );
+
+    let old = request(addr).replacen("Version: 13", "Version: 8", 1);
+    let response = exchange(addr, &old).await;
+    assert!(
+        response.starts_with("HTTP/1.1 426 Upgrade Required\r\nSec-WebSocket-Version: 13\r\n"),
+        "{response}"
+    );
+}
+
+#[tokio::test]
+async fn test_invalid_requests_can_be_dropped() {
+    let server = Server::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .with_invalid_request(InvalidRequest::Drop);
+    let addr = server.local_addr().unwrap();
+    spawn_echo(server);
+
+    let post = request(addr).replacen("GET", "POST", 1);
+    assert_eq!(exchange(addr, &post).await, "");
+}
+
+/// Send an upgrade request for `path` with `extra` headers and return the
+/// response head.
+async fn upgrade_head(addr: SocketAddr, path: &str, extra: &str) -> String {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let request = request(addr).replacen("/chat", path, 1);
+    let request = request.replacen("\r\n\r\n", &format!("\r\n{extra}\r\n"), 1);
+    stream.write_all(request.as_bytes()).await.unwrap();
+
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        match stream.read_u8().await {
+            Ok(byte) => head.push(byte),
+            Err(_) => break,
+        }
+    }
+    String::from_utf8(head).unwrap()
+}
+
+#[tokio::test]
+async fn test_router_applies_route_per_path() {
+    let router = Router::new()
+        .route("/control", Route::new().with_protocols(["control.v1"]))
+        .route("/rooms/*", Route::new());
+    let server = Server::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .with_router(router);
+    let addr = server.local_addr().unwrap();
+    spawn_echo(server);
+
+    let control = upgrade_head(
+        addr,
+        "/control",
+        "Sec-WebSocket-Protocol: chat, control.v1\r\n",
+    )
+    .await;
+    assert!(control.starts_with("HTTP/1.1 101"), "{control}");
+    assert!(
+        control.contains("Sec-WebSocket-Protocol: control.v1\r\n"),
+        "{control}"
+    );
+
+    let room = upgrade_head(addr, "/rooms/7", "Sec-WebSocket-Protocol: chat\r\n").await;
+    assert!(room.starts_with("HTTP/1.1 101"), "{room}");
+    assert!(room.contains("Sec-WebSocket-Protocol: chat\r\n"), "{room}");
+
+    let missing = upgrade_head(addr, "/elsewhere", "").await;
+    assert!(missing.starts_with("HTTP/1.1 404"), "{missing}");
+}
+
+#[cfg(feature = "compression")]
+#[tokio::test]
+async fn test_router_negotiates_extensions_per_path() {
+    use rsws::extensions::ExtensionRegistry;
+    use rsws::extensions::deflate::{DeflateConfig, DeflateExtension};
+
+    let deflate = || {
+        let mut registry = ExtensionRegistry::new();
+        registry
+            .add(Box::new(DeflateExtension::server(DeflateConfig::new())))
+            .unwrap();
+        registry
+    };
+    let router = Router::new()
+        .route("/feed", Route::new().with_extensions(deflate))
+        .route("/control", Route::new());
+    let server = Server::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .with_router(router);
+    let addr = server.local_addr().unwrap();
+    spawn_echo(server);
+
+    let offer = "Sec-WebSocket-Extensions: permessage-deflate\r\n";
+    let feed = upgrade_head(addr, "/feed", offer).await;
+    assert!(
+        feed.contains("Sec-WebSocket-Extensions: permessage-deflate"),
+        "{feed}"
+    );
+    let control = upgrade_head(addr, "/control", offer).await;
+    assert!(control.starts_with("HTTP/1.1 101"), "{control}");
+    assert!(!control.contains("Sec-WebSocket-Extensions"), "{control}");
+}
@@ -1,16 +1,25 @@
-#![cfg(feature = "tls-rustls")]
+// The configs here are built with the crate's crypto backend
+#![cfg(any(feature = "rustls-aws-lc-rs", feature = "rustls-ring"))]
 
 use std::sync::Arc;
 
 use rcgen::{CertifiedKey, generate_simple_self_signed};
-use rsws::tls::{TlsAcceptor, TlsConnector, TlsError};
+use rsws::tls::{
+    OcspStapler, SessionResumption, SniCertificates, TlsAcceptor, TlsConnector, TlsError,
+    TlsStream, server_config_with_cert_resolver, server_config_with_ocsp,
+};
 use rustls::pki_types::{CertificateDer, PrivateKeyDer};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
-use tokio_rustls::rustls::{ClientConfig, RootCertStore, ServerConfig};
+use tokio_rustls::rustls::{ClientConfig, HandshakeKind, RootCertStore, ServerConfig};
 
 fn generate_test_cert() -> (Vec<CertificateDer<'static>>, PrivateKeyDer<'static>) {
-    let subject_alt_names = vec!["localhost".to_string()];
+    generate_test_cert_for(vec!["localhost".to_string()])
+}
+
+fn generate_test_cert_for(
+    subject_alt_names: Vec<String>,
+) -> (Vec<CertificateDer<'static>>, PrivateKeyDer<'static>) {
     let CertifiedKey { cert, key_pair } = generate_simple_self_signed(subject_alt_names).unwrap();
 
     let cert_der = CertificateDer::from(cert.der().to_vec());
@@ -78,6 +87,202 @@ async fn test_tls_client_server_handshake() {
     client_result.unwrap();
 }
 
+async fn run_echo_handshake(
+    connector: TlsConnector,
+    domain: &'static str,
+    certs_key: (Vec<CertificateDer<'static>>, PrivateKeyDer<'static>),
+) {
+    let (certs, key) = certs_key;
+    let server_config = create_test_server_config(certs, key);
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server_handle = tokio::spawn(async move {
+        let acceptor = TlsAcceptor::new(server_config);
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut tls_stream = acceptor.accept(stream).await.unwrap();
+
+        let mut buf = [0u8; 4];
+        tls_stream.read_exact(&mut buf).await.unwrap();
+        tls_stream.write_all(&buf).await.unwrap();
+    });
+
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let mut tls_stream = connector.connect(domain, stream).await.unwrap();
+    tls_stream.write_all(b"ping").await.unwrap();
+
+    let mut buf = [0u8; 4];
+    tls_stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"ping");
+
+    server_handle.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_server_name_override() {
+    let (certs, key) = generate_test_cert();
+    let client_config = create_test_client_config(certs[0].clone());
+
+    // Dial by IP, but verify the certificate issued for "localhost".
+    let connector = TlsConnector::new(client_config)
+        .with_server_name("localhost")
+        .unwrap();
+    run_echo_handshake(connector, "127.0.0.1", (certs, key)).await;
+}
+
+#[tokio::test]
+async fn test_ip_literal_without_override() {
+    let (certs, key) = generate_test_cert_for(vec!["127.0.0.1".to_string()]);
+    let client_config = create_test_client_config(certs[0].clone());
+
+    let connector = TlsConnector::new(client_config);
+    run_echo_handshake(connector, "127.0.0.1", (certs, key)).await;
+}
+
+async fn connect_and_report_handshake(
+    connector: &TlsConnector,
+    acceptor: Arc<TlsAcceptor>,
+) -> Option<HandshakeKind> {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server_handle = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut tls_stream = acceptor.accept(stream).await.unwrap();
+
+        let mut buf = [0u8; 4];
+        tls_stream.read_exact(&mut buf).await.unwrap();
+        tls_stream.write_all(&buf).await.unwrap();
+        tls_stream.flush().await.unwrap();
+    });
+
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let mut tls_stream = connector.connect("localhost", stream).await.unwrap();
+    tls_stream.write_all(b"ping").await.unwrap();
+
+    // Reading the echo also drains the TLS 1.3 session tickets.
+    let mut buf = [0u8; 4];
+    tls_stream.read_exact(&mut buf).await.unwrap();
+    server_handle.await.unwrap();
+
+    match &tls_stream {
+        TlsStream::Client(s) => s.get_ref().1.handshake_kind(),
+        _ => unreachable!(),
+    }
+}
+
+fn resumption_test_configs(resumption: &SessionResumption) -> (Arc<TlsAcceptor>, TlsConnector) {
+    let (certs, key) = generate_test_cert();
+
+    let mut server_config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs.clone(), key)
+        .unwrap();
+    resumption.apply_to_server(&mut server_config).unwrap();
+
+    let mut root_store = RootCertStore::empty();
+    root_store.add(certs[0].clone()).unwrap();
+    let mut client_config = ClientConfig::builder()
+        .with_root_certificates(root_store)
+        .with_no_client_auth();
+    resumption.apply_to_client(&mut client_config);
+
+    (
+        Arc::new(TlsAcceptor::new(Arc::new(server_config))),
+        TlsConnector::new(Arc::new(client_config)),
+    )
+}
+
+#[tokio::test]
+async fn test_session_resumption() {
+    let resumption = SessionResumption::new().with_server_tickets(true);
+    let (acceptor, connector) = resumption_test_configs(&resumption);
+
+    let first = connect_and_report_handshake(&connector, acceptor.clone()).await;
+    assert_eq!(first, Some(HandshakeKind::Full));
+
+    let second = connect_and_report_handshake(&connector, acceptor).await;
+    assert_eq!(second, Some(HandshakeKind::Resumed));
+}
+
+#[tokio::test]
+async fn test_session_resumption_disabled() {
+    let (acceptor, connector) = resumption_test_configs(&SessionResumption::disabled());
+
+    connect_and_report_handshake(&connector, acceptor.clone()).await;
+    let second = connect_and_report_handshake(&connector, acceptor).await;
+    assert_eq!(second, Some(HandshakeKind::Full));
+}
+
+#[cfg(all(feature = "ktls", target_os = "linux"))]
+#[tokio::test]
+async fn test_ktls_echo_with_fallback() {
+    let (certs, key) = generate_test_cert();
+    let client_config = create_test_client_config(certs[0].clone());
+    let server_config = create_test_server_config(certs, key);
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server_handle = tokio::spawn(async move {
+        let acceptor = TlsAcceptor::new(server_config).with_ktls();
+        assert!(acceptor.ktls_enabled());
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut tls_stream = acceptor.accept_ktls(stream).await.unwrap();
+
+        let mut buf = vec![0u8; 64 * 1024];
+        tls_stream.read_exact(&mut buf).await.unwrap();
+        tls_stream.write_all(&buf).await.unwrap();
+        tls_stream.shutdown().await.unwrap();
+    });
+
+    // Offload happens when the kernel has the `tls` module; either way the
+    // stream must behave the same.
+    let connector = TlsConnector::new(client_config).with_ktls();
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let mut tls_stream = connector.connect_ktls("localhost", stream).await.unwrap();
+
+    let payload: Vec<u8> = (0..64 * 1024).map(|i| (i % 251) as u8).collect();
+    tls_stream.write_all(&payload).await.unwrap();
+
+    let mut echoed = vec![0u8; payload.len()];
+    tls_stream.read_exact(&mut echoed).await.unwrap();
+    assert_eq!(echoed, payload);
+
+    let mut rest = Vec::new();
+    tls_stream.read_to_end(&mut rest).await.unwrap();
+    assert!(rest.is_empty());
+
+    server_handle.await.unwrap();
+}
+
+#[cfg(all(feature = "ktls", target_os = "linux"))]
+#[tokio::test]
+async fn test_ktls_not_enabled_keeps_userspace_stream() {
+    let (certs, key) = generate_test_cert();
+    let client_config = create_test_client_config(certs[0].clone());
+    let server_config = create_test_server_config(certs, key);
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server_handle = tokio::spawn(async move {
+        let acceptor = TlsAcceptor::new(server_config);
+        let (stream, _) = listener.accept().await.unwrap();
+        let tls_stream = acceptor.accept_ktls(stream).await.unwrap();
+        assert!(!tls_stream.is_ktls());
+    });
+
+    let connector = TlsConnector::new(client_config);
+    assert!(!connector.ktls_enabled());
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let tls_stream = connector.connect_ktls("localhost", stream).await.unwrap();
+    assert!(!tls_stream.is_ktls());
+
+    server_handle.await.unwrap();
+}
+
 #[tokio::test]
 async fn test_invalid_dns_name() {
     let config = rsws::tls::client_config_with_native_roots().unwrap();
@@ -94,7 +299,7 @@ async fn test_invalid_dns_name() {
 
 #[test]
 fn test_tls_error_display() {
-    let io_err = TlsError::Io(std::io::Error::new(std::io::ErrorKind::Other, "test"));
+    let io_err = TlsError::Io(std::io::Error::other("test"));
     assert!(io_err.to_string().contains("TLS I/O error"));
 
     let config_err = TlsError::Configuration("bad config".to_string());
@@ -112,3 +317,221 @@ fn test_tls_error_display() {
     let invalid_dns = TlsError::InvalidDnsName("bad.name".to_string());
     assert!(invalid_dns.to_string().contains("invalid DNS name"));
 }
+
+#[tokio::test]
+async fn test_server_with_tls_acceptor() {
+    use rsws::server::Server;
+    use rsws::{Config, Connection, HandshakeResponse, Message, Role};
+
+    let (certs, key) = generate_test_cert();
+    let client_config = create_test_client_config(certs[0].clone());
+    let acceptor = TlsAcceptor::new(create_test_server_config(certs, key));
+
+    let mut server = Server::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .with_tls(acceptor);
+    let addr = server.local_addr().unwrap();
+
+    let server_handle = tokio::spawn(async move {
+        let (mut conn, request, _) = server.accept().await.unwrap();
+        assert_eq!(request.path, "/secure");
+        let msg = conn.recv().await.unwrap().unwrap();
+        conn.send(msg).await.unwrap();
+    });
+
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let mut tls_stream = TlsConnector::new(client_config)
+        .connect("localhost", stream)
+        .await
+        .unwrap();
+    tls_stream
+        .write_all(
+            b"GET /secure HTTP/1.1\r\n\
+              Host: localhost\r\n\
+              Upgrade: websocket\r\n\
+              Connection: Upgrade\r\n\
+              Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+              Sec-WebSocket-Version: 13\r\n\r\n",
+        )
+        .await
+        .unwrap();
+
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        head.push(tls_stream.read_u8().await.unwrap());
+    }
+    HandshakeResponse::parse(&head).unwrap();
+
+    let mut conn = Connection::new(tls_stream, Role::Client, Config::client());
+    conn.send(Message::text("over wss")).await.unwrap();
+    let echoed = conn.recv().await.unwrap().unwrap();
+    assert_eq!(echoed.as_text(), Some("over wss"));
+
+    server_handle.await.unwrap();
+}
+
+async fn served_certificate(
+    acceptor: Arc<TlsAcceptor>,
+    client_config: Arc<ClientConfig>,
+    domain: &str,
+) -> Result<CertificateDer<'static>, TlsError> {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server_handle = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let _ = acceptor.accept(stream).await;
+    });
+
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let result = TlsConnector::new(client_config)
+        .connect(domain, stream)
+        .await
+        .map(|tls_stream| match &tls_stream {
+            TlsStream::Client(s) => s.get_ref().1.peer_certificates().unwrap()[0].clone(),
+            _ => unreachable!(),
+        });
+    server_handle.await.unwrap();
+    result
+}
+
+#[tokio::test]
+async fn test_sni_certificate_selection() {
+    let (a_certs, a_key) = generate_test_cert_for(vec!["a.test".to_string()]);
+    let (b_certs, b_key) = generate_test_cert_for(vec!["b.test".to_string()]);
+
+    let mut root_store = RootCertStore::empty();
+    root_store.add(a_certs[0].clone()).unwrap();
+    root_store.add(b_certs[0].clone()).unwrap();
+    let client_config = Arc::new(
+        ClientConfig::builder()
+            .with_root_certificates(root_store)
+            .with_no_client_auth(),
+    );
+
+    // A certificate must cover the name it is registered under.
+    assert!(matches!(
+        SniCertificates::new().with_certificate("c.test", a_certs.clone(), a_key.clone_key()),
+        Err(TlsError::Configuration(_))
+    ));
+
+    let certs = SniCertificates::new()
+        .with_certificate("a.test", a_certs.clone(), a_key)
+        .unwrap()
+        .with_certificate("b.test", b_certs.clone(), b_key)
+        .unwrap();
+    let server_config =
+        server_config_with_cert_resolver(Arc::new(certs), &SessionResumption::new()).unwrap();
+    let acceptor = Arc::new(TlsAcceptor::new(server_config));
+
+    let served = served_certificate(acceptor.clone(), client_config.clone(), "a.test").await;
+    assert_eq!(served.unwrap(), a_certs[0]);
+    let served = served_certificate(acceptor.clone(), client_config.clone(), "b.test").await;
+    assert_eq!(served.unwrap(), b_certs[0]);
+
+    // No fallback: unknown names fail the handshake.
+    assert!(
+        served_certificate(acceptor, client_config, "c.test")
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn test_sni_fallback_certificate() {
+    let (certs, key) = generate_test_cert();
+    let (other_certs, other_key) = generate_test_cert_for(vec!["other.test".to_string()]);
+    let client_config = create_test_client_config(certs[0].clone());
+
+    let sni = SniCertificates::new()
+        .with_certificate("other.test", other_certs, other_key)
+        .unwrap()
+        .with_fallback(certs.clone(), key)
+        .unwrap();
+    let (initial_certs, initial_key) = generate_test_cert();
+    let acceptor = TlsAcceptor::new(create_test_server_config(initial_certs, initial_key))
+        .with_cert_resolver(Arc::new(sni));
+
+    let served = served_certificate(Arc::new(acceptor), client_config, "localhost").await;
+    assert_eq!(served.unwrap(), certs[0]);
+}
+
+/// Accepts any certificate and records the stapled OCSP response.
+#[derive(Debug, Default)]
+struct RecordOcsp(std::sync::Mutex<Option<Vec<u8>>>);
+
+impl rustls::client::danger::ServerCertVerifier for RecordOcsp {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &rustls::pki_types::ServerName<'_>,
+        ocsp_response: &[u8],
+        _now: rustls::pki_types::UnixTime,
+    ) -> Result<rustls::client::danger::ServerCertVerified, rustls::Error> {
+        *self.0.lock().unwrap() = Some(ocsp_response.to_vec());
+        Ok(rustls::client::danger::ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        _message: &[u8],
+        _cert: &CertificateDer<'_>,
+        _dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        Ok(rustls::client::danger::HandshakeSignatureValid::assertion())
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        _message: &[u8],
+        _cert: &CertificateDer<'_>,
+        _dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        Ok(rustls::client::danger::HandshakeSignatureValid::assertion())
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        rustls::crypto::CryptoProvider::get_default()
+            .expect("installed by ClientConfig::builder")
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+async fn stapled_response(server_config: Arc<ServerConfig>) -> Vec<u8> {
+    let verifier = Arc::new(RecordOcsp::default());
+    let client_config = ClientConfig::builder()
+        .dangerous()
+        .with_custom_certificate_verifier(verifier.clone())
+        .with_no_client_auth();
+
+    let acceptor = Arc::new(TlsAcceptor::new(server_config));
+    served_certificate(acceptor, Arc::new(client_config), "localhost")
+        .await
+        .unwrap();
+    let recorded = verifier.0.lock().unwrap().take();
+    recorded.unwrap()
+}
+
+#[tokio::test]
+async fn test_ocsp_stapling() {
+    let (certs, key) = generate_test_cert();
+    let server_config = server_config_with_ocsp(
+        certs.clone(),
+        key.clone_key(),
+        b"fixed".to_vec(),
+        &SessionResumption::new(),
+    )
+    .unwrap();
+    assert_eq!(stapled_response(server_config).await, b"fixed");
+
+    let stapler = Arc::new(OcspStapler::new(certs, key, b"first".to_vec()).unwrap());
+    let server_config =
+        server_config_with_cert_resolver(stapler.clone(), &SessionResumption::disabled()).unwrap();
+    assert_eq!(stapled_response(server_config.clone()).await, b"first");
+
+    stapler.update(b"second".to_vec());
+    assert_eq!(stapled_response(server_config).await, b"second");
+}